@@ -0,0 +1,39 @@
+#!/bin/sh
+# Builds the release binaries and packs them with the systemd units and
+# the config templates into target/dist/ledger-<version>-<target>.tar.gz:
+#
+#   dist/package.sh                            # for this machine
+#   dist/package.sh x86_64-unknown-linux-gnu   # for another target
+#
+# The node binary, `main` in the node crate, is packed as `node`.
+set -eu
+
+cd "$(dirname "$0")/.."
+target=${1:-$(rustc -vV | sed -n 's/^host: //p')}
+version=$(sed -n 's/^version = "\(.*\)"/\1/p' node/Cargo.toml | head -n 1)
+name=ledger-$version-$target
+stage=target/dist/$name
+
+# the crates aren't built as one workspace, so share one target directory
+for crate in node miner lib; do
+    cargo build --release --manifest-path "$crate/Cargo.toml" \
+        --target "$target" --target-dir target --bins
+done
+release=target/$target/release
+
+rm -rf "$stage"
+mkdir -p "$stage/bin" "$stage/systemd" "$stage/config"
+cp "$release/main" "$stage/bin/node"
+# node operator tools, the miners and the wallet tools
+for bin in blockadmin chaincheck proto_dump reserves \
+    online_miner offline_miner \
+    key_gen key_convert vanity_gen message tx_gen tx_print \
+    block_gen block_print block_cosign genesis_gen; do
+    cp "$release/$bin" "$stage/bin/"
+done
+cp dist/systemd/*.service "$stage/systemd/"
+cp dist/node.toml dist/miner.toml "$stage/config/"
+cp README.md "$stage/"
+
+tar -czf "target/dist/$name.tar.gz" -C target/dist "$name"
+echo "target/dist/$name.tar.gz"
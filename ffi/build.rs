@@ -0,0 +1,18 @@
+use std::{env, path::Path};
+
+// regenerates include/btclib.h from the exported functions
+fn main() {
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    let crate_dir = env::var("CARGO_MANIFEST_DIR").expect("cargo sets CARGO_MANIFEST_DIR");
+    let crate_dir = Path::new(&crate_dir);
+    let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml"))
+        .expect("cbindgen.toml is valid");
+    match cbindgen::generate_with_config(crate_dir, config) {
+        Ok(bindings) => {
+            bindings.write_to_file(crate_dir.join("include/btclib.h"));
+        }
+        // a broken header shouldn't keep Rust users from building
+        Err(e) => println!("cargo:warning=failed to generate btclib.h: {e}"),
+    }
+}
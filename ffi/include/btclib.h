@@ -0,0 +1,257 @@
+#ifndef BTCLIB_H
+#define BTCLIB_H
+
+/* Generated with cbindgen:0.29.4 */
+
+/* Generated by cbindgen from btclib-ffi, do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Outcome of a fallible call.
+typedef enum BtcStatus {
+  BTC_STATUS_OK = 0,
+  // A required pointer was null
+  BTC_STATUS_NULL_POINTER = 1,
+  // Bytes or text that don't decode to what was asked for
+  BTC_STATUS_INVALID_ENCODING = 2,
+  // An index past the end
+  BTC_STATUS_OUT_OF_RANGE = 3,
+} BtcStatus;
+
+// A block as the node serves and stores it.
+typedef struct BtcBlock BtcBlock;
+
+// A signing key.
+typedef struct BtcPrivateKey BtcPrivateKey;
+
+// A key outputs pay to.
+typedef struct BtcPublicKey BtcPublicKey;
+
+// A signed transaction.
+typedef struct BtcTransaction BtcTransaction;
+
+// Inputs and outputs of a transaction being put together.
+typedef struct BtcTransactionBuilder BtcTransactionBuilder;
+
+// Bytes handed to the caller, released with `btc_buffer_free`.
+typedef struct BtcBuffer {
+  uint8_t *data;
+  size_t len;
+} BtcBuffer;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// # Safety
+// `block` must be null or come from this library and not be freed
+// before.
+void btc_block_free(struct BtcBlock *block);
+
+// Decodes a block, as `FetchBlock` answers it or `btc_block_encode`
+// wrote it, into `*out`.
+//
+// # Safety
+// `data` must point to `len` readable bytes and `out` be writable.
+enum BtcStatus btc_block_decode(const uint8_t *data, size_t len, struct BtcBlock **out);
+
+// Encodes `block` into `*out`.
+//
+// # Safety
+// `block` must be a live block and `out` writable.
+enum BtcStatus btc_block_encode(const struct BtcBlock *block, struct BtcBuffer *out);
+
+// Writes the (header) hash of `block` to the 32 bytes at `out`.
+//
+// # Safety
+// `block` must be a live block and `out` point to 32 writable bytes.
+enum BtcStatus btc_block_hash(const struct BtcBlock *block, uint8_t *out);
+
+// Writes the hash of the block `block` builds on to the 32 bytes at
+// `out`.
+//
+// # Safety
+// `block` must be a live block and `out` point to 32 writable bytes.
+enum BtcStatus btc_block_prev_hash(const struct BtcBlock *block, uint8_t *out);
+
+// Seconds since the Unix epoch in the header of `block`, 0 if it is
+// null.
+//
+// # Safety
+// `block` must be null or a live block.
+int64_t btc_block_timestamp(const struct BtcBlock *block);
+
+// Number of transactions in `block`, the coinbase included; 0 if it is
+// null.
+//
+// # Safety
+// `block` must be null or a live block.
+size_t btc_block_transaction_count(const struct BtcBlock *block);
+
+// A copy of transaction `index` of `block` into `*out`, released with
+// `btc_transaction_free`. The coinbase is transaction 0.
+//
+// # Safety
+// `block` must be a live block and `out` writable.
+enum BtcStatus btc_block_transaction(const struct BtcBlock *block,
+                                     size_t index,
+                                     struct BtcTransaction **out);
+
+// Releases the bytes of `buffer`.
+//
+// # Safety
+// `buffer` must come from this library and not be freed before.
+void btc_buffer_free(struct BtcBuffer buffer);
+
+// Releases a string returned by this library.
+//
+// # Safety
+// `string` must come from this library and not be freed before.
+void btc_string_free(char *string);
+
+// What went wrong in the last failed call on this thread, or null.
+// The string stays valid until the next failing call on the thread.
+const char *btc_last_error(void);
+
+// A new random private key, released with `btc_private_key_free`.
+struct BtcPrivateKey *btc_private_key_generate(void);
+
+// # Safety
+// `key` must be null or come from this library and not be freed before.
+void btc_private_key_free(struct BtcPrivateKey *key);
+
+// Encodes `key` as in the node's and wallet's key files, into `*out`.
+//
+// # Safety
+// `key` must be a live private key and `out` writable.
+enum BtcStatus btc_private_key_encode(const struct BtcPrivateKey *key, struct BtcBuffer *out);
+
+// Decodes a private key encoded by `btc_private_key_encode` (or read
+// from a key file) into `*out`.
+//
+// # Safety
+// `data` must point to `len` readable bytes and `out` be writable.
+enum BtcStatus btc_private_key_decode(const uint8_t *data, size_t len, struct BtcPrivateKey **out);
+
+// The public key of `key`, released with `btc_public_key_free`; null
+// if `key` is.
+//
+// # Safety
+// `key` must be null or a live private key.
+struct BtcPublicKey *btc_private_key_public_key(const struct BtcPrivateKey *key);
+
+// # Safety
+// `key` must be null or come from this library and not be freed before.
+void btc_public_key_free(struct BtcPublicKey *key);
+
+// `key` as hex of its compressed SEC1 encoding, as the node's HTTP
+// port takes it; released with `btc_string_free`, null if `key` is.
+//
+// # Safety
+// `key` must be null or a live public key.
+char *btc_public_key_to_hex(const struct BtcPublicKey *key);
+
+// Parses a public key written by `btc_public_key_to_hex` into `*out`.
+//
+// # Safety
+// `hex` must be a NUL-terminated string and `out` writable.
+enum BtcStatus btc_public_key_from_hex(const char *hex, struct BtcPublicKey **out);
+
+// The Base58Check address of `key`, released with `btc_string_free`;
+// null if `key` is.
+//
+// # Safety
+// `key` must be null or a live public key.
+char *btc_public_key_address(const struct BtcPublicKey *key);
+
+// An empty transaction, released with `btc_transaction_builder_free`
+// or turned into a transaction by `btc_transaction_builder_build`.
+struct BtcTransactionBuilder *btc_transaction_builder_new(void);
+
+// # Safety
+// `builder` must be null or come from this library and not be freed
+// or built before.
+void btc_transaction_builder_free(struct BtcTransactionBuilder *builder);
+
+// Spends the output whose hash is the 32 bytes at `outpoint`, signing
+// it with `key`, which the output has to pay.
+//
+// # Safety
+// `builder` must be a live builder, `outpoint` point to 32 readable
+// bytes and `key` be a live private key.
+enum BtcStatus btc_transaction_builder_add_input(struct BtcTransactionBuilder *builder,
+                                                 const uint8_t *outpoint,
+                                                 const struct BtcPrivateKey *key);
+
+// Pays `value` satoshis to `key`.
+//
+// # Safety
+// `builder` must be a live builder and `key` a live public key.
+enum BtcStatus btc_transaction_builder_add_output(struct BtcTransactionBuilder *builder,
+                                                  uint64_t value,
+                                                  const struct BtcPublicKey *key);
+
+// The transaction with the inputs and outputs added so far, released
+// with `btc_transaction_free`. Consumes `builder`; null if it is.
+//
+// # Safety
+// `builder` must be null or a live builder, not used afterwards.
+struct BtcTransaction *btc_transaction_builder_build(struct BtcTransactionBuilder *builder);
+
+// # Safety
+// `transaction` must be null or come from this library and not be
+// freed before.
+void btc_transaction_free(struct BtcTransaction *transaction);
+
+// Encodes `transaction` as `SubmitTransaction` carries it, into `*out`.
+//
+// # Safety
+// `transaction` must be a live transaction and `out` writable.
+enum BtcStatus btc_transaction_encode(const struct BtcTransaction *transaction,
+                                      struct BtcBuffer *out);
+
+// Decodes a transaction encoded by `btc_transaction_encode` into `*out`.
+//
+// # Safety
+// `data` must point to `len` readable bytes and `out` be writable.
+enum BtcStatus btc_transaction_decode(const uint8_t *data, size_t len, struct BtcTransaction **out);
+
+// Writes the hash of `transaction` to the 32 bytes at `out`.
+//
+// # Safety
+// `transaction` must be a live transaction and `out` point to 32
+// writable bytes.
+enum BtcStatus btc_transaction_hash(const struct BtcTransaction *transaction, uint8_t *out);
+
+// Number of inputs of `transaction`, 0 if it is null.
+//
+// # Safety
+// `transaction` must be null or a live transaction.
+size_t btc_transaction_input_count(const struct BtcTransaction *transaction);
+
+// Number of outputs of `transaction`, 0 if it is null.
+//
+// # Safety
+// `transaction` must be null or a live transaction.
+size_t btc_transaction_output_count(const struct BtcTransaction *transaction);
+
+// Writes the value of output `index` to `*value` and its hash, the
+// outpoint that spends it, to the 32 bytes at `hash`.
+//
+// # Safety
+// `transaction` must be a live transaction, `value` writable and
+// `hash` point to 32 writable bytes.
+enum BtcStatus btc_transaction_output(const struct BtcTransaction *transaction,
+                                      size_t index,
+                                      uint64_t *value,
+                                      uint8_t *hash);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* BTCLIB_H */
@@ -0,0 +1,221 @@
+use btclib::{types::Block, utils::Saveable};
+
+use crate::{
+    BtcBuffer, BtcStatus, BtcTransaction,
+    buffer::{input_bytes, write_hash},
+    error::{fail, null_pointer},
+};
+
+/// A block as the node serves and stores it.
+pub struct BtcBlock(Block);
+
+/// # Safety
+/// `block` must be null or come from this library and not be freed
+/// before.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn btc_block_free(block: *mut BtcBlock) {
+    if !block.is_null() {
+        // SAFETY: made by `Box::into_raw`
+        drop(unsafe { Box::from_raw(block) });
+    }
+}
+
+/// Decodes a block, as `FetchBlock` answers it or `btc_block_encode`
+/// wrote it, into `*out`.
+///
+/// # Safety
+/// `data` must point to `len` readable bytes and `out` be writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn btc_block_decode(
+    data: *const u8,
+    len: usize,
+    out: *mut *mut BtcBlock,
+) -> BtcStatus {
+    // SAFETY: the caller promises `len` bytes at `data`
+    let Some(bytes) = (unsafe { input_bytes(data, len) }) else {
+        return null_pointer("data");
+    };
+    if out.is_null() {
+        return null_pointer("out");
+    }
+    let block = match Block::load(bytes) {
+        Ok(block) => block,
+        Err(e) => return fail(BtcStatus::InvalidEncoding, e.to_string()),
+    };
+    // SAFETY: checked for null, the caller promises it is writable
+    unsafe { out.write(Box::into_raw(Box::new(BtcBlock(block)))) };
+    BtcStatus::Ok
+}
+
+/// Encodes `block` into `*out`.
+///
+/// # Safety
+/// `block` must be a live block and `out` writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn btc_block_encode(
+    block: *const BtcBlock,
+    out: *mut BtcBuffer,
+) -> BtcStatus {
+    // SAFETY: the caller promises a live block
+    let Some(block) = (unsafe { block.as_ref() }) else {
+        return null_pointer("block");
+    };
+    if out.is_null() {
+        return null_pointer("out");
+    }
+    let mut bytes = vec![];
+    if let Err(e) = block.0.save(&mut bytes) {
+        return fail(BtcStatus::InvalidEncoding, e.to_string());
+    }
+    // SAFETY: checked for null, the caller promises it is writable
+    unsafe { out.write(BtcBuffer::from_vec(bytes)) };
+    BtcStatus::Ok
+}
+
+/// Writes the (header) hash of `block` to the 32 bytes at `out`.
+///
+/// # Safety
+/// `block` must be a live block and `out` point to 32 writable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn btc_block_hash(block: *const BtcBlock, out: *mut u8) -> BtcStatus {
+    // SAFETY: the caller promises a live block
+    let Some(block) = (unsafe { block.as_ref() }) else {
+        return null_pointer("block");
+    };
+    // SAFETY: the caller promises 32 bytes
+    unsafe { write_hash(&block.0.hash(), out) }
+}
+
+/// Writes the hash of the block `block` builds on to the 32 bytes at
+/// `out`.
+///
+/// # Safety
+/// `block` must be a live block and `out` point to 32 writable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn btc_block_prev_hash(block: *const BtcBlock, out: *mut u8) -> BtcStatus {
+    // SAFETY: the caller promises a live block
+    let Some(block) = (unsafe { block.as_ref() }) else {
+        return null_pointer("block");
+    };
+    // SAFETY: the caller promises 32 bytes
+    unsafe { write_hash(block.0.header().prev_block_hash(), out) }
+}
+
+/// Seconds since the Unix epoch in the header of `block`, 0 if it is
+/// null.
+///
+/// # Safety
+/// `block` must be null or a live block.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn btc_block_timestamp(block: *const BtcBlock) -> i64 {
+    // SAFETY: the caller promises a live block
+    unsafe { block.as_ref() }.map_or(0, |block| block.0.header().timestamp().timestamp())
+}
+
+/// Number of transactions in `block`, the coinbase included; 0 if it is
+/// null.
+///
+/// # Safety
+/// `block` must be null or a live block.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn btc_block_transaction_count(block: *const BtcBlock) -> usize {
+    // SAFETY: the caller promises a live block
+    unsafe { block.as_ref() }.map_or(0, |block| block.0.transactions().len())
+}
+
+/// A copy of transaction `index` of `block` into `*out`, released with
+/// `btc_transaction_free`. The coinbase is transaction 0.
+///
+/// # Safety
+/// `block` must be a live block and `out` writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn btc_block_transaction(
+    block: *const BtcBlock,
+    index: usize,
+    out: *mut *mut BtcTransaction,
+) -> BtcStatus {
+    // SAFETY: the caller promises a live block
+    let Some(block) = (unsafe { block.as_ref() }) else {
+        return null_pointer("block");
+    };
+    if out.is_null() {
+        return null_pointer("out");
+    }
+    let Some(transaction) = block.0.transactions().get(index) else {
+        return fail(
+            BtcStatus::OutOfRange,
+            format!("the block has no transaction {index}"),
+        );
+    };
+    let transaction = BtcTransaction(transaction.clone());
+    // SAFETY: checked for null, the caller promises it is writable
+    unsafe { out.write(Box::into_raw(Box::new(transaction))) };
+    BtcStatus::Ok
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use btclib::{
+        MIN_TARGET,
+        crypto::PrivateKey,
+        custom_sha_types::Hash,
+        types::{BlockBuilder, BlockHeight},
+    };
+
+    use super::*;
+    use crate::{btc_buffer_free, btc_transaction_free, btc_transaction_hash};
+
+    #[test]
+    fn test_parse_block() {
+        let block = BlockBuilder::new(Hash::hash(&"parent"), BlockHeight::new(3), MIN_TARGET)
+            .build(PrivateKey::default().public_key());
+        let mut bytes = vec![];
+        block.save(&mut bytes).unwrap();
+        unsafe {
+            let mut parsed = ptr::null_mut();
+            assert_eq!(
+                btc_block_decode(bytes.as_ptr(), bytes.len(), &mut parsed),
+                BtcStatus::Ok
+            );
+            let mut hash = [0; 32];
+            assert_eq!(btc_block_hash(parsed, hash.as_mut_ptr()), BtcStatus::Ok);
+            assert_eq!(Hash::from_bytes(hash), block.hash());
+            assert_eq!(
+                btc_block_prev_hash(parsed, hash.as_mut_ptr()),
+                BtcStatus::Ok
+            );
+            assert_eq!(Hash::from_bytes(hash), Hash::hash(&"parent"));
+            assert_eq!(
+                btc_block_timestamp(parsed),
+                block.header().timestamp().timestamp()
+            );
+            assert_eq!(btc_block_transaction_count(parsed), 1);
+
+            let mut coinbase = ptr::null_mut();
+            assert_eq!(
+                btc_block_transaction(parsed, 0, &mut coinbase),
+                BtcStatus::Ok
+            );
+            assert_eq!(
+                btc_transaction_hash(coinbase, hash.as_mut_ptr()),
+                BtcStatus::Ok
+            );
+            assert_eq!(Hash::from_bytes(hash), block.transactions()[0].hash());
+            let mut missing = ptr::null_mut();
+            assert_eq!(
+                btc_block_transaction(parsed, 1, &mut missing),
+                BtcStatus::OutOfRange
+            );
+
+            let mut encoded = BtcBuffer::default();
+            assert_eq!(btc_block_encode(parsed, &mut encoded), BtcStatus::Ok);
+            assert_eq!(std::slice::from_raw_parts(encoded.data, encoded.len), bytes);
+            btc_buffer_free(encoded);
+
+            btc_transaction_free(coinbase);
+            btc_block_free(parsed);
+        }
+    }
+}
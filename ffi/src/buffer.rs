@@ -0,0 +1,90 @@
+use std::{
+    ffi::{CString, c_char},
+    ptr, slice,
+};
+
+use btclib::custom_sha_types::Hash;
+
+use crate::{BtcStatus, error::null_pointer};
+
+/// Bytes handed to the caller, released with `btc_buffer_free`.
+#[repr(C)]
+#[derive(Debug)]
+pub struct BtcBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+impl BtcBuffer {
+    pub(crate) fn from_vec(bytes: Vec<u8>) -> Self {
+        let len = bytes.len();
+        let data = Box::into_raw(bytes.into_boxed_slice()).cast::<u8>();
+        BtcBuffer { data, len }
+    }
+}
+
+impl Default for BtcBuffer {
+    fn default() -> Self {
+        BtcBuffer {
+            data: ptr::null_mut(),
+            len: 0,
+        }
+    }
+}
+
+/// Releases the bytes of `buffer`.
+///
+/// # Safety
+/// `buffer` must come from this library and not be freed before.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn btc_buffer_free(buffer: BtcBuffer) {
+    if !buffer.data.is_null() {
+        // SAFETY: made by `from_vec` from a boxed slice of this length
+        drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(buffer.data, buffer.len)) });
+    }
+}
+
+/// Releases a string returned by this library.
+///
+/// # Safety
+/// `string` must come from this library and not be freed before.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn btc_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        // SAFETY: made by `CString::into_raw`
+        drop(unsafe { CString::from_raw(string) });
+    }
+}
+
+// the `len` bytes at `data`, None if `data` is null
+pub(crate) unsafe fn input_bytes<'a>(data: *const u8, len: usize) -> Option<&'a [u8]> {
+    if data.is_null() {
+        return None;
+    }
+    // SAFETY: the caller promises `len` readable bytes at `data`
+    Some(unsafe { slice::from_raw_parts(data, len) })
+}
+
+pub(crate) fn output_string(string: String) -> *mut c_char {
+    // hex and base58 never contain NUL
+    CString::new(string).map_or(ptr::null_mut(), CString::into_raw)
+}
+
+// copies the 32 bytes of `hash` to `out`
+pub(crate) unsafe fn write_hash(hash: &Hash, out: *mut u8) -> BtcStatus {
+    if out.is_null() {
+        return null_pointer("out");
+    }
+    // SAFETY: checked for null, the caller promises 32 writable bytes
+    unsafe { ptr::copy_nonoverlapping(hash.as_bytes().as_ptr(), out, 32) };
+    BtcStatus::Ok
+}
+
+// the hash in the 32 bytes at `data`, None if `data` is null
+pub(crate) unsafe fn read_hash(data: *const u8) -> Option<Hash> {
+    // SAFETY: the caller promises 32 readable bytes
+    let bytes = unsafe { input_bytes(data, 32) }?;
+    let mut hash = [0; 32];
+    hash.copy_from_slice(bytes);
+    Some(Hash::from_bytes(hash))
+}
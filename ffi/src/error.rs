@@ -0,0 +1,45 @@
+use std::{
+    cell::RefCell,
+    ffi::{CString, c_char},
+    ptr,
+};
+
+/// Outcome of a fallible call.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BtcStatus {
+    Ok = 0,
+    /// A required pointer was null
+    NullPointer = 1,
+    /// Bytes or text that don't decode to what was asked for
+    InvalidEncoding = 2,
+    /// An index past the end
+    OutOfRange = 3,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+// records `message` for `btc_last_error` and returns `status`
+pub(crate) fn fail(status: BtcStatus, message: impl Into<String>) -> BtcStatus {
+    // interior NULs can't be passed to C, cut the message there
+    let mut message = message.into().into_bytes();
+    if let Some(nul) = message.iter().position(|byte| *byte == 0) {
+        message.truncate(nul);
+    }
+    let message = CString::new(message).ok();
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+    status
+}
+
+pub(crate) fn null_pointer(name: &str) -> BtcStatus {
+    fail(BtcStatus::NullPointer, format!("{name} is null"))
+}
+
+/// What went wrong in the last failed call on this thread, or null.
+/// The string stays valid until the next failing call on the thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn btc_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |m| m.as_ptr()))
+}
@@ -0,0 +1,239 @@
+use std::{
+    ffi::{CStr, c_char},
+    ptr,
+};
+
+use btclib::{
+    crypto::{PrivateKey, PublicKey},
+    utils::Saveable,
+};
+
+use crate::{
+    BtcBuffer, BtcStatus,
+    buffer::{input_bytes, output_string},
+    error::{fail, null_pointer},
+};
+
+/// A signing key.
+pub struct BtcPrivateKey(pub(crate) PrivateKey);
+
+/// A key outputs pay to.
+pub struct BtcPublicKey(pub(crate) PublicKey);
+
+/// A new random private key, released with `btc_private_key_free`.
+#[unsafe(no_mangle)]
+pub extern "C" fn btc_private_key_generate() -> *mut BtcPrivateKey {
+    Box::into_raw(Box::new(BtcPrivateKey(PrivateKey::default())))
+}
+
+/// # Safety
+/// `key` must be null or come from this library and not be freed before.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn btc_private_key_free(key: *mut BtcPrivateKey) {
+    if !key.is_null() {
+        // SAFETY: made by `Box::into_raw`
+        drop(unsafe { Box::from_raw(key) });
+    }
+}
+
+/// Encodes `key` as in the node's and wallet's key files, into `*out`.
+///
+/// # Safety
+/// `key` must be a live private key and `out` writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn btc_private_key_encode(
+    key: *const BtcPrivateKey,
+    out: *mut BtcBuffer,
+) -> BtcStatus {
+    // SAFETY: the caller promises a live key
+    let Some(key) = (unsafe { key.as_ref() }) else {
+        return null_pointer("key");
+    };
+    if out.is_null() {
+        return null_pointer("out");
+    }
+    let mut bytes = vec![];
+    if let Err(e) = key.0.save(&mut bytes) {
+        return fail(BtcStatus::InvalidEncoding, e.to_string());
+    }
+    // SAFETY: checked for null, the caller promises it is writable
+    unsafe { out.write(BtcBuffer::from_vec(bytes)) };
+    BtcStatus::Ok
+}
+
+/// Decodes a private key encoded by `btc_private_key_encode` (or read
+/// from a key file) into `*out`.
+///
+/// # Safety
+/// `data` must point to `len` readable bytes and `out` be writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn btc_private_key_decode(
+    data: *const u8,
+    len: usize,
+    out: *mut *mut BtcPrivateKey,
+) -> BtcStatus {
+    // SAFETY: the caller promises `len` bytes at `data`
+    let Some(bytes) = (unsafe { input_bytes(data, len) }) else {
+        return null_pointer("data");
+    };
+    if out.is_null() {
+        return null_pointer("out");
+    }
+    let key = match PrivateKey::load(bytes) {
+        Ok(key) => key,
+        Err(e) => return fail(BtcStatus::InvalidEncoding, e.to_string()),
+    };
+    // SAFETY: checked for null, the caller promises it is writable
+    unsafe { out.write(Box::into_raw(Box::new(BtcPrivateKey(key)))) };
+    BtcStatus::Ok
+}
+
+/// The public key of `key`, released with `btc_public_key_free`; null
+/// if `key` is.
+///
+/// # Safety
+/// `key` must be null or a live private key.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn btc_private_key_public_key(
+    key: *const BtcPrivateKey,
+) -> *mut BtcPublicKey {
+    // SAFETY: the caller promises a live key
+    match unsafe { key.as_ref() } {
+        Some(key) => Box::into_raw(Box::new(BtcPublicKey(key.0.public_key()))),
+        None => ptr::null_mut(),
+    }
+}
+
+/// # Safety
+/// `key` must be null or come from this library and not be freed before.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn btc_public_key_free(key: *mut BtcPublicKey) {
+    if !key.is_null() {
+        // SAFETY: made by `Box::into_raw`
+        drop(unsafe { Box::from_raw(key) });
+    }
+}
+
+/// `key` as hex of its compressed SEC1 encoding, as the node's HTTP
+/// port takes it; released with `btc_string_free`, null if `key` is.
+///
+/// # Safety
+/// `key` must be null or a live public key.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn btc_public_key_to_hex(key: *const BtcPublicKey) -> *mut c_char {
+    // SAFETY: the caller promises a live key
+    match unsafe { key.as_ref() } {
+        Some(key) => output_string(key.0.to_hex()),
+        None => ptr::null_mut(),
+    }
+}
+
+/// Parses a public key written by `btc_public_key_to_hex` into `*out`.
+///
+/// # Safety
+/// `hex` must be a NUL-terminated string and `out` writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn btc_public_key_from_hex(
+    hex: *const c_char,
+    out: *mut *mut BtcPublicKey,
+) -> BtcStatus {
+    if hex.is_null() {
+        return null_pointer("hex");
+    }
+    if out.is_null() {
+        return null_pointer("out");
+    }
+    // SAFETY: checked for null, the caller promises a C string
+    let hex = unsafe { CStr::from_ptr(hex) }.to_string_lossy();
+    let key = match PublicKey::from_hex(&hex) {
+        Ok(key) => key,
+        Err(e) => return fail(BtcStatus::InvalidEncoding, e.to_string()),
+    };
+    // SAFETY: checked for null, the caller promises it is writable
+    unsafe { out.write(Box::into_raw(Box::new(BtcPublicKey(key)))) };
+    BtcStatus::Ok
+}
+
+/// The Base58Check address of `key`, released with `btc_string_free`;
+/// null if `key` is.
+///
+/// # Safety
+/// `key` must be null or a live public key.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn btc_public_key_address(key: *const BtcPublicKey) -> *mut c_char {
+    // SAFETY: the caller promises a live key
+    match unsafe { key.as_ref() } {
+        Some(key) => output_string(key.0.address().to_string()),
+        None => ptr::null_mut(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+
+    use super::*;
+    use crate::{btc_buffer_free, btc_last_error, btc_string_free};
+
+    #[test]
+    fn test_keys_round_trip() {
+        unsafe {
+            let key = btc_private_key_generate();
+            let mut encoded = BtcBuffer::default();
+            assert_eq!(btc_private_key_encode(key, &mut encoded), BtcStatus::Ok);
+            let mut decoded = ptr::null_mut();
+            assert_eq!(
+                btc_private_key_decode(encoded.data, encoded.len, &mut decoded),
+                BtcStatus::Ok
+            );
+            btc_buffer_free(encoded);
+            assert_eq!((*decoded).0.public_key(), (*key).0.public_key());
+
+            let public_key = btc_private_key_public_key(key);
+            let hex = btc_public_key_to_hex(public_key);
+            let mut parsed = ptr::null_mut();
+            assert_eq!(btc_public_key_from_hex(hex, &mut parsed), BtcStatus::Ok);
+            assert_eq!((*parsed).0, (*public_key).0);
+            let address = btc_public_key_address(public_key);
+            assert!(CStr::from_ptr(address).to_str().unwrap().starts_with('1'));
+
+            btc_string_free(hex);
+            btc_string_free(address);
+            btc_public_key_free(parsed);
+            btc_public_key_free(public_key);
+            btc_private_key_free(decoded);
+            btc_private_key_free(key);
+        }
+    }
+
+    #[test]
+    fn test_invalid_key_reports_error() {
+        unsafe {
+            let hex = CString::new("not hex").unwrap();
+            let mut parsed = ptr::null_mut();
+            assert_eq!(
+                btc_public_key_from_hex(hex.as_ptr(), &mut parsed),
+                BtcStatus::InvalidEncoding
+            );
+            assert!(parsed.is_null());
+            assert!(!btc_last_error().is_null());
+            assert_eq!(
+                btc_public_key_from_hex(ptr::null(), &mut parsed),
+                BtcStatus::NullPointer
+            );
+            assert_eq!(
+                CStr::from_ptr(btc_last_error()).to_str().unwrap(),
+                "hex is null"
+            );
+            assert!(btc_public_key_to_hex(ptr::null()).is_null());
+
+            // CBOR bytes too short to be a key
+            let short = [0x43, 1, 2, 3];
+            let mut decoded = ptr::null_mut();
+            assert_eq!(
+                btc_private_key_decode(short.as_ptr(), short.len(), &mut decoded),
+                BtcStatus::InvalidEncoding
+            );
+        }
+    }
+}
@@ -0,0 +1,25 @@
+//! C bindings for the btclib core types, so tooling in other languages
+//! can manage keys, build and sign transactions and parse blocks the
+//! way the node and wallet do. `include/btclib.h` is generated from
+//! this crate by cbindgen on every build.
+//!
+//! Conventions:
+//! - Objects are opaque pointers created by `btc_*_new`, `btc_*_generate`
+//!   or `btc_*_decode` and released with the matching `btc_*_free`.
+//! - Fallible functions return a `BtcStatus` and write their result
+//!   through an out pointer; `btc_last_error` describes the last failure
+//!   on the calling thread.
+//! - Hashes are 32 bytes the caller provides room for.
+//! - Encodings are the CBOR the node speaks and saves, handed out as a
+//!   `BtcBuffer` released with `btc_buffer_free`.
+mod block;
+mod buffer;
+mod error;
+mod keys;
+mod transaction;
+
+pub use block::*;
+pub use buffer::*;
+pub use error::*;
+pub use keys::*;
+pub use transaction::*;
@@ -0,0 +1,321 @@
+use std::ptr;
+
+use btclib::{
+    crypto::Signature,
+    types::{Transaction, TransactionInput, TransactionOutput},
+    utils::Saveable,
+};
+use uuid::Uuid;
+
+use crate::{
+    BtcBuffer, BtcPrivateKey, BtcPublicKey, BtcStatus,
+    buffer::{input_bytes, read_hash, write_hash},
+    error::{fail, null_pointer},
+};
+
+/// A signed transaction.
+pub struct BtcTransaction(pub(crate) Transaction);
+
+/// Inputs and outputs of a transaction being put together.
+#[derive(Default)]
+pub struct BtcTransactionBuilder {
+    inputs: Vec<TransactionInput>,
+    outputs: Vec<TransactionOutput>,
+}
+
+/// An empty transaction, released with `btc_transaction_builder_free`
+/// or turned into a transaction by `btc_transaction_builder_build`.
+#[unsafe(no_mangle)]
+pub extern "C" fn btc_transaction_builder_new() -> *mut BtcTransactionBuilder {
+    Box::into_raw(Box::default())
+}
+
+/// # Safety
+/// `builder` must be null or come from this library and not be freed
+/// or built before.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn btc_transaction_builder_free(builder: *mut BtcTransactionBuilder) {
+    if !builder.is_null() {
+        // SAFETY: made by `Box::into_raw`
+        drop(unsafe { Box::from_raw(builder) });
+    }
+}
+
+/// Spends the output whose hash is the 32 bytes at `outpoint`, signing
+/// it with `key`, which the output has to pay.
+///
+/// # Safety
+/// `builder` must be a live builder, `outpoint` point to 32 readable
+/// bytes and `key` be a live private key.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn btc_transaction_builder_add_input(
+    builder: *mut BtcTransactionBuilder,
+    outpoint: *const u8,
+    key: *const BtcPrivateKey,
+) -> BtcStatus {
+    // SAFETY: the caller promises a live builder and key
+    let (Some(builder), Some(key)) = (unsafe { builder.as_mut() }, unsafe { key.as_ref() }) else {
+        return null_pointer("builder or key");
+    };
+    // SAFETY: the caller promises 32 bytes
+    let Some(outpoint) = (unsafe { read_hash(outpoint) }) else {
+        return null_pointer("outpoint");
+    };
+    let signature = Signature::sign_output(&outpoint, &key.0);
+    builder
+        .inputs
+        .push(TransactionInput::new(outpoint, signature));
+    BtcStatus::Ok
+}
+
+/// Pays `value` satoshis to `key`.
+///
+/// # Safety
+/// `builder` must be a live builder and `key` a live public key.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn btc_transaction_builder_add_output(
+    builder: *mut BtcTransactionBuilder,
+    value: u64,
+    key: *const BtcPublicKey,
+) -> BtcStatus {
+    // SAFETY: the caller promises a live builder and key
+    let (Some(builder), Some(key)) = (unsafe { builder.as_mut() }, unsafe { key.as_ref() }) else {
+        return null_pointer("builder or key");
+    };
+    builder
+        .outputs
+        .push(TransactionOutput::new(value, Uuid::new_v4(), key.0.clone()));
+    BtcStatus::Ok
+}
+
+/// The transaction with the inputs and outputs added so far, released
+/// with `btc_transaction_free`. Consumes `builder`; null if it is.
+///
+/// # Safety
+/// `builder` must be null or a live builder, not used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn btc_transaction_builder_build(
+    builder: *mut BtcTransactionBuilder,
+) -> *mut BtcTransaction {
+    if builder.is_null() {
+        return ptr::null_mut();
+    }
+    // SAFETY: made by `Box::into_raw`, the caller gives it up
+    let builder = unsafe { Box::from_raw(builder) };
+    let transaction = Transaction::new(builder.inputs, builder.outputs);
+    Box::into_raw(Box::new(BtcTransaction(transaction)))
+}
+
+/// # Safety
+/// `transaction` must be null or come from this library and not be
+/// freed before.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn btc_transaction_free(transaction: *mut BtcTransaction) {
+    if !transaction.is_null() {
+        // SAFETY: made by `Box::into_raw`
+        drop(unsafe { Box::from_raw(transaction) });
+    }
+}
+
+/// Encodes `transaction` as `SubmitTransaction` carries it, into `*out`.
+///
+/// # Safety
+/// `transaction` must be a live transaction and `out` writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn btc_transaction_encode(
+    transaction: *const BtcTransaction,
+    out: *mut BtcBuffer,
+) -> BtcStatus {
+    // SAFETY: the caller promises a live transaction
+    let Some(transaction) = (unsafe { transaction.as_ref() }) else {
+        return null_pointer("transaction");
+    };
+    if out.is_null() {
+        return null_pointer("out");
+    }
+    let mut bytes = vec![];
+    if let Err(e) = transaction.0.save(&mut bytes) {
+        return fail(BtcStatus::InvalidEncoding, e.to_string());
+    }
+    // SAFETY: checked for null, the caller promises it is writable
+    unsafe { out.write(BtcBuffer::from_vec(bytes)) };
+    BtcStatus::Ok
+}
+
+/// Decodes a transaction encoded by `btc_transaction_encode` into `*out`.
+///
+/// # Safety
+/// `data` must point to `len` readable bytes and `out` be writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn btc_transaction_decode(
+    data: *const u8,
+    len: usize,
+    out: *mut *mut BtcTransaction,
+) -> BtcStatus {
+    // SAFETY: the caller promises `len` bytes at `data`
+    let Some(bytes) = (unsafe { input_bytes(data, len) }) else {
+        return null_pointer("data");
+    };
+    if out.is_null() {
+        return null_pointer("out");
+    }
+    let transaction = match Transaction::load(bytes) {
+        Ok(transaction) => transaction,
+        Err(e) => return fail(BtcStatus::InvalidEncoding, e.to_string()),
+    };
+    // SAFETY: checked for null, the caller promises it is writable
+    unsafe { out.write(Box::into_raw(Box::new(BtcTransaction(transaction)))) };
+    BtcStatus::Ok
+}
+
+/// Writes the hash of `transaction` to the 32 bytes at `out`.
+///
+/// # Safety
+/// `transaction` must be a live transaction and `out` point to 32
+/// writable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn btc_transaction_hash(
+    transaction: *const BtcTransaction,
+    out: *mut u8,
+) -> BtcStatus {
+    // SAFETY: the caller promises a live transaction
+    let Some(transaction) = (unsafe { transaction.as_ref() }) else {
+        return null_pointer("transaction");
+    };
+    // SAFETY: the caller promises 32 bytes
+    unsafe { write_hash(&transaction.0.hash(), out) }
+}
+
+/// Number of inputs of `transaction`, 0 if it is null.
+///
+/// # Safety
+/// `transaction` must be null or a live transaction.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn btc_transaction_input_count(transaction: *const BtcTransaction) -> usize {
+    // SAFETY: the caller promises a live transaction
+    unsafe { transaction.as_ref() }.map_or(0, |transaction| transaction.0.inputs().len())
+}
+
+/// Number of outputs of `transaction`, 0 if it is null.
+///
+/// # Safety
+/// `transaction` must be null or a live transaction.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn btc_transaction_output_count(transaction: *const BtcTransaction) -> usize {
+    // SAFETY: the caller promises a live transaction
+    unsafe { transaction.as_ref() }.map_or(0, |transaction| transaction.0.outputs().len())
+}
+
+/// Writes the value of output `index` to `*value` and its hash, the
+/// outpoint that spends it, to the 32 bytes at `hash`.
+///
+/// # Safety
+/// `transaction` must be a live transaction, `value` writable and
+/// `hash` point to 32 writable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn btc_transaction_output(
+    transaction: *const BtcTransaction,
+    index: usize,
+    value: *mut u64,
+    hash: *mut u8,
+) -> BtcStatus {
+    // SAFETY: the caller promises a live transaction
+    let Some(transaction) = (unsafe { transaction.as_ref() }) else {
+        return null_pointer("transaction");
+    };
+    if value.is_null() {
+        return null_pointer("value");
+    }
+    let Some(output) = transaction.0.outputs().get(index) else {
+        return fail(
+            BtcStatus::OutOfRange,
+            format!("the transaction has no output {index}"),
+        );
+    };
+    // SAFETY: checked for null, the caller promises it is writable
+    unsafe { value.write(output.value()) };
+    // SAFETY: the caller promises 32 bytes
+    unsafe { write_hash(&output.hash(), hash) }
+}
+
+#[cfg(test)]
+mod tests {
+    use btclib::custom_sha_types::Hash;
+
+    use super::*;
+    use crate::{
+        btc_buffer_free, btc_private_key_free, btc_private_key_generate,
+        btc_private_key_public_key, btc_public_key_free,
+    };
+
+    #[test]
+    fn test_build_sign_and_parse_transaction() {
+        unsafe {
+            let key = btc_private_key_generate();
+            let public_key = btc_private_key_public_key(key);
+            let outpoint = Hash::hash(&"funding");
+
+            let builder = btc_transaction_builder_new();
+            assert_eq!(
+                btc_transaction_builder_add_input(builder, outpoint.as_bytes().as_ptr(), key),
+                BtcStatus::Ok
+            );
+            assert_eq!(
+                btc_transaction_builder_add_output(builder, 1_000, public_key),
+                BtcStatus::Ok
+            );
+            assert_eq!(
+                btc_transaction_builder_add_output(ptr::null_mut(), 1, public_key),
+                BtcStatus::NullPointer
+            );
+            let transaction = btc_transaction_builder_build(builder);
+            let input = &(*transaction).0.inputs()[0];
+            assert_eq!(*input.prev_transaction_output_hash(), outpoint);
+            assert!(input.signature().verify(&outpoint, &(*public_key).0));
+
+            let mut encoded = BtcBuffer::default();
+            assert_eq!(
+                btc_transaction_encode(transaction, &mut encoded),
+                BtcStatus::Ok
+            );
+            let mut decoded = ptr::null_mut();
+            assert_eq!(
+                btc_transaction_decode(encoded.data, encoded.len, &mut decoded),
+                BtcStatus::Ok
+            );
+            btc_buffer_free(encoded);
+            let mut hash = [0; 32];
+            assert_eq!(
+                btc_transaction_hash(decoded, hash.as_mut_ptr()),
+                BtcStatus::Ok
+            );
+            assert_eq!(Hash::from_bytes(hash), (*transaction).0.hash());
+            assert_eq!(btc_transaction_input_count(decoded), 1);
+            assert_eq!(btc_transaction_output_count(decoded), 1);
+
+            let mut value = 0;
+            assert_eq!(
+                btc_transaction_output(decoded, 0, &mut value, hash.as_mut_ptr()),
+                BtcStatus::Ok
+            );
+            assert_eq!(value, 1_000);
+            assert_eq!(Hash::from_bytes(hash), (*transaction).0.outputs()[0].hash());
+            assert_eq!(
+                btc_transaction_output(decoded, 1, &mut value, hash.as_mut_ptr()),
+                BtcStatus::OutOfRange
+            );
+
+            let garbage = [0xff; 8];
+            let mut parsed = ptr::null_mut();
+            assert_eq!(
+                btc_transaction_decode(garbage.as_ptr(), garbage.len(), &mut parsed),
+                BtcStatus::InvalidEncoding
+            );
+
+            btc_transaction_free(decoded);
+            btc_transaction_free(transaction);
+            btc_public_key_free(public_key);
+            btc_private_key_free(key);
+        }
+    }
+}
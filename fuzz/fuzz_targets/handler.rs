@@ -0,0 +1,98 @@
+#![no_main]
+
+//! Feeds well-formed but hostile messages to the connection handler of
+//! an in-memory node. The input is a sequence of big endian u16 length
+//! prefixed chunks, each decoded as a `Message`; chunks that don't
+//! decode are skipped, as `message_decode` covers them.
+
+use std::{sync::Arc, time::Duration};
+
+use btclib::{
+    crypto::PrivateKey,
+    custom_sha_types::Hash,
+    network::Message,
+    types::{Block, BlockHeader, Transaction, TransactionOutput},
+    utils::MerkleRoot,
+};
+use libfuzzer_sys::fuzz_target;
+use node::{NodeState, handler::serve_connection};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+fn messages(mut data: &[u8]) -> Vec<Message> {
+    let mut messages = vec![];
+    while let [high, low, rest @ ..] = data {
+        let len = (usize::from(*high) << 8 | usize::from(*low)).min(rest.len());
+        let (chunk, rest) = rest.split_at(len);
+        data = rest;
+        match Message::decode(chunk) {
+            // would wait for tip changes until the timeout
+            Ok(Message::SubscribeTips) | Err(_) => {}
+            Ok(message) => messages.push(message),
+        }
+    }
+    messages
+}
+
+// a node with a genesis block, so lookups have something to find
+async fn node_state() -> Arc<NodeState> {
+    let state = NodeState::default();
+    let transactions = vec![Transaction::new(
+        vec![],
+        vec![TransactionOutput::new(
+            5000000000,
+            uuid::Uuid::nil(),
+            PrivateKey::from_seed(b"fuzz").public_key(),
+        )],
+    )];
+    let merkle_root = MerkleRoot::calculate(&transactions);
+    let header = BlockHeader::new(
+        chrono::DateTime::UNIX_EPOCH,
+        0,
+        Hash::zero(),
+        merkle_root,
+        btclib::MIN_TARGET,
+    );
+    {
+        let mut blockchain = state.blockchain.write().await;
+        blockchain
+            .add_block(Block::new(header, transactions))
+            .expect("genesis block rejected");
+        blockchain.rebuild_utxos();
+    }
+    Arc::new(state)
+}
+
+fuzz_target!(|data: &[u8]| {
+    let messages = messages(data);
+    if messages.is_empty() {
+        return;
+    }
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+    runtime.block_on(async {
+        let state = node_state().await;
+        let (client, server) = tokio::io::duplex(1 << 20);
+        let (mut client_reader, mut client_writer) = tokio::io::split(client);
+        let requests = async move {
+            for message in messages {
+                if message.send_async(&mut client_writer).await.is_err() {
+                    // the node hung up
+                    return;
+                }
+            }
+            let _ = client_writer.shutdown().await;
+        };
+        let responses = async move {
+            let mut sink = vec![];
+            let _ = client_reader.read_to_end(&mut sink).await;
+        };
+        let serve = serve_connection(state, server, None);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            tokio::join!(requests, responses, serve)
+        })
+        .await
+        .expect("handler hung");
+    });
+});
@@ -0,0 +1,14 @@
+#![no_main]
+
+use btclib::network::Message;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(message) = Message::decode(data) else {
+        return;
+    };
+    // whatever decodes must survive a roundtrip
+    let encoded = message.encode().expect("decoded message doesn't encode");
+    let decoded = Message::decode(&encoded).expect("encoded message doesn't decode");
+    assert_eq!(decoded.encode().expect("reencoding failed"), encoded);
+});
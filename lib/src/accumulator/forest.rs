@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+
+use crate::{
+    accumulator::{AccumulatorProof, Stump, UtxoProof, parent_hash, tree_of},
+    custom_sha_types::Hash,
+    types::{Block, BlockHeight, Blockchain, TransactionOutput},
+};
+
+/// The whole accumulator a node keeps to hand out `UtxoProof`s: every
+/// leaf and the nodes above them, built by replaying the chain. Outputs
+/// are added in the order blocks create them and spent leaves are zeroed
+/// in place, so the forest only grows; pruning spent subtrees as utreexo
+/// does is left for later.
+#[derive(Debug, Clone)]
+pub struct Forest {
+    // rows[0] the leaves, rows[h] the nodes h levels up as far as both
+    // of their children exist
+    rows: Vec<Vec<Hash>>,
+    // unspent outputs by outpoint, with the position of their leaf
+    unspent: HashMap<Hash, (u64, TransactionOutput)>,
+    height: BlockHeight,
+    tip: Hash,
+}
+
+impl Default for Forest {
+    fn default() -> Self {
+        Forest {
+            rows: vec![vec![]],
+            unspent: HashMap::new(),
+            height: BlockHeight::GENESIS,
+            tip: Hash::zero(),
+        }
+    }
+}
+
+impl Forest {
+    /// Number of blocks applied so far.
+    pub fn height(&self) -> BlockHeight {
+        self.height
+    }
+
+    /// Number of unspent outputs.
+    pub fn unspent(&self) -> usize {
+        self.unspent.len()
+    }
+
+    /// Applies the blocks of `blockchain` the forest hasn't seen yet,
+    /// starting over if the chain reorganized below its tip.
+    pub fn index(&mut self, blockchain: &Blockchain) {
+        if let Some(last) = self.height.previous()
+            && blockchain.block_at(last).map(Block::hash) != Some(self.tip)
+        {
+            *self = Forest::default();
+        }
+        for (_, block) in blockchain.iter_blocks_in(self.height..) {
+            self.connect_block(block);
+        }
+    }
+
+    /// Applies `block` and returns the proofs of the outputs it spends,
+    /// each as it held just before its spend, which is what
+    /// `Stump::connect_block` takes.
+    pub fn connect_block(&mut self, block: &Block) -> Vec<UtxoProof> {
+        let mut proofs = vec![];
+        for transaction in block.transactions() {
+            for input in transaction.inputs() {
+                let outpoint = input.prev_transaction_output_hash();
+                if let Some(proof) = self.prove(outpoint) {
+                    self.remove(outpoint);
+                    proofs.push(proof);
+                }
+            }
+            for output in transaction.outputs() {
+                self.add(output.clone());
+            }
+        }
+        self.height = self.height.next();
+        self.tip = block.hash();
+        proofs
+    }
+
+    /// The proof that `outpoint` is unspent, `None` if it isn't.
+    pub fn prove(&self, outpoint: &Hash) -> Option<UtxoProof> {
+        let (position, output) = self.unspent.get(outpoint)?;
+        let (_, height, _) = tree_of(self.leaves(), *position)?;
+        let siblings = (0..height as usize)
+            .map(|level| self.rows[level][((position >> level) ^ 1) as usize])
+            .collect();
+        Some(UtxoProof {
+            output: output.clone(),
+            proof: AccumulatorProof {
+                position: *position,
+                siblings,
+            },
+        })
+    }
+
+    /// The roots, what stateless clients check proofs against.
+    pub fn stump(&self) -> Stump {
+        let leaves = self.leaves();
+        let mut roots = vec![];
+        let mut offset = 0;
+        for height in (0..u64::BITS).rev() {
+            if leaves & (1 << height) != 0 {
+                roots.push(self.rows[height as usize][(offset >> height) as usize]);
+                offset += 1 << height;
+            }
+        }
+        Stump {
+            height: self.height,
+            tip: self.tip,
+            leaves,
+            roots,
+        }
+    }
+
+    fn leaves(&self) -> u64 {
+        self.rows[0].len() as u64
+    }
+
+    fn add(&mut self, output: TransactionOutput) {
+        let leaf = output.hash();
+        self.unspent.insert(leaf, (self.leaves(), output));
+        self.rows[0].push(leaf);
+        let mut position = self.rows[0].len() - 1;
+        let mut level = 0;
+        // a right child completes the pair, and so on up
+        while position % 2 == 1 {
+            let row = &self.rows[level];
+            let parent = parent_hash(&row[position - 1], &row[position]);
+            if self.rows.len() == level + 1 {
+                self.rows.push(vec![]);
+            }
+            self.rows[level + 1].push(parent);
+            position /= 2;
+            level += 1;
+        }
+    }
+
+    fn remove(&mut self, outpoint: &Hash) {
+        let Some((position, _)) = self.unspent.remove(outpoint) else {
+            return;
+        };
+        let mut position = position as usize;
+        self.rows[0][position] = Hash::zero();
+        let mut level = 0;
+        while self
+            .rows
+            .get(level + 1)
+            .is_some_and(|row| row.len() > position / 2)
+        {
+            let left = position & !1;
+            let parent = parent_hash(&self.rows[level][left], &self.rows[level][left + 1]);
+            position /= 2;
+            level += 1;
+            self.rows[level][position] = parent;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        crypto::{PrivateKey, Signature},
+        error::BtcError,
+        testutil::TestBlock,
+        types::{Transaction, TransactionInput},
+    };
+    use uuid::Uuid;
+
+    fn coinbase(key: &PrivateKey, values: &[u64]) -> Transaction {
+        let outputs = values
+            .iter()
+            .map(|value| TransactionOutput::new(*value, Uuid::new_v4(), key.public_key()))
+            .collect();
+        Transaction::new(vec![], outputs)
+    }
+
+    fn spend(key: &PrivateKey, outputs: &[&TransactionOutput], to: &PrivateKey) -> Transaction {
+        let inputs = outputs
+            .iter()
+            .map(|output| {
+                TransactionInput::new(output.hash(), Signature::sign_output(&output.hash(), key))
+            })
+            .collect();
+        let value = outputs.iter().map(|output| output.value()).sum::<u64>() - 10;
+        Transaction::new(
+            inputs,
+            vec![TransactionOutput::new(
+                value,
+                Uuid::new_v4(),
+                to.public_key(),
+            )],
+        )
+    }
+
+    #[test]
+    fn test_stateless_client_follows_forest() {
+        let alice = PrivateKey::from_seed(b"alice");
+        let bob = PrivateKey::from_seed(b"bob");
+        let mut forest = Forest::default();
+        let mut stump = Stump::default();
+
+        let first =
+            TestBlock::with_transactions(vec![coinbase(&alice, &[1_000, 2_000, 3_000])]).build();
+        let [a, b, c] = [0, 1, 2].map(|i| first.transactions()[0].outputs()[i].clone());
+        assert!(forest.connect_block(&first).is_empty());
+        stump.connect_block(&first, &[]).unwrap();
+        assert_eq!(stump, forest.stump());
+        let stale_a = forest.prove(&a.hash()).unwrap();
+
+        // spends one output, then another along with one the same block
+        // created
+        let pay = spend(&alice, &[&a], &bob);
+        let d = pay.outputs()[0].clone();
+        let second = TestBlock::with_transactions(vec![
+            coinbase(&alice, &[5_000]),
+            pay,
+            spend(&alice, &[&b], &alice),
+            spend(&bob, &[&d], &alice),
+        ])
+        .prev_block_hash(first.hash())
+        .build();
+        let proofs = forest.connect_block(&second);
+        assert_eq!(proofs.len(), 3);
+        assert_eq!(forest.unspent(), 4);
+        stump.connect_block(&second, &proofs).unwrap();
+        assert_eq!(stump, forest.stump());
+        assert_eq!(stump.height, BlockHeight::new(2));
+
+        // unspent outputs prove, spent ones don't
+        let proof_c = forest.prove(&c.hash()).unwrap();
+        assert!(stump.verify(&proof_c));
+        assert!(forest.prove(&a.hash()).is_none());
+        assert!(!stump.verify(&stale_a));
+
+        let fee = stump
+            .validate_spend(&spend(&alice, &[&c], &bob), std::slice::from_ref(&proof_c))
+            .unwrap();
+        assert_eq!(fee, 10);
+        assert!(matches!(
+            stump.validate_spend(&spend(&bob, &[&c], &bob), std::slice::from_ref(&proof_c)),
+            Err(BtcError::InvalidSignature)
+        ));
+        assert!(matches!(
+            stump.validate_spend(&spend(&alice, &[&a], &bob), &[stale_a]),
+            Err(BtcError::InvalidTransactionInput)
+        ));
+
+        // a block missing a proof, or not on the tip, changes nothing
+        let third = TestBlock::with_transactions(vec![spend(&alice, &[&c], &bob)])
+            .prev_block_hash(second.hash())
+            .build();
+        assert!(stump.connect_block(&third, &[]).is_err());
+        let orphan = TestBlock::with_transactions(vec![coinbase(&alice, &[1])]).build();
+        assert!(matches!(
+            stump.connect_block(&orphan, &[]),
+            Err(BtcError::InvalidBlock)
+        ));
+        assert_eq!(stump, forest.stump());
+        let proofs = forest.connect_block(&third);
+        stump.connect_block(&third, &proofs).unwrap();
+        assert_eq!(stump, forest.stump());
+    }
+}
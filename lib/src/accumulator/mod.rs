@@ -0,0 +1,9 @@
+#[cfg(feature = "utxo-accumulator")]
+mod forest;
+mod proof;
+mod stump;
+
+#[cfg(feature = "utxo-accumulator")]
+pub use forest::*;
+pub use proof::*;
+pub use stump::*;
@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{custom_sha_types::Hash, types::TransactionOutput};
+
+/// Hash of the node above `left` and `right`, as the Merkle root pairs
+/// hashes.
+pub(crate) fn parent_hash(left: &Hash, right: &Hash) -> Hash {
+    Hash::hash(&[*left, *right])
+}
+
+/// The tree of a forest of `leaves` leaves holding `position`: the index
+/// of its root, highest tree first, its height and its first position.
+/// There is a perfect tree for every bit set in `leaves`.
+pub(crate) fn tree_of(leaves: u64, position: u64) -> Option<(usize, u32, u64)> {
+    let mut offset = 0;
+    let mut index = 0;
+    for height in (0..u64::BITS).rev() {
+        if leaves & (1 << height) == 0 {
+            continue;
+        }
+        if position < offset + (1 << height) {
+            return Some((index, height, offset));
+        }
+        offset += 1 << height;
+        index += 1;
+    }
+    None
+}
+
+/// The hashes next to a leaf on the way up to the root of its tree,
+/// lowest first, like a `MerkleProof` for one tree of the forest.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AccumulatorProof {
+    /// Position of the leaf, counting every leaf ever added
+    pub position: u64,
+    pub siblings: Vec<Hash>,
+}
+
+impl AccumulatorProof {
+    /// The root the proof leads to from `leaf`, for a tree starting at
+    /// `offset`.
+    pub(crate) fn root_from(&self, leaf: &Hash, offset: u64) -> Hash {
+        let mut hash = *leaf;
+        let mut position = self.position - offset;
+        for sibling in &self.siblings {
+            hash = if position.is_multiple_of(2) {
+                parent_hash(&hash, sibling)
+            } else {
+                parent_hash(sibling, &hash)
+            };
+            position /= 2;
+        }
+        hash
+    }
+}
+
+/// An unspent output with the proof that it is in the accumulator, all a
+/// stateless client needs to check a spend of it: the outpoint is the
+/// hash of the output, which is the leaf.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UtxoProof {
+    pub output: TransactionOutput,
+    pub proof: AccumulatorProof,
+}
+
+impl UtxoProof {
+    /// The hash inputs spend the output by.
+    pub fn outpoint(&self) -> Hash {
+        self.output.hash()
+    }
+}
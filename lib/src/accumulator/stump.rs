@@ -0,0 +1,197 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    accumulator::{AccumulatorProof, UtxoProof, parent_hash, tree_of},
+    custom_sha_types::Hash,
+    error::{BtcError, Result},
+    types::{Block, BlockHeight, Transaction},
+};
+
+/// What a stateless client keeps of the UTXO set: the roots of the
+/// accumulator forest, a perfect Merkle tree for every bit set in the
+/// number of leaves, at a block. A few hundred bytes however large the
+/// UTXO set is; spends are checked against it with a `UtxoProof` each.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Stump {
+    /// Blocks applied so far
+    pub height: BlockHeight,
+    /// Hash of the last block applied, zero before the genesis block
+    pub tip: Hash,
+    /// Leaves ever added, spent ones included
+    pub leaves: u64,
+    /// Highest tree first
+    pub roots: Vec<Hash>,
+}
+
+impl Default for Stump {
+    fn default() -> Self {
+        Stump {
+            height: BlockHeight::GENESIS,
+            tip: Hash::zero(),
+            leaves: 0,
+            roots: vec![],
+        }
+    }
+}
+
+impl Stump {
+    /// Whether the output of `utxo` is unspent at this block.
+    pub fn verify(&self, utxo: &UtxoProof) -> bool {
+        self.tree_root(&utxo.outpoint(), &utxo.proof).is_some()
+    }
+
+    /// Adds a new output by its outpoint, merging the trees of equal
+    /// height it completes.
+    pub fn add(&mut self, leaf: Hash) {
+        let mut hash = leaf;
+        let mut height = 0;
+        while self.leaves & (1 << height) != 0 {
+            let left = self.roots.pop().expect("BUG: a root for every bit set");
+            hash = parent_hash(&left, &hash);
+            height += 1;
+        }
+        self.roots.push(hash);
+        self.leaves += 1;
+    }
+
+    /// Spends the output of `utxo`, replacing its leaf with a zero hash.
+    /// Fails if the proof doesn't hold at this block. Proofs of other
+    /// outputs of the same tree don't hold afterwards.
+    pub fn remove(&mut self, utxo: &UtxoProof) -> bool {
+        let Some((index, offset)) = self.tree_root(&utxo.outpoint(), &utxo.proof) else {
+            return false;
+        };
+        self.roots[index] = utxo.proof.root_from(&Hash::zero(), offset);
+        true
+    }
+
+    /// Checks a transaction against this block without the UTXO set:
+    /// `proofs` holds the output every input spends, in order. Each must
+    /// be unspent, spent once and signed for by its key, and together
+    /// they must cover the outputs. Returns the fee.
+    pub fn validate_spend(&self, transaction: &Transaction, proofs: &[UtxoProof]) -> Result<u64> {
+        if transaction.inputs().is_empty() || transaction.inputs().len() != proofs.len() {
+            return Err(BtcError::InvalidTransaction);
+        }
+        let mut spent = HashSet::new();
+        let mut input_value: u64 = 0;
+        for (input, utxo) in transaction.inputs().iter().zip(proofs) {
+            let outpoint = input.prev_transaction_output_hash();
+            if utxo.outpoint() != *outpoint || !spent.insert(*outpoint) || !self.verify(utxo) {
+                return Err(BtcError::InvalidTransactionInput);
+            }
+            if !input.signature().verify(outpoint, utxo.output.pubkey()) {
+                return Err(BtcError::InvalidSignature);
+            }
+            input_value = input_value
+                .checked_add(utxo.output.value())
+                .ok_or(BtcError::InvalidTransactionInput)?;
+        }
+        let output_value = transaction
+            .outputs()
+            .iter()
+            .try_fold(0u64, |sum, output| sum.checked_add(output.value()))
+            .ok_or(BtcError::InvalidTransactionOutput)?;
+        input_value
+            .checked_sub(output_value)
+            .ok_or(BtcError::InvalidTransaction)
+    }
+
+    /// Applies `block`, the one after `tip`, as the node's `Forest` does:
+    /// transaction by transaction, the spent outputs removed and then the
+    /// new ones added. `proofs` are the ones `Forest::connect_block`
+    /// returned, each holding just before its spend. Checks that every
+    /// output spent is unspent and signed for, nothing else of the
+    /// consensus rules. Leaves the stump as it was on failure.
+    pub fn connect_block(&mut self, block: &Block, proofs: &[UtxoProof]) -> Result<()> {
+        if self.height > BlockHeight::GENESIS && *block.header().prev_block_hash() != self.tip {
+            return Err(BtcError::InvalidBlock);
+        }
+        let mut next = self.clone();
+        let mut proofs = proofs.iter();
+        for transaction in block.transactions() {
+            for input in transaction.inputs() {
+                let utxo = proofs.next().ok_or(BtcError::InvalidBlock)?;
+                let outpoint = input.prev_transaction_output_hash();
+                if utxo.outpoint() != *outpoint {
+                    return Err(BtcError::InvalidTransactionInput);
+                }
+                if !input.signature().verify(outpoint, utxo.output.pubkey()) {
+                    return Err(BtcError::InvalidSignature);
+                }
+                if !next.remove(utxo) {
+                    return Err(BtcError::InvalidTransactionInput);
+                }
+            }
+            for output in transaction.outputs() {
+                next.add(output.hash());
+            }
+        }
+        if proofs.next().is_some() {
+            return Err(BtcError::InvalidBlock);
+        }
+        next.height = next.height.next();
+        next.tip = block.hash();
+        *self = next;
+        Ok(())
+    }
+
+    // index of the root `proof` leads to from `leaf` and the first
+    // position of its tree, if it is one of the roots; spent leaves
+    // prove nothing
+    fn tree_root(&self, leaf: &Hash, proof: &AccumulatorProof) -> Option<(usize, u64)> {
+        if *leaf == Hash::zero() {
+            return None;
+        }
+        let (index, height, offset) = tree_of(self.leaves, proof.position)?;
+        (proof.siblings.len() == height as usize
+            && self.roots.get(index) == Some(&proof.root_from(leaf, offset)))
+        .then_some((index, offset))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{crypto::PrivateKey, types::TransactionOutput};
+    use uuid::Uuid;
+
+    fn utxo(output: &TransactionOutput, position: u64, siblings: Vec<Hash>) -> UtxoProof {
+        UtxoProof {
+            output: output.clone(),
+            proof: AccumulatorProof { position, siblings },
+        }
+    }
+
+    #[test]
+    fn test_stump_add_verify_remove() {
+        let key = PrivateKey::from_seed(b"alice").public_key();
+        let outputs: Vec<_> = (1..=3)
+            .map(|value| TransactionOutput::new(value, Uuid::new_v4(), key.clone()))
+            .collect();
+        let [a, b, c] = [0, 1, 2].map(|i| outputs[i].hash());
+        let mut stump = Stump::default();
+        for output in &outputs {
+            stump.add(output.hash());
+        }
+        // a tree of two and one of the third leaf alone
+        assert_eq!(stump.leaves, 3);
+        assert_eq!(stump.roots, vec![parent_hash(&a, &b), c]);
+
+        let proof_a = utxo(&outputs[0], 0, vec![b]);
+        let proof_c = utxo(&outputs[2], 2, vec![]);
+        assert!(stump.verify(&proof_a));
+        assert!(stump.verify(&proof_c));
+        // not at another position, or for another output
+        assert!(!stump.verify(&utxo(&outputs[0], 1, vec![b])));
+        assert!(!stump.verify(&utxo(&outputs[1], 0, vec![b])));
+
+        assert!(stump.remove(&proof_a));
+        assert!(!stump.verify(&proof_a));
+        assert!(!stump.remove(&proof_a));
+        assert_eq!(stump.roots[0], parent_hash(&Hash::zero(), &b));
+        assert!(stump.verify(&utxo(&outputs[1], 1, vec![Hash::zero()])));
+    }
+}
@@ -0,0 +1,185 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    assets::AssetRecord,
+    crypto::PublicKey,
+    custom_sha_types::Hash,
+    types::{Block, BlockHeight, Blockchain, Transaction},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssetInfo {
+    pub ticker: String,
+    pub supply: u64,
+}
+
+/// Asset balances derived by replaying the asset records in a chain.
+#[derive(Debug, Clone, Default)]
+pub struct AssetLedger {
+    assets: HashMap<Hash, AssetInfo>,
+    balances: HashMap<Hash, BTreeMap<PublicKey, u64>>,
+    // owner of every output seen so far, to tell who is spending
+    owners: HashMap<Hash, PublicKey>,
+    height: BlockHeight,
+}
+
+impl AssetLedger {
+    /// Number of blocks applied so far.
+    pub fn height(&self) -> BlockHeight {
+        self.height
+    }
+
+    /// Applies the blocks of `blockchain` this ledger hasn't seen yet.
+    pub fn index(&mut self, blockchain: &Blockchain) {
+        for (_, block) in blockchain.iter_blocks_in(self.height..) {
+            self.apply_block(block);
+        }
+    }
+
+    pub fn apply_block(&mut self, block: &Block) {
+        for transaction in block.transactions() {
+            let sender = transaction
+                .inputs()
+                .first()
+                .and_then(|input| self.owners.get(input.prev_transaction_output_hash()))
+                .cloned();
+            for output in transaction.outputs() {
+                self.owners.insert(output.hash(), output.pubkey().clone());
+            }
+            for data_output in transaction.data_outputs() {
+                if let Some(record) = AssetRecord::from_data_output(data_output) {
+                    self.apply_record(transaction, sender.as_ref(), record);
+                }
+            }
+        }
+        self.height = self.height.next();
+    }
+
+    fn apply_record(
+        &mut self,
+        transaction: &Transaction,
+        sender: Option<&PublicKey>,
+        record: AssetRecord,
+    ) {
+        match record {
+            AssetRecord::Issue {
+                ticker,
+                supply,
+                output,
+            } => {
+                let asset = transaction.hash();
+                let Some(output) = transaction.outputs().get(output as usize) else {
+                    return;
+                };
+                if self.assets.contains_key(&asset) {
+                    return;
+                }
+                self.assets.insert(asset, AssetInfo { ticker, supply });
+                self.balances
+                    .entry(asset)
+                    .or_default()
+                    .insert(output.pubkey().clone(), supply);
+            }
+            AssetRecord::Transfer {
+                asset,
+                amount,
+                output,
+            } => {
+                let (Some(sender), Some(output), Some(balances)) = (
+                    sender,
+                    transaction.outputs().get(output as usize),
+                    self.balances.get_mut(&asset),
+                ) else {
+                    return;
+                };
+                match balances.get_mut(sender) {
+                    Some(balance) if *balance >= amount => *balance -= amount,
+                    _ => return,
+                }
+                *balances.entry(output.pubkey().clone()).or_default() += amount;
+            }
+        }
+    }
+
+    pub fn asset(&self, asset: &Hash) -> Option<&AssetInfo> {
+        self.assets.get(asset)
+    }
+
+    pub fn balance(&self, asset: &Hash, owner: &PublicKey) -> u64 {
+        self.balances
+            .get(asset)
+            .and_then(|balances| balances.get(owner))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Every asset `owner` holds a non-zero amount of.
+    pub fn balances_of(&self, owner: &PublicKey) -> Vec<(Hash, &AssetInfo, u64)> {
+        self.balances
+            .iter()
+            .filter_map(|(asset, balances)| {
+                let amount = *balances.get(owner)?;
+                (amount > 0).then(|| (*asset, &self.assets[asset], amount))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        crypto::{PrivateKey, Signature},
+        testutil::TestBlock,
+        types::{TransactionInput, TransactionOutput},
+    };
+    use uuid::Uuid;
+
+    #[test]
+    fn test_issue_and_transfer() {
+        let alice = PrivateKey::default();
+        let bob = PrivateKey::default();
+
+        let alice_output = TransactionOutput::new(1000, Uuid::new_v4(), alice.public_key());
+        let issue = Transaction::new(vec![], vec![alice_output.clone()]).with_data_output(
+            AssetRecord::Issue {
+                ticker: "GOLD".to_string(),
+                supply: 100,
+                output: 0,
+            }
+            .to_data_output()
+            .unwrap(),
+        );
+        let asset = issue.hash();
+
+        let spend_alice = TransactionInput::new(
+            alice_output.hash(),
+            Signature::sign_output(&alice_output.hash(), &alice),
+        );
+        let transfer_record = |amount| {
+            AssetRecord::Transfer {
+                asset,
+                amount,
+                output: 0,
+            }
+            .to_data_output()
+            .unwrap()
+        };
+        let bob_output = TransactionOutput::new(1000, Uuid::new_v4(), bob.public_key());
+        let transfer = Transaction::new(vec![spend_alice.clone()], vec![bob_output.clone()])
+            .with_data_output(transfer_record(30));
+        // more than alice owns, ignored
+        let overdraft = Transaction::new(vec![spend_alice], vec![bob_output])
+            .with_data_output(transfer_record(1000));
+
+        let mut ledger = AssetLedger::default();
+        ledger.apply_block(&TestBlock::with_transactions(vec![issue]).build());
+        ledger.apply_block(&TestBlock::with_transactions(vec![transfer, overdraft]).build());
+
+        assert_eq!(ledger.height(), BlockHeight::new(2));
+        assert_eq!(ledger.asset(&asset).unwrap().ticker, "GOLD");
+        assert_eq!(ledger.balance(&asset, &alice.public_key()), 70);
+        assert_eq!(ledger.balance(&asset, &bob.public_key()), 30);
+        assert_eq!(ledger.balances_of(&bob.public_key()).len(), 1);
+    }
+}
@@ -0,0 +1,5 @@
+mod ledger;
+mod record;
+
+pub use ledger::*;
+pub use record::*;
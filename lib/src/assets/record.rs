@@ -0,0 +1,131 @@
+use crate::{custom_sha_types::Hash, types::DataOutput};
+
+// marks a data output as an asset record
+const MAGIC: &[u8; 2] = b"AS";
+const ISSUE: u8 = 1;
+const TRANSFER: u8 = 2;
+// magic, kind, output index, amount
+const HEADER_SIZE: usize = 2 + 1 + 1 + 8;
+
+/// Maximum length in bytes of an asset ticker
+pub const MAX_TICKER_SIZE: usize = 16;
+
+/// A colored-coin style asset operation, carried in a data output.
+///
+/// Amounts are credited to the public key of the transaction's regular
+/// output at `output`; transfers are debited from the owner of the
+/// transaction's first input. Records that don't add up (unknown asset,
+/// insufficient balance, missing output) are ignored by the ledger.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssetRecord {
+    /// Creates a new asset, identified by the hash of the issuing transaction
+    Issue {
+        ticker: String,
+        supply: u64,
+        output: u8,
+    },
+    /// Moves units of an existing asset
+    Transfer {
+        asset: Hash,
+        amount: u64,
+        output: u8,
+    },
+}
+
+impl AssetRecord {
+    /// Encodes the record into a data output. Returns `None` if the
+    /// ticker is longer than `MAX_TICKER_SIZE`.
+    pub fn to_data_output(&self) -> Option<DataOutput> {
+        let mut data = MAGIC.to_vec();
+        match self {
+            AssetRecord::Issue {
+                ticker,
+                supply,
+                output,
+            } => {
+                if ticker.len() > MAX_TICKER_SIZE {
+                    return None;
+                }
+                data.push(ISSUE);
+                data.push(*output);
+                data.extend_from_slice(&supply.to_be_bytes());
+                data.extend_from_slice(ticker.as_bytes());
+            }
+            AssetRecord::Transfer {
+                asset,
+                amount,
+                output,
+            } => {
+                data.push(TRANSFER);
+                data.push(*output);
+                data.extend_from_slice(&amount.to_be_bytes());
+                data.extend_from_slice(&asset.as_bytes());
+            }
+        }
+        Some(DataOutput::new(data))
+    }
+
+    /// Decodes a data output, or returns `None` if it isn't a well-formed asset record.
+    pub fn from_data_output(data_output: &DataOutput) -> Option<Self> {
+        let data = data_output.data();
+        if data.len() < HEADER_SIZE || &data[..2] != MAGIC {
+            return None;
+        }
+        let output = data[3];
+        let amount = u64::from_be_bytes(data[4..HEADER_SIZE].try_into().ok()?);
+        let rest = &data[HEADER_SIZE..];
+        match data[2] {
+            ISSUE if rest.len() <= MAX_TICKER_SIZE => Some(AssetRecord::Issue {
+                ticker: String::from_utf8(rest.to_vec()).ok()?,
+                supply: amount,
+                output,
+            }),
+            TRANSFER => Some(AssetRecord::Transfer {
+                asset: Hash::from_bytes(rest.try_into().ok()?),
+                amount,
+                output,
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_asset_record_roundtrip() {
+        let records = [
+            AssetRecord::Issue {
+                ticker: "GOLD".to_string(),
+                supply: 1_000_000,
+                output: 0,
+            },
+            AssetRecord::Transfer {
+                asset: Hash::hash(&"issuing tx"),
+                amount: 250,
+                output: 1,
+            },
+        ];
+        for record in records {
+            let data_output = record.to_data_output().unwrap();
+            assert!(data_output.is_within_size_limit());
+            assert_eq!(AssetRecord::from_data_output(&data_output), Some(record));
+        }
+    }
+
+    #[test]
+    fn test_asset_record_rejects_foreign_data() {
+        assert_eq!(
+            AssetRecord::from_data_output(&DataOutput::new(b"hello world!".to_vec())),
+            None
+        );
+        let long_ticker = AssetRecord::Issue {
+            ticker: "X".repeat(MAX_TICKER_SIZE + 1),
+            supply: 1,
+            output: 0,
+        };
+        assert!(long_ticker.to_data_output().is_none());
+    }
+}
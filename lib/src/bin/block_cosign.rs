@@ -0,0 +1,55 @@
+use btclib::{crypto::PrivateKey, types::Block, utils::Saveable};
+
+use clap::{Arg, Command};
+use log::{error, info};
+use std::process::exit;
+
+fn main() {
+    env_logger::init();
+
+    let matches = Command::new("block_cosign")
+        .version("1.0")
+        .author("Charalampos Polychronakis <polychronakis.h@gmail.com>")
+        .about("Adds an authority co-signature to a mined block file")
+        .arg(
+            Arg::new("block_file")
+                .help("Path to the block file, signed in place")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            Arg::new("private_key_file")
+                .help("Path to the authority's private key file")
+                .required(true)
+                .index(2),
+        )
+        .get_matches();
+
+    let path = matches.get_one::<String>("block_file").unwrap();
+    let key_file = matches.get_one::<String>("private_key_file").unwrap();
+
+    let mut block = match Block::load_from_file(path) {
+        Ok(block) => block,
+        Err(e) => {
+            error!("Failed to load block from '{}': {}", path, e);
+            exit(1);
+        }
+    };
+    let private_key = match PrivateKey::load_from_file(key_file) {
+        Ok(key) => key,
+        Err(e) => {
+            error!("Failed to load private key from '{}': {}", key_file, e);
+            exit(1);
+        }
+    };
+    block.cosign(&private_key);
+    if let Err(e) = block.save_to_file(path) {
+        error!("Failed to save block: {}", e);
+        exit(1);
+    }
+    info!(
+        "Block {:x?} now carries {} authority signatures",
+        block.hash(),
+        block.authority_signatures().len()
+    );
+}
@@ -3,14 +3,12 @@ use std::process::exit;
 use btclib::{
     crypto::PrivateKey,
     custom_sha_types::Hash,
-    types::{Block, BlockHeader, Transaction, TransactionOutput},
-    utils::{MerkleRoot, Saveable},
+    types::{BlockBuilder, BlockHeight},
+    utils::Saveable,
 };
 
-use chrono::Utc;
 use clap::{Arg, Command};
 use log::error;
-use uuid::Uuid;
 
 fn main() {
     env_logger::init();
@@ -29,19 +27,8 @@ fn main() {
 
     let path = matches.get_one::<String>("block_file").unwrap();
     let private_key = PrivateKey::default();
-    let transactions = vec![Transaction::new(
-        vec![],
-        vec![TransactionOutput::new(
-            btclib::INITIAL_REWARD * 10u64.pow(8),
-            Uuid::new_v4(),
-            private_key.public_key(),
-        )],
-    )];
-    let merkle_root = MerkleRoot::calculate(&transactions);
-    let block = Block::new(
-        BlockHeader::new(Utc::now(), 0, Hash::zero(), merkle_root, btclib::MIN_TARGET),
-        transactions,
-    );
+    let block = BlockBuilder::new(Hash::zero(), BlockHeight::GENESIS, btclib::MIN_TARGET)
+        .build(private_key.public_key());
     match block.save_to_file(path) {
         Ok(tx) => tx,
         Err(e) => {
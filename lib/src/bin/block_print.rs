@@ -1,6 +1,6 @@
 use btclib::{types::Block, utils::Saveable};
 
-use clap::{Arg, Command};
+use clap::{Arg, ArgAction, Command};
 use log::{error, info};
 use std::{fs::File, process::exit};
 
@@ -14,21 +14,45 @@ pub fn main() {
         .arg(
             Arg::new("block_file")
                 .help("Path to the block file to print")
-                .required(true)
+                .required_unless_present("from_hex")
                 .index(1),
         )
+        .arg(
+            Arg::new("from_hex")
+                .long("from-hex")
+                .value_name("HEX")
+                .help("Print this hex-encoded block instead of a file")
+                .conflicts_with("block_file"),
+        )
+        .arg(
+            Arg::new("hex")
+                .long("hex")
+                .action(ArgAction::SetTrue)
+                .help("Print the block's hex encoding to stdout"),
+        )
         .get_matches();
 
-    let path = matches.get_one::<String>("block_file").unwrap();
-
-    match File::open(path) {
-        Ok(file) => {
-            let block = Block::load(file).expect("Failed to load block");
-            info!("{:#?}", block);
+    let block = if let Some(encoded) = matches.get_one::<String>("from_hex") {
+        match Block::from_hex(encoded) {
+            Ok(block) => block,
+            Err(e) => {
+                error!("Failed to decode block: {}", e);
+                exit(1);
+            }
         }
-        Err(e) => {
-            error!("Failed to open file '{}': {}", path, e);
-            exit(1);
+    } else {
+        let path = matches.get_one::<String>("block_file").unwrap();
+        match File::open(path) {
+            Ok(file) => Block::load(file).expect("Failed to load block"),
+            Err(e) => {
+                error!("Failed to open file '{}': {}", path, e);
+                exit(1);
+            }
         }
+    };
+    if matches.get_flag("hex") {
+        println!("{}", block.to_hex());
+    } else {
+        info!("{:#?}", block);
     }
 }
@@ -0,0 +1,166 @@
+use std::process::exit;
+
+use btclib::{
+    U256,
+    crypto::{PrivateKey, PublicKey},
+    types::ChainParams,
+    utils::Saveable,
+};
+
+use chrono::{DateTime, Utc};
+use clap::{Arg, Command};
+use log::{error, info};
+
+// nonces tried between progress messages
+const MINING_STEPS: usize = 1_000_000;
+
+fn parse_timestamp(value: &str) -> Option<DateTime<Utc>> {
+    match value.parse::<i64>() {
+        Ok(seconds) => DateTime::from_timestamp(seconds, 0),
+        Err(_) => DateTime::parse_from_rfc3339(value)
+            .ok()
+            .map(|time| time.with_timezone(&Utc)),
+    }
+}
+
+fn main() {
+    env_logger::init();
+
+    let matches = Command::new("genesis_gen")
+        .version("1.0")
+        .author("Charalampos Polychronakis <polychronakis.h@gmail.com>")
+        .about(
+            "Generates the genesis block of a new network from its parameters, the same \
+             block every time, and prints the constants to embed",
+        )
+        .arg(
+            Arg::new("key")
+                .long("key")
+                .value_name("PUBLIC_KEY_FILE")
+                .help("Public key file the genesis coinbase pays")
+                .required(true),
+        )
+        .arg(
+            Arg::new("timestamp")
+                .long("timestamp")
+                .value_name("TIME")
+                .help("Genesis timestamp, as unix seconds or RFC 3339")
+                .required(true),
+        )
+        .arg(
+            Arg::new("target")
+                .long("target")
+                .value_name("HEX")
+                .help("Genesis target in hex [default: MIN_TARGET]"),
+        )
+        .arg(
+            Arg::new("message")
+                .long("message")
+                .value_name("TEXT")
+                .help("Tag of the genesis coinbase, e.g. a headline of the day"),
+        )
+        .arg(
+            Arg::new("validator_key")
+                .long("validator-key")
+                .value_name("PRIVATE_KEY_FILE")
+                .help(
+                    "Sign the block with this proof-of-authority validator's key \
+                     instead of mining it",
+                ),
+        )
+        .arg(
+            Arg::new("block_file")
+                .help("Also save the block to this file")
+                .index(1),
+        )
+        .get_matches();
+
+    let key_file = matches.get_one::<String>("key").unwrap();
+    let payout = match PublicKey::load_from_file(key_file) {
+        Ok(key) => key,
+        Err(e) => {
+            error!("Failed to load public key from '{}': {}", key_file, e);
+            exit(1);
+        }
+    };
+    let timestamp = matches.get_one::<String>("timestamp").unwrap();
+    let Some(genesis_time) = parse_timestamp(timestamp) else {
+        error!("Invalid timestamp '{}'", timestamp);
+        exit(1);
+    };
+    let target = match matches.get_one::<String>("target") {
+        Some(hex) => match U256::from_str_radix(hex.trim_start_matches("0x"), 16) {
+            Ok(target) if !target.is_zero() => target,
+            _ => {
+                error!("Invalid target '{}'", hex);
+                exit(1);
+            }
+        },
+        None => btclib::MIN_TARGET,
+    };
+    let genesis_message = matches
+        .get_one::<String>("message")
+        .map(|message| message.as_bytes().to_vec());
+    if genesis_message
+        .as_ref()
+        .is_some_and(|message| message.len() > btclib::MAX_COINBASE_TAG_SIZE)
+    {
+        error!(
+            "The message is longer than {} bytes",
+            btclib::MAX_COINBASE_TAG_SIZE
+        );
+        exit(1);
+    }
+
+    let params = ChainParams {
+        genesis_time,
+        target,
+        genesis_message,
+    };
+    let mut block = params.genesis_block(&payout);
+    if let Some(validator_file) = matches.get_one::<String>("validator_key") {
+        let validator = match PrivateKey::load_from_file(validator_file) {
+            Ok(key) => key,
+            Err(e) => {
+                error!(
+                    "Failed to load private key from '{}': {}",
+                    validator_file, e
+                );
+                exit(1);
+            }
+        };
+        block.cosign(&validator);
+    } else {
+        // always from nonce 0, so the same params find the same nonce
+        while !block.mine(MINING_STEPS) {
+            info!("Tried {} nonces", block.header().nonce());
+        }
+    }
+
+    if let Some(path) = matches.get_one::<String>("block_file")
+        && let Err(e) = block.save_to_file(path)
+    {
+        error!("Failed to save block: {}", e);
+        exit(1);
+    }
+
+    let header = block.header();
+    println!(
+        "pub const GENESIS_HASH: &str = \"{}\";",
+        hex::encode(block.hash().as_bytes())
+    );
+    println!(
+        "pub const GENESIS_TIMESTAMP: i64 = {};",
+        header.timestamp().timestamp()
+    );
+    println!("pub const GENESIS_NONCE: u64 = {};", header.nonce());
+    println!(
+        "pub const GENESIS_MERKLE_ROOT: &str = \"{}\";",
+        hex::encode(header.merkle_root().as_hash().as_bytes())
+    );
+    println!(
+        "pub const GENESIS_TARGET: &str = \"{:064x}\";",
+        header.target()
+    );
+    println!("pub const GENESIS_BLOCK: &str = \"{}\";", block.to_hex());
+}
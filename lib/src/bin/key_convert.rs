@@ -0,0 +1,82 @@
+use btclib::crypto::{KeyFormat, PrivateKey};
+use btclib::utils::Saveable;
+use clap::{Arg, Command};
+
+pub fn main() {
+    env_logger::init();
+
+    let matches = Command::new("Key Converter")
+        .version("1.0")
+        .about("Exports private key files as WIF, hex or PEM, and imports them back")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("export")
+                .about("Prints a saved private key in the given format")
+                .arg(
+                    Arg::new("file")
+                        .help("Private key file (e.g. 'mykey.priv.cbor')")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .help("wif, hex or pem")
+                        .default_value("wif"),
+                ),
+        )
+        .subcommand(
+            Command::new("import")
+                .about("Saves an encoded private key as '<name>.priv.cbor' and '<name>.pub.pem'")
+                .arg(
+                    Arg::new("key")
+                        .help("The encoded private key")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("name")
+                        .help("Base name for the key files")
+                        .required(true)
+                        .index(2),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .help("wif, hex or pem; detected when omitted"),
+                ),
+        )
+        .get_matches();
+
+    match matches.subcommand() {
+        Some(("export", matches)) => {
+            let file = matches.get_one::<String>("file").unwrap();
+            let format: KeyFormat = matches
+                .get_one::<String>("format")
+                .unwrap()
+                .parse()
+                .unwrap_or_else(|e| panic!("{e}"));
+            let private_key = PrivateKey::load_from_file(file).expect("Failed to load private key");
+            println!("{}", private_key.export(format));
+        }
+        Some(("import", matches)) => {
+            let key = matches.get_one::<String>("key").unwrap();
+            let name = matches.get_one::<String>("name").unwrap();
+            let format = match matches.get_one::<String>("format") {
+                Some(format) => format.parse().unwrap_or_else(|e| panic!("{e}")),
+                None => KeyFormat::detect(key),
+            };
+            let private_key =
+                PrivateKey::import(key, format).expect("Failed to decode private key");
+            private_key
+                .save_to_file(format!("{}.priv.cbor", name))
+                .unwrap();
+            private_key
+                .public_key()
+                .save_to_file(format!("{}.pub.pem", name))
+                .unwrap();
+            println!("imported {format} key as {name}.priv.cbor / {name}.pub.pem");
+        }
+        _ => unreachable!(),
+    }
+}
@@ -0,0 +1,78 @@
+use std::process::exit;
+
+use btclib::crypto::{PrivateKey, PublicKey, Signature};
+use btclib::utils::Saveable;
+use clap::{Arg, Command};
+
+pub fn main() {
+    env_logger::init();
+
+    let matches = Command::new("Message Signer")
+        .version("1.0")
+        .about("Signs messages with a private key and verifies them, to prove control of a key off chain")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("sign")
+                .about("Prints the signature of a message in hex")
+                .arg(
+                    Arg::new("key")
+                        .help("Private key file (e.g. 'mykey.priv.cbor')")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("message")
+                        .help("The message to sign")
+                        .required(true)
+                        .index(2),
+                ),
+        )
+        .subcommand(
+            Command::new("verify")
+                .about("Checks a signature of a message, exiting with 1 if it doesn't match")
+                .arg(
+                    Arg::new("key")
+                        .help("Public key file (e.g. 'mykey.pub.pem') or public key hex")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("signature")
+                        .help("The signature in hex")
+                        .required(true)
+                        .index(2),
+                )
+                .arg(
+                    Arg::new("message")
+                        .help("The signed message")
+                        .required(true)
+                        .index(3),
+                ),
+        )
+        .get_matches();
+
+    match matches.subcommand() {
+        Some(("sign", matches)) => {
+            let file = matches.get_one::<String>("key").unwrap();
+            let message = matches.get_one::<String>("message").unwrap();
+            let private_key = PrivateKey::load_from_file(file).expect("Failed to load private key");
+            println!("{}", private_key.sign_message(message.as_bytes()).to_hex());
+        }
+        Some(("verify", matches)) => {
+            let key = matches.get_one::<String>("key").unwrap();
+            let signature = matches.get_one::<String>("signature").unwrap();
+            let message = matches.get_one::<String>("message").unwrap();
+            let public_key = PublicKey::from_hex(key)
+                .or_else(|_| PublicKey::load_from_file(key))
+                .expect("Failed to load public key");
+            let signature = Signature::from_hex(signature).expect("Failed to decode signature");
+            if public_key.verify_message(message.as_bytes(), &signature) {
+                println!("valid signature by {}", public_key.address());
+            } else {
+                println!("invalid signature");
+                exit(1);
+            }
+        }
+        _ => unreachable!(),
+    }
+}
@@ -1,6 +1,6 @@
 use btclib::{types::Transaction, utils::Saveable};
 
-use clap::{Arg, Command};
+use clap::{Arg, ArgAction, Command};
 use log::{error, info};
 use std::{fs::File, process::exit};
 
@@ -13,23 +13,49 @@ fn main() {
         .arg(
             Arg::new("tx_file")
                 .help("Path to the transaction file to print")
-                .required(true)
+                .required_unless_present("from_hex")
                 .index(1),
         )
+        .arg(
+            Arg::new("from_hex")
+                .long("from-hex")
+                .value_name("HEX")
+                .help("Print this hex-encoded transaction instead of a file")
+                .conflicts_with("tx_file"),
+        )
+        .arg(
+            Arg::new("hex")
+                .long("hex")
+                .action(ArgAction::SetTrue)
+                .help("Print the transaction's hex encoding to stdout"),
+        )
         .get_matches();
 
-    let path = matches.get_one::<String>("tx_file").unwrap();
-    if let Ok(file) = File::open(path) {
-        let tx = match Transaction::load(file) {
+    let tx = if let Some(encoded) = matches.get_one::<String>("from_hex") {
+        match Transaction::from_hex(encoded) {
             Ok(tx) => tx,
             Err(e) => {
-                error!("Failed to load transaction: {}", e);
+                error!("Failed to decode transaction: {}", e);
                 exit(1);
             }
+        }
+    } else {
+        let path = matches.get_one::<String>("tx_file").unwrap();
+        let Ok(file) = File::open(path) else {
+            error!("Failed to open file '{}'", path);
+            exit(1);
         };
-        info!("{:#?}", tx);
+        match Transaction::load(file) {
+            Ok(tx) => tx,
+            Err(e) => {
+                error!("Failed to load transaction: {}", e);
+                exit(1);
+            }
+        }
+    };
+    if matches.get_flag("hex") {
+        println!("{}", tx.to_hex());
     } else {
-        error!("Failed to open file '{}'", path);
-        exit(1);
+        info!("{:#?}", tx);
     }
 }
@@ -0,0 +1,60 @@
+use btclib::{crypto::PrivateKey, types::UnsignedTransaction, utils::Saveable};
+
+use clap::{Arg, Command};
+use std::process::exit;
+
+fn main() {
+    env_logger::init();
+    let matches = Command::new("tx_sign")
+        .version("1.0")
+        .about(
+            "Signs a transaction a node built for a watch-only key (BuildTransaction) \
+             and prints it in hex, ready for POST /tx or tx_print --from-hex",
+        )
+        .arg(
+            Arg::new("key")
+                .help(
+                    "Private key files owning the inputs, comma separated \
+                     (e.g. 'mykey.priv.cbor,savings.priv.cbor')",
+                )
+                .required(true)
+                .value_delimiter(',')
+                .index(1),
+        )
+        .arg(
+            Arg::new("unsigned")
+                .help("The unsigned transaction in hex (UnsignedTransaction::to_hex)")
+                .required(true)
+                .index(2),
+        )
+        .get_matches();
+
+    let keys: Vec<PrivateKey> = matches
+        .get_many::<String>("key")
+        .unwrap()
+        .map(|key_file| PrivateKey::load_from_file(key_file).expect("Failed to load private key"))
+        .collect();
+    let encoded = matches.get_one::<String>("unsigned").unwrap();
+    let unsigned = UnsignedTransaction::from_hex(encoded).unwrap_or_else(|e| {
+        eprintln!("Failed to decode unsigned transaction: {e}");
+        exit(1);
+    });
+    // shown before signing, as the node that built it isn't trusted
+    // with the amounts
+    for output in &unsigned.outputs {
+        eprintln!("pay {} to {}", output.value(), output.pubkey().address());
+    }
+    eprintln!(
+        "spending {} in {} outputs, fee {}",
+        unsigned.input_value(),
+        unsigned.inputs.len(),
+        unsigned.fee()
+    );
+    match unsigned.sign_with(&keys) {
+        Ok(transaction) => println!("{}", transaction.to_hex()),
+        Err(e) => {
+            eprintln!("Failed to sign: {e}, an input pays none of the keys");
+            exit(1);
+        }
+    }
+}
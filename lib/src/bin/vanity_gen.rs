@@ -0,0 +1,179 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use btclib::crypto::PrivateKey;
+use btclib::utils::Saveable;
+use clap::{Arg, ArgGroup, Command, value_parser};
+use regex::Regex;
+
+// how often progress is reported
+const REPORT_INTERVAL: Duration = Duration::from_secs(2);
+const BASE58_ALPHABET: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+enum Pattern {
+    Prefix(String),
+    Regex(Regex),
+}
+
+impl Pattern {
+    fn matches(&self, address: &str) -> bool {
+        match self {
+            Pattern::Prefix(prefix) => address.starts_with(prefix.as_str()),
+            Pattern::Regex(regex) => regex.is_match(address),
+        }
+    }
+
+    /// Roughly how many keys it takes on average to find a match, if
+    /// that can be told from the pattern. Every address starts with '1',
+    /// each character after that is one of 58.
+    fn expected_attempts(&self) -> Option<f64> {
+        match self {
+            Pattern::Prefix(prefix) => Some(58f64.powi(prefix.len() as i32 - 1)),
+            Pattern::Regex(_) => None,
+        }
+    }
+}
+
+fn parse_pattern(matches: &clap::ArgMatches) -> Pattern {
+    if let Some(prefix) = matches.get_one::<String>("prefix") {
+        if !prefix.starts_with('1') {
+            panic!("every address starts with '1', so '{prefix}' can never match");
+        }
+        if let Some(c) = prefix.chars().find(|c| !BASE58_ALPHABET.contains(*c)) {
+            panic!("'{c}' is not a Base58 character, so '{prefix}' can never match");
+        }
+        Pattern::Prefix(prefix.clone())
+    } else {
+        let regex = matches.get_one::<String>("regex").unwrap();
+        Pattern::Regex(Regex::new(regex).unwrap_or_else(|e| panic!("invalid regex: {e}")))
+    }
+}
+
+/// Keeps generating keys until one's address matches, or another
+/// thread found one.
+fn grind(pattern: &Pattern, attempts: &AtomicU64, found: &AtomicBool) -> Option<PrivateKey> {
+    while !found.load(Ordering::Relaxed) {
+        let private_key = PrivateKey::default();
+        attempts.fetch_add(1, Ordering::Relaxed);
+        if pattern.matches(&private_key.public_key().address().to_string()) {
+            found.store(true, Ordering::Relaxed);
+            return Some(private_key);
+        }
+    }
+    None
+}
+
+fn report_progress(attempts: u64, elapsed: Duration, expected: Option<f64>) {
+    let rate = attempts as f64 / elapsed.as_secs_f64();
+    match expected {
+        // the chance of a match is the same for every key, so the
+        // number of keys it takes is geometrically distributed
+        Some(expected) if rate > 0.0 => {
+            let half_chance = expected * std::f64::consts::LN_2 / rate;
+            println!(
+                "{attempts} keys, {rate:.0} keys/s, 50% chance of a match after {half_chance:.0}s \
+                 ({:.0}s elapsed)",
+                elapsed.as_secs_f64()
+            );
+        }
+        _ => println!("{attempts} keys, {rate:.0} keys/s"),
+    }
+}
+
+pub fn main() {
+    env_logger::init();
+
+    let matches = Command::new("Vanity Address Generator")
+        .version("1.0")
+        .about("Generates keys until the address of one matches a prefix or regex, and saves it")
+        .arg(
+            Arg::new("name")
+                .help("Base name for the key files (e.g., 'mykey' creates 'mykey.pub.pem' and 'mykey.priv.cbor')")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            Arg::new("prefix")
+                .long("prefix")
+                .help("Prefix the address must start with, including the leading '1'"),
+        )
+        .arg(
+            Arg::new("regex")
+                .long("regex")
+                .help("Regular expression the address must match"),
+        )
+        .group(
+            ArgGroup::new("pattern")
+                .args(["prefix", "regex"])
+                .required(true),
+        )
+        .arg(
+            Arg::new("threads")
+                .long("threads")
+                .help("Number of threads; one per CPU by default")
+                .value_parser(value_parser!(usize)),
+        )
+        .get_matches();
+
+    let name = matches.get_one::<String>("name").unwrap();
+    let pattern = Arc::new(parse_pattern(&matches));
+    let threads = matches
+        .get_one::<usize>("threads")
+        .copied()
+        .unwrap_or_else(|| {
+            thread::available_parallelism()
+                .map(|threads| threads.get())
+                .unwrap_or(1)
+        });
+
+    let attempts = Arc::new(AtomicU64::new(0));
+    let found = Arc::new(AtomicBool::new(false));
+    let (sender, receiver) = mpsc::channel();
+    for _ in 0..threads.max(1) {
+        let (pattern, attempts, found, sender) = (
+            pattern.clone(),
+            attempts.clone(),
+            found.clone(),
+            sender.clone(),
+        );
+        thread::spawn(move || {
+            if let Some(private_key) = grind(&pattern, &attempts, &found) {
+                let _ = sender.send(private_key);
+            }
+        });
+    }
+
+    let start = Instant::now();
+    let private_key = loop {
+        match receiver.recv_timeout(REPORT_INTERVAL) {
+            Ok(private_key) => break private_key,
+            Err(mpsc::RecvTimeoutError::Timeout) => report_progress(
+                attempts.load(Ordering::Relaxed),
+                start.elapsed(),
+                pattern.expected_attempts(),
+            ),
+            Err(mpsc::RecvTimeoutError::Disconnected) => unreachable!("a thread always finds one"),
+        }
+    };
+
+    let public_key = private_key.public_key();
+    private_key
+        .save_to_file(format!("{}.priv.cbor", name))
+        .unwrap();
+    public_key
+        .save_to_file(format!("{}.pub.pem", name))
+        .unwrap();
+    println!(
+        "found {} after {} keys in {:.1}s, saved as {name}.priv.cbor / {name}.pub.pem",
+        public_key.address(),
+        attempts.load(Ordering::Relaxed),
+        start.elapsed().as_secs_f64()
+    );
+}
@@ -0,0 +1,3 @@
+mod node_client;
+
+pub use node_client::*;
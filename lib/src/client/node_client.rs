@@ -0,0 +1,576 @@
+use std::{io::Result as IoResult, net::SocketAddr};
+
+use tokio::net::{TcpStream, ToSocketAddrs};
+
+use crate::{
+    accumulator::{Stump, UtxoProof},
+    crypto::PublicKey,
+    custom_sha_types::Hash,
+    error::ClientError,
+    network::{
+        BlockTimings, ChainAnalytics, ChainStats, ChainTip, DiskUsage, FeeHistogram, MemoryInfo,
+        MempoolEntry, Message, NetworkHealth, NetworkInfo, PeerKind, RelayPolicy, RescanResult,
+        Role, Session, SyncProgress, ZeroConfRisk,
+    },
+    types::{
+        Block, BlockHeader, BlockHeight, BlockUndo, Transaction, UnsignedTransaction, UtxoDelta,
+        UtxoEntry,
+    },
+};
+
+pub type ClientResult<T> = std::result::Result<T, ClientError>;
+
+/// Typed request/response access to a node over the wire protocol.
+///
+/// Shared by the miner, the wallet, and node-to-node sync so none of them
+/// has to hand-roll message exchanges on a raw `TcpStream`.
+#[derive(Debug)]
+pub struct NodeClient {
+    stream: TcpStream,
+}
+
+impl NodeClient {
+    pub async fn connect(address: impl ToSocketAddrs) -> ClientResult<Self> {
+        let stream = TcpStream::connect(address).await?;
+        Ok(Self::from_stream(stream))
+    }
+
+    pub fn from_stream(stream: TcpStream) -> Self {
+        NodeClient { stream }
+    }
+
+    pub fn peer_addr(&self) -> IoResult<SocketAddr> {
+        self.stream.peer_addr()
+    }
+
+    /// Our end of the connection, e.g. to tell the node which of our
+    /// addresses reaches us.
+    pub fn local_addr(&self) -> IoResult<SocketAddr> {
+        self.stream.local_addr()
+    }
+
+    /// Sends a message without waiting for a response.
+    pub async fn send(&mut self, message: &Message) -> ClientResult<()> {
+        message.send_async(&mut self.stream).await?;
+        Ok(())
+    }
+
+    /// Sends a message and waits for the node's response.
+    /// A `Reject` response is turned into `ClientError::Rejected`.
+    pub async fn request(&mut self, message: &Message) -> ClientResult<Message> {
+        self.send(message).await?;
+        match Message::receive_async(&mut self.stream).await? {
+            Message::Reject { code, reason, .. } => Err(ClientError::Rejected { code, reason }),
+            response => Ok(response),
+        }
+    }
+
+    /// Presents an auth token; the granted role lasts for this connection.
+    pub async fn authenticate(&mut self, token: &str) -> ClientResult<Role> {
+        match self
+            .request(&Message::Authenticate(token.to_string()))
+            .await?
+        {
+            Message::Authenticated(role) => Ok(role),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    /// Says what kind of program this is, asking for an idle timeout in
+    /// seconds (0 for the longest the node allows). The node then serves
+    /// only the requests of that kind and closes the connection if it
+    /// stays silent past the granted timeout: `ping` within it to keep
+    /// the connection open.
+    pub async fn hello(&mut self, kind: PeerKind, idle_timeout_secs: u64) -> ClientResult<Session> {
+        match self
+            .request(&Message::Hello(kind, idle_timeout_secs))
+            .await?
+        {
+            Message::Welcome(session) => Ok(session),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    /// Keeps the connection open past its idle timeout, checking that
+    /// the node still answers.
+    pub async fn ping(&mut self) -> ClientResult<()> {
+        let nonce = rand::random();
+        match self.request(&Message::Ping(nonce)).await? {
+            Message::Pong(echoed) if echoed == nonce => Ok(()),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    /// Asks the node to shut down. Requires the admin role.
+    pub async fn shutdown(&mut self) -> ClientResult<()> {
+        self.send(&Message::Shutdown).await
+    }
+
+    /// Makes the node refuse the block `hash` and any chain through it,
+    /// moving to another branch if it is in the node's chain. Returns
+    /// the node's tip afterwards. Requires the admin role.
+    pub async fn invalidate_block(&mut self, hash: Hash) -> ClientResult<Option<ChainTip>> {
+        match self.request(&Message::InvalidateBlock(hash)).await? {
+            Message::Tip(tip) => Ok(tip),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    /// Makes the node accept the block `hash` again, switching back to
+    /// it if a peer serves it in a longer chain. Returns the node's tip
+    /// afterwards. Requires the admin role.
+    pub async fn reconsider_block(&mut self, hash: Hash) -> ClientResult<Option<ChainTip>> {
+        match self.request(&Message::ReconsiderBlock(hash)).await? {
+            Message::Tip(tip) => Ok(tip),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    /// Fetches all UTXOs belonging to `pubkey`, with their mempool marks.
+    pub async fn get_utxos(&mut self, pubkey: &PublicKey) -> ClientResult<Vec<UtxoEntry>> {
+        match self.request(&Message::FetchUTXOs(pubkey.clone())).await? {
+            Message::UTXOs(utxos) => Ok(utxos),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    pub async fn submit_tx(&mut self, transaction: Transaction) -> ClientResult<()> {
+        self.send(&Message::SubmitTransaction(transaction)).await
+    }
+
+    /// Submits a transaction of the operator's own wallet to the
+    /// mempool's priority lane, whatever its fee. Requires the admin
+    /// role.
+    pub async fn submit_priority_tx(&mut self, transaction: Transaction) -> ClientResult<()> {
+        self.send(&Message::SubmitPriorityTransaction(transaction))
+            .await
+    }
+
+    pub async fn get_template(
+        &mut self,
+        pubkey: &PublicKey,
+        coinbase_tag: Option<Vec<u8>>,
+    ) -> ClientResult<Block> {
+        match self
+            .request(&Message::FetchTemplate(pubkey.clone(), coinbase_tag))
+            .await?
+        {
+            Message::Template(template) => Ok(template),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    pub async fn validate_template(&mut self, template: Block) -> ClientResult<bool> {
+        match self.request(&Message::ValidateTemplate(template)).await? {
+            Message::TemplateValidity(valid) => Ok(valid),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    pub async fn submit_template(&mut self, block: Block) -> ClientResult<()> {
+        self.send(&Message::SubmitTemplate(block)).await
+    }
+
+    /// `block` with the authority node's signature added. Rejected with
+    /// `Unsupported` by nodes without a co-signing key, and with `Invalid`
+    /// if the block doesn't extend the node's best block or the node
+    /// already co-signed another block at its height.
+    pub async fn cosign_block(&mut self, block: Block) -> ClientResult<Block> {
+        match self.request(&Message::CosignBlock(block)).await? {
+            Message::Cosigned(block) => Ok(block),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    pub async fn discover_nodes(&mut self) -> ClientResult<Vec<String>> {
+        match self.request(&Message::DiscoverNodes).await? {
+            Message::NodeList(nodes) => Ok(nodes),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    /// Asks how many blocks the node has beyond `height`.
+    pub async fn get_difference(&mut self, height: BlockHeight) -> ClientResult<i32> {
+        match self.request(&Message::AskDifference(height)).await? {
+            Message::Difference(count) => Ok(count),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    pub async fn get_block(&mut self, height: BlockHeight) -> ClientResult<Block> {
+        match self.request(&Message::FetchBlock(height)).await? {
+            Message::NewBlock(block) => Ok(block),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    pub async fn get_block_by_hash(&mut self, hash: Hash) -> ClientResult<Block> {
+        match self.request(&Message::FetchBlockByHash(hash)).await? {
+            Message::NewBlock(block) => Ok(block),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    pub async fn get_header(&mut self, hash: Hash) -> ClientResult<BlockHeader> {
+        match self.request(&Message::FetchHeader(hash)).await? {
+            Message::Header(header) => Ok(header),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    /// The node's best block, `None` if its chain is empty.
+    pub async fn get_tip(&mut self) -> ClientResult<Option<ChainTip>> {
+        match self.request(&Message::FetchTip).await? {
+            Message::Tip(tip) => Ok(tip),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    /// Hashes of the transactions in the node's mempool, highest fee first.
+    pub async fn get_mempool(&mut self) -> ClientResult<Vec<Hash>> {
+        match self.request(&Message::FetchMempool).await? {
+            Message::Mempool(transactions) => Ok(transactions),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    /// The node's mempool transactions with these hashes. Ones it
+    /// doesn't have are left out, as are any past the first
+    /// `MAX_FETCH_TRANSACTIONS`.
+    pub async fn get_transactions(&mut self, hashes: Vec<Hash>) -> ClientResult<Vec<Transaction>> {
+        match self.request(&Message::FetchTransactions(hashes)).await? {
+            Message::Transactions(transactions) => Ok(transactions),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    /// The node's mempool transactions with their package fee rates,
+    /// highest first.
+    pub async fn get_mempool_entries(&mut self) -> ClientResult<Vec<MempoolEntry>> {
+        match self.request(&Message::FetchMempoolEntries).await? {
+            Message::MempoolEntries(entries) => Ok(entries),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    /// How the node's mempool is spread over fee rates, highest first.
+    pub async fn get_fee_histogram(&mut self) -> ClientResult<FeeHistogram> {
+        match self.request(&Message::FetchFeeHistogram).await? {
+            Message::FeeHistogram(histogram) => Ok(histogram),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    /// When the node received and connected the block with `hash`.
+    /// Rejected with `NotFound` if it doesn't know when, e.g. for blocks
+    /// it loaded from disk or downloaded at startup.
+    pub async fn get_block_timings(&mut self, hash: Hash) -> ClientResult<BlockTimings> {
+        match self.request(&Message::FetchBlockTimings(hash)).await? {
+            Message::BlockTimings(timings) => Ok(timings),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    /// How likely the mempool transaction `hash` is to be double spent,
+    /// for accepting it as a payment unconfirmed. Rejected with
+    /// `NotFound` if it isn't in the node's mempool, and `Unsupported` if
+    /// the node doesn't score transactions.
+    pub async fn get_zero_conf_risk(&mut self, hash: Hash) -> ClientResult<ZeroConfRisk> {
+        match self.request(&Message::FetchZeroConfRisk(hash)).await? {
+            Message::ZeroConfRisk(risk) => Ok(risk),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    /// The roots of the node's UTXO accumulator at its tip, which
+    /// `get_utxo_proof` proofs are checked against. Rejected with
+    /// `Unsupported` by nodes built without the `utxo-accumulator`
+    /// feature.
+    pub async fn get_utxo_accumulator(&mut self) -> ClientResult<Stump> {
+        match self.request(&Message::FetchUtxoAccumulator).await? {
+            Message::UtxoAccumulator(stump) => Ok(stump),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    /// The output `outpoint` refers to with the proof that it is
+    /// unspent, `None` if it isn't. Check it with `Stump::verify` against
+    /// roots from a source you trust, not only this node.
+    pub async fn get_utxo_proof(&mut self, outpoint: Hash) -> ClientResult<Option<UtxoProof>> {
+        match self.request(&Message::FetchUtxoProof(outpoint)).await? {
+            Message::UtxoProof(proof) => Ok(proof),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    /// Fetches supply and UTXO set statistics with the `top` richest keys.
+    /// Requires the read-only role.
+    pub async fn get_stats(&mut self, top: usize) -> ClientResult<ChainStats> {
+        match self.request(&Message::FetchStats(top)).await? {
+            Message::Stats(stats) => Ok(stats),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    /// Fetches UTXO ages and the value moved and coin-days destroyed by
+    /// the last `blocks` blocks. Requires the read-only role.
+    pub async fn get_analytics(&mut self, blocks: usize) -> ClientResult<ChainAnalytics> {
+        match self.request(&Message::FetchAnalytics(blocks)).await? {
+            Message::Analytics(analytics) => Ok(analytics),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    /// Requires the read-only role.
+    pub async fn get_disk_usage(&mut self) -> ClientResult<DiskUsage> {
+        match self.request(&Message::FetchDiskUsage).await? {
+            Message::DiskUsage(usage) => Ok(usage),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    /// Estimated memory taken by the node's UTXO set and mempool, and
+    /// its memory budget. Requires the read-only role.
+    pub async fn get_memory_info(&mut self) -> ClientResult<MemoryInfo> {
+        match self.request(&Message::FetchMemoryInfo).await? {
+            Message::MemoryInfo(info) => Ok(info),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    pub async fn get_health(&mut self) -> ClientResult<NetworkHealth> {
+        match self.request(&Message::FetchHealth).await? {
+            Message::Health(health) => Ok(health),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    /// Fetches the node's blockchain download under way or its last one,
+    /// `None` if it never downloaded blocks.
+    pub async fn get_sync_progress(&mut self) -> ClientResult<Option<SyncProgress>> {
+        match self.request(&Message::FetchSyncProgress).await? {
+            Message::SyncProgress(progress) => Ok(progress),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    /// Fetches the node's relay rules, to check transactions before submitting them.
+    pub async fn get_policy(&mut self) -> ClientResult<RelayPolicy> {
+        match self.request(&Message::FetchPolicy).await? {
+            Message::Policy(policy) => Ok(policy),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    /// Fetches the node's connection limits, e.g. the largest message it accepts.
+    pub async fn get_network_info(&mut self) -> ClientResult<NetworkInfo> {
+        match self.request(&Message::FetchNetworkInfo).await? {
+            Message::NetworkInfo(info) => Ok(info),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    /// Asks the node for every output paying `keys` in the blocks from
+    /// `from_height` on, to rebuild a wallet's history from scratch.
+    /// Needs the `ReadOnly` role and at most `MAX_RESCAN_KEYS` keys.
+    pub async fn rescan(
+        &mut self,
+        keys: Vec<PublicKey>,
+        from_height: BlockHeight,
+    ) -> ClientResult<RescanResult> {
+        match self.request(&Message::Rescan(keys, from_height)).await? {
+            Message::RescanResult(result) => Ok(result),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    /// Asks the node to pay `recipients` from the outputs of the
+    /// watch-only key `from`, at `fee_rate` satoshis per byte or the
+    /// node's minimum. The result is signed where the private key is
+    /// (`UnsignedTransaction::sign`) and submitted with `submit_tx`.
+    pub async fn build_transaction(
+        &mut self,
+        from: PublicKey,
+        recipients: Vec<(PublicKey, u64)>,
+        fee_rate: Option<u64>,
+    ) -> ClientResult<UnsignedTransaction> {
+        match self
+            .request(&Message::BuildTransaction(from, recipients, fee_rate))
+            .await?
+        {
+            Message::UnsignedTransaction(unsigned) => Ok(unsigned),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    /// Asks a watchtower node to alert its webhooks of conflicting
+    /// spends of `outpoints`. Returns how many outputs it watches in all.
+    pub async fn watch_outpoints(&mut self, outpoints: Vec<Hash>) -> ClientResult<usize> {
+        match self.request(&Message::WatchOutpoints(outpoints)).await? {
+            Message::Watching(count) => Ok(count),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    /// Asks a watchtower node to stop watching `outpoints`. Returns how
+    /// many outputs it still watches.
+    pub async fn unwatch_outpoints(&mut self, outpoints: Vec<Hash>) -> ClientResult<usize> {
+        match self.request(&Message::UnwatchOutpoints(outpoints)).await? {
+            Message::Watching(count) => Ok(count),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    /// Asks an archive node for the balance of `pubkey` just before the
+    /// block at `height`.
+    pub async fn get_balance_at(
+        &mut self,
+        pubkey: PublicKey,
+        height: BlockHeight,
+    ) -> ClientResult<u64> {
+        match self
+            .request(&Message::FetchBalanceAt(pubkey, height))
+            .await?
+        {
+            Message::BalanceAt(balance) => Ok(balance),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    /// Asks an archive node what the block at `height` spent and created.
+    pub async fn get_undo(&mut self, height: BlockHeight) -> ClientResult<BlockUndo> {
+        match self.request(&Message::FetchUndo(height)).await? {
+            Message::Undo(undo) => Ok(undo),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    /// Fetches how the UTXO set changed over the blocks from `from` up to
+    /// `to`. Check its headers continue the chain it is applied to.
+    pub async fn get_utxo_delta(
+        &mut self,
+        from: BlockHeight,
+        to: BlockHeight,
+    ) -> ClientResult<UtxoDelta> {
+        match self.request(&Message::FetchUtxoDelta(from, to)).await? {
+            Message::UtxoDelta(delta) => Ok(delta),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    /// Fetches (asset id, ticker, amount) for every asset `pubkey` holds.
+    /// Only served by nodes built with the `assets` feature.
+    pub async fn get_asset_balances(
+        &mut self,
+        pubkey: &PublicKey,
+    ) -> ClientResult<Vec<(Hash, String, u64)>> {
+        match self
+            .request(&Message::FetchAssetBalances(pubkey.clone()))
+            .await?
+        {
+            Message::AssetBalances(balances) => Ok(balances),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+
+    /// Turns this connection into a stream of best block changes.
+    pub async fn subscribe_tips(mut self) -> ClientResult<TipSubscription> {
+        self.send(&Message::SubscribeTips).await?;
+        Ok(TipSubscription { client: self })
+    }
+
+    /// Relays a block to a peer node.
+    pub async fn announce_block(&mut self, block: Block) -> ClientResult<()> {
+        self.send(&Message::NewBlock(block)).await
+    }
+
+    /// Relays a transaction to a peer node.
+    pub async fn announce_tx(&mut self, transaction: Transaction) -> ClientResult<()> {
+        self.send(&Message::NewTransaction(transaction)).await
+    }
+
+    /// Tells a peer node where nodes can be reached.
+    pub async fn announce_addresses(&mut self, addresses: Vec<String>) -> ClientResult<()> {
+        self.send(&Message::Addr(addresses)).await
+    }
+}
+
+/// A connection that receives `TipChanged` pushes, see `NodeClient::subscribe_tips`.
+#[derive(Debug)]
+pub struct TipSubscription {
+    client: NodeClient,
+}
+
+impl TipSubscription {
+    /// Waits for the node's next best block change.
+    pub async fn next(&mut self) -> ClientResult<ChainTip> {
+        match Message::receive_async(&mut self.client.stream).await? {
+            Message::TipChanged(tip) => Ok(tip),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{crypto::PrivateKey, network::RejectCode, types::TransactionOutput};
+    use tokio::net::TcpListener;
+    use uuid::Uuid;
+
+    async fn serve_once(response: Message) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let _request = Message::receive_async(&mut socket).await.unwrap();
+            response.send_async(&mut socket).await.unwrap();
+        });
+        address
+    }
+
+    #[tokio::test]
+    async fn test_get_utxos() {
+        let private_key = PrivateKey::default();
+        let output = TransactionOutput::new(1000, Uuid::new_v4(), private_key.public_key());
+        let address = serve_once(Message::UTXOs(vec![UtxoEntry::new(output)])).await;
+
+        let mut client = NodeClient::connect(address).await.unwrap();
+        let utxos = client.get_utxos(&private_key.public_key()).await.unwrap();
+
+        assert_eq!(utxos.len(), 1);
+        assert_eq!(utxos[0].output().value(), 1000);
+        assert!(utxos[0].is_unspent());
+    }
+
+    #[tokio::test]
+    async fn test_rejected_request() {
+        let address = serve_once(Message::reject(
+            "FetchBlock",
+            RejectCode::NotFound,
+            "missing",
+        ))
+        .await;
+
+        let mut client = NodeClient::connect(address).await.unwrap();
+        let result = client.get_block(BlockHeight::new(42)).await;
+
+        assert!(matches!(
+            result,
+            Err(ClientError::Rejected {
+                code: RejectCode::NotFound,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_unexpected_response() {
+        let address = serve_once(Message::Difference(3)).await;
+
+        let mut client = NodeClient::connect(address).await.unwrap();
+        let result = client.discover_nodes().await;
+
+        assert!(matches!(result, Err(ClientError::UnexpectedResponse(_))));
+    }
+}
@@ -0,0 +1,36 @@
+mod poa;
+mod pow;
+
+pub use poa::*;
+pub use pow::*;
+
+use std::fmt;
+
+use crate::{
+    crypto::PublicKey,
+    error::Result,
+    types::{Block, BlockHeight},
+};
+
+/// How a chain decides who may add the next block. `Blockchain` checks
+/// every block's seal with its engine, proof of work unless the node
+/// configures another one.
+pub trait ConsensusEngine: fmt::Debug + Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Checks that `block`, to be connected at `height`, is sealed the
+    /// way this engine wants, e.g. mined below its target.
+    fn verify_seal(&self, block: &Block, height: BlockHeight) -> Result<()>;
+
+    /// The key that has to sign the block at `height`, for engines
+    /// where blocks are signed instead of mined. None if anyone can
+    /// produce it, and templates are served to miners then.
+    fn proposer(&self, _height: BlockHeight) -> Option<&PublicKey> {
+        None
+    }
+
+    /// Whether `key` takes part in producing blocks by signing them.
+    fn is_validator(&self, _key: &PublicKey) -> bool {
+        false
+    }
+}
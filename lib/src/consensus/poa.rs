@@ -0,0 +1,109 @@
+use crate::{
+    consensus::ConsensusEngine,
+    crypto::PublicKey,
+    error::{BtcError, Result},
+    types::{Block, BlockHeight},
+};
+
+/// Blocks are signed instead of mined, by a fixed set of validators
+/// taking turns: the block at height `h` must carry a signature from
+/// validator `h % n`, in the order given. Nothing is mined, so there
+/// is no work to waste, but the chain stalls while the validator
+/// whose turn it is is offline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProofOfAuthority {
+    validators: Vec<PublicKey>,
+}
+
+impl ProofOfAuthority {
+    /// Fails without validators or if one is listed twice, as it would
+    /// then take more than its share of turns.
+    pub fn new(validators: Vec<PublicKey>) -> Result<Self> {
+        let mut distinct = validators.clone();
+        distinct.sort();
+        distinct.dedup();
+        if validators.is_empty() || distinct.len() != validators.len() {
+            return Err(BtcError::InvalidValidatorSet);
+        }
+        Ok(ProofOfAuthority { validators })
+    }
+
+    pub fn validators(&self) -> &[PublicKey] {
+        &self.validators
+    }
+
+    /// The validator whose turn it is at `height`.
+    pub fn validator_at(&self, height: BlockHeight) -> &PublicKey {
+        let turn = height.value() % self.validators.len() as u64;
+        &self.validators[turn as usize]
+    }
+}
+
+impl ConsensusEngine for ProofOfAuthority {
+    fn name(&self) -> &'static str {
+        "proof-of-authority"
+    }
+
+    fn verify_seal(&self, block: &Block, height: BlockHeight) -> Result<()> {
+        let validator = self.validator_at(height);
+        let hash = block.hash();
+        let signed = block
+            .authority_signatures()
+            .iter()
+            .any(|signature| signature.signer == *validator && signature.verify(&hash));
+        if !signed {
+            return Err(BtcError::MissingValidatorSignature);
+        }
+        Ok(())
+    }
+
+    fn proposer(&self, height: BlockHeight) -> Option<&PublicKey> {
+        Some(self.validator_at(height))
+    }
+
+    fn is_validator(&self, key: &PublicKey) -> bool {
+        self.validators.contains(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MIN_TARGET, crypto::PrivateKey, custom_sha_types::Hash, types::BlockBuilder};
+
+    fn block(height: u64) -> Block {
+        BlockBuilder::new(Hash::zero(), BlockHeight::new(height), MIN_TARGET)
+            .build(PrivateKey::default().public_key())
+    }
+
+    #[test]
+    fn test_proof_of_authority_validators() {
+        let key = PrivateKey::default().public_key();
+        assert!(ProofOfAuthority::new(vec![]).is_err());
+        assert!(ProofOfAuthority::new(vec![key.clone(), key]).is_err());
+    }
+
+    #[test]
+    fn test_proof_of_authority_takes_turns() {
+        let alice = PrivateKey::default();
+        let bob = PrivateKey::default();
+        let engine = ProofOfAuthority::new(vec![alice.public_key(), bob.public_key()]).unwrap();
+        assert_eq!(
+            *engine.validator_at(BlockHeight::new(0)),
+            alice.public_key()
+        );
+        assert_eq!(*engine.validator_at(BlockHeight::new(3)), bob.public_key());
+        assert!(engine.is_validator(&bob.public_key()));
+
+        let mut block = block(3);
+        assert!(matches!(
+            engine.verify_seal(&block, BlockHeight::new(3)),
+            Err(BtcError::MissingValidatorSignature)
+        ));
+        // it isn't alice's turn
+        block.cosign(&alice);
+        assert!(engine.verify_seal(&block, BlockHeight::new(3)).is_err());
+        block.cosign(&bob);
+        assert!(engine.verify_seal(&block, BlockHeight::new(3)).is_ok());
+    }
+}
@@ -0,0 +1,34 @@
+use log::error;
+
+use crate::{
+    consensus::ConsensusEngine,
+    error::{BtcError, Result},
+    types::{Block, BlockHeight},
+};
+
+/// Blocks are mined: the header hash must be below the header target.
+/// The genesis block isn't checked.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProofOfWork;
+
+impl ConsensusEngine for ProofOfWork {
+    fn name(&self) -> &'static str {
+        "proof-of-work"
+    }
+
+    fn verify_seal(&self, block: &Block, height: BlockHeight) -> Result<()> {
+        if height == BlockHeight::GENESIS {
+            return Ok(());
+        }
+        let hash = block.header().hash();
+        if !hash.matches_target(block.header().target()) {
+            error!(
+                "Does not match target: {:x?} >= {:x?}",
+                hash,
+                block.header().target()
+            );
+            return Err(BtcError::InvalidBlock);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,425 @@
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+
+use crate::{
+    MAX_BLOCK_SIZE, MAX_COINBASE_TAG_SIZE, MAX_DATA_OUTPUT_SIZE, MAX_DATA_OUTPUTS_PER_TX, U256,
+    crypto::{PrivateKey, Signature},
+    custom_sha_types::Hash,
+    error::{BtcError, Result},
+    testutil::{coinbase_transaction_to, spend_transaction, test_key},
+    types::{
+        Block, BlockBuilder, BlockHeader, BlockHeight, Blockchain, DataOutput, Transaction,
+        TransactionInput, TransactionOutput,
+    },
+    utils::MerkleRoot,
+};
+
+fn key() -> PrivateKey {
+    test_key("consensus spec")
+}
+
+// a chain of one mined genesis block paying `key()`
+fn chain() -> Blockchain {
+    let mut chain = Blockchain::default();
+    let mut genesis = BlockBuilder::on(&chain).build(key().public_key());
+    genesis.mine(usize::MAX);
+    chain.add_block(genesis).unwrap();
+    chain.rebuild_utxos();
+    chain
+}
+
+fn tip_time(chain: &Blockchain) -> DateTime<Utc> {
+    chain.blocks().last().unwrap().header().timestamp()
+}
+
+fn genesis_output(chain: &Blockchain) -> TransactionOutput {
+    chain.blocks()[0].transactions()[0].outputs()[0].clone()
+}
+
+fn coinbase(value: u64) -> Transaction {
+    coinbase_transaction_to(value, &key().public_key())
+}
+
+// spends `output` back to `key()`, leaving `fee`
+fn spend(output: &TransactionOutput, fee: u64) -> Transaction {
+    spend_transaction(output, &key(), fee)
+}
+
+// `count` transactions, each spending the previous one's output
+fn spend_chain(output: &TransactionOutput, count: usize, fee: u64) -> Vec<Transaction> {
+    let mut output = output.clone();
+    (0..count)
+        .map(|_| {
+            let transaction = spend(&output, fee);
+            output = transaction.outputs()[0].clone();
+            transaction
+        })
+        .collect()
+}
+
+// A block to put to a rule: by default the valid next block of a chain,
+// changed field by field into the vector
+struct Draft {
+    timestamp: DateTime<Utc>,
+    prev_block_hash: Hash,
+    target: U256,
+    transactions: Vec<Transaction>,
+    merkle_root: Option<MerkleRoot>,
+}
+
+impl Draft {
+    // an empty next block a second after the best block
+    fn on(chain: &Blockchain) -> Self {
+        Draft {
+            timestamp: tip_time(chain) + Duration::seconds(1),
+            prev_block_hash: chain.tip().unwrap().hash,
+            target: chain.target(),
+            transactions: vec![coinbase(chain.calculate_block_reward())],
+            merkle_root: None,
+        }
+    }
+
+    fn build(self) -> Block {
+        let merkle_root = self
+            .merkle_root
+            .unwrap_or_else(|| MerkleRoot::calculate(&self.transactions));
+        let header = BlockHeader::new(
+            self.timestamp,
+            0,
+            self.prev_block_hash,
+            merkle_root,
+            self.target,
+        );
+        Block::new(header, self.transactions)
+    }
+
+    fn seal(self) -> Block {
+        let mut block = self.build();
+        block.mine(usize::MAX);
+        block
+    }
+}
+
+// what the chain makes of `block` as its next block
+fn connect(chain: &Blockchain, block: Block) -> Result<()> {
+    chain.clone().add_block(block)
+}
+
+#[test]
+fn rule_previous_block() {
+    // the first block builds on nothing
+    let genesis = BlockBuilder::new(Hash::hash(&"elsewhere"), BlockHeight::GENESIS, U256::MAX)
+        .build(key().public_key());
+    assert!(matches!(
+        connect(&Blockchain::default(), genesis),
+        Err(BtcError::InvalidBlock)
+    ));
+
+    // every other block builds on the best block
+    let chain = chain();
+    assert!(connect(&chain, Draft::on(&chain).seal()).is_ok());
+    let stale = Draft {
+        prev_block_hash: Hash::zero(),
+        ..Draft::on(&chain)
+    };
+    assert!(matches!(
+        connect(&chain, stale.seal()),
+        Err(BtcError::InvalidBlock)
+    ));
+}
+
+#[test]
+fn rule_coinbase_amount() {
+    let chain = chain();
+    let reward = chain.calculate_block_reward();
+    let fee = 1_000;
+    let payment = spend(&genesis_output(&chain), fee);
+    let paying = |value| Draft {
+        transactions: vec![coinbase(value), payment.clone()],
+        ..Draft::on(&chain)
+    };
+
+    // exactly the subsidy plus the fees, to the satoshi: claiming less
+    // is refused as well as claiming more
+    assert!(connect(&chain, paying(reward + fee).seal()).is_ok());
+    for value in [reward + fee + 1, reward + fee - 1, reward] {
+        assert!(matches!(
+            connect(&chain, paying(value).seal()),
+            Err(BtcError::InvalidTransaction)
+        ));
+    }
+
+    // a block without fees pays the subsidy alone
+    let unpaid = Draft {
+        transactions: vec![coinbase(reward + 1)],
+        ..Draft::on(&chain)
+    };
+    assert!(matches!(
+        connect(&chain, unpaid.seal()),
+        Err(BtcError::InvalidTransaction)
+    ));
+}
+
+#[test]
+fn rule_coinbase_shape() {
+    let chain = chain();
+    let reward = chain.calculate_block_reward();
+
+    // the first transaction is the coinbase: no inputs, some outputs
+    let with_input = Transaction::new(
+        spend(&genesis_output(&chain), 0).inputs().clone(),
+        coinbase(reward).outputs().clone(),
+    );
+    let without_outputs = Transaction::new(vec![], vec![]);
+    for first in [with_input, without_outputs] {
+        let draft = Draft {
+            transactions: vec![first],
+            ..Draft::on(&chain)
+        };
+        assert!(matches!(
+            connect(&chain, draft.seal()),
+            Err(BtcError::InvalidTransaction)
+        ));
+    }
+    let empty = Draft {
+        transactions: vec![],
+        merkle_root: Some(MerkleRoot::calculate(&[coinbase(reward)])),
+        ..Draft::on(&chain)
+    };
+    assert!(matches!(
+        connect(&chain, empty.seal()),
+        Err(BtcError::InvalidTransaction)
+    ));
+
+    // its tag is at most MAX_COINBASE_TAG_SIZE bytes
+    let tagged = |size| Draft {
+        transactions: vec![coinbase(reward).with_coinbase_tag(vec![0; size])],
+        ..Draft::on(&chain)
+    };
+    assert!(connect(&chain, tagged(MAX_COINBASE_TAG_SIZE).seal()).is_ok());
+    assert!(matches!(
+        connect(&chain, tagged(MAX_COINBASE_TAG_SIZE + 1).seal()),
+        Err(BtcError::InvalidTransaction)
+    ));
+
+    // and no other transaction carries one
+    let payment = spend(&genesis_output(&chain), 0).with_coinbase_tag(vec![1]);
+    let draft = Draft {
+        transactions: vec![coinbase(reward), payment],
+        ..Draft::on(&chain)
+    };
+    assert!(matches!(
+        connect(&chain, draft.seal()),
+        Err(BtcError::InvalidTransaction)
+    ));
+}
+
+#[test]
+fn rule_timestamps() {
+    let chain = chain();
+    let at = |timestamp| Draft {
+        timestamp,
+        ..Draft::on(&chain)
+    };
+
+    // strictly after the previous block
+    assert!(
+        connect(
+            &chain,
+            at(tip_time(&chain) + Duration::milliseconds(1)).seal()
+        )
+        .is_ok()
+    );
+    for timestamp in [tip_time(&chain), tip_time(&chain) - Duration::seconds(1)] {
+        assert!(matches!(
+            connect(&chain, at(timestamp).seal()),
+            Err(BtcError::InvalidBlockHeader)
+        ));
+    }
+
+    // not a rule today: there is no upper bound, a block from the future
+    // is accepted. Adding one is a consensus change
+    assert!(connect(&chain, at(Utc::now() + Duration::days(1)).seal()).is_ok());
+}
+
+#[test]
+fn rule_target() {
+    let chain = chain();
+
+    // the header hash meets the header's target
+    assert!(connect(&chain, Draft::on(&chain).seal()).is_ok());
+    let unmet = Draft {
+        target: U256::zero(),
+        ..Draft::on(&chain)
+    };
+    assert!(matches!(
+        connect(&chain, unmet.build()),
+        Err(BtcError::InvalidBlock)
+    ));
+    // that the header's target is the chain's current target is not
+    // checked by `add_block`; difficulty is only followed by block builders
+}
+
+#[test]
+fn rule_merkle_root() {
+    let chain = chain();
+
+    // the header commits to exactly the block's transactions
+    let other = Draft {
+        merkle_root: Some(MerkleRoot::calculate(&[coinbase(1)])),
+        ..Draft::on(&chain)
+    };
+    assert!(matches!(
+        connect(&chain, other.seal()),
+        Err(BtcError::InvalidMerkleRoot)
+    ));
+    let reward = chain.calculate_block_reward();
+    let payment = spend(&genesis_output(&chain), 0);
+    let reordered = Draft {
+        merkle_root: Some(MerkleRoot::calculate(&[payment.clone(), coinbase(reward)])),
+        transactions: vec![coinbase(reward), payment],
+        ..Draft::on(&chain)
+    };
+    assert!(matches!(
+        connect(&chain, reordered.seal()),
+        Err(BtcError::InvalidMerkleRoot)
+    ));
+}
+
+#[test]
+fn rule_coinbase_maturity() {
+    // not a rule today: a coinbase output can be spent in the very next
+    // block. Adding a maturity is a consensus change
+    let chain = chain();
+    let reward = chain.calculate_block_reward();
+    let draft = Draft {
+        transactions: vec![coinbase(reward), spend(&genesis_output(&chain), 0)],
+        ..Draft::on(&chain)
+    };
+    assert!(connect(&chain, draft.seal()).is_ok());
+}
+
+#[test]
+fn rule_size() {
+    let mut chain = chain();
+    let reward = chain.calculate_block_reward();
+    assert_eq!(chain.max_block_size(), MAX_BLOCK_SIZE);
+
+    // the transactions of a block, coinbase included, take at most the
+    // chain's maximum block size, whatever their number
+    let spends = spend_chain(&genesis_output(&chain), 25, 0);
+    let mut transactions = vec![coinbase(reward)];
+    transactions.extend(spends.clone());
+    let full = Draft {
+        transactions,
+        ..Draft::on(&chain)
+    }
+    .seal();
+    let size = full.transactions_size();
+    assert!(connect(&chain, full.clone()).is_ok());
+    chain.set_max_block_size(size - 1);
+    assert!(matches!(
+        connect(&chain, full),
+        Err(BtcError::BlockTooLarge { .. })
+    ));
+
+    // and templates are built within the same limit
+    let mut template = BlockBuilder::on(&chain)
+        .timestamp(tip_time(&chain) + Duration::seconds(1))
+        .packages(spends.into_iter().map(|spend| (spend, 0)))
+        .build(key().public_key());
+    assert!(template.transactions().len() > 1);
+    assert!(template.transactions_size() < size);
+    template.mine(usize::MAX);
+    assert!(connect(&chain, template).is_ok());
+    chain.set_max_block_size(MAX_BLOCK_SIZE);
+
+    // data outputs are limited in size and number per transaction
+    let with_data = |size, count| {
+        let payment = (0..count).fold(spend(&genesis_output(&chain), 0), |payment, _| {
+            payment.with_data_output(DataOutput::new(vec![0; size]))
+        });
+        Draft {
+            transactions: vec![coinbase(reward), payment],
+            ..Draft::on(&chain)
+        }
+    };
+    assert!(
+        connect(
+            &chain,
+            with_data(MAX_DATA_OUTPUT_SIZE, MAX_DATA_OUTPUTS_PER_TX).seal()
+        )
+        .is_ok()
+    );
+    for (size, count) in [
+        (MAX_DATA_OUTPUT_SIZE + 1, 1),
+        (MAX_DATA_OUTPUT_SIZE, MAX_DATA_OUTPUTS_PER_TX + 1),
+    ] {
+        assert!(matches!(
+            connect(&chain, with_data(size, count).seal()),
+            Err(BtcError::InvalidTransaction)
+        ));
+    }
+}
+
+#[test]
+fn rule_transaction_inputs() {
+    let chain = chain();
+    let reward = chain.calculate_block_reward();
+    let output = genesis_output(&chain);
+    let with = |transactions: Vec<Transaction>| {
+        let mut all = vec![coinbase(reward)];
+        all.extend(transactions);
+        Draft {
+            transactions: all,
+            ..Draft::on(&chain)
+        }
+    };
+
+    // inputs are signed by the key the output pays
+    let forged = Transaction::new(
+        vec![TransactionInput::new(
+            output.hash(),
+            Signature::sign_output(&output.hash(), &PrivateKey::from_seed(b"thief")),
+        )],
+        spend(&output, 0).outputs().clone(),
+    );
+    assert!(matches!(
+        connect(&chain, with(vec![forged]).seal()),
+        Err(BtcError::InvalidSignature)
+    ));
+
+    // spend an existing output once
+    let missing = TransactionOutput::new(1, Uuid::new_v4(), key().public_key());
+    assert!(matches!(
+        connect(&chain, with(vec![spend(&missing, 0)]).seal()),
+        Err(BtcError::InvalidTransaction)
+    ));
+    let twice = with(vec![spend(&output, 0), spend(&output, 0)]);
+    assert!(matches!(
+        connect(&chain, twice.seal()),
+        Err(BtcError::DoubleSpending)
+    ));
+
+    // and pay out at most what they spend
+    let overspend = Transaction::new(
+        spend(&output, 0).inputs().clone(),
+        vec![TransactionOutput::new(
+            output.value() + 1,
+            Uuid::new_v4(),
+            key().public_key(),
+        )],
+    );
+    assert!(matches!(
+        connect(&chain, with(vec![overspend]).seal()),
+        Err(BtcError::InvalidTransaction)
+    ));
+
+    // within their expiry height
+    let expiring = |height| with(vec![spend(&output, 0).with_expiry(height)]);
+    assert!(connect(&chain, expiring(BlockHeight::new(1)).seal()).is_ok());
+    assert!(matches!(
+        connect(&chain, expiring(BlockHeight::GENESIS).seal()),
+        Err(BtcError::ExpiredTransaction)
+    ));
+}
@@ -0,0 +1,110 @@
+use std::{fmt, str::FromStr};
+
+use crate::{
+    crypto::{PublicKey, base58check_decode, base58check_encode},
+    error::{BtcError, Result},
+};
+
+// version byte of an address; zero, so every address starts with '1'
+const ADDRESS_VERSION: u8 = 0x00;
+const ADDRESS_HASH_LEN: usize = 20;
+
+/// Short, checksummed text form of a public key for showing to and
+/// copying between users: Base58Check of a version byte and the first
+/// 20 bytes of the SHA-256 of the compressed key. Outputs still pay
+/// the full public key; an address only identifies it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Address([u8; ADDRESS_HASH_LEN]);
+
+impl Address {
+    /// Whether this is the address of `pubkey`.
+    pub fn matches(&self, pubkey: &PublicKey) -> bool {
+        pubkey.address() == *self
+    }
+}
+
+impl PublicKey {
+    pub fn address(&self) -> Address {
+        let compressed = self.as_verifying_key().to_encoded_point(true);
+        let digest =
+            hex::decode(sha256::digest(compressed.as_bytes())).expect("BUG: digest is hex");
+        let mut hash = [0; ADDRESS_HASH_LEN];
+        hash.copy_from_slice(&digest[..ADDRESS_HASH_LEN]);
+        Address(hash)
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut payload = vec![ADDRESS_VERSION];
+        payload.extend_from_slice(&self.0);
+        write!(f, "{}", base58check_encode(&payload))
+    }
+}
+
+impl FromStr for Address {
+    type Err = BtcError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let payload = base58check_decode(s.trim()).map_err(|_| BtcError::InvalidAddress)?;
+        match payload.as_slice() {
+            [ADDRESS_VERSION, hash @ ..] => hash
+                .try_into()
+                .map(Address)
+                .map_err(|_| BtcError::InvalidAddress),
+            _ => Err(BtcError::InvalidAddress),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{KeyFormat, PrivateKey};
+
+    #[test]
+    fn test_address_roundtrip() {
+        let pubkey = PrivateKey::default().public_key();
+        let address = pubkey.address();
+        let encoded = address.to_string();
+        assert!(encoded.starts_with('1'));
+        assert_eq!(encoded.parse::<Address>().unwrap(), address);
+        assert!(address.matches(&pubkey));
+        assert!(!address.matches(&PrivateKey::default().public_key()));
+    }
+
+    #[test]
+    fn test_address_known_vector() {
+        // private key 1
+        let key = PrivateKey::import(
+            "0000000000000000000000000000000000000000000000000000000000000001",
+            KeyFormat::Hex,
+        )
+        .unwrap();
+        let address = key.public_key().address();
+        // SHA-256 of the compressed generator point, truncated
+        assert_eq!(
+            hex::encode(address.0),
+            "0f715baf5d4c2ed329785cef29e562f73488c8a2"
+        );
+        assert_eq!(address.to_string(), "12Qew9628up4XwNAGWsP66GfaBhibCjqaQ");
+    }
+
+    #[test]
+    fn test_address_rejects_bad_input() {
+        let encoded = PrivateKey::default().public_key().address().to_string();
+        let mut corrupted = encoded.clone().into_bytes();
+        let last = corrupted.len() - 1;
+        corrupted[last] = if corrupted[last] == b'2' { b'3' } else { b'2' };
+        assert!(
+            String::from_utf8(corrupted)
+                .unwrap()
+                .parse::<Address>()
+                .is_err()
+        );
+        assert!("0OIl".parse::<Address>().is_err());
+        // a WIF key is valid Base58Check, but not an address
+        let wif = PrivateKey::default().export(KeyFormat::Wif);
+        assert!(wif.parse::<Address>().is_err());
+    }
+}
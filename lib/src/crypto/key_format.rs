@@ -0,0 +1,212 @@
+use std::{fmt, str::FromStr};
+
+use ecdsa::SigningKey;
+use k256::pkcs8::{DecodePrivateKey, EncodePrivateKey, LineEnding};
+
+use crate::{
+    crypto::PrivateKey,
+    error::{BtcError, Result},
+};
+
+// version byte and compressed-key suffix of a mainnet WIF key
+const WIF_VERSION: u8 = 0x80;
+const WIF_COMPRESSED: u8 = 0x01;
+const BASE58_ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// Text encodings a private key can be imported from and exported to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyFormat {
+    /// Base58Check, as used by Bitcoin's wallet import format
+    Wif,
+    /// The 32 raw key bytes in hex
+    Hex,
+    /// PKCS#8 PEM, matching the PEM files public keys are saved as
+    Pem,
+}
+
+impl KeyFormat {
+    /// Guesses the format of an encoded key.
+    pub fn detect(encoded: &str) -> Self {
+        let encoded = encoded.trim();
+        if encoded.starts_with("-----BEGIN") {
+            KeyFormat::Pem
+        } else if encoded.len() == 64 && encoded.bytes().all(|b| b.is_ascii_hexdigit()) {
+            KeyFormat::Hex
+        } else {
+            KeyFormat::Wif
+        }
+    }
+}
+
+impl FromStr for KeyFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "wif" => Ok(KeyFormat::Wif),
+            "hex" => Ok(KeyFormat::Hex),
+            "pem" => Ok(KeyFormat::Pem),
+            other => Err(format!("unknown key format: {other}")),
+        }
+    }
+}
+
+impl fmt::Display for KeyFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeyFormat::Wif => write!(f, "wif"),
+            KeyFormat::Hex => write!(f, "hex"),
+            KeyFormat::Pem => write!(f, "pem"),
+        }
+    }
+}
+
+impl PrivateKey {
+    pub fn export(&self, format: KeyFormat) -> String {
+        let bytes = self.as_signing_key().to_bytes();
+        match format {
+            KeyFormat::Wif => {
+                let mut payload = vec![WIF_VERSION];
+                payload.extend_from_slice(&bytes);
+                payload.push(WIF_COMPRESSED);
+                base58check_encode(&payload)
+            }
+            KeyFormat::Hex => hex::encode(bytes),
+            KeyFormat::Pem => self
+                .as_signing_key()
+                .to_pkcs8_pem(LineEnding::LF)
+                .expect("BUG: a valid key always encodes")
+                .to_string(),
+        }
+    }
+
+    pub fn import(encoded: &str, format: KeyFormat) -> Result<Self> {
+        let encoded = encoded.trim();
+        let signing_key = match format {
+            KeyFormat::Wif => {
+                let payload = base58check_decode(encoded)?;
+                match payload.as_slice() {
+                    [WIF_VERSION, key @ .., WIF_COMPRESSED] | [WIF_VERSION, key @ ..]
+                        if key.len() == 32 =>
+                    {
+                        SigningKey::from_slice(key)
+                    }
+                    _ => return Err(BtcError::InvalidPrivateKey),
+                }
+            }
+            KeyFormat::Hex => {
+                let bytes = hex::decode(encoded).map_err(|_| BtcError::InvalidPrivateKey)?;
+                SigningKey::from_slice(&bytes)
+            }
+            KeyFormat::Pem => {
+                return SigningKey::from_pkcs8_pem(encoded)
+                    .map(PrivateKey::from_signing_key)
+                    .map_err(|_| BtcError::InvalidPrivateKey);
+            }
+        };
+        signing_key
+            .map(PrivateKey::from_signing_key)
+            .map_err(|_| BtcError::InvalidPrivateKey)
+    }
+}
+
+fn checksum(payload: &[u8]) -> [u8; 4] {
+    let once = hex::decode(sha256::digest(payload)).expect("BUG: digest is hex");
+    let twice = hex::decode(sha256::digest(once.as_slice())).expect("BUG: digest is hex");
+    [twice[0], twice[1], twice[2], twice[3]]
+}
+
+pub(crate) fn base58check_encode(payload: &[u8]) -> String {
+    let mut bytes = payload.to_vec();
+    bytes.extend_from_slice(&checksum(payload));
+    // base 58 digits, least significant first
+    let mut digits: Vec<u8> = vec![];
+    for &byte in &bytes {
+        let mut carry = byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    // every leading zero byte is written as a leading '1'
+    let zeros = bytes.iter().take_while(|&&byte| byte == 0).count();
+    std::iter::repeat_n(b'1', zeros)
+        .chain(
+            digits
+                .iter()
+                .rev()
+                .map(|&digit| BASE58_ALPHABET[digit as usize]),
+        )
+        .map(char::from)
+        .collect()
+}
+
+pub(crate) fn base58check_decode(encoded: &str) -> Result<Vec<u8>> {
+    // base 256 digits, least significant first
+    let mut bytes: Vec<u8> = vec![];
+    for c in encoded.bytes() {
+        let mut carry = BASE58_ALPHABET
+            .iter()
+            .position(|&a| a == c)
+            .ok_or(BtcError::InvalidPrivateKey)? as u32;
+        for byte in bytes.iter_mut() {
+            carry += *byte as u32 * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push(carry as u8);
+            carry >>= 8;
+        }
+    }
+    let zeros = encoded.bytes().take_while(|&c| c == b'1').count();
+    bytes.extend(std::iter::repeat_n(0, zeros));
+    bytes.reverse();
+    if bytes.len() < 4 {
+        return Err(BtcError::InvalidPrivateKey);
+    }
+    let (payload, check) = bytes.split_at(bytes.len() - 4);
+    if checksum(payload) != check {
+        return Err(BtcError::InvalidPrivateKey);
+    }
+    Ok(payload.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_roundtrip_in_every_format() {
+        let key = PrivateKey::default();
+        for format in [KeyFormat::Wif, KeyFormat::Hex, KeyFormat::Pem] {
+            let encoded = key.export(format);
+            assert_eq!(KeyFormat::detect(&encoded), format);
+            let imported = PrivateKey::import(&encoded, format).unwrap();
+            assert_eq!(imported.public_key(), key.public_key());
+        }
+    }
+
+    #[test]
+    fn test_wif_known_vector() {
+        // private key 1, compressed
+        let wif = "KwDiBf89QgGbjEhKnhXJuH7LrciVrZi3qYjgd9M7rFU73sVHnoWn";
+        let key = PrivateKey::import(wif, KeyFormat::Wif).unwrap();
+        assert_eq!(
+            key.export(KeyFormat::Hex),
+            "0000000000000000000000000000000000000000000000000000000000000001"
+        );
+        assert_eq!(key.export(KeyFormat::Wif), wif);
+    }
+
+    #[test]
+    fn test_wif_rejects_bad_checksum() {
+        let wif = "KwDiBf89QgGbjEhKnhXJuH7LrciVrZi3qYjgd9M7rFU73sVHnoWo";
+        assert!(PrivateKey::import(wif, KeyFormat::Wif).is_err());
+    }
+}
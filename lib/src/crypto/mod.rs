@@ -1,7 +1,11 @@
+mod address;
+mod key_format;
 mod private_key;
 mod public_key;
 mod signature;
 
+pub use address::*;
+pub use key_format::*;
 pub use private_key::*;
 pub use public_key::*;
 pub use signature::*;
@@ -6,16 +6,44 @@ use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Result as IoResu
 
 use rand_core::OsRng; // Use rand_core's OsRng for compatibility
 
-use crate::{crypto::PublicKey, utils::Saveable};
+use crate::{
+    crypto::{PublicKey, Signature},
+    utils::Saveable,
+};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct PrivateKey(#[serde(with = "signkey_serde")] SigningKey<Secp256k1>);
 
 impl PrivateKey {
+    pub fn from_signing_key(key: SigningKey<Secp256k1>) -> Self {
+        PrivateKey(key)
+    }
+
     pub fn public_key(&self) -> PublicKey {
         PublicKey::new(*self.0.verifying_key())
     }
 
+    /// Signs `message` under the signed message domain; see
+    /// `Signature::sign_message`.
+    pub fn sign_message(&self, message: &[u8]) -> Signature {
+        Signature::sign_message(message, self)
+    }
+
+    /// Derives a key from `seed`, the same one on every run. Meant for
+    /// tests and fixtures only: anyone who knows the seed has the key.
+    #[cfg(any(test, feature = "deterministic-keys"))]
+    pub fn from_seed(seed: &[u8]) -> Self {
+        let mut bytes = hex::decode(sha256::digest(seed)).expect("BUG: digest is hex");
+        // the digest is a valid scalar unless it is zero or above the
+        // curve order, which is astronomically unlikely; rehash if so
+        loop {
+            if let Ok(key) = SigningKey::from_slice(&bytes) {
+                return PrivateKey(key);
+            }
+            bytes = hex::decode(sha256::digest(bytes.as_slice())).expect("BUG: digest is hex");
+        }
+    }
+
     /// Returns a reference to the inner SigningKey.
     ///
     /// # Safety
@@ -63,6 +91,39 @@ mod signkey_serde {
         D: serde::Deserializer<'de>,
     {
         let bytes: Vec<u8> = Vec::<u8>::deserialize(deserializer)?;
-        Ok(super::SigningKey::from_slice(&bytes).unwrap())
+        super::SigningKey::from_slice(&bytes).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KeyFormat;
+
+    #[test]
+    fn test_from_seed_is_reproducible() {
+        let alice = PrivateKey::from_seed(b"alice");
+        assert_eq!(
+            alice.public_key(),
+            PrivateKey::from_seed(b"alice").public_key()
+        );
+        assert_ne!(
+            alice.public_key(),
+            PrivateKey::from_seed(b"bob").public_key()
+        );
+        // the key is the SHA-256 of the seed
+        assert_eq!(
+            alice.export(KeyFormat::Hex),
+            "2bd806c97f0e00af1a1fc3328fa763a9269723c8db8fac4f93af71db186d6e90"
+        );
+    }
+
+    #[test]
+    fn test_load_rejects_invalid_key_bytes() {
+        // a three byte string, and 32 zero bytes, aren't valid scalars
+        assert!(PrivateKey::load(&[0x43, 1, 2, 3][..]).is_err());
+        let mut zero = vec![0x58, 32];
+        zero.extend([0; 32]);
+        assert!(PrivateKey::load(zero.as_slice()).is_err());
     }
 }
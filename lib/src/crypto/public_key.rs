@@ -3,13 +3,28 @@ use k256::Secp256k1;
 use serde::{Deserialize, Serialize};
 use spki::EncodePublicKey;
 
-use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Result as IoResult, Write};
+use std::{
+    hash::{Hash as StdHash, Hasher},
+    io::{Error as IoError, ErrorKind as IoErrorKind, Read, Result as IoResult, Write},
+};
 
-use crate::utils::Saveable;
+use crate::{
+    crypto::Signature,
+    error::{BtcError, Result},
+    utils::Saveable,
+};
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct PublicKey(VerifyingKey<Secp256k1>);
 
+// by the compressed point, which equal keys share, so keys can index
+// maps such as the signing keys of `UnsignedTransaction::sign_with`
+impl StdHash for PublicKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_encoded_point(true).as_bytes().hash(state);
+    }
+}
+
 impl PublicKey {
     pub fn new(key: VerifyingKey<Secp256k1>) -> Self {
         PublicKey(key)
@@ -19,6 +34,25 @@ impl PublicKey {
     pub fn as_verifying_key(&self) -> &VerifyingKey<Secp256k1> {
         &self.0
     }
+
+    /// Hex of the compressed SEC1 encoding, for text protocols and URLs.
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0.to_encoded_point(true).as_bytes())
+    }
+
+    /// Parses `to_hex` output; uncompressed keys are accepted too.
+    pub fn from_hex(encoded: &str) -> Result<Self> {
+        let bytes = hex::decode(encoded).map_err(|_| BtcError::InvalidPublicKey)?;
+        VerifyingKey::from_sec1_bytes(&bytes)
+            .map(PublicKey)
+            .map_err(|_| BtcError::InvalidPublicKey)
+    }
+
+    /// Whether `signature` is this key's over `message`, made by
+    /// `PrivateKey::sign_message`.
+    pub fn verify_message(&self, message: &[u8], signature: &Signature) -> bool {
+        signature.verify_message(message, self)
+    }
 }
 
 impl Saveable for PublicKey {
@@ -41,3 +75,19 @@ impl Saveable for PublicKey {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::PrivateKey;
+
+    #[test]
+    fn test_public_key_hex() {
+        let key = PrivateKey::default().public_key();
+        let encoded = key.to_hex();
+        assert_eq!(encoded.len(), 66);
+        assert_eq!(PublicKey::from_hex(&encoded).unwrap(), key);
+        assert!(PublicKey::from_hex(&encoded[2..]).is_err());
+        assert!(PublicKey::from_hex("zz").is_err());
+    }
+}
@@ -6,8 +6,13 @@ use signature::{Signer, Verifier};
 use crate::{
     crypto::{PrivateKey, PublicKey},
     custom_sha_types::Hash,
+    error::{BtcError, Result},
 };
 
+// prefixed to signed messages, so no message signature can pass for one
+// over an output or block hash, or the other way round
+const MESSAGE_DOMAIN: &[u8] = b"Custom Decentralized Ledger Signed Message:\n";
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Signature(ECDSASignature<Secp256k1>);
 
@@ -18,10 +23,90 @@ impl Signature {
         Signature(signature)
     }
 
+    /// A signature that verifies nothing but encodes to the most bytes
+    /// any can: each byte of r and s takes two bytes in CBOR from 24 on,
+    /// so real ones vary in size. Stands in for the signatures of a
+    /// transaction that isn't signed yet to bound its size.
+    pub(crate) fn largest() -> Self {
+        let scalar = [0x7f; 32];
+        Signature(
+            ECDSASignature::from_scalars(scalar, scalar).expect("BUG: 0x7f.. is a valid scalar"),
+        )
+    }
+
     pub fn verify(&self, output_hash: &Hash, public_key: &PublicKey) -> bool {
         public_key
             .as_verifying_key()
             .verify(&output_hash.as_bytes(), &self.0)
             .is_ok()
     }
+
+    /// What `sign_message` signs: the hash of `message` behind the
+    /// signed message domain.
+    pub fn message_hash(message: &[u8]) -> Hash {
+        Hash::hash(&(MESSAGE_DOMAIN, message))
+    }
+
+    /// Signs an arbitrary message, e.g. to prove control of a key off
+    /// chain.
+    pub fn sign_message(message: &[u8], private_key: &PrivateKey) -> Self {
+        Self::sign_output(&Self::message_hash(message), private_key)
+    }
+
+    pub fn verify_message(&self, message: &[u8], public_key: &PublicKey) -> bool {
+        self.verify(&Self::message_hash(message), public_key)
+    }
+
+    /// Hex of the 64 byte `r || s` encoding, for text protocols and URLs.
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0.to_bytes())
+    }
+
+    pub fn from_hex(encoded: &str) -> Result<Self> {
+        let bytes = hex::decode(encoded).map_err(|_| BtcError::InvalidSignature)?;
+        ECDSASignature::from_slice(&bytes)
+            .map(Signature)
+            .map_err(|_| BtcError::InvalidSignature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_signature() {
+        let key = PrivateKey::from_seed(b"signer");
+        let signature = Signature::sign_message(b"I control this key", &key);
+        assert!(signature.verify_message(b"I control this key", &key.public_key()));
+        assert!(!signature.verify_message(b"I control that key", &key.public_key()));
+        let other = PrivateKey::from_seed(b"other").public_key();
+        assert!(!signature.verify_message(b"I control this key", &other));
+
+        // a message signature is no signature over the message's hash
+        let hash = Hash::hash(&b"I control this key");
+        assert!(!signature.verify(&hash, &key.public_key()));
+        let output_signature = Signature::sign_output(&hash, &key);
+        assert!(!output_signature.verify_message(b"I control this key", &key.public_key()));
+
+        let decoded = Signature::from_hex(&signature.to_hex()).unwrap();
+        assert!(decoded.verify_message(b"I control this key", &key.public_key()));
+        assert!(Signature::from_hex(&signature.to_hex()[2..]).is_err());
+        assert!(Signature::from_hex("zz").is_err());
+    }
+
+    #[test]
+    fn test_largest_signature() {
+        let size = |signature: &Signature| {
+            let mut bytes = vec![];
+            ciborium::ser::into_writer(signature, &mut bytes).unwrap();
+            bytes.len()
+        };
+        let largest = size(&Signature::largest());
+        for seed in 0..200u32 {
+            let key = PrivateKey::from_seed(&seed.to_be_bytes());
+            let signature = Signature::sign_output(&Hash::hash(&seed), &key);
+            assert!(size(&signature) <= largest);
+        }
+    }
 }
@@ -10,6 +10,7 @@ use crate::U256;
 pub struct Hash(U256);
 
 impl Hash {
+    #[allow(clippy::self_named_constructors)]
     pub fn hash<T: serde::Serialize>(data: &T) -> Self {
         let mut serialized: Vec<u8> = vec![];
 
@@ -35,6 +36,10 @@ impl Hash {
         Hash(U256::zero())
     }
 
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Hash(U256::from_big_endian(&bytes))
+    }
+
     pub fn as_bytes(&self) -> [u8; 32] {
         self.0.to_big_endian()
     }
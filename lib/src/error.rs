@@ -1,5 +1,9 @@
+use std::io::Error as IoError;
+
 use thiserror::Error;
 
+use crate::network::{Message, RejectCode};
+
 #[derive(Error, Debug)]
 pub enum BtcError {
     #[error("Invalid transaction")]
@@ -22,8 +26,44 @@ pub enum BtcError {
     InvalidPublicKey,
     #[error("Invalid private key")]
     InvalidPrivateKey,
+    #[error("Invalid address")]
+    InvalidAddress,
+    #[error("Transaction expired")]
+    ExpiredTransaction,
     #[error("Double spending detected")]
     DoubleSpending,
+    #[error("Authority threshold must be between one and the number of keys")]
+    InvalidAuthoritySet,
+    #[error("Block signed by {signed} authorities, {required} required")]
+    MissingAuthoritySignatures { signed: usize, required: usize },
+    #[error("Validators must be distinct, and there must be at least one")]
+    InvalidValidatorSet,
+    #[error("Block not signed by the validator whose turn it is")]
+    MissingValidatorSignature,
+    #[error("Reorganization would disconnect {depth} blocks, at most {max} allowed")]
+    ReorgTooDeep { depth: u64, max: u64 },
+    #[error("Branch isn't longer than the blocks it would replace")]
+    BranchNotLonger,
+    #[error("Block was marked invalid by an operator")]
+    BlockMarkedInvalid,
+    #[error("Block's transactions take {size} bytes, at most {max} allowed")]
+    BlockTooLarge { size: usize, max: usize },
+    #[error("Outputs hold {available}, {needed} needed")]
+    InsufficientFunds { available: u64, needed: u64 },
 }
 
 pub type Result<T> = std::result::Result<T, BtcError>;
+
+#[derive(Error, Debug)]
+pub enum ClientError {
+    #[error("Failed to connect to node: {0}")]
+    Connect(#[from] IoError),
+    #[error("Failed to send message: {0}")]
+    Send(#[from] ciborium::ser::Error<IoError>),
+    #[error("Failed to receive message: {0}")]
+    Receive(#[from] ciborium::de::Error<IoError>),
+    #[error("Unexpected response from node: {0:?}")]
+    UnexpectedResponse(Box<Message>),
+    #[error("Node rejected the request ({code:?}): {reason}")]
+    Rejected { code: RejectCode, reason: String },
+}
@@ -1,11 +1,16 @@
-use serde::{Deserialize, Serialize};
-use uint::construct_uint;
-construct_uint! {
- // Construct an unsigned 256-bit integer
- // consisting of 4 x 64-bit words
- #[derive(Serialize, Deserialize)]
- pub struct U256(4);
+// the impls generated by construct_uint! predate `div_ceil`
+#[allow(clippy::manual_div_ceil)]
+mod uint256 {
+    use serde::{Deserialize, Serialize};
+    use uint::construct_uint;
+    construct_uint! {
+     // Construct an unsigned 256-bit integer
+     // consisting of 4 x 64-bit words
+     #[derive(Serialize, Deserialize)]
+     pub struct U256(4);
+    }
 }
+pub use uint256::U256;
 
 // initial reward in bitcoin - multiply by 10^8 to get satoshis
 pub const INITIAL_REWARD: u64 = 50;
@@ -32,15 +37,55 @@ pub const MIN_TARGET: U256 = U256([
 // ]);
 // difficulty update interval in blocks (Bitcoin uses 2016)
 pub const DIFFICULTY_UPDATE_INTERVAL: u64 = 2016;
-// maximum mempool transaction age in seconds
+// default maximum mempool transaction age in seconds, see `RelayPolicy`
 pub const MAX_MEMPOOL_TX_AGE: u64 = 600; // 10 minutes
-// maximum amount of transactions allowed in the block
-pub const BLOCK_TRANSACTION_CAP: usize = 20;
+// maximum number of unconfirmed ancestors a mempool transaction may have
+pub const MAX_MEMPOOL_ANCESTORS: usize = 25;
+// default maximum size in bytes of a block's transactions, see `Blockchain::set_max_block_size`
+pub const MAX_BLOCK_SIZE: usize = 1_000_000;
+// maximum size in bytes of the tag a miner can embed in the coinbase transaction
+pub const MAX_COINBASE_TAG_SIZE: usize = 100;
+// maximum size in bytes of a single data output
+pub const MAX_DATA_OUTPUT_SIZE: usize = 80;
+// maximum amount of data outputs allowed in a transaction
+pub const MAX_DATA_OUTPUTS_PER_TX: usize = 4;
+// maximum number of entries a node returns in the rich list
+pub const MAX_RICH_LIST_SIZE: usize = 100;
+// maximum number of recent blocks a node reports activity for in analytics
+pub const MAX_ANALYTICS_BLOCKS: usize = 1000;
+// default number of blocks a node disconnects at most to switch to a longer branch
+pub const MAX_REORG_DEPTH: u64 = 100;
+// maximum number of mempool transactions a node returns for one FetchTransactions
+pub const MAX_FETCH_TRANSACTIONS: usize = 500;
+// maximum number of blocks one UTXO delta spans, keeping it well under the message size
+pub const MAX_UTXO_DELTA_BLOCKS: u64 = 100;
+// maximum number of keys one Rescan scans the chain for
+pub const MAX_RESCAN_KEYS: usize = 1_000;
 
+/// A utreexo-style accumulator committing to the UTXO set, so clients
+/// holding only its roots can check spends with inclusion proofs.
+/// Building the accumulator (`Forest`) takes the `utxo-accumulator`
+/// feature; checking proofs against it doesn't.
+pub mod accumulator;
+#[cfg(feature = "assets")]
+pub mod assets;
+pub mod client;
+pub mod consensus;
+/// The consensus rules as executable specification: each rule a block
+/// must follow to extend the chain, with blocks that follow it and blocks
+/// that break it. Storage and validation may be reworked, but these
+/// vectors only change with the consensus rules themselves.
+#[cfg(test)]
+mod consensus_spec;
 pub mod crypto;
 pub mod custom_sha_types;
-pub mod network;
 pub mod error;
 pub mod network;
+/// Fixtures shared by the tests of every crate: deterministic keys,
+/// coinbase and spending transactions, blocks built field by field
+/// (`TestBlock`) and chains extended with valid blocks. Other crates
+/// enable the `testutil` feature in their dev-dependencies.
+#[cfg(any(test, feature = "testutil"))]
+pub mod testutil;
 pub mod types;
 pub mod utils;
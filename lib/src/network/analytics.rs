@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+
+use crate::types::BlockHeight;
+
+/// Upper bounds, in days, of the age buckets of `ChainAnalytics::utxo_age`.
+/// A last bucket holds everything older.
+pub const UTXO_AGE_BUCKETS: [u32; 5] = [1, 7, 30, 180, 365];
+
+/// Unspent outputs created within an age range.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct AgeBucket {
+    /// Outputs at most this many days old, and older than the bucket
+    /// before; `None` for the oldest bucket
+    pub max_days: Option<u32>,
+    pub outputs: u64,
+    /// Their total value, in satoshis
+    pub value: u64,
+}
+
+/// What the transactions of one block moved.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct BlockActivity {
+    pub height: BlockHeight,
+    /// Value of the outputs the block spent, in satoshis
+    pub value_moved: u64,
+    /// Coins spent times the days they had been unspent for. Old coins
+    /// moving weigh more than the same coins changing hands daily.
+    pub coin_days_destroyed: f64,
+}
+
+/// Economic activity figures over a node's chain, served in response
+/// to `FetchAnalytics`. Ages are measured at the tip's timestamp.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ChainAnalytics {
+    /// Height of the next block when the figures were taken
+    pub height: BlockHeight,
+    /// Unspent outputs by age, youngest first, see `UTXO_AGE_BUCKETS`
+    pub utxo_age: Vec<AgeBucket>,
+    /// The most recent blocks, oldest first
+    pub blocks: Vec<BlockActivity>,
+    /// Value moved by `blocks` over the total supply: how many times
+    /// over the supply changed hands in that window
+    pub velocity: f64,
+}
+
+impl ChainAnalytics {
+    pub fn coin_days_destroyed(&self) -> f64 {
+        self.blocks
+            .iter()
+            .map(|block| block.coin_days_destroyed)
+            .sum()
+    }
+}
@@ -0,0 +1,11 @@
+use serde::{Deserialize, Serialize};
+
+/// What an authenticated connection may do. Anonymous connections
+/// (peers, miners, wallets) can use everything that requires no role.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub enum Role {
+    /// Queries that are expensive or reveal operator details, e.g. for an explorer
+    ReadOnly,
+    /// Everything, including stopping the node
+    Admin,
+}
@@ -0,0 +1,19 @@
+use serde::{Deserialize, Serialize};
+
+/// On-disk footprint of a node, served in response to `FetchDiskUsage`.
+/// Sizes are in bytes; missing files count as zero.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct DiskUsage {
+    /// The saved blockchain (chainstate) file
+    pub blockchain: u64,
+    /// Blocks accepted since the last save
+    pub write_ahead_log: u64,
+    /// A save that is in progress or was interrupted
+    pub pending_save: u64,
+}
+
+impl DiskUsage {
+    pub fn total(&self) -> u64 {
+        self.blockchain + self.write_ahead_log + self.pending_save
+    }
+}
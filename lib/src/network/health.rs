@@ -0,0 +1,31 @@
+use serde::{Deserialize, Serialize};
+
+/// Rolling block production and traffic figures, served in response to `FetchHealth`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct NetworkHealth {
+    /// Number of recent block intervals the interval figures cover
+    pub intervals: u64,
+    /// Mean time between recent blocks, in seconds
+    pub average_block_interval: f64,
+    /// Variance of the time between recent blocks, in seconds squared
+    pub block_interval_variance: f64,
+    /// Time since the best block was mined, in seconds
+    pub seconds_since_last_block: i64,
+    /// Transactions accepted into the mempool per minute, recently
+    pub mempool_inflow_per_minute: f64,
+    /// Share of received blocks that didn't build on the best block
+    pub orphan_rate: f64,
+    /// Number of recent blocks relayed by peers the propagation figures cover
+    pub propagation_samples: u64,
+    /// Mean time from a recent block's header timestamp until it arrived,
+    /// in seconds
+    pub average_propagation_delay: f64,
+    /// Longest time a recent block took to arrive, in seconds
+    pub max_propagation_delay: f64,
+    /// Mean time spent validating and connecting a recent block, in seconds
+    pub average_validation_time: f64,
+    /// Invalid blocks peers sent in the last minute
+    pub rejected_blocks_last_minute: u64,
+    /// Invalid transactions peers and wallets sent in the last minute
+    pub rejected_transactions_last_minute: u64,
+}
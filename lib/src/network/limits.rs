@@ -0,0 +1,188 @@
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::network::{DEFAULT_MAX_MESSAGE_SIZE, PeerKind};
+
+/// Smallest message size limit a node accepts, so that it can still
+/// exchange ordinary blocks and transactions.
+pub const MIN_MAX_MESSAGE_SIZE: usize = 64 * 1024;
+/// Largest message size limit a node accepts. Message buffers are
+/// allocated up front, so a peer can make the node allocate this much
+/// per connection.
+pub const MAX_MAX_MESSAGE_SIZE: usize = 1024 * 1024 * 1024;
+
+/// The resource limits a node enforces on its connections, served in
+/// `NetworkInfo` so peers know how large a message they may send.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct NetworkLimits {
+    /// Largest encoded message accepted, in bytes
+    pub max_message_size: usize,
+    /// Incoming connections served at the same time
+    pub max_connections: usize,
+    /// Seconds a message may take to arrive once its length is known
+    pub message_timeout_secs: u64,
+    /// Seconds between mempool and stale peer cleanups
+    pub cleanup_interval_secs: u64,
+    /// Connections that said `Hello` as a wallet served at the same time
+    pub max_wallets: usize,
+    /// Connections that said `Hello` as a miner served at the same time
+    pub max_miners: usize,
+    /// Longest idle timeout granted to a wallet or miner session, in seconds
+    pub max_idle_secs: u64,
+}
+
+impl Default for NetworkLimits {
+    fn default() -> Self {
+        NetworkLimits {
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            max_connections: 100,
+            message_timeout_secs: 60,
+            cleanup_interval_secs: 30,
+            max_wallets: 50,
+            max_miners: 10,
+            max_idle_secs: 300,
+        }
+    }
+}
+
+/// Why a `NetworkLimits` can't be used.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum InvalidLimits {
+    #[error(
+        "max message size of {0} bytes is outside {MIN_MAX_MESSAGE_SIZE}..={MAX_MAX_MESSAGE_SIZE}"
+    )]
+    MessageSize(usize),
+    #[error("at least one connection must be allowed")]
+    NoConnections,
+    #[error("message timeout must be at least a second")]
+    NoMessageTimeout,
+    #[error("cleanup interval must be at least a second")]
+    NoCleanupInterval,
+    #[error("idle timeout must be at least a second")]
+    NoIdleTimeout,
+}
+
+impl NetworkLimits {
+    pub fn validate(&self) -> Result<(), InvalidLimits> {
+        if !(MIN_MAX_MESSAGE_SIZE..=MAX_MAX_MESSAGE_SIZE).contains(&self.max_message_size) {
+            return Err(InvalidLimits::MessageSize(self.max_message_size));
+        }
+        if self.max_connections == 0 {
+            return Err(InvalidLimits::NoConnections);
+        }
+        if self.message_timeout_secs == 0 {
+            return Err(InvalidLimits::NoMessageTimeout);
+        }
+        if self.cleanup_interval_secs == 0 {
+            return Err(InvalidLimits::NoCleanupInterval);
+        }
+        if self.max_idle_secs == 0 {
+            return Err(InvalidLimits::NoIdleTimeout);
+        }
+        Ok(())
+    }
+
+    /// Sessions of `kind` served at the same time, `None` if only
+    /// `max_connections` limits them.
+    pub fn max_sessions(&self, kind: PeerKind) -> Option<usize> {
+        match kind {
+            PeerKind::FullNode => None,
+            PeerKind::Wallet => Some(self.max_wallets),
+            PeerKind::Miner => Some(self.max_miners),
+        }
+    }
+
+    /// The idle timeout granted to a session of `kind` that asked for
+    /// `requested_secs`, 0 asking for the longest allowed. Full nodes
+    /// may stay idle however long unless they ask for a timeout.
+    pub fn idle_timeout(&self, kind: PeerKind, requested_secs: u64) -> Option<u64> {
+        let max = match kind {
+            PeerKind::FullNode => None,
+            PeerKind::Wallet | PeerKind::Miner => Some(self.max_idle_secs),
+        };
+        match (requested_secs, max) {
+            (0, max) => max,
+            (requested, Some(max)) => Some(requested.min(max)),
+            (requested, None) => Some(requested),
+        }
+    }
+}
+
+/// A node's connection limits and how much of them is in use, the
+/// response to `FetchNetworkInfo`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct NetworkInfo {
+    pub limits: NetworkLimits,
+    /// Incoming connections currently served
+    pub connections: usize,
+    /// Peers the node keeps outgoing connections to
+    pub peers: usize,
+    /// Wallet sessions currently open
+    pub wallets: usize,
+    /// Miner sessions currently open
+    pub miners: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_limits_are_valid() {
+        assert_eq!(NetworkLimits::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_invalid_limits() {
+        let limits = NetworkLimits::default();
+        let too_small = NetworkLimits {
+            max_message_size: MIN_MAX_MESSAGE_SIZE - 1,
+            ..limits
+        };
+        assert_eq!(
+            too_small.validate(),
+            Err(InvalidLimits::MessageSize(MIN_MAX_MESSAGE_SIZE - 1))
+        );
+        let too_large = NetworkLimits {
+            max_message_size: MAX_MAX_MESSAGE_SIZE + 1,
+            ..limits
+        };
+        assert!(too_large.validate().is_err());
+        let no_connections = NetworkLimits {
+            max_connections: 0,
+            ..limits
+        };
+        assert_eq!(no_connections.validate(), Err(InvalidLimits::NoConnections));
+        let no_timeout = NetworkLimits {
+            message_timeout_secs: 0,
+            ..limits
+        };
+        assert_eq!(no_timeout.validate(), Err(InvalidLimits::NoMessageTimeout));
+        let no_cleanup = NetworkLimits {
+            cleanup_interval_secs: 0,
+            ..limits
+        };
+        assert_eq!(no_cleanup.validate(), Err(InvalidLimits::NoCleanupInterval));
+        let no_idle = NetworkLimits {
+            max_idle_secs: 0,
+            ..limits
+        };
+        assert_eq!(no_idle.validate(), Err(InvalidLimits::NoIdleTimeout));
+    }
+
+    #[test]
+    fn test_session_limits() {
+        let limits = NetworkLimits {
+            max_wallets: 2,
+            max_idle_secs: 60,
+            ..NetworkLimits::default()
+        };
+        assert_eq!(limits.max_sessions(PeerKind::Wallet), Some(2));
+        assert_eq!(limits.max_sessions(PeerKind::FullNode), None);
+        assert_eq!(limits.idle_timeout(PeerKind::Wallet, 0), Some(60));
+        assert_eq!(limits.idle_timeout(PeerKind::Wallet, 30), Some(30));
+        assert_eq!(limits.idle_timeout(PeerKind::Miner, 600), Some(60));
+        assert_eq!(limits.idle_timeout(PeerKind::FullNode, 0), None);
+        assert_eq!(limits.idle_timeout(PeerKind::FullNode, 600), Some(600));
+    }
+}
@@ -0,0 +1,51 @@
+use serde::{Deserialize, Serialize};
+
+/// Memory taken by the in-memory chainstate, served in response to
+/// `FetchMemoryInfo`. Sizes are estimates in bytes, see
+/// `Blockchain::memory_info`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MemoryInfo {
+    /// Number of unspent outputs
+    pub utxos: u64,
+    /// The UTXO set, including the unused capacity of its table
+    pub utxo_bytes: u64,
+    /// Number of mempool transactions
+    pub mempool_transactions: u64,
+    /// The mempool transactions
+    pub mempool_bytes: u64,
+    /// Memory the node's operator allows the chainstate, if any
+    pub budget: Option<u64>,
+}
+
+impl MemoryInfo {
+    pub fn total(&self) -> u64 {
+        self.utxo_bytes + self.mempool_bytes
+    }
+
+    /// Share of the budget in use, above 1 once it is exceeded.
+    pub fn budget_used(&self) -> Option<f64> {
+        self.budget
+            .map(|budget| self.total() as f64 / budget.max(1) as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_info_budget_used() {
+        let info = MemoryInfo {
+            utxo_bytes: 600,
+            mempool_bytes: 200,
+            ..MemoryInfo::default()
+        };
+        assert_eq!(info.total(), 800);
+        assert_eq!(info.budget_used(), None);
+        let budgeted = MemoryInfo {
+            budget: Some(1000),
+            ..info
+        };
+        assert_eq!(budgeted.budget_used(), Some(0.8));
+    }
+}
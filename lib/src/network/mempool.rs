@@ -0,0 +1,168 @@
+use serde::{Deserialize, Serialize};
+
+use crate::custom_sha_types::Hash;
+
+/// A mempool transaction with the totals of its ancestor package: the
+/// transaction and the unconfirmed ones it spends outputs of, directly
+/// or not, which a block has to include with it. Served in response to
+/// `FetchMempoolEntries`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MempoolEntry {
+    pub hash: Hash,
+    /// Fee of the transaction alone, in satoshis
+    pub fee: u64,
+    /// Size of the encoded transaction, in bytes
+    pub size: usize,
+    /// Number of unconfirmed ancestors
+    pub ancestors: usize,
+    /// Fee of the transaction and its ancestors together
+    pub package_fee: u64,
+    /// Size of the transaction and its ancestors together
+    pub package_size: usize,
+}
+
+impl MempoolEntry {
+    /// Satoshis per byte the transaction pays on its own.
+    pub fn fee_rate(&self) -> f64 {
+        self.fee as f64 / self.size.max(1) as f64
+    }
+
+    /// Satoshis per byte of the whole package, which is what template
+    /// assembly ranks the transaction by.
+    pub fn package_fee_rate(&self) -> f64 {
+        self.package_fee as f64 / self.package_size.max(1) as f64
+    }
+}
+
+/// Lower bounds of the fee rate buckets of a `FeeHistogram`, in
+/// satoshis per byte.
+pub const FEE_HISTOGRAM_BUCKETS: [u64; 16] = [
+    0, 1, 2, 3, 5, 8, 10, 15, 20, 30, 50, 75, 100, 200, 500, 1000,
+];
+
+/// Mempool transactions paying from `min_fee_rate` up to the next
+/// bucket's rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FeeBucket {
+    /// Satoshis per byte, inclusive
+    pub min_fee_rate: u64,
+    pub transactions: usize,
+    /// Bytes of the transactions in this bucket
+    pub size: usize,
+    /// Bytes of the transactions in this bucket and all higher ones,
+    /// i.e. what a transaction paying `min_fee_rate` waits behind
+    pub cumulative_size: usize,
+}
+
+/// How the mempool is spread over fee rates, so a wallet can see how
+/// congested it is and what rate gets into the next blocks. Served in
+/// response to `FetchFeeHistogram`.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub struct FeeHistogram {
+    /// Highest rate first; empty buckets are left out
+    pub buckets: Vec<FeeBucket>,
+}
+
+impl FeeHistogram {
+    /// Buckets `entries` by their package fee rate, which is what they
+    /// are mined by.
+    pub fn new(entries: &[MempoolEntry]) -> Self {
+        let mut buckets: Vec<FeeBucket> = FEE_HISTOGRAM_BUCKETS
+            .iter()
+            .map(|&min_fee_rate| FeeBucket {
+                min_fee_rate,
+                transactions: 0,
+                size: 0,
+                cumulative_size: 0,
+            })
+            .collect();
+        for entry in entries {
+            // the integer rate, so 1.9 sat/byte doesn't land in 2
+            let rate = entry.package_fee / entry.package_size.max(1) as u64;
+            let index = FEE_HISTOGRAM_BUCKETS.partition_point(|&min| min <= rate) - 1;
+            buckets[index].transactions += 1;
+            buckets[index].size += entry.size;
+        }
+        buckets.reverse();
+        let mut cumulative_size = 0;
+        for bucket in &mut buckets {
+            cumulative_size += bucket.size;
+            bucket.cumulative_size = cumulative_size;
+        }
+        buckets.retain(|bucket| bucket.transactions > 0);
+        FeeHistogram { buckets }
+    }
+
+    /// Bytes of mempool transactions paying at least `fee_rate`.
+    pub fn size_above(&self, fee_rate: u64) -> usize {
+        self.buckets
+            .iter()
+            .take_while(|bucket| bucket.min_fee_rate >= fee_rate)
+            .map(|bucket| bucket.size)
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mempool_entry_fee_rates() {
+        // a child paying for a parent that paid nothing
+        let entry = MempoolEntry {
+            hash: Hash::zero(),
+            fee: 300,
+            size: 100,
+            ancestors: 1,
+            package_fee: 300,
+            package_size: 200,
+        };
+        assert_eq!(entry.fee_rate(), 3.0);
+        assert_eq!(entry.package_fee_rate(), 1.5);
+    }
+
+    fn entry(fee: u64, size: usize) -> MempoolEntry {
+        MempoolEntry {
+            hash: Hash::zero(),
+            fee,
+            size,
+            ancestors: 0,
+            package_fee: fee,
+            package_size: size,
+        }
+    }
+
+    #[test]
+    fn test_fee_histogram() {
+        assert_eq!(FeeHistogram::new(&[]).buckets, vec![]);
+
+        let histogram = FeeHistogram::new(&[
+            entry(0, 100),
+            entry(190, 100),
+            entry(200, 100),
+            entry(2500, 100),
+            entry(5000, 50),
+            entry(u64::MAX, 10),
+        ]);
+        let rates: Vec<(u64, usize, usize, usize)> = histogram
+            .buckets
+            .iter()
+            .map(|b| (b.min_fee_rate, b.transactions, b.size, b.cumulative_size))
+            .collect();
+        assert_eq!(
+            rates,
+            vec![
+                (1000, 1, 10, 10),
+                (100, 1, 50, 60),
+                (20, 1, 100, 160),
+                (2, 1, 100, 260),
+                (1, 1, 100, 360),
+                (0, 1, 100, 460),
+            ]
+        );
+        assert_eq!(histogram.size_above(20), 160);
+        assert_eq!(histogram.size_above(21), 60);
+        assert_eq!(histogram.size_above(0), 460);
+    }
+}
@@ -1,25 +1,48 @@
 use crate::{
+    accumulator::{Stump, UtxoProof},
     crypto::PublicKey,
-    types::{Block, Transaction, TransactionOutput},
+    custom_sha_types::Hash,
+    network::{
+        BlockTimings, ChainAnalytics, ChainStats, ChainTip, DiskUsage, FeeHistogram, MemoryInfo,
+        MempoolEntry, NetworkHealth, NetworkInfo, PeerKind, RejectCode, RelayPolicy, RescanResult,
+        Role, Session, SyncProgress, ZeroConfRisk,
+    },
+    types::{
+        Block, BlockHeader, BlockHeight, BlockUndo, Transaction, UnsignedTransaction, UtxoDelta,
+        UtxoEntry,
+    },
 };
 use serde::{Deserialize, Serialize};
-use std::io::{Error as IoError, Read, Write};
+use std::{
+    io::{Error as IoError, ErrorKind, Read, Write},
+    time::Duration,
+};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
+/// Largest message accepted unless a node is configured otherwise
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 10 * 1024 * 1024; // 10 MB
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub enum Message {
     /// Fetch all UTXOs belonging to a public key
     FetchUTXOs(PublicKey),
-    /// UTXOs belonging to a public key. Bool determines if marked
-    UTXOs(Vec<(TransactionOutput, bool)>),
+    /// UTXOs belonging to a public key, each marked spent by the mempool
+    /// transaction spending it if any
+    UTXOs(Vec<UtxoEntry>),
     /// Send a transaction to the network
     SubmitTransaction(Transaction),
+    /// Ask a node to pay the recipients, with their amounts, from the
+    /// outputs of the first public key, at the fee rate given or the
+    /// node's minimum, for a client that holds the private key elsewhere
+    BuildTransaction(PublicKey, Vec<(PublicKey, u64)>, Option<u64>),
+    /// This is the response to BuildTransaction, to sign and submit
+    UnsignedTransaction(UnsignedTransaction),
     /// Broadcast a new transaction to other nodes
     NewTransaction(Transaction),
     /// Ask the node to prepare the optimal block template
     /// with the coinbase transaction paying the specified
-    /// public key
-    FetchTemplate(PublicKey),
+    /// public key, optionally tagged by the miner
+    FetchTemplate(PublicKey, Option<Vec<u8>>),
     /// The template
     Template(Block),
     /// Ask the node to validate a block template.
@@ -31,23 +54,312 @@ pub enum Message {
     TemplateValidity(bool),
     /// Submit a mined block to a node
     SubmitTemplate(Block),
+    /// Ask an authority node to co-sign a mined block that extends its
+    /// best block, for chains with an `AuthoritySet`
+    CosignBlock(Block),
+    /// This is the response to CosignBlock: the block with the node's
+    /// signature added
+    Cosigned(Block),
     /// Ask a node to report all the other nodes it knows
     /// about
     DiscoverNodes,
     /// This is the response to DiscoverNodes
     NodeList(Vec<String>),
+    /// Announce addresses nodes can be reached at. Not answered;
+    /// nodes remember new addresses and pass them on to their peers
+    Addr(Vec<String>),
     /// Ask a node whats the highest block it knows about
     /// in comparison to the local blockchain
-    AskDifference(u32),
+    AskDifference(BlockHeight),
     /// This is the response to AskDifference
     Difference(i32),
     /// Ask a node to send a block with the specified height
-    FetchBlock(usize),
+    FetchBlock(BlockHeight),
+    /// Ask a node to send the block with the specified hash.
+    /// Answered with NewBlock, like FetchBlock
+    FetchBlockByHash(Hash),
+    /// Ask a node to send only the header of the block
+    /// with the specified hash
+    FetchHeader(Hash),
+    /// This is the response to FetchHeader
+    Header(BlockHeader),
     /// Broadcast a new block to other nodes
     NewBlock(Block),
+    /// Subscribe to best block changes. The connection then only
+    /// carries TipChanged pushes, so use a dedicated one
+    SubscribeTips,
+    /// Pushed to subscribers whenever the node's best block changes
+    TipChanged(ChainTip),
+    /// Ask a node for its best block once, without subscribing
+    FetchTip,
+    /// This is the response to FetchTip, None for an empty chain
+    Tip(Option<ChainTip>),
+    /// Ask a node which transactions are in its mempool
+    FetchMempool,
+    /// This is the response to FetchMempool: the hashes of the
+    /// transactions, highest fee first
+    Mempool(Vec<Hash>),
+    /// Ask a node for the mempool transactions with these hashes, e.g.
+    /// the ones missing from ours in its `Mempool`
+    FetchTransactions(Vec<Hash>),
+    /// This is the response to FetchTransactions: the transactions
+    /// still in the mempool, at most `MAX_FETCH_TRANSACTIONS`
+    Transactions(Vec<Transaction>),
+    /// Ask a node for its mempool transactions with their fees and the
+    /// fee rates of their ancestor packages
+    FetchMempoolEntries,
+    /// This is the response to FetchMempoolEntries, highest package fee
+    /// rate first
+    MempoolEntries(Vec<MempoolEntry>),
+    /// Ask a node how its mempool is spread over fee rates
+    FetchFeeHistogram,
+    /// This is the response to FetchFeeHistogram
+    FeeHistogram(FeeHistogram),
+    /// Ask a node when it received and connected the block with this hash
+    FetchBlockTimings(Hash),
+    /// This is the response to FetchBlockTimings
+    BlockTimings(BlockTimings),
+    /// Ask a node how likely the mempool transaction with this hash is
+    /// to be double spent, on nodes run with `--zero-conf-risk`
+    FetchZeroConfRisk(Hash),
+    /// This is the response to FetchZeroConfRisk
+    ZeroConfRisk(ZeroConfRisk),
+    /// Ask a node for the roots of its UTXO accumulator at its tip, on
+    /// nodes built with the `utxo-accumulator` feature
+    FetchUtxoAccumulator,
+    /// This is the response to FetchUtxoAccumulator
+    UtxoAccumulator(Stump),
+    /// Ask a node to prove the output with this hash is unspent, on
+    /// nodes built with the `utxo-accumulator` feature
+    FetchUtxoProof(Hash),
+    /// This is the response to FetchUtxoProof: the output and its proof
+    /// against the accumulator at the tip, `None` if it isn't unspent
+    UtxoProof(Option<UtxoProof>),
+    /// Ask a node for supply and UTXO set statistics, with a
+    /// rich list of at most the specified number of entries
+    FetchStats(usize),
+    /// This is the response to FetchStats
+    Stats(ChainStats),
+    /// Ask a node for UTXO ages, and the activity of at most the
+    /// specified number of recent blocks
+    FetchAnalytics(usize),
+    /// This is the response to FetchAnalytics
+    Analytics(ChainAnalytics),
+    /// Ask a node how much disk space its data takes up
+    FetchDiskUsage,
+    /// This is the response to FetchDiskUsage
+    DiskUsage(DiskUsage),
+    /// Ask a node how much memory its UTXO set and mempool take up
+    FetchMemoryInfo,
+    /// This is the response to FetchMemoryInfo
+    MemoryInfo(MemoryInfo),
+    /// Ask a node for block interval and traffic statistics
+    FetchHealth,
+    /// This is the response to FetchHealth
+    Health(NetworkHealth),
+    /// Ask a node how far its blockchain download got
+    FetchSyncProgress,
+    /// This is the response to FetchSyncProgress: the download under way
+    /// or the last one, `None` if the node never downloaded blocks
+    SyncProgress(Option<SyncProgress>),
+    /// Ask a node for the assets held by a public key
+    FetchAssetBalances(PublicKey),
+    /// This is the response to FetchAssetBalances:
+    /// (asset id, ticker, amount) for every asset held
+    AssetBalances(Vec<(Hash, String, u64)>),
+    /// Ask a node for the rules it relays transactions by
+    FetchPolicy,
+    /// This is the response to FetchPolicy
+    Policy(RelayPolicy),
+    /// Ask a node for its connection limits and usage
+    FetchNetworkInfo,
+    /// This is the response to FetchNetworkInfo
+    NetworkInfo(NetworkInfo),
+    /// Ask a node to scan its blocks from the specified height
+    /// on for outputs paying any of the public keys
+    Rescan(Vec<PublicKey>, BlockHeight),
+    /// This is the response to Rescan
+    RescanResult(RescanResult),
+    /// Ask an archive node for the balance of a public key just
+    /// before the block at the specified height
+    FetchBalanceAt(PublicKey, BlockHeight),
+    /// This is the response to FetchBalanceAt, in satoshis
+    BalanceAt(u64),
+    /// Ask an archive node for the undo data of the block at the
+    /// specified height
+    FetchUndo(BlockHeight),
+    /// This is the response to FetchUndo
+    Undo(BlockUndo),
+    /// Ask a node how the UTXO set changed over the blocks from the
+    /// first height up to the second, at most `MAX_UTXO_DELTA_BLOCKS`
+    FetchUtxoDelta(BlockHeight, BlockHeight),
+    /// This is the response to FetchUtxoDelta
+    UtxoDelta(UtxoDelta),
+    /// Say what kind of program the connection is from, asking for
+    /// an idle timeout in seconds (0 for the longest the node allows).
+    /// Sent at most once, before the requests it should apply to
+    Hello(PeerKind, u64),
+    /// This is the response to Hello
+    Welcome(Session),
+    /// Keep a connection open past its idle timeout. Answered with
+    /// Pong carrying the same number
+    Ping(u64),
+    /// This is the response to Ping
+    Pong(u64),
+    /// Present an auth token to gain its role for the rest
+    /// of the connection
+    Authenticate(String),
+    /// This is the response to Authenticate
+    Authenticated(Role),
+    /// Ask the node to shut down (admin only)
+    Shutdown,
+    /// Ask the node to refuse the block with this hash and every
+    /// chain through it, switching to another branch if the block
+    /// is in its chain (admin only). Answered with Tip
+    InvalidateBlock(Hash),
+    /// Ask the node to accept an invalidated block again (admin
+    /// only). Answered with Tip
+    ReconsiderBlock(Hash),
+    /// Send a transaction of the node operator's own wallet, which
+    /// skips the relay policy and goes in the mempool's priority lane
+    /// (admin only). Like SubmitTransaction, only answered by a Reject
+    SubmitPriorityTransaction(Transaction),
+    /// Ask a watchtower node to alert its webhooks when any of these
+    /// outputs is spent by two conflicting transactions. Answered with
+    /// Watching
+    WatchOutpoints(Vec<Hash>),
+    /// Ask a watchtower node to stop watching these outputs. Answered
+    /// with Watching
+    UnwatchOutpoints(Vec<Hash>),
+    /// This is the response to WatchOutpoints and UnwatchOutpoints: how
+    /// many outputs the node watches in all
+    Watching(usize),
+    /// Sent instead of the regular response when a node can't
+    /// serve a request, so the peer can tell a refusal apart
+    /// from a network failure
+    Reject {
+        request_kind: String,
+        code: RejectCode,
+        reason: String,
+    },
 }
 
 impl Message {
+    pub fn reject(request_kind: &str, code: RejectCode, reason: impl Into<String>) -> Self {
+        Message::Reject {
+            request_kind: request_kind.to_string(),
+            code,
+            reason: reason.into(),
+        }
+    }
+
+    /// Name of the message variant, used to tell a peer which request was rejected
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Message::FetchUTXOs(_) => "FetchUTXOs",
+            Message::UTXOs(_) => "UTXOs",
+            Message::SubmitTransaction(_) => "SubmitTransaction",
+            Message::BuildTransaction(..) => "BuildTransaction",
+            Message::UnsignedTransaction(_) => "UnsignedTransaction",
+            Message::SubmitPriorityTransaction(_) => "SubmitPriorityTransaction",
+            Message::NewTransaction(_) => "NewTransaction",
+            Message::FetchTemplate(..) => "FetchTemplate",
+            Message::Template(_) => "Template",
+            Message::ValidateTemplate(_) => "ValidateTemplate",
+            Message::TemplateValidity(_) => "TemplateValidity",
+            Message::SubmitTemplate(_) => "SubmitTemplate",
+            Message::CosignBlock(_) => "CosignBlock",
+            Message::Cosigned(_) => "Cosigned",
+            Message::DiscoverNodes => "DiscoverNodes",
+            Message::NodeList(_) => "NodeList",
+            Message::Addr(_) => "Addr",
+            Message::AskDifference(_) => "AskDifference",
+            Message::Difference(_) => "Difference",
+            Message::FetchBlock(_) => "FetchBlock",
+            Message::FetchBlockByHash(_) => "FetchBlockByHash",
+            Message::FetchHeader(_) => "FetchHeader",
+            Message::Header(_) => "Header",
+            Message::NewBlock(_) => "NewBlock",
+            Message::SubscribeTips => "SubscribeTips",
+            Message::TipChanged(_) => "TipChanged",
+            Message::FetchTip => "FetchTip",
+            Message::Tip(_) => "Tip",
+            Message::FetchMempool => "FetchMempool",
+            Message::Mempool(_) => "Mempool",
+            Message::FetchTransactions(_) => "FetchTransactions",
+            Message::Transactions(_) => "Transactions",
+            Message::FetchMempoolEntries => "FetchMempoolEntries",
+            Message::MempoolEntries(_) => "MempoolEntries",
+            Message::FetchFeeHistogram => "FetchFeeHistogram",
+            Message::FeeHistogram(_) => "FeeHistogram",
+            Message::FetchBlockTimings(_) => "FetchBlockTimings",
+            Message::BlockTimings(_) => "BlockTimings",
+            Message::FetchZeroConfRisk(_) => "FetchZeroConfRisk",
+            Message::ZeroConfRisk(_) => "ZeroConfRisk",
+            Message::FetchUtxoAccumulator => "FetchUtxoAccumulator",
+            Message::UtxoAccumulator(_) => "UtxoAccumulator",
+            Message::FetchUtxoProof(_) => "FetchUtxoProof",
+            Message::UtxoProof(_) => "UtxoProof",
+            Message::FetchStats(_) => "FetchStats",
+            Message::Stats(_) => "Stats",
+            Message::FetchAnalytics(_) => "FetchAnalytics",
+            Message::Analytics(_) => "Analytics",
+            Message::FetchDiskUsage => "FetchDiskUsage",
+            Message::DiskUsage(_) => "DiskUsage",
+            Message::FetchMemoryInfo => "FetchMemoryInfo",
+            Message::MemoryInfo(_) => "MemoryInfo",
+            Message::FetchHealth => "FetchHealth",
+            Message::Health(_) => "Health",
+            Message::FetchSyncProgress => "FetchSyncProgress",
+            Message::SyncProgress(_) => "SyncProgress",
+            Message::FetchAssetBalances(_) => "FetchAssetBalances",
+            Message::AssetBalances(_) => "AssetBalances",
+            Message::FetchPolicy => "FetchPolicy",
+            Message::Policy(_) => "Policy",
+            Message::FetchNetworkInfo => "FetchNetworkInfo",
+            Message::NetworkInfo(_) => "NetworkInfo",
+            Message::Rescan(..) => "Rescan",
+            Message::RescanResult(_) => "RescanResult",
+            Message::FetchBalanceAt(..) => "FetchBalanceAt",
+            Message::BalanceAt(_) => "BalanceAt",
+            Message::FetchUndo(_) => "FetchUndo",
+            Message::Undo(_) => "Undo",
+            Message::FetchUtxoDelta(..) => "FetchUtxoDelta",
+            Message::UtxoDelta(_) => "UtxoDelta",
+            Message::Hello(..) => "Hello",
+            Message::Welcome(_) => "Welcome",
+            Message::Ping(_) => "Ping",
+            Message::Pong(_) => "Pong",
+            Message::Authenticate(_) => "Authenticate",
+            Message::Authenticated(_) => "Authenticated",
+            Message::Shutdown => "Shutdown",
+            Message::InvalidateBlock(_) => "InvalidateBlock",
+            Message::ReconsiderBlock(_) => "ReconsiderBlock",
+            Message::WatchOutpoints(_) => "WatchOutpoints",
+            Message::UnwatchOutpoints(_) => "UnwatchOutpoints",
+            Message::Watching(_) => "Watching",
+            Message::Reject { .. } => "Reject",
+        }
+    }
+
+    /// The role a connection needs before the node serves this request.
+    pub fn required_role(&self) -> Option<Role> {
+        match self {
+            Message::FetchStats(_)
+            | Message::FetchAnalytics(_)
+            | Message::FetchDiskUsage
+            | Message::FetchMemoryInfo
+            | Message::Rescan(..)
+            | Message::WatchOutpoints(_)
+            | Message::UnwatchOutpoints(_) => Some(Role::ReadOnly),
+            Message::Shutdown
+            | Message::InvalidateBlock(_)
+            | Message::ReconsiderBlock(_)
+            | Message::SubmitPriorityTransaction(_) => Some(Role::Admin),
+            _ => None,
+        }
+    }
+
     pub fn encode(&self) -> Result<Vec<u8>, ciborium::ser::Error<IoError>> {
         let mut bytes = Vec::new();
         ciborium::into_writer(self, &mut bytes)?;
@@ -66,15 +378,13 @@ impl Message {
         Ok(())
     }
 
-    const MAX_MESSAGE_SIZE: usize = 10 * 1024 * 1024; // 10 MB
-
     pub fn receive(stream: &mut impl Read) -> Result<Self, ciborium::de::Error<IoError>> {
         let mut len_bytes = [0u8; 8];
         stream.read_exact(&mut len_bytes)?;
         let len = u64::from_be_bytes(len_bytes) as usize;
-        if len > Self::MAX_MESSAGE_SIZE {
+        if len > DEFAULT_MAX_MESSAGE_SIZE {
             return Err(ciborium::de::Error::Io(IoError::new(
-                std::io::ErrorKind::InvalidData,
+                ErrorKind::InvalidData,
                 "Message size exceeds maximum allowed",
             )));
         }
@@ -96,18 +406,146 @@ impl Message {
 
     pub async fn receive_async(
         stream: &mut (impl AsyncRead + Unpin),
+    ) -> Result<Self, ciborium::de::Error<IoError>> {
+        Self::receive_async_limited(stream, DEFAULT_MAX_MESSAGE_SIZE, None).await
+    }
+
+    /// Like `receive_async`, but refuses messages larger than `max_size`
+    /// bytes and, given a `body_timeout`, fails if a message doesn't
+    /// fully arrive within it once its length prefix did. Waiting for the
+    /// next message to start is never timed out.
+    pub async fn receive_async_limited(
+        stream: &mut (impl AsyncRead + Unpin),
+        max_size: usize,
+        body_timeout: Option<Duration>,
     ) -> Result<Self, ciborium::de::Error<IoError>> {
         let mut len_bytes = [0u8; 8];
         stream.read_exact(&mut len_bytes).await?;
         let len = u64::from_be_bytes(len_bytes) as usize;
-        if len > Self::MAX_MESSAGE_SIZE {
+        if len > max_size {
             return Err(ciborium::de::Error::Io(IoError::new(
-                std::io::ErrorKind::InvalidData,
+                ErrorKind::InvalidData,
                 "Message size exceeds maximum allowed",
             )));
         }
         let mut buffer = vec![0u8; len];
-        stream.read_exact(&mut buffer).await?;
+        match body_timeout {
+            Some(body_timeout) => {
+                tokio::time::timeout(body_timeout, stream.read_exact(&mut buffer))
+                    .await
+                    .map_err(|_| {
+                        IoError::new(ErrorKind::TimedOut, "Message took too long to arrive")
+                    })??
+            }
+            None => stream.read_exact(&mut buffer).await?,
+        };
         Self::decode(&buffer)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reject_roundtrip() {
+        let message = Message::reject("FetchBlock", RejectCode::NotFound, "no block at height 7");
+
+        let decoded = Message::decode(&message.encode().unwrap()).unwrap();
+
+        match decoded {
+            Message::Reject {
+                request_kind,
+                code,
+                reason,
+            } => {
+                assert_eq!(request_kind, "FetchBlock");
+                assert_eq!(code, RejectCode::NotFound);
+                assert_eq!(reason, "no block at height 7");
+            }
+            other => panic!("unexpected message: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_required_role() {
+        assert_eq!(
+            Message::FetchBlock(BlockHeight::GENESIS).required_role(),
+            None
+        );
+        assert_eq!(
+            Message::FetchStats(10).required_role(),
+            Some(Role::ReadOnly)
+        );
+        assert_eq!(
+            Message::FetchAnalytics(10).required_role(),
+            Some(Role::ReadOnly)
+        );
+        assert_eq!(
+            Message::FetchMemoryInfo.required_role(),
+            Some(Role::ReadOnly)
+        );
+        assert_eq!(
+            Message::Rescan(vec![], BlockHeight::GENESIS).required_role(),
+            Some(Role::ReadOnly)
+        );
+        assert_eq!(
+            Message::WatchOutpoints(vec![]).required_role(),
+            Some(Role::ReadOnly)
+        );
+        assert_eq!(Message::Shutdown.required_role(), Some(Role::Admin));
+        assert_eq!(
+            Message::InvalidateBlock(Hash::zero()).required_role(),
+            Some(Role::Admin)
+        );
+        assert!(Role::ReadOnly < Role::Admin);
+    }
+
+    #[test]
+    fn test_message_kind() {
+        assert_eq!(
+            Message::FetchBlock(BlockHeight::GENESIS).kind(),
+            "FetchBlock"
+        );
+        assert_eq!(Message::DiscoverNodes.kind(), "DiscoverNodes");
+    }
+
+    #[tokio::test]
+    async fn test_receive_limited_refuses_large_messages() {
+        let mut bytes = vec![];
+        Message::NodeList(vec!["x".repeat(100)])
+            .send_async(&mut bytes)
+            .await
+            .unwrap();
+
+        assert!(
+            Message::receive_async_limited(&mut bytes.as_slice(), 64, None)
+                .await
+                .is_err()
+        );
+        assert!(matches!(
+            Message::receive_async_limited(&mut bytes.as_slice(), 1024, None).await,
+            Ok(Message::NodeList(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_receive_limited_times_out_stalled_messages() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let bytes = Message::DiscoverNodes.encode().unwrap();
+        // the length prefix arrives, the body never does
+        client
+            .write_all(&(bytes.len() as u64).to_be_bytes())
+            .await
+            .unwrap();
+
+        let error =
+            Message::receive_async_limited(&mut server, 1024, Some(Duration::from_millis(10)))
+                .await
+                .unwrap_err();
+        assert!(matches!(
+            error,
+            ciborium::de::Error::Io(e) if e.kind() == ErrorKind::TimedOut
+        ));
+    }
+}
@@ -1,3 +1,35 @@
+mod analytics;
+mod auth;
+mod disk;
+mod health;
+mod limits;
+mod memory;
+mod mempool;
 mod message;
+mod policy;
+mod reject;
+mod scan;
+mod session;
+mod stats;
+mod sync;
+mod timing;
+mod tip;
+mod zeroconf;
 
+pub use analytics::*;
+pub use auth::*;
+pub use disk::*;
+pub use health::*;
+pub use limits::*;
+pub use memory::*;
+pub use mempool::*;
 pub use message::*;
+pub use policy::*;
+pub use reject::*;
+pub use scan::*;
+pub use session::*;
+pub use stats::*;
+pub use sync::*;
+pub use timing::*;
+pub use tip::*;
+pub use zeroconf::*;
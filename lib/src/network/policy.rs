@@ -0,0 +1,230 @@
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::types::Transaction;
+
+/// A node's relay rules, served in response to `FetchPolicy` so wallets
+/// can check a transaction before submitting it. Unlike consensus rules
+/// these differ between nodes: a transaction that breaks them is still
+/// valid in a block, it just won't be relayed by this node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RelayPolicy {
+    /// Minimum fee in satoshis per byte of the encoded transaction
+    pub min_fee_rate: u64,
+    /// Largest encoded transaction accepted, in bytes
+    pub max_tx_size: usize,
+    /// Most inputs of a transaction accepted; each is looked up and has
+    /// its signature checked, so they bound the work one transaction costs
+    pub max_tx_inputs: usize,
+    /// Most outputs of a transaction accepted
+    pub max_tx_outputs: usize,
+    /// Most data outputs of a transaction accepted, at most the
+    /// `MAX_DATA_OUTPUTS_PER_TX` blocks allow
+    pub max_data_outputs: usize,
+    /// Outputs below this value in satoshis are refused
+    pub dust_threshold: u64,
+    /// Transactions below the minimum fee rate accepted from one
+    /// peer per hour
+    pub free_tx_per_hour: u32,
+    /// Seconds a transaction without an expiry height stays in the mempool
+    pub mempool_expiry_secs: u64,
+    /// Mempool transactions spending outputs of one public key at a time
+    pub max_unconfirmed_per_key: usize,
+    /// Mempool transactions relayed or submitted by one peer at a time
+    pub max_unconfirmed_per_peer: usize,
+}
+
+impl Default for RelayPolicy {
+    fn default() -> Self {
+        RelayPolicy {
+            min_fee_rate: 1,
+            max_tx_size: 100_000,
+            max_tx_inputs: 500,
+            max_tx_outputs: 500,
+            max_data_outputs: 1,
+            dust_threshold: 546,
+            free_tx_per_hour: 10,
+            mempool_expiry_secs: crate::MAX_MEMPOOL_TX_AGE,
+            max_unconfirmed_per_key: 25,
+            max_unconfirmed_per_peer: 100,
+        }
+    }
+}
+
+/// Why a transaction goes against a `RelayPolicy`.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum PolicyViolation {
+    #[error("transaction is {size} bytes, the limit is {max}")]
+    TooLarge { size: usize, max: usize },
+    #[error("transaction has {count} inputs, the limit is {max}")]
+    TooManyInputs { count: usize, max: usize },
+    #[error("transaction has {count} outputs, the limit is {max}")]
+    TooManyOutputs { count: usize, max: usize },
+    #[error("transaction has {count} data outputs, the limit is {max}")]
+    TooManyDataOutputs { count: usize, max: usize },
+    #[error("output of {value} is below the dust threshold of {threshold}")]
+    Dust { value: u64, threshold: u64 },
+    #[error("fee of {fee} is below the required {required}")]
+    FeeTooLow { fee: u64, required: u64 },
+    #[error("sender already has {max} unconfirmed transactions")]
+    TooManyFromKey { max: usize },
+    #[error("peer already sent {max} unconfirmed transactions")]
+    TooManyFromPeer { max: usize },
+}
+
+impl RelayPolicy {
+    /// The fee a transaction of `size` bytes has to pay.
+    pub fn min_fee(&self, size: usize) -> u64 {
+        self.min_fee_rate.saturating_mul(size as u64)
+    }
+
+    /// Checks that `transaction` has a standard shape: no more inputs,
+    /// outputs and data outputs than the policy allows. Cheap, so nodes
+    /// run it before looking up the inputs or checking signatures.
+    pub fn check_standard(&self, transaction: &Transaction) -> Result<(), PolicyViolation> {
+        let count = transaction.inputs().len();
+        if count > self.max_tx_inputs {
+            return Err(PolicyViolation::TooManyInputs {
+                count,
+                max: self.max_tx_inputs,
+            });
+        }
+        let count = transaction.outputs().len();
+        if count > self.max_tx_outputs {
+            return Err(PolicyViolation::TooManyOutputs {
+                count,
+                max: self.max_tx_outputs,
+            });
+        }
+        let count = transaction.data_outputs().len();
+        if count > self.max_data_outputs {
+            return Err(PolicyViolation::TooManyDataOutputs {
+                count,
+                max: self.max_data_outputs,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks `transaction`, which pays `fee`, against every rule except
+    /// the free transaction quota and the unconfirmed transaction limits,
+    /// which only the node can track.
+    pub fn check(&self, transaction: &Transaction, fee: u64) -> Result<(), PolicyViolation> {
+        self.check_standard(transaction)?;
+        let size = transaction.size();
+        if size > self.max_tx_size {
+            return Err(PolicyViolation::TooLarge {
+                size,
+                max: self.max_tx_size,
+            });
+        }
+        if let Some(output) = transaction
+            .outputs()
+            .iter()
+            .find(|output| output.value() < self.dust_threshold)
+        {
+            return Err(PolicyViolation::Dust {
+                value: output.value(),
+                threshold: self.dust_threshold,
+            });
+        }
+        let required = self.min_fee(size);
+        if fee < required {
+            return Err(PolicyViolation::FeeTooLow { fee, required });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        crypto::{PrivateKey, Signature},
+        custom_sha_types::Hash,
+        types::{DataOutput, TransactionInput, TransactionOutput},
+    };
+    use uuid::Uuid;
+
+    fn transaction_paying(value: u64) -> Transaction {
+        let output =
+            TransactionOutput::new(value, Uuid::new_v4(), PrivateKey::default().public_key());
+        Transaction::new(vec![], vec![output])
+    }
+
+    #[test]
+    fn test_policy_accepts_standard_transaction() {
+        let policy = RelayPolicy::default();
+        let transaction = transaction_paying(10_000);
+        let fee = policy.min_fee(transaction.size());
+
+        assert_eq!(policy.check(&transaction, fee), Ok(()));
+    }
+
+    #[test]
+    fn test_policy_violations() {
+        let policy = RelayPolicy::default();
+
+        let dust = transaction_paying(policy.dust_threshold - 1);
+        assert!(matches!(
+            policy.check(&dust, u64::MAX),
+            Err(PolicyViolation::Dust { .. })
+        ));
+
+        let transaction = transaction_paying(10_000);
+        assert!(matches!(
+            policy.check(&transaction, 0),
+            Err(PolicyViolation::FeeTooLow { fee: 0, .. })
+        ));
+
+        let strict = RelayPolicy {
+            max_tx_size: 10,
+            ..policy
+        };
+        assert!(matches!(
+            strict.check(&transaction, u64::MAX),
+            Err(PolicyViolation::TooLarge { max: 10, .. })
+        ));
+    }
+
+    #[test]
+    fn test_policy_standardness() {
+        let policy = RelayPolicy::default();
+        let key = PrivateKey::default().public_key();
+        let outputs = (0..=policy.max_tx_outputs)
+            .map(|_| TransactionOutput::new(10_000, Uuid::new_v4(), key.clone()))
+            .collect();
+        let transaction = Transaction::new(vec![], outputs);
+        assert_eq!(
+            policy.check_standard(&transaction),
+            Err(PolicyViolation::TooManyOutputs {
+                count: 501,
+                max: 500
+            })
+        );
+
+        let inputs = (0..=policy.max_tx_inputs)
+            .map(|_| TransactionInput::new(Hash::zero(), Signature::largest()))
+            .collect();
+        let transaction = Transaction::new(inputs, vec![]);
+        assert!(matches!(
+            policy.check(&transaction, u64::MAX),
+            Err(PolicyViolation::TooManyInputs { count: 501, .. })
+        ));
+
+        // one data output is standard, blocks take up to four
+        let transaction = transaction_paying(10_000)
+            .with_data_output(DataOutput::new(b"a".to_vec()))
+            .with_data_output(DataOutput::new(b"b".to_vec()));
+        assert!(transaction.data_outputs_within_limits());
+        assert_eq!(
+            policy.check_standard(&transaction),
+            Err(PolicyViolation::TooManyDataOutputs { count: 2, max: 1 })
+        );
+        let lenient = RelayPolicy {
+            max_data_outputs: 2,
+            ..policy
+        };
+        assert_eq!(lenient.check_standard(&transaction), Ok(()));
+    }
+}
@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+
+/// Why a node refused to serve a request, carried by `Message::Reject`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum RejectCode {
+    /// The request (or the transaction/block it carried) failed validation
+    Invalid,
+    /// The requested data does not exist on this node
+    NotFound,
+    /// The node does not serve this kind of message
+    Unsupported,
+    /// The node failed to process an otherwise valid request
+    Internal,
+    /// The connection lacks the role the request requires
+    Unauthorized,
+    /// The transaction is valid but goes against the node's relay policy
+    Policy,
+    /// The node already serves as many connections of this kind as it allows
+    Busy,
+}
@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    custom_sha_types::Hash,
+    types::{BlockHeight, Confirmations, TransactionOutput},
+};
+
+/// An output paying one of the rescanned keys.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ScannedOutput {
+    /// Height of the block that created the output
+    pub height: BlockHeight,
+    /// Hash of the transaction that created the output
+    pub transaction: Hash,
+    pub output: TransactionOutput,
+    /// Height of the block that spent the output, if any
+    pub spent_at: Option<BlockHeight>,
+}
+
+/// Everything a wallet needs to rebuild its history for a set of keys,
+/// served in response to `Rescan`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RescanResult {
+    /// First block that was scanned
+    pub from_height: BlockHeight,
+    /// Height of the next block when the scan finished
+    pub to_height: BlockHeight,
+    /// Outputs paying the keys, in chain order
+    pub outputs: Vec<ScannedOutput>,
+}
+
+impl RescanResult {
+    /// Outputs that haven't been spent as of `to_height`.
+    pub fn unspent(&self) -> impl Iterator<Item = &ScannedOutput> {
+        self.outputs
+            .iter()
+            .filter(|output| output.spent_at.is_none())
+    }
+
+    /// Confirmations of the block that created `output`, as of `to_height`.
+    pub fn confirmations(&self, output: &ScannedOutput) -> Confirmations {
+        output.height.confirmations(self.to_height)
+    }
+
+    /// Sum of the unspent outputs, in satoshis.
+    pub fn balance(&self) -> u64 {
+        self.unspent().map(|output| output.output.value()).sum()
+    }
+}
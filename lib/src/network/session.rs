@@ -0,0 +1,105 @@
+use serde::{Deserialize, Serialize};
+
+use crate::network::Message;
+
+/// What a connection says it is with `Hello`. The node then serves it
+/// only the requests its kind needs, closes it when it stays silent past
+/// its idle timeout and limits how many of its kind it serves at once.
+/// Connections that never say `Hello` are served as before.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum PeerKind {
+    /// Another node, syncing and relaying blocks, transactions and addresses
+    FullNode,
+    /// A wallet, looking up outputs and submitting transactions
+    Wallet,
+    /// A miner, fetching templates and submitting blocks
+    Miner,
+}
+
+impl PeerKind {
+    /// Whether a connection of this kind may send `message`. Only full
+    /// nodes relay blocks, transactions and addresses, wallets don't mine
+    /// and miners don't look up or spend outputs. Which role a request
+    /// requires is checked separately.
+    pub fn allows(&self, message: &Message) -> bool {
+        let relay = matches!(
+            message,
+            Message::NewBlock(_) | Message::NewTransaction(_) | Message::Addr(_)
+        );
+        match self {
+            PeerKind::FullNode => true,
+            PeerKind::Wallet => {
+                !relay
+                    && !matches!(
+                        message,
+                        Message::FetchTemplate(..)
+                            | Message::ValidateTemplate(_)
+                            | Message::SubmitTemplate(_)
+                            | Message::CosignBlock(_)
+                    )
+            }
+            PeerKind::Miner => {
+                !relay
+                    && !matches!(
+                        message,
+                        Message::FetchUTXOs(_)
+                            | Message::SubmitTransaction(_)
+                            | Message::SubmitPriorityTransaction(_)
+                            | Message::BuildTransaction(..)
+                            | Message::Rescan(..)
+                            | Message::FetchBalanceAt(..)
+                            | Message::FetchUndo(_)
+                            | Message::FetchUtxoDelta(..)
+                            | Message::WatchOutpoints(_)
+                            | Message::UnwatchOutpoints(_)
+                            | Message::FetchAssetBalances(_)
+                    )
+            }
+        }
+    }
+}
+
+/// The session a node opened for a connection's `Hello`, the response
+/// to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Session {
+    pub kind: PeerKind,
+    /// Seconds the connection may go without sending anything before
+    /// the node closes it, `None` if it may stay open however long.
+    /// `Ping` keeps it open
+    pub idle_timeout_secs: Option<u64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{crypto::PrivateKey, types::BlockHeight};
+
+    #[test]
+    fn test_peer_kind_allows() {
+        let key = PrivateKey::default().public_key();
+        let relay = Message::Addr(vec![]);
+        let utxos = Message::FetchUTXOs(key.clone());
+        let template = Message::FetchTemplate(key, None);
+        let block = Message::FetchBlock(BlockHeight::from_index(0));
+
+        assert!(PeerKind::FullNode.allows(&relay));
+        assert!(PeerKind::FullNode.allows(&template));
+        assert!(!PeerKind::Wallet.allows(&relay));
+        assert!(PeerKind::Wallet.allows(&utxos));
+        assert!(!PeerKind::Wallet.allows(&template));
+        assert!(!PeerKind::Miner.allows(&relay));
+        assert!(!PeerKind::Miner.allows(&utxos));
+        assert!(PeerKind::Miner.allows(&template));
+        let watch = Message::WatchOutpoints(vec![]);
+        assert!(PeerKind::Wallet.allows(&watch));
+        assert!(!PeerKind::Miner.allows(&watch));
+        let build = Message::BuildTransaction(PrivateKey::default().public_key(), vec![], None);
+        assert!(PeerKind::Wallet.allows(&build));
+        assert!(!PeerKind::Miner.allows(&build));
+        for kind in [PeerKind::FullNode, PeerKind::Wallet, PeerKind::Miner] {
+            assert!(kind.allows(&block));
+            assert!(kind.allows(&Message::Ping(1)));
+        }
+    }
+}
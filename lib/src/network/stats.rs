@@ -0,0 +1,18 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{crypto::PublicKey, types::BlockHeight};
+
+/// Aggregate figures over a node's UTXO set, served in response to `FetchStats`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ChainStats {
+    /// Height of the next block when the figures were taken
+    pub height: BlockHeight,
+    /// Sum of all unspent output values, in satoshis
+    pub total_supply: u64,
+    /// Number of unspent outputs
+    pub utxo_count: u64,
+    /// Size of the UTXO set when serialized as CBOR, in bytes
+    pub utxo_set_bytes: u64,
+    /// Richest public keys and their balances, largest first
+    pub rich_list: Vec<(PublicKey, u64)>,
+}
@@ -0,0 +1,170 @@
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::types::BlockHeight;
+
+// characters of the bar `SyncProgress` displays
+const BAR_WIDTH: usize = 20;
+
+/// How far a node got downloading the blockchain from a peer, served in
+/// response to `FetchSyncProgress` and logged as the download goes.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SyncProgress {
+    /// The peer the blocks come from
+    pub peer: String,
+    /// Height of the first block downloaded, ours when the download started
+    pub start: BlockHeight,
+    /// Height the peer reported, the download stops below it
+    pub target: BlockHeight,
+    /// Blocks received so far, some of them maybe not connected yet
+    pub downloaded: u64,
+    /// Blocks connected so far
+    pub connected: u64,
+    /// Encoded bytes of the transactions of the blocks received so far
+    pub bytes: u64,
+    pub started_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// Whether the download is over, finished or failed
+    pub done: bool,
+}
+
+impl SyncProgress {
+    pub fn new(peer: &str, start: BlockHeight, target: BlockHeight, now: DateTime<Utc>) -> Self {
+        SyncProgress {
+            peer: peer.to_string(),
+            start,
+            target,
+            downloaded: 0,
+            connected: 0,
+            bytes: 0,
+            started_at: now,
+            updated_at: now,
+            done: false,
+        }
+    }
+
+    /// Counts a block of `bytes` bytes of transactions received.
+    pub fn record_downloaded(&mut self, bytes: usize, now: DateTime<Utc>) {
+        self.downloaded += 1;
+        self.bytes = self.bytes.saturating_add(bytes as u64);
+        self.updated_at = now;
+    }
+
+    /// Counts a block connected.
+    pub fn record_connected(&mut self, now: DateTime<Utc>) {
+        self.connected += 1;
+        self.updated_at = now;
+    }
+
+    /// Marks the download over, whether all blocks came or not.
+    pub fn finish(&mut self, now: DateTime<Utc>) {
+        self.done = true;
+        self.updated_at = now;
+    }
+
+    /// Blocks to download in all.
+    pub fn total(&self) -> u64 {
+        self.target.value().saturating_sub(self.start.value())
+    }
+
+    /// Share of the blocks connected, from 0 to 1.
+    pub fn fraction(&self) -> f64 {
+        match self.total() {
+            0 => 1.0,
+            total => self.connected.min(total) as f64 / total as f64,
+        }
+    }
+
+    /// Blocks connected per second since the download started.
+    pub fn blocks_per_sec(&self) -> f64 {
+        self.connected as f64 / self.elapsed_secs()
+    }
+
+    /// Bytes received per second since the download started.
+    pub fn bytes_per_sec(&self) -> f64 {
+        self.bytes as f64 / self.elapsed_secs()
+    }
+
+    /// Time left at the rate so far, `None` once the download is over or
+    /// before a block is connected.
+    pub fn eta(&self) -> Option<chrono::Duration> {
+        let rate = self.blocks_per_sec();
+        if self.done || rate <= 0.0 {
+            return None;
+        }
+        let remaining = self.total().saturating_sub(self.connected);
+        chrono::Duration::try_milliseconds((remaining as f64 / rate * 1000.0) as i64)
+    }
+
+    // at least a millisecond, so rates stay finite
+    fn elapsed_secs(&self) -> f64 {
+        let elapsed = self.updated_at - self.started_at;
+        (elapsed.num_milliseconds().max(1)) as f64 / 1000.0
+    }
+}
+
+impl fmt::Display for SyncProgress {
+    /// One log line, e.g. `[#####---------------] 1250/5000 blocks
+    /// (25.0%) from 10.0.0.1:9000, 3.2 MB at 410.5 blocks/s, ETA 9s`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let filled = (self.fraction() * BAR_WIDTH as f64) as usize;
+        write!(
+            f,
+            "[{}{}] {}/{} blocks ({:.1}%) from {}, {:.1} MB at {:.1} blocks/s",
+            "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled),
+            self.connected,
+            self.total(),
+            self.fraction() * 100.0,
+            self.peer,
+            self.bytes as f64 / 1_000_000.0,
+            self.blocks_per_sec()
+        )?;
+        match self.eta() {
+            Some(eta) => write!(f, ", ETA {}s", eta.num_seconds()),
+            None if self.done && self.connected < self.total() => write!(f, ", stopped"),
+            None if self.done => write!(f, ", done"),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sync_progress() {
+        let start = Utc::now();
+        let mut progress = SyncProgress::new(
+            "peer:9000",
+            BlockHeight::new(100),
+            BlockHeight::new(500),
+            start,
+        );
+        assert_eq!(progress.total(), 400);
+        assert_eq!(progress.fraction(), 0.0);
+        assert_eq!(progress.eta(), None);
+
+        // 100 blocks in 10 seconds leave 30 seconds for the other 300
+        for _ in 0..100 {
+            progress.record_downloaded(10_000, start);
+            progress.record_connected(start);
+        }
+        progress.updated_at = start + chrono::Duration::seconds(10);
+        assert_eq!(progress.fraction(), 0.25);
+        assert_eq!(progress.blocks_per_sec(), 10.0);
+        assert_eq!(progress.bytes_per_sec(), 100_000.0);
+        assert_eq!(progress.eta(), Some(chrono::Duration::seconds(30)));
+        assert_eq!(
+            progress.to_string(),
+            "[#####---------------] 100/400 blocks (25.0%) from peer:9000, 1.0 MB at 10.0 blocks/s, ETA 30s"
+        );
+
+        progress.finish(start + chrono::Duration::seconds(10));
+        assert_eq!(progress.eta(), None);
+        assert!(progress.to_string().ends_with(", stopped"));
+    }
+}
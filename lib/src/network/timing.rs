@@ -0,0 +1,80 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{custom_sha_types::Hash, types::BlockHeight};
+
+/// When a node first saw a block, served in response to `FetchBlockTimings`.
+/// Comparing `received_at` across nodes shows how long the block took to
+/// reach each of them.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BlockTimings {
+    pub hash: Hash,
+    pub height: BlockHeight,
+    /// Timestamp in the block header, set by the miner's clock
+    pub mined_at: DateTime<Utc>,
+    /// When the block arrived, before it was validated
+    pub received_at: DateTime<Utc>,
+    /// When the block was validated and added to the chain
+    pub connected_at: DateTime<Utc>,
+    /// Peer that relayed the block, `None` if a miner submitted it here
+    pub relayed_by: Option<String>,
+}
+
+impl BlockTimings {
+    /// Seconds from the header timestamp until the block arrived. Only
+    /// as accurate as the miner's clock, and negative if it runs ahead.
+    pub fn propagation_delay(&self) -> f64 {
+        seconds_between(self.mined_at, self.received_at)
+    }
+
+    /// Seconds the node took to validate and add the block once it arrived.
+    pub fn validation_time(&self) -> f64 {
+        seconds_between(self.received_at, self.connected_at)
+    }
+}
+
+/// Seconds between the first and the last node to receive a block, from
+/// the timings each node reported for it. `None` for fewer than two.
+pub fn propagation_spread(timings: &[BlockTimings]) -> Option<f64> {
+    if timings.len() < 2 {
+        return None;
+    }
+    let first = timings.iter().map(|timing| timing.received_at).min()?;
+    let last = timings.iter().map(|timing| timing.received_at).max()?;
+    Some(seconds_between(first, last))
+}
+
+fn seconds_between(from: DateTime<Utc>, to: DateTime<Utc>) -> f64 {
+    (to - from).num_milliseconds() as f64 / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn timings(received_after_ms: i64) -> BlockTimings {
+        let mined_at = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let received_at = mined_at + Duration::milliseconds(received_after_ms);
+        BlockTimings {
+            hash: Hash::zero(),
+            height: BlockHeight::GENESIS,
+            mined_at,
+            received_at,
+            connected_at: received_at + Duration::milliseconds(20),
+            relayed_by: None,
+        }
+    }
+
+    #[test]
+    fn test_block_timings() {
+        let timing = timings(1_500);
+        assert_eq!(timing.propagation_delay(), 1.5);
+        assert_eq!(timing.validation_time(), 0.02);
+        assert_eq!(propagation_spread(std::slice::from_ref(&timing)), None);
+        assert_eq!(
+            propagation_spread(&[timings(400), timing, timings(250)]),
+            Some(1.25)
+        );
+    }
+}
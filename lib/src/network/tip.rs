@@ -0,0 +1,14 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{custom_sha_types::Hash, types::BlockHeight};
+
+/// Compact description of a node's best block, pushed to tip subscribers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ChainTip {
+    /// Hash of the new best block
+    pub hash: Hash,
+    /// Height of the new best block (the index `FetchBlock` serves it under)
+    pub height: BlockHeight,
+    /// Hash of the block it builds on
+    pub prev: Hash,
+}
@@ -0,0 +1,130 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{custom_sha_types::Hash, types::Confirmations};
+
+// share of the score each factor makes up
+const FEE_WEIGHT: f64 = 0.3;
+const DEPTH_WEIGHT: f64 = 0.3;
+const PROPAGATION_WEIGHT: f64 = 0.4;
+// blocks of better paying transactions from which the fee risk is full
+const FEE_RISK_BLOCKS: f64 = 6.0;
+// scores from which a payment is rated `Medium` and `High` risk
+const MEDIUM_RISK: f64 = 0.3;
+const HIGH_RISK: f64 = 0.6;
+
+/// Lowest score of any unconfirmed transaction. The mempool replaces a
+/// transaction with any other spending the same outputs, there is no
+/// replace-by-fee signal to opt out with, so none is free of risk.
+pub const MIN_ZEROCONF_RISK: f64 = 0.05;
+
+/// How a merchant should treat a payment scored by `ZeroConfRisk::score`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum RiskLevel {
+    /// Fine to accept unconfirmed for small amounts
+    Low,
+    /// Worth waiting a little, or a confirmation for larger amounts
+    Medium,
+    /// Wait for a confirmation
+    High,
+}
+
+/// How likely an unconfirmed transaction is to be double spent before
+/// it is mined, for merchants deciding whether to accept a payment with
+/// zero confirmations. Served in response to `FetchZeroConfRisk`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ZeroConfRisk {
+    pub hash: Hash,
+    /// Satoshis per byte of the transaction and its unconfirmed
+    /// ancestors, which it is mined by
+    pub fee_rate: f64,
+    /// Bytes of mempool transactions paying a better rate, mined first
+    pub bytes_ahead: usize,
+    /// Full blocks those make, i.e. about how many blocks it waits
+    pub blocks_ahead: f64,
+    /// Inputs spending outputs of other unconfirmed transactions
+    pub unconfirmed_inputs: usize,
+    /// Fewest confirmations of the outputs it spends, `NONE` if one is
+    /// unconfirmed
+    pub min_input_confirmations: Confirmations,
+    /// Peers that answered whether they have the transaction, and
+    /// those that do
+    pub peers_asked: usize,
+    pub peers_with_transaction: usize,
+}
+
+impl ZeroConfRisk {
+    /// Grows with `blocks_ahead`, full from `FEE_RISK_BLOCKS` on.
+    pub fn fee_risk(&self) -> f64 {
+        (self.blocks_ahead / FEE_RISK_BLOCKS).min(1.0)
+    }
+
+    /// `1 / (1 + confirmations)` of the least confirmed input.
+    pub fn depth_risk(&self) -> f64 {
+        1.0 / (1.0 + self.min_input_confirmations.value() as f64)
+    }
+
+    /// Share of the peers asked that lack the transaction, full if none
+    /// answered.
+    pub fn propagation_risk(&self) -> f64 {
+        match self.peers_asked {
+            0 => 1.0,
+            asked => 1.0 - self.peers_with_transaction.min(asked) as f64 / asked as f64,
+        }
+    }
+
+    /// The weighted risks, from `MIN_ZEROCONF_RISK` to 1, higher the
+    /// riskier.
+    pub fn score(&self) -> f64 {
+        (FEE_WEIGHT * self.fee_risk()
+            + DEPTH_WEIGHT * self.depth_risk()
+            + PROPAGATION_WEIGHT * self.propagation_risk())
+        .max(MIN_ZEROCONF_RISK)
+    }
+
+    pub fn level(&self) -> RiskLevel {
+        let score = self.score();
+        if score >= HIGH_RISK {
+            RiskLevel::High
+        } else if score >= MEDIUM_RISK {
+            RiskLevel::Medium
+        } else {
+            RiskLevel::Low
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zero_conf_risk_levels() {
+        let risk =
+            |blocks_ahead, confirmations, peers_asked, peers_with_transaction| ZeroConfRisk {
+                hash: Hash::zero(),
+                fee_rate: 1.0,
+                bytes_ahead: 0,
+                blocks_ahead,
+                unconfirmed_inputs: 0,
+                min_input_confirmations: Confirmations::new(confirmations),
+                peers_asked,
+                peers_with_transaction,
+            };
+        // next in line, deeply confirmed inputs, every peer has it
+        let safe = risk(0.0, 99, 4, 4);
+        assert_eq!(safe.score(), MIN_ZEROCONF_RISK);
+        assert_eq!(safe.level(), RiskLevel::Low);
+        // one confirmation halves the depth risk
+        let shallow = risk(0.0, 1, 4, 4);
+        assert!((shallow.score() - 0.15).abs() < 1e-9);
+        assert_eq!(shallow.level(), RiskLevel::Low);
+        // half the peers lack it and it waits 3 blocks
+        let medium = risk(3.0, 1, 4, 2);
+        assert!((medium.score() - 0.5).abs() < 1e-9);
+        assert_eq!(medium.level(), RiskLevel::Medium);
+        // unconfirmed parent, nobody asked, far back in line
+        let high = risk(20.0, 0, 0, 0);
+        assert!((high.score() - 1.0).abs() < 1e-9);
+        assert_eq!(high.level(), RiskLevel::High);
+    }
+}
@@ -0,0 +1,178 @@
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+
+use crate::{
+    MIN_TARGET, U256,
+    crypto::{PrivateKey, PublicKey, Signature},
+    custom_sha_types::Hash,
+    types::{
+        Block, BlockBuilder, BlockHeader, BlockHeight, Blockchain, Transaction, TransactionInput,
+        TransactionOutput,
+    },
+    utils::MerkleRoot,
+};
+
+/// Seed of `fixture_key`.
+pub const FIXTURE_SEED: &str = "testutil";
+
+/// The key derived from `seed`, the same on every run, see
+/// `PrivateKey::from_seed`.
+pub fn test_key(seed: &str) -> PrivateKey {
+    PrivateKey::from_seed(seed.as_bytes())
+}
+
+/// The key fixtures pay unless told otherwise.
+pub fn fixture_key() -> PrivateKey {
+    test_key(FIXTURE_SEED)
+}
+
+/// A transaction without inputs paying `value` to `fixture_key`. Each
+/// one is different, as every output gets a new unique id.
+pub fn coinbase_transaction(value: u64) -> Transaction {
+    coinbase_transaction_to(value, &fixture_key().public_key())
+}
+
+/// A transaction without inputs paying `value` to `pubkey`.
+pub fn coinbase_transaction_to(value: u64, pubkey: &PublicKey) -> Transaction {
+    Transaction::new(
+        vec![],
+        vec![TransactionOutput::new(
+            value,
+            Uuid::new_v4(),
+            pubkey.clone(),
+        )],
+    )
+}
+
+/// Spends `output`, which pays `key`, back to `key`, leaving `fee`.
+pub fn spend_transaction(output: &TransactionOutput, key: &PrivateKey, fee: u64) -> Transaction {
+    let outpoint = output.hash();
+    Transaction::new(
+        vec![TransactionInput::new(
+            outpoint,
+            Signature::sign_output(&outpoint, key),
+        )],
+        vec![TransactionOutput::new(
+            output.value() - fee,
+            Uuid::new_v4(),
+            key.public_key(),
+        )],
+    )
+}
+
+/// A genesis block of `TestBlock::new()`: a coinbase of the first block
+/// reward to `fixture_key`, timestamped now, not mined.
+pub fn genesis_block() -> Block {
+    TestBlock::new().build()
+}
+
+/// Mines `blocks` blocks on top of `blockchain` with `BlockBuilder`, each
+/// paying a new random key, so chains extended separately never share a
+/// block.
+pub fn extend_chain(blockchain: &mut Blockchain, blocks: usize) {
+    for _ in 0..blocks {
+        let block = BlockBuilder::on(blockchain).build(PrivateKey::default().public_key());
+        blockchain
+            .add_block(block)
+            .expect("BUG: BlockBuilder blocks extend the chain");
+        blockchain.rebuild_utxos();
+    }
+}
+
+/// Builds blocks for tests field by field, valid or not. Unlike
+/// `BlockBuilder` it checks nothing and takes the coinbase as given.
+#[derive(Debug, Clone)]
+pub struct TestBlock {
+    prev_block_hash: Hash,
+    timestamp: DateTime<Utc>,
+    target: U256,
+    coinbase: Option<Transaction>,
+    transactions: Vec<Transaction>,
+    mined: bool,
+}
+
+impl Default for TestBlock {
+    fn default() -> Self {
+        TestBlock {
+            prev_block_hash: Hash::zero(),
+            timestamp: Utc::now(),
+            target: MIN_TARGET,
+            coinbase: Some(coinbase_transaction(BlockHeight::GENESIS.block_reward())),
+            transactions: vec![],
+            mined: false,
+        }
+    }
+}
+
+impl TestBlock {
+    /// A genesis block: no parent, timestamped now, at `MIN_TARGET`, with
+    /// a coinbase of the first block reward to `fixture_key`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A block of `transactions` alone, no coinbase added.
+    pub fn with_transactions(transactions: Vec<Transaction>) -> Self {
+        TestBlock {
+            coinbase: None,
+            transactions,
+            ..Self::default()
+        }
+    }
+
+    /// A block after `parent`, timestamped a second after it.
+    pub fn on(parent: &Block) -> Self {
+        TestBlock {
+            prev_block_hash: parent.hash(),
+            timestamp: parent.header().timestamp() + Duration::seconds(1),
+            ..Self::default()
+        }
+    }
+
+    pub fn prev_block_hash(mut self, prev_block_hash: Hash) -> Self {
+        self.prev_block_hash = prev_block_hash;
+        self
+    }
+
+    pub fn timestamp(mut self, timestamp: DateTime<Utc>) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    pub fn target(mut self, target: U256) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// Replaces the coinbase, `None` for a block without one.
+    pub fn coinbase(mut self, coinbase: Option<Transaction>) -> Self {
+        self.coinbase = coinbase;
+        self
+    }
+
+    /// Adds `transaction` after the coinbase and those added before.
+    pub fn transaction(mut self, transaction: Transaction) -> Self {
+        self.transactions.push(transaction);
+        self
+    }
+
+    /// Searches a nonce meeting the target when built.
+    pub fn mined(mut self) -> Self {
+        self.mined = true;
+        self
+    }
+
+    pub fn build(self) -> Block {
+        let transactions: Vec<_> = self.coinbase.into_iter().chain(self.transactions).collect();
+        let merkle_root = MerkleRoot::calculate(&transactions);
+        let mut header = BlockHeader::new(
+            self.timestamp,
+            0,
+            self.prev_block_hash,
+            merkle_root,
+            self.target,
+        );
+        while self.mined && !header.mine(1_000_000) {}
+        Block::new(header, transactions)
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    crypto::{PrivateKey, PublicKey, Signature},
+    custom_sha_types::Hash,
+    error::{BtcError, Result},
+    types::{Block, BlockHeight},
+};
+
+/// An authority key's signature over a block hash.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BlockSignature {
+    pub signer: PublicKey,
+    pub signature: Signature,
+}
+
+impl BlockSignature {
+    pub fn sign(block_hash: &Hash, private_key: &PrivateKey) -> Self {
+        BlockSignature {
+            signer: private_key.public_key(),
+            signature: Signature::sign_output(block_hash, private_key),
+        }
+    }
+
+    pub fn verify(&self, block_hash: &Hash) -> bool {
+        self.signature.verify(block_hash, &self.signer)
+    }
+}
+
+/// Keys of a permissioned deployment, at least `threshold` of which
+/// have to co-sign every block from `from_height` on. Proof of work
+/// alone then can't extend the chain, so blocks the authorities signed
+/// are final.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct AuthoritySet {
+    keys: BTreeSet<PublicKey>,
+    threshold: usize,
+    from_height: BlockHeight,
+}
+
+impl AuthoritySet {
+    /// Fails unless `threshold` is between one and the number of
+    /// distinct `keys`.
+    pub fn new(
+        keys: impl IntoIterator<Item = PublicKey>,
+        threshold: usize,
+        from_height: BlockHeight,
+    ) -> Result<Self> {
+        let keys: BTreeSet<PublicKey> = keys.into_iter().collect();
+        if threshold == 0 || threshold > keys.len() {
+            return Err(BtcError::InvalidAuthoritySet);
+        }
+        Ok(AuthoritySet {
+            keys,
+            threshold,
+            from_height,
+        })
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &PublicKey> {
+        self.keys.iter()
+    }
+
+    pub fn contains(&self, key: &PublicKey) -> bool {
+        self.keys.contains(key)
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    pub fn from_height(&self) -> BlockHeight {
+        self.from_height
+    }
+
+    /// Checks that `block`, to be connected at `height`, carries valid
+    /// signatures from at least `threshold` distinct authority keys.
+    /// Blocks below `from_height` need none.
+    pub fn verify(&self, block: &Block, height: BlockHeight) -> Result<()> {
+        if height < self.from_height {
+            return Ok(());
+        }
+        let hash = block.hash();
+        let signers: BTreeSet<&PublicKey> = block
+            .authority_signatures()
+            .iter()
+            .filter(|signature| self.keys.contains(&signature.signer) && signature.verify(&hash))
+            .map(|signature| &signature.signer)
+            .collect();
+        if signers.len() < self.threshold {
+            return Err(BtcError::MissingAuthoritySignatures {
+                signed: signers.len(),
+                required: self.threshold,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MIN_TARGET, types::BlockBuilder};
+
+    fn block() -> Block {
+        BlockBuilder::new(Hash::zero(), BlockHeight::new(5), MIN_TARGET)
+            .build(PrivateKey::default().public_key())
+    }
+
+    #[test]
+    fn test_authority_set_threshold() {
+        let key = PrivateKey::default().public_key();
+        assert!(AuthoritySet::new(vec![], 1, BlockHeight::GENESIS).is_err());
+        assert!(AuthoritySet::new(vec![key.clone()], 0, BlockHeight::GENESIS).is_err());
+        // the same key twice still counts once
+        assert!(AuthoritySet::new(vec![key.clone(), key], 2, BlockHeight::GENESIS).is_err());
+    }
+
+    #[test]
+    fn test_authority_set_verify() {
+        let alice = PrivateKey::default();
+        let bob = PrivateKey::default();
+        let mallory = PrivateKey::default();
+        let authority = AuthoritySet::new(
+            vec![alice.public_key(), bob.public_key()],
+            2,
+            BlockHeight::new(5),
+        )
+        .unwrap();
+
+        let mut block = block();
+        assert!(authority.verify(&block, BlockHeight::new(4)).is_ok());
+        assert!(matches!(
+            authority.verify(&block, BlockHeight::new(5)),
+            Err(BtcError::MissingAuthoritySignatures {
+                signed: 0,
+                required: 2
+            })
+        ));
+
+        block.cosign(&alice);
+        // signing twice, or with a key outside the set, doesn't help
+        block.cosign(&alice);
+        block.cosign(&mallory);
+        assert!(matches!(
+            authority.verify(&block, BlockHeight::new(5)),
+            Err(BtcError::MissingAuthoritySignatures { signed: 1, .. })
+        ));
+
+        // a signature over another hash doesn't count either
+        block.add_authority_signature(BlockSignature::sign(&Hash::zero(), &bob));
+        assert!(authority.verify(&block, BlockHeight::new(5)).is_err());
+
+        block.cosign(&bob);
+        assert!(authority.verify(&block, BlockHeight::new(5)).is_ok());
+    }
+}
@@ -6,9 +6,10 @@ use std::{
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    crypto::{PrivateKey, PublicKey},
     custom_sha_types::Hash,
     error::{BtcError, Result},
-    types::{BlockHeader, Transaction, TransactionOutput},
+    types::{BlockHeader, BlockHeight, BlockSignature, Transaction, TransactionOutput, UtxoEntry},
     utils::Saveable,
 };
 
@@ -16,6 +17,10 @@ use crate::{
 pub struct Block {
     header: BlockHeader,
     transactions: Vec<Transaction>,
+    /// Co-signatures for chains with an `AuthoritySet`. Not covered by
+    /// the block hash, and left out of the encoding when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    authority_signatures: Vec<BlockSignature>,
 }
 
 impl Block {
@@ -23,17 +28,53 @@ impl Block {
         Block {
             header,
             transactions,
+            authority_signatures: vec![],
         }
     }
 
+    pub fn authority_signatures(&self) -> &[BlockSignature] {
+        &self.authority_signatures
+    }
+
+    /// Adds `signature`, replacing an earlier one by the same signer.
+    pub fn add_authority_signature(&mut self, signature: BlockSignature) {
+        self.authority_signatures
+            .retain(|existing| existing.signer != signature.signer);
+        self.authority_signatures.push(signature);
+    }
+
+    /// Signs the block hash with `private_key`. Done once the block is
+    /// mined, since a new nonce changes the hash.
+    pub fn cosign(&mut self, private_key: &PrivateKey) {
+        self.add_authority_signature(BlockSignature::sign(&self.hash(), private_key));
+    }
+
+    /// The block is identified by its header hash, which is what
+    /// `prev_block_hash` links to and what `FetchBlockByHash` looks up.
+    /// The header commits to the transactions through the merkle root.
     pub fn hash(&self) -> Hash {
-        Hash::hash(self)
+        self.header.hash()
     }
 
+    /// Checks the transactions against `utxos`, the UTXO set before the
+    /// block. A transaction may spend outputs of the transactions before
+    /// it in the block.
     pub fn verify_transactions(
         &self,
-        predicted_block_height: u64,
-        utxos: &HashMap<Hash, (bool, TransactionOutput)>,
+        predicted_block_height: BlockHeight,
+        utxos: &HashMap<Hash, UtxoEntry>,
+    ) -> Result<()> {
+        self.check_transactions(predicted_block_height, utxos, true)
+    }
+
+    /// `verify_transactions`, leaving out the input signatures if
+    /// `check_signatures` is false because `verify_signatures` already
+    /// found them valid.
+    pub(crate) fn check_transactions(
+        &self,
+        predicted_block_height: BlockHeight,
+        utxos: &HashMap<Hash, UtxoEntry>,
+        check_signatures: bool,
     ) -> Result<()> {
         let mut inputs: HashMap<Hash, TransactionOutput> = HashMap::new();
 
@@ -44,13 +85,41 @@ impl Block {
         // Verify coinbase transaction
         self.verify_coinbase_transaction(predicted_block_height, utxos)?;
 
+        // Only the coinbase transaction may carry a tag
+        if self.transactions[1..]
+            .iter()
+            .any(|transaction| transaction.coinbase_tag().is_some())
+        {
+            return Err(BtcError::InvalidTransaction);
+        }
+
+        if self
+            .transactions
+            .iter()
+            .any(|transaction| transaction.is_expired_at(predicted_block_height))
+        {
+            return Err(BtcError::ExpiredTransaction);
+        }
+
+        if !self
+            .transactions
+            .iter()
+            .all(Transaction::data_outputs_within_limits)
+        {
+            return Err(BtcError::InvalidTransaction);
+        }
+
+        // outputs of the transactions checked so far, which later
+        // transactions in the block may spend
+        let mut created: HashMap<Hash, TransactionOutput> = HashMap::new();
         for transaction in &self.transactions {
             let mut input_value = 0;
             let mut output_value = 0;
             for input in transaction.inputs() {
                 let prev_output = utxos
                     .get(input.prev_transaction_output_hash())
-                    .map(|(_, output)| output);
+                    .map(UtxoEntry::output)
+                    .or_else(|| created.get(input.prev_transaction_output_hash()));
 
                 let prev_output = prev_output.ok_or(BtcError::InvalidTransaction)?;
 
@@ -58,9 +127,10 @@ impl Block {
                     return Err(BtcError::DoubleSpending);
                 }
 
-                if !input
-                    .signature()
-                    .verify(input.prev_transaction_output_hash(), prev_output.pubkey())
+                if check_signatures
+                    && !input
+                        .signature()
+                        .verify(input.prev_transaction_output_hash(), prev_output.pubkey())
                 {
                     return Err(BtcError::InvalidSignature);
                 }
@@ -71,9 +141,12 @@ impl Block {
 
             for output in transaction.outputs() {
                 output_value += output.value();
+                created.insert(output.hash(), output.clone());
             }
 
-            if input_value < output_value {
+            // the coinbase creates its outputs, checked above
+            let is_coinbase = std::ptr::eq(transaction, &self.transactions[0]);
+            if !is_coinbase && input_value < output_value {
                 return Err(BtcError::InvalidTransaction);
             }
         }
@@ -81,10 +154,37 @@ impl Block {
         Ok(())
     }
 
+    /// Checks the signature of every input against the key of the output
+    /// it spends, found with `find_key` or among the outputs of the
+    /// block's own transactions. None if a spent output can't be found,
+    /// and the block has to be checked in full. An outpoint names a
+    /// single output, so the outcome doesn't depend on the UTXO set the
+    /// block is connected on: the checks can run on other threads, ahead
+    /// of `Blockchain::add_block_with_verified_signatures`.
+    pub fn verify_signatures(&self, find_key: impl Fn(&Hash) -> Option<PublicKey>) -> Option<bool> {
+        let created: HashMap<Hash, &PublicKey> = self
+            .transactions
+            .iter()
+            .flat_map(Transaction::outputs)
+            .map(|output| (output.hash(), output.pubkey()))
+            .collect();
+        for input in self.transactions.iter().flat_map(Transaction::inputs) {
+            let outpoint = input.prev_transaction_output_hash();
+            let key = match created.get(outpoint) {
+                Some(key) => (*key).clone(),
+                None => find_key(outpoint)?,
+            };
+            if !input.signature().verify(outpoint, &key) {
+                return Some(false);
+            }
+        }
+        Some(true)
+    }
+
     pub fn verify_coinbase_transaction(
         &self,
-        predicted_block_height: u64,
-        utxos: &HashMap<Hash, (bool, TransactionOutput)>,
+        predicted_block_height: BlockHeight,
+        utxos: &HashMap<Hash, UtxoEntry>,
     ) -> Result<()> {
         let coinbase_transaction = &self.transactions[0];
 
@@ -96,9 +196,15 @@ impl Block {
             return Err(BtcError::InvalidTransaction);
         }
 
+        if coinbase_transaction
+            .coinbase_tag()
+            .is_some_and(|tag| tag.len() > crate::MAX_COINBASE_TAG_SIZE)
+        {
+            return Err(BtcError::InvalidTransaction);
+        }
+
         let miner_fees = self.calculated_miner_fees(utxos)?;
-        let block_reward = crate::INITIAL_REWARD * 10u64.pow(8)
-            / 2u64.pow((predicted_block_height / crate::HALVING_INTERVAL) as u32);
+        let block_reward = predicted_block_height.block_reward();
 
         let total_coinbase_outputs: u64 = coinbase_transaction
             .outputs()
@@ -113,10 +219,7 @@ impl Block {
         Ok(())
     }
 
-    pub fn calculated_miner_fees(
-        &self,
-        utxos: &HashMap<Hash, (bool, TransactionOutput)>,
-    ) -> Result<u64> {
+    pub fn calculated_miner_fees(&self, utxos: &HashMap<Hash, UtxoEntry>) -> Result<u64> {
         let mut inputs: HashMap<Hash, TransactionOutput> = HashMap::new();
         let mut outputs: HashMap<Hash, TransactionOutput> = HashMap::new();
 
@@ -127,9 +230,11 @@ impl Block {
                     return Err(BtcError::DoubleSpending);
                 }
 
+                // an earlier transaction of the block may have created it
                 let prev_output = utxos
                     .get(previous_transaction_output_hash)
-                    .map(|(_, output)| output);
+                    .map(UtxoEntry::output)
+                    .or_else(|| outputs.get(previous_transaction_output_hash));
 
                 let prev_output = prev_output.ok_or(BtcError::InvalidTransaction)?;
 
@@ -160,9 +265,37 @@ impl Block {
         self.header.mine(steps)
     }
 
+    /// Where `mine` goes on from, so several miners can search distinct
+    /// ranges of nonces of one template.
+    pub fn set_nonce(&mut self, nonce: u64) {
+        self.header.set_nonce(nonce);
+    }
+
     pub fn transactions(&self) -> &Vec<Transaction> {
         &self.transactions
     }
+
+    /// Bytes of the block's transactions, the coinbase included, which
+    /// the chain's maximum block size limits. The header is left out as
+    /// it is the same size in every block.
+    pub fn transactions_size(&self) -> usize {
+        self.transactions.iter().map(Transaction::size).sum()
+    }
+
+    /// Hex of the canonical CBOR encoding, as blocks are sent and
+    /// saved. See `Transaction::to_hex`.
+    pub fn to_hex(&self) -> String {
+        let mut bytes = vec![];
+        self.save(&mut bytes)
+            .expect("BUG: encoding into memory can't fail");
+        hex::encode(bytes)
+    }
+
+    /// Parses `to_hex` output, ignoring surrounding whitespace.
+    pub fn from_hex(encoded: &str) -> Result<Self> {
+        let bytes = hex::decode(encoded.trim()).map_err(|_| BtcError::InvalidBlock)?;
+        Block::load(bytes.as_slice()).map_err(|_| BtcError::InvalidBlock)
+    }
 }
 
 impl Saveable for Block {
@@ -180,25 +313,15 @@ impl Saveable for Block {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{MIN_TARGET, crypto::PrivateKey, utils::MerkleRoot};
+    use crate::{
+        MIN_TARGET, crypto::PrivateKey, testutil::coinbase_transaction, utils::MerkleRoot,
+    };
     use chrono::Utc;
     use uuid::Uuid;
 
-    fn create_coinbase_transaction(value: u64) -> Transaction {
-        let private_key = PrivateKey::default();
-        Transaction::new(
-            vec![],
-            vec![TransactionOutput::new(
-                value,
-                Uuid::new_v4(),
-                private_key.public_key(),
-            )],
-        )
-    }
-
     #[test]
     fn test_block_creation() {
-        let transactions = vec![create_coinbase_transaction(5000000000)];
+        let transactions = vec![coinbase_transaction(5000000000)];
         let merkle_root = MerkleRoot::calculate(&transactions);
         let header = BlockHeader::new(Utc::now(), 0, Hash::zero(), merkle_root, MIN_TARGET);
         let block = Block::new(header, transactions);
@@ -206,9 +329,52 @@ mod tests {
         assert_eq!(block.transactions.len(), 1);
     }
 
+    #[test]
+    fn test_block_verify_signatures() {
+        use crate::{crypto::Signature, types::TransactionInput};
+
+        let owner = PrivateKey::from_seed(b"owner");
+        let funding = TransactionOutput::new(100, Uuid::new_v4(), owner.public_key());
+        let spend = |outpoint: Hash, key: &PrivateKey| {
+            Transaction::new(
+                vec![TransactionInput::new(
+                    outpoint,
+                    Signature::sign_output(&outpoint, key),
+                )],
+                vec![TransactionOutput::new(
+                    100,
+                    Uuid::new_v4(),
+                    owner.public_key(),
+                )],
+            )
+        };
+        let block = |transactions: Vec<Transaction>| {
+            let merkle_root = MerkleRoot::calculate(&transactions);
+            let header = BlockHeader::new(Utc::now(), 0, Hash::zero(), merkle_root, MIN_TARGET);
+            Block::new(header, transactions)
+        };
+        let find_key = |outpoint: &Hash| (*outpoint == funding.hash()).then(|| owner.public_key());
+
+        // the second spend is of an output created earlier in the block
+        let first = spend(funding.hash(), &owner);
+        let second = spend(first.outputs()[0].hash(), &owner);
+        let valid = block(vec![coinbase_transaction(0), first, second]);
+        assert_eq!(valid.verify_signatures(find_key), Some(true));
+
+        let forged = block(vec![spend(
+            funding.hash(),
+            &PrivateKey::from_seed(b"thief"),
+        )]);
+        assert_eq!(forged.verify_signatures(find_key), Some(false));
+
+        // unknown outputs are left to the full check
+        let unknown = block(vec![spend(Hash::hash(&1u32), &owner)]);
+        assert_eq!(unknown.verify_signatures(find_key), None);
+    }
+
     #[test]
     fn test_block_hash_deterministic() {
-        let transactions = vec![create_coinbase_transaction(5000000000)];
+        let transactions = vec![coinbase_transaction(5000000000)];
         let merkle_root = MerkleRoot::calculate(&transactions);
         let header = BlockHeader::new(Utc::now(), 0, Hash::zero(), merkle_root, MIN_TARGET);
         let block = Block::new(header, transactions);
@@ -217,12 +383,13 @@ mod tests {
         let hash2 = block.hash();
 
         assert_eq!(hash1, hash2);
+        assert_eq!(hash1, block.header().hash());
     }
 
     #[test]
     fn test_block_verify_empty_transactions() {
         // Create a dummy transaction for merkle root calculation
-        let dummy_tx = create_coinbase_transaction(5000000000);
+        let dummy_tx = coinbase_transaction(5000000000);
         let merkle_root = MerkleRoot::calculate(&[dummy_tx]);
 
         let header = BlockHeader::new(Utc::now(), 0, Hash::zero(), merkle_root, MIN_TARGET);
@@ -230,25 +397,80 @@ mod tests {
         let block = Block::new(header, vec![]);
         let utxos = HashMap::new();
 
-        let result = block.verify_transactions(0, &utxos);
+        let result = block.verify_transactions(BlockHeight::GENESIS, &utxos);
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_block_verify_rejects_expired_transaction() {
+        let transactions = vec![coinbase_transaction(5000000000).with_expiry(BlockHeight::GENESIS)];
+        let merkle_root = MerkleRoot::calculate(&transactions);
+        let header = BlockHeader::new(Utc::now(), 0, Hash::zero(), merkle_root, MIN_TARGET);
+        let block = Block::new(header, transactions);
+        let utxos = HashMap::new();
+
+        assert!(!matches!(
+            block.verify_transactions(BlockHeight::GENESIS, &utxos),
+            Err(BtcError::ExpiredTransaction)
+        ));
+        assert!(matches!(
+            block.verify_transactions(BlockHeight::new(1), &utxos),
+            Err(BtcError::ExpiredTransaction)
+        ));
+    }
+
     #[test]
     fn test_block_verify_coinbase_no_inputs() {
-        let transactions = vec![create_coinbase_transaction(5000000000)];
+        let transactions = vec![coinbase_transaction(5000000000)];
         let merkle_root = MerkleRoot::calculate(&transactions);
         let header = BlockHeader::new(Utc::now(), 0, Hash::zero(), merkle_root, MIN_TARGET);
         let block = Block::new(header, transactions);
         let utxos = HashMap::new();
 
-        let result = block.verify_coinbase_transaction(0, &utxos);
+        let result = block.verify_coinbase_transaction(BlockHeight::GENESIS, &utxos);
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_block_verify_coinbase_tag_within_limit() {
+        let transactions = vec![
+            coinbase_transaction(5000000000)
+                .with_coinbase_tag(vec![b'x'; crate::MAX_COINBASE_TAG_SIZE]),
+        ];
+        let merkle_root = MerkleRoot::calculate(&transactions);
+        let header = BlockHeader::new(Utc::now(), 0, Hash::zero(), merkle_root, MIN_TARGET);
+        let block = Block::new(header, transactions);
+        let utxos = HashMap::new();
+
+        assert!(
+            block
+                .verify_coinbase_transaction(BlockHeight::GENESIS, &utxos)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_block_verify_coinbase_tag_too_large() {
+        let transactions = vec![coinbase_transaction(5000000000).with_coinbase_tag(vec![
+            b'x';
+            crate::MAX_COINBASE_TAG_SIZE
+                + 1
+        ])];
+        let merkle_root = MerkleRoot::calculate(&transactions);
+        let header = BlockHeader::new(Utc::now(), 0, Hash::zero(), merkle_root, MIN_TARGET);
+        let block = Block::new(header, transactions);
+        let utxos = HashMap::new();
+
+        assert!(
+            block
+                .verify_coinbase_transaction(BlockHeight::GENESIS, &utxos)
+                .is_err()
+        );
+    }
+
     #[test]
     fn test_block_serialization() {
-        let transactions = vec![create_coinbase_transaction(5000000000)];
+        let transactions = vec![coinbase_transaction(5000000000)];
         let merkle_root = MerkleRoot::calculate(&transactions);
         let header = BlockHeader::new(Utc::now(), 0, Hash::zero(), merkle_root, MIN_TARGET);
         let block = Block::new(header, transactions);
@@ -261,9 +483,61 @@ mod tests {
         assert_eq!(block.transactions.len(), loaded_block.transactions.len());
     }
 
+    #[test]
+    fn test_block_cosign() {
+        let transactions = vec![coinbase_transaction(5000000000)];
+        let merkle_root = MerkleRoot::calculate(&transactions);
+        let header = BlockHeader::new(Utc::now(), 0, Hash::zero(), merkle_root, MIN_TARGET);
+        let mut block = Block::new(header, transactions);
+        let mut unsigned = Vec::new();
+        block.save(&mut unsigned).unwrap();
+
+        let hash = block.hash();
+        block.cosign(&PrivateKey::default());
+        assert_eq!(block.hash(), hash);
+        let mut signed = Vec::new();
+        block.save(&mut signed).unwrap();
+        assert!(signed.len() > unsigned.len());
+
+        let loaded = Block::load(signed.as_slice()).unwrap();
+        assert_eq!(loaded.authority_signatures().len(), 1);
+        assert!(loaded.authority_signatures()[0].verify(&hash));
+        // unsigned blocks encode as before the field existed
+        assert!(
+            Block::load(unsigned.as_slice())
+                .unwrap()
+                .authority_signatures()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_block_hex() {
+        let transactions = vec![coinbase_transaction(5000000000)];
+        let merkle_root = MerkleRoot::calculate(&transactions);
+        let header = BlockHeader::new(Utc::now(), 0, Hash::zero(), merkle_root, MIN_TARGET);
+        let block = Block::new(header, transactions);
+        let mut bytes = Vec::new();
+        block.save(&mut bytes).unwrap();
+
+        let encoded = block.to_hex();
+        assert_eq!(encoded, hex::encode(&bytes));
+        let decoded = Block::from_hex(&format!("{encoded}\n")).unwrap();
+        assert_eq!(decoded.hash(), block.hash());
+        assert_eq!(
+            decoded.transactions()[0].hash(),
+            block.transactions()[0].hash()
+        );
+        assert!(matches!(
+            Block::from_hex(&encoded[..encoded.len() - 2]),
+            Err(BtcError::InvalidBlock)
+        ));
+        assert!(Block::from_hex("not hex").is_err());
+    }
+
     #[test]
     fn test_calculated_miner_fees_no_transactions() {
-        let transactions = vec![create_coinbase_transaction(5000000000)];
+        let transactions = vec![coinbase_transaction(5000000000)];
         let merkle_root = MerkleRoot::calculate(&transactions);
         let header = BlockHeader::new(Utc::now(), 0, Hash::zero(), merkle_root, MIN_TARGET);
         let block = Block::new(header, transactions);
@@ -273,4 +547,40 @@ mod tests {
         assert!(fees.is_ok());
         assert_eq!(fees.unwrap(), 0);
     }
+
+    #[test]
+    fn test_calculated_miner_fees_spending_within_block() {
+        let private_key = PrivateKey::default();
+        let funding = TransactionOutput::new(1000, Uuid::new_v4(), private_key.public_key());
+        let spend = |outpoint: Hash, value: u64| {
+            Transaction::new(
+                vec![crate::types::TransactionInput::new(
+                    outpoint,
+                    crate::crypto::Signature::sign_output(&outpoint, &private_key),
+                )],
+                vec![TransactionOutput::new(
+                    value,
+                    Uuid::new_v4(),
+                    private_key.public_key(),
+                )],
+            )
+        };
+        let parent = spend(funding.hash(), 1000);
+        let child = spend(parent.outputs()[0].hash(), 700);
+        let mut utxos = HashMap::new();
+        utxos.insert(funding.hash(), UtxoEntry::new(funding));
+        let block_of = |transactions: Vec<Transaction>| {
+            let merkle_root = MerkleRoot::calculate(&transactions);
+            let header = BlockHeader::new(Utc::now(), 0, Hash::zero(), merkle_root, MIN_TARGET);
+            Block::new(header, transactions)
+        };
+
+        let coinbase = coinbase_transaction(5000000300);
+        let block = block_of(vec![coinbase.clone(), parent.clone(), child.clone()]);
+        assert_eq!(block.calculated_miner_fees(&utxos).unwrap(), 300);
+
+        // a transaction can't spend outputs created after it
+        let block = block_of(vec![coinbase, child, parent]);
+        assert!(block.calculated_miner_fees(&utxos).is_err());
+    }
 }
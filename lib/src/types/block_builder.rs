@@ -0,0 +1,542 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::{
+    U256,
+    crypto::PublicKey,
+    custom_sha_types::Hash,
+    types::{Block, BlockHeader, BlockHeight, Blockchain, Transaction, TransactionOutput},
+    utils::MerkleRoot,
+};
+
+// bytes kept free for the coinbase, enough for one paying any value
+// with the largest tag
+const COINBASE_RESERVE: usize = 512;
+
+/// Assembles a block template: picks candidate transactions, tallies
+/// their fees, pays subsidy plus fees to the coinbase and computes the
+/// merkle root. The nonce is left at zero for the miner.
+///
+/// Given the same parent, candidates, timestamp and payee, the template
+/// is the same byte for byte, so pools can cache templates and check
+/// shares against them. The extranonce is the only thing to vary to get
+/// distinct work for the same inputs.
+#[derive(Debug, Clone)]
+pub struct BlockBuilder {
+    prev_block_hash: Hash,
+    height: BlockHeight,
+    target: U256,
+    timestamp: DateTime<Utc>,
+    not_before: Option<DateTime<Utc>>,
+    coinbase_tag: Option<Vec<u8>>,
+    extranonce: u64,
+    priority: HashSet<Hash>,
+    max_size: usize,
+    transactions: Vec<Transaction>,
+    // bytes of `transactions`
+    size: usize,
+    spent: HashSet<Hash>,
+    fees: u64,
+}
+
+impl BlockBuilder {
+    /// A builder for the block at `height`, on top of `prev_block_hash`.
+    pub fn new(prev_block_hash: Hash, height: BlockHeight, target: U256) -> Self {
+        BlockBuilder {
+            prev_block_hash,
+            height,
+            target,
+            timestamp: Utc::now(),
+            not_before: None,
+            coinbase_tag: None,
+            extranonce: 0,
+            priority: HashSet::new(),
+            max_size: crate::MAX_BLOCK_SIZE,
+            transactions: vec![],
+            size: 0,
+            spent: HashSet::new(),
+            fees: 0,
+        }
+    }
+
+    /// A builder for the block extending `blockchain`'s best block,
+    /// within its maximum block size and timestamped after it.
+    pub fn on(blockchain: &Blockchain) -> Self {
+        let prev_block_hash = blockchain.tip().map(|tip| tip.hash).unwrap_or(Hash::zero());
+        let builder = Self::new(
+            prev_block_hash,
+            blockchain.block_height(),
+            blockchain.target(),
+        )
+        .max_size(blockchain.max_block_size());
+        match blockchain.earliest_next_timestamp() {
+            Some(earliest) => builder.not_before(earliest),
+            None => builder,
+        }
+    }
+
+    /// The header's timestamp, by default the time the builder was made.
+    pub fn timestamp(mut self, timestamp: DateTime<Utc>) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    /// Earliest timestamp the header may have, replacing an earlier one.
+    /// A parent timestamped ahead of the local clock would otherwise get
+    /// a child no later than itself, which the chain refuses.
+    pub fn not_before(mut self, earliest: DateTime<Utc>) -> Self {
+        self.not_before = Some(earliest);
+        self
+    }
+
+    /// Tag for the coinbase transaction. The size limit is enforced when
+    /// the block is verified.
+    pub fn coinbase_tag(mut self, tag: Option<Vec<u8>>) -> Self {
+        self.coinbase_tag = tag;
+        self
+    }
+
+    /// Varies the coinbase output, and so the merkle root, without
+    /// changing anything else. 0 by default.
+    pub fn extranonce(mut self, extranonce: u64) -> Self {
+        self.extranonce = extranonce;
+        self
+    }
+
+    /// Transactions `packages` picks before any other, whatever their fee,
+    /// e.g. the mempool's priority lane.
+    pub fn priority(mut self, priority: HashSet<Hash>) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Most bytes of transactions in the block, the coinbase included, by
+    /// default `MAX_BLOCK_SIZE`. See `Block::transactions_size`.
+    pub fn max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Adds `transaction`, which pays `fee`, unless it doesn't fit, it
+    /// expired before this height or it spends an output an included
+    /// transaction already spends. Returns whether it was added.
+    pub fn add_transaction(&mut self, transaction: Transaction, fee: u64) -> bool {
+        self.add_package(vec![(transaction, fee)])
+    }
+
+    /// Adds `package`, transactions with their fees with parents before
+    /// children, if every one of them can be added, see `add_transaction`.
+    /// Returns whether it was added.
+    pub fn add_package(&mut self, package: Vec<(Transaction, u64)>) -> bool {
+        let size: usize = package
+            .iter()
+            .map(|(transaction, _)| transaction.size())
+            .sum();
+        if COINBASE_RESERVE + self.size + size > self.max_size {
+            return false;
+        }
+        let mut spent = HashSet::new();
+        for (transaction, _) in &package {
+            if transaction.is_expired_at(self.height) {
+                return false;
+            }
+            for input in transaction.inputs() {
+                let outpoint = *input.prev_transaction_output_hash();
+                if self.spent.contains(&outpoint) || !spent.insert(outpoint) {
+                    return false;
+                }
+            }
+        }
+        self.spent.extend(spent);
+        self.size += size;
+        for (transaction, fee) in package {
+            self.fees += fee;
+            self.transactions.push(transaction);
+        }
+        true
+    }
+
+    /// Adds candidates with their fees in order, see `add_transaction`.
+    pub fn transactions(
+        mut self,
+        candidates: impl IntoIterator<Item = (Transaction, u64)>,
+    ) -> Self {
+        for (transaction, fee) in candidates {
+            self.add_transaction(transaction, fee);
+        }
+        self
+    }
+
+    /// Adds candidates by the fee rate of their ancestor package, highest
+    /// first. A candidate spending outputs of other candidates is added
+    /// together with them, so a child paying a high fee pulls in a parent
+    /// paying too little to be picked on its own (child pays for parent).
+    /// Packages holding a `priority` transaction come first. Packages that
+    /// don't fit or conflict with what's included are skipped whole; ties
+    /// keep the order of `candidates`.
+    pub fn packages(mut self, candidates: impl IntoIterator<Item = (Transaction, u64)>) -> Self {
+        let candidates: Vec<(Transaction, u64)> = candidates.into_iter().collect();
+        let sizes: Vec<u64> = candidates
+            .iter()
+            .map(|(transaction, _)| transaction.size() as u64)
+            .collect();
+        // output hash -> index of the candidate creating it
+        let creators: HashMap<Hash, usize> = candidates
+            .iter()
+            .enumerate()
+            .flat_map(|(index, (transaction, _))| {
+                transaction
+                    .outputs()
+                    .iter()
+                    .map(move |output| (output.hash(), index))
+            })
+            .collect();
+        let parents: Vec<Vec<usize>> = candidates
+            .iter()
+            .map(|(transaction, _)| {
+                transaction
+                    .inputs()
+                    .iter()
+                    .filter_map(|input| creators.get(input.prev_transaction_output_hash()))
+                    .copied()
+                    .collect()
+            })
+            .collect();
+        let prioritized: Vec<bool> = candidates
+            .iter()
+            .map(|(transaction, _)| self.priority.contains(&transaction.hash()))
+            .collect();
+        let mut included = vec![false; candidates.len()];
+        let mut skipped = vec![false; candidates.len()];
+
+        loop {
+            // (package, priority, fee, size) of the best package left
+            let mut best: Option<(Vec<usize>, bool, u64, u64)> = None;
+            for index in 0..candidates.len() {
+                if included[index] || skipped[index] {
+                    continue;
+                }
+                let Some(package) = pending_package(index, &parents, &included, &skipped) else {
+                    // an ancestor was skipped, so this can't be mined either
+                    skipped[index] = true;
+                    continue;
+                };
+                let priority = package.iter().any(|&i| prioritized[i]);
+                let fee: u64 = package.iter().map(|&i| candidates[i].1).sum();
+                let size: u64 = package.iter().map(|&i| sizes[i]).sum();
+                if best
+                    .as_ref()
+                    .is_none_or(|(_, best_priority, best_fee, best_size)| {
+                        (priority, fee as u128 * *best_size as u128)
+                            > (*best_priority, *best_fee as u128 * size as u128)
+                    })
+                {
+                    best = Some((package, priority, fee, size));
+                }
+            }
+            let Some((package, ..)) = best else {
+                break;
+            };
+            let transactions = package
+                .iter()
+                .map(|&index| candidates[index].clone())
+                .collect();
+            if self.add_package(transactions) {
+                for index in package {
+                    included[index] = true;
+                }
+            } else {
+                // its ancestors may still make it in another package
+                skipped[*package.last().expect("BUG: a package is never empty")] = true;
+            }
+        }
+        self
+    }
+
+    /// Total fees of the transactions added so far.
+    pub fn fees(&self) -> u64 {
+        self.fees
+    }
+
+    /// The block, with a coinbase paying subsidy plus fees to `pubkey`.
+    pub fn build(self, pubkey: PublicKey) -> Block {
+        // derived rather than random, so the template is reproducible;
+        // the parent makes it unique on any one chain
+        let seed = Hash::hash(&(self.prev_block_hash, self.height, &pubkey, self.extranonce));
+        let unique_id = Uuid::from_bytes(
+            seed.as_bytes()[..16]
+                .try_into()
+                .expect("BUG: hash is at least 16 bytes"),
+        );
+        let coinbase = Transaction::new(
+            vec![],
+            vec![TransactionOutput::new(
+                self.height.block_reward() + self.fees,
+                unique_id,
+                pubkey,
+            )],
+        );
+        let coinbase = match self.coinbase_tag {
+            Some(tag) => coinbase.with_coinbase_tag(tag),
+            None => coinbase,
+        };
+        let mut transactions = vec![coinbase];
+        transactions.extend(self.transactions);
+        let merkle_root = MerkleRoot::calculate(&transactions);
+        let timestamp = match self.not_before {
+            Some(earliest) => self.timestamp.max(earliest),
+            None => self.timestamp,
+        };
+        let header = BlockHeader::new(timestamp, 0, self.prev_block_hash, merkle_root, self.target);
+        Block::new(header, transactions)
+    }
+}
+
+/// `index` and its ancestors among the candidates that aren't included
+/// yet, parents before children. None if one of them was skipped.
+fn pending_package(
+    index: usize,
+    parents: &[Vec<usize>],
+    included: &[bool],
+    skipped: &[bool],
+) -> Option<Vec<usize>> {
+    fn visit(
+        index: usize,
+        parents: &[Vec<usize>],
+        included: &[bool],
+        skipped: &[bool],
+        package: &mut Vec<usize>,
+    ) -> Option<()> {
+        for &parent in &parents[index] {
+            if included[parent] || package.contains(&parent) {
+                continue;
+            }
+            if skipped[parent] {
+                return None;
+            }
+            visit(parent, parents, included, skipped, package)?;
+        }
+        package.push(index);
+        Some(())
+    }
+    let mut package = vec![];
+    visit(index, parents, included, skipped, &mut package)?;
+    Some(package)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        MIN_TARGET,
+        crypto::{PrivateKey, Signature},
+        types::TransactionInput,
+    };
+
+    fn spending(output_hash: Hash) -> Transaction {
+        let private_key = PrivateKey::default();
+        let signature = Signature::sign_output(&output_hash, &private_key);
+        Transaction::new(
+            vec![TransactionInput::new(output_hash, signature)],
+            vec![TransactionOutput::new(
+                100,
+                Uuid::new_v4(),
+                private_key.public_key(),
+            )],
+        )
+    }
+
+    #[test]
+    fn test_builder_pays_reward_and_fees() {
+        let pubkey = PrivateKey::default().public_key();
+        let block = BlockBuilder::new(Hash::zero(), BlockHeight::GENESIS, MIN_TARGET)
+            .coinbase_tag(Some(b"pool".to_vec()))
+            .transactions([(spending(Hash::zero()), 7)])
+            .build(pubkey.clone());
+
+        assert_eq!(block.transactions().len(), 2);
+        let coinbase = &block.transactions()[0];
+        assert_eq!(coinbase.coinbase_tag(), Some(&b"pool"[..]));
+        assert_eq!(
+            coinbase.outputs()[0].value(),
+            BlockHeight::GENESIS.block_reward() + 7
+        );
+        assert_eq!(coinbase.outputs()[0].pubkey(), &pubkey);
+        assert_eq!(
+            *block.header().merkle_root(),
+            MerkleRoot::calculate(block.transactions())
+        );
+    }
+
+    #[test]
+    fn test_coinbase_reserve() {
+        let largest = BlockBuilder::new(Hash::zero(), BlockHeight::GENESIS, MIN_TARGET)
+            .coinbase_tag(Some(vec![u8::MAX; crate::MAX_COINBASE_TAG_SIZE]))
+            .build(PrivateKey::default().public_key());
+        let coinbase = largest.transactions()[0].clone();
+        let value = TransactionOutput::new(
+            u64::MAX,
+            *coinbase.outputs()[0].unique_id(),
+            coinbase.outputs()[0].pubkey().clone(),
+        );
+        let coinbase = Transaction::new(vec![], vec![value])
+            .with_coinbase_tag(vec![u8::MAX; crate::MAX_COINBASE_TAG_SIZE]);
+        assert!(coinbase.size() <= COINBASE_RESERVE);
+    }
+
+    #[test]
+    fn test_builder_limits_and_conflicts() {
+        let conflicting = spending(Hash::zero());
+        let other = spending(Hash::hash(&1u8));
+        // room for these two only
+        let mut builder = BlockBuilder::new(Hash::zero(), BlockHeight::GENESIS, MIN_TARGET)
+            .max_size(COINBASE_RESERVE + conflicting.size() + other.size());
+        assert!(builder.add_transaction(conflicting.clone(), 1));
+        assert!(!builder.add_transaction(conflicting, 1));
+        let builder = builder.transactions([(other, 2), (spending(Hash::hash(&2u8)), 4)]);
+
+        assert_eq!(builder.fees(), 3);
+        let block = builder.build(PrivateKey::default().public_key());
+        assert_eq!(block.transactions().len(), 3);
+    }
+
+    #[test]
+    fn test_builder_child_pays_for_parent() {
+        let parent = spending(Hash::zero());
+        let child = spending(parent.outputs()[0].hash());
+        let unrelated = spending(Hash::hash(&1u8));
+        let candidates = vec![
+            (unrelated.clone(), 30),
+            (parent.clone(), 0),
+            (child.clone(), 100),
+        ];
+
+        let builder = BlockBuilder::new(Hash::zero(), BlockHeight::new(1), MIN_TARGET)
+            .max_size(COINBASE_RESERVE + parent.size() + child.size())
+            .packages(candidates.clone());
+        assert_eq!(builder.fees(), 100);
+        let block = builder.build(PrivateKey::default().public_key());
+        let hashes: Vec<Hash> = block.transactions()[1..]
+            .iter()
+            .map(|tx| tx.hash())
+            .collect();
+        assert_eq!(hashes, vec![parent.hash(), child.hash()]);
+
+        // a child can't be added without its parent
+        let builder =
+            BlockBuilder::new(Hash::zero(), BlockHeight::new(1), MIN_TARGET).packages(vec![
+                (parent.with_expiry(BlockHeight::GENESIS), 0),
+                (child, 100),
+            ]);
+        assert_eq!(builder.fees(), 0);
+
+        let builder =
+            BlockBuilder::new(Hash::zero(), BlockHeight::new(1), MIN_TARGET).packages(candidates);
+        assert_eq!(builder.fees(), 130);
+    }
+
+    #[test]
+    fn test_builder_priority_comes_first() {
+        let parent = spending(Hash::zero());
+        let own = spending(parent.outputs()[0].hash());
+        let paying = spending(Hash::hash(&1u8));
+        let candidates = vec![
+            (paying.clone(), 1_000),
+            (parent.clone(), 0),
+            (own.clone(), 0),
+        ];
+
+        // room for the prioritized transaction and its parent only
+        let block = BlockBuilder::new(Hash::zero(), BlockHeight::new(1), MIN_TARGET)
+            .max_size(COINBASE_RESERVE + parent.size() + own.size())
+            .priority(HashSet::from([own.hash()]))
+            .packages(candidates.clone())
+            .build(PrivateKey::default().public_key());
+        let hashes: Vec<Hash> = block.transactions()[1..]
+            .iter()
+            .map(|tx| tx.hash())
+            .collect();
+        assert_eq!(hashes, vec![parent.hash(), own.hash()]);
+
+        // the rest still goes by fee rate
+        let builder = BlockBuilder::new(Hash::zero(), BlockHeight::new(1), MIN_TARGET)
+            .priority(HashSet::from([own.hash()]))
+            .packages(candidates);
+        assert_eq!(builder.fees(), 1_000);
+        let block = builder.build(PrivateKey::default().public_key());
+        assert_eq!(block.transactions()[3].hash(), paying.hash());
+    }
+
+    #[test]
+    fn test_builder_skips_expired_transactions() {
+        let mut builder = BlockBuilder::new(Hash::zero(), BlockHeight::new(3), MIN_TARGET);
+        let expired = spending(Hash::zero()).with_expiry(BlockHeight::new(2));
+        let last_chance = spending(Hash::hash(&1u8)).with_expiry(BlockHeight::new(3));
+        assert!(!builder.add_transaction(expired, 1));
+        assert!(builder.add_transaction(last_chance, 1));
+    }
+
+    #[test]
+    fn test_builder_follows_parent_from_the_future() {
+        let key = PrivateKey::default().public_key();
+        let mut blockchain = Blockchain::default();
+        let ahead = Utc::now() + chrono::Duration::hours(1);
+        let genesis = BlockBuilder::on(&blockchain)
+            .timestamp(ahead)
+            .build(key.clone());
+        blockchain.add_block(genesis).unwrap();
+        blockchain.rebuild_utxos();
+        let skew = blockchain.clock_skew(Utc::now()).unwrap();
+        assert!(skew > chrono::Duration::minutes(59));
+
+        // the clock says now, the template says a second past its parent
+        let block = BlockBuilder::on(&blockchain).build(key);
+        assert_eq!(
+            block.header().timestamp(),
+            ahead + chrono::Duration::seconds(1)
+        );
+        blockchain.add_block(block).unwrap();
+        assert!(
+            blockchain
+                .clock_skew(ahead + chrono::Duration::seconds(2))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_builder_is_deterministic() {
+        let pubkey = PrivateKey::from_seed(b"pool").public_key();
+        let candidates = vec![(spending(Hash::zero()), 5), (spending(Hash::hash(&1u8)), 9)];
+        let timestamp = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let template = |extranonce| {
+            BlockBuilder::new(Hash::hash(&"tip"), BlockHeight::new(7), MIN_TARGET)
+                .timestamp(timestamp)
+                .extranonce(extranonce)
+                .packages(candidates.clone())
+                .build(pubkey.clone())
+        };
+        assert_eq!(template(0).to_hex(), template(0).to_hex());
+
+        let (first, second) = (template(0), template(1));
+        assert_ne!(first.header().merkle_root(), second.header().merkle_root());
+        assert_eq!(first.transactions()[1..].len(), 2);
+        for (a, b) in first.transactions()[1..]
+            .iter()
+            .zip(&second.transactions()[1..])
+        {
+            assert_eq!(a.hash(), b.hash());
+        }
+        assert_eq!(first.header().timestamp(), second.header().timestamp());
+
+        // on another parent, the coinbase pays to another output
+        let other_parent = BlockBuilder::new(Hash::hash(&"other"), BlockHeight::new(7), MIN_TARGET)
+            .timestamp(timestamp)
+            .build(pubkey.clone());
+        assert_ne!(
+            other_parent.transactions()[0].outputs()[0].hash(),
+            first.transactions()[0].outputs()[0].hash()
+        );
+    }
+}
@@ -51,7 +51,8 @@ impl BlockHeader {
                 self.nonce = new_nonce;
             } else {
                 self.nonce = 0;
-                self.timestamp = Utc::now();
+                // a timestamp ahead of the clock stays, the parent may be ahead too
+                self.timestamp = Utc::now().max(self.timestamp);
             }
 
             if self.hash().matches_target(self.target) {
@@ -77,6 +78,10 @@ impl BlockHeader {
         self.nonce
     }
 
+    pub fn set_nonce(&mut self, nonce: u64) {
+        self.nonce = nonce;
+    }
+
     pub fn prev_block_hash(&self) -> &Hash {
         &self.prev_block_hash
     }
@@ -136,7 +141,8 @@ mod tests {
     fn test_block_header_nonce_increment() {
         let timestamp = Utc::now();
         let merkle_root = create_test_merkle_root();
-        let mut header = BlockHeader::new(timestamp, 0, Hash::zero(), merkle_root, MIN_TARGET);
+        // an unreachable target, so `mine` has to step the nonce
+        let mut header = BlockHeader::new(timestamp, 0, Hash::zero(), merkle_root, U256::zero());
 
         let initial_nonce = header.nonce;
         header.mine(1);
@@ -1,6 +1,8 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     io::{Error as IoError, ErrorKind as IoErrorKind, Read, Result as IoResult, Write},
+    ops::{Bound, RangeBounds},
+    sync::Arc,
 };
 
 use bigdecimal::BigDecimal;
@@ -9,28 +11,65 @@ use log::error;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    INITIAL_REWARD, U256,
+    U256,
+    consensus::{ConsensusEngine, ProofOfWork},
+    crypto::PublicKey,
     custom_sha_types::Hash,
     error::{BtcError, Result},
-    types::{Block, Transaction, TransactionOutput},
-    utils::{MerkleRoot, Saveable},
+    network::{
+        AgeBucket, BlockActivity, ChainAnalytics, ChainStats, ChainTip, FeeHistogram, MemoryInfo,
+        MempoolEntry, RescanResult, ScannedOutput, UTXO_AGE_BUCKETS, ZeroConfRisk,
+    },
+    types::{
+        AuthoritySet, Block, BlockHeight, Confirmations, Transaction, TransactionOutput, UtxoEntry,
+    },
+    utils::{MerkleProof, MerkleRoot, Saveable, UtxoFilter},
 };
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Blockchain {
-    // UTXO: Unspent Transaction Outputs mapped by their hash
-    utxos: HashMap<Hash, (bool, TransactionOutput)>,
+    // UTXO: Unspent Transaction Outputs mapped by their hash, each marked
+    // spent by the mempool transaction spending it if any
+    utxos: HashMap<Hash, UtxoEntry>,
     target: U256,
     blocks: Vec<Block>,
     #[serde(default, skip_serializing)]
     mempool: Vec<(DateTime<Utc>, Transaction)>,
+    // mempool transactions of the priority lane, see
+    // `add_priority_transaction_to_mempool`
+    #[serde(skip)]
+    mempool_priority: HashSet<Hash>,
+    // negative lookups into `utxos`, built by `rebuild_utxos`
+    #[serde(skip)]
+    utxo_filter: Option<UtxoFilter>,
+    // co-signers new blocks need, set by the node from its configuration
+    #[serde(skip)]
+    authority: Option<AuthoritySet>,
+    // checks how blocks are sealed, set by the node from its configuration
+    #[serde(skip, default = "default_consensus")]
+    consensus: Arc<dyn ConsensusEngine>,
+    // blocks refused whatever chain they come in, see `invalidate_block`
+    #[serde(default)]
+    invalid_blocks: HashSet<Hash>,
+    // most bytes of transactions in a block, set by the node from its
+    // configuration
+    #[serde(skip, default = "default_max_block_size")]
+    max_block_size: usize,
+}
+
+fn default_consensus() -> Arc<dyn ConsensusEngine> {
+    Arc::new(ProofOfWork)
+}
+
+fn default_max_block_size() -> usize {
+    crate::MAX_BLOCK_SIZE
 }
 
 impl Blockchain {
     pub fn utxos(&self) -> HashMap<Hash, TransactionOutput> {
         self.utxos
             .iter()
-            .map(|(hash, (_spent, output))| (*hash, output.clone()))
+            .map(|(hash, entry)| (*hash, entry.output().clone()))
             .collect()
     }
 
@@ -42,15 +81,514 @@ impl Blockchain {
         &self.blocks
     }
 
-    pub fn block_height(&self) -> u64 {
-        self.blocks.len() as u64
+    /// Blocks in chain order, starting at the genesis block.
+    pub fn iter_blocks(&self) -> impl DoubleEndedIterator<Item = &Block> + ExactSizeIterator {
+        self.blocks.iter()
+    }
+
+    /// Blocks whose height falls in `heights`, paired with their height.
+    /// Heights past the tip are ignored.
+    pub fn iter_blocks_in(
+        &self,
+        heights: impl RangeBounds<BlockHeight>,
+    ) -> impl DoubleEndedIterator<Item = (BlockHeight, &Block)> {
+        let start = match heights.start_bound() {
+            Bound::Included(start) => start.as_index(),
+            Bound::Excluded(start) => start.as_index().saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        let end = match heights.end_bound() {
+            Bound::Included(end) => end.as_index().saturating_add(1),
+            Bound::Excluded(end) => end.as_index(),
+            Bound::Unbounded => usize::MAX,
+        };
+        let len = self.blocks.len();
+        let (start, end) = (start.min(len), end.min(len));
+        let start = start.min(end);
+        self.blocks[start..end]
+            .iter()
+            .enumerate()
+            .map(move |(offset, block)| (BlockHeight::from_index(start + offset), block))
+    }
+
+    pub fn block_at(&self, height: BlockHeight) -> Option<&Block> {
+        self.blocks.get(height.as_index())
+    }
+
+    /// Confirmations of the block at `height`; none if there is no such block.
+    pub fn confirmations(&self, height: BlockHeight) -> Confirmations {
+        height.confirmations(self.block_height())
+    }
+
+    /// Every transaction in the chain, paired with the height of its block.
+    pub fn iter_transactions(&self) -> impl Iterator<Item = (BlockHeight, &Transaction)> {
+        self.iter_transactions_in(..)
+    }
+
+    /// Transactions in the blocks whose height falls in `heights`, see `iter_blocks_in`.
+    pub fn iter_transactions_in(
+        &self,
+        heights: impl RangeBounds<BlockHeight>,
+    ) -> impl Iterator<Item = (BlockHeight, &Transaction)> {
+        self.iter_blocks_in(heights).flat_map(|(height, block)| {
+            block
+                .transactions()
+                .iter()
+                .map(move |transaction| (height, transaction))
+        })
+    }
+
+    /// Unspent outputs with their hash, each marked spent by the mempool
+    /// transaction spending it if any.
+    pub fn iter_utxos(&self) -> impl Iterator<Item = (&Hash, &UtxoEntry)> {
+        self.utxos.iter()
+    }
+
+    /// The unspent output `outpoint` names, whether a mempool
+    /// transaction spends it or not.
+    pub fn utxo(&self, outpoint: &Hash) -> Option<&TransactionOutput> {
+        self.utxos.get(outpoint).map(UtxoEntry::output)
+    }
+
+    /// Hash of the UTXO set, the same on every node holding the same
+    /// unspent outputs whatever their mempool marks: the hash of their
+    /// outpoints in byte order.
+    pub fn utxo_set_hash(&self) -> Hash {
+        let mut outpoints: Vec<[u8; 32]> = self.utxos.keys().map(Hash::as_bytes).collect();
+        outpoints.sort_unstable();
+        Hash::hash(&outpoints)
+    }
+
+    /// Unspent outputs paying `pubkey`, see `iter_utxos`.
+    pub fn iter_utxos_for(
+        &self,
+        pubkey: &PublicKey,
+    ) -> impl Iterator<Item = (&Hash, &UtxoEntry)> + use<'_> {
+        let pubkey = pubkey.clone();
+        self.iter_utxos()
+            .filter(move |(_, entry)| *entry.output().pubkey() == pubkey)
+    }
+
+    /// Looks up a block by its (header) hash.
+    pub fn block_by_hash(&self, hash: &Hash) -> Option<&Block> {
+        self.blocks.iter().find(|block| block.hash() == *hash)
+    }
+
+    /// Earliest timestamp the next block may have, one second past the
+    /// best block's. `None` for an empty chain.
+    pub fn earliest_next_timestamp(&self) -> Option<DateTime<Utc>> {
+        let last_block = self.blocks.last()?;
+        Some(last_block.header().timestamp() + chrono::Duration::seconds(1))
+    }
+
+    /// How far the best block's timestamp is ahead of `now`, `None` if it
+    /// isn't. Blocks built on it then take their timestamp from it
+    /// rather than from the clock.
+    pub fn clock_skew(&self, now: DateTime<Utc>) -> Option<chrono::Duration> {
+        let skew = self.blocks.last()?.header().timestamp() - now;
+        (skew > chrono::Duration::zero()).then_some(skew)
+    }
+
+    /// The current best block, or `None` for an empty chain.
+    pub fn tip(&self) -> Option<ChainTip> {
+        let last_block = self.blocks.last()?;
+        Some(ChainTip {
+            hash: last_block.hash(),
+            height: BlockHeight::from_index(self.blocks.len() - 1),
+            prev: *last_block.header().prev_block_hash(),
+        })
+    }
+
+    /// Supply and UTXO set statistics, with the `top` richest public keys
+    /// (capped at `MAX_RICH_LIST_SIZE`).
+    pub fn stats(&self, top: usize) -> ChainStats {
+        let mut total_supply = 0;
+        let mut utxo_set_bytes = 0;
+        let mut balances: BTreeMap<&PublicKey, u64> = BTreeMap::new();
+        for (hash, entry) in &self.utxos {
+            let output = entry.output();
+            total_supply += output.value();
+            *balances.entry(output.pubkey()).or_default() += output.value();
+            let mut bytes = Vec::new();
+            ciborium::ser::into_writer(&(hash, output), &mut bytes)
+                .expect("BUG: UTXO serialization cannot fail");
+            utxo_set_bytes += bytes.len() as u64;
+        }
+        let mut rich_list: Vec<_> = balances
+            .into_iter()
+            .map(|(pubkey, balance)| (pubkey.clone(), balance))
+            .collect();
+        rich_list.sort_by_key(|(_, balance)| std::cmp::Reverse(*balance));
+        rich_list.truncate(top.min(crate::MAX_RICH_LIST_SIZE));
+        ChainStats {
+            height: self.block_height(),
+            total_supply,
+            utxo_count: self.utxos.len() as u64,
+            utxo_set_bytes,
+            rich_list,
+        }
+    }
+
+    /// Estimated memory taken by the UTXO set and the mempool, without a
+    /// budget. UTXOs are counted at their in-memory size, including the
+    /// unused capacity of the table; mempool transactions at their
+    /// in-memory size plus their encoded size for what they hold on the
+    /// heap.
+    pub fn memory_info(&self) -> MemoryInfo {
+        // hashbrown keeps a control byte per slot next to the entry
+        let utxo_slot = std::mem::size_of::<(Hash, UtxoEntry)>() + 1;
+        let mempool_slot = std::mem::size_of::<(DateTime<Utc>, Transaction)>();
+        let mempool_heap: usize = self.mempool.iter().map(|(_, tx)| tx.size()).sum();
+        MemoryInfo {
+            utxos: self.utxos.len() as u64,
+            utxo_bytes: (self.utxos.capacity() * utxo_slot) as u64,
+            mempool_transactions: self.mempool.len() as u64,
+            mempool_bytes: (self.mempool.capacity() * mempool_slot + mempool_heap) as u64,
+            budget: None,
+        }
+    }
+
+    /// UTXO age distribution, and value moved and coin-days destroyed by
+    /// each of the last `blocks` blocks (at most `MAX_ANALYTICS_BLOCKS`).
+    /// Walks the whole chain, since the UTXO set doesn't record when
+    /// outputs were created.
+    pub fn analytics(&self, blocks: usize) -> ChainAnalytics {
+        const SECONDS_PER_DAY: f64 = 86_400.0;
+        const SATOSHIS_PER_COIN: f64 = 100_000_000.0;
+        let first = self
+            .blocks
+            .len()
+            .saturating_sub(blocks.min(crate::MAX_ANALYTICS_BLOCKS));
+        // unspent output hash -> (value, when its block was mined)
+        let mut unspent: HashMap<Hash, (u64, DateTime<Utc>)> = HashMap::new();
+        let mut activity = vec![];
+        for (height, block) in self.iter_blocks_in(..) {
+            let timestamp = block.header().timestamp();
+            let mut value_moved = 0;
+            let mut coin_days_destroyed = 0.0;
+            for transaction in block.transactions() {
+                for input in transaction.inputs() {
+                    if let Some((value, created)) =
+                        unspent.remove(input.prev_transaction_output_hash())
+                    {
+                        let days =
+                            (timestamp - created).num_seconds().max(0) as f64 / SECONDS_PER_DAY;
+                        value_moved += value;
+                        coin_days_destroyed += value as f64 / SATOSHIS_PER_COIN * days;
+                    }
+                }
+                for output in transaction.outputs() {
+                    unspent.insert(output.hash(), (output.value(), timestamp));
+                }
+            }
+            if height.as_index() >= first {
+                activity.push(BlockActivity {
+                    height,
+                    value_moved,
+                    coin_days_destroyed,
+                });
+            }
+        }
+
+        let mut utxo_age: Vec<AgeBucket> = UTXO_AGE_BUCKETS
+            .iter()
+            .map(|&days| Some(days))
+            .chain([None])
+            .map(|max_days| AgeBucket {
+                max_days,
+                outputs: 0,
+                value: 0,
+            })
+            .collect();
+        let now = self.blocks.last().map(|block| block.header().timestamp());
+        let mut total_supply = 0;
+        for (value, created) in unspent.values() {
+            let age = now.map_or(0, |now| (now - *created).num_seconds().max(0));
+            let bucket = UTXO_AGE_BUCKETS
+                .iter()
+                .position(|&days| age <= i64::from(days) * SECONDS_PER_DAY as i64)
+                .unwrap_or(UTXO_AGE_BUCKETS.len());
+            utxo_age[bucket].outputs += 1;
+            utxo_age[bucket].value += value;
+            total_supply += value;
+        }
+        let value_moved: u64 = activity.iter().map(|block| block.value_moved).sum();
+        ChainAnalytics {
+            height: self.block_height(),
+            utxo_age,
+            blocks: activity,
+            velocity: if total_supply == 0 {
+                0.0
+            } else {
+                value_moved as f64 / total_supply as f64
+            },
+        }
+    }
+
+    /// The block holding the transaction `hash`, with its height and the
+    /// Merkle proof of the transaction. Walks the chain from the tip down.
+    pub fn transaction_proof(&self, hash: &Hash) -> Option<(BlockHeight, &Block, MerkleProof)> {
+        self.iter_blocks_in(..).rev().find_map(|(height, block)| {
+            let index = block
+                .transactions()
+                .iter()
+                .position(|transaction| transaction.hash() == *hash)?;
+            let proof = MerkleProof::new(block.transactions(), index)?;
+            Some((height, block, proof))
+        })
+    }
+
+    /// Walks the blocks from `from_height` on and collects every output paying
+    /// one of `keys`, noting where it was spent. Spends of outputs created
+    /// before `from_height` can't be recognised, so a wallet rebuilding its
+    /// state should start no later than its oldest key.
+    pub fn rescan(&self, keys: &[PublicKey], from_height: BlockHeight) -> RescanResult {
+        let keys: BTreeSet<&PublicKey> = keys.iter().collect();
+        let mut outputs: Vec<ScannedOutput> = vec![];
+        // output hash -> index in `outputs`, for matching spends
+        let mut found: HashMap<Hash, usize> = HashMap::new();
+        for (height, transaction) in self.iter_transactions_in(from_height..) {
+            for input in transaction.inputs() {
+                if let Some(&index) = found.get(input.prev_transaction_output_hash()) {
+                    outputs[index].spent_at = Some(height);
+                }
+            }
+            for output in transaction.outputs() {
+                if keys.contains(output.pubkey()) {
+                    found.insert(output.hash(), outputs.len());
+                    outputs.push(ScannedOutput {
+                        height,
+                        transaction: transaction.hash(),
+                        output: output.clone(),
+                        spent_at: None,
+                    });
+                }
+            }
+        }
+        RescanResult {
+            from_height,
+            to_height: self.block_height(),
+            outputs,
+        }
+    }
+
+    /// Mean and variance (in seconds) of the intervals between the last
+    /// `window` + 1 blocks, or `None` with fewer than two blocks.
+    pub fn block_interval_stats(&self, window: usize) -> Option<(f64, f64)> {
+        let start = self.blocks.len().saturating_sub(window + 1);
+        let intervals: Vec<f64> = self.blocks[start..]
+            .windows(2)
+            .map(|pair| {
+                (pair[1].header().timestamp() - pair[0].header().timestamp()).num_milliseconds()
+                    as f64
+                    / 1000.0
+            })
+            .collect();
+        if intervals.is_empty() {
+            return None;
+        }
+        let count = intervals.len() as f64;
+        let mean = intervals.iter().sum::<f64>() / count;
+        let variance = intervals.iter().map(|i| (i - mean).powi(2)).sum::<f64>() / count;
+        Some((mean, variance))
+    }
+
+    /// Height the next block will have, which is also the number of blocks.
+    pub fn block_height(&self) -> BlockHeight {
+        BlockHeight::from_index(self.blocks.len())
     }
 
     pub fn mempool(&self) -> &[(DateTime<Utc>, Transaction)] {
         &self.mempool
     }
 
+    /// Hashes of the mempool transactions in the priority lane. Some may
+    /// have left the mempool since, until the next `cleanup_mempool`.
+    pub fn mempool_priority(&self) -> &HashSet<Hash> {
+        &self.mempool_priority
+    }
+
+    /// Makes `add_block` require co-signatures from `authority` on the
+    /// blocks it covers. Blocks already in the chain aren't checked.
+    pub fn set_authority(&mut self, authority: Option<AuthoritySet>) {
+        self.authority = authority;
+    }
+
+    pub fn authority(&self) -> Option<&AuthoritySet> {
+        self.authority.as_ref()
+    }
+
+    /// Makes `verify_block` check blocks are sealed the way `consensus`
+    /// wants. Blocks already in the chain aren't checked.
+    pub fn set_consensus(&mut self, consensus: Arc<dyn ConsensusEngine>) {
+        self.consensus = consensus;
+    }
+
+    pub fn consensus(&self) -> &Arc<dyn ConsensusEngine> {
+        &self.consensus
+    }
+
+    /// Makes `verify_block` refuse blocks whose transactions take more
+    /// than `max_block_size` bytes, and `BlockBuilder::on` build blocks
+    /// within it. Every node of a network has to use the same limit.
+    /// Blocks already in the chain aren't checked.
+    pub fn set_max_block_size(&mut self, max_block_size: usize) {
+        self.max_block_size = max_block_size;
+    }
+
+    pub fn max_block_size(&self) -> usize {
+        self.max_block_size
+    }
+
+    /// Adds `block` on top of the chain if it passes `verify_block` and,
+    /// with an authority set, carries enough co-signatures.
     pub fn add_block(&mut self, block: Block) -> Result<()> {
+        self.connect_block(block, true)
+    }
+
+    /// `add_block` for a block whose input signatures
+    /// `Block::verify_signatures` already found valid, e.g. on another
+    /// thread while the next blocks were downloaded. Every other rule is
+    /// checked.
+    pub fn add_block_with_verified_signatures(&mut self, block: Block) -> Result<()> {
+        self.connect_block(block, false)
+    }
+
+    fn connect_block(&mut self, block: Block, check_signatures: bool) -> Result<()> {
+        self.check_block(&block, check_signatures)?;
+        if let Some(authority) = &self.authority {
+            authority.verify(&block, self.block_height())?;
+        }
+
+        let block_transactions: HashSet<_> =
+            block.transactions().iter().map(|tx| tx.hash()).collect();
+        self.remove_from_mempool(&block_transactions);
+
+        self.blocks.push(block);
+
+        self.try_adjust_target();
+        // the UTXO set isn't rebuilt yet, so it still holds the outputs
+        // the block spends
+        #[cfg(feature = "consensus-checks")]
+        self.assert_invariants(false);
+        Ok(())
+    }
+
+    /// Switches to `branch`, whose first block follows the block before
+    /// `fork_height`, if it is longer than the blocks from `fork_height`
+    /// on. Those blocks are disconnected and returned, oldest first, and
+    /// their transactions go back to the mempool if still valid. Refused
+    /// with `ReorgTooDeep` if more than `max_depth` blocks would be
+    /// disconnected, so deeper blocks are final. Every branch block is
+    /// checked like `add_block` does; the chain is left as it was on any
+    /// error.
+    pub fn reorganize(
+        &mut self,
+        fork_height: BlockHeight,
+        branch: Vec<Block>,
+        max_depth: u64,
+    ) -> Result<Vec<Block>> {
+        if fork_height > self.block_height() {
+            return Err(BtcError::InvalidBlock);
+        }
+        let depth = (self.blocks.len() - fork_height.as_index()) as u64;
+        if depth > max_depth {
+            return Err(BtcError::ReorgTooDeep {
+                depth,
+                max: max_depth,
+            });
+        }
+        if branch.len() as u64 <= depth {
+            return Err(BtcError::BranchNotLonger);
+        }
+
+        let mut candidate = self.clone();
+        let disconnected = candidate.disconnect_from(fork_height);
+        for block in branch {
+            candidate.add_block(block)?;
+            candidate.rebuild_utxos();
+        }
+        candidate.restore_transactions(&disconnected);
+        *self = candidate;
+        Ok(disconnected)
+    }
+
+    /// Marks the block `hash` invalid, so neither it nor any chain
+    /// through it is accepted again until `reconsider_block`. If it is
+    /// in the chain, it and the blocks after it are disconnected and
+    /// returned, oldest first, and their transactions go back to the
+    /// mempool if still valid. The genesis block can't be invalidated.
+    pub fn invalidate_block(&mut self, hash: Hash) -> Result<Vec<Block>> {
+        if self
+            .blocks
+            .first()
+            .is_some_and(|genesis| genesis.hash() == hash)
+        {
+            return Err(BtcError::InvalidBlock);
+        }
+        self.invalid_blocks.insert(hash);
+        let Some(index) = self.blocks.iter().position(|block| block.hash() == hash) else {
+            return Ok(vec![]);
+        };
+        let disconnected = self.disconnect_from(BlockHeight::from_index(index));
+        self.restore_transactions(&disconnected);
+        Ok(disconnected)
+    }
+
+    /// Accepts the block `hash` again. The chain isn't changed; the node
+    /// has to be offered the block again to switch back to it. False if
+    /// it wasn't marked invalid.
+    pub fn reconsider_block(&mut self, hash: &Hash) -> bool {
+        self.invalid_blocks.remove(hash)
+    }
+
+    pub fn is_marked_invalid(&self, hash: &Hash) -> bool {
+        self.invalid_blocks.contains(hash)
+    }
+
+    // removes the blocks from `height` on, leaving the chain as it was
+    // when the block before was its tip
+    fn disconnect_from(&mut self, height: BlockHeight) -> Vec<Block> {
+        let disconnected = self.blocks.split_off(height.as_index());
+        // the target the last remaining block left the chain with
+        self.target = match self.blocks.last() {
+            Some(block) => block.header().target(),
+            None => crate::MIN_TARGET,
+        };
+        self.try_adjust_target();
+        self.rebuild_utxos();
+        disconnected
+    }
+
+    fn restore_transactions(&mut self, disconnected: &[Block]) {
+        for transaction in disconnected
+            .iter()
+            .flat_map(|block| block.transactions().iter().skip(1))
+        {
+            // spent again since, or no longer valid
+            let _ = self.add_transaction_to_mempool(transaction.clone());
+        }
+    }
+
+    /// Checks that `block` can extend the chain, by every rule except
+    /// the authority co-signatures.
+    pub fn verify_block(&self, block: &Block) -> Result<()> {
+        self.check_block(block, true)
+    }
+
+    fn check_block(&self, block: &Block, check_signatures: bool) -> Result<()> {
+        if self.invalid_blocks.contains(&block.hash()) {
+            return Err(BtcError::BlockMarkedInvalid);
+        }
+        self.consensus.verify_seal(block, self.block_height())?;
+        let size = block.transactions_size();
+        if size > self.max_block_size {
+            error!("Block too large: {} > {} bytes", size, self.max_block_size);
+            return Err(BtcError::BlockTooLarge {
+                size,
+                max: self.max_block_size,
+            });
+        }
         if self.blocks.is_empty() {
             // if this is the first block, check if the block's previous hash is all zeros
             if *block.header().prev_block_hash() != Hash::zero() {
@@ -73,20 +611,11 @@ impl Blockchain {
                 return Err(crate::error::BtcError::InvalidBlock);
             }
 
-            // check if the block's hash is less than the target
-            if !block
-                .header()
-                .hash()
-                .matches_target(block.header().target())
-            {
-                error!(
-                    "Does not match target: {:x?} >= {:x?}",
-                    block.header().hash(),
-                    block.header().target()
-                );
-                return Err(crate::error::BtcError::InvalidBlock);
+            // a block without even a coinbase has no merkle root to check
+            if block.transactions().is_empty() {
+                error!("Block has no transactions");
+                return Err(crate::error::BtcError::InvalidTransaction);
             }
-
             let calculated_merkle_root = MerkleRoot::calculate(block.transactions());
             if *block.header().merkle_root() != calculated_merkle_root {
                 error!(
@@ -106,25 +635,109 @@ impl Blockchain {
                 return Err(crate::error::BtcError::InvalidBlockHeader);
             }
 
-            block.verify_transactions(self.block_height(), &self.utxos)?;
+            block.check_transactions(self.block_height(), &self.utxos, check_signatures)?;
         }
+        Ok(())
+    }
 
-        let block_transactions: HashSet<_> =
-            block.transactions().iter().map(|tx| tx.hash()).collect();
-        self.mempool
-            .retain(|tx| !block_transactions.contains(&tx.1.hash()));
+    /// Panics with a dump of the chainstate if it has been corrupted.
+    /// Only compiled in with the `consensus-checks` feature, as it walks
+    /// the whole chain.
+    #[cfg(feature = "consensus-checks")]
+    pub fn check_invariants(&self) {
+        self.assert_invariants(true);
+    }
 
-        self.blocks.push(block);
+    /// `check_invariants`, leaving out that spent outpoints are gone from
+    /// the UTXO set unless `utxos_rebuilt`.
+    #[cfg(feature = "consensus-checks")]
+    fn assert_invariants(&self, utxos_rebuilt: bool) {
+        let mut violations = vec![];
 
-        self.try_adjust_target();
-        Ok(())
+        // miners may claim less than the subsidy, so the UTXO set can hold
+        // less than was issued, but never more
+        let issued: u64 = (0..self.blocks.len())
+            .map(|index| BlockHeight::from_index(index).block_reward())
+            .sum();
+        let utxo_sum: u64 = self
+            .utxos
+            .values()
+            .map(|entry| entry.output().value())
+            .sum();
+        if utxo_sum > issued {
+            violations.push(format!(
+                "UTXO set holds {utxo_sum} but only {issued} has been issued"
+            ));
+        }
+
+        // no outpoint may be spent twice in the chain, nor stay unspent after
+        let mut spent = HashSet::new();
+        for (height, block) in self.blocks.iter().enumerate() {
+            for input in block.transactions().iter().flat_map(|tx| tx.inputs()) {
+                let outpoint = input.prev_transaction_output_hash();
+                if !spent.insert(*outpoint) {
+                    violations.push(format!(
+                        "outpoint {outpoint:x?} spent twice (again at height {height})"
+                    ));
+                }
+                if utxos_rebuilt && self.utxos.contains_key(outpoint) {
+                    violations.push(format!(
+                        "outpoint {outpoint:x?} spent at height {height} is still in the UTXO set"
+                    ));
+                }
+            }
+        }
+
+        // a marked UTXO must be spent by the one mempool transaction it
+        // names
+        let mut mempool_spent = HashMap::new();
+        for (_, transaction) in &self.mempool {
+            for input in transaction.inputs() {
+                let outpoint = input.prev_transaction_output_hash();
+                if mempool_spent
+                    .insert(*outpoint, transaction.hash())
+                    .is_some()
+                {
+                    violations.push(format!(
+                        "outpoint {outpoint:x?} spent by more than one mempool transaction"
+                    ));
+                }
+            }
+        }
+        for (hash, entry) in &self.utxos {
+            if let Some(spender) = entry.spender()
+                && mempool_spent.get(hash) != Some(&spender)
+            {
+                violations.push(format!(
+                    "UTXO {hash:x?} is marked {:?} but that mempool transaction doesn't spend it",
+                    entry.state()
+                ));
+            }
+        }
+
+        if !violations.is_empty() {
+            panic!(
+                "consensus invariants violated at height {} (tip {:x?}, {} UTXOs, \
+                 {} mempool transactions, target {}):\n  {}",
+                self.block_height(),
+                self.tip().map(|tip| tip.hash),
+                self.utxos.len(),
+                self.mempool.len(),
+                self.target,
+                violations.join("\n  ")
+            );
+        }
     }
 
     pub fn try_adjust_target(&mut self) {
         if self.blocks.is_empty() {
             return;
         }
-        if self.blocks.len() % crate::DIFFICULTY_UPDATE_INTERVAL as usize != 0 {
+        if !self
+            .blocks
+            .len()
+            .is_multiple_of(crate::DIFFICULTY_UPDATE_INTERVAL as usize)
+        {
             return;
         }
         // measure the time it took to mine the last
@@ -171,89 +784,361 @@ impl Blockchain {
     }
 
     pub fn rebuild_utxos(&mut self) {
+        self.rebuild_utxo_set();
+        #[cfg(feature = "consensus-checks")]
+        self.check_invariants();
+    }
+
+    fn rebuild_utxo_set(&mut self) {
+        // from scratch, so outputs of disconnected blocks don't linger
+        self.utxos.clear();
         for block in &self.blocks {
             for tx in block.transactions() {
                 // Remove spent UTXOs
                 for input in tx.inputs() {
                     self.utxos.remove(input.prev_transaction_output_hash());
                 }
-                // Add new UTXOs
-                self.utxos
-                    .extend(tx.outputs().iter().map(|o| (tx.hash(), (false, o.clone()))));
+                // Add new UTXOs, under the hash inputs spend them by
+                self.utxos.extend(
+                    tx.outputs()
+                        .iter()
+                        .map(|o| (o.hash(), UtxoEntry::new(o.clone()))),
+                );
+            }
+        }
+        // and the marks of the mempool transactions on them again
+        let mempool = std::mem::take(&mut self.mempool);
+        for (_, transaction) in &mempool {
+            self.mark_spent_in_mempool(transaction);
+        }
+        self.mempool = mempool;
+        self.utxo_filter = Some(UtxoFilter::from_outpoints(self.utxos.keys()));
+    }
+
+    /// False only if `outpoint` is certainly not in the UTXO set. Until
+    /// the filter is built every outpoint may be.
+    fn may_be_unspent(&self, outpoint: &Hash) -> bool {
+        self.utxo_filter
+            .as_ref()
+            .is_none_or(|filter| filter.may_contain(outpoint))
+    }
+
+    /// The output `outpoint` refers to, if it is unspent in the chain or
+    /// created by a mempool transaction.
+    fn spendable_output(&self, outpoint: &Hash) -> Option<&TransactionOutput> {
+        if self.may_be_unspent(outpoint)
+            && let Some(entry) = self.utxos.get(outpoint)
+        {
+            return Some(entry.output());
+        }
+        self.mempool
+            .iter()
+            .flat_map(|(_, transaction)| transaction.outputs())
+            .find(|output| output.hash() == *outpoint)
+    }
+
+    // output hash -> hash of the mempool transaction creating it
+    fn mempool_outputs(&self) -> HashMap<Hash, Hash> {
+        self.mempool
+            .iter()
+            .flat_map(|(_, transaction)| {
+                let hash = transaction.hash();
+                transaction
+                    .outputs()
+                    .iter()
+                    .map(move |output| (output.hash(), hash))
+            })
+            .collect()
+    }
+
+    /// The mempool transactions `transaction` spends outputs of, directly
+    /// or through other mempool transactions, parents before children.
+    pub fn mempool_ancestors(&self, transaction: &Transaction) -> Vec<&Transaction> {
+        let creators = self.mempool_outputs();
+        let by_hash: HashMap<Hash, &Transaction> = self
+            .mempool
+            .iter()
+            .map(|(_, transaction)| (transaction.hash(), transaction))
+            .collect();
+        // depth first, so every transaction comes after its own parents
+        fn visit<'a>(
+            transaction: &Transaction,
+            creators: &HashMap<Hash, Hash>,
+            by_hash: &HashMap<Hash, &'a Transaction>,
+            seen: &mut HashSet<Hash>,
+            ancestors: &mut Vec<&'a Transaction>,
+        ) {
+            for input in transaction.inputs() {
+                if let Some(parent) = creators.get(input.prev_transaction_output_hash())
+                    && seen.insert(*parent)
+                {
+                    let parent = by_hash[parent];
+                    visit(parent, creators, by_hash, seen, ancestors);
+                    ancestors.push(parent);
+                }
+            }
+        }
+        let mut ancestors = vec![];
+        visit(
+            transaction,
+            &creators,
+            &by_hash,
+            &mut HashSet::new(),
+            &mut ancestors,
+        );
+        ancestors
+    }
+
+    // `hashes` and every mempool transaction spending their outputs,
+    // directly or not
+    fn with_mempool_descendants(&self, mut hashes: HashSet<Hash>) -> HashSet<Hash> {
+        let creators = self.mempool_outputs();
+        loop {
+            let children: Vec<Hash> = self
+                .mempool
+                .iter()
+                .map(|(_, transaction)| transaction)
+                .filter(|transaction| {
+                    !hashes.contains(&transaction.hash())
+                        && transaction.inputs().iter().any(|input| {
+                            creators
+                                .get(input.prev_transaction_output_hash())
+                                .is_some_and(|parent| hashes.contains(parent))
+                        })
+                })
+                .map(|transaction| transaction.hash())
+                .collect();
+            if children.is_empty() {
+                return hashes;
+            }
+            hashes.extend(children);
+        }
+    }
+
+    // marks the UTXOs `transaction` spends as spent by it
+    fn mark_spent_in_mempool(&mut self, transaction: &Transaction) {
+        let hash = transaction.hash();
+        for input in transaction.inputs() {
+            if let Some(entry) = self.utxos.get_mut(input.prev_transaction_output_hash())
+                && entry.spend_in_mempool(hash).is_err()
+            {
+                error!(
+                    "UTXO {:x?} already spent by another mempool transaction",
+                    input.prev_transaction_output_hash()
+                );
+            }
+        }
+    }
+
+    // takes the transactions in `hashes` out of the mempool, in mempool
+    // order, and unmarks the UTXOs they spent
+    fn remove_from_mempool(&mut self, hashes: &HashSet<Hash>) -> Vec<Transaction> {
+        let mut removed = vec![];
+        self.mempool.retain(|(_, transaction)| {
+            if hashes.contains(&transaction.hash()) {
+                removed.push(transaction.clone());
+                false
+            } else {
+                true
+            }
+        });
+        for transaction in &removed {
+            let hash = transaction.hash();
+            for input in transaction.inputs() {
+                if let Some(entry) = self.utxos.get_mut(input.prev_transaction_output_hash()) {
+                    entry.release(&hash);
+                }
             }
         }
+        removed
+    }
+
+    /// Every mempool transaction with the fee and size of its ancestor
+    /// package, highest package fee rate first.
+    pub fn mempool_entries(&self) -> Vec<MempoolEntry> {
+        let mut entries: Vec<MempoolEntry> = self
+            .mempool
+            .iter()
+            .map(|(_, transaction)| {
+                let fee = self.transaction_fee(transaction).unwrap_or(0);
+                let size = transaction.size();
+                let ancestors = self.mempool_ancestors(transaction);
+                MempoolEntry {
+                    hash: transaction.hash(),
+                    fee,
+                    size,
+                    ancestors: ancestors.len(),
+                    package_fee: ancestors
+                        .iter()
+                        .map(|ancestor| self.transaction_fee(ancestor).unwrap_or(0))
+                        .sum::<u64>()
+                        + fee,
+                    package_size: ancestors
+                        .iter()
+                        .map(|ancestor| ancestor.size())
+                        .sum::<usize>()
+                        + size,
+                }
+            })
+            .collect();
+        // compared as fractions, so equal rates keep mempool order
+        entries.sort_by(|a, b| {
+            (b.package_fee as u128 * a.package_size as u128)
+                .cmp(&(a.package_fee as u128 * b.package_size as u128))
+        });
+        entries
+    }
+
+    /// The mempool bucketed by package fee rate.
+    pub fn fee_histogram(&self) -> FeeHistogram {
+        FeeHistogram::new(&self.mempool_entries())
+    }
+
+    /// What the risk of the mempool transaction `hash` being double spent
+    /// depends on here, with how many of `peers_asked` have it; `None` if
+    /// it isn't in the mempool. The blocks holding the outputs it spends
+    /// are looked up from the tip down.
+    pub fn zero_conf_risk(
+        &self,
+        hash: &Hash,
+        peers_asked: usize,
+        peers_with_transaction: usize,
+    ) -> Option<ZeroConfRisk> {
+        let (_, transaction) = self
+            .mempool
+            .iter()
+            .find(|(_, transaction)| transaction.hash() == *hash)?;
+        let entries = self.mempool_entries();
+        let position = entries.iter().position(|entry| entry.hash == *hash)?;
+        let bytes_ahead: usize = entries[..position].iter().map(|entry| entry.size).sum();
+        let confirmed: HashSet<Hash> = transaction
+            .inputs()
+            .iter()
+            .map(|input| *input.prev_transaction_output_hash())
+            .filter(|outpoint| self.utxos.contains_key(outpoint))
+            .collect();
+        let unconfirmed_inputs = transaction.inputs().len() - confirmed.len();
+        let min_input_confirmations = if unconfirmed_inputs > 0 {
+            Confirmations::NONE
+        } else {
+            // the first block down from the tip creating one is the
+            // least confirmed
+            self.iter_blocks_in(..)
+                .rev()
+                .find(|(_, block)| {
+                    block
+                        .transactions()
+                        .iter()
+                        .flat_map(Transaction::outputs)
+                        .any(|output| confirmed.contains(&output.hash()))
+                })
+                .map_or(Confirmations::NONE, |(height, _)| {
+                    self.confirmations(height)
+                })
+        };
+        Some(ZeroConfRisk {
+            hash: *hash,
+            fee_rate: entries[position].package_fee_rate(),
+            bytes_ahead,
+            blocks_ahead: bytes_ahead as f64 / self.max_block_size.max(1) as f64,
+            unconfirmed_inputs,
+            min_input_confirmations,
+            peers_asked,
+            peers_with_transaction,
+        })
+    }
+
+    /// What a transaction leaves to the miner: its inputs minus its outputs.
+    /// None if an input is neither in the UTXO set nor created by a mempool
+    /// transaction, or the outputs exceed the inputs.
+    pub fn transaction_fee(&self, transaction: &Transaction) -> Option<u64> {
+        let mut all_inputs = 0u64;
+        for input in transaction.inputs() {
+            let output = self.spendable_output(input.prev_transaction_output_hash())?;
+            all_inputs = all_inputs.checked_add(output.value())?;
+        }
+        let all_outputs = transaction
+            .outputs()
+            .iter()
+            .map(|output| output.value())
+            .sum::<u64>();
+        all_inputs.checked_sub(all_outputs)
+    }
+
+    /// The public keys `transaction` spends outputs of, unspent in the
+    /// chain or created in the mempool: its senders.
+    pub fn transaction_senders(&self, transaction: &Transaction) -> Vec<PublicKey> {
+        let mut senders: Vec<PublicKey> = transaction
+            .inputs()
+            .iter()
+            .filter_map(|input| self.spendable_output(input.prev_transaction_output_hash()))
+            .map(|output| output.pubkey().clone())
+            .collect();
+        senders.sort();
+        senders.dedup();
+        senders
+    }
+
+    /// Mempool transactions spending outputs paying `pubkey`.
+    pub fn unconfirmed_from(&self, pubkey: &PublicKey) -> usize {
+        self.mempool
+            .iter()
+            .filter(|(_, transaction)| self.transaction_senders(transaction).contains(pubkey))
+            .count()
     }
 
     pub fn add_transaction_to_mempool(&mut self, transaction: Transaction) -> Result<()> {
+        // coinbase tags are reserved for the miner of a block
+        if transaction.coinbase_tag().is_some() {
+            error!("coinbase tag found on a mempool transaction");
+            return Err(BtcError::InvalidTransaction);
+        }
+        if !transaction.data_outputs_within_limits() {
+            error!("data outputs exceed the size or count limit");
+            return Err(BtcError::InvalidTransaction);
+        }
+        // no block could hold it
+        if transaction.size() > self.max_block_size {
+            error!("transaction larger than a block");
+            return Err(BtcError::InvalidTransaction);
+        }
+        // it couldn't be mined in the next block anymore
+        if transaction.is_expired_at(self.block_height()) {
+            error!("transaction expired");
+            return Err(BtcError::ExpiredTransaction);
+        }
+        // a transaction already in the mempool would replace itself and
+        // evict its descendants
+        let hash = transaction.hash();
+        if self
+            .mempool
+            .iter()
+            .any(|(_, pending)| pending.hash() == hash)
+        {
+            return Ok(());
+        }
         // validate transaction before insertion
-        // all inputs must match known UTXOs, and must be unique
+        // all inputs must match known UTXOs or outputs of mempool
+        // transactions, and must be unique
         let mut known_inputs = HashSet::new();
+        let mut all_inputs = 0u64;
         for input in transaction.inputs() {
             let prev_transaction_output = input.prev_transaction_output_hash();
 
-            if !self.utxos.contains_key(prev_transaction_output) {
+            let Some(output) = self.spendable_output(prev_transaction_output) else {
                 error!(
                     "UTXO not found for input {:x?}",
                     input.prev_transaction_output_hash()
                 );
                 return Err(BtcError::InvalidTransaction);
-            }
-            if !known_inputs.insert(prev_transaction_output) {
+            };
+            if !known_inputs.insert(*prev_transaction_output) {
                 error!("duplicate input found");
                 return Err(BtcError::InvalidTransaction);
             }
-        }
-        // check if any of the utxos have the bool mark set to true
-        // and if so, find the transaction that references them
-        // in mempool, remove it, and set all the utxos it references
-        // to false
-        for input in transaction.inputs() {
-            if let Some((true, _)) = self.utxos.get_mut(input.prev_transaction_output_hash()) {
-                // find the transaction that references the UTXO
-                // we are trying to reference
-                let referencing_transaction =
-                    self.mempool.iter().enumerate().find(|(_, transaction)| {
-                        transaction
-                            .1
-                            .outputs()
-                            .iter()
-                            .any(|output| output.hash() == *input.prev_transaction_output_hash())
-                    });
-                // If we have found one, unmark all of its UTXOs
-                if let Some((idx, referencing_transaction)) = referencing_transaction {
-                    for input in referencing_transaction.1.inputs() {
-                        // set all utxos from this transaction to false
-                        self.utxos
-                            .entry(*input.prev_transaction_output_hash())
-                            .and_modify(|(marked, _)| {
-                                *marked = false;
-                            });
-                    }
-                    // remove the transaction from the mempool
-                    self.mempool.remove(idx);
-                } else {
-                    // if, somehow, there is no matching transaction,
-                    // set this utxo to false
-                    self.utxos
-                        .entry(*input.prev_transaction_output_hash())
-                        .and_modify(|(marked, _)| {
-                            *marked = false;
-                        });
-                }
-            }
+            all_inputs = all_inputs.saturating_add(output.value());
         }
         // all inputs must be lower than all outputs
-        let all_inputs = transaction
-            .inputs()
-            .iter()
-            .map(|input| {
-                self.utxos
-                    .get(input.prev_transaction_output_hash())
-                    .expect("BUG: impossible")
-                    .1 // < - - - Look here
-                    .value()
-            })
-            .sum::<u64>();
         let all_outputs = transaction
             .outputs()
             .iter()
@@ -262,67 +1147,107 @@ impl Blockchain {
         if all_inputs < all_outputs {
             return Err(BtcError::InvalidTransaction);
         }
+        // mempool transactions spending any of the same outputs are
+        // replaced, along with the transactions spending their outputs
+        let conflicts = self
+            .mempool
+            .iter()
+            .filter(|(_, pending)| {
+                pending
+                    .inputs()
+                    .iter()
+                    .any(|input| known_inputs.contains(input.prev_transaction_output_hash()))
+            })
+            .map(|(_, pending)| pending.hash())
+            .collect();
+        let replaced = self.with_mempool_descendants(conflicts);
+        let ancestors = self.mempool_ancestors(&transaction);
+        if ancestors
+            .iter()
+            .any(|ancestor| replaced.contains(&ancestor.hash()))
+        {
+            error!("transaction conflicts with its own unconfirmed ancestors");
+            return Err(BtcError::DoubleSpending);
+        }
+        if ancestors.len() > crate::MAX_MEMPOOL_ANCESTORS {
+            error!(
+                "transaction has more than {} unconfirmed ancestors",
+                crate::MAX_MEMPOOL_ANCESTORS
+            );
+            return Err(BtcError::InvalidTransaction);
+        }
+        self.remove_from_mempool(&replaced);
+        self.mark_spent_in_mempool(&transaction);
         self.mempool.push((Utc::now(), transaction));
         // sort by miner fee descending
-        self.mempool.sort_by_key(|transaction| {
-            let all_inputs = transaction
-                .1
-                .inputs()
-                .iter()
-                .map(|input| {
-                    self.utxos
-                        .get(input.prev_transaction_output_hash())
-                        .expect("BUG: impossible")
-                        .1
-                        .value()
-                })
-                .sum::<u64>();
-
-            let all_outputs = transaction
-                .1
-                .outputs()
-                .iter()
-                .map(|output| output.value())
-                .sum::<u64>();
+        let fees: HashMap<Hash, u64> = self
+            .mempool
+            .iter()
+            .map(|(_, transaction)| {
+                let fee = self.transaction_fee(transaction).unwrap_or(0);
+                (transaction.hash(), fee)
+            })
+            .collect();
+        self.mempool
+            .sort_by_key(|(_, transaction)| std::cmp::Reverse(fees[&transaction.hash()]));
 
-            let miner_fee = all_inputs - all_outputs;
-            std::cmp::Reverse(miner_fee)
-        });
+        Ok(())
+    }
 
+    /// Adds `transaction` to the mempool like `add_transaction_to_mempool`,
+    /// in the priority lane: it isn't evicted for its age, nor are the
+    /// unconfirmed transactions it spends from, and templates include it
+    /// before any other whatever its fee (`BlockBuilder::priority`). Meant
+    /// for the operator's own transactions. A transaction already in the
+    /// mempool is moved to the lane.
+    pub fn add_priority_transaction_to_mempool(&mut self, transaction: Transaction) -> Result<()> {
+        let hash = transaction.hash();
+        self.add_transaction_to_mempool(transaction)?;
+        self.mempool_priority.insert(hash);
         Ok(())
     }
 
-    pub fn cleanup_mempool(&mut self) {
+    /// Evicts transactions that can't be mined in the next block anymore,
+    /// and those without an expiry height older than `max_age_secs`
+    /// unless the priority lane needs them, along with the transactions
+    /// spending their outputs, and returns them.
+    pub fn cleanup_mempool(&mut self, max_age_secs: u64) -> Vec<Transaction> {
         let now = Utc::now();
-        let mut utxo_hashes_to_unmark: Vec<Hash> = vec![];
-
-        self.mempool.retain(|(timestamp, transaction)| {
-            let age = (now - *timestamp).num_seconds() as u64;
-            if age > crate::MAX_MEMPOOL_TX_AGE {
-                // collect all utxo hashes to unmark
-                utxo_hashes_to_unmark.extend(
-                    transaction
-                        .inputs()
+        let next_height = self.block_height();
+        let pending: HashSet<Hash> = self
+            .mempool
+            .iter()
+            .map(|(_, transaction)| transaction.hash())
+            .collect();
+        self.mempool_priority.retain(|hash| pending.contains(hash));
+        let mut retained = self.mempool_priority.clone();
+        for (_, transaction) in &self.mempool {
+            if self.mempool_priority.contains(&transaction.hash()) {
+                retained.extend(
+                    self.mempool_ancestors(transaction)
                         .iter()
-                        .map(|input| *input.prev_transaction_output_hash()),
+                        .map(|ancestor| ancestor.hash()),
                 );
-                false
-            } else {
-                true
             }
-        });
-        // unmark all of the UTXOs
-        for hash in utxo_hashes_to_unmark {
-            self.utxos.entry(hash).and_modify(|(marked, _)| {
-                *marked = false;
-            });
         }
+        let stale = self
+            .mempool
+            .iter()
+            .filter(|(timestamp, transaction)| {
+                let age = (now - *timestamp).num_seconds() as u64;
+                match transaction.expires_at_height() {
+                    Some(_) => transaction.is_expired_at(next_height),
+                    None => age > max_age_secs && !retained.contains(&transaction.hash()),
+                }
+            })
+            .map(|(_, transaction)| transaction.hash())
+            .collect();
+        let evicted = self.with_mempool_descendants(stale);
+        self.remove_from_mempool(&evicted)
     }
 
     pub fn calculate_block_reward(&self) -> u64 {
-        let block_height = self.block_height();
-        let halvings = block_height / crate::HALVING_INTERVAL;
-        (INITIAL_REWARD * 10u64.pow(8)) >> halvings
+        self.block_height().block_reward()
     }
 }
 
@@ -333,6 +1258,12 @@ impl Default for Blockchain {
             target: crate::MIN_TARGET,
             blocks: vec![],
             mempool: vec![],
+            mempool_priority: HashSet::new(),
+            utxo_filter: None,
+            authority: None,
+            consensus: default_consensus(),
+            invalid_blocks: HashSet::new(),
+            max_block_size: default_max_block_size(),
         }
     }
 }
@@ -343,74 +1274,282 @@ impl Saveable for Blockchain {
             .map_err(|_| IoError::new(IoErrorKind::InvalidData, "Failed to deserialize Blockchain"))
     }
 
-    fn save<O: Write>(&self, writer: O) -> IoResult<()> {
-        ciborium::ser::into_writer(self, writer)
-            .map_err(|_| IoError::new(IoErrorKind::InvalidData, "Failed to serialize Blockchain"))
+    fn save<O: Write>(&self, writer: O) -> IoResult<()> {
+        ciborium::ser::into_writer(self, writer)
+            .map_err(|_| IoError::new(IoErrorKind::InvalidData, "Failed to serialize Blockchain"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        MIN_TARGET,
+        crypto::{PrivateKey, Signature},
+        testutil::{TestBlock, coinbase_transaction, genesis_block},
+        types::{BlockBuilder, TransactionInput, UtxoState},
+    };
+    use chrono::{Duration, Utc};
+    use uuid::Uuid;
+
+    #[test]
+    fn test_blockchain_new() {
+        let blockchain = Blockchain::default();
+        assert_eq!(blockchain.block_height(), BlockHeight::GENESIS);
+        assert_eq!(blockchain.blocks().len(), 0);
+        assert_eq!(blockchain.mempool().len(), 0);
+    }
+
+    #[test]
+    fn test_blockchain_add_genesis_block() {
+        let mut blockchain = Blockchain::default();
+        let block = genesis_block();
+
+        let result = blockchain.add_block(block);
+        assert!(result.is_ok());
+        assert_eq!(blockchain.block_height(), BlockHeight::new(1));
+    }
+
+    #[test]
+    fn test_blockchain_authority_signatures() {
+        let authority_key = PrivateKey::default();
+        let authority =
+            AuthoritySet::new(vec![authority_key.public_key()], 1, BlockHeight::GENESIS).unwrap();
+        let mut blockchain = Blockchain::default();
+        blockchain.set_authority(Some(authority));
+
+        let mut block = genesis_block();
+        assert!(blockchain.verify_block(&block).is_ok());
+        assert!(matches!(
+            blockchain.add_block(block.clone()),
+            Err(BtcError::MissingAuthoritySignatures { .. })
+        ));
+        block.cosign(&authority_key);
+        blockchain.add_block(block).unwrap();
+        assert_eq!(blockchain.block_height(), BlockHeight::new(1));
+    }
+
+    #[test]
+    fn test_blockchain_proof_of_authority() {
+        use crate::consensus::ProofOfAuthority;
+        let validator = PrivateKey::default();
+        let engine = ProofOfAuthority::new(vec![validator.public_key()]).unwrap();
+        let mut blockchain = Blockchain::default();
+        blockchain.set_consensus(Arc::new(engine));
+        assert_eq!(blockchain.consensus().name(), "proof-of-authority");
+
+        // even the genesis block has to be signed rather than mined
+        let mut block = genesis_block();
+        assert!(matches!(
+            blockchain.add_block(block.clone()),
+            Err(BtcError::MissingValidatorSignature)
+        ));
+        block.cosign(&validator);
+        blockchain.add_block(block).unwrap();
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::{
-        MIN_TARGET,
-        crypto::{PrivateKey, Signature},
-        types::TransactionInput,
-    };
-    use chrono::{Duration, Utc};
-    use uuid::Uuid;
+    fn extend(blockchain: &mut Blockchain, blocks: usize) {
+        for _ in 0..blocks {
+            let block = BlockBuilder::on(blockchain).build(PrivateKey::default().public_key());
+            blockchain.add_block(block).unwrap();
+            blockchain.rebuild_utxos();
+        }
+    }
 
-    fn create_coinbase_transaction(value: u64) -> Transaction {
-        let private_key = PrivateKey::default();
-        Transaction::new(
-            vec![],
+    #[test]
+    fn test_blockchain_add_block_with_verified_signatures() {
+        let owner = PrivateKey::from_seed(b"owner");
+        let mut blockchain = Blockchain::default();
+        let genesis = BlockBuilder::on(&blockchain).build(owner.public_key());
+        blockchain.add_block(genesis).unwrap();
+        blockchain.rebuild_utxos();
+        let (outpoint, output) = blockchain.utxos().into_iter().next().unwrap();
+        let spend = Transaction::new(
+            vec![TransactionInput::new(
+                outpoint,
+                Signature::sign_output(&outpoint, &owner),
+            )],
             vec![TransactionOutput::new(
-                value,
+                output.value(),
                 Uuid::new_v4(),
-                private_key.public_key(),
+                output.pubkey().clone(),
             )],
-        )
-    }
+        );
+        let mut builder = BlockBuilder::on(&blockchain);
+        assert!(builder.add_transaction(spend, 0));
+        let block = builder.build(PrivateKey::default().public_key());
+
+        let find_key = |hash: &Hash| blockchain.utxo(hash).map(|output| output.pubkey().clone());
+        assert_eq!(block.verify_signatures(find_key), Some(true));
+        // every other rule still applies
+        let mut stale = blockchain.clone();
+        stale.add_block(block.clone()).unwrap();
+        assert!(
+            stale
+                .add_block_with_verified_signatures(block.clone())
+                .is_err()
+        );
 
-    fn create_genesis_block() -> Block {
-        let transactions = vec![create_coinbase_transaction(5000000000)];
-        let merkle_root = MerkleRoot::calculate(&transactions);
-        let header =
-            crate::types::BlockHeader::new(Utc::now(), 0, Hash::zero(), merkle_root, MIN_TARGET);
-        Block::new(header, transactions)
+        blockchain
+            .add_block_with_verified_signatures(block)
+            .unwrap();
+        assert_eq!(blockchain.blocks().len(), 2);
     }
 
-    fn create_mined_genesis_block() -> Block {
-        let transactions = vec![create_coinbase_transaction(5000000000)];
-        let merkle_root = MerkleRoot::calculate(&transactions);
-        let mut header =
-            crate::types::BlockHeader::new(Utc::now(), 0, Hash::zero(), merkle_root, MIN_TARGET);
-        header.mine(1000000);
-        Block::new(header, transactions)
+    #[test]
+    fn test_blockchain_reorganize() {
+        let mut blockchain = Blockchain::default();
+        extend(&mut blockchain, 3);
+        let mut fork = blockchain.clone();
+        extend(&mut blockchain, 2);
+        extend(&mut fork, 3);
+        let ours: Vec<Hash> = blockchain.blocks().iter().map(Block::hash).collect();
+        let branch = fork.blocks()[3..].to_vec();
+
+        // a branch only as long as ours doesn't replace it
+        assert!(matches!(
+            blockchain.reorganize(BlockHeight::new(3), branch[..2].to_vec(), 10),
+            Err(BtcError::BranchNotLonger)
+        ));
+        assert!(matches!(
+            blockchain.reorganize(BlockHeight::new(3), branch.clone(), 1),
+            Err(BtcError::ReorgTooDeep { depth: 2, max: 1 })
+        ));
+        let unchanged: Vec<Hash> = blockchain.blocks().iter().map(Block::hash).collect();
+        assert_eq!(unchanged, ours);
+
+        let disconnected = blockchain
+            .reorganize(BlockHeight::new(3), branch, 2)
+            .unwrap();
+        assert_eq!(
+            disconnected.iter().map(Block::hash).collect::<Vec<_>>(),
+            ours[3..]
+        );
+        assert_eq!(blockchain.tip().unwrap().hash, fork.tip().unwrap().hash);
+        assert_eq!(blockchain.utxos().len(), fork.utxos().len());
     }
 
     #[test]
-    fn test_blockchain_new() {
-        let blockchain = Blockchain::default();
-        assert_eq!(blockchain.block_height(), 0);
-        assert_eq!(blockchain.blocks().len(), 0);
-        assert_eq!(blockchain.mempool().len(), 0);
+    fn test_blockchain_reorganize_keeps_chain_on_invalid_branch() {
+        let mut blockchain = Blockchain::default();
+        extend(&mut blockchain, 2);
+        let mut fork = blockchain.clone();
+        extend(&mut blockchain, 1);
+        extend(&mut fork, 2);
+        let tip = blockchain.tip();
+        let mut branch = fork.blocks()[2..].to_vec();
+        // the second branch block no longer follows the first
+        branch.swap(0, 1);
+        assert!(
+            blockchain
+                .reorganize(BlockHeight::new(2), branch, 10)
+                .is_err()
+        );
+        assert_eq!(blockchain.tip(), tip);
     }
 
     #[test]
-    fn test_blockchain_add_genesis_block() {
+    fn test_blockchain_analytics() {
+        let key = PrivateKey::default();
+        let start = Utc::now() - Duration::days(400);
         let mut blockchain = Blockchain::default();
-        let block = create_genesis_block();
+        let genesis = BlockBuilder::on(&blockchain)
+            .timestamp(start)
+            .build(key.public_key());
+        let reward = genesis.transactions()[0].outputs()[0].clone();
+        blockchain.add_block(genesis).unwrap();
+        blockchain.rebuild_utxos();
+        // the reward moves ten days later
+        let spend = Transaction::new(
+            vec![TransactionInput::new(
+                reward.hash(),
+                Signature::sign_output(&reward.hash(), &key),
+            )],
+            vec![TransactionOutput::new(
+                reward.value(),
+                Uuid::new_v4(),
+                key.public_key(),
+            )],
+        );
+        let mut builder = BlockBuilder::on(&blockchain).timestamp(start + Duration::days(10));
+        assert!(builder.add_transaction(spend, 0));
+        blockchain
+            .add_block(builder.build(key.public_key()))
+            .unwrap();
+        blockchain.rebuild_utxos();
+        extend(&mut blockchain, 1);
+
+        let analytics = blockchain.analytics(2);
+        assert_eq!(analytics.height, BlockHeight::new(3));
+        assert_eq!(analytics.blocks.len(), 2);
+        assert_eq!(analytics.blocks[0].height, BlockHeight::new(1));
+        assert_eq!(analytics.blocks[0].value_moved, reward.value());
+        let coins = reward.value() as f64 / 100_000_000.0;
+        assert!((analytics.blocks[0].coin_days_destroyed - coins * 10.0).abs() < 1e-6);
+        assert_eq!(analytics.blocks[1].coin_days_destroyed, 0.0);
+        assert_eq!(
+            analytics.coin_days_destroyed(),
+            analytics.blocks[0].coin_days_destroyed
+        );
 
-        let result = blockchain.add_block(block);
-        assert!(result.is_ok());
-        assert_eq!(blockchain.block_height(), 1);
+        // the newest coinbase is the only young output, the other two
+        // are over a year old
+        let outputs: Vec<u64> = analytics
+            .utxo_age
+            .iter()
+            .map(|bucket| bucket.outputs)
+            .collect();
+        assert_eq!(outputs, vec![1, 0, 0, 0, 0, 2]);
+        assert_eq!(analytics.utxo_age[5].max_days, None);
+        let supply: u64 = analytics.utxo_age.iter().map(|bucket| bucket.value).sum();
+        assert_eq!(supply, blockchain.stats(0).total_supply);
+        assert_eq!(analytics.velocity, reward.value() as f64 / supply as f64);
+    }
+
+    #[test]
+    fn test_blockchain_invalidate_block() {
+        let mut blockchain = Blockchain::default();
+        extend(&mut blockchain, 1);
+        let mut fork = blockchain.clone();
+        extend(&mut blockchain, 2);
+        extend(&mut fork, 1);
+        let genesis = blockchain.blocks()[0].hash();
+        let bad = blockchain.blocks()[1].clone();
+
+        assert!(matches!(
+            blockchain.invalidate_block(genesis),
+            Err(BtcError::InvalidBlock)
+        ));
+        let disconnected = blockchain.invalidate_block(bad.hash()).unwrap();
+        assert_eq!(disconnected.len(), 2);
+        assert_eq!(disconnected[0].hash(), bad.hash());
+        assert_eq!(blockchain.tip().unwrap().hash, genesis);
+        assert_eq!(blockchain.utxos().len(), 1);
+        assert!(matches!(
+            blockchain.add_block(bad.clone()),
+            Err(BtcError::BlockMarkedInvalid)
+        ));
+
+        // the other fork is shorter than the invalidated one, yet taken
+        blockchain
+            .reorganize(BlockHeight::new(1), fork.blocks()[1..].to_vec(), 10)
+            .unwrap();
+        assert_eq!(blockchain.tip(), fork.tip());
+
+        // the mark survives a save
+        let mut bytes = vec![];
+        blockchain.save(&mut bytes).unwrap();
+        let mut loaded = Blockchain::load(bytes.as_slice()).unwrap();
+        assert!(loaded.is_marked_invalid(&bad.hash()));
+        assert!(loaded.reconsider_block(&bad.hash()));
+        assert!(!loaded.reconsider_block(&bad.hash()));
+        assert!(!loaded.is_marked_invalid(&bad.hash()));
     }
 
     #[test]
     fn test_blockchain_reject_invalid_prev_hash() {
         let mut blockchain = Blockchain::default();
-        let transactions = vec![create_coinbase_transaction(5000000000)];
+        let transactions = vec![coinbase_transaction(5000000000)];
         let merkle_root = MerkleRoot::calculate(&transactions);
         let header = crate::types::BlockHeader::new(
             Utc::now(),
@@ -428,28 +1567,52 @@ mod tests {
     #[test]
     fn test_blockchain_reject_invalid_target() {
         let mut blockchain = Blockchain::default();
-        blockchain.add_block(create_mined_genesis_block()).unwrap();
+        blockchain
+            .add_block(TestBlock::new().mined().build())
+            .unwrap();
 
-        let transactions = vec![create_coinbase_transaction(5000000000)];
+        let transactions = vec![coinbase_transaction(5000000000)];
         let merkle_root = MerkleRoot::calculate(&transactions);
         let last_hash = blockchain.blocks().last().unwrap().header().hash();
 
-        // Create block with invalid nonce (won't match target)
+        // Create block with a target no hash meets
         let header =
-            crate::types::BlockHeader::new(Utc::now(), 0, last_hash, merkle_root, MIN_TARGET);
+            crate::types::BlockHeader::new(Utc::now(), 0, last_hash, merkle_root, U256::zero());
         let block = Block::new(header, transactions);
 
         let result = blockchain.add_block(block);
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_blockchain_accepts_coinbase_reward_after_genesis() {
+        let mut blockchain = Blockchain::default();
+        blockchain
+            .add_block(TestBlock::new().mined().build())
+            .unwrap();
+        blockchain.rebuild_utxos();
+
+        // the coinbase has no inputs to cover its outputs
+        let transactions = vec![coinbase_transaction(5000000000)];
+        let merkle_root = MerkleRoot::calculate(&transactions);
+        let last_hash = blockchain.blocks().last().unwrap().header().hash();
+        let header =
+            crate::types::BlockHeader::new(Utc::now(), 0, last_hash, merkle_root, MIN_TARGET);
+        blockchain
+            .add_block(Block::new(header, transactions))
+            .unwrap();
+        assert_eq!(blockchain.block_height(), BlockHeight::new(2));
+    }
+
     #[test]
     fn test_blockchain_reject_invalid_merkle_root() {
         let mut blockchain = Blockchain::default();
-        blockchain.add_block(create_mined_genesis_block()).unwrap();
+        blockchain
+            .add_block(TestBlock::new().mined().build())
+            .unwrap();
 
-        let transactions = vec![create_coinbase_transaction(5000000000)];
-        let wrong_merkle = MerkleRoot::calculate(&[create_coinbase_transaction(1000)]);
+        let transactions = vec![coinbase_transaction(5000000000)];
+        let wrong_merkle = MerkleRoot::calculate(&[coinbase_transaction(1000)]);
         let last_hash = blockchain.blocks().last().unwrap().header().hash();
 
         let mut header =
@@ -464,11 +1627,11 @@ mod tests {
     #[test]
     fn test_blockchain_reject_invalid_timestamp() {
         let mut blockchain = Blockchain::default();
-        let first_block = create_mined_genesis_block();
+        let first_block = TestBlock::new().mined().build();
         let first_timestamp = first_block.header().timestamp();
         blockchain.add_block(first_block).unwrap();
 
-        let transactions = vec![create_coinbase_transaction(5000000000)];
+        let transactions = vec![coinbase_transaction(5000000000)];
         let merkle_root = MerkleRoot::calculate(&transactions);
         let last_hash = blockchain.blocks().last().unwrap().header().hash();
 
@@ -524,22 +1687,46 @@ mod tests {
     #[test]
     fn test_blockchain_rebuild_utxos_with_blocks() {
         let mut blockchain = Blockchain::default();
-        blockchain.add_block(create_genesis_block()).unwrap();
+        blockchain.add_block(genesis_block()).unwrap();
 
         // Clear utxos
         blockchain.utxos.clear();
         assert_eq!(blockchain.utxos().len(), 0);
 
         // Rebuild
+        let empty = blockchain.utxo_set_hash();
         blockchain.rebuild_utxos();
-        assert!(blockchain.utxos().len() > 0);
+        assert!(!blockchain.utxos().is_empty());
+        assert_ne!(blockchain.utxo_set_hash(), empty);
+        let filter = blockchain.utxo_filter.as_ref().unwrap();
+        assert!(blockchain.utxos.keys().all(|hash| filter.may_contain(hash)));
     }
 
     #[test]
     fn test_blockchain_cleanup_mempool() {
         let mut blockchain = Blockchain::default();
-        blockchain.cleanup_mempool();
+        assert!(
+            blockchain
+                .cleanup_mempool(crate::MAX_MEMPOOL_TX_AGE)
+                .is_empty()
+        );
         assert_eq!(blockchain.mempool().len(), 0);
+
+        let stale = Utc::now() - Duration::seconds(crate::MAX_MEMPOOL_TX_AGE as i64 + 1);
+        blockchain.mempool.push((stale, coinbase_transaction(1)));
+        blockchain
+            .mempool
+            .push((Utc::now(), coinbase_transaction(2)));
+        let evicted = blockchain.cleanup_mempool(crate::MAX_MEMPOOL_TX_AGE);
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].outputs()[0].value(), 1);
+        assert_eq!(blockchain.mempool().len(), 1);
+
+        // a shorter age limit evicts younger transactions
+        blockchain.mempool[0].0 = Utc::now() - Duration::seconds(61);
+        assert!(blockchain.cleanup_mempool(120).is_empty());
+        assert_eq!(blockchain.cleanup_mempool(60).len(), 1);
+        assert!(blockchain.mempool().is_empty());
     }
 
     #[test]
@@ -566,11 +1753,11 @@ mod tests {
     #[test]
     fn test_blockchain_add_transaction_duplicate_inputs() {
         let mut blockchain = Blockchain::default();
-        blockchain.add_block(create_genesis_block()).unwrap();
+        blockchain.add_block(genesis_block()).unwrap();
         blockchain.rebuild_utxos();
 
         let private_key = PrivateKey::default();
-        let utxo_hash = blockchain.utxos().keys().next().unwrap().clone();
+        let utxo_hash = *blockchain.utxos().keys().next().unwrap();
         let signature = Signature::sign_output(&utxo_hash, &private_key);
 
         let tx = Transaction::new(
@@ -592,17 +1779,17 @@ mod tests {
     #[test]
     fn test_blockchain_add_transaction_invalid_value() {
         let mut blockchain = Blockchain::default();
-        blockchain.add_block(create_genesis_block()).unwrap();
+        blockchain.add_block(genesis_block()).unwrap();
         blockchain.rebuild_utxos();
 
         let private_key = PrivateKey::default();
         let utxos = blockchain.utxos();
         let (utxo_hash, utxo_output) = utxos.iter().next().unwrap();
-        let signature = Signature::sign_output(&utxo_hash, &private_key);
+        let signature = Signature::sign_output(utxo_hash, &private_key);
 
         // Try to spend more than input value
         let tx = Transaction::new(
-            vec![TransactionInput::new(utxo_hash.clone(), signature)],
+            vec![TransactionInput::new(*utxo_hash, signature)],
             vec![TransactionOutput::new(
                 utxo_output.value() + 1000,
                 Uuid::new_v4(),
@@ -617,16 +1804,16 @@ mod tests {
     #[test]
     fn test_blockchain_add_valid_transaction_to_mempool() {
         let mut blockchain = Blockchain::default();
-        blockchain.add_block(create_genesis_block()).unwrap();
+        blockchain.add_block(genesis_block()).unwrap();
         blockchain.rebuild_utxos();
 
         let private_key = PrivateKey::default();
         let utxos = blockchain.utxos();
         let (utxo_hash, utxo_output) = utxos.iter().next().unwrap();
-        let signature = Signature::sign_output(&utxo_hash, &private_key);
+        let signature = Signature::sign_output(utxo_hash, &private_key);
 
         let tx = Transaction::new(
-            vec![TransactionInput::new(utxo_hash.clone(), signature)],
+            vec![TransactionInput::new(*utxo_hash, signature)],
             vec![TransactionOutput::new(
                 utxo_output.value() - 100,
                 Uuid::new_v4(),
@@ -639,6 +1826,316 @@ mod tests {
         assert_eq!(blockchain.mempool().len(), 1);
     }
 
+    #[test]
+    fn test_blockchain_unconfirmed_from() {
+        let mut blockchain = Blockchain::default();
+        blockchain.add_block(genesis_block()).unwrap();
+        blockchain.rebuild_utxos();
+
+        let private_key = PrivateKey::default();
+        let (utxo_hash, utxo_output) = blockchain.utxos().into_iter().next().unwrap();
+        let sender = utxo_output.pubkey().clone();
+        let recipient = PrivateKey::from_seed(b"recipient").public_key();
+        let tx = Transaction::new(
+            vec![TransactionInput::new(
+                utxo_hash,
+                Signature::sign_output(&utxo_hash, &private_key),
+            )],
+            vec![TransactionOutput::new(
+                utxo_output.value() - 100,
+                Uuid::new_v4(),
+                recipient.clone(),
+            )],
+        );
+        assert_eq!(blockchain.transaction_senders(&tx), vec![sender.clone()]);
+        assert_eq!(blockchain.unconfirmed_from(&sender), 0);
+
+        blockchain.add_transaction_to_mempool(tx).unwrap();
+        assert_eq!(blockchain.unconfirmed_from(&sender), 1);
+        assert_eq!(blockchain.unconfirmed_from(&recipient), 0);
+    }
+
+    #[test]
+    fn test_blockchain_mempool_packages() {
+        let mut blockchain = Blockchain::default();
+        blockchain.add_block(genesis_block()).unwrap();
+        blockchain.rebuild_utxos();
+
+        let private_key = PrivateKey::default();
+        let (utxo_hash, utxo_output) = blockchain.utxos().into_iter().next().unwrap();
+        // UTXOs are found under the hash inputs spend them by
+        assert_eq!(utxo_hash, utxo_output.hash());
+        let spend = |outpoints: &[Hash], value: u64| {
+            Transaction::new(
+                outpoints
+                    .iter()
+                    .map(|outpoint| {
+                        TransactionInput::new(
+                            *outpoint,
+                            Signature::sign_output(outpoint, &private_key),
+                        )
+                    })
+                    .collect(),
+                vec![TransactionOutput::new(
+                    value,
+                    Uuid::new_v4(),
+                    private_key.public_key(),
+                )],
+            )
+        };
+        let parent = spend(&[utxo_hash], utxo_output.value());
+        let parent_output = parent.outputs()[0].hash();
+        let child = spend(&[parent_output], utxo_output.value() - 1000);
+        blockchain
+            .add_transaction_to_mempool(parent.clone())
+            .unwrap();
+        blockchain
+            .add_transaction_to_mempool(child.clone())
+            .unwrap();
+        assert_eq!(blockchain.transaction_fee(&child), Some(1000));
+        let ancestors: Vec<Hash> = blockchain
+            .mempool_ancestors(&child)
+            .iter()
+            .map(|tx| tx.hash())
+            .collect();
+        assert_eq!(ancestors, vec![parent.hash()]);
+
+        let entries = blockchain.mempool_entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].hash, child.hash());
+        assert_eq!(entries[0].ancestors, 1);
+        assert_eq!(entries[0].package_fee, 1000);
+        assert_eq!(entries[0].package_size, parent.size() + child.size());
+        assert_eq!(entries[1].hash, parent.hash());
+        assert_eq!(entries[1].package_fee, 0);
+
+        // spending the same output as its own parent
+        assert!(matches!(
+            blockchain.add_transaction_to_mempool(spend(&[utxo_hash, parent_output], 1)),
+            Err(BtcError::DoubleSpending)
+        ));
+        // replacing the parent takes the child with it
+        let replacement = spend(&[utxo_hash], utxo_output.value() - 10);
+        blockchain
+            .add_transaction_to_mempool(replacement.clone())
+            .unwrap();
+        assert_eq!(blockchain.mempool().len(), 1);
+        assert_eq!(blockchain.mempool()[0].1.hash(), replacement.hash());
+        assert!(blockchain.add_transaction_to_mempool(child).is_err());
+    }
+
+    #[test]
+    fn test_blockchain_zero_conf_risk() {
+        let mut blockchain = Blockchain::default();
+        blockchain.add_block(genesis_block()).unwrap();
+        blockchain.rebuild_utxos();
+        let private_key = PrivateKey::default();
+        let (utxo_hash, utxo_output) = blockchain.utxos().into_iter().next().unwrap();
+        let spend = |outpoint: Hash, value: u64| {
+            Transaction::new(
+                vec![TransactionInput::new(
+                    outpoint,
+                    Signature::sign_output(&outpoint, &private_key),
+                )],
+                vec![TransactionOutput::new(
+                    value,
+                    Uuid::new_v4(),
+                    private_key.public_key(),
+                )],
+            )
+        };
+        let parent = spend(utxo_hash, utxo_output.value() - 1000);
+        let child = spend(parent.outputs()[0].hash(), utxo_output.value() - 1100);
+        blockchain
+            .add_transaction_to_mempool(parent.clone())
+            .unwrap();
+        blockchain
+            .add_transaction_to_mempool(child.clone())
+            .unwrap();
+        assert!(blockchain.zero_conf_risk(&utxo_hash, 1, 1).is_none());
+
+        // the parent spends the genesis coinbase and pays the better rate
+        let risk = blockchain.zero_conf_risk(&parent.hash(), 3, 2).unwrap();
+        assert_eq!(risk.bytes_ahead, 0);
+        assert_eq!(risk.unconfirmed_inputs, 0);
+        assert_eq!(risk.min_input_confirmations, Confirmations::new(1));
+        assert_eq!(risk.fee_rate, 1000.0 / parent.size() as f64);
+        assert_eq!((risk.peers_asked, risk.peers_with_transaction), (3, 2));
+
+        // the child waits behind it, and spends an unconfirmed output
+        let risk = blockchain.zero_conf_risk(&child.hash(), 3, 2).unwrap();
+        assert_eq!(risk.bytes_ahead, parent.size());
+        assert_eq!(risk.unconfirmed_inputs, 1);
+        assert_eq!(risk.min_input_confirmations, Confirmations::NONE);
+        assert!(
+            risk.score()
+                > blockchain
+                    .zero_conf_risk(&parent.hash(), 3, 2)
+                    .unwrap()
+                    .score()
+        );
+    }
+
+    #[test]
+    fn test_blockchain_cleanup_evicts_descendants() {
+        let mut blockchain = Blockchain::default();
+        blockchain.add_block(genesis_block()).unwrap();
+        blockchain.rebuild_utxos();
+
+        let private_key = PrivateKey::default();
+        let (utxo_hash, utxo_output) = blockchain.utxos().into_iter().next().unwrap();
+        let parent = Transaction::new(
+            vec![TransactionInput::new(
+                utxo_hash,
+                Signature::sign_output(&utxo_hash, &private_key),
+            )],
+            vec![TransactionOutput::new(
+                utxo_output.value(),
+                Uuid::new_v4(),
+                private_key.public_key(),
+            )],
+        );
+        let parent_output = parent.outputs()[0].hash();
+        let child = Transaction::new(
+            vec![TransactionInput::new(
+                parent_output,
+                Signature::sign_output(&parent_output, &private_key),
+            )],
+            vec![TransactionOutput::new(
+                utxo_output.value() - 100,
+                Uuid::new_v4(),
+                private_key.public_key(),
+            )],
+        );
+        blockchain
+            .add_transaction_to_mempool(parent.clone())
+            .unwrap();
+        blockchain
+            .add_transaction_to_mempool(child.clone())
+            .unwrap();
+
+        // only the parent is old enough to go, but the child can't stay
+        let stale = Utc::now() - Duration::seconds(crate::MAX_MEMPOOL_TX_AGE as i64 + 1);
+        for (timestamp, transaction) in &mut blockchain.mempool {
+            if transaction.hash() == parent.hash() {
+                *timestamp = stale;
+            }
+        }
+        let evicted: HashSet<Hash> = blockchain
+            .cleanup_mempool(crate::MAX_MEMPOOL_TX_AGE)
+            .iter()
+            .map(|tx| tx.hash())
+            .collect();
+        assert_eq!(evicted, HashSet::from([parent.hash(), child.hash()]));
+        assert!(blockchain.mempool().is_empty());
+    }
+
+    #[test]
+    fn test_blockchain_mempool_expiry() {
+        let mut blockchain = Blockchain::default();
+        blockchain.add_block(genesis_block()).unwrap();
+        blockchain.rebuild_utxos();
+
+        let private_key = PrivateKey::default();
+        let (utxo_hash, utxo_output) = blockchain.utxos().into_iter().next().unwrap();
+        let tx = Transaction::new(
+            vec![TransactionInput::new(
+                utxo_hash,
+                Signature::sign_output(&utxo_hash, &private_key),
+            )],
+            vec![TransactionOutput::new(
+                utxo_output.value() - 100,
+                Uuid::new_v4(),
+                private_key.public_key(),
+            )],
+        );
+        // the next block is at height 1
+        assert!(matches!(
+            blockchain.add_transaction_to_mempool(tx.clone().with_expiry(BlockHeight::GENESIS)),
+            Err(BtcError::ExpiredTransaction)
+        ));
+        let tx = tx.with_expiry(BlockHeight::new(1));
+        blockchain.add_transaction_to_mempool(tx.clone()).unwrap();
+        assert_eq!(
+            blockchain.utxos[&utxo_hash].state(),
+            UtxoState::SpentInMempool(tx.hash())
+        );
+
+        // an expiry height replaces the age limit
+        let stale = Utc::now() - Duration::seconds(crate::MAX_MEMPOOL_TX_AGE as i64 + 1);
+        blockchain.mempool[0].0 = stale;
+        assert!(
+            blockchain
+                .cleanup_mempool(crate::MAX_MEMPOOL_TX_AGE)
+                .is_empty()
+        );
+        // once the chain passes it, the transaction goes
+        blockchain.blocks.push(genesis_block());
+        let evicted = blockchain.cleanup_mempool(crate::MAX_MEMPOOL_TX_AGE);
+        assert_eq!(evicted.len(), 1);
+        assert!(blockchain.mempool().is_empty());
+        assert!(blockchain.utxos[&utxo_hash].is_unspent());
+    }
+
+    #[test]
+    fn test_blockchain_mempool_priority_lane() {
+        let mut blockchain = Blockchain::default();
+        blockchain.add_block(genesis_block()).unwrap();
+        blockchain.rebuild_utxos();
+
+        let private_key = PrivateKey::default();
+        let (utxo_hash, utxo_output) = blockchain.utxos().into_iter().next().unwrap();
+        let parent = Transaction::new(
+            vec![TransactionInput::new(
+                utxo_hash,
+                Signature::sign_output(&utxo_hash, &private_key),
+            )],
+            vec![TransactionOutput::new(
+                utxo_output.value(),
+                Uuid::new_v4(),
+                private_key.public_key(),
+            )],
+        );
+        let output = parent.outputs()[0].clone();
+        let child = Transaction::new(
+            vec![TransactionInput::new(
+                output.hash(),
+                Signature::sign_output(&output.hash(), &private_key),
+            )],
+            vec![TransactionOutput::new(
+                output.value(),
+                Uuid::new_v4(),
+                private_key.public_key(),
+            )],
+        );
+        blockchain.add_transaction_to_mempool(parent).unwrap();
+        blockchain
+            .add_priority_transaction_to_mempool(child.clone())
+            .unwrap();
+        assert!(blockchain.mempool_priority().contains(&child.hash()));
+
+        // neither the priority transaction nor its parent age out
+        let stale = Utc::now() - Duration::seconds(crate::MAX_MEMPOOL_TX_AGE as i64 + 1);
+        for entry in &mut blockchain.mempool {
+            entry.0 = stale;
+        }
+        assert!(
+            blockchain
+                .cleanup_mempool(crate::MAX_MEMPOOL_TX_AGE)
+                .is_empty()
+        );
+        assert_eq!(blockchain.mempool().len(), 2);
+
+        // the lane forgets transactions that left the mempool
+        let hashes = HashSet::from([child.hash()]);
+        blockchain.remove_from_mempool(&hashes);
+        assert_eq!(
+            blockchain.cleanup_mempool(crate::MAX_MEMPOOL_TX_AGE).len(),
+            1
+        );
+        assert!(blockchain.mempool_priority().is_empty());
+    }
+
     #[test]
     fn test_blockchain_try_adjust_target_empty() {
         let mut blockchain = Blockchain::default();
@@ -652,7 +2149,7 @@ mod tests {
     #[test]
     fn test_blockchain_try_adjust_target_not_at_interval() {
         let mut blockchain = Blockchain::default();
-        blockchain.add_block(create_genesis_block()).unwrap();
+        blockchain.add_block(genesis_block()).unwrap();
         let initial_target = blockchain.target();
 
         blockchain.try_adjust_target();
@@ -666,8 +2163,8 @@ mod tests {
         let mut blockchain = Blockchain::default();
 
         // Manually add some transactions to mempool
-        let tx1 = create_coinbase_transaction(1000);
-        let tx2 = create_coinbase_transaction(2000);
+        let tx1 = coinbase_transaction(1000);
+        let tx2 = coinbase_transaction(2000);
 
         blockchain.mempool.push((Utc::now(), tx1.clone()));
         blockchain.mempool.push((Utc::now(), tx2.clone()));
@@ -691,10 +2188,228 @@ mod tests {
         let mut blockchain = Blockchain::default();
         assert_eq!(blockchain.blocks().len(), 0);
 
-        blockchain.add_block(create_genesis_block()).unwrap();
+        blockchain.add_block(genesis_block()).unwrap();
         assert_eq!(blockchain.blocks().len(), 1);
     }
 
+    #[test]
+    fn test_blockchain_block_by_hash() {
+        let mut blockchain = Blockchain::default();
+        let block = genesis_block();
+        let hash = block.hash();
+        blockchain.add_block(block).unwrap();
+
+        assert!(blockchain.block_by_hash(&hash).is_some());
+        assert!(blockchain.block_by_hash(&Hash::zero()).is_none());
+    }
+
+    #[test]
+    fn test_blockchain_tip() {
+        let mut blockchain = Blockchain::default();
+        assert!(blockchain.tip().is_none());
+
+        let block = genesis_block();
+        let hash = block.hash();
+        blockchain.add_block(block).unwrap();
+
+        let tip = blockchain.tip().unwrap();
+        assert_eq!(tip.hash, hash);
+        assert_eq!(tip.height, BlockHeight::GENESIS);
+        assert_eq!(tip.prev, Hash::zero());
+    }
+
+    #[test]
+    fn test_blockchain_stats() {
+        let mut blockchain = Blockchain::default();
+        blockchain.add_block(genesis_block()).unwrap();
+        blockchain.rebuild_utxos();
+
+        let stats = blockchain.stats(10);
+        assert_eq!(stats.height, BlockHeight::new(1));
+        assert_eq!(stats.total_supply, 5000000000);
+        assert_eq!(stats.utxo_count, 1);
+        assert!(stats.utxo_set_bytes > 0);
+        assert_eq!(stats.rich_list.len(), 1);
+        assert_eq!(stats.rich_list[0].1, 5000000000);
+
+        assert!(blockchain.stats(0).rich_list.is_empty());
+    }
+
+    #[test]
+    fn test_blockchain_memory_info() {
+        let mut blockchain = Blockchain::default();
+        assert_eq!(blockchain.memory_info().total(), 0);
+
+        blockchain.blocks.push(genesis_block());
+        blockchain.rebuild_utxos();
+        let info = blockchain.memory_info();
+        assert_eq!(info.utxos, 1);
+        assert!(info.utxo_bytes > 0);
+        assert_eq!(info.mempool_transactions, 0);
+
+        let transaction = blockchain.blocks[0].transactions()[0].clone();
+        let size = transaction.size() as u64;
+        blockchain.mempool.push((Utc::now(), transaction));
+        let info = blockchain.memory_info();
+        assert_eq!(info.mempool_transactions, 1);
+        assert!(info.mempool_bytes > size);
+        assert_eq!(info.budget, None);
+    }
+
+    #[test]
+    fn test_blockchain_iterators() {
+        let private_key = PrivateKey::default();
+        let mut blockchain = Blockchain::default();
+        for _ in 0..3 {
+            blockchain.blocks.push(genesis_block());
+        }
+        let output = TransactionOutput::new(1000, Uuid::new_v4(), private_key.public_key());
+        let mut entry = UtxoEntry::new(output);
+        entry.spend_in_mempool(Hash::zero()).unwrap();
+        blockchain.utxos.insert(entry.output().hash(), entry);
+
+        assert_eq!(blockchain.iter_blocks().len(), 3);
+        let heights = |range: (Bound<u64>, Bound<u64>)| {
+            let range = (range.0.map(BlockHeight::new), range.1.map(BlockHeight::new));
+            blockchain
+                .iter_blocks_in(range)
+                .map(|(height, _)| height.value())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(heights((Bound::Included(1), Bound::Unbounded)), vec![1, 2]);
+        assert_eq!(heights((Bound::Unbounded, Bound::Excluded(1))), vec![0]);
+        assert_eq!(heights((Bound::Included(5), Bound::Included(9))), vec![]);
+        assert_eq!(blockchain.iter_transactions().count(), 3);
+        assert_eq!(
+            blockchain
+                .iter_transactions_in(BlockHeight::new(2)..)
+                .count(),
+            1
+        );
+        assert_eq!(blockchain.confirmations(BlockHeight::new(2)).value(), 1);
+        assert!(!blockchain.confirmations(BlockHeight::new(3)).is_confirmed());
+
+        let mine: Vec<_> = blockchain
+            .iter_utxos_for(&private_key.public_key())
+            .collect();
+        assert_eq!(mine.len(), 1);
+        assert_eq!(mine[0].1.output().value(), 1000);
+        assert!(mine[0].1.is_spent_in_mempool());
+        assert_eq!(blockchain.iter_utxos().count(), 1);
+    }
+
+    #[test]
+    fn test_blockchain_rescan() {
+        let private_key = PrivateKey::default();
+        let received = TransactionOutput::new(1000, Uuid::new_v4(), private_key.public_key());
+        let change = TransactionOutput::new(400, Uuid::new_v4(), private_key.public_key());
+        let elsewhere =
+            TransactionOutput::new(600, Uuid::new_v4(), PrivateKey::default().public_key());
+        let signature = Signature::sign_output(&received.hash(), &private_key);
+        let spend = Transaction::new(
+            vec![TransactionInput::new(received.hash(), signature)],
+            vec![elsewhere, change],
+        );
+        let block = |transactions: Vec<Transaction>| {
+            let merkle_root = MerkleRoot::calculate(&transactions);
+            let header = crate::types::BlockHeader::new(
+                Utc::now(),
+                0,
+                Hash::zero(),
+                merkle_root,
+                MIN_TARGET,
+            );
+            Block::new(header, transactions)
+        };
+        let mut blockchain = Blockchain::default();
+        blockchain.blocks.push(genesis_block());
+        blockchain
+            .blocks
+            .push(block(vec![Transaction::new(vec![], vec![received])]));
+        blockchain.blocks.push(block(vec![spend]));
+
+        let result = blockchain.rescan(&[private_key.public_key()], BlockHeight::GENESIS);
+        assert_eq!(result.to_height, BlockHeight::new(3));
+        assert_eq!(result.outputs.len(), 2);
+        assert_eq!(result.outputs[0].height, BlockHeight::new(1));
+        assert_eq!(result.confirmations(&result.outputs[0]).value(), 2);
+        assert_eq!(result.outputs[0].spent_at, Some(BlockHeight::new(2)));
+        assert_eq!(result.outputs[1].spent_at, None);
+        assert_eq!(result.balance(), 400);
+
+        // the spent output is before the scan window
+        assert_eq!(
+            blockchain
+                .rescan(&[private_key.public_key()], BlockHeight::new(2))
+                .outputs
+                .len(),
+            1
+        );
+    }
+
+    #[cfg(feature = "consensus-checks")]
+    #[test]
+    #[should_panic(expected = "consensus invariants violated")]
+    fn test_check_invariants_catches_stray_mark() {
+        let mut blockchain = Blockchain::default();
+        blockchain.add_block(genesis_block()).unwrap();
+        blockchain.rebuild_utxos();
+        // mark a UTXO without a mempool transaction spending it
+        for entry in blockchain.utxos.values_mut() {
+            entry.spend_in_mempool(Hash::zero()).unwrap();
+        }
+
+        blockchain.check_invariants();
+    }
+
+    #[cfg(feature = "consensus-checks")]
+    #[test]
+    fn test_check_invariants_allow_spending_block_before_rebuild() {
+        let owner = PrivateKey::from_seed(b"owner");
+        let mut blockchain = Blockchain::default();
+        let genesis = BlockBuilder::on(&blockchain).build(owner.public_key());
+        blockchain.add_block(genesis).unwrap();
+        blockchain.rebuild_utxos();
+        let (_, output) = blockchain.utxos().into_iter().next().unwrap();
+        let mut builder = BlockBuilder::on(&blockchain);
+        assert!(builder.add_transaction(crate::testutil::spend_transaction(&output, &owner, 0), 0));
+        let block = builder.build(owner.public_key());
+
+        // the spent output stays in the UTXO set until the rebuild
+        blockchain.add_block(block).unwrap();
+        assert!(blockchain.utxo(&output.hash()).is_some());
+        blockchain.rebuild_utxos();
+        assert!(blockchain.utxo(&output.hash()).is_none());
+    }
+
+    #[test]
+    fn test_blockchain_block_interval_stats() {
+        let mut blockchain = Blockchain::default();
+        assert!(blockchain.block_interval_stats(10).is_none());
+
+        let start = Utc::now();
+        for (i, offset) in [0, 10, 30, 40].into_iter().enumerate() {
+            let prev = blockchain.tip().map(|tip| tip.hash).unwrap_or(Hash::zero());
+            let transactions = vec![coinbase_transaction(i as u64)];
+            let merkle_root = MerkleRoot::calculate(&transactions);
+            let header = crate::types::BlockHeader::new(
+                start + Duration::seconds(offset),
+                0,
+                prev,
+                merkle_root,
+                MIN_TARGET,
+            );
+            blockchain.blocks.push(Block::new(header, transactions));
+        }
+
+        // intervals 10, 20, 10
+        let (mean, variance) = blockchain.block_interval_stats(10).unwrap();
+        assert!((mean - 40.0 / 3.0).abs() < 1e-9);
+        assert!((variance - 200.0 / 9.0).abs() < 1e-9);
+        // only the last interval
+        assert_eq!(blockchain.block_interval_stats(1), Some((10.0, 0.0)));
+    }
+
     #[test]
     fn test_blockchain_mempool_accessor() {
         let blockchain = Blockchain::default();
@@ -0,0 +1,113 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    U256,
+    crypto::PublicKey,
+    custom_sha_types::Hash,
+    types::{Block, BlockHeader, BlockHeight, Transaction, TransactionOutput},
+    utils::MerkleRoot,
+};
+
+/// What a new network launches with: everything its genesis block is
+/// made of except who the coinbase pays. The same params and payout key
+/// always give the same genesis block, so every node of a network can
+/// check it.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ChainParams {
+    /// Timestamp of the genesis block
+    pub genesis_time: DateTime<Utc>,
+    /// Target of the genesis block, and of the chain until it retargets
+    pub target: U256,
+    /// Tag of the genesis coinbase, e.g. a headline proving the block
+    /// wasn't made before its date. At most `MAX_COINBASE_TAG_SIZE` bytes
+    pub genesis_message: Option<Vec<u8>>,
+}
+
+impl Default for ChainParams {
+    fn default() -> Self {
+        ChainParams {
+            genesis_time: DateTime::UNIX_EPOCH,
+            target: crate::MIN_TARGET,
+            genesis_message: None,
+        }
+    }
+}
+
+impl ChainParams {
+    /// The genesis block paying the subsidy to `payout`, not yet sealed:
+    /// mine it on a proof-of-work network, or have the first validator
+    /// sign it on a proof-of-authority one. Mining only moves the nonce,
+    /// so the sealed block is as reproducible as this one.
+    pub fn genesis_block(&self, payout: &PublicKey) -> Block {
+        // derived rather than random, so the output hash is reproducible
+        let seed = Hash::hash(&(payout, self)).as_bytes();
+        let unique_id = Uuid::from_bytes(seed[..16].try_into().unwrap());
+        let coinbase = Transaction::new(
+            vec![],
+            vec![TransactionOutput::new(
+                BlockHeight::GENESIS.block_reward(),
+                unique_id,
+                payout.clone(),
+            )],
+        );
+        let coinbase = match &self.genesis_message {
+            Some(message) => coinbase.with_coinbase_tag(message.clone()),
+            None => coinbase,
+        };
+        let transactions = vec![coinbase];
+        let header = BlockHeader::new(
+            self.genesis_time,
+            0,
+            Hash::zero(),
+            MerkleRoot::calculate(&transactions),
+            self.target,
+        );
+        Block::new(header, transactions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{crypto::PrivateKey, types::Blockchain};
+
+    #[test]
+    fn test_genesis_block_is_reproducible() {
+        let payout = PrivateKey::from_seed(b"payout").public_key();
+        let params = ChainParams {
+            genesis_time: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            target: crate::MIN_TARGET >> 8,
+            genesis_message: Some(b"launch".to_vec()),
+        };
+
+        let mut genesis = params.genesis_block(&payout);
+        assert_eq!(genesis.hash(), params.genesis_block(&payout).hash());
+        assert!(genesis.mine(usize::MAX));
+        let mut again = params.genesis_block(&payout);
+        assert!(again.mine(usize::MAX));
+        assert_eq!(genesis.hash(), again.hash());
+        assert_eq!(genesis.header().timestamp(), params.genesis_time);
+        assert_eq!(
+            genesis.transactions()[0].coinbase_tag(),
+            Some(&b"launch"[..])
+        );
+
+        // any other payout key or parameter gives another block
+        let other = PrivateKey::from_seed(b"other").public_key();
+        assert_ne!(genesis.hash(), params.genesis_block(&other).hash());
+        let untagged = ChainParams {
+            genesis_message: None,
+            ..params
+        };
+        assert_ne!(
+            params.genesis_block(&payout).hash(),
+            untagged.genesis_block(&payout).hash()
+        );
+
+        let mut blockchain = Blockchain::default();
+        blockchain.add_block(genesis).unwrap();
+        assert_eq!(blockchain.blocks().len(), 1);
+    }
+}
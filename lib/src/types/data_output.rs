@@ -0,0 +1,52 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Arbitrary application data anchored in a transaction. It carries no
+/// value and can never be spent, so it never enters the UTXO set.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DataOutput {
+    data: Vec<u8>,
+}
+
+impl DataOutput {
+    /// The size limit is enforced when the transaction is verified.
+    pub fn new(data: Vec<u8>) -> Self {
+        DataOutput { data }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn is_within_size_limit(&self) -> bool {
+        self.data.len() <= crate::MAX_DATA_OUTPUT_SIZE
+    }
+}
+
+/// Shows printable UTF-8 as text and anything else as hex.
+impl fmt::Display for DataOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match std::str::from_utf8(&self.data) {
+            Ok(text) if !text.chars().any(char::is_control) => write!(f, "{text:?}"),
+            _ => write!(f, "0x{}", hex::encode(&self.data)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_data_output_size_limit() {
+        assert!(DataOutput::new(vec![0; crate::MAX_DATA_OUTPUT_SIZE]).is_within_size_limit());
+        assert!(!DataOutput::new(vec![0; crate::MAX_DATA_OUTPUT_SIZE + 1]).is_within_size_limit());
+    }
+
+    #[test]
+    fn test_data_output_display() {
+        assert_eq!(DataOutput::new(b"hello".to_vec()).to_string(), "\"hello\"");
+        assert_eq!(DataOutput::new(vec![0, 255]).to_string(), "0x00ff");
+    }
+}
@@ -0,0 +1,166 @@
+use std::{fmt, ops::Add};
+
+use serde::{Deserialize, Serialize};
+
+/// Position of a block in the chain, counting from the genesis block at 0.
+/// This is also the index `FetchBlock` serves the block under.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize,
+)]
+#[serde(transparent)]
+pub struct BlockHeight(u64);
+
+impl BlockHeight {
+    pub const GENESIS: BlockHeight = BlockHeight(0);
+
+    pub const fn new(height: u64) -> Self {
+        BlockHeight(height)
+    }
+
+    pub const fn value(self) -> u64 {
+        self.0
+    }
+
+    /// Height of the block at `index` in a list of blocks starting at genesis.
+    pub fn from_index(index: usize) -> Self {
+        BlockHeight(index as u64)
+    }
+
+    /// Index of the block in a list of blocks starting at genesis.
+    pub fn as_index(self) -> usize {
+        self.0 as usize
+    }
+
+    pub fn next(self) -> Self {
+        self + 1
+    }
+
+    /// The height before this one, or `None` at genesis.
+    pub fn previous(self) -> Option<Self> {
+        self.0.checked_sub(1).map(BlockHeight)
+    }
+
+    /// Number of blocks from this height up to (not including) `later`,
+    /// zero if `later` isn't later.
+    pub fn blocks_until(self, later: BlockHeight) -> u64 {
+        later.0.saturating_sub(self.0)
+    }
+
+    /// Number of reward halvings that happened before this height.
+    pub fn halvings(self) -> u64 {
+        self.0 / crate::HALVING_INTERVAL
+    }
+
+    /// Subsidy of the block at this height in satoshis, halved every
+    /// `HALVING_INTERVAL` blocks.
+    pub fn block_reward(self) -> u64 {
+        (crate::INITIAL_REWARD * 10u64.pow(8))
+            .checked_shr(self.halvings() as u32)
+            .unwrap_or(0)
+    }
+
+    /// How deeply a block at this height is buried in a chain whose next
+    /// block will be at `next_height`. The best block has one confirmation.
+    pub fn confirmations(self, next_height: BlockHeight) -> Confirmations {
+        Confirmations(self.blocks_until(next_height))
+    }
+}
+
+impl Add<u64> for BlockHeight {
+    type Output = BlockHeight;
+
+    fn add(self, blocks: u64) -> Self::Output {
+        BlockHeight(self.0 + blocks)
+    }
+}
+
+impl From<u64> for BlockHeight {
+    fn from(height: u64) -> Self {
+        BlockHeight(height)
+    }
+}
+
+impl From<BlockHeight> for u64 {
+    fn from(height: BlockHeight) -> Self {
+        height.0
+    }
+}
+
+impl fmt::Display for BlockHeight {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Number of blocks that include or build on a block, see `BlockHeight::confirmations`.
+/// Zero means the block (or transaction) isn't in the chain.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize,
+)]
+#[serde(transparent)]
+pub struct Confirmations(u64);
+
+impl Confirmations {
+    pub const NONE: Confirmations = Confirmations(0);
+
+    pub const fn new(confirmations: u64) -> Self {
+        Confirmations(confirmations)
+    }
+
+    pub const fn value(self) -> u64 {
+        self.0
+    }
+
+    pub fn is_confirmed(self) -> bool {
+        self.0 > 0
+    }
+
+    /// Whether there are at least `required` confirmations.
+    pub fn at_least(self, required: u64) -> bool {
+        self.0 >= required
+    }
+}
+
+impl fmt::Display for Confirmations {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_height_arithmetic() {
+        let height = BlockHeight::new(5);
+        assert_eq!(height.next(), BlockHeight::new(6));
+        assert_eq!(height.previous(), Some(BlockHeight::new(4)));
+        assert_eq!(BlockHeight::GENESIS.previous(), None);
+        assert_eq!(BlockHeight::GENESIS.blocks_until(height), 5);
+        assert_eq!(height.blocks_until(BlockHeight::GENESIS), 0);
+        assert_eq!(BlockHeight::from_index(height.as_index()), height);
+        assert_eq!(BlockHeight::new(crate::HALVING_INTERVAL * 2).halvings(), 2);
+        assert_eq!(
+            BlockHeight::new(crate::HALVING_INTERVAL).block_reward(),
+            BlockHeight::GENESIS.block_reward() / 2
+        );
+        assert_eq!(
+            BlockHeight::new(crate::HALVING_INTERVAL * 64).block_reward(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_confirmations() {
+        // chain of 10 blocks, the best block is at height 9
+        let next_height = BlockHeight::new(10);
+        assert_eq!(BlockHeight::new(9).confirmations(next_height).value(), 1);
+        assert!(BlockHeight::new(0).confirmations(next_height).at_least(10));
+        assert!(
+            !BlockHeight::new(10)
+                .confirmations(next_height)
+                .is_confirmed()
+        );
+    }
+}
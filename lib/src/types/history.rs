@@ -0,0 +1,407 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    crypto::PublicKey,
+    custom_sha_types::Hash,
+    error::{BtcError, Result},
+    types::{Block, BlockHeader, BlockHeight, Blockchain, TransactionOutput},
+};
+
+/// An output spent by a block, with where it came from.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SpentOutput {
+    /// Hash of the output, as referenced by the spending input
+    pub outpoint: Hash,
+    pub output: TransactionOutput,
+    /// Height of the block that created the output
+    pub created_at: BlockHeight,
+}
+
+/// What it takes to undo a block: the outputs it spent, which would
+/// become unspent again, and the outputs it created, which would
+/// disappear. Served in response to `FetchUndo`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BlockUndo {
+    pub height: BlockHeight,
+    pub block: Hash,
+    pub spent: Vec<SpentOutput>,
+    pub created: Vec<Hash>,
+}
+
+/// How the UTXO set changed over the blocks from `from` up to `to`,
+/// without the blocks: the outputs created and still unspent at the end,
+/// and the outputs from before `from` spent on the way. Outputs created
+/// and spent within the range show up in neither. Applied to the UTXO
+/// set just before `from`, it gives the one just before `to`. Served in
+/// response to `FetchUtxoDelta`, so a mirror a few blocks behind can
+/// catch up without downloading them.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UtxoDelta {
+    pub from: BlockHeight,
+    pub to: BlockHeight,
+    /// Headers of the blocks in the range, to check the delta continues
+    /// the mirror's chain
+    pub headers: Vec<BlockHeader>,
+    pub created: Vec<(Hash, TransactionOutput)>,
+    pub spent: Vec<Hash>,
+}
+
+impl UtxoDelta {
+    /// The delta over the blocks of `blockchain` from `from` up to `to`,
+    /// `None` unless `from <= to` and the chain has every block before `to`.
+    pub fn between(blockchain: &Blockchain, from: BlockHeight, to: BlockHeight) -> Option<Self> {
+        if from > to || to > blockchain.block_height() {
+            return None;
+        }
+        let mut headers = vec![];
+        let mut created: Vec<(Hash, TransactionOutput)> = vec![];
+        let mut created_here = HashSet::new();
+        let mut spent_here = HashSet::new();
+        let mut spent = vec![];
+        for (_, block) in blockchain.iter_blocks_in(from..to) {
+            headers.push(block.header().clone());
+            for transaction in block.transactions() {
+                for input in transaction.inputs() {
+                    let outpoint = *input.prev_transaction_output_hash();
+                    if !spent_here.insert(outpoint) {
+                        continue;
+                    }
+                    if !created_here.contains(&outpoint) {
+                        spent.push(outpoint);
+                    }
+                }
+                for output in transaction.outputs() {
+                    let hash = output.hash();
+                    if created_here.insert(hash) {
+                        created.push((hash, output.clone()));
+                    }
+                }
+            }
+        }
+        created.retain(|(hash, _)| !spent_here.contains(hash));
+        Some(UtxoDelta {
+            from,
+            to,
+            headers,
+            created,
+            spent,
+        })
+    }
+
+    /// Checks the headers are the `to - from` blocks following the block
+    /// with hash `prev`, each linked to the one before. Nothing ties the
+    /// outputs to the headers, so this only catches a node serving
+    /// another chain, not one lying about the outputs.
+    pub fn check_headers(&self, prev: Hash) -> Result<()> {
+        if self.headers.len() as u64 != self.from.blocks_until(self.to) {
+            return Err(BtcError::InvalidBlockHeader);
+        }
+        let mut prev = prev;
+        for header in &self.headers {
+            if *header.prev_block_hash() != prev {
+                return Err(BtcError::InvalidBlockHeader);
+            }
+            prev = header.hash();
+        }
+        Ok(())
+    }
+
+    /// Brings `utxos`, the UTXO set just before `from`, to the one just
+    /// before `to`.
+    pub fn apply(&self, utxos: &mut HashMap<Hash, TransactionOutput>) {
+        for outpoint in &self.spent {
+            utxos.remove(outpoint);
+        }
+        utxos.extend(self.created.iter().cloned());
+    }
+}
+
+/// Every output the chain ever created and when it was spent, with the
+/// undo data of every block, so the UTXO set and balances as of any
+/// past height can be reconstructed. Kept by archive nodes, which may
+/// drop the undo data of older blocks with `prune_undo`.
+#[derive(Debug, Clone, Default)]
+pub struct ChainHistory {
+    // output hash -> (height it was created at, output)
+    outputs: HashMap<Hash, (BlockHeight, TransactionOutput)>,
+    spent_at: HashMap<Hash, BlockHeight>,
+    outputs_by_key: BTreeMap<PublicKey, Vec<Hash>>,
+    // undo data of the blocks from `pruned` on
+    undo: Vec<BlockUndo>,
+    pruned: u64,
+}
+
+impl ChainHistory {
+    /// Number of blocks applied so far.
+    pub fn height(&self) -> BlockHeight {
+        BlockHeight::new(self.pruned + self.undo.len() as u64)
+    }
+
+    /// Height of the oldest block whose undo data is still kept.
+    pub fn oldest_undo(&self) -> BlockHeight {
+        BlockHeight::new(self.pruned)
+    }
+
+    /// Drops the undo data of all but the last `keep` blocks applied.
+    /// Outputs and spends stay, so balances and UTXO sets as of any
+    /// height can still be reconstructed. Returns how many blocks lost
+    /// their undo data.
+    pub fn prune_undo(&mut self, keep: u64) -> u64 {
+        let drop = (self.undo.len() as u64).saturating_sub(keep);
+        self.undo.drain(..drop as usize);
+        self.pruned += drop;
+        drop
+    }
+
+    /// Applies the blocks of `blockchain` this history hasn't seen yet.
+    pub fn index(&mut self, blockchain: &Blockchain) {
+        for (_, block) in blockchain.iter_blocks_in(self.height()..) {
+            self.apply_block(block);
+        }
+    }
+
+    pub fn apply_block(&mut self, block: &Block) {
+        let height = self.height();
+        let mut undo = BlockUndo {
+            height,
+            block: block.hash(),
+            spent: vec![],
+            created: vec![],
+        };
+        for transaction in block.transactions() {
+            for input in transaction.inputs() {
+                let outpoint = *input.prev_transaction_output_hash();
+                // an input spending an unknown output can't be undone, and
+                // a second spend doesn't move the first one
+                if self.spent_at.contains_key(&outpoint) {
+                    continue;
+                }
+                if let Some((created_at, output)) = self.outputs.get(&outpoint) {
+                    undo.spent.push(SpentOutput {
+                        outpoint,
+                        output: output.clone(),
+                        created_at: *created_at,
+                    });
+                    self.spent_at.insert(outpoint, height);
+                }
+            }
+            for output in transaction.outputs() {
+                let hash = output.hash();
+                self.outputs.insert(hash, (height, output.clone()));
+                self.outputs_by_key
+                    .entry(output.pubkey().clone())
+                    .or_default()
+                    .push(hash);
+                undo.created.push(hash);
+            }
+        }
+        self.undo.push(undo);
+    }
+
+    /// Undo data of the block at `height`, `None` once pruned.
+    pub fn undo(&self, height: BlockHeight) -> Option<&BlockUndo> {
+        let index = height.value().checked_sub(self.pruned)?;
+        self.undo.get(index as usize)
+    }
+
+    // created before `height` and not yet spent by then
+    fn unspent_at(&self, outpoint: &Hash, height: BlockHeight) -> bool {
+        self.outputs
+            .get(outpoint)
+            .is_some_and(|(created_at, _)| *created_at < height)
+            && self
+                .spent_at
+                .get(outpoint)
+                .is_none_or(|spent_at| *spent_at >= height)
+    }
+
+    /// Sum of the outputs paying `pubkey` that were unspent just before
+    /// the block at `height`, in satoshis.
+    pub fn balance_at(&self, pubkey: &PublicKey, height: BlockHeight) -> u64 {
+        self.outputs_by_key
+            .get(pubkey)
+            .into_iter()
+            .flatten()
+            .filter(|outpoint| self.unspent_at(outpoint, height))
+            .map(|outpoint| self.outputs[outpoint].1.value())
+            .sum()
+    }
+
+    /// The UTXO set just before the block at `height`.
+    pub fn utxos_at(&self, height: BlockHeight) -> HashMap<Hash, TransactionOutput> {
+        self.outputs
+            .iter()
+            .filter(|(outpoint, _)| self.unspent_at(outpoint, height))
+            .map(|(outpoint, (_, output))| (*outpoint, output.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        crypto::{PrivateKey, Signature},
+        testutil::TestBlock,
+        types::{BlockBuilder, Transaction, TransactionInput},
+    };
+    use uuid::Uuid;
+
+    #[test]
+    fn test_chain_history() {
+        let alice = PrivateKey::default();
+        let bob = PrivateKey::default().public_key();
+        let reward = TransactionOutput::new(50, Uuid::new_v4(), alice.public_key());
+        let payment = TransactionOutput::new(30, Uuid::new_v4(), bob.clone());
+        let change = TransactionOutput::new(20, Uuid::new_v4(), alice.public_key());
+        let spend = Transaction::new(
+            vec![TransactionInput::new(
+                reward.hash(),
+                Signature::sign_output(&reward.hash(), &alice),
+            )],
+            vec![payment.clone(), change.clone()],
+        );
+
+        let mut history = ChainHistory::default();
+        history.apply_block(
+            &TestBlock::with_transactions(vec![Transaction::new(vec![], vec![reward.clone()])])
+                .build(),
+        );
+        history.apply_block(&TestBlock::with_transactions(vec![spend]).build());
+        assert_eq!(history.height(), BlockHeight::new(2));
+
+        let alice = alice.public_key();
+        assert_eq!(history.balance_at(&alice, BlockHeight::GENESIS), 0);
+        assert_eq!(history.balance_at(&alice, BlockHeight::new(1)), 50);
+        assert_eq!(history.balance_at(&alice, BlockHeight::new(2)), 20);
+        assert_eq!(history.balance_at(&bob, BlockHeight::new(2)), 30);
+        assert_eq!(history.utxos_at(BlockHeight::new(1)).len(), 1);
+        assert_eq!(history.utxos_at(BlockHeight::new(2)).len(), 2);
+
+        let undo = history.undo(BlockHeight::new(1)).unwrap();
+        assert_eq!(undo.spent.len(), 1);
+        assert_eq!(undo.spent[0].outpoint, reward.hash());
+        assert_eq!(undo.spent[0].created_at, BlockHeight::GENESIS);
+        assert_eq!(undo.created, vec![payment.hash(), change.hash()]);
+        assert!(history.undo(BlockHeight::new(2)).is_none());
+    }
+
+    #[test]
+    fn test_chain_history_prune_undo() {
+        let key = PrivateKey::default();
+        let mut history = ChainHistory::default();
+        let reward = TransactionOutput::new(50, Uuid::new_v4(), key.public_key());
+        history.apply_block(
+            &TestBlock::with_transactions(vec![Transaction::new(vec![], vec![reward.clone()])])
+                .build(),
+        );
+        for _ in 0..3 {
+            history.apply_block(&TestBlock::new().build());
+        }
+
+        assert_eq!(history.prune_undo(2), 2);
+        assert_eq!(history.oldest_undo(), BlockHeight::new(2));
+        assert_eq!(history.height(), BlockHeight::new(4));
+        assert!(history.undo(BlockHeight::new(1)).is_none());
+        assert_eq!(
+            history.undo(BlockHeight::new(3)).unwrap().height,
+            BlockHeight::new(3)
+        );
+        // balances don't need undo data
+        assert_eq!(
+            history.balance_at(&key.public_key(), BlockHeight::new(1)),
+            50
+        );
+        assert_eq!(history.prune_undo(2), 0);
+
+        // new blocks are still indexed at the right height
+        history.apply_block(&TestBlock::new().build());
+        assert_eq!(
+            history.undo(BlockHeight::new(4)).unwrap().height,
+            BlockHeight::new(4)
+        );
+    }
+
+    #[test]
+    fn test_utxo_delta() {
+        let key = PrivateKey::default();
+        let mut blockchain = Blockchain::default();
+        let mut history = ChainHistory::default();
+        let mut mine = |blockchain: &mut Blockchain, transactions: Vec<Transaction>| {
+            let mut builder = BlockBuilder::on(blockchain);
+            for transaction in transactions {
+                assert!(builder.add_transaction(transaction, 0));
+            }
+            let block = builder.build(key.public_key());
+            history.apply_block(&block);
+            blockchain.add_block(block).unwrap();
+            blockchain.rebuild_utxos();
+        };
+        let spend = |output: &TransactionOutput| {
+            Transaction::new(
+                vec![TransactionInput::new(
+                    output.hash(),
+                    Signature::sign_output(&output.hash(), &key),
+                )],
+                vec![TransactionOutput::new(
+                    output.value(),
+                    Uuid::new_v4(),
+                    key.public_key(),
+                )],
+            )
+        };
+        mine(&mut blockchain, vec![]);
+        mine(&mut blockchain, vec![]);
+        let reward = |blockchain: &Blockchain, height| {
+            blockchain
+                .block_at(BlockHeight::new(height))
+                .unwrap()
+                .transactions()[0]
+                .outputs()[0]
+                .clone()
+        };
+        // block 2 spends the genesis reward, block 3 the output it created
+        let first = spend(&reward(&blockchain, 0));
+        let second = spend(&first.outputs()[0]);
+        mine(&mut blockchain, vec![first]);
+        mine(&mut blockchain, vec![second]);
+        let third = spend(&reward(&blockchain, 1));
+        mine(&mut blockchain, vec![third]);
+
+        let outpoints = |utxos: HashMap<Hash, TransactionOutput>| {
+            let mut outpoints: Vec<Hash> = utxos.into_keys().collect();
+            outpoints.sort_by_key(|hash| hash.as_bytes());
+            outpoints
+        };
+        let mut utxos = HashMap::new();
+        let head =
+            UtxoDelta::between(&blockchain, BlockHeight::GENESIS, BlockHeight::new(2)).unwrap();
+        head.check_headers(Hash::zero()).unwrap();
+        head.apply(&mut utxos);
+        assert_eq!(
+            outpoints(utxos.clone()),
+            outpoints(history.utxos_at(BlockHeight::new(2)))
+        );
+
+        let tail =
+            UtxoDelta::between(&blockchain, BlockHeight::new(2), BlockHeight::new(5)).unwrap();
+        // three rewards and two payments, the payment made at 2 and
+        // spent at 3 is in neither list
+        assert_eq!(tail.created.len(), 5);
+        assert_eq!(tail.spent.len(), 2);
+        let prev = blockchain.block_at(BlockHeight::new(1)).unwrap().hash();
+        tail.check_headers(prev).unwrap();
+        assert!(tail.check_headers(Hash::zero()).is_err());
+        tail.apply(&mut utxos);
+        assert_eq!(outpoints(utxos), outpoints(blockchain.utxos()));
+
+        assert!(
+            UtxoDelta::between(&blockchain, BlockHeight::new(3), BlockHeight::new(2)).is_none()
+        );
+        assert!(
+            UtxoDelta::between(&blockchain, BlockHeight::new(2), BlockHeight::new(6)).is_none()
+        );
+    }
+}
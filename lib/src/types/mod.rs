@@ -1,13 +1,31 @@
+mod authority;
 mod block;
+mod block_builder;
 mod block_header;
 mod blockchain;
+mod chain_params;
+mod data_output;
+mod height;
+mod history;
+mod reserve_proof;
 mod transaction;
 mod transaction_input;
 mod transaction_output;
+mod unsigned_transaction;
+mod utxo_entry;
 
+pub use authority::*;
 pub use block::*;
+pub use block_builder::*;
 pub use block_header::*;
 pub use blockchain::*;
+pub use chain_params::*;
+pub use data_output::*;
+pub use height::*;
+pub use history::*;
+pub use reserve_proof::*;
 pub use transaction::*;
 pub use transaction_input::*;
 pub use transaction_output::*;
+pub use unsigned_transaction::*;
+pub use utxo_entry::*;
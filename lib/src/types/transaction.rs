@@ -4,7 +4,8 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     custom_sha_types::Hash,
-    types::{TransactionInput, TransactionOutput},
+    error::{BtcError, Result},
+    types::{BlockHeight, DataOutput, TransactionInput, TransactionOutput},
     utils::Saveable,
 };
 
@@ -12,11 +13,52 @@ use crate::{
 pub struct Transaction {
     inputs: Vec<TransactionInput>,
     outputs: Vec<TransactionOutput>,
+    /// Arbitrary miner-chosen data (e.g. a pool name), only allowed on coinbase transactions.
+    /// Skipped when absent so the hashes of untagged transactions stay the same.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    coinbase_tag: Option<Vec<u8>>,
+    /// Unspendable data, kept apart from `outputs` so it never enters the UTXO set.
+    /// Skipped when empty for the same reason as `coinbase_tag`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    data_outputs: Vec<DataOutput>,
+    /// Last height the transaction may be mined at. Skipped when absent
+    /// for the same reason as `coinbase_tag`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at_height: Option<BlockHeight>,
 }
 
 impl Transaction {
     pub fn new(inputs: Vec<TransactionInput>, outputs: Vec<TransactionOutput>) -> Self {
-        Transaction { inputs, outputs }
+        Transaction {
+            inputs,
+            outputs,
+            coinbase_tag: None,
+            data_outputs: vec![],
+            expires_at_height: None,
+        }
+    }
+
+    /// Attaches a coinbase tag to the transaction. The size limit is enforced
+    /// when the block containing it is verified.
+    pub fn with_coinbase_tag(mut self, tag: Vec<u8>) -> Self {
+        self.coinbase_tag = Some(tag);
+        self
+    }
+
+    /// Appends a data output. Size and count limits are enforced when the
+    /// transaction is verified.
+    pub fn with_data_output(mut self, data_output: DataOutput) -> Self {
+        self.data_outputs.push(data_output);
+        self
+    }
+
+    /// Makes the transaction invalid in any block above `height`. It is
+    /// evicted from mempools once the chain passes that height, instead
+    /// of after the node's mempool expiry. Like the outputs, the expiry isn't
+    /// covered by the input signatures.
+    pub fn with_expiry(mut self, height: BlockHeight) -> Self {
+        self.expires_at_height = Some(height);
+        self
     }
 
     pub fn hash(&self) -> Hash {
@@ -30,6 +72,57 @@ impl Transaction {
     pub fn outputs(&self) -> &Vec<TransactionOutput> {
         &self.outputs
     }
+
+    pub fn coinbase_tag(&self) -> Option<&[u8]> {
+        self.coinbase_tag.as_deref()
+    }
+
+    pub fn data_outputs(&self) -> &[DataOutput] {
+        &self.data_outputs
+    }
+
+    pub fn expires_at_height(&self) -> Option<BlockHeight> {
+        self.expires_at_height
+    }
+
+    /// Whether the transaction can no longer be mined in the block at `height`.
+    pub fn is_expired_at(&self, height: BlockHeight) -> bool {
+        self.expires_at_height
+            .is_some_and(|expires_at| height > expires_at)
+    }
+
+    /// Size in bytes of the CBOR encoding, as limited by relay policy.
+    pub fn size(&self) -> usize {
+        let mut bytes = Vec::new();
+        self.save(&mut bytes)
+            .expect("BUG: serializing into memory can't fail");
+        bytes.len()
+    }
+
+    /// Whether the data outputs respect `MAX_DATA_OUTPUTS_PER_TX` and `MAX_DATA_OUTPUT_SIZE`.
+    pub fn data_outputs_within_limits(&self) -> bool {
+        self.data_outputs.len() <= crate::MAX_DATA_OUTPUTS_PER_TX
+            && self
+                .data_outputs
+                .iter()
+                .all(DataOutput::is_within_size_limit)
+    }
+
+    /// Hex of the canonical CBOR encoding, the one `SubmitTransaction`
+    /// carries and the hash is taken over, for raw transaction commands
+    /// and pasting into debugging tools.
+    pub fn to_hex(&self) -> String {
+        let mut bytes = vec![];
+        self.save(&mut bytes)
+            .expect("BUG: encoding into memory can't fail");
+        hex::encode(bytes)
+    }
+
+    /// Parses `to_hex` output, ignoring surrounding whitespace.
+    pub fn from_hex(encoded: &str) -> Result<Self> {
+        let bytes = hex::decode(encoded.trim()).map_err(|_| BtcError::InvalidTransaction)?;
+        Transaction::load(bytes.as_slice()).map_err(|_| BtcError::InvalidTransaction)
+    }
 }
 
 impl Saveable for Transaction {
@@ -68,6 +161,25 @@ mod tests {
         assert_eq!(tx.outputs[0].value(), 1000);
     }
 
+    #[test]
+    fn test_transaction_data_output_limits() {
+        let tx = Transaction::new(vec![], vec![create_test_output(1000)]);
+        assert!(tx.data_outputs_within_limits());
+
+        let tx = tx.with_data_output(DataOutput::new(vec![1; crate::MAX_DATA_OUTPUT_SIZE]));
+        assert!(tx.data_outputs_within_limits());
+        assert_eq!(tx.outputs().len(), 1);
+
+        let oversized =
+            tx.clone()
+                .with_data_output(DataOutput::new(vec![1; crate::MAX_DATA_OUTPUT_SIZE + 1]));
+        assert!(!oversized.data_outputs_within_limits());
+
+        let too_many = (0..crate::MAX_DATA_OUTPUTS_PER_TX)
+            .fold(tx, |tx, _| tx.with_data_output(DataOutput::new(vec![])));
+        assert!(!too_many.data_outputs_within_limits());
+    }
+
     #[test]
     fn test_transaction_hash_deterministic() {
         let outputs = vec![create_test_output(1000)];
@@ -103,6 +215,45 @@ mod tests {
         assert_eq!(tx.outputs[0].value(), loaded_tx.outputs[0].value());
     }
 
+    #[test]
+    fn test_transaction_coinbase_tag_changes_hash() {
+        let tx = Transaction::new(vec![], vec![create_test_output(1000)]);
+        let tagged = tx.clone().with_coinbase_tag(b"pool".to_vec());
+
+        assert_eq!(tx.coinbase_tag(), None);
+        assert_eq!(tagged.coinbase_tag(), Some(&b"pool"[..]));
+        assert_ne!(tx.hash(), tagged.hash());
+    }
+
+    #[test]
+    fn test_transaction_expiry() {
+        let tx = Transaction::new(vec![], vec![create_test_output(1000)]);
+        let expiring = tx.clone().with_expiry(BlockHeight::new(5));
+
+        assert!(!tx.is_expired_at(BlockHeight::new(u64::MAX)));
+        assert_eq!(expiring.expires_at_height(), Some(BlockHeight::new(5)));
+        assert!(!expiring.is_expired_at(BlockHeight::new(5)));
+        assert!(expiring.is_expired_at(BlockHeight::new(6)));
+        assert_ne!(tx.hash(), expiring.hash());
+    }
+
+    #[test]
+    fn test_transaction_hex() {
+        let tx = Transaction::new(vec![], vec![create_test_output(1000)])
+            .with_expiry(BlockHeight::new(5));
+        let encoded = tx.to_hex();
+        let decoded = Transaction::from_hex(&format!("  {encoded}\n")).unwrap();
+        assert_eq!(decoded.hash(), tx.hash());
+        assert_eq!(decoded.expires_at_height(), Some(BlockHeight::new(5)));
+        // the hex of the encoding the hash is taken over
+        assert_eq!(decoded.to_hex(), encoded);
+        assert!(matches!(
+            Transaction::from_hex(&encoded[1..]),
+            Err(BtcError::InvalidTransaction)
+        ));
+        assert!(Transaction::from_hex("").is_err());
+    }
+
     #[test]
     fn test_transaction_empty_inputs_outputs() {
         let tx = Transaction::new(vec![], vec![]);
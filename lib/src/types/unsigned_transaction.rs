@@ -0,0 +1,312 @@
+use std::{
+    collections::HashMap,
+    io::{Error as IoError, ErrorKind as IoErrorKind, Read, Result as IoResult, Write},
+    thread,
+};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    crypto::{PrivateKey, PublicKey, Signature},
+    custom_sha_types::Hash,
+    error::{BtcError, Result},
+    network::RelayPolicy,
+    types::{Transaction, TransactionInput, TransactionOutput},
+    utils::Saveable,
+};
+
+// inputs from which `sign_with` spreads the signing over threads
+const PARALLEL_SIGNING_INPUTS: usize = 64;
+
+/// A transaction waiting for its inputs to be signed, the counterpart of
+/// a PSBT: a node assembles it for a watch-only key (`BuildTransaction`)
+/// and whoever holds the private key signs it offline. Each input comes
+/// with the output it spends, so the signer can check the amounts and fee
+/// without asking a node.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UnsignedTransaction {
+    /// The outputs spent, by hash
+    pub inputs: Vec<(Hash, TransactionOutput)>,
+    pub outputs: Vec<TransactionOutput>,
+}
+
+impl UnsignedTransaction {
+    /// Pays `recipients` from `utxos` of `payer`, largest outputs first,
+    /// with the fee `policy` asks for the signed transaction. What is left
+    /// goes back to `payer`, unless it would be dust and goes to the fee.
+    /// Fails with `InsufficientFunds` if the outputs don't cover the
+    /// payments and fee.
+    pub fn fund(
+        utxos: &[(Hash, TransactionOutput)],
+        payer: &PublicKey,
+        recipients: &[(PublicKey, u64)],
+        policy: &RelayPolicy,
+    ) -> Result<Self> {
+        let amount = recipients
+            .iter()
+            .try_fold(0u64, |sum, (_, value)| sum.checked_add(*value))
+            .ok_or(BtcError::InvalidTransactionOutput)?;
+        let mut utxos = utxos.to_vec();
+        utxos.sort_by(|(a_hash, a), (b_hash, b)| {
+            b.value()
+                .cmp(&a.value())
+                .then_with(|| a_hash.as_bytes().cmp(&b_hash.as_bytes()))
+        });
+        let mut unsigned = UnsignedTransaction {
+            inputs: vec![],
+            outputs: recipients
+                .iter()
+                .map(|(key, value)| TransactionOutput::new(*value, Uuid::new_v4(), key.clone()))
+                .collect(),
+        };
+        let placeholder = Signature::largest();
+        let mut needed = amount.saturating_add(policy.min_fee(unsigned.size(&placeholder)));
+        for utxo in utxos {
+            unsigned.inputs.push(utxo);
+            let available = unsigned.input_value();
+            needed = amount.saturating_add(policy.min_fee(unsigned.size(&placeholder)));
+            if available < needed {
+                continue;
+            }
+            // integers encode shorter the smaller they are, so sizing the
+            // change as all of the inputs never falls short of the fee
+            let mut with_change = unsigned.clone();
+            with_change.outputs.push(TransactionOutput::new(
+                available,
+                Uuid::new_v4(),
+                payer.clone(),
+            ));
+            let fee = policy.min_fee(with_change.size(&placeholder));
+            let change = available.saturating_sub(amount.saturating_add(fee));
+            if change >= policy.dust_threshold {
+                let last = with_change.outputs.len() - 1;
+                with_change.outputs[last] =
+                    TransactionOutput::new(change, Uuid::new_v4(), payer.clone());
+                return Ok(with_change);
+            }
+            return Ok(unsigned);
+        }
+        Err(BtcError::InsufficientFunds {
+            available: unsigned.input_value(),
+            needed,
+        })
+    }
+
+    pub fn input_value(&self) -> u64 {
+        self.inputs.iter().map(|(_, output)| output.value()).sum()
+    }
+
+    pub fn output_value(&self) -> u64 {
+        self.outputs.iter().map(TransactionOutput::value).sum()
+    }
+
+    /// What the inputs hold beyond the outputs, zero if they hold less.
+    pub fn fee(&self) -> u64 {
+        self.input_value().saturating_sub(self.output_value())
+    }
+
+    /// Most bytes the transaction can take once signed, which its fee
+    /// pays for.
+    pub fn signed_size(&self) -> usize {
+        self.size(&Signature::largest())
+    }
+
+    /// Signs every input with `key`. Fails with `InvalidPrivateKey` if an
+    /// input pays another key.
+    pub fn sign(&self, key: &PrivateKey) -> Result<Transaction> {
+        self.sign_with(std::slice::from_ref(key))
+    }
+
+    /// Signs every input with the one of `keys` its output pays, e.g. a
+    /// consolidation of the outputs of a whole wallet. Keys are looked up
+    /// by public key, and from `PARALLEL_SIGNING_INPUTS` inputs on the
+    /// signing is spread over a thread per CPU. Fails with
+    /// `InvalidPrivateKey`, signing nothing, if an input pays none of them.
+    pub fn sign_with(&self, keys: &[PrivateKey]) -> Result<Transaction> {
+        let keys: HashMap<PublicKey, &PrivateKey> =
+            keys.iter().map(|key| (key.public_key(), key)).collect();
+        let signers = self
+            .inputs
+            .iter()
+            .map(|(outpoint, output)| Some((outpoint, *keys.get(output.pubkey())?)))
+            .collect::<Option<Vec<_>>>()
+            .ok_or(BtcError::InvalidPrivateKey)?;
+        let sign = |signers: &[(&Hash, &PrivateKey)]| -> Vec<Signature> {
+            signers
+                .iter()
+                .map(|(outpoint, key)| Signature::sign_output(outpoint, key))
+                .collect()
+        };
+        let signatures = if signers.len() < PARALLEL_SIGNING_INPUTS {
+            sign(&signers)
+        } else {
+            let threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+            let chunk = signers.len().div_ceil(threads);
+            thread::scope(|scope| {
+                let handles: Vec<_> = signers
+                    .chunks(chunk)
+                    .map(|chunk| scope.spawn(|| sign(chunk)))
+                    .collect();
+                handles
+                    .into_iter()
+                    .flat_map(|handle| handle.join().expect("BUG: signing can't panic"))
+                    .collect()
+            })
+        };
+        let inputs = signers
+            .iter()
+            .zip(signatures)
+            .map(|((outpoint, _), signature)| TransactionInput::new(**outpoint, signature))
+            .collect();
+        Ok(Transaction::new(inputs, self.outputs.clone()))
+    }
+
+    /// Hex of the CBOR encoding, to carry the transaction to the signer.
+    pub fn to_hex(&self) -> String {
+        let mut bytes = vec![];
+        self.save(&mut bytes)
+            .expect("BUG: encoding into memory can't fail");
+        hex::encode(bytes)
+    }
+
+    /// Parses `to_hex` output, ignoring surrounding whitespace.
+    pub fn from_hex(encoded: &str) -> Result<Self> {
+        let bytes = hex::decode(encoded.trim()).map_err(|_| BtcError::InvalidTransaction)?;
+        UnsignedTransaction::load(bytes.as_slice()).map_err(|_| BtcError::InvalidTransaction)
+    }
+
+    // the size with `placeholder` for every signature
+    fn size(&self, placeholder: &Signature) -> usize {
+        let inputs = self
+            .inputs
+            .iter()
+            .map(|(outpoint, _)| TransactionInput::new(*outpoint, placeholder.clone()))
+            .collect();
+        Transaction::new(inputs, self.outputs.clone()).size()
+    }
+}
+
+impl Saveable for UnsignedTransaction {
+    fn load<I: Read>(reader: I) -> IoResult<Self> {
+        ciborium::de::from_reader(reader).map_err(|_| {
+            IoError::new(
+                IoErrorKind::InvalidData,
+                "Failed to deserialize UnsignedTransaction",
+            )
+        })
+    }
+
+    fn save<O: Write>(&self, writer: O) -> IoResult<()> {
+        ciborium::ser::into_writer(self, writer).map_err(|_| {
+            IoError::new(
+                IoErrorKind::InvalidData,
+                "Failed to serialize UnsignedTransaction",
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utxos(key: &PublicKey, values: &[u64]) -> Vec<(Hash, TransactionOutput)> {
+        values
+            .iter()
+            .map(|value| {
+                let output = TransactionOutput::new(*value, Uuid::new_v4(), key.clone());
+                (output.hash(), output)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_fund_and_sign() {
+        let alice = PrivateKey::from_seed(b"alice");
+        let bob = PrivateKey::from_seed(b"bob").public_key();
+        let policy = RelayPolicy::default();
+        let utxos = utxos(&alice.public_key(), &[1_000, 50_000, 20_000]);
+
+        // the largest output covers it, the rest comes back as change
+        let unsigned = UnsignedTransaction::fund(
+            &utxos,
+            &alice.public_key(),
+            &[(bob.clone(), 30_000)],
+            &policy,
+        )
+        .unwrap();
+        assert_eq!(unsigned.inputs.len(), 1);
+        assert_eq!(unsigned.input_value(), 50_000);
+        assert_eq!(unsigned.outputs.len(), 2);
+        assert_eq!(*unsigned.outputs[1].pubkey(), alice.public_key());
+
+        let loaded = UnsignedTransaction::from_hex(&unsigned.to_hex()).unwrap();
+        let transaction = loaded.sign(&alice).unwrap();
+        let (outpoint, _) = &loaded.inputs[0];
+        assert!(
+            transaction.inputs()[0]
+                .signature()
+                .verify(outpoint, &alice.public_key())
+        );
+        assert_eq!(policy.check(&transaction, loaded.fee()), Ok(()));
+        let mallory = PrivateKey::from_seed(b"mallory");
+        assert!(matches!(
+            loaded.sign(&mallory),
+            Err(BtcError::InvalidPrivateKey)
+        ));
+    }
+
+    #[test]
+    fn test_sign_consolidation() {
+        let alice = PrivateKey::from_seed(b"alice");
+        let bob = PrivateKey::from_seed(b"bob");
+        let mut inputs = utxos(&alice.public_key(), &[1_000; 60]);
+        inputs.extend(utxos(&bob.public_key(), &[2_000; 40]));
+        let unsigned = UnsignedTransaction {
+            outputs: vec![TransactionOutput::new(
+                150_000,
+                Uuid::new_v4(),
+                alice.public_key(),
+            )],
+            inputs,
+        };
+
+        let transaction = unsigned.sign_with(&[bob.clone(), alice.clone()]).unwrap();
+        assert_eq!(transaction.inputs().len(), 100);
+        for (input, (outpoint, output)) in transaction.inputs().iter().zip(&unsigned.inputs) {
+            assert_eq!(input.prev_transaction_output_hash(), outpoint);
+            assert!(input.signature().verify(outpoint, output.pubkey()));
+        }
+        assert!(matches!(
+            unsigned.sign_with(&[alice]),
+            Err(BtcError::InvalidPrivateKey)
+        ));
+    }
+
+    #[test]
+    fn test_fund_leaves_dust_to_the_fee() {
+        let alice = PrivateKey::from_seed(b"alice");
+        let bob = PrivateKey::from_seed(b"bob").public_key();
+        let policy = RelayPolicy::default();
+        let utxos = utxos(&alice.public_key(), &[10_000]);
+
+        let unsigned = UnsignedTransaction::fund(
+            &utxos,
+            &alice.public_key(),
+            &[(bob.clone(), 9_500)],
+            &policy,
+        )
+        .unwrap();
+        assert_eq!(unsigned.outputs.len(), 1);
+        assert_eq!(unsigned.fee(), 500);
+
+        assert!(matches!(
+            UnsignedTransaction::fund(&utxos, &alice.public_key(), &[(bob, 9_999)], &policy),
+            Err(BtcError::InsufficientFunds {
+                available: 10_000,
+                ..
+            })
+        ));
+    }
+}
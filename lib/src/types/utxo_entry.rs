@@ -0,0 +1,206 @@
+use std::fmt;
+
+use serde::{
+    Deserialize, Deserializer, Serialize,
+    de::{self, IgnoredAny, MapAccess, SeqAccess, Visitor, value::MapAccessDeserializer},
+};
+
+use crate::{
+    custom_sha_types::Hash,
+    error::{BtcError, Result},
+    types::TransactionOutput,
+};
+
+/// What an unspent output is set aside for, if anything. Both the node's
+/// UTXO set and wallets keep one per output, so a coin can't be picked
+/// for two transactions at once.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UtxoState {
+    /// Free to spend
+    Unspent,
+    /// Picked by a wallet for the transaction with this hash, which
+    /// hasn't reached the mempool yet
+    ReservedBy(Hash),
+    /// Spent by the mempool transaction with this hash
+    SpentInMempool(Hash),
+}
+
+/// An unspent output and its `UtxoState`, changed only through the
+/// transitions below: `Unspent` to `ReservedBy` to `SpentInMempool`, or
+/// straight to `SpentInMempool`, and back to `Unspent` with `release`.
+#[derive(Serialize, Clone, Debug)]
+pub struct UtxoEntry {
+    output: TransactionOutput,
+    state: UtxoState,
+}
+
+impl UtxoEntry {
+    pub fn new(output: TransactionOutput) -> Self {
+        UtxoEntry {
+            output,
+            state: UtxoState::Unspent,
+        }
+    }
+
+    pub fn output(&self) -> &TransactionOutput {
+        &self.output
+    }
+
+    pub fn state(&self) -> UtxoState {
+        self.state
+    }
+
+    pub fn is_unspent(&self) -> bool {
+        self.state == UtxoState::Unspent
+    }
+
+    pub fn is_spent_in_mempool(&self) -> bool {
+        matches!(self.state, UtxoState::SpentInMempool(_))
+    }
+
+    /// The transaction the output is reserved for or spent by.
+    pub fn spender(&self) -> Option<Hash> {
+        match self.state {
+            UtxoState::Unspent => None,
+            UtxoState::ReservedBy(txid) | UtxoState::SpentInMempool(txid) => Some(txid),
+        }
+    }
+
+    /// Sets the output aside for `txid`. Fails with `DoubleSpending` if
+    /// another transaction has it already.
+    pub fn reserve(&mut self, txid: Hash) -> Result<()> {
+        match self.state {
+            UtxoState::Unspent => {
+                self.state = UtxoState::ReservedBy(txid);
+                Ok(())
+            }
+            UtxoState::ReservedBy(reserved) if reserved == txid => Ok(()),
+            _ => Err(BtcError::DoubleSpending),
+        }
+    }
+
+    /// Records that the mempool transaction `txid` spends the output,
+    /// whether it was reserved for it or not. Fails with `DoubleSpending`
+    /// if another transaction has it.
+    pub fn spend_in_mempool(&mut self, txid: Hash) -> Result<()> {
+        if self.spender().is_some_and(|spender| spender != txid) {
+            return Err(BtcError::DoubleSpending);
+        }
+        self.state = UtxoState::SpentInMempool(txid);
+        Ok(())
+    }
+
+    /// Frees the output if `txid` reserved or spent it, e.g. once the
+    /// transaction is dropped from the mempool. Returns whether it did.
+    pub fn release(&mut self, txid: &Hash) -> bool {
+        if self.spender().as_ref() != Some(txid) {
+            return false;
+        }
+        self.state = UtxoState::Unspent;
+        true
+    }
+}
+
+// chains saved before `UtxoEntry` hold a `(marked, output)` pair per
+// UTXO; the mempool isn't saved, so their marks mean nothing anymore
+impl<'de> Deserialize<'de> for UtxoEntry {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Entry {
+            output: TransactionOutput,
+            state: UtxoState,
+        }
+
+        struct EntryVisitor;
+
+        impl<'de> Visitor<'de> for EntryVisitor {
+            type Value = UtxoEntry;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a UTXO entry, or a marked output")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(
+                self,
+                map: A,
+            ) -> std::result::Result<UtxoEntry, A::Error> {
+                let Entry { output, state } = Entry::deserialize(MapAccessDeserializer::new(map))?;
+                Ok(UtxoEntry { output, state })
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> std::result::Result<UtxoEntry, A::Error> {
+                seq.next_element::<IgnoredAny>()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let output = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                Ok(UtxoEntry::new(output))
+            }
+        }
+
+        deserializer.deserialize_any(EntryVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::PrivateKey;
+    use uuid::Uuid;
+
+    fn entry() -> UtxoEntry {
+        let key = PrivateKey::from_seed(b"alice").public_key();
+        UtxoEntry::new(TransactionOutput::new(1_000, Uuid::new_v4(), key))
+    }
+
+    #[test]
+    fn test_utxo_entry_transitions() {
+        let (first, second) = (Hash::hash(&"first"), Hash::hash(&"second"));
+        let mut utxo = entry();
+        assert!(utxo.is_unspent());
+
+        utxo.reserve(first).unwrap();
+        assert_eq!(utxo.state(), UtxoState::ReservedBy(first));
+        utxo.reserve(first).unwrap();
+        assert!(matches!(
+            utxo.reserve(second),
+            Err(BtcError::DoubleSpending)
+        ));
+        assert!(matches!(
+            utxo.spend_in_mempool(second),
+            Err(BtcError::DoubleSpending)
+        ));
+
+        // the reserving transaction reaches the mempool
+        utxo.spend_in_mempool(first).unwrap();
+        assert_eq!(utxo.state(), UtxoState::SpentInMempool(first));
+        assert!(matches!(utxo.reserve(first), Err(BtcError::DoubleSpending)));
+
+        assert!(!utxo.release(&second));
+        assert!(utxo.release(&first));
+        assert!(utxo.is_unspent());
+        utxo.spend_in_mempool(second).unwrap();
+        assert_eq!(utxo.spender(), Some(second));
+    }
+
+    #[test]
+    fn test_utxo_entry_serialization() {
+        let mut utxo = entry();
+        utxo.reserve(Hash::hash(&"txid")).unwrap();
+        let mut bytes = vec![];
+        ciborium::ser::into_writer(&utxo, &mut bytes).unwrap();
+        let decoded: UtxoEntry = ciborium::de::from_reader(bytes.as_slice()).unwrap();
+        assert_eq!(decoded.state(), utxo.state());
+        assert_eq!(decoded.output().hash(), utxo.output().hash());
+
+        // as saved before, marks dropped
+        let mut bytes = vec![];
+        ciborium::ser::into_writer(&(true, utxo.output()), &mut bytes).unwrap();
+        let decoded: UtxoEntry = ciborium::de::from_reader(bytes.as_slice()).unwrap();
+        assert!(decoded.is_unspent());
+        assert_eq!(decoded.output().hash(), utxo.output().hash());
+    }
+}
@@ -0,0 +1,154 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use env_logger::{Builder, Env, Target, WriteStyle};
+
+/// Where the node, miner and wallet log to, and at which levels. By
+/// default everything goes to stderr, filtered by `RUST_LOG` alone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogConfig {
+    /// Append to this file instead of writing to stderr
+    pub file: Option<PathBuf>,
+    /// Levels per module in `RUST_LOG` syntax, e.g.
+    /// `info,node::handler=debug,btclib::consensus=warn`. `RUST_LOG`, if
+    /// set, overrides them for the modules it names
+    pub filters: Option<String>,
+    /// Size in bytes past which the log file is rotated
+    pub max_size: u64,
+    /// Rotated files kept, `<file>.1` being the most recent
+    pub max_files: usize,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            file: None,
+            filters: None,
+            max_size: 10 * 1024 * 1024,
+            max_files: 5,
+        }
+    }
+}
+
+impl LogConfig {
+    /// Installs the logger for the whole process. Fails if the log file
+    /// can't be opened.
+    pub fn init(&self) -> io::Result<()> {
+        let mut builder = Builder::new();
+        if let Some(filters) = &self.filters {
+            builder.parse_filters(filters);
+        }
+        builder.parse_env(Env::default());
+        if let Some(path) = &self.file {
+            let file = RotatingFile::open(path, self.max_size, self.max_files)?;
+            builder
+                .target(Target::Pipe(Box::new(file)))
+                .write_style(WriteStyle::Never);
+        }
+        builder.init();
+        Ok(())
+    }
+}
+
+/// A log file that is moved aside once it grows past `max_size`: the
+/// current file becomes `<path>.1`, `<path>.1` becomes `<path>.2` and so
+/// on, dropping the oldest beyond `max_files`.
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    /// Opens `path` for appending, creating it if needed.
+    pub fn open(path: impl AsRef<Path>, max_size: u64, max_files: usize) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path,
+            file,
+            size,
+            max_size,
+            max_files,
+        })
+    }
+
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{index}"));
+        name.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        for index in (1..=self.max_files).rev() {
+            let from = match index {
+                1 => self.path.clone(),
+                _ => self.rotated(index - 1),
+            };
+            match fs::rename(&from, self.rotated(index)) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        // moved aside by now, or started over if no rotated files are kept
+        self.file = File::create(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotating_file() {
+        let dir = std::env::temp_dir().join(format!("log-{}", uuid::Uuid::new_v4()));
+        fs::create_dir(&dir).unwrap();
+        let path = dir.join("node.log");
+        let mut file = RotatingFile::open(&path, 10, 2).unwrap();
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        file.flush().unwrap();
+
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read("node.log"), "fourth\n");
+        assert_eq!(read("node.log.1"), "third\n");
+        assert_eq!(read("node.log.2"), "second\n");
+        assert!(!dir.join("node.log.3").exists());
+
+        // reopening appends, and rotates once full
+        let mut file = RotatingFile::open(&path, 12, 0).unwrap();
+        file.write_all(b"end\n").unwrap();
+        assert_eq!(read("node.log"), "fourth\nend\n");
+        file.write_all(b"over\n").unwrap();
+        assert_eq!(read("node.log"), "over\n");
+        assert_eq!(read("node.log.1"), "third\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -13,44 +13,86 @@ impl MerkleRoot {
         }
 
         while layer.len() > 1 {
-            let mut next_layer: Vec<Hash> = Vec::with_capacity(layer.len().div_ceil(2));
-            for pair in layer.chunks(2) {
-                let left = pair[0];
-                let right = pair.get(1).unwrap_or(&pair[0]);
-                next_layer.push(Hash::hash(&[left, *right]));
-            }
-            layer = next_layer;
+            layer = next_layer(&layer);
         }
 
         MerkleRoot(layer[0])
     }
+
+    pub fn as_hash(&self) -> Hash {
+        self.0
+    }
+}
+
+// hashes of the pairs of `layer`, the last hash paired with itself if
+// it has no partner
+fn next_layer(layer: &[Hash]) -> Vec<Hash> {
+    let mut next_layer: Vec<Hash> = Vec::with_capacity(layer.len().div_ceil(2));
+    for pair in layer.chunks(2) {
+        let left = pair[0];
+        let right = pair.get(1).unwrap_or(&pair[0]);
+        next_layer.push(Hash::hash(&[left, *right]));
+    }
+    next_layer
+}
+
+/// What it takes to check that a transaction is in a block without the
+/// block's other transactions: the hashes next to it on the way up to
+/// the Merkle root, lowest first. Lets light wallets trust a header
+/// chain instead of downloading blocks.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MerkleProof {
+    /// Position of the transaction in the block
+    pub index: usize,
+    pub siblings: Vec<Hash>,
+}
+
+impl MerkleProof {
+    /// The proof for the transaction at `index`, None past the end.
+    pub fn new(transactions: &[Transaction], index: usize) -> Option<Self> {
+        if index >= transactions.len() {
+            return None;
+        }
+        let mut layer: Vec<Hash> = transactions.iter().map(Hash::hash).collect();
+        let mut position = index;
+        let mut siblings = vec![];
+        while layer.len() > 1 {
+            siblings.push(*layer.get(position ^ 1).unwrap_or(&layer[position]));
+            layer = next_layer(&layer);
+            position /= 2;
+        }
+        Some(MerkleProof { index, siblings })
+    }
+
+    /// Whether the transaction whose hash is `transaction` is at `index`
+    /// in a block with Merkle root `root`.
+    pub fn verify(&self, transaction: &Hash, root: &MerkleRoot) -> bool {
+        let mut hash = *transaction;
+        let mut position = self.index;
+        for sibling in &self.siblings {
+            hash = if position.is_multiple_of(2) {
+                Hash::hash(&[hash, *sibling])
+            } else {
+                Hash::hash(&[*sibling, hash])
+            };
+            position /= 2;
+        }
+        position == 0 && hash == root.0
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{crypto::PrivateKey, types::TransactionOutput};
-    use uuid::Uuid;
-
-    fn create_test_transaction(value: u64) -> Transaction {
-        let private_key = PrivateKey::default();
-        Transaction::new(
-            vec![],
-            vec![TransactionOutput::new(
-                value,
-                Uuid::new_v4(),
-                private_key.public_key(),
-            )],
-        )
-    }
+    use crate::testutil::coinbase_transaction;
 
     #[test]
     fn test_merkle_root_single_transaction() {
-        let tx = create_test_transaction(1000);
+        let tx = coinbase_transaction(1000);
         let merkle_root = MerkleRoot::calculate(&[tx]);
 
         // The merkle root of a single transaction should be deterministic
-        let tx2 = create_test_transaction(1000);
+        let tx2 = coinbase_transaction(1000);
         let merkle_root2 = MerkleRoot::calculate(&[tx2]);
 
         // Different transactions should have different roots
@@ -59,8 +101,8 @@ mod tests {
 
     #[test]
     fn test_merkle_root_same_transaction() {
-        let tx = create_test_transaction(1000);
-        let merkle_root1 = MerkleRoot::calculate(&[tx.clone()]);
+        let tx = coinbase_transaction(1000);
+        let merkle_root1 = MerkleRoot::calculate(std::slice::from_ref(&tx));
         let merkle_root2 = MerkleRoot::calculate(&[tx]);
 
         // Same transaction should produce same merkle root
@@ -69,8 +111,8 @@ mod tests {
 
     #[test]
     fn test_merkle_root_two_transactions() {
-        let tx1 = create_test_transaction(1000);
-        let tx2 = create_test_transaction(2000);
+        let tx1 = coinbase_transaction(1000);
+        let tx2 = coinbase_transaction(2000);
 
         let merkle_root = MerkleRoot::calculate(&[tx1.clone(), tx2.clone()]);
 
@@ -81,9 +123,9 @@ mod tests {
 
     #[test]
     fn test_merkle_root_odd_number_transactions() {
-        let tx1 = create_test_transaction(1000);
-        let tx2 = create_test_transaction(2000);
-        let tx3 = create_test_transaction(3000);
+        let tx1 = coinbase_transaction(1000);
+        let tx2 = coinbase_transaction(2000);
+        let tx3 = coinbase_transaction(3000);
 
         // Odd number of transactions should duplicate the last one
         let merkle_root = MerkleRoot::calculate(&[tx1.clone(), tx2.clone(), tx3.clone()]);
@@ -95,8 +137,8 @@ mod tests {
 
     #[test]
     fn test_merkle_root_order_matters() {
-        let tx1 = create_test_transaction(1000);
-        let tx2 = create_test_transaction(2000);
+        let tx1 = coinbase_transaction(1000);
+        let tx2 = coinbase_transaction(2000);
 
         let merkle_root1 = MerkleRoot::calculate(&[tx1.clone(), tx2.clone()]);
         let merkle_root2 = MerkleRoot::calculate(&[tx2, tx1]);
@@ -108,7 +150,7 @@ mod tests {
     #[test]
     fn test_merkle_root_many_transactions() {
         let transactions: Vec<Transaction> =
-            (0..8).map(|i| create_test_transaction(i * 1000)).collect();
+            (0..8).map(|i| coinbase_transaction(i * 1000)).collect();
 
         let merkle_root = MerkleRoot::calculate(&transactions);
 
@@ -117,18 +159,46 @@ mod tests {
         assert_eq!(merkle_root, merkle_root2);
     }
 
+    #[test]
+    fn test_merkle_proof() {
+        let transactions: Vec<Transaction> =
+            (0..5).map(|i| coinbase_transaction(i * 1000)).collect();
+        let root = MerkleRoot::calculate(&transactions);
+        for (index, transaction) in transactions.iter().enumerate() {
+            let proof = MerkleProof::new(&transactions, index).unwrap();
+            assert_eq!(proof.siblings.len(), 3);
+            assert!(proof.verify(&transaction.hash(), &root));
+            // not for another transaction, or at another position
+            assert!(!proof.verify(&transactions[(index + 1) % 5].hash(), &root));
+            let moved = MerkleProof {
+                index: index + 8,
+                ..proof
+            };
+            assert!(!moved.verify(&transaction.hash(), &root));
+        }
+        assert!(MerkleProof::new(&transactions, 5).is_none());
+
+        // a block with only a coinbase needs no siblings
+        let proof = MerkleProof::new(&transactions[..1], 0).unwrap();
+        assert!(proof.siblings.is_empty());
+        assert!(proof.verify(
+            &transactions[0].hash(),
+            &MerkleRoot::calculate(&transactions[..1])
+        ));
+    }
+
     #[test]
     fn test_merkle_root_clone_and_eq() {
-        let tx = create_test_transaction(1000);
+        let tx = coinbase_transaction(1000);
         let merkle_root = MerkleRoot::calculate(&[tx]);
 
-        let cloned = merkle_root.clone();
+        let cloned = merkle_root;
         assert_eq!(merkle_root, cloned);
     }
 
     #[test]
     fn test_merkle_root_debug_format() {
-        let tx = create_test_transaction(1000);
+        let tx = coinbase_transaction(1000);
         let merkle_root = MerkleRoot::calculate(&[tx]);
 
         let debug_str = format!("{:?}", merkle_root);
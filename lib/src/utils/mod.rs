@@ -1,5 +1,9 @@
+mod logging;
 mod merkle_root;
 mod saveable;
+mod utxo_filter;
 
+pub use logging::*;
 pub use merkle_root::*;
 pub use saveable::*;
+pub use utxo_filter::*;
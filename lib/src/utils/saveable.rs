@@ -23,28 +23,12 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{
-        crypto::PrivateKey,
-        types::{Transaction, TransactionOutput},
-    };
+    use crate::{testutil::coinbase_transaction, types::Transaction};
     use std::fs;
-    use uuid::Uuid;
-
-    fn create_test_transaction(value: u64) -> Transaction {
-        let private_key = PrivateKey::default();
-        Transaction::new(
-            vec![],
-            vec![TransactionOutput::new(
-                value,
-                Uuid::new_v4(),
-                private_key.public_key(),
-            )],
-        )
-    }
 
     #[test]
     fn test_save_and_load_from_file() {
-        let tx = create_test_transaction(1000);
+        let tx = coinbase_transaction(1000);
         let temp_path = "test_transaction_saveable.cbor";
 
         // Save to file
@@ -63,7 +47,7 @@ mod tests {
 
     #[test]
     fn test_save_and_load_from_memory() {
-        let tx = create_test_transaction(2000);
+        let tx = coinbase_transaction(2000);
         let mut buffer = Vec::new();
 
         // Save to memory buffer
@@ -79,7 +63,7 @@ mod tests {
 
     #[test]
     fn test_save_to_nonexistent_directory() {
-        let tx = create_test_transaction(3000);
+        let tx = coinbase_transaction(3000);
 
         // Try to save to a directory that doesn't exist
         let result = tx.save_to_file("nonexistent_dir/test.cbor");
@@ -99,7 +83,7 @@ mod tests {
 
     #[test]
     fn test_multiple_save_load_cycles() {
-        let tx = create_test_transaction(5000);
+        let tx = coinbase_transaction(5000);
         let temp_path = "test_multiple_cycles.cbor";
 
         // First save and load
@@ -121,7 +105,7 @@ mod tests {
 
     #[test]
     fn test_save_to_empty_path_string() {
-        let tx = create_test_transaction(1000);
+        let tx = coinbase_transaction(1000);
 
         // Empty string should create a file (though it's not a valid practice)
         let result = tx.save_to_file("");
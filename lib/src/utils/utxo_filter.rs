@@ -0,0 +1,140 @@
+use std::fmt;
+
+use crate::custom_sha_types::Hash;
+
+/// Bloom filter over UTXO outpoints, for answering "definitely not
+/// unspent" without touching the UTXO set. A miss is certain; a hit only
+/// means the outpoint may be there. Outpoints can't be removed, so spent
+/// ones keep answering "maybe" until the filter is rebuilt.
+#[derive(Clone)]
+pub struct UtxoFilter {
+    bits: Vec<u64>,
+    capacity: usize,
+    items: usize,
+}
+
+impl UtxoFilter {
+    // about 1% false positives at full capacity
+    const BITS_PER_ITEM: usize = 10;
+    const HASHES: u64 = 7;
+
+    /// An empty filter sized for `capacity` outpoints.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let words = (capacity.max(1) * Self::BITS_PER_ITEM).div_ceil(64);
+        UtxoFilter {
+            bits: vec![0; words],
+            capacity,
+            items: 0,
+        }
+    }
+
+    pub fn from_outpoints<'a>(outpoints: impl ExactSizeIterator<Item = &'a Hash>) -> Self {
+        let mut filter = Self::with_capacity(outpoints.len());
+        for outpoint in outpoints {
+            filter.insert(outpoint);
+        }
+        filter
+    }
+
+    pub fn insert(&mut self, outpoint: &Hash) {
+        for bit in self.bit_positions(outpoint) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+        self.items += 1;
+    }
+
+    /// False if `outpoint` was never inserted.
+    pub fn may_contain(&self, outpoint: &Hash) -> bool {
+        self.bit_positions(outpoint)
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Outpoints inserted so far, counting repeats.
+    pub fn len(&self) -> usize {
+        self.items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items == 0
+    }
+
+    /// Whether more outpoints were inserted than the filter was sized
+    /// for, so that its false positive rate has gone up.
+    pub fn is_saturated(&self) -> bool {
+        self.items > self.capacity
+    }
+
+    // outpoints are already SHA-256 hashes, so two words of one serve
+    // as independent hashes for double hashing
+    fn bit_positions(&self, outpoint: &Hash) -> impl Iterator<Item = usize> + use<> {
+        let bytes = outpoint.as_bytes();
+        let word = |i: usize| {
+            u64::from_le_bytes(bytes[i * 8..i * 8 + 8].try_into().expect("BUG: 8 bytes"))
+        };
+        let (first, second) = (word(0), word(1) | 1);
+        let bits = (self.bits.len() * 64) as u64;
+        (0..Self::HASHES).map(move |i| (first.wrapping_add(i.wrapping_mul(second)) % bits) as usize)
+    }
+}
+
+impl Default for UtxoFilter {
+    fn default() -> Self {
+        Self::with_capacity(0)
+    }
+}
+
+impl fmt::Debug for UtxoFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UtxoFilter")
+            .field("bits", &(self.bits.len() * 64))
+            .field("capacity", &self.capacity)
+            .field("items", &self.items)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outpoint(i: u32) -> Hash {
+        Hash::hash(&i)
+    }
+
+    #[test]
+    fn test_utxo_filter_has_no_false_negatives() {
+        let outpoints: Vec<_> = (0..1000).map(outpoint).collect();
+        let filter = UtxoFilter::from_outpoints(outpoints.iter());
+        assert_eq!(filter.len(), 1000);
+        assert!(!filter.is_saturated());
+        assert!(
+            outpoints
+                .iter()
+                .all(|outpoint| filter.may_contain(outpoint))
+        );
+    }
+
+    #[test]
+    fn test_utxo_filter_false_positive_rate() {
+        let outpoints: Vec<_> = (0..1000).map(outpoint).collect();
+        let filter = UtxoFilter::from_outpoints(outpoints.iter());
+        let false_positives = (1000..11000)
+            .filter(|i| filter.may_contain(&outpoint(*i)))
+            .count();
+        // about 1% expected
+        assert!(false_positives < 300, "{false_positives} false positives");
+    }
+
+    #[test]
+    fn test_empty_utxo_filter() {
+        let filter = UtxoFilter::default();
+        assert!(filter.is_empty());
+        assert!(!filter.may_contain(&outpoint(0)));
+        let mut filter = UtxoFilter::with_capacity(1);
+        filter.insert(&outpoint(0));
+        assert!(filter.may_contain(&outpoint(0)));
+        assert!(!filter.is_saturated());
+        filter.insert(&outpoint(1));
+        assert!(filter.is_saturated());
+    }
+}
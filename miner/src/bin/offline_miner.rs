@@ -1,11 +1,11 @@
 use btclib::{types::Block, utils::Saveable};
 use clap::{Arg, Command};
 use log::info;
+use miner::{log_args, log_config};
+use std::process::exit;
 
 fn main() {
-    env_logger::init();
-
-    let matches = Command::new("CPU Miner")
+    let command = Command::new("CPU Miner")
         .version("1.0")
         .author("Charalampos Polychronakis <polychronakis.h@gmail.com>")
         .about("Reads a block template file, mines the block in specified increments, and prints the original and mined blocks with their hashes")
@@ -21,8 +21,12 @@ fn main() {
                 .required(true)
                 .index(2)
                 .value_parser(clap::value_parser!(usize)),
-        )
-        .get_matches();
+        );
+    let matches = log_args(command).get_matches();
+    if let Err(e) = log_config(&matches).init() {
+        eprintln!("Failed to open the log file: {e}");
+        exit(1);
+    }
 
     // Get block path and steps count from clap matches
     let path = matches.get_one::<String>("block_file").unwrap().to_string();
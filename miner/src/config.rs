@@ -0,0 +1,457 @@
+use std::{fs, path::PathBuf, str::FromStr, time::Duration};
+
+use anyhow::{Context, Result};
+use clap::{Arg, ArgAction, ArgMatches, Command, ValueEnum, value_parser};
+use serde::Deserialize;
+
+/// The configuration file `init_config` writes, shipped in release
+/// packages as `config/miner.toml`.
+pub const CONFIG_TEMPLATE: &str = include_str!("../../dist/miner.toml");
+
+/// What the online miner does when it fails, e.g. because its node went
+/// away or refused a block.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// Exit with an error
+    #[default]
+    Never,
+    /// Mine for the next node after `restart_delay`
+    OnFailure,
+}
+
+/// The online miner's options, from a `miner.toml` and the command line:
+///
+/// ```toml
+/// nodes = ["127.0.0.1:9000", "backup.example.com:9000"]
+/// public_key_file = "miner.pub.pem"
+/// threads = 4
+/// throttle = 50
+/// stats_interval_secs = 60
+/// restart = "on-failure"
+/// restart_delay_secs = 5
+/// tag = "my pool"
+/// metrics_port = 9100
+/// ```
+///
+/// Options left out take the same defaults as on the command line.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MinerConfig {
+    /// Nodes to mine for, moving on to the next whenever the miner fails
+    pub nodes: Vec<String>,
+    /// Public key the block rewards pay
+    pub public_key_file: PathBuf,
+    #[serde(default = "default_threads")]
+    pub threads: usize,
+    /// Share of the time the threads spend hashing, in percent
+    #[serde(default = "default_throttle")]
+    pub throttle: u8,
+    /// Seconds between logs of the hashrate and blocks found, 0 for none
+    #[serde(default = "default_stats_interval_secs")]
+    pub stats_interval_secs: u64,
+    #[serde(default)]
+    pub restart: RestartPolicy,
+    #[serde(default = "default_restart_delay_secs")]
+    pub restart_delay_secs: u64,
+    /// Embedded in the coinbase of mined blocks
+    pub tag: Option<String>,
+    /// Authority nodes to have mined blocks co-signed by
+    #[serde(default)]
+    pub cosigners: Vec<String>,
+    /// Port to serve metrics to Prometheus on
+    pub metrics_port: Option<u16>,
+}
+
+fn default_threads() -> usize {
+    1
+}
+
+fn default_throttle() -> u8 {
+    100
+}
+
+fn default_stats_interval_secs() -> u64 {
+    60
+}
+
+fn default_restart_delay_secs() -> u64 {
+    5
+}
+
+impl Default for MinerConfig {
+    fn default() -> Self {
+        MinerConfig {
+            nodes: vec![],
+            public_key_file: PathBuf::new(),
+            threads: default_threads(),
+            throttle: default_throttle(),
+            stats_interval_secs: default_stats_interval_secs(),
+            restart: RestartPolicy::default(),
+            restart_delay_secs: default_restart_delay_secs(),
+            tag: None,
+            cosigners: vec![],
+            metrics_port: None,
+        }
+    }
+}
+
+impl FromStr for MinerConfig {
+    type Err = toml::de::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        toml::from_str(s)
+    }
+}
+
+impl MinerConfig {
+    pub fn load(path: &str) -> Result<Self> {
+        let contents =
+            fs::read_to_string(path).with_context(|| format!("failed to read config {path}"))?;
+        contents
+            .parse()
+            .with_context(|| format!("invalid config {path}"))
+    }
+
+    /// The configuration file given with `--config`, if any, with the
+    /// options given on the command line of `miner_args` over it.
+    pub fn from_args(matches: &ArgMatches) -> Result<Self> {
+        let mut config = match matches.get_one::<String>("config") {
+            Some(path) => Self::load(path)?,
+            None => Self::default(),
+        };
+        if let Some(address) = matches.get_one::<String>("address") {
+            config.nodes = vec![address.clone()];
+        }
+        if let Some(file) = matches.get_one::<String>("public_key_file") {
+            config.public_key_file = file.into();
+        }
+        if let Some(threads) = matches.get_one::<usize>("threads") {
+            config.threads = *threads;
+        }
+        if let Some(throttle) = matches.get_one::<u8>("throttle") {
+            config.throttle = *throttle;
+        }
+        if let Some(secs) = matches.get_one::<u64>("stats_interval") {
+            config.stats_interval_secs = *secs;
+        }
+        if let Some(restart) = matches.get_one::<RestartPolicy>("restart") {
+            config.restart = *restart;
+        }
+        if let Some(secs) = matches.get_one::<u64>("restart_delay") {
+            config.restart_delay_secs = *secs;
+        }
+        if let Some(tag) = matches.get_one::<String>("tag") {
+            config.tag = Some(tag.clone());
+        }
+        if let Some(cosigners) = matches.get_many::<String>("cosigner") {
+            config.cosigners = cosigners.cloned().collect();
+        }
+        if let Some(port) = matches.get_one::<u16>("metrics_port") {
+            config.metrics_port = Some(*port);
+        }
+        config.check()?;
+        Ok(config)
+    }
+
+    /// Fails on options the miner can't run with.
+    pub fn check(&self) -> Result<()> {
+        anyhow::ensure!(
+            !self.nodes.is_empty(),
+            "no node to mine for, give an address or a config file"
+        );
+        for node in &self.nodes {
+            anyhow::ensure!(
+                node.matches(':').count() == 1,
+                "invalid node address '{node}', expected host:port (e.g. 127.0.0.1:9000)"
+            );
+        }
+        anyhow::ensure!(
+            !self.public_key_file.as_os_str().is_empty(),
+            "no public key file, give one or a config file"
+        );
+        anyhow::ensure!(
+            self.tag
+                .as_ref()
+                .is_none_or(|tag| tag.len() <= btclib::MAX_COINBASE_TAG_SIZE),
+            "coinbase tag exceeds {} bytes",
+            btclib::MAX_COINBASE_TAG_SIZE
+        );
+        anyhow::ensure!(self.threads > 0, "threads must be at least 1");
+        anyhow::ensure!(
+            (1..=100).contains(&self.throttle),
+            "throttle must be a percentage from 1 to 100"
+        );
+        Ok(())
+    }
+
+    pub fn stats_interval(&self) -> Option<Duration> {
+        (self.stats_interval_secs > 0).then(|| Duration::from_secs(self.stats_interval_secs))
+    }
+
+    pub fn restart_delay(&self) -> Duration {
+        Duration::from_secs(self.restart_delay_secs)
+    }
+
+    pub fn coinbase_tag(&self) -> Option<Vec<u8>> {
+        self.tag.as_ref().map(|tag| tag.as_bytes().to_vec())
+    }
+}
+
+/// Adds the online miner's options and its `init` command to a command
+/// line, see `MinerConfig::from_args` and `InitOptions::from_args`.
+pub fn miner_args(command: Command) -> Command {
+    command
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
+        .arg(
+            Arg::new("address")
+                .help("Node to connect to (e.g. 127.0.0.1:9000), instead of the config's nodes")
+                .required_unless_present("config")
+                .index(1),
+        )
+        .arg(
+            Arg::new("public_key_file")
+                .help("Public key file the block rewards pay")
+                .required_unless_present("config")
+                .index(2),
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .help("Read the options from this miner.toml, see `init`"),
+        )
+        .arg(
+            Arg::new("threads")
+                .long("threads")
+                .value_parser(value_parser!(usize))
+                .help("Mining threads [default: 1]"),
+        )
+        .arg(
+            Arg::new("throttle")
+                .long("throttle")
+                .value_name("PERCENT")
+                .value_parser(value_parser!(u8).range(1..=100))
+                .help("Share of the time the threads spend hashing [default: 100]"),
+        )
+        .arg(
+            Arg::new("stats_interval")
+                .long("stats-interval")
+                .value_name("SECS")
+                .value_parser(value_parser!(u64))
+                .help("Seconds between logs of the hashrate and blocks found, 0 for none [default: 60]"),
+        )
+        .arg(
+            Arg::new("restart")
+                .long("restart")
+                .value_parser(value_parser!(RestartPolicy))
+                .help("Mine for the next node when the miner fails, or exit [default: never]"),
+        )
+        .arg(
+            Arg::new("restart_delay")
+                .long("restart-delay")
+                .value_name("SECS")
+                .value_parser(value_parser!(u64))
+                .help("Seconds to wait before mining for the next node [default: 5]"),
+        )
+        .arg(
+            Arg::new("tag")
+                .long("tag")
+                .help("Short tag (e.g. pool name) to embed in the coinbase of mined blocks"),
+        )
+        .arg(
+            Arg::new("cosigner")
+                .long("cosigner")
+                .value_delimiter(',')
+                .help("Authority nodes to have mined blocks co-signed by, on a permissioned chain"),
+        )
+        .arg(
+            Arg::new("metrics_port")
+                .long("metrics-port")
+                .value_parser(value_parser!(u16))
+                .help("Serve hashrate, template age and block counts to Prometheus on this port"),
+        )
+        .subcommand(
+            Command::new("init")
+                .about("Writes a miner.toml to start from")
+                .arg(
+                    Arg::new("path")
+                        .help("Where to write it")
+                        .default_value("miner.toml")
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("node")
+                        .long("node")
+                        .value_delimiter(',')
+                        .default_value("127.0.0.1:9000")
+                        .help("Nodes to mine for"),
+                )
+                .arg(
+                    Arg::new("public_key_file")
+                        .long("public-key-file")
+                        .default_value("miner.pub.pem")
+                        .help("Public key file the block rewards pay"),
+                )
+                .arg(
+                    Arg::new("threads")
+                        .long("threads")
+                        .value_parser(value_parser!(usize))
+                        .help("Mining threads [default: one per CPU]"),
+                )
+                .arg(
+                    Arg::new("force")
+                        .long("force")
+                        .action(ArgAction::SetTrue)
+                        .help("Replace an existing file"),
+                ),
+        )
+}
+
+/// What `online_miner init` writes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InitOptions {
+    pub path: PathBuf,
+    pub nodes: Vec<String>,
+    pub public_key_file: PathBuf,
+    pub threads: usize,
+    /// Replace an existing file
+    pub force: bool,
+}
+
+impl InitOptions {
+    /// The options of the `init` command, if it was given.
+    pub fn from_args(matches: &ArgMatches) -> Option<Self> {
+        let matches = matches.subcommand_matches("init")?;
+        let threads = matches
+            .get_one::<usize>("threads")
+            .copied()
+            .unwrap_or_else(|| {
+                std::thread::available_parallelism()
+                    .map(Into::into)
+                    .unwrap_or(1)
+            });
+        Some(InitOptions {
+            path: matches.get_one::<String>("path").unwrap().into(),
+            nodes: matches
+                .get_many::<String>("node")
+                .unwrap()
+                .cloned()
+                .collect(),
+            public_key_file: matches.get_one::<String>("public_key_file").unwrap().into(),
+            threads,
+            force: matches.get_flag("force"),
+        })
+    }
+}
+
+/// Fills in `CONFIG_TEMPLATE`.
+pub fn render_config(options: &InitOptions) -> String {
+    // TOML values, so addresses and paths with quotes stay intact
+    let nodes = toml::Value::Array(
+        options
+            .nodes
+            .iter()
+            .cloned()
+            .map(toml::Value::String)
+            .collect(),
+    );
+    let public_key_file =
+        toml::Value::String(options.public_key_file.to_string_lossy().into_owned());
+    CONFIG_TEMPLATE
+        .replace("{nodes}", &nodes.to_string())
+        .replace("{public_key_file}", &public_key_file.to_string())
+        .replace("{threads}", &options.threads.to_string())
+}
+
+/// Writes a configuration file to run the miner from with `--config`,
+/// refusing to replace one unless `force` is set. The public key file
+/// doesn't have to exist yet.
+pub fn init_config(options: &InitOptions) -> Result<PathBuf> {
+    anyhow::ensure!(
+        options.force || !options.path.exists(),
+        "{} already exists, pass --force to replace it",
+        options.path.display()
+    );
+    let contents = render_config(options);
+    let config: MinerConfig = contents.parse().context("invalid config template")?;
+    config.check()?;
+    fs::write(&options.path, contents)
+        .with_context(|| format!("failed to write {}", options.path.display()))?;
+    Ok(options.path.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_with_overrides() {
+        let dir = std::env::temp_dir().join(format!("miner-config-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("miner.toml");
+        let options = InitOptions {
+            path: path.clone(),
+            nodes: vec!["127.0.0.1:9000".to_string(), "backup:9000".to_string()],
+            public_key_file: dir.join("miner.pub.pem"),
+            threads: 2,
+            force: false,
+        };
+        assert_eq!(init_config(&options).unwrap(), path);
+        assert!(init_config(&options).is_err());
+
+        let path = path.to_string_lossy().into_owned();
+        let config = MinerConfig::load(&path).unwrap();
+        assert_eq!(config.nodes, options.nodes);
+        assert_eq!(config.public_key_file, options.public_key_file);
+        assert_eq!(config.threads, 2);
+        assert_eq!(config.restart, RestartPolicy::OnFailure);
+        assert_eq!(config.stats_interval(), Some(Duration::from_secs(60)));
+
+        let command = miner_args(Command::new("online_miner"));
+        let matches = command.clone().get_matches_from([
+            "online_miner",
+            "--config",
+            &path,
+            "--threads",
+            "8",
+            "--restart",
+            "never",
+            "--stats-interval",
+            "0",
+        ]);
+        let overridden = MinerConfig::from_args(&matches).unwrap();
+        assert_eq!(overridden.nodes, options.nodes);
+        assert_eq!(overridden.threads, 8);
+        assert_eq!(overridden.restart, RestartPolicy::Never);
+        assert_eq!(overridden.stats_interval(), None);
+
+        // positional arguments alone still do without a file
+        let matches =
+            command
+                .clone()
+                .get_matches_from(["online_miner", "localhost:9000", "alice.pub.pem"]);
+        let config = MinerConfig::from_args(&matches).unwrap();
+        assert_eq!(config.nodes, vec!["localhost:9000".to_string()]);
+        assert_eq!(config.threads, 1);
+        assert_eq!(config.restart, RestartPolicy::Never);
+        assert!(
+            command
+                .clone()
+                .try_get_matches_from(["online_miner"])
+                .is_err()
+        );
+
+        let matches = command.get_matches_from(["online_miner", "init", "--threads", "3"]);
+        let init = InitOptions::from_args(&matches).unwrap();
+        assert_eq!(init.path, PathBuf::from("miner.toml"));
+        assert_eq!(init.threads, 3);
+
+        assert!(
+            "nodes = []\npublic_key_file = \"k\"\nthreads = 1\nspeed = 2"
+                .parse::<MinerConfig>()
+                .is_err()
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
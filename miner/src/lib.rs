@@ -1,3 +1,9 @@
+pub mod config;
+pub mod logging;
+pub mod metrics;
 pub mod miner;
 
-pub use miner::Miner;
+pub use config::{InitOptions, MinerConfig, RestartPolicy, init_config, miner_args};
+pub use logging::{log_args, log_config};
+pub use metrics::{MinerMetrics, serve_metrics};
+pub use miner::{Miner, mine};
@@ -0,0 +1,74 @@
+use btclib::utils::LogConfig;
+use clap::{Arg, ArgMatches, Command, value_parser};
+
+/// Adds the options of `log_config` to a miner's command line.
+pub fn log_args(command: Command) -> Command {
+    command
+        .arg(
+            Arg::new("log_file")
+                .long("log-file")
+                .help("Log to this file instead of stderr, rotating it as it grows"),
+        )
+        .arg(
+            Arg::new("log_filter")
+                .long("log-filter")
+                .help("Log levels per module, e.g. info,miner=debug (RUST_LOG overrides them)"),
+        )
+        .arg(
+            Arg::new("log_max_size")
+                .long("log-max-size")
+                .value_name("MB")
+                .value_parser(value_parser!(u64))
+                .help("Megabytes past which the log file is rotated [default: 10]"),
+        )
+        .arg(
+            Arg::new("log_files")
+                .long("log-files")
+                .value_parser(value_parser!(usize))
+                .help("Rotated log files kept [default: 5]"),
+        )
+}
+
+/// The default `LogConfig` with the overrides given on the command line.
+pub fn log_config(matches: &ArgMatches) -> LogConfig {
+    let default = LogConfig::default();
+    LogConfig {
+        file: matches.get_one::<String>("log_file").map(Into::into),
+        filters: matches.get_one::<String>("log_filter").cloned(),
+        max_size: matches
+            .get_one::<u64>("log_max_size")
+            .map(|mb| mb.saturating_mul(1024 * 1024))
+            .unwrap_or(default.max_size),
+        max_files: matches
+            .get_one::<usize>("log_files")
+            .copied()
+            .unwrap_or(default.max_files),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_config() {
+        let command = log_args(Command::new("miner"));
+        let matches = command.clone().get_matches_from(["miner"]);
+        assert_eq!(log_config(&matches), LogConfig::default());
+
+        let matches = command.get_matches_from([
+            "miner",
+            "--log-file",
+            "miner.log",
+            "--log-filter",
+            "miner=debug",
+            "--log-max-size",
+            "2",
+        ]);
+        let config = log_config(&matches);
+        assert_eq!(config.file, Some("miner.log".into()));
+        assert_eq!(config.filters.as_deref(), Some("miner=debug"));
+        assert_eq!(config.max_size, 2 * 1024 * 1024);
+        assert_eq!(config.max_files, LogConfig::default().max_files);
+    }
+}
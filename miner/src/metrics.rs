@@ -0,0 +1,231 @@
+use std::{
+    collections::HashSet,
+    fmt::Write,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use btclib::custom_sha_types::Hash;
+use log::{debug, info};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time,
+};
+
+// request line and headers taken from one scrape
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+// a scraper that sends nothing for this long is dropped
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What a miner has done since it started, served in the Prometheus text
+/// format by `serve_metrics`.
+#[derive(Debug, Default)]
+pub struct MinerMetrics {
+    hashes: AtomicU64,
+    // of each mining thread, by index
+    hashrates: Mutex<Vec<u64>>,
+    template_received: Mutex<Option<Instant>>,
+    blocks_submitted: AtomicU64,
+    blocks_accepted: AtomicU64,
+    // submitted blocks not yet seen as a tip
+    pending: Mutex<HashSet<Hash>>,
+}
+
+impl MinerMetrics {
+    /// Counts `hashes` nonces tried by mining thread `thread` in
+    /// `elapsed`, which also sets that thread's hashrate.
+    pub fn record_hashes(&self, thread: usize, hashes: u64, elapsed: Duration) {
+        self.hashes.fetch_add(hashes, Ordering::Relaxed);
+        if !elapsed.is_zero() {
+            let rate = hashes as f64 / elapsed.as_secs_f64();
+            let mut hashrates = self.hashrates.lock().unwrap();
+            if hashrates.len() <= thread {
+                hashrates.resize(thread + 1, 0);
+            }
+            hashrates[thread] = rate as u64;
+        }
+    }
+
+    pub fn record_template(&self) {
+        *self.template_received.lock().unwrap() = Some(Instant::now());
+    }
+
+    pub fn record_submitted(&self, hash: Hash) {
+        self.blocks_submitted.fetch_add(1, Ordering::Relaxed);
+        self.pending.lock().unwrap().insert(hash);
+    }
+
+    /// Counts a submitted block as accepted once the node reports it as
+    /// its best block.
+    pub fn record_tip(&self, hash: &Hash) {
+        if self.pending.lock().unwrap().remove(hash) {
+            self.blocks_accepted.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Nonces tried per second over the last mining round of each
+    /// thread, summed.
+    pub fn hashrate(&self) -> u64 {
+        self.hashrates.lock().unwrap().iter().sum()
+    }
+
+    pub fn blocks_submitted(&self) -> u64 {
+        self.blocks_submitted.load(Ordering::Relaxed)
+    }
+
+    pub fn blocks_accepted(&self) -> u64 {
+        self.blocks_accepted.load(Ordering::Relaxed)
+    }
+
+    /// Time since the current template was fetched, if there is one.
+    pub fn template_age(&self) -> Option<Duration> {
+        self.template_received
+            .lock()
+            .unwrap()
+            .map(|received| received.elapsed())
+    }
+
+    /// The metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+            let _ = writeln!(text, "# HELP {name} {help}");
+            let _ = writeln!(text, "# TYPE {name} {kind}");
+            let _ = writeln!(text, "{name} {value}");
+        };
+        metric(
+            "miner_hashes_total",
+            "counter",
+            "Nonces tried",
+            self.hashes.load(Ordering::Relaxed).to_string(),
+        );
+        metric(
+            "miner_hashrate",
+            "gauge",
+            "Nonces tried per second over the last mining round, summed over the threads",
+            self.hashrate().to_string(),
+        );
+        if let Some(age) = self.template_age() {
+            metric(
+                "miner_template_age_seconds",
+                "gauge",
+                "Time since the current template was fetched",
+                format!("{:.3}", age.as_secs_f64()),
+            );
+        }
+        metric(
+            "miner_blocks_submitted_total",
+            "counter",
+            "Mined blocks submitted to the node",
+            self.blocks_submitted().to_string(),
+        );
+        metric(
+            "miner_blocks_accepted_total",
+            "counter",
+            "Submitted blocks the node made its best block",
+            self.blocks_accepted().to_string(),
+        );
+        text
+    }
+}
+
+/// Answers `GET /metrics` with `metrics` until the task is dropped.
+pub async fn serve_metrics(listener: TcpListener, metrics: Arc<MinerMetrics>) {
+    if let Ok(addr) = listener.local_addr() {
+        info!("Serving metrics on http://{addr}/metrics");
+    }
+    loop {
+        let Ok((socket, peer)) = listener.accept().await else {
+            continue;
+        };
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            if let Err(e) = answer_scrape(socket, &metrics).await {
+                debug!("metrics request from {peer} failed: {e}");
+            }
+        });
+    }
+}
+
+async fn answer_scrape(mut socket: TcpStream, metrics: &MinerMetrics) -> std::io::Result<()> {
+    let mut request = vec![];
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = time::timeout(REQUEST_TIMEOUT, socket.read(&mut buffer))
+            .await
+            .map_err(|_| std::io::ErrorKind::TimedOut)??;
+        if read == 0 || request.len() + read > MAX_REQUEST_SIZE {
+            return Ok(());
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+    let request_line = String::from_utf8_lossy(&request);
+    let (status, body) = match request_line.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+        ["GET", "/metrics"] => ("200 OK", metrics.render()),
+        _ => ("404 Not Found", String::new()),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics_render() {
+        let metrics = MinerMetrics::default();
+        assert!(!metrics.render().contains("miner_template_age_seconds"));
+
+        metrics.record_hashes(0, 2_000, Duration::from_secs(2));
+        metrics.record_hashes(2, 1_000, Duration::from_secs(2));
+        metrics.record_template();
+        let mined = Hash::hash(&"mined");
+        metrics.record_submitted(mined);
+        metrics.record_tip(&Hash::hash(&"someone else's"));
+        assert_eq!(metrics.blocks_accepted(), 0);
+        metrics.record_tip(&mined);
+        metrics.record_tip(&mined);
+        assert_eq!(metrics.blocks_accepted(), 1);
+
+        let text = metrics.render();
+        assert!(text.contains("# TYPE miner_hashes_total counter\nminer_hashes_total 3000\n"));
+        assert!(text.contains("\nminer_hashrate 1500\n"));
+        assert!(text.contains("\nminer_template_age_seconds "));
+        assert!(text.contains("\nminer_blocks_submitted_total 1\n"));
+        assert!(text.contains("\nminer_blocks_accepted_total 1\n"));
+    }
+
+    #[tokio::test]
+    async fn test_serve_metrics() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let metrics = Arc::new(MinerMetrics::default());
+        metrics.record_hashes(0, 5, Duration::from_secs(1));
+        let server = tokio::spawn(serve_metrics(listener, metrics));
+
+        let get = |path: &'static str| async move {
+            let mut socket = TcpStream::connect(addr).await.unwrap();
+            let request = format!("GET {path} HTTP/1.1\r\nHost: miner\r\n\r\n");
+            socket.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            socket.read_to_string(&mut response).await.unwrap();
+            response
+        };
+        let response = get("/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("miner_blocks_accepted_total 0\n"));
+        assert!(response.contains("\nminer_hashes_total 5\n"));
+        assert!(get("/").await.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        server.abort();
+    }
+}
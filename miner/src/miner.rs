@@ -4,50 +4,134 @@ use std::{
         atomic::{AtomicBool, Ordering},
     },
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use anyhow::{Result, anyhow};
-use btclib::{crypto::PublicKey, network::Message, types::Block};
+use btclib::{client::NodeClient, crypto::PublicKey, network::ChainTip, types::Block};
 use flume::{Receiver, Sender};
-use log::{info, warn};
-use tokio::{net::TcpStream, sync::Mutex, time::interval};
+use log::{error, info, warn};
+use tokio::{
+    sync::Mutex,
+    time::{interval, sleep},
+};
+
+use crate::{
+    config::{MinerConfig, RestartPolicy},
+    metrics::MinerMetrics,
+};
+
+// nonces tried between checks whether the template was replaced
+const MINING_STEPS: usize = 10_000_000;
 
 pub struct Miner {
+    address: String,
     public_key: PublicKey,
-    stream: Mutex<TcpStream>,
+    coinbase_tag: Option<Vec<u8>>,
+    /// Authority nodes asked to co-sign mined blocks before submitting
+    cosigners: Vec<String>,
+    threads: usize,
+    /// Share of the time the threads spend hashing, in percent
+    throttle: u8,
+    client: Mutex<NodeClient>,
     current_template: Arc<std::sync::Mutex<Option<Block>>>,
     mining: Arc<AtomicBool>,
+    // set once `run` returns, so the threads stop with this miner
+    stopped: Arc<AtomicBool>,
     mined_block_sender: Sender<Block>,
     mined_block_receiver: Receiver<Block>,
-    mining_thread_handle: Arc<std::sync::Mutex<Option<std::thread::JoinHandle<()>>>>,
+    mining_thread_handles: std::sync::Mutex<Vec<std::thread::JoinHandle<()>>>,
+    metrics: Arc<MinerMetrics>,
 }
 
 impl Miner {
-    pub async fn new(address: String, public_key: PublicKey) -> Result<Self> {
-        let stream = TcpStream::connect(&address).await?;
+    pub async fn new(
+        address: String,
+        public_key: PublicKey,
+        coinbase_tag: Option<Vec<u8>>,
+    ) -> Result<Self> {
+        if coinbase_tag
+            .as_ref()
+            .is_some_and(|tag| tag.len() > btclib::MAX_COINBASE_TAG_SIZE)
+        {
+            return Err(anyhow!(
+                "Coinbase tag exceeds {} bytes",
+                btclib::MAX_COINBASE_TAG_SIZE
+            ));
+        }
+        let client = NodeClient::connect(&address).await?;
         let (mined_block_sender, mined_block_receiver) = flume::unbounded();
         Ok(Self {
+            address,
             public_key,
-            stream: Mutex::new(stream),
+            coinbase_tag,
+            cosigners: vec![],
+            threads: 1,
+            throttle: 100,
+            client: Mutex::new(client),
             current_template: Arc::new(std::sync::Mutex::new(None)),
             mining: Arc::new(AtomicBool::new(false)),
+            stopped: Arc::new(AtomicBool::new(false)),
             mined_block_sender,
             mined_block_receiver,
-            mining_thread_handle: Arc::new(std::sync::Mutex::new(None)),
+            mining_thread_handles: std::sync::Mutex::new(vec![]),
+            metrics: Arc::new(MinerMetrics::default()),
         })
     }
 
+    /// Has the authority nodes at `cosigners` co-sign every mined block
+    /// before it is submitted, on a permissioned chain. The node mined
+    /// for adds its own signature if it is an authority too.
+    pub fn with_cosigners(mut self, cosigners: Vec<String>) -> Self {
+        self.cosigners = cosigners;
+        self
+    }
+
+    /// Mines on `threads` threads, each searching its own range of
+    /// nonces, spending `throttle` percent of the time hashing.
+    pub fn with_threads(mut self, threads: usize, throttle: u8) -> Self {
+        self.threads = threads.max(1);
+        self.throttle = throttle.clamp(1, 100);
+        self
+    }
+
+    /// Counts into `metrics` instead of the miner's own, so they carry
+    /// over from one miner to the next.
+    pub fn with_metrics(mut self, metrics: Arc<MinerMetrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Hashrate, template age and block counts, e.g. for `serve_metrics`.
+    pub fn metrics(&self) -> Arc<MinerMetrics> {
+        self.metrics.clone()
+    }
+
+    /// Mines until `running` is cleared or talking to the node fails,
+    /// then stops the mining threads.
     pub async fn run(&self, running: Arc<AtomicBool>) -> Result<()> {
-        self.spawn_mining_thread();
-        
+        for thread in 0..self.threads {
+            self.spawn_mining_thread(thread, running.clone());
+        }
+        let result = self.mine_blocks(&running).await;
+        self.stopped.store(true, Ordering::SeqCst);
+        self.mining.store(false, Ordering::SeqCst);
+        for handle in self.mining_thread_handles.lock().unwrap().drain(..) {
+            handle.join().expect("Failed to join mining thread");
+        }
+        result
+    }
+
+    async fn mine_blocks(&self, running: &AtomicBool) -> Result<()> {
+        let tip_receiver = self.subscribe_tips().await?;
+
         let mut template_interval = interval(Duration::from_secs(5));
         // Skip the first tick since intervals tick immediately
         template_interval.tick().await;
-        
+
         // Fetch initial template immediately upon connection
         self.fetch_template().await?;
-        
+
         loop {
             if !running.load(Ordering::SeqCst) {
                 info!("Miner shutdown signal received. Exiting run loop.");
@@ -61,45 +145,96 @@ impl Miner {
                 Ok(mined_block) = receiver_clone.recv_async() => {
                     self.submit_block(mined_block).await?;
                 }
+                Ok(tip) = tip_receiver.recv_async() => {
+                    self.metrics.record_tip(&tip.hash);
+                    info!("Chain tip moved to height {}, refreshing template", tip.height);
+                    self.fetch_template().await?;
+                }
             }
         }
-        // Signal mining thread to stop and join it
-        self.mining.store(false, Ordering::SeqCst);
-        if let Some(handle) = self.mining_thread_handle.lock().unwrap().take() {
-            handle.join().expect("Failed to join mining thread");
-        }
         Ok(())
     }
 
-    fn spawn_mining_thread(&self) {
+    /// Opens a dedicated tip subscription and forwards every best block
+    /// change, so a stale template is replaced without waiting for the next poll.
+    async fn subscribe_tips(&self) -> Result<Receiver<ChainTip>> {
+        let mut subscription = NodeClient::connect(&self.address)
+            .await?
+            .subscribe_tips()
+            .await?;
+        let (tip_sender, tip_receiver) = flume::unbounded();
+        tokio::spawn(async move {
+            loop {
+                match subscription.next().await {
+                    Ok(tip) => {
+                        if tip_sender.send_async(tip).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        error!("Tip subscription ended: {}", e);
+                        break;
+                    }
+                }
+            }
+        });
+        Ok(tip_receiver)
+    }
+
+    // mining thread `index` of `self.threads` starts at its share of
+    // the nonces, so no two threads try the same one
+    fn spawn_mining_thread(&self, index: usize, running: Arc<AtomicBool>) {
         let template = self.current_template.clone();
         let mining = self.mining.clone();
+        let stopped = self.stopped.clone();
         let sender = self.mined_block_sender.clone();
+        let metrics = self.metrics.clone();
+        let first_nonce = index as u64 * (u64::MAX / self.threads as u64);
+        let throttle = u32::from(self.throttle);
         let handle = thread::spawn(move || {
-            thread::yield_now();
-            thread::sleep(Duration::from_millis(10));
-            loop {
-                if mining.load(Ordering::SeqCst) && template.lock().unwrap().is_some() {
-                    let mut block = template.lock().unwrap().clone().unwrap();
-                    info!("Mining block with target: {}", block.header().target());
-                    // Keep mining until we find a valid block or mining is stopped
-                    while mining.load(Ordering::SeqCst) {
-                        if block.mine(10_000_000) {
+            // Exit once the miner is shut down
+            while running.load(Ordering::SeqCst) && !stopped.load(Ordering::SeqCst) {
+                let current = template.lock().unwrap().clone();
+                let Some(mut block) = current.filter(|_| mining.load(Ordering::SeqCst)) else {
+                    thread::sleep(Duration::from_millis(10));
+                    continue;
+                };
+                block.set_nonce(first_nonce);
+                let merkle_root = *block.header().merkle_root();
+                info!("Mining block with target: {}", block.header().target());
+                // Keep mining until we find a valid block, mining is stopped,
+                // or the template gets replaced (e.g. after a tip change)
+                while running.load(Ordering::SeqCst)
+                    && mining.load(Ordering::SeqCst)
+                    && template
+                        .lock()
+                        .unwrap()
+                        .as_ref()
+                        .is_some_and(|current| *current.header().merkle_root() == merkle_root)
+                {
+                    let start_nonce = block.header().nonce();
+                    let started = Instant::now();
+                    let found = block.mine(MINING_STEPS);
+                    // one hash per nonce, the starting one included
+                    let tried = block.header().nonce().wrapping_sub(start_nonce) + 1;
+                    let elapsed = started.elapsed();
+                    metrics.record_hashes(index, tried, elapsed);
+                    if found {
+                        // another thread may have found one for the
+                        // same template first
+                        if mining.swap(false, Ordering::SeqCst) {
                             info!("Block mined: {:?}", block.hash());
                             sender.send(block).expect("Failed to send mined block");
-                            mining.store(false, Ordering::SeqCst);
-                            break;
                         }
+                        break;
+                    }
+                    if throttle < 100 {
+                        thread::sleep(elapsed * (100 - throttle) / throttle);
                     }
                 }
-                // Exit if mining flag is false (shutdown)
-                if !mining.load(Ordering::SeqCst) {
-                    break;
-                }
-                thread::yield_now();
             }
         });
-        *self.mining_thread_handle.lock().unwrap() = Some(handle);
+        self.mining_thread_handles.lock().unwrap().push(handle);
     }
 
     async fn fetch_and_validate_template(&self) -> Result<()> {
@@ -113,23 +248,20 @@ impl Miner {
 
     async fn fetch_template(&self) -> Result<()> {
         info!("Fetching new template");
-        let message = Message::FetchTemplate(self.public_key.clone());
-        let mut stream_lock = self.stream.lock().await;
-        message.send_async(&mut *stream_lock).await?;
-        match Message::receive_async(&mut *stream_lock).await? {
-            Message::Template(template) => {
-                info!(
-                    "Received new template with target: {}",
-                    template.header().target()
-                );
-                *self.current_template.lock().unwrap() = Some(template);
-                self.mining.store(true, Ordering::SeqCst);
-                Ok(())
-            }
-            _ => Err(anyhow!(
-                "Unexpected message received when fetching template"
-            )),
-        }
+        let template = self
+            .client
+            .lock()
+            .await
+            .get_template(&self.public_key, self.coinbase_tag.clone())
+            .await?;
+        info!(
+            "Received new template with target: {}",
+            template.header().target()
+        );
+        *self.current_template.lock().unwrap() = Some(template);
+        self.metrics.record_template();
+        self.mining.store(true, Ordering::SeqCst);
+        Ok(())
     }
 
     async fn validate_template(&self) -> Result<()> {
@@ -139,69 +271,121 @@ impl Miner {
             guard.clone()
         };
         if let Some(template) = template_opt {
-            let message = Message::ValidateTemplate(template);
-            let mut stream_lock = self.stream.lock().await;
-            message.send_async(&mut *stream_lock).await?;
-            match Message::receive_async(&mut *stream_lock).await? {
-                Message::TemplateValidity(valid) => {
-                    if !valid {
-                        warn!("Current template is no longer valid");
-                        self.mining.store(false, Ordering::Relaxed);
-                    } else {
-                        info!("Current template is still valid");
+            let valid = self.client.lock().await.validate_template(template).await?;
+            if !valid {
+                warn!("Current template is no longer valid");
+                self.mining.store(false, Ordering::Relaxed);
+            } else {
+                info!("Current template is still valid");
+            }
+        }
+        Ok(())
+    }
+
+    // collects the signatures of every reachable cosigner
+    async fn cosign_block(&self, block: &mut Block) {
+        for cosigner in &self.cosigners {
+            let signed = match NodeClient::connect(cosigner).await {
+                Ok(mut client) => client.cosign_block(block.clone()).await,
+                Err(e) => Err(e),
+            };
+            match signed {
+                Ok(signed) => {
+                    for signature in signed.authority_signatures() {
+                        block.add_authority_signature(signature.clone());
                     }
-                    Ok(())
                 }
-                _ => Err(anyhow!(
-                    "Unexpected message received when validating template"
-                )),
+                Err(e) => warn!("{} didn't co-sign the block: {}", cosigner, e),
             }
-        } else {
-            Ok(())
         }
     }
 
-    async fn submit_block(&self, block: Block) -> Result<()> {
+    async fn submit_block(&self, mut block: Block) -> Result<()> {
+        self.cosign_block(&mut block).await;
         info!("Submitting mined block");
-        let message = Message::SubmitTemplate(block);
-        let mut stream_lock = self.stream.lock().await;
-        message.send_async(&mut *stream_lock).await?;
+        self.metrics.record_submitted(block.hash());
+        self.client.lock().await.submit_template(block).await?;
         self.mining.store(false, Ordering::Relaxed);
         Ok(())
     }
 }
 
+/// Mines for the nodes of `config` in turn until `running` is cleared.
+/// With `RestartPolicy::OnFailure`, a miner that fails, e.g. because its
+/// node went away, is replaced after the restart delay by one mining for
+/// the next node; otherwise its error is returned. Logs the hashrate and
+/// blocks found every stats interval. `metrics` carry over from one
+/// miner to the next.
+pub async fn mine(
+    config: &MinerConfig,
+    public_key: PublicKey,
+    metrics: Arc<MinerMetrics>,
+    running: Arc<AtomicBool>,
+) -> Result<()> {
+    let stats = config
+        .stats_interval()
+        .map(|every| tokio::spawn(log_stats(metrics.clone(), every)));
+    let mut result = Ok(());
+    for address in config.nodes.iter().cycle() {
+        if !running.load(Ordering::SeqCst) {
+            break;
+        }
+        info!("Connecting to {} to mine", address);
+        let mined =
+            match Miner::new(address.clone(), public_key.clone(), config.coinbase_tag()).await {
+                Ok(miner) => {
+                    miner
+                        .with_cosigners(config.cosigners.clone())
+                        .with_threads(config.threads, config.throttle)
+                        .with_metrics(metrics.clone())
+                        .run(running.clone())
+                        .await
+                }
+                Err(e) => Err(e.context(format!(
+                    "failed to connect to {address}, is the node running and listening there?"
+                ))),
+            };
+        match mined {
+            Ok(()) => break,
+            Err(e) if config.restart == RestartPolicy::OnFailure => {
+                warn!(
+                    "Mining for {} failed: {:#}, moving on to the next node in {}s",
+                    address, e, config.restart_delay_secs
+                );
+                sleep(config.restart_delay()).await;
+            }
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+    if let Some(stats) = stats {
+        stats.abort();
+    }
+    result
+}
+
+async fn log_stats(metrics: Arc<MinerMetrics>, every: Duration) {
+    let mut stats_interval = interval(every);
+    // the first tick is immediate, before anything was mined
+    stats_interval.tick().await;
+    loop {
+        stats_interval.tick().await;
+        info!(
+            "Mining at {} H/s, {} blocks submitted, {} accepted",
+            metrics.hashrate(),
+            metrics.blocks_submitted(),
+            metrics.blocks_accepted()
+        );
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use btclib::{
-        crypto::PrivateKey,
-        custom_sha_types::Hash,
-        types::{Block, BlockHeader, Transaction, TransactionOutput},
-        utils::MerkleRoot,
-    };
-    use chrono::Utc;
+    use btclib::{testutil::genesis_block, types::Block};
     use std::sync::atomic::AtomicBool;
-    use uuid::Uuid;
-
-    fn create_test_block() -> Block {
-        let private_key = PrivateKey::default();
-        let transactions = vec![Transaction::new(
-            vec![],
-            vec![TransactionOutput::new(
-                btclib::INITIAL_REWARD * 10u64.pow(8),
-                Uuid::new_v4(),
-                private_key.public_key(),
-            )],
-        )];
-        let merkle_root = MerkleRoot::calculate(&transactions);
-        let block = Block::new(
-            BlockHeader::new(Utc::now(), 0, Hash::zero(), merkle_root, btclib::MIN_TARGET),
-            transactions,
-        );
-
-        block
-    }
 
     // Positive test: successful mining sets mining flag to false and sends block
     #[test]
@@ -210,15 +394,15 @@ mod tests {
         use std::sync::atomic::Ordering;
         use std::sync::{Arc, Mutex};
         let mining = Arc::new(AtomicBool::new(true));
-        let template = Arc::new(Mutex::new(Some(create_test_block())));
+        let template = Arc::new(Mutex::new(Some(genesis_block())));
         let (sender, receiver) = flume::unbounded::<Block>();
         // Simulate mining thread logic
-        if mining.load(Ordering::Relaxed) {
-            if let Some(block) = template.lock().unwrap().clone() {
-                // Simulate successful mining
-                sender.send(block.clone()).unwrap();
-                mining.store(false, Ordering::Relaxed);
-            }
+        if mining.load(Ordering::Relaxed)
+            && let Some(block) = template.lock().unwrap().clone()
+        {
+            // Simulate successful mining
+            sender.send(block.clone()).unwrap();
+            mining.store(false, Ordering::Relaxed);
         }
         assert!(!mining.load(Ordering::SeqCst));
         let received = receiver.recv().unwrap();
@@ -244,11 +428,11 @@ mod tests {
         let template = Arc::new(Mutex::new(None::<Block>));
         let (sender, receiver) = flume::unbounded::<Block>();
         // Simulate mining thread logic
-        if mining.load(Ordering::Relaxed) {
-            if let Some(_block) = template.lock().unwrap().clone() {
-                sender.send(_block).unwrap();
-                mining.store(false, Ordering::Relaxed);
-            }
+        if mining.load(Ordering::Relaxed)
+            && let Some(block) = template.lock().unwrap().clone()
+        {
+            sender.send(block).unwrap();
+            mining.store(false, Ordering::Relaxed);
         }
         // Mining flag should remain true, no block sent
         assert!(mining.load(Ordering::SeqCst));
@@ -262,14 +446,14 @@ mod tests {
         use std::sync::atomic::Ordering;
         use std::sync::{Arc, Mutex};
         let mining = Arc::new(AtomicBool::new(false));
-        let template = Arc::new(Mutex::new(Some(create_test_block())));
+        let template = Arc::new(Mutex::new(Some(genesis_block())));
         let (sender, receiver) = flume::unbounded::<Block>();
         // Simulate mining thread logic
-        if mining.load(Ordering::Relaxed) {
-            if let Some(block) = template.lock().unwrap().clone() {
-                sender.send(block).unwrap();
-                mining.store(false, Ordering::Relaxed);
-            }
+        if mining.load(Ordering::Relaxed)
+            && let Some(block) = template.lock().unwrap().clone()
+        {
+            sender.send(block).unwrap();
+            mining.store(false, Ordering::Relaxed);
         }
         // Mining flag should remain false, no block sent
         assert!(!mining.load(Ordering::Relaxed));
@@ -281,7 +465,7 @@ mod tests {
     fn test_block_broadcast_via_channel() {
         use flume;
         let (sender, receiver) = flume::unbounded::<Block>();
-        let block = create_test_block();
+        let block = genesis_block();
         sender.send(block.clone()).unwrap();
         let received = receiver.recv().unwrap();
         assert_eq!(
@@ -304,7 +488,7 @@ mod tests {
         let template = Arc::new(std::sync::Mutex::new(None));
         assert!(template.lock().unwrap().is_none());
 
-        let block = create_test_block();
+        let block = genesis_block();
         *template.lock().unwrap() = Some(block.clone());
 
         assert!(template.lock().unwrap().is_some());
@@ -318,7 +502,7 @@ mod tests {
     #[test]
     fn test_flume_channel() {
         let (sender, receiver) = flume::unbounded::<Block>();
-        let block = create_test_block();
+        let block = genesis_block();
 
         sender.send(block.clone()).unwrap();
         let received = receiver.recv().unwrap();
@@ -331,7 +515,7 @@ mod tests {
 
     #[test]
     fn test_block_creation() {
-        let block = create_test_block();
+        let block = genesis_block();
 
         assert_eq!(block.transactions().len(), 1);
         // Assuming coinbase transactions have is_coinbase field or can be identified by input/output
@@ -350,7 +534,7 @@ mod tests {
         assert!(template.lock().unwrap().is_none());
 
         // Simulate receiving a template
-        let block = create_test_block();
+        let block = genesis_block();
         *template.lock().unwrap() = Some(block);
         mining.store(true, Ordering::SeqCst);
 
@@ -366,7 +550,7 @@ mod tests {
     #[test]
     fn test_block_cloning() {
         let template = Arc::new(std::sync::Mutex::new(None::<Block>));
-        let block = create_test_block();
+        let block = genesis_block();
 
         *template.lock().unwrap() = Some(block.clone());
 
@@ -395,7 +579,7 @@ mod tests {
     #[tokio::test]
     async fn test_channel_send_receive() {
         let (sender, receiver) = flume::unbounded::<Block>();
-        let block = create_test_block();
+        let block = genesis_block();
 
         // Test async send/receive
         sender.send(block.clone()).unwrap();
@@ -408,8 +592,8 @@ mod tests {
     fn test_multiple_blocks_in_channel() {
         let (sender, receiver) = flume::unbounded::<Block>();
 
-        let block1 = create_test_block();
-        let block2 = create_test_block();
+        let block1 = genesis_block();
+        let block2 = genesis_block();
 
         sender.send(block1).unwrap();
         sender.send(block2).unwrap();
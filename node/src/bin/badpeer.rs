@@ -0,0 +1,46 @@
+use anyhow::Result;
+use clap::Parser;
+use node::util::{Attack, run_attack};
+use tokio::time::Duration;
+
+/// Connects to a node and misbehaves, to check that the node survives
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// Address of the node to attack
+    #[arg(short, long, default_value = "127.0.0.1:9000")]
+    address: String,
+
+    /// Attacks to run; all of them by default
+    #[arg(long, value_enum, value_delimiter = ',')]
+    attack: Vec<Attack>,
+
+    /// Milliseconds between bytes in the slow-loris attack
+    #[arg(long, default_value_t = 200)]
+    dribble_ms: u64,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+    let cli = Cli::parse();
+    let attacks = if cli.attack.is_empty() {
+        Attack::ALL.to_vec()
+    } else {
+        cli.attack
+    };
+    let mut failures = 0;
+    for attack in attacks {
+        match run_attack(&cli.address, attack, Duration::from_millis(cli.dribble_ms)).await {
+            Ok(()) => println!("{attack:?}: ok"),
+            Err(e) => {
+                println!("{attack:?}: FAILED: {e}");
+                failures += 1;
+            }
+        }
+    }
+    if failures > 0 {
+        anyhow::bail!("{failures} attacks were not handled as expected");
+    }
+    Ok(())
+}
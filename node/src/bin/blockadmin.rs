@@ -0,0 +1,61 @@
+use std::fs;
+
+use anyhow::{Context, Result};
+use btclib::client::NodeClient;
+use clap::{Parser, Subcommand};
+use node::util::parse_block_hash;
+
+/// Tells a node to invalidate a block, moving it off the fork the block
+/// is on, or to reconsider a block it was told to invalidate
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// Address of the node
+    #[arg(short, long, default_value = "127.0.0.1:9000")]
+    node: String,
+
+    /// File holding the node's admin token, `<blockchain-file>.admin.token`
+    #[arg(short, long)]
+    token_file: String,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Refuse the block and every chain through it
+    Invalidate {
+        /// Hash of the block, in hex
+        hash: String,
+    },
+    /// Accept an invalidated block again
+    Reconsider {
+        /// Hash of the block, in hex
+        hash: String,
+    },
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+    let cli = Cli::parse();
+    let token = fs::read_to_string(&cli.token_file)
+        .with_context(|| format!("failed to read token file {}", cli.token_file))?;
+    let mut client = NodeClient::connect(cli.node.as_str()).await?;
+    client.authenticate(token.trim()).await?;
+    let tip = match &cli.command {
+        Command::Invalidate { hash } => client.invalidate_block(parse_block_hash(hash)?).await?,
+        Command::Reconsider { hash } => client.reconsider_block(parse_block_hash(hash)?).await?,
+    };
+    match tip {
+        Some(tip) => println!(
+            "{}: tip {} at height {}",
+            cli.node,
+            hex::encode(tip.hash.as_bytes()),
+            tip.height
+        ),
+        None => println!("{}: no blocks", cli.node),
+    }
+    Ok(())
+}
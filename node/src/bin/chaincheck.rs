@@ -0,0 +1,46 @@
+use anyhow::Result;
+use clap::Parser;
+use node::util::{check_consistency, tip_propagation};
+
+/// Asks several nodes for their tip, recent blocks and mempool, and
+/// reports where they disagree
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// Addresses of the nodes to compare
+    #[arg(short, long, value_delimiter = ',', required = true)]
+    nodes: Vec<String>,
+
+    /// Number of recent blocks to compare when looking for a fork
+    #[arg(short, long, default_value_t = 10)]
+    depth: usize,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+    let cli = Cli::parse();
+    let (snapshots, divergences) = check_consistency(&cli.nodes, cli.depth).await;
+    for snapshot in &snapshots {
+        match snapshot.tip {
+            Some(tip) => println!(
+                "{}: tip {:?} at height {}, {} mempool transactions",
+                snapshot.address,
+                tip.hash,
+                tip.height,
+                snapshot.mempool.len()
+            ),
+            None => println!("{}: no blocks", snapshot.address),
+        }
+    }
+    if let Some(spread) = tip_propagation(&snapshots) {
+        println!("the tip reached all nodes that have it within {spread:.3}s");
+    }
+    for divergence in &divergences {
+        println!("DIVERGED: {divergence}");
+    }
+    if !divergences.is_empty() {
+        anyhow::bail!("{} divergences between nodes", divergences.len());
+    }
+    Ok(())
+}
@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use btclib::network::Message;
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand};
+use node::util::ProtoCapture;
+use tokio::{
+    net::TcpStream,
+    time::{Duration, sleep},
+};
+
+/// Inspect and replay protocol captures recorded with `main --capture`
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print every captured message
+    Print {
+        /// Capture file
+        file: String,
+    },
+    /// Send the captured messages to a node, one connection per
+    /// captured connection, and print its responses
+    Replay {
+        /// Capture file
+        file: String,
+        /// Address of the node to replay against
+        #[arg(short, long, default_value = "127.0.0.1:9000")]
+        address: String,
+        /// Keep the original delays between messages
+        #[arg(long)]
+        realtime: bool,
+        /// Seconds to wait for responses after the last message
+        #[arg(long, default_value_t = 2)]
+        linger: u64,
+    },
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+    match Cli::parse().command {
+        Command::Print { file } => {
+            for captured in ProtoCapture::read_all(file)? {
+                println!(
+                    "{} #{} {}: {:?}",
+                    captured.timestamp, captured.connection, captured.peer, captured.message
+                );
+            }
+        }
+        Command::Replay {
+            file,
+            address,
+            realtime,
+            linger,
+        } => {
+            let captured = ProtoCapture::read_all(file)?;
+            let mut connections = HashMap::new();
+            let mut previous: Option<DateTime<Utc>> = None;
+            for captured in captured {
+                if realtime && let Some(previous) = previous {
+                    let delay = (captured.timestamp - previous).to_std().unwrap_or_default();
+                    sleep(delay).await;
+                }
+                previous = Some(captured.timestamp);
+
+                if !connections.contains_key(&captured.connection) {
+                    let (mut reader, writer) = TcpStream::connect(&address).await?.into_split();
+                    let connection = captured.connection;
+                    tokio::spawn(async move {
+                        while let Ok(response) = Message::receive_async(&mut reader).await {
+                            println!("#{connection} <- {response:?}");
+                        }
+                    });
+                    connections.insert(connection, writer);
+                }
+                let writer = connections.get_mut(&captured.connection).unwrap();
+                println!("#{} -> {:?}", captured.connection, captured.message);
+                if let Err(e) = captured.message.send_async(writer).await {
+                    // the node closed this connection, like it would have for the peer
+                    println!("#{} closed: {e}", captured.connection);
+                    connections.remove(&captured.connection);
+                }
+            }
+            sleep(Duration::from_secs(linger)).await;
+        }
+    }
+    Ok(())
+}
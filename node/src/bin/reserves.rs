@@ -0,0 +1,106 @@
+use anyhow::{Context, Result};
+use btclib::{
+    client::NodeClient,
+    crypto::PrivateKey,
+    types::{BlockHeight, ReserveProof},
+    utils::Saveable,
+};
+use clap::{Parser, Subcommand};
+use node::util::audit_reserves;
+
+/// Proves control of funds without moving them: the holder signs an
+/// auditor's challenge with their keys, and the auditor checks the
+/// signatures and looks up what the keys own
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Sign the challenge with every key and save the proof
+    Sign {
+        /// Text chosen by the auditor
+        #[arg(short, long)]
+        challenge: String,
+        /// Private key files holding the funds
+        #[arg(short, long, value_delimiter = ',', required = true)]
+        keys: Vec<String>,
+        /// File to save the proof to
+        proof_file: String,
+    },
+    /// Check a proof and report the balance of its keys
+    Verify {
+        /// Address of the node to look the balances up on
+        #[arg(short, long, default_value = "127.0.0.1:9000")]
+        node: String,
+        /// Take the balances from just before the block at this height,
+        /// which takes an archive node [default: the current chain]
+        #[arg(long)]
+        height: Option<u64>,
+        /// Only accept a proof of this challenge
+        #[arg(short, long)]
+        challenge: Option<String>,
+        proof_file: String,
+    },
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+    let cli = Cli::parse();
+    match cli.command {
+        Command::Sign {
+            challenge,
+            keys,
+            proof_file,
+        } => {
+            let keys = keys
+                .iter()
+                .map(|file| {
+                    PrivateKey::load_from_file(file)
+                        .with_context(|| format!("failed to load private key {file}"))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let proof = ReserveProof::sign(&challenge, &keys);
+            proof
+                .save_to_file(&proof_file)
+                .with_context(|| format!("failed to save {proof_file}"))?;
+            println!("signed the challenge with {} keys", keys.len());
+        }
+        Command::Verify {
+            node,
+            height,
+            challenge,
+            proof_file,
+        } => {
+            let proof = ReserveProof::load_from_file(&proof_file)
+                .with_context(|| format!("failed to load {proof_file}"))?;
+            if let Some(challenge) = challenge {
+                anyhow::ensure!(
+                    proof.challenge == challenge,
+                    "the proof is of another challenge: {:?}",
+                    proof.challenge
+                );
+            }
+            let mut client = NodeClient::connect(node.as_str()).await?;
+            let report = audit_reserves(&mut client, &proof, height.map(BlockHeight::new)).await?;
+            println!("challenge: {:?}", report.challenge);
+            match report.height {
+                Some(height) => println!("balances before block {height}:"),
+                None => println!("balances on the current chain of {node}:"),
+            }
+            for (key, balance) in &report.balances {
+                println!("{}  {}", key.address(), balance);
+            }
+            println!(
+                "total: {} in {} keys",
+                report.total(),
+                report.balances.len()
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,64 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use clap::Parser;
+use node::util::{SoakConfig, run_soak};
+use tokio::time::Duration;
+
+/// Runs a localnet for hours, restarting nodes, partitioning the network
+/// and injecting invalid blocks while mining, and fails unless the nodes
+/// left agree on the tip and the UTXO set at the end
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// Nodes of the localnet
+    #[arg(short, long, default_value_t = 4)]
+    nodes: usize,
+
+    /// Minutes faults are injected for
+    #[arg(short, long, default_value_t = 240)]
+    minutes: u64,
+
+    /// Seconds between two faults
+    #[arg(long, default_value_t = 30)]
+    chaos_interval_secs: u64,
+
+    /// Seconds between two blocks
+    #[arg(long, default_value_t = 5)]
+    block_interval_secs: u64,
+
+    /// Seconds the nodes get to agree once faults stop
+    #[arg(long, default_value_t = 120)]
+    convergence_timeout_secs: u64,
+
+    /// Seed of the faults and of where blocks are mined, to replay a
+    /// run [default: random]
+    #[arg(short, long)]
+    seed: Option<u64>,
+
+    /// Directory for the blockchain files, kept for inspection
+    /// [default: soak in the temporary directory]
+    #[arg(short, long)]
+    dir: Option<PathBuf>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+    let cli = Cli::parse();
+    let defaults = SoakConfig::default();
+    let config = SoakConfig {
+        nodes: cli.nodes,
+        duration: Duration::from_secs(cli.minutes * 60),
+        chaos_interval: Duration::from_secs(cli.chaos_interval_secs),
+        block_interval: Duration::from_secs(cli.block_interval_secs),
+        convergence_timeout: Duration::from_secs(cli.convergence_timeout_secs),
+        seed: cli.seed.unwrap_or_else(rand::random),
+        dir: cli.dir.unwrap_or(defaults.dir),
+    };
+    println!("soak run with seed {}", config.seed);
+    let report = run_soak(&config).await?;
+    print!("{report}");
+    println!("all nodes agree");
+    Ok(())
+}
@@ -1,43 +1,269 @@
 use btclib::{
     custom_sha_types::Hash,
     network::Message::{
-        self, AskDifference, Difference, DiscoverNodes, FetchBlock, FetchTemplate, FetchUTXOs,
-        NewBlock, NewTransaction, NodeList, SubmitTemplate, SubmitTransaction, Template,
-        TemplateValidity, UTXOs, ValidateTemplate,
+        self, Addr, Analytics, AskDifference, AssetBalances, Authenticate, Authenticated,
+        BalanceAt, BlockTimings, BuildTransaction, CosignBlock, Cosigned, Difference,
+        DiscoverNodes, DiskUsage, FeeHistogram, FetchAnalytics, FetchAssetBalances, FetchBalanceAt,
+        FetchBlock, FetchBlockByHash, FetchBlockTimings, FetchDiskUsage, FetchFeeHistogram,
+        FetchHeader, FetchHealth, FetchMemoryInfo, FetchMempool, FetchMempoolEntries,
+        FetchNetworkInfo, FetchPolicy, FetchStats, FetchSyncProgress, FetchTemplate, FetchTip,
+        FetchTransactions, FetchUTXOs, FetchUndo, FetchUtxoAccumulator, FetchUtxoDelta,
+        FetchUtxoProof, FetchZeroConfRisk, Header, Health, Hello, InvalidateBlock, MemoryInfo,
+        Mempool, MempoolEntries, NetworkInfo, NewBlock, NewTransaction, NodeList, Ping, Policy,
+        Pong, ReconsiderBlock, Reject, Rescan, RescanResult, Shutdown, Stats,
+        SubmitPriorityTransaction, SubmitTemplate, SubmitTransaction, SubscribeTips, SyncProgress,
+        Template, TemplateValidity, Tip, TipChanged, Transactions, UTXOs, Undo,
+        UnsignedTransaction, UnwatchOutpoints, UtxoAccumulator, UtxoDelta, UtxoProof,
+        ValidateTemplate, WatchOutpoints, Watching, Welcome, ZeroConfRisk,
     },
-    types::{Block, BlockHeader, Transaction, TransactionOutput},
-    utils::MerkleRoot,
+    network::RejectCode,
 };
 use chrono::Utc;
 use log::error;
-use tokio::net::TcpStream;
-use uuid::Uuid;
+use std::{
+    collections::HashSet,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{Arc, atomic::Ordering},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+    time,
+};
 
-use crate::{BLOCKCHAIN, NODES};
+use crate::{
+    NodeState,
+    util::{
+        ChainstateSnapshot, MAX_WATCHED_OUTPOINTS, RejectedKind, SessionSlot,
+        assess_zero_conf_risk, authenticate, balance_at, block_template, block_timings, block_undo,
+        build_transaction, capture_message, check_relay_policy, connect_new_block, disk_usage,
+        follow_fork, forward_tips, gossip_addresses, invalidate_block, is_archive, known_addresses,
+        learn_addresses, log_block, memory_info, message_timeout, network_health, network_info,
+        network_limits, next_connection_id, notify_block_connected, notify_tx_accepted,
+        open_session, queue_transaction, reconsider_block, record_announcement, record_block,
+        record_block_timings, record_mempool_origin, record_rejection, relay_block, relay_policy,
+        submit_priority_transaction, submit_transaction, watchtower,
+    },
+};
 
-pub async fn handle_connection(mut socket: TcpStream) {
+pub async fn handle_connection(state: Arc<NodeState>, socket: TcpStream) {
+    let peer_addr = socket.peer_addr().ok();
+    serve_connection(state, socket, peer_addr).await;
+}
+
+/// Answers requests on any byte stream until it closes or the peer
+/// misbehaves. `handle_connection` is this over TCP; the fuzz harness
+/// uses an in-memory stream.
+pub async fn serve_connection(
+    state: Arc<NodeState>,
+    mut socket: impl AsyncRead + AsyncWrite + Unpin,
+    peer_addr: Option<SocketAddr>,
+) {
+    // granted by Authenticate, anonymous until then
+    let mut role = None;
+    // opened by Hello, served without idle timeout or restrictions until then
+    let mut session = None;
+    let connection = next_connection_id();
+    let peer = peer_addr.map(|addr| addr.to_string()).unwrap_or_default();
+    // free transaction quotas are per host, not per connection
+    let peer_ip = peer_addr
+        .map(|addr| addr.ip())
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
     loop {
         // read a message from the socket
-        let message = match Message::receive_async(&mut socket).await {
+        let max_size = network_limits(&state).max_message_size;
+        let receive =
+            Message::receive_async_limited(&mut socket, max_size, Some(message_timeout(&state)));
+        let received = match session.as_ref().and_then(SessionSlot::idle_timeout) {
+            Some(idle_timeout) => match time::timeout(idle_timeout, receive).await {
+                Ok(received) => received,
+                Err(_) => {
+                    log::info!("closing connection idle for {idle_timeout:?}");
+                    return;
+                }
+            },
+            None => receive.await,
+        };
+        let message = match received {
             Ok(message) => message,
             Err(e) => {
                 error!("invalid message from peer: {e}, closing that connection");
                 return;
             }
         };
+        capture_message(&state, connection, &peer, &message);
+        let request_kind = message.kind();
+        if let Some(required) = message.required_role()
+            && role.is_none_or(|role| role < required)
+        {
+            log::warn!("refusing {request_kind} without {required:?} role");
+            let message = Message::reject(
+                request_kind,
+                RejectCode::Unauthorized,
+                format!("{request_kind} requires the {required:?} role"),
+            );
+            if let Err(e) = message.send_async(&mut socket).await {
+                log::error!("Failed to send reject: {}", e);
+                return;
+            }
+            continue;
+        }
+        if let Some(slot) = &session
+            && !slot.session.kind.allows(&message)
+        {
+            let kind = slot.session.kind;
+            log::warn!("refusing {request_kind} from a {kind:?} connection");
+            let message = Message::reject(
+                request_kind,
+                RejectCode::Unsupported,
+                format!("{kind:?} connections don't send {request_kind}"),
+            );
+            if let Err(e) = message.send_async(&mut socket).await {
+                log::error!("Failed to send reject: {}", e);
+                return;
+            }
+            continue;
+        }
+        if state.seed_only.load(Ordering::Relaxed) {
+            match &message {
+                // peers relay these to every node, don't bother them
+                // with a reject
+                NewBlock(_) | NewTransaction(_) => continue,
+                FetchUTXOs(_)
+                | SubmitTransaction(_)
+                | BuildTransaction(..)
+                | FetchTemplate(..)
+                | ValidateTemplate(_)
+                | SubmitTemplate(_)
+                | CosignBlock(_)
+                | AskDifference(_)
+                | FetchBlock(_)
+                | FetchBlockByHash(_)
+                | FetchUtxoDelta(..)
+                | FetchHeader(_)
+                | SubscribeTips
+                | FetchTip
+                | FetchMempool
+                | FetchTransactions(_)
+                | FetchMempoolEntries
+                | FetchFeeHistogram
+                | FetchBlockTimings(_)
+                | FetchZeroConfRisk(_)
+                | FetchUtxoAccumulator
+                | FetchUtxoProof(_)
+                | FetchStats(_)
+                | FetchAnalytics(_)
+                | FetchDiskUsage
+                | FetchMemoryInfo
+                | FetchHealth
+                | FetchAssetBalances(_)
+                | FetchPolicy
+                | Rescan(..)
+                | InvalidateBlock(_)
+                | ReconsiderBlock(_)
+                | SubmitPriorityTransaction(_)
+                | WatchOutpoints(_)
+                | UnwatchOutpoints(_) => {
+                    let message = Message::reject(
+                        request_kind,
+                        RejectCode::Unsupported,
+                        "seed-only node, ask it for addresses only",
+                    );
+                    if let Err(e) = message.send_async(&mut socket).await {
+                        log::error!("Failed to send reject: {}", e);
+                        return;
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        if !state.serve_templates.load(Ordering::Relaxed)
+            && matches!(
+                message,
+                FetchTemplate(..) | ValidateTemplate(_) | SubmitTemplate(_)
+            )
+        {
+            let message = Message::reject(
+                request_kind,
+                RejectCode::Unsupported,
+                "this node doesn't serve block templates",
+            );
+            if let Err(e) = message.send_async(&mut socket).await {
+                log::error!("Failed to send reject: {}", e);
+                return;
+            }
+            continue;
+        }
         match message {
-            UTXOs(_) | Template(_) | Difference(_) | TemplateValidity(_) | NodeList(_) => {
+            UTXOs(_)
+            | Template(_)
+            | Difference(_)
+            | TemplateValidity(_)
+            | NodeList(_)
+            | Header(_)
+            | TipChanged(_)
+            | Stats(_)
+            | Analytics(_)
+            | DiskUsage(_)
+            | MemoryInfo(_)
+            | Authenticated(_)
+            | AssetBalances(_)
+            | Health(_)
+            | SyncProgress(_)
+            | Policy(_)
+            | NetworkInfo(_)
+            | RescanResult(_)
+            | BalanceAt(_)
+            | Undo(_)
+            | UtxoDelta(_)
+            | Tip(_)
+            | Mempool(_)
+            | Transactions(_)
+            | MempoolEntries(_)
+            | FeeHistogram(_)
+            | BlockTimings(_)
+            | ZeroConfRisk(_)
+            | UtxoAccumulator(_)
+            | UtxoProof(_)
+            | Cosigned(_)
+            | Welcome(_)
+            | Pong(_)
+            | Watching(_)
+            | UnsignedTransaction(_) => {
                 log::info!(
                     "I am neither a miner nor a \
             wallet! Goodbye"
                 );
+                let message = Message::reject(
+                    request_kind,
+                    RejectCode::Unsupported,
+                    "responses are not accepted as requests",
+                );
+                let _ = message.send_async(&mut socket).await;
                 return;
             }
+            Reject {
+                request_kind,
+                code,
+                reason,
+            } => {
+                log::warn!("peer rejected {request_kind} ({code:?}): {reason}");
+            }
             FetchBlock(height) => {
-                let blockchain = BLOCKCHAIN.read().await;
-                let Some(block) = blockchain.blocks().get(height).cloned() else {
+                let blockchain = state.blockchain.read().await;
+                let Some(block) = blockchain.block_at(height).cloned() else {
                     log::warn!("Block at height {} not found", height);
-                    return;
+                    let message = Message::reject(
+                        request_kind,
+                        RejectCode::NotFound,
+                        format!("no block at height {height}"),
+                    );
+                    if let Err(e) = message.send_async(&mut socket).await {
+                        log::error!("Failed to send reject: {}", e);
+                        return;
+                    }
+                    continue;
                 };
                 let message = NewBlock(block);
                 if let Err(e) = message.send_async(&mut socket).await {
@@ -46,21 +272,490 @@ pub async fn handle_connection(mut socket: TcpStream) {
                 }
             }
 
-            DiscoverNodes => {
-                let nodes = crate::NODES
+            FetchBlockByHash(hash) => {
+                let blockchain = state.blockchain.read().await;
+                let message = match blockchain.block_by_hash(&hash) {
+                    Some(block) => NewBlock(block.clone()),
+                    None => Message::reject(
+                        request_kind,
+                        RejectCode::NotFound,
+                        format!("no block with hash {hash:?}"),
+                    ),
+                };
+                if let Err(e) = message.send_async(&mut socket).await {
+                    log::error!("Failed to send block: {}", e);
+                    return;
+                }
+            }
+
+            FetchHeader(hash) => {
+                let blockchain = state.blockchain.read().await;
+                let message = match blockchain.block_by_hash(&hash) {
+                    Some(block) => Header(block.header().clone()),
+                    None => Message::reject(
+                        request_kind,
+                        RejectCode::NotFound,
+                        format!("no block with hash {hash:?}"),
+                    ),
+                };
+                if let Err(e) = message.send_async(&mut socket).await {
+                    log::error!("Failed to send header: {}", e);
+                    return;
+                }
+            }
+
+            FetchBlockTimings(hash) => {
+                let message = match block_timings(&state, &hash) {
+                    Some(timings) => BlockTimings(timings),
+                    None => Message::reject(
+                        request_kind,
+                        RejectCode::NotFound,
+                        format!("no timings for block {hash:?}"),
+                    ),
+                };
+                if let Err(e) = message.send_async(&mut socket).await {
+                    log::error!("Failed to send block timings: {}", e);
+                    return;
+                }
+            }
+            FetchZeroConfRisk(hash) => {
+                let message = if !state.zero_conf_risk.load(Ordering::Relaxed) {
+                    Message::reject(
+                        request_kind,
+                        RejectCode::Unsupported,
+                        "this node doesn't score zero-conf risk",
+                    )
+                } else {
+                    match assess_zero_conf_risk(&state, hash).await {
+                        Some(risk) => ZeroConfRisk(risk),
+                        None => Message::reject(
+                            request_kind,
+                            RejectCode::NotFound,
+                            format!("transaction {hash:?} isn't in the mempool"),
+                        ),
+                    }
+                };
+                if let Err(e) = message.send_async(&mut socket).await {
+                    log::error!("Failed to send zero-conf risk: {}", e);
+                    return;
+                }
+            }
+
+            FetchUtxoAccumulator => {
+                #[cfg(feature = "utxo-accumulator")]
+                let message = UtxoAccumulator(crate::util::utxo_accumulator(&state).await);
+                #[cfg(not(feature = "utxo-accumulator"))]
+                let message = Message::reject(
+                    request_kind,
+                    RejectCode::Unsupported,
+                    "this node keeps no UTXO accumulator",
+                );
+                if let Err(e) = message.send_async(&mut socket).await {
+                    log::error!("Failed to send UTXO accumulator: {}", e);
+                    return;
+                }
+            }
+
+            FetchUtxoProof(outpoint) => {
+                #[cfg(feature = "utxo-accumulator")]
+                let message = UtxoProof(crate::util::utxo_proof(&state, &outpoint).await);
+                #[cfg(not(feature = "utxo-accumulator"))]
+                let message = {
+                    let _ = outpoint;
+                    Message::reject(
+                        request_kind,
+                        RejectCode::Unsupported,
+                        "this node keeps no UTXO accumulator",
+                    )
+                };
+                if let Err(e) = message.send_async(&mut socket).await {
+                    log::error!("Failed to send UTXO proof: {}", e);
+                    return;
+                }
+            }
+
+            FetchTip => {
+                let blockchain = state.blockchain.read().await;
+                let message = Tip(blockchain.tip());
+                if let Err(e) = message.send_async(&mut socket).await {
+                    log::error!("Failed to send tip: {}", e);
+                    return;
+                }
+            }
+
+            FetchMempool => {
+                let blockchain = state.blockchain.read().await;
+                let transactions = blockchain
+                    .mempool()
                     .iter()
-                    .map(|x| x.key().clone())
-                    .collect::<Vec<_>>();
-                let message = NodeList(nodes);
+                    .map(|(_, transaction)| transaction.hash())
+                    .collect();
+                let message = Mempool(transactions);
+                if let Err(e) = message.send_async(&mut socket).await {
+                    log::error!("Failed to send mempool: {}", e);
+                    return;
+                }
+            }
+
+            FetchTransactions(hashes) => {
+                let wanted: HashSet<Hash> = hashes.into_iter().collect();
+                let blockchain = state.blockchain.read().await;
+                let transactions = blockchain
+                    .mempool()
+                    .iter()
+                    .map(|(_, transaction)| transaction)
+                    .filter(|transaction| wanted.contains(&transaction.hash()))
+                    .take(btclib::MAX_FETCH_TRANSACTIONS)
+                    .cloned()
+                    .collect();
+                let message = Transactions(transactions);
+                if let Err(e) = message.send_async(&mut socket).await {
+                    log::error!("Failed to send transactions: {}", e);
+                    return;
+                }
+            }
+
+            FetchMempoolEntries => {
+                let blockchain = state.blockchain.read().await;
+                let message = MempoolEntries(blockchain.mempool_entries());
+                if let Err(e) = message.send_async(&mut socket).await {
+                    log::error!("Failed to send mempool entries: {}", e);
+                    return;
+                }
+            }
+
+            FetchFeeHistogram => {
+                let blockchain = state.blockchain.read().await;
+                let message = FeeHistogram(blockchain.fee_histogram());
+                if let Err(e) = message.send_async(&mut socket).await {
+                    log::error!("Failed to send fee histogram: {}", e);
+                    return;
+                }
+            }
+
+            SubscribeTips => {
+                log::info!("peer subscribed to tip changes");
+                forward_tips(&state, &mut socket).await;
+                return;
+            }
+
+            FetchStats(top) => {
+                let blockchain = state.blockchain.read().await;
+                let message = Stats(blockchain.stats(top));
+                if let Err(e) = message.send_async(&mut socket).await {
+                    log::error!("Failed to send stats: {}", e);
+                    return;
+                }
+            }
+
+            FetchAnalytics(blocks) => {
+                let blockchain = state.blockchain.read().await;
+                let message = Analytics(blockchain.analytics(blocks));
+                if let Err(e) = message.send_async(&mut socket).await {
+                    log::error!("Failed to send analytics: {}", e);
+                    return;
+                }
+            }
+
+            FetchHealth => {
+                let blockchain = state.blockchain.read().await;
+                let message = Health(network_health(&state, &blockchain));
+                if let Err(e) = message.send_async(&mut socket).await {
+                    log::error!("Failed to send health: {}", e);
+                    return;
+                }
+            }
+            FetchSyncProgress => {
+                let progress = state.sync_progress.lock().unwrap().clone();
+                if let Err(e) = SyncProgress(progress).send_async(&mut socket).await {
+                    log::error!("Failed to send sync progress: {}", e);
+                    return;
+                }
+            }
+            FetchBalanceAt(..) | FetchUndo(_) if !is_archive(&state) => {
+                let message =
+                    Message::reject(request_kind, RejectCode::Unsupported, "not an archive node");
+                if let Err(e) = message.send_async(&mut socket).await {
+                    log::error!("Failed to send reject: {}", e);
+                    return;
+                }
+            }
+
+            FetchBalanceAt(pubkey, height) => {
+                let message = match balance_at(&state, &pubkey, height).await {
+                    Some(balance) => BalanceAt(balance),
+                    None => Message::reject(
+                        request_kind,
+                        RejectCode::NotFound,
+                        format!("the chain hasn't reached height {height} yet"),
+                    ),
+                };
+                if let Err(e) = message.send_async(&mut socket).await {
+                    log::error!("Failed to send historical balance: {}", e);
+                    return;
+                }
+            }
+
+            FetchUndo(height) => {
+                let message = match block_undo(&state, height).await {
+                    Some(undo) => Undo(undo),
+                    None => Message::reject(
+                        request_kind,
+                        RejectCode::NotFound,
+                        format!("no block at height {height}"),
+                    ),
+                };
+                if let Err(e) = message.send_async(&mut socket).await {
+                    log::error!("Failed to send undo data: {}", e);
+                    return;
+                }
+            }
+
+            FetchUtxoDelta(from, to) => {
+                let message = if from.blocks_until(to) > btclib::MAX_UTXO_DELTA_BLOCKS {
+                    Message::reject(
+                        request_kind,
+                        RejectCode::Invalid,
+                        format!(
+                            "a delta spans at most {} blocks",
+                            btclib::MAX_UTXO_DELTA_BLOCKS
+                        ),
+                    )
+                } else {
+                    let blockchain = state.blockchain.read().await;
+                    match btclib::types::UtxoDelta::between(&blockchain, from, to) {
+                        Some(delta) => UtxoDelta(delta),
+                        None if from > to => Message::reject(
+                            request_kind,
+                            RejectCode::Invalid,
+                            format!("height {from} is past {to}"),
+                        ),
+                        None => Message::reject(
+                            request_kind,
+                            RejectCode::NotFound,
+                            format!("the chain hasn't reached height {to} yet"),
+                        ),
+                    }
+                };
+                if let Err(e) = message.send_async(&mut socket).await {
+                    log::error!("Failed to send UTXO delta: {}", e);
+                    return;
+                }
+            }
+
+            WatchOutpoints(outpoints) => {
+                let message = match watchtower(&state) {
+                    Some(tower) => {
+                        let blockchain = state.blockchain.read().await;
+                        match tower.watch(&outpoints, &blockchain) {
+                            Some(count) => Watching(count),
+                            None => Message::reject(
+                                request_kind,
+                                RejectCode::Busy,
+                                format!("already watching up to {MAX_WATCHED_OUTPOINTS} outputs"),
+                            ),
+                        }
+                    }
+                    None => Message::reject(
+                        request_kind,
+                        RejectCode::Unsupported,
+                        "not a watchtower node",
+                    ),
+                };
+                if let Err(e) = message.send_async(&mut socket).await {
+                    log::error!("Failed to send watched outputs: {}", e);
+                    return;
+                }
+            }
+
+            UnwatchOutpoints(outpoints) => {
+                let message = match watchtower(&state) {
+                    Some(tower) => Watching(tower.unwatch(&outpoints)),
+                    None => Message::reject(
+                        request_kind,
+                        RejectCode::Unsupported,
+                        "not a watchtower node",
+                    ),
+                };
+                if let Err(e) = message.send_async(&mut socket).await {
+                    log::error!("Failed to send watched outputs: {}", e);
+                    return;
+                }
+            }
+
+            Rescan(keys, from_height) => {
+                let message = if keys.len() > btclib::MAX_RESCAN_KEYS {
+                    Message::reject(
+                        request_kind,
+                        RejectCode::Invalid,
+                        format!("a rescan covers at most {} keys", btclib::MAX_RESCAN_KEYS),
+                    )
+                } else {
+                    let blockchain = state.blockchain.read().await;
+                    log::info!(
+                        "rescanning {} blocks for {} keys",
+                        from_height.blocks_until(blockchain.block_height()),
+                        keys.len()
+                    );
+                    RescanResult(blockchain.rescan(&keys, from_height))
+                };
+                if let Err(e) = message.send_async(&mut socket).await {
+                    log::error!("Failed to send rescan result: {}", e);
+                    return;
+                }
+            }
+            FetchPolicy => {
+                let message = Policy(relay_policy(&state));
+                if let Err(e) = message.send_async(&mut socket).await {
+                    log::error!("Failed to send relay policy: {}", e);
+                    return;
+                }
+            }
+
+            FetchNetworkInfo => {
+                let message = NetworkInfo(network_info(&state));
+                if let Err(e) = message.send_async(&mut socket).await {
+                    log::error!("Failed to send network info: {}", e);
+                    return;
+                }
+            }
+
+            FetchAssetBalances(pubkey) => {
+                #[cfg(feature = "assets")]
+                let message = AssetBalances(crate::util::asset_balances(&state, &pubkey).await);
+                #[cfg(not(feature = "assets"))]
+                let message = {
+                    let _ = pubkey;
+                    Message::reject(
+                        request_kind,
+                        RejectCode::Unsupported,
+                        "this node does not index assets",
+                    )
+                };
+                if let Err(e) = message.send_async(&mut socket).await {
+                    log::error!("Failed to send asset balances: {}", e);
+                    return;
+                }
+            }
+
+            Hello(kind, requested_secs) => {
+                let message = if session.is_some() {
+                    Message::reject(request_kind, RejectCode::Invalid, "already said hello")
+                } else {
+                    match open_session(&state, kind, requested_secs) {
+                        Some(slot) => {
+                            let opened = Welcome(slot.session);
+                            session = Some(slot);
+                            opened
+                        }
+                        None => {
+                            log::warn!("refusing {kind:?} session, the limit is reached");
+                            let message = Message::reject(
+                                request_kind,
+                                RejectCode::Busy,
+                                format!("serving as many {kind:?} connections as allowed"),
+                            );
+                            let _ = message.send_async(&mut socket).await;
+                            return;
+                        }
+                    }
+                };
+                if let Err(e) = message.send_async(&mut socket).await {
+                    log::error!("Failed to send session: {}", e);
+                    return;
+                }
+            }
+
+            Ping(nonce) => {
+                if let Err(e) = Pong(nonce).send_async(&mut socket).await {
+                    log::error!("Failed to send pong: {}", e);
+                    return;
+                }
+            }
+
+            Authenticate(token) => {
+                let message = match authenticate(&state, &token) {
+                    Some(granted) => {
+                        role = Some(granted);
+                        Authenticated(granted)
+                    }
+                    None => {
+                        log::warn!("peer presented an invalid auth token");
+                        Message::reject(request_kind, RejectCode::Unauthorized, "invalid token")
+                    }
+                };
+                if let Err(e) = message.send_async(&mut socket).await {
+                    log::error!("Failed to send authentication result: {}", e);
+                    return;
+                }
+            }
+
+            Shutdown => {
+                log::info!("shutdown requested by an admin");
+                state.shutdown.notify_one();
+                return;
+            }
+
+            InvalidateBlock(hash) => {
+                log::info!("block invalidation requested by an admin");
+                let message = match invalidate_block(&state, hash).await {
+                    Ok(()) => Tip(state.blockchain.read().await.tip()),
+                    Err(e) => Message::reject(request_kind, RejectCode::Invalid, e.to_string()),
+                };
+                if let Err(e) = message.send_async(&mut socket).await {
+                    log::error!("Failed to send tip: {}", e);
+                    return;
+                }
+            }
+            ReconsiderBlock(hash) => {
+                log::info!("block reconsideration requested by an admin");
+                let message = match reconsider_block(&state, hash).await {
+                    Ok(()) => Tip(state.blockchain.read().await.tip()),
+                    Err(e) => Message::reject(request_kind, RejectCode::Invalid, e.to_string()),
+                };
+                if let Err(e) = message.send_async(&mut socket).await {
+                    log::error!("Failed to send tip: {}", e);
+                    return;
+                }
+            }
+
+            FetchDiskUsage => {
+                let message = match disk_usage(&state) {
+                    Ok(usage) => DiskUsage(usage),
+                    Err(e) => Message::reject(request_kind, RejectCode::Internal, e.to_string()),
+                };
+                if let Err(e) = message.send_async(&mut socket).await {
+                    log::error!("Failed to send disk usage: {}", e);
+                    return;
+                }
+            }
+
+            FetchMemoryInfo => {
+                let message = MemoryInfo(memory_info(&state, &*state.blockchain.read().await));
+                if let Err(e) = message.send_async(&mut socket).await {
+                    log::error!("Failed to send memory info: {}", e);
+                    return;
+                }
+            }
+
+            DiscoverNodes => {
+                let message = NodeList(known_addresses(&state));
                 if let Err(e) = message.send_async(&mut socket).await {
                     log::error!("Failed to send node list: {}", e);
                     return;
                 }
             }
 
+            Addr(addresses) => {
+                let new_addresses = learn_addresses(&state, addresses);
+                gossip_addresses(&state, new_addresses).await;
+            }
+
             AskDifference(height) => {
-                let blockchain = BLOCKCHAIN.read().await;
-                let count = blockchain.block_height() as i32 - height as i32;
+                let blockchain = state.blockchain.read().await;
+                // heights come from the peer, so don't let the difference overflow
+                let count = (blockchain.block_height().value() as i128 - height.value() as i128)
+                    .clamp(i32::MIN as i128, i32::MAX as i128) as i32;
                 let message = Difference(count);
                 if let Err(e) = message.send_async(&mut socket).await {
                     log::error!("Failed to send difference: {}", e);
@@ -70,12 +765,11 @@ pub async fn handle_connection(mut socket: TcpStream) {
 
             FetchUTXOs(key) => {
                 log::info!("received request to fetch UTXOs");
-                let blockchain = BLOCKCHAIN.read().await;
-                let utxos = blockchain
-                    .utxos()
-                    .iter()
-                    .filter(|(_, txout)| txout.pubkey() == &key)
-                    .map(|(_, txout)| (txout.clone(), false))
+                let snapshot = ChainstateSnapshot::take(&state).await;
+                let utxos = snapshot
+                    .utxos_for(&key)
+                    .into_iter()
+                    .map(|(_, entry)| entry.clone())
                     .collect::<Vec<_>>();
                 let message = UTXOs(utxos);
                 if let Err(e) = message.send_async(&mut socket).await {
@@ -84,27 +778,77 @@ pub async fn handle_connection(mut socket: TcpStream) {
                 }
             }
             NewBlock(block) => {
-                let mut blockchain = BLOCKCHAIN.write().await;
+                let received_at = Utc::now();
                 log::info!("received new block");
-                if blockchain.add_block(block).is_err() {
-                    log::info!("block rejected");
+                record_announcement(&state, peer_ip, block.hash());
+                {
+                    let mut blockchain = state.blockchain.write().await;
+                    let builds_on_tip = *block.header().prev_block_hash()
+                        == blockchain.tip().map(|tip| tip.hash).unwrap_or(Hash::zero());
+                    record_block(&state, !builds_on_tip);
+                    // a block we already have doesn't build on the tip
+                    // either, so only new blocks are passed on
+                    if let Err(e) = blockchain.add_block(block.clone()) {
+                        log::info!("block rejected: {e}");
+                        if builds_on_tip {
+                            record_rejection(&state, RejectedKind::Block);
+                            state
+                                .peer_quality
+                                .lock()
+                                .unwrap()
+                                .record_relayed_block(peer_ip, false);
+                        }
+                        // a new block off the tip may end a longer branch
+                        let unknown =
+                            !builds_on_tip && blockchain.block_by_hash(&block.hash()).is_none();
+                        drop(blockchain);
+                        if unknown && let Err(e) = follow_fork(&state, block.hash()).await {
+                            log::info!("not following the fork: {e}");
+                        }
+                        continue;
+                    }
+                    blockchain.rebuild_utxos();
+                    if let Err(e) = log_block(&state, &block) {
+                        log::error!("Failed to write block to the WAL: {e}");
+                    }
+                    notify_block_connected(&state, &blockchain);
+                    record_block_timings(&state, &blockchain, received_at, Some(peer.clone()));
+                    state
+                        .peer_quality
+                        .lock()
+                        .unwrap()
+                        .record_relayed_block(peer_ip, true);
                 }
+                relay_block(&state, &block).await;
             }
             NewTransaction(tx) => {
-                let mut blockchain = BLOCKCHAIN.write().await;
+                let mut blockchain = state.blockchain.write().await;
                 log::info!("received transaction from friend");
-                if blockchain.add_transaction_to_mempool(tx).is_err() {
+                record_announcement(&state, peer_ip, tx.hash());
+                if blockchain
+                    .mempool()
+                    .iter()
+                    .any(|(_, pending)| pending.hash() == tx.hash())
+                {
+                    // already relayed when we first saw it
+                    continue;
+                }
+                if let Err(e) = check_relay_policy(&state, peer_ip, &tx, &blockchain) {
+                    // valid, so no reason to drop the peer
+                    log::info!("not relaying transaction: {e}");
+                    continue;
+                }
+                if blockchain.add_transaction_to_mempool(tx.clone()).is_err() {
                     log::info!("transaction rejected, closing connection");
+                    record_rejection(&state, RejectedKind::Transaction);
                     return;
                 }
-
-                // TODO: We are making a simplification here in that we just add it to the mempool. It would
-                // be a nice idea to send it back to other nodes that may not have it. However, we would
-                // have to add a mechanism for preventing the network from creating notification
-                // loops. You can try implementing one, if you want.
+                record_mempool_origin(&state, peer_ip, &tx);
+                notify_tx_accepted(&state, &tx);
+                queue_transaction(&state, tx);
             }
             ValidateTemplate(block_template) => {
-                let blockchain = BLOCKCHAIN.read().await;
+                let blockchain = state.blockchain.read().await;
                 let status = *block_template.header().prev_block_hash()
                     == blockchain
                         .blocks()
@@ -117,121 +861,98 @@ pub async fn handle_connection(mut socket: TcpStream) {
                     return;
                 }
             }
-            SubmitTemplate(block) => {
-                log::info!("received allegedly mined template");
-                let mut blockchain = BLOCKCHAIN.write().await;
-                if let Err(e) = blockchain.add_block(block.clone()) {
-                    log::info!("block rejected: {e}, closing connection");
+            CosignBlock(block) => {
+                let blockchain = state.blockchain.read().await;
+                let signed = state
+                    .cosigner
+                    .lock()
+                    .unwrap()
+                    .as_mut()
+                    .map(|cosigner| cosigner.sign(&blockchain, block));
+                drop(blockchain);
+                let message = match signed {
+                    None => Message::reject(
+                        request_kind,
+                        RejectCode::Unsupported,
+                        "this node doesn't co-sign blocks",
+                    ),
+                    Some(Ok(block)) => Cosigned(block),
+                    Some(Err(e)) => {
+                        log::info!("refusing to co-sign block: {e}");
+                        Message::reject(request_kind, RejectCode::Invalid, e.to_string())
+                    }
+                };
+                if let Err(e) = message.send_async(&mut socket).await {
+                    log::error!("Failed to send co-signed block: {}", e);
                     return;
                 }
-                blockchain.rebuild_utxos();
-                log::info!("block looks good, broadcasting");
-                // send block to all friend nodes
-                let nodes = crate::NODES
-                    .iter()
-                    .map(|x| x.key().clone())
-                    .collect::<Vec<_>>();
-                for node in nodes {
-                    if let Some(mut stream) = NODES.get_mut(&node) {
-                        let message = Message::NewBlock(block.clone());
-                        if message.send_async(&mut *stream).await.is_err() {
-                            log::info!("failed to send new block to node");
-                        }
+            }
+            SubmitTemplate(block) => {
+                let received_at = Utc::now();
+                log::info!("received allegedly mined template");
+                let block = match connect_new_block(&state, block, received_at).await {
+                    Ok(block) => block,
+                    Err(e) => {
+                        log::info!("block rejected: {e}, closing connection");
+                        let message =
+                            Message::reject(request_kind, RejectCode::Invalid, e.to_string());
+                        let _ = message.send_async(&mut socket).await;
+                        return;
                     }
-                }
+                };
+                log::info!("block looks good, broadcasting");
+                relay_block(&state, &block).await;
             }
             SubmitTransaction(tx) => {
                 log::info!("submit tx");
-                let mut blockchain = crate::BLOCKCHAIN.write().await;
-                if let Err(e) = blockchain.add_transaction_to_mempool(tx.clone()) {
-                    log::info!("transaction rejected, closing connection: {e}");
+                if let Err((code, reason)) = submit_transaction(&state, peer_ip, tx).await {
+                    log::info!("transaction rejected ({code:?}), closing connection: {reason}");
+                    let message = Message::reject(request_kind, code, reason);
+                    let _ = message.send_async(&mut socket).await;
                     return;
                 }
-                log::info!("added transaction to mempool");
-                // send transaction to all friend nodes
-                let nodes = crate::NODES
-                    .iter()
-                    .map(|x| x.key().clone())
-                    .collect::<Vec<_>>();
-                for node in nodes {
-                    log::info!("sending to friend: {node}");
-                    if let Some(mut stream) = crate::NODES.get_mut(&node) {
-                        let message = Message::NewTransaction(tx.clone());
-                        if message.send_async(&mut *stream).await.is_err() {
-                            log::info!("failed to send transaction to {}", node);
-                        }
-                    }
+            }
+            SubmitPriorityTransaction(tx) => {
+                log::info!("priority transaction submitted by an admin");
+                if let Err((code, reason)) = submit_priority_transaction(&state, peer_ip, tx).await
+                {
+                    log::info!("transaction rejected ({code:?}), closing connection: {reason}");
+                    let message = Message::reject(request_kind, code, reason);
+                    let _ = message.send_async(&mut socket).await;
+                    return;
                 }
-                log::info!("transaction sent to friends");
-            }
-            FetchTemplate(pubkey) => {
-                let blockchain = crate::BLOCKCHAIN.read().await;
-                let mut transactions = vec![];
-                // insert transactions from mempool
-                transactions.extend(
-                    blockchain
-                        .mempool()
-                        .iter()
-                        .take(btclib::BLOCK_TRANSACTION_CAP)
-                        .map(|(_, tx)| tx)
-                        .cloned()
-                        .collect::<Vec<_>>(),
-                );
-                // insert coinbase tx with pubkey
-                transactions.insert(
-                    0,
-                    Transaction::new(
-                        vec![],
-                        vec![TransactionOutput::new(0, Uuid::new_v4(), pubkey.clone())],
-                    ),
-                );
-                let merkle_root = MerkleRoot::calculate(&transactions);
-                let header = BlockHeader::new(
-                    Utc::now(),
-                    0,
-                    blockchain
-                        .blocks()
-                        .last()
-                        .map(|last_block| last_block.hash())
-                        .unwrap_or(Hash::zero()),
-                    merkle_root,
-                    blockchain.target(),
-                );
-                let mut block = Block::new(header, transactions);
-                let miner_fees = match block.calculated_miner_fees(
-                    &blockchain
-                        .utxos()
-                        .iter()
-                        .map(|(k, v)| (*k, (false, v.clone())))
-                        .collect(),
-                ) {
-                    Ok(fees) => fees,
-                    Err(e) => {
-                        eprintln!("{e}");
-                        return;
-                    }
+            }
+            BuildTransaction(from, recipients, fee_rate) => {
+                log::info!("received request to build a transaction");
+                let message = match build_transaction(&state, &from, &recipients, fee_rate).await {
+                    Ok(unsigned) => UnsignedTransaction(unsigned),
+                    Err((code, reason)) => Message::reject(request_kind, code, reason),
                 };
-                let reward = blockchain.calculate_block_reward();
-                // update coinbase tx with reward and recalculate merkle root
-                let mut updated_transactions = block.transactions().clone();
-                updated_transactions[0] = Transaction::new(
-                    vec![],
-                    vec![TransactionOutput::new(
-                        reward + miner_fees,
-                        Uuid::new_v4(),
+                if let Err(e) = message.send_async(&mut socket).await {
+                    log::error!("Failed to send unsigned transaction: {}", e);
+                    return;
+                }
+            }
+            FetchTemplate(pubkey, coinbase_tag) => {
+                let blockchain = state.blockchain.read().await;
+                let message = if blockchain
+                    .consensus()
+                    .proposer(blockchain.block_height())
+                    .is_some()
+                {
+                    Message::reject(
+                        request_kind,
+                        RejectCode::Unsupported,
+                        "blocks are signed by validators, not mined",
+                    )
+                } else {
+                    Template(block_template(
+                        &blockchain,
                         pubkey,
-                    )],
-                );
-                let new_merkle_root = MerkleRoot::calculate(&updated_transactions);
-                let updated_header = BlockHeader::new(
-                    block.header().timestamp(),
-                    0,
-                    *block.header().prev_block_hash(),
-                    new_merkle_root,
-                    blockchain.target(),
-                );
-                block = Block::new(updated_header, updated_transactions);
-                let message = Template(block);
+                        coinbase_tag,
+                        Utc::now(),
+                    ))
+                };
                 if let Err(e) = message.send_async(&mut socket).await {
                     log::error!("Failed to send template: {}", e);
                     return;
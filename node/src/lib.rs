@@ -1,15 +1,166 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex, RwLock as StdRwLock,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize},
+    },
+};
+
 use dashmap::DashMap;
-use static_init::dynamic;
-use tokio::net::TcpStream;
-use tokio::sync::RwLock;
+use tokio::sync::{Notify, RwLock, broadcast};
+
+use btclib::{
+    MAX_REORG_DEPTH,
+    client::NodeClient,
+    network::{ChainTip, NetworkLimits, PeerKind, RelayPolicy, SyncProgress},
+    types::{Blockchain, ChainHistory, Transaction},
+};
 
-use btclib::types::Blockchain;
+#[cfg(feature = "utxo-accumulator")]
+use btclib::accumulator::Forest;
+#[cfg(feature = "assets")]
+use btclib::assets::AssetLedger;
+
+use crate::util::{
+    AddressBook, AuthTokens, BlockTimingLog, ChainObserver, ChainWal, Cosigner, FreeTxQuota,
+    HealthRecorder, HealthTracker, KnownInventory, MempoolOrigins, PeerQualityBook, ProtoCapture,
+    RejectionTracker, TipPublisher, Watchtower,
+};
 
 pub mod handler;
+mod node;
 pub mod util;
 
-#[dynamic]
-pub static BLOCKCHAIN: RwLock<Blockchain> = RwLock::new(Blockchain::default());
+pub use node::*;
+
+/// Everything one node shares between its connections and background
+/// tasks. Each `Node` owns its own, so several can run in one process.
+pub struct NodeState {
+    pub blockchain: RwLock<Blockchain>,
+    pub nodes: DashMap<String, NodeClient>,
+    /// Addresses learned from discovery and `Addr` gossip
+    pub address_book: Mutex<AddressBook>,
+    /// Serving addresses only, without a blockchain or mempool
+    pub seed_only: AtomicBool,
+    /// Serving historical balances and undo data from `history`
+    pub archive: AtomicBool,
+    /// Answering `FetchTemplate`, `ValidateTemplate` and `SubmitTemplate`,
+    /// set from the configuration at startup
+    pub serve_templates: AtomicBool,
+    /// Answering `FetchZeroConfRisk`, set from the configuration at
+    /// startup
+    pub zero_conf_risk: AtomicBool,
+    /// Spent outputs and undo data of every block, indexed lazily
+    /// on archive nodes
+    pub history: RwLock<ChainHistory>,
+    /// Blocks whose undo data `history` keeps, 0 for all of them; set
+    /// from the configuration at startup
+    pub undo_retention: AtomicU64,
+    /// Best block changes, fanned out to `SubscribeTips` connections
+    pub tip_updates: broadcast::Sender<ChainTip>,
+    /// Hooks notified of chain and mempool changes, see `register_observer`
+    pub observers: StdRwLock<Vec<Arc<dyn ChainObserver>>>,
+    /// Write-ahead log of accepted blocks, opened at startup
+    pub wal: Mutex<Option<ChainWal>>,
+    /// Tokens accepted by `Authenticate`, loaded at startup
+    pub auth_tokens: StdRwLock<Option<AuthTokens>>,
+    /// Authority key blocks are co-signed with, set from the
+    /// configuration at startup
+    pub cosigner: Mutex<Option<Cosigner>>,
+    /// Deepest fork the node reorganizes to, set from the configuration
+    /// at startup
+    pub max_reorg_depth: AtomicU64,
+    /// Bytes the chainstate should stay within, 0 without a budget; set
+    /// from the configuration at startup
+    pub memory_budget: AtomicU64,
+    /// Signalled by an admin `Shutdown` request
+    pub shutdown: Notify,
+    /// Incoming message recorder, enabled with `--capture`
+    pub capture: Mutex<Option<ProtoCapture>>,
+    /// Traffic counters behind `FetchHealth`
+    pub health: Arc<Mutex<HealthTracker>>,
+    /// Invalid blocks and transactions from the network, alerted about
+    /// past the limits set from the configuration at startup
+    pub rejections: Mutex<RejectionTracker>,
+    /// When recent blocks arrived and were connected, for `FetchBlockTimings`
+    pub block_timings: Mutex<BlockTimingLog>,
+    /// The blockchain download under way or the last one, for
+    /// `FetchSyncProgress`
+    pub sync_progress: Mutex<Option<SyncProgress>>,
+    /// Relay rules, set from the configuration at startup
+    pub policy: StdRwLock<RelayPolicy>,
+    /// Connection limits, set from the configuration at startup
+    pub limits: StdRwLock<NetworkLimits>,
+    /// Incoming connections currently served
+    pub connections: AtomicUsize,
+    /// Sessions opened by `Hello`, per kind, limited by `limits`
+    pub sessions: Mutex<HashMap<PeerKind, usize>>,
+    /// Free transactions relayed per peer, limited by `policy`
+    pub free_tx_quota: Mutex<FreeTxQuota>,
+    /// The peer each mempool transaction came from, limited by `policy`
+    pub mempool_origins: Mutex<MempoolOrigins>,
+    /// What each peer already has, so it isn't sent again
+    pub known_inventory: Mutex<KnownInventory>,
+    /// How well each peer served us, to prefer the best ones
+    pub peer_quality: Mutex<PeerQualityBook>,
+    /// Transactions waiting for the next relay round
+    pub tx_relay_queue: Mutex<Vec<Transaction>>,
+    /// Watched outputs, set at startup on watchtower nodes
+    pub watchtower: StdRwLock<Option<Arc<Watchtower>>>,
+    /// Asset balances, indexed lazily from the blockchain
+    #[cfg(feature = "assets")]
+    pub assets: RwLock<AssetLedger>,
+    /// UTXO accumulator, built lazily from the blockchain
+    #[cfg(feature = "utxo-accumulator")]
+    pub utxo_accumulator: RwLock<Forest>,
+}
 
-#[dynamic]
-pub static NODES: DashMap<String, TcpStream> = DashMap::new();
+impl Default for NodeState {
+    fn default() -> Self {
+        let tip_updates = broadcast::channel(16).0;
+        let health = Arc::new(Mutex::new(HealthTracker::default()));
+        // the observers every node starts with
+        let observers: Vec<Arc<dyn ChainObserver>> = vec![
+            Arc::new(TipPublisher::new(tip_updates.clone())),
+            Arc::new(HealthRecorder::new(health.clone())),
+        ];
+        NodeState {
+            blockchain: RwLock::new(Blockchain::default()),
+            nodes: DashMap::new(),
+            address_book: Mutex::new(AddressBook::default()),
+            seed_only: AtomicBool::new(false),
+            archive: AtomicBool::new(false),
+            serve_templates: AtomicBool::new(true),
+            zero_conf_risk: AtomicBool::new(false),
+            history: RwLock::new(ChainHistory::default()),
+            undo_retention: AtomicU64::new(0),
+            tip_updates,
+            observers: StdRwLock::new(observers),
+            wal: Mutex::new(None),
+            auth_tokens: StdRwLock::new(None),
+            cosigner: Mutex::new(None),
+            max_reorg_depth: AtomicU64::new(MAX_REORG_DEPTH),
+            memory_budget: AtomicU64::new(0),
+            shutdown: Notify::new(),
+            capture: Mutex::new(None),
+            health,
+            rejections: Mutex::new(RejectionTracker::default()),
+            block_timings: Mutex::new(BlockTimingLog::default()),
+            sync_progress: Mutex::new(None),
+            policy: StdRwLock::new(RelayPolicy::default()),
+            limits: StdRwLock::new(NetworkLimits::default()),
+            connections: AtomicUsize::new(0),
+            sessions: Mutex::new(HashMap::new()),
+            free_tx_quota: Mutex::new(FreeTxQuota::default()),
+            mempool_origins: Mutex::new(MempoolOrigins::default()),
+            known_inventory: Mutex::new(KnownInventory::default()),
+            peer_quality: Mutex::new(PeerQualityBook::default()),
+            tx_relay_queue: Mutex::new(vec![]),
+            watchtower: StdRwLock::new(None),
+            #[cfg(feature = "assets")]
+            assets: RwLock::new(AssetLedger::default()),
+            #[cfg(feature = "utxo-accumulator")]
+            utxo_accumulator: RwLock::new(Forest::default()),
+        }
+    }
+}
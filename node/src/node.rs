@@ -0,0 +1,527 @@
+use std::{
+    net::SocketAddr,
+    path::Path,
+    sync::{Arc, atomic::Ordering},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use btclib::{
+    IDEAL_BLOCK_TIME, MAX_BLOCK_SIZE, MAX_REORG_DEPTH,
+    consensus::{ConsensusEngine, ProofOfWork},
+    crypto::PrivateKey,
+    network::{NetworkLimits, RelayPolicy},
+    types::AuthoritySet,
+};
+use log::info;
+use tokio::{
+    net::TcpListener,
+    sync::Semaphore,
+    task::{JoinHandle, JoinSet},
+};
+
+use crate::{
+    NodeState,
+    handler::handle_connection,
+    util::{
+        HttpConfig, OutboundConfig, RejectionLimits, WebhookConfig, advertise_address, bind_http,
+        check_cosign_key, check_validator_key, checkpoint, cleanup, compact, init_archive,
+        init_auth, init_authority, init_consensus, init_limits, init_max_block_size,
+        init_memory_budget, init_policy, init_rejection_limits, init_undo_retention,
+        load_blockchain, maintain_outbound, monitor_health, monitor_memory, network_limits,
+        open_wal, populate_connections, produce_blocks, rank_chain_nodes, reconcile_mempools, save,
+        serve_http, start_capture, start_watchtower, start_webhooks, sync_blockchain,
+        trickle_transactions, wal_path,
+    },
+};
+
+/// How a `Node` is set up, usually built from the command line with
+/// `Cli::node_config`.
+#[derive(Debug, Clone)]
+pub struct NodeConfig {
+    /// Port to listen on, 0 picks a free one
+    pub port: u16,
+    pub blockchain_file: String,
+    /// Peers to connect to and download the blockchain from
+    pub nodes: Vec<String>,
+    /// Record all incoming protocol messages to this file
+    pub capture: Option<String>,
+    pub relay_policy: RelayPolicy,
+    /// Message size, connection and timing limits, checked on start
+    pub limits: NetworkLimits,
+    /// Only take part in address discovery, without keeping a
+    /// blockchain or mempool. `blockchain_file`, if not empty, is only
+    /// used to locate the auth tokens.
+    pub seed_only: bool,
+    /// Keep the history of every output and the undo data of every
+    /// block, to answer historical balance and undo queries
+    pub archive: bool,
+    /// Watch outputs registered with `WatchOutpoints` and report
+    /// conflicting spends of them as double spends, see `Watchtower`
+    pub watchtower: bool,
+    /// Fetch the transactions missing from our mempool from every peer
+    /// on startup, see `reconcile_mempools`
+    pub mempool_sync: bool,
+    /// Don't listen for peers, only connect out to them and poll them
+    /// for blocks and transactions. The port is bound on the loopback
+    /// interface only, for local wallets, miners and admin tools.
+    pub outbound_only: Option<OutboundConfig>,
+    /// Hand out and accept block templates, for miners
+    pub serve_templates: bool,
+    /// Answer `FetchZeroConfRisk` and `GET /risk`, asking every peer
+    /// whether it has the transaction, see `assess_zero_conf_risk`
+    pub zero_conf_risk: bool,
+    /// HTTP endpoints told about new blocks and large transactions
+    pub webhooks: WebhookConfig,
+    /// Also answer light wallets with JSON over HTTP, for browsers
+    pub http: Option<HttpConfig>,
+    /// Keys that have to co-sign blocks, on a permissioned chain
+    pub authority: Option<AuthoritySet>,
+    /// Co-sign blocks with this key, one of `authority`'s
+    pub cosign_key: Option<PrivateKey>,
+    /// How blocks are sealed, proof of work by default
+    pub consensus: Arc<dyn ConsensusEngine>,
+    /// Sign a block with this key whenever it is its turn, on a chain
+    /// whose blocks are signed by validators instead of mined
+    pub validator_key: Option<PrivateKey>,
+    /// How often the validator checks whether it is its turn
+    pub block_interval: Duration,
+    /// Most blocks a reorganization onto a longer branch may disconnect.
+    /// Deeper forks are reported as chain conflicts instead.
+    pub max_reorg_depth: u64,
+    /// Most bytes of transactions in a block, see
+    /// `Blockchain::set_max_block_size`. Every node of the chain has to
+    /// use the same limit, and full blocks have to fit in a message.
+    pub max_block_size: usize,
+    /// Blocks whose undo data an archive node keeps, every block's
+    /// without a limit. Older undo data is dropped every minute, see
+    /// `compact`.
+    pub undo_retention: Option<u64>,
+    /// Bytes the UTXO set and mempool should stay within. The node
+    /// warns as they get close, see `monitor_memory`.
+    pub memory_budget: Option<u64>,
+    /// Invalid blocks and transactions a minute from which the node
+    /// alerts, see `record_rejection`
+    pub rejection_limits: RejectionLimits,
+}
+
+impl NodeConfig {
+    pub fn new(blockchain_file: impl Into<String>) -> Self {
+        NodeConfig {
+            port: 9000,
+            blockchain_file: blockchain_file.into(),
+            nodes: vec![],
+            capture: None,
+            relay_policy: RelayPolicy::default(),
+            limits: NetworkLimits::default(),
+            seed_only: false,
+            archive: false,
+            watchtower: false,
+            mempool_sync: true,
+            outbound_only: None,
+            serve_templates: true,
+            zero_conf_risk: false,
+            webhooks: WebhookConfig::default(),
+            http: None,
+            authority: None,
+            cosign_key: None,
+            consensus: Arc::new(ProofOfWork),
+            validator_key: None,
+            block_interval: Duration::from_secs(IDEAL_BLOCK_TIME),
+            max_reorg_depth: MAX_REORG_DEPTH,
+            max_block_size: MAX_BLOCK_SIZE,
+            undo_retention: None,
+            memory_budget: None,
+            rejection_limits: RejectionLimits::default(),
+        }
+    }
+}
+
+/// A node with its own state, listener and background tasks, so it can
+/// be embedded in another program and several can run in one process.
+pub struct Node {
+    config: NodeConfig,
+    state: Arc<NodeState>,
+    local_addr: Option<SocketAddr>,
+    http_addr: Option<SocketAddr>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Node {
+    pub fn new(config: NodeConfig) -> Self {
+        Node {
+            config,
+            state: Arc::new(NodeState::default()),
+            local_addr: None,
+            http_addr: None,
+            tasks: vec![],
+        }
+    }
+
+    pub fn config(&self) -> &NodeConfig {
+        &self.config
+    }
+
+    pub fn state(&self) -> &Arc<NodeState> {
+        &self.state
+    }
+
+    /// The address the node listens on, once started.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    /// The address light wallets are served on over HTTP, once started
+    /// with `NodeConfig::http`.
+    pub fn http_addr(&self) -> Option<SocketAddr> {
+        self.http_addr
+    }
+
+    /// Loads the blockchain (or downloads it from the configured peers),
+    /// starts listening and spawns the background tasks. Returns the
+    /// address the node listens on.
+    pub async fn start(&mut self) -> Result<SocketAddr> {
+        anyhow::ensure!(self.local_addr.is_none(), "node is already running");
+        anyhow::ensure!(
+            !(self.config.seed_only && self.config.archive),
+            "a seed-only node keeps no blockchain to archive"
+        );
+        anyhow::ensure!(
+            !(self.config.seed_only && self.config.watchtower),
+            "a seed-only node keeps no mempool to watch"
+        );
+        anyhow::ensure!(
+            !(self.config.seed_only && self.config.http.is_some()),
+            "a seed-only node has no blockchain to serve light wallets from"
+        );
+        anyhow::ensure!(
+            !(self.config.seed_only && self.config.outbound_only.is_some()),
+            "a seed-only node that doesn't listen has no one to serve addresses to"
+        );
+        anyhow::ensure!(
+            self.config
+                .outbound_only
+                .as_ref()
+                .is_none_or(|outbound| !outbound.poll_interval.is_zero()),
+            "the outbound poll interval must be positive"
+        );
+        anyhow::ensure!(
+            self.config.undo_retention.is_none() || self.config.archive,
+            "only archive nodes keep undo data"
+        );
+        anyhow::ensure!(
+            self.config.undo_retention != Some(0),
+            "the undo retention must be positive"
+        );
+        anyhow::ensure!(
+            self.config.memory_budget != Some(0),
+            "the memory budget must be positive"
+        );
+        anyhow::ensure!(
+            self.config.max_block_size > 0,
+            "the maximum block size must be positive"
+        );
+        if self.config.max_block_size >= self.config.limits.max_message_size {
+            log::warn!(
+                "Blocks of up to {} bytes may not fit in messages of at most {} bytes",
+                self.config.max_block_size,
+                self.config.limits.max_message_size
+            );
+        }
+        let state = &self.state;
+        init_limits(state, self.config.limits, &self.config.relay_policy)
+            .context("invalid network limits")?;
+        self.config.webhooks.validate()?;
+        check_cosign_key(
+            self.config.authority.as_ref(),
+            self.config.cosign_key.as_ref(),
+        )?;
+        check_validator_key(&*self.config.consensus, self.config.validator_key.as_ref())?;
+        let blockchain_file = self.config.blockchain_file.as_str();
+
+        if self.config.seed_only {
+            info!("Running as a seed node, serving addresses only");
+            state.seed_only.store(true, Ordering::Relaxed);
+            // the configured peers are only asked for the nodes they know
+            populate_connections(state, &self.config.nodes).await?;
+            if !blockchain_file.is_empty() {
+                init_auth(state, blockchain_file)?;
+            }
+        } else {
+            self.init_chain().await?;
+        }
+        if self.config.archive {
+            info!("Running as an archive node");
+            init_archive(state).await;
+            if let Some(blocks) = self.config.undo_retention {
+                info!("Keeping the undo data of the last {blocks} blocks");
+            }
+            init_undo_retention(state, self.config.undo_retention);
+        }
+        if !self.config.serve_templates {
+            info!("Not serving block templates");
+        }
+        state
+            .serve_templates
+            .store(self.config.serve_templates, Ordering::Relaxed);
+        if self.config.zero_conf_risk {
+            info!("Scoring the zero-conf risk of mempool transactions");
+        }
+        state
+            .zero_conf_risk
+            .store(self.config.zero_conf_risk, Ordering::Relaxed);
+        info!("Relay policy: {:?}", self.config.relay_policy);
+        init_policy(state, self.config.relay_policy);
+        state
+            .max_reorg_depth
+            .store(self.config.max_reorg_depth, Ordering::Relaxed);
+        if let Some(budget) = self.config.memory_budget {
+            info!("Chainstate memory budget: {} bytes", budget);
+        }
+        init_memory_budget(state, self.config.memory_budget);
+        init_rejection_limits(state, self.config.rejection_limits);
+        if let Some(capture) = &self.config.capture {
+            info!("Capturing incoming messages to {}", capture);
+            start_capture(state, capture)?;
+        }
+        if !self.config.seed_only && !state.nodes.is_empty() {
+            if self.config.mempool_sync {
+                let added = reconcile_mempools(state).await;
+                info!("Fetched {} transactions missing from our mempool", added);
+            } else {
+                info!("Not fetching the mempools of peers, mempool sync is off");
+            }
+        }
+
+        // peers can't reach a node behind a NAT, local clients still can
+        let interface = match self.config.outbound_only {
+            Some(_) => "127.0.0.1",
+            None => "0.0.0.0",
+        };
+        let addr = format!("{interface}:{}", self.config.port);
+        let listener = TcpListener::bind(&addr)
+            .await
+            .with_context(|| format!("failed to listen on {addr}"))?;
+        let local_addr = listener.local_addr()?;
+        if self.config.outbound_only.is_some() {
+            info!(
+                "Not listening for peers, serving local clients on {}",
+                local_addr
+            );
+        } else {
+            info!("Node listening on {}", local_addr);
+            advertise_address(state, local_addr.port()).await;
+        }
+
+        // shared by the protocol and HTTP listeners to prevent DoS
+        let connection_limit = Arc::new(Semaphore::new(network_limits(state).max_connections));
+        self.tasks = vec![
+            tokio::spawn(accept_connections(
+                listener,
+                state.clone(),
+                connection_limit.clone(),
+            )),
+            tokio::spawn(cleanup(state.clone())),
+        ];
+        if !self.config.seed_only {
+            self.tasks.extend([
+                tokio::spawn(monitor_health(state.clone())),
+                tokio::spawn(monitor_memory(state.clone())),
+                tokio::spawn(save(state.clone(), blockchain_file.to_string())),
+                tokio::spawn(trickle_transactions(state.clone())),
+            ]);
+            if self.config.undo_retention.is_some() {
+                self.tasks.push(tokio::spawn(compact(state.clone())));
+            }
+            if let Some(outbound) = &self.config.outbound_only {
+                info!(
+                    "Keeping {} outbound peers, polled every {:?}",
+                    outbound.peers, outbound.poll_interval
+                );
+                self.tasks.push(tokio::spawn(maintain_outbound(
+                    state.clone(),
+                    outbound.clone(),
+                    self.config.mempool_sync,
+                )));
+            }
+            if let Some(key) = &self.config.validator_key {
+                info!(
+                    "Producing blocks as a validator every {:?}",
+                    self.config.block_interval
+                );
+                self.tasks.push(tokio::spawn(produce_blocks(
+                    state.clone(),
+                    key.clone(),
+                    self.config.block_interval,
+                )));
+            }
+            if !self.config.webhooks.hooks.is_empty() {
+                info!(
+                    "Sending events to {} webhooks",
+                    self.config.webhooks.hooks.len()
+                );
+                let webhooks = self.config.webhooks.clone();
+                self.tasks.push(start_webhooks(state, webhooks));
+            }
+            if self.config.watchtower {
+                info!("Watching outputs for double spends");
+                self.tasks.push(start_watchtower(state));
+            }
+            if let Some(http) = &self.config.http {
+                let (listener, http_addr) = bind_http(http).await?;
+                self.tasks.push(tokio::spawn(serve_http(
+                    listener,
+                    state.clone(),
+                    http.cors_origin.clone(),
+                    connection_limit,
+                )));
+                self.http_addr = Some(http_addr);
+            }
+        }
+        info!(
+            "Node ready to accept connections (max: {})",
+            self.config.limits.max_connections
+        );
+        self.local_addr = Some(local_addr);
+        Ok(local_addr)
+    }
+
+    // loads or downloads the blockchain and opens its write-ahead log
+    // and auth tokens
+    async fn init_chain(&self) -> Result<()> {
+        let state = &self.state;
+        let blockchain_file = self.config.blockchain_file.as_str();
+        let nodes = &self.config.nodes;
+        init_consensus(state, self.config.consensus.clone()).await;
+        init_max_block_size(state, self.config.max_block_size).await;
+        init_authority(
+            state,
+            self.config.authority.clone(),
+            self.config.cosign_key.clone(),
+        )
+        .await;
+
+        // Load or initialize the blockchain
+        if Path::new(blockchain_file).exists() || Path::new(&wal_path(blockchain_file)).exists() {
+            info!("Loading blockchain from file: {}", blockchain_file);
+            load_blockchain(state, blockchain_file).await?;
+            // back after a restart, the peers have what we missed meanwhile
+            if !nodes.is_empty()
+                && let Err(e) = populate_connections(state, nodes).await
+            {
+                log::warn!("Failed to reconnect to the configured nodes: {e}");
+            }
+        } else {
+            log::warn!("Blockchain file does not exist!");
+            if !nodes.is_empty() {
+                populate_connections(state, nodes).await?;
+                info!("Total amount of known nodes: {}", state.nodes.len());
+                let peers = rank_chain_nodes(state).await;
+                // request the blockchain from the node with the longest blockchain
+                if !peers.is_empty() {
+                    sync_blockchain(state, &peers).await?;
+                    // recalculate UTXOs and adjust target if necessary
+                    let mut blockchain = state.blockchain.write().await;
+                    blockchain.rebuild_utxos();
+                    blockchain.try_adjust_target();
+                } else {
+                    info!("Connected nodes have empty blockchains, starting with empty blockchain");
+                }
+            } else {
+                info!("No initial nodes provided, starting as a seed node with empty blockchain");
+            }
+        }
+
+        // Blocks accepted from now on are logged until the next save covers them
+        open_wal(state, blockchain_file)?;
+        // Tokens for the read-only and admin roles live next to the blockchain file
+        init_auth(state, blockchain_file)?;
+        Ok(())
+    }
+
+    /// Resolves when an admin asks the node to shut down.
+    pub async fn stopped(&self) {
+        self.state.shutdown.notified().await;
+    }
+
+    /// Stops listening, drops every connection and background task and
+    /// saves the blockchain one last time (unless seed-only).
+    pub async fn stop(&mut self) -> Result<()> {
+        if self.local_addr.take().is_none() {
+            return Ok(());
+        }
+        self.http_addr = None;
+        for task in self.tasks.drain(..) {
+            task.abort();
+            // only a cancellation is expected here
+            let _ = task.await;
+        }
+        if self.config.seed_only {
+            info!("Node shutdown complete");
+            return Ok(());
+        }
+        let blockchain = self.state.blockchain.read().await;
+        checkpoint(&self.state, &blockchain, &self.config.blockchain_file)?;
+        info!("Node shutdown complete");
+        Ok(())
+    }
+}
+
+async fn accept_connections(
+    listener: TcpListener,
+    state: Arc<NodeState>,
+    connection_limit: Arc<Semaphore>,
+) {
+    // dropped, and with it every connection, when the node stops
+    let mut connections = JoinSet::new();
+    loop {
+        // reap finished connections so the set doesn't grow forever
+        while connections.try_join_next().is_some() {}
+        match listener.accept().await {
+            Ok((socket, addr)) => {
+                info!("New connection from: {}", addr);
+
+                // Acquire connection permit
+                let permit = match connection_limit.clone().try_acquire_owned() {
+                    Ok(permit) => permit,
+                    Err(_) => {
+                        log::warn!(
+                            "Connection limit reached, rejecting connection from {}",
+                            addr
+                        );
+                        continue;
+                    }
+                };
+
+                let state = state.clone();
+                let counted = CountedConnection::new(state.clone());
+                connections.spawn(async move {
+                    let _permit = permit; // Hold permit until task completes
+                    let _counted = counted;
+                    handle_connection(state, socket).await;
+                    info!("Connection from {} closed", addr);
+                });
+            }
+            Err(e) => {
+                log::error!("Failed to accept connection: {}", e);
+            }
+        }
+    }
+}
+
+// counts a connection in `NodeState::connections` for as long as it
+// lives, even if its task is aborted
+struct CountedConnection(Arc<NodeState>);
+
+impl CountedConnection {
+    fn new(state: Arc<NodeState>) -> Self {
+        state.connections.fetch_add(1, Ordering::Relaxed);
+        CountedConnection(state)
+    }
+}
+
+impl Drop for CountedConnection {
+    fn drop(&mut self) {
+        self.0.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
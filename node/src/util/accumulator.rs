@@ -0,0 +1,23 @@
+use btclib::{
+    accumulator::{Stump, UtxoProof},
+    custom_sha_types::Hash,
+};
+
+use crate::{NodeState, util::ChainstateSnapshot};
+
+/// The roots of the UTXO accumulator at the tip. The forest catches up
+/// with any blocks added since it was last queried.
+pub async fn utxo_accumulator(state: &NodeState) -> Stump {
+    let blockchain = ChainstateSnapshot::take(state).await;
+    let mut forest = state.utxo_accumulator.write().await;
+    forest.index(&blockchain);
+    forest.stump()
+}
+
+/// The proof that `outpoint` is unspent at the tip, `None` if it isn't.
+pub async fn utxo_proof(state: &NodeState, outpoint: &Hash) -> Option<UtxoProof> {
+    let blockchain = ChainstateSnapshot::take(state).await;
+    let mut forest = state.utxo_accumulator.write().await;
+    forest.index(&blockchain);
+    forest.prove(outpoint)
+}
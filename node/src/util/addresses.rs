@@ -0,0 +1,136 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    net::SocketAddr,
+};
+
+use log::{debug, info};
+
+use crate::NodeState;
+
+// addresses remembered before the oldest are forgotten
+const MAX_ADDRESSES: usize = 1_000;
+// addresses taken from a single Addr message, the rest is ignored
+const MAX_ADDRESSES_PER_MESSAGE: usize = 100;
+
+/// Addresses of nodes we heard about through `Addr` announcements or
+/// discovery, whether or not we are connected to them. Only the most
+/// recent `MAX_ADDRESSES` are remembered.
+#[derive(Debug, Default)]
+pub struct AddressBook {
+    addresses: HashSet<String>,
+    order: VecDeque<String>,
+}
+
+impl AddressBook {
+    /// Remembers `address`. False if it was already known.
+    pub fn insert(&mut self, address: &str) -> bool {
+        if !self.addresses.insert(address.to_string()) {
+            return false;
+        }
+        self.order.push_back(address.to_string());
+        if self.order.len() > MAX_ADDRESSES
+            && let Some(oldest) = self.order.pop_front()
+        {
+            self.addresses.remove(&oldest);
+        }
+        true
+    }
+
+    pub fn contains(&self, address: &str) -> bool {
+        self.addresses.contains(address)
+    }
+
+    /// The known addresses, most recently learned first.
+    pub fn addresses(&self) -> Vec<String> {
+        self.order.iter().rev().cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+}
+
+/// Whether `address` is an `ip:port` another node could connect to.
+pub fn is_reachable_address(address: &str) -> bool {
+    address
+        .parse::<SocketAddr>()
+        .is_ok_and(|addr| !addr.ip().is_unspecified() && addr.port() != 0)
+}
+
+/// Adds the usable `addresses` to the address book and returns the
+/// ones that were new.
+pub fn learn_addresses(state: &NodeState, addresses: Vec<String>) -> Vec<String> {
+    let mut book = state.address_book.lock().unwrap();
+    addresses
+        .into_iter()
+        .take(MAX_ADDRESSES_PER_MESSAGE)
+        .filter(|address| is_reachable_address(address))
+        .filter(|address| book.insert(address))
+        .collect()
+}
+
+/// Every address we know of: connected peers first, then the address
+/// book. This is what `DiscoverNodes` is answered with.
+pub fn known_addresses(state: &NodeState) -> Vec<String> {
+    let mut addresses = state
+        .nodes
+        .iter()
+        .map(|entry| entry.key().clone())
+        .collect::<Vec<_>>();
+    let book = state.address_book.lock().unwrap().addresses();
+    for address in book {
+        if !addresses.contains(&address) {
+            addresses.push(address);
+        }
+    }
+    addresses
+}
+
+/// Passes newly learned `addresses` on to every connected peer.
+/// Addresses are only gossiped when they are new to us, so an
+/// announcement dies out once every node has seen it.
+pub async fn gossip_addresses(state: &NodeState, addresses: Vec<String>) {
+    if addresses.is_empty() {
+        return;
+    }
+    debug!("gossiping {} new addresses", addresses.len());
+    let nodes = state
+        .nodes
+        .iter()
+        .map(|entry| entry.key().clone())
+        .collect::<Vec<_>>();
+    for node in nodes {
+        if let Some(mut client) = state.nodes.get_mut(&node)
+            && client.announce_addresses(addresses.clone()).await.is_err()
+        {
+            info!("failed to send addresses to {node}");
+        }
+    }
+}
+
+/// Tells every connected peer that we listen on `port`, at the address
+/// we reach that peer from.
+pub async fn advertise_address(state: &NodeState, port: u16) {
+    let nodes = state
+        .nodes
+        .iter()
+        .map(|entry| entry.key().clone())
+        .collect::<Vec<_>>();
+    for node in nodes {
+        let Some(mut client) = state.nodes.get_mut(&node) else {
+            continue;
+        };
+        let Ok(local) = client.local_addr() else {
+            continue;
+        };
+        let address = SocketAddr::new(local.ip(), port).to_string();
+        info!("advertising {address} to {node}");
+        if client.announce_addresses(vec![address]).await.is_err() {
+            info!("failed to advertise our address to {node}");
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use std::sync::{Arc, atomic::Ordering};
+
+use btclib::{
+    crypto::PublicKey,
+    types::{BlockHeight, BlockUndo},
+};
+use log::info;
+use tokio::time;
+
+use crate::{NodeState, util::ChainstateSnapshot};
+
+pub fn is_archive(state: &NodeState) -> bool {
+    state.archive.load(Ordering::Relaxed)
+}
+
+/// Makes the node an archive node and indexes the history of the
+/// blockchain loaded so far, so the first query doesn't have to.
+pub async fn init_archive(state: &NodeState) {
+    state.archive.store(true, Ordering::Relaxed);
+    let blockchain = state.blockchain.read().await;
+    let mut history = state.history.write().await;
+    history.index(&blockchain);
+    info!("Archive indexed {} blocks", history.height());
+}
+
+/// Makes the archive keep the undo data of only the last `blocks`
+/// blocks, or of every block with `None`.
+pub fn init_undo_retention(state: &NodeState, blocks: Option<u64>) {
+    state
+        .undo_retention
+        .store(blocks.unwrap_or(0), Ordering::Relaxed);
+}
+
+/// Brings the archive up to date and drops the undo data of blocks
+/// past the retention. Returns how many blocks lost theirs.
+pub async fn compact_history(state: &NodeState) -> u64 {
+    let keep = state.undo_retention.load(Ordering::Relaxed);
+    if keep == 0 {
+        return 0;
+    }
+    let blockchain = ChainstateSnapshot::take(state).await;
+    let mut history = state.history.write().await;
+    history.index(&blockchain);
+    history.prune_undo(keep)
+}
+
+/// Compacts the archive every minute, see `compact_history`.
+pub async fn compact(state: Arc<NodeState>) {
+    let mut interval = time::interval(time::Duration::from_secs(60));
+    loop {
+        interval.tick().await;
+        let pruned = compact_history(&state).await;
+        if pruned > 0 {
+            info!("dropped the undo data of {pruned} blocks");
+        }
+    }
+}
+
+/// Balance of `pubkey` just before the block at `height`, if the chain
+/// got that far. The index catches up with any blocks added since it
+/// was last queried.
+pub async fn balance_at(state: &NodeState, pubkey: &PublicKey, height: BlockHeight) -> Option<u64> {
+    let blockchain = ChainstateSnapshot::take(state).await;
+    if height > blockchain.block_height() {
+        return None;
+    }
+    let mut history = state.history.write().await;
+    history.index(&blockchain);
+    Some(history.balance_at(pubkey, height))
+}
+
+/// Undo data of the block at `height`, if there is one and it wasn't
+/// pruned.
+pub async fn block_undo(state: &NodeState, height: BlockHeight) -> Option<BlockUndo> {
+    let blockchain = ChainstateSnapshot::take(state).await;
+    let mut history = state.history.write().await;
+    history.index(&blockchain);
+    history.undo(height).cloned()
+}
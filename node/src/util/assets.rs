@@ -0,0 +1,16 @@
+use btclib::{crypto::PublicKey, custom_sha_types::Hash};
+
+use crate::{NodeState, util::ChainstateSnapshot};
+
+/// Assets held by `owner` as (asset id, ticker, amount). The index
+/// catches up with any blocks added since it was last queried.
+pub async fn asset_balances(state: &NodeState, owner: &PublicKey) -> Vec<(Hash, String, u64)> {
+    let blockchain = ChainstateSnapshot::take(state).await;
+    let mut ledger = state.assets.write().await;
+    ledger.index(&blockchain);
+    ledger
+        .balances_of(owner)
+        .into_iter()
+        .map(|(asset, info, amount)| (asset, info.ticker.clone(), amount))
+        .collect()
+}
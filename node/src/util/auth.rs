@@ -0,0 +1,96 @@
+use std::{fs, io::ErrorKind, path::Path};
+
+use anyhow::{Context, Result};
+use btclib::network::Role;
+use log::info;
+
+use crate::NodeState;
+
+/// Secrets that grant a connection a role, see `Message::Authenticate`.
+#[derive(Debug, Clone)]
+pub struct AuthTokens {
+    read_only: String,
+    admin: String,
+}
+
+impl AuthTokens {
+    pub fn new(read_only: String, admin: String) -> Self {
+        AuthTokens { read_only, admin }
+    }
+
+    /// Reads the tokens stored next to `blockchain_file`, generating
+    /// any that don't exist yet.
+    pub fn load_or_generate(blockchain_file: &str) -> Result<Self> {
+        Ok(AuthTokens {
+            read_only: load_or_generate_token(&token_path(blockchain_file, Role::ReadOnly))?,
+            admin: load_or_generate_token(&token_path(blockchain_file, Role::Admin))?,
+        })
+    }
+
+    pub fn role_for(&self, token: &str) -> Option<Role> {
+        if constant_time_eq(token, &self.admin) {
+            Some(Role::Admin)
+        } else if constant_time_eq(token, &self.read_only) {
+            Some(Role::ReadOnly)
+        } else {
+            None
+        }
+    }
+}
+
+pub fn token_path(blockchain_file: &str, role: Role) -> String {
+    match role {
+        Role::ReadOnly => format!("{blockchain_file}.readonly.token"),
+        Role::Admin => format!("{blockchain_file}.admin.token"),
+    }
+}
+
+/// Loads (or generates) the auth tokens and starts accepting them.
+pub fn init_auth(state: &NodeState, blockchain_file: &str) -> Result<()> {
+    let tokens = AuthTokens::load_or_generate(blockchain_file)?;
+    *state.auth_tokens.write().unwrap() = Some(tokens);
+    Ok(())
+}
+
+/// The role granted by `token`, if any.
+pub fn authenticate(state: &NodeState, token: &str) -> Option<Role> {
+    state.auth_tokens.read().unwrap().as_ref()?.role_for(token)
+}
+
+fn load_or_generate_token(path: &str) -> Result<String> {
+    match fs::read_to_string(path) {
+        Ok(token) => return Ok(token.trim().to_string()),
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e).context(format!("failed to read auth token {path}")),
+    }
+    let token = hex::encode(rand::random::<[u8; 32]>());
+    write_private(Path::new(path), &token)
+        .with_context(|| format!("failed to write auth token {path}"))?;
+    info!("generated auth token {path}");
+    Ok(token)
+}
+
+#[cfg(unix)]
+fn write_private(path: &Path, contents: &str) -> std::io::Result<()> {
+    use std::{io::Write, os::unix::fs::OpenOptionsExt};
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(contents.as_bytes())
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, contents: &str) -> std::io::Result<()> {
+    fs::write(path, contents)
+}
+
+// avoid leaking how much of a token matched through timing
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (x, y)| diff | (x ^ y))
+            == 0
+}
@@ -0,0 +1,121 @@
+use std::collections::BTreeSet;
+
+use anyhow::{Context, Result};
+use btclib::{
+    crypto::{PrivateKey, PublicKey},
+    custom_sha_types::Hash,
+    types::{AuthoritySet, Block, BlockHeight, Blockchain},
+    utils::Saveable,
+};
+use log::info;
+
+use crate::NodeState;
+
+/// The authorities whose public key files are `key_files`, `threshold`
+/// of which (all by default) have to co-sign blocks from `from_height`
+/// (genesis by default) on. None without key files.
+pub fn load_authority(
+    key_files: &[String],
+    threshold: Option<usize>,
+    from_height: Option<u64>,
+) -> Result<Option<AuthoritySet>> {
+    if key_files.is_empty() {
+        return Ok(None);
+    }
+    let keys = key_files
+        .iter()
+        .map(|file| {
+            PublicKey::load_from_file(file)
+                .with_context(|| format!("failed to load authority key {file}"))
+        })
+        .collect::<Result<BTreeSet<_>>>()?;
+    let threshold = threshold.unwrap_or(keys.len());
+    let authority = AuthoritySet::new(keys, threshold, BlockHeight::new(from_height.unwrap_or(0)))?;
+    Ok(Some(authority))
+}
+
+pub fn load_cosign_key(key_file: Option<&str>) -> Result<Option<PrivateKey>> {
+    key_file
+        .map(|file| {
+            PrivateKey::load_from_file(file)
+                .with_context(|| format!("failed to load co-signing key {file}"))
+        })
+        .transpose()
+}
+
+/// Checks that a co-signing key belongs to the authority set.
+pub fn check_cosign_key(
+    authority: Option<&AuthoritySet>,
+    cosign_key: Option<&PrivateKey>,
+) -> Result<()> {
+    if let Some(key) = cosign_key {
+        anyhow::ensure!(
+            authority.is_some_and(|authority| authority.contains(&key.public_key())),
+            "the co-signing key is not one of the authority keys"
+        );
+    }
+    Ok(())
+}
+
+/// An authority key and the last block it signed. A co-signer never
+/// signs two different blocks at the same height, so the authorities
+/// can't be made to finalize both sides of a fork.
+#[derive(Debug, Clone)]
+pub struct Cosigner {
+    key: PrivateKey,
+    last_signed: Option<(BlockHeight, Hash)>,
+}
+
+impl Cosigner {
+    pub fn new(key: PrivateKey) -> Self {
+        Cosigner {
+            key,
+            last_signed: None,
+        }
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        self.key.public_key()
+    }
+
+    /// `block` with this key's signature added, if it can extend
+    /// `blockchain` and no other block was signed at its height.
+    /// Signatures it already carries are kept.
+    pub fn sign(&mut self, blockchain: &Blockchain, mut block: Block) -> Result<Block> {
+        blockchain.verify_block(&block)?;
+        let height = blockchain.block_height();
+        let hash = block.hash();
+        if let Some((signed_height, signed_hash)) = self.last_signed {
+            anyhow::ensure!(
+                signed_height != height || signed_hash == hash,
+                "already co-signed another block at height {height}"
+            );
+        }
+        block.cosign(&self.key);
+        self.last_signed = Some((height, hash));
+        Ok(block)
+    }
+}
+
+/// Makes the node's blockchain require co-signatures from `authority`
+/// and has the node co-sign blocks with `cosign_key`. Called before the
+/// blockchain is loaded, so downloaded and replayed blocks are checked.
+pub async fn init_authority(
+    state: &NodeState,
+    authority: Option<AuthoritySet>,
+    cosign_key: Option<PrivateKey>,
+) {
+    if let Some(authority) = &authority {
+        info!(
+            "Blocks from height {} need {} of {} authority signatures",
+            authority.from_height(),
+            authority.threshold(),
+            authority.keys().count()
+        );
+    }
+    if cosign_key.is_some() {
+        info!("Co-signing blocks as an authority");
+    }
+    state.blockchain.write().await.set_authority(authority);
+    *state.cosigner.lock().unwrap() = cosign_key.map(Cosigner::new);
+}
@@ -0,0 +1,133 @@
+use anyhow::{Result, bail};
+use btclib::{
+    custom_sha_types::Hash,
+    network::Message,
+    types::{Block, BlockHeader, BlockHeight, Transaction},
+    utils::MerkleRoot,
+};
+use chrono::Utc;
+use clap::ValueEnum;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, ToSocketAddrs},
+    time::{Duration, sleep, timeout},
+};
+
+// comfortably above the default message size limit
+const OVERSIZED_BLOCK_SIZE: usize = btclib::network::DEFAULT_MAX_MESSAGE_SIZE + 1024 * 1024;
+
+/// Misbehaviour a hostile peer can throw at a node, see the `badpeer` binary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Attack {
+    /// A length prefix far beyond the message size limit
+    MalformedLength,
+    /// A length prefix followed by fewer bytes than announced
+    TruncatedCbor,
+    /// A well-framed message whose body isn't valid CBOR
+    GarbageCbor,
+    /// A response sent as if it were a request
+    ProtocolViolation,
+    /// A block that is larger than the message size limit
+    OversizedBlock,
+    /// A valid message dribbled one byte at a time
+    SlowLoris,
+}
+
+impl Attack {
+    pub const ALL: [Attack; 6] = [
+        Attack::MalformedLength,
+        Attack::TruncatedCbor,
+        Attack::GarbageCbor,
+        Attack::ProtocolViolation,
+        Attack::OversizedBlock,
+        Attack::SlowLoris,
+    ];
+
+    /// Whether a well-behaved node answers this attack by closing the connection.
+    pub fn expects_disconnect(&self) -> bool {
+        !matches!(self, Attack::TruncatedCbor | Attack::SlowLoris)
+    }
+}
+
+/// Runs `attack` against the node at `address`. For attacks the node
+/// should hang up on, fails if the connection is still open afterwards.
+/// `dribble_delay` paces the slow-loris attack.
+pub async fn run_attack(
+    address: impl ToSocketAddrs,
+    attack: Attack,
+    dribble_delay: Duration,
+) -> Result<()> {
+    let mut stream = TcpStream::connect(address).await?;
+    match attack {
+        Attack::MalformedLength => {
+            stream.write_all(&u64::MAX.to_be_bytes()).await?;
+        }
+        Attack::TruncatedCbor => {
+            let bytes = Message::DiscoverNodes.encode()?;
+            stream
+                .write_all(&(bytes.len() as u64 + 64).to_be_bytes())
+                .await?;
+            stream.write_all(&bytes).await?;
+            // hang up before the announced length is reached
+            return Ok(());
+        }
+        Attack::GarbageCbor => {
+            let garbage = [0xff; 32];
+            stream
+                .write_all(&(garbage.len() as u64).to_be_bytes())
+                .await?;
+            stream.write_all(&garbage).await?;
+        }
+        Attack::ProtocolViolation => {
+            Message::NodeList(vec!["127.0.0.1:1".to_string()])
+                .send_async(&mut stream)
+                .await?;
+        }
+        Attack::OversizedBlock => {
+            let transactions = vec![
+                Transaction::new(vec![], vec![]).with_coinbase_tag(vec![0; OVERSIZED_BLOCK_SIZE]),
+            ];
+            let merkle_root = MerkleRoot::calculate(&transactions);
+            let header =
+                BlockHeader::new(Utc::now(), 0, Hash::zero(), merkle_root, btclib::MIN_TARGET);
+            // the node hangs up after reading the length prefix,
+            // so failing to write the rest is the expected outcome
+            let sent = Message::NewBlock(Block::new(header, transactions))
+                .send_async(&mut stream)
+                .await;
+            if sent.is_err() {
+                return Ok(());
+            }
+        }
+        Attack::SlowLoris => {
+            let bytes = Message::AskDifference(BlockHeight::GENESIS).encode()?;
+            let mut framed = (bytes.len() as u64).to_be_bytes().to_vec();
+            framed.extend_from_slice(&bytes);
+            for byte in framed {
+                stream.write_all(&[byte]).await?;
+                sleep(dribble_delay).await;
+            }
+            // the node should still answer a request that arrived this slowly
+            return match Message::receive_async(&mut stream).await? {
+                Message::Difference(_) => Ok(()),
+                other => bail!("unexpected response to a dribbled request: {other:?}"),
+            };
+        }
+    }
+    if attack.expects_disconnect() {
+        expect_disconnect(&mut stream).await?;
+    }
+    Ok(())
+}
+
+// drains whatever the node still sends (e.g. a Reject) until it hangs up
+async fn expect_disconnect(stream: &mut TcpStream) -> Result<()> {
+    let mut buffer = [0u8; 1024];
+    loop {
+        match timeout(Duration::from_secs(5), stream.read(&mut buffer)).await {
+            Ok(Ok(0)) | Ok(Err(_)) => return Ok(()),
+            Ok(Ok(_)) => continue,
+            Err(_) => bail!("node kept the connection open"),
+        }
+    }
+}
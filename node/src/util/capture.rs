@@ -0,0 +1,103 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use anyhow::{Context, Result};
+use btclib::network::Message;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::NodeState;
+
+/// One incoming protocol message, as recorded by `--capture`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CapturedMessage {
+    pub timestamp: DateTime<Utc>,
+    /// Distinguishes connections, even several from the same peer
+    pub connection: u64,
+    pub peer: String,
+    pub message: Message,
+}
+
+/// Appends captured messages to a file, framed like wire messages
+/// (big endian u64 length, then CBOR).
+#[derive(Debug)]
+pub struct ProtoCapture {
+    writer: BufWriter<File>,
+}
+
+impl ProtoCapture {
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .context("failed to open the capture file")?;
+        Ok(ProtoCapture {
+            writer: BufWriter::new(file),
+        })
+    }
+
+    pub fn record(&mut self, captured: &CapturedMessage) -> Result<()> {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(captured, &mut bytes)?;
+        self.writer.write_all(&(bytes.len() as u64).to_be_bytes())?;
+        self.writer.write_all(&bytes)?;
+        // flush per message, the node may be killed by the bug being captured
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Reads a whole capture file back, in recording order.
+    pub fn read_all(path: impl AsRef<Path>) -> Result<Vec<CapturedMessage>> {
+        let mut reader = BufReader::new(File::open(path).context("failed to open capture")?);
+        let mut captured = vec![];
+        loop {
+            let mut len_bytes = [0u8; 8];
+            match reader.read_exact(&mut len_bytes) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            }
+            let mut buffer = vec![0u8; u64::from_be_bytes(len_bytes) as usize];
+            reader
+                .read_exact(&mut buffer)
+                .context("capture ends in the middle of a message")?;
+            captured.push(ciborium::from_reader(buffer.as_slice())?);
+        }
+        Ok(captured)
+    }
+}
+
+static NEXT_CONNECTION: AtomicU64 = AtomicU64::new(0);
+
+/// A fresh id for `CapturedMessage::connection`.
+pub fn next_connection_id() -> u64 {
+    NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Starts recording every incoming message to `path`.
+pub fn start_capture(state: &NodeState, path: &str) -> Result<()> {
+    *state.capture.lock().unwrap() = Some(ProtoCapture::create(path)?);
+    Ok(())
+}
+
+/// Records an incoming message if capturing is enabled.
+pub fn capture_message(state: &NodeState, connection: u64, peer: &str, message: &Message) {
+    let mut capture = state.capture.lock().unwrap();
+    let Some(capture) = capture.as_mut() else {
+        return;
+    };
+    let captured = CapturedMessage {
+        timestamp: Utc::now(),
+        connection,
+        peer: peer.to_string(),
+        message: message.clone(),
+    };
+    if let Err(e) = capture.record(&captured) {
+        log::error!("Failed to capture message: {e}");
+    }
+}
@@ -1,40 +1,56 @@
-use anyhow::{Context, Result};
-use btclib::network::Message;
-use log::info;
+use std::time::Instant;
 
-use crate::NODES;
+use btclib::{error::ClientError, types::BlockHeight};
+use log::{info, warn};
+use tokio::time;
 
-pub async fn find_longest_chain_node() -> Result<(String, u32)> {
+use crate::{
+    NodeState,
+    util::{message_timeout, ranked_peers, record_response},
+};
+
+/// The peers with a chain to offer and the height their next block will
+/// have, best first: flaky peers (see `PeerQuality::is_flaky`) last, the
+/// others longest chain first and, among chains as long, by quality.
+/// Peers that fail to answer within the message timeout are left out.
+pub async fn rank_chain_nodes(state: &NodeState) -> Vec<(String, BlockHeight)> {
     info!("finding nodes with the highest blockchain length...");
-    let mut longest_name = String::new();
-    let mut longest_count = 0;
-    let all_nodes = NODES.iter().map(|x| x.key().clone()).collect::<Vec<_>>();
+    let mut ranked = vec![];
+    // already in order of quality, which the sort below keeps
+    let all_nodes = ranked_peers(state);
     for node in all_nodes {
-        info!("asking {} for blockchain length", node);
-        let mut stream = NODES.get_mut(&node).context("no node")?;
-        let message = Message::AskDifference(0);
-        message.send_async(&mut *stream).await.unwrap();
-        info!("sent AskDifference to {}", node);
-        let message = Message::receive_async(&mut *stream).await?;
-        match message {
-            Message::Difference(count) => {
-                info!("received Difference from {}", node);
-                if count > longest_count {
-                    info!(
-                        "new longest blockchain: \
- {} blocks from {node}",
-                        count
-                    );
-                    longest_count = count;
-                    longest_name = node;
-                }
+        let Some(mut client) = state.nodes.get_mut(&node) else {
+            continue;
+        };
+        info!("sending AskDifference to {}", node);
+        let sent = Instant::now();
+        let request = client.get_difference(BlockHeight::GENESIS);
+        let answer = time::timeout(message_timeout(state), request).await;
+        drop(client);
+        let answered = matches!(answer, Ok(Ok(_)) | Ok(Err(ClientError::Rejected { .. })));
+        record_response(state, &node, sent, answered);
+        match answer {
+            Ok(Ok(count)) if count > 0 => {
+                info!("{} serves {} blocks", node, count);
+                ranked.push((node, BlockHeight::new(count as u64)));
             }
-            e => {
+            Ok(Ok(_)) => info!("{} has an empty blockchain", node),
+            Ok(Err(ClientError::UnexpectedResponse(e))) => {
                 info!("unexpected message from {}: {:?}", node, e);
             }
+            // e.g. a seed node, which keeps no blockchain
+            Ok(Err(ClientError::Rejected { reason, .. })) => {
+                info!("{} has no chain to offer: {}", node, reason);
+            }
+            Ok(Err(e)) => warn!("failed to ask {} for its chain: {}", node, e),
+            Err(_) => warn!("{} didn't say how long its chain is in time", node),
         }
     }
-    Ok((longest_name, longest_count as u32))
+    let quality = state.peer_quality.lock().unwrap();
+    let flaky = |node: &str| quality.get(node).is_some_and(|quality| quality.is_flaky());
+    ranked.sort_by_key(|(node, height)| (flaky(node), std::cmp::Reverse(*height)));
+    drop(quality);
+    ranked
 }
 
 // TODO: a proper implementation of a consensus algorithm
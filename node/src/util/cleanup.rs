@@ -1,31 +1,44 @@
+use std::sync::Arc;
+
+use chrono::{Duration, Utc};
 use log::info;
 use tokio::time;
 
-use crate::{BLOCKCHAIN, NODES};
+use crate::{
+    NodeState,
+    util::{network_limits, notify_tx_evicted, relay_policy},
+};
 
-pub async fn cleanup() {
-    let mut interval = time::interval(time::Duration::from_secs(30));
+pub async fn cleanup(state: Arc<NodeState>) {
+    let period = time::Duration::from_secs(network_limits(&state).cleanup_interval_secs);
+    let mut interval = time::interval(period);
     loop {
         interval.tick().await;
         
         // Clean mempool
         info!("cleaning the mempool from old transactions");
         {
-            let mut blockchain = BLOCKCHAIN.write().await;
-            blockchain.cleanup_mempool();
+            let max_age = relay_policy(&state).mempool_expiry_secs;
+            let mut blockchain = state.blockchain.write().await;
+            for transaction in blockchain.cleanup_mempool(max_age) {
+                notify_tx_evicted(&state, &transaction);
+            }
+            // peers have dropped what they knew for as long by now
+            let cutoff = Utc::now() - Duration::seconds(max_age as i64);
+            state.known_inventory.lock().unwrap().expire(cutoff);
         }
         
         // Clean stale connections
         info!("checking for stale connections");
         let mut stale_nodes = Vec::new();
         
-        for entry in NODES.iter() {
+        for entry in state.nodes.iter() {
             let node_addr = entry.key().clone();
-            let stream = entry.value();
+            let client = entry.value();
             
             // Try to peek at the stream to see if it's still alive
             // If we can't peek, the connection is likely dead
-            if stream.peer_addr().is_err() {
+            if client.peer_addr().is_err() {
                 stale_nodes.push(node_addr);
             }
         }
@@ -33,9 +46,11 @@ pub async fn cleanup() {
         // Remove stale connections
         for node in stale_nodes {
             info!("Removing stale connection: {}", node);
-            NODES.remove(&node);
+            state.nodes.remove(&node);
+            state.known_inventory.lock().unwrap().forget_peer(&node);
+            state.peer_quality.lock().unwrap().record_disconnect(&node);
         }
         
-        info!("Active connections: {}", NODES.len());
+        info!("Active connections: {}", state.nodes.len());
     }
 }
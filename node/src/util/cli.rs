@@ -1,19 +1,291 @@
-use clap::Parser;
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::Result;
+use btclib::{
+    IDEAL_BLOCK_TIME, MAX_BLOCK_SIZE, MAX_REORG_DEPTH,
+    network::{NetworkLimits, RelayPolicy},
+    utils::LogConfig,
+};
+use clap::{Parser, Subcommand};
+
+use crate::{
+    NodeConfig,
+    util::{
+        ChainsConfig, DEFAULT_OUTBOUND_PEERS, DEFAULT_OUTBOUND_POLL_SECS, HttpConfig, InitOptions,
+        OutboundConfig, RejectionLimits, Webhook, WebhookConfig, load_authority, load_consensus,
+        load_cosign_key, load_validator_key, megabytes,
+    },
+};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Port to listen on
     #[arg(short, long, default_value_t = 9000)]
     port: u16,
 
     /// Path to the blockchain file
-    #[arg(short, long)]
-    blockchain_file: String,
+    #[arg(short, long, required_unless_present_any = ["config", "seed_only"])]
+    blockchain_file: Option<String>,
+
+    /// Run every chain described in this file instead (see ChainsConfig)
+    #[arg(long, conflicts_with_all = ["port", "blockchain_file", "nodes", "capture", "seed_only", "archive", "watchtower", "authority_key", "cosign_key", "validators", "validator_key"])]
+    config: Option<String>,
+
+    /// Only serve addresses to other nodes, without keeping a blockchain
+    /// or mempool. A blockchain file, if given, only locates the auth tokens
+    #[arg(long)]
+    seed_only: bool,
+
+    /// Keep the undo data of every block and answer historical balance
+    /// and undo queries
+    #[arg(long, conflicts_with = "seed_only")]
+    archive: bool,
+
+    /// Keep the undo data of only the last BLOCKS blocks on an archive
+    /// node, dropping older undo data every minute
+    #[arg(long, value_name = "BLOCKS", requires = "archive")]
+    undo_retention: Option<u64>,
+
+    /// Watch the outputs wallets register and send `double_spend`
+    /// webhooks when one is spent by two conflicting transactions
+    #[arg(long, conflicts_with = "seed_only")]
+    watchtower: bool,
+
+    /// Don't fetch the transactions missing from our mempool from the
+    /// peers on startup, only take new ones as they are relayed
+    #[arg(long, conflicts_with = "seed_only")]
+    no_mempool_sync: bool,
+
+    /// Don't listen for peers, e.g. behind a NAT: only connect out to
+    /// them and poll them for blocks and transactions. Local clients can
+    /// still connect on the loopback interface
+    #[arg(long, conflicts_with = "seed_only")]
+    nolisten: bool,
+
+    /// Peers a --nolisten node keeps connections to
+    #[arg(long, default_value_t = DEFAULT_OUTBOUND_PEERS, requires = "nolisten")]
+    outbound_peers: usize,
+
+    /// Seconds between a --nolisten node's polls of its peers
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value_t = DEFAULT_OUTBOUND_POLL_SECS,
+        requires = "nolisten"
+    )]
+    poll_interval: u64,
+
+    /// Don't hand out or accept block templates, on a node no miner uses
+    #[arg(long, conflicts_with = "seed_only")]
+    no_templates: bool,
+
+    /// Score how likely mempool transactions are to be double spent, for
+    /// merchants accepting unconfirmed payments
+    #[arg(long, conflicts_with = "seed_only")]
+    zero_conf_risk: bool,
 
     /// List of peer nodes
     #[arg(short, long, value_delimiter = ',')]
     nodes: Vec<String>,
+
+    /// Record all incoming protocol messages to this file (see proto_dump)
+    #[arg(long)]
+    capture: Option<String>,
+
+    /// Minimum relay fee in satoshis per byte [default: 1]
+    #[arg(long)]
+    min_fee_rate: Option<u64>,
+
+    /// Largest transaction relayed, in bytes [default: 100000]
+    #[arg(long)]
+    max_tx_size: Option<usize>,
+
+    /// Most inputs of a transaction relayed [default: 500]
+    #[arg(long)]
+    max_tx_inputs: Option<usize>,
+
+    /// Most outputs of a transaction relayed [default: 500]
+    #[arg(long)]
+    max_tx_outputs: Option<usize>,
+
+    /// Most data outputs of a transaction relayed [default: 1]
+    #[arg(long)]
+    max_data_outputs: Option<usize>,
+
+    /// Smallest output value relayed, in satoshis [default: 546]
+    #[arg(long)]
+    dust_threshold: Option<u64>,
+
+    /// Transactions below the minimum fee rate relayed per peer per hour [default: 10]
+    #[arg(long)]
+    free_tx_per_hour: Option<u32>,
+
+    /// Seconds a transaction without an expiry height stays in the mempool [default: 600]
+    #[arg(long)]
+    mempool_expiry: Option<u64>,
+
+    /// Mempool transactions spending one public key's outputs at a time [default: 25]
+    #[arg(long)]
+    max_unconfirmed_per_key: Option<usize>,
+
+    /// Mempool transactions from one peer at a time [default: 100]
+    #[arg(long)]
+    max_unconfirmed_per_peer: Option<usize>,
+
+    /// Largest message accepted from a peer, in bytes [default: 10485760]
+    #[arg(long)]
+    max_message_size: Option<usize>,
+
+    /// Incoming connections served at the same time [default: 100]
+    #[arg(long)]
+    max_connections: Option<usize>,
+
+    /// Seconds a message may take to arrive once it started [default: 60]
+    #[arg(long)]
+    message_timeout: Option<u64>,
+
+    /// Seconds between mempool and stale peer cleanups [default: 30]
+    #[arg(long)]
+    cleanup_interval: Option<u64>,
+
+    /// Wallet sessions served at the same time [default: 50]
+    #[arg(long)]
+    max_wallets: Option<usize>,
+
+    /// Miner sessions served at the same time [default: 10]
+    #[arg(long)]
+    max_miners: Option<usize>,
+
+    /// Longest a wallet or miner session may stay silent, in seconds [default: 300]
+    #[arg(long)]
+    max_idle_timeout: Option<u64>,
+
+    /// http:// URLs to POST block and large transaction events to
+    #[arg(long, value_delimiter = ',')]
+    webhook: Vec<String>,
+
+    /// Key the webhook bodies are signed with (X-Signature header)
+    #[arg(long, requires = "webhook")]
+    webhook_secret: Option<String>,
+
+    /// Transactions moving at least this many satoshis are sent to the
+    /// webhooks as large_tx_detected [default: 10000000000]
+    #[arg(long)]
+    large_tx_threshold: Option<u64>,
+
+    /// Times a failed webhook delivery is retried [default: 5]
+    #[arg(long)]
+    webhook_retries: Option<u32>,
+
+    /// Also serve light wallets JSON over HTTP on this port
+    #[arg(long, conflicts_with = "seed_only")]
+    http_port: Option<u16>,
+
+    /// Web origin allowed to call the HTTP port (CORS)
+    #[arg(long, default_value = "*", requires = "http_port")]
+    http_cors_origin: String,
+
+    /// Public key files of the authorities that co-sign blocks, making
+    /// this a permissioned chain
+    #[arg(long, value_delimiter = ',')]
+    authority_key: Vec<String>,
+
+    /// Authority signatures a block needs [default: all of them]
+    #[arg(long, requires = "authority_key")]
+    authority_threshold: Option<usize>,
+
+    /// Height from which blocks need authority signatures [default: 0]
+    #[arg(long, requires = "authority_key")]
+    authority_height: Option<u64>,
+
+    /// Private key file to co-sign blocks with, one of the authority keys
+    #[arg(long, requires = "authority_key")]
+    cosign_key: Option<String>,
+
+    /// Public key files of the validators taking turns to sign blocks,
+    /// in that order, replacing proof of work with proof of authority
+    #[arg(long, value_delimiter = ',')]
+    validators: Vec<String>,
+
+    /// Private key file to sign blocks with, one of the validators
+    #[arg(long, requires = "validators", conflicts_with = "seed_only")]
+    validator_key: Option<String>,
+
+    /// Seconds between checks whether it is the validator's turn [default: 600]
+    #[arg(long, requires = "validator_key")]
+    block_interval: Option<u64>,
+
+    /// Most blocks to disconnect when switching to a longer branch
+    #[arg(long, default_value_t = MAX_REORG_DEPTH, conflicts_with = "seed_only")]
+    max_reorg_depth: u64,
+
+    /// Most bytes of transactions in a block; every node of the chain
+    /// has to use the same
+    #[arg(
+        long,
+        value_name = "BYTES",
+        default_value_t = MAX_BLOCK_SIZE,
+        conflicts_with = "seed_only"
+    )]
+    max_block_size: usize,
+
+    /// Megabytes the UTXO set and mempool should stay within; the node
+    /// warns as they get close
+    #[arg(long, value_name = "MB", conflicts_with = "seed_only")]
+    memory_budget: Option<u64>,
+
+    /// Invalid blocks from peers in a minute before alerting [default: 3]
+    #[arg(long)]
+    max_invalid_blocks_per_minute: Option<u32>,
+
+    /// Invalid transactions from peers and wallets in a minute before
+    /// alerting [default: 60]
+    #[arg(long)]
+    max_invalid_txs_per_minute: Option<u32>,
+
+    /// Log to this file instead of stderr, rotating it as it grows
+    #[arg(long)]
+    log_file: Option<String>,
+
+    /// Log levels per module, e.g. info,node::handler=debug (RUST_LOG
+    /// overrides them)
+    #[arg(long)]
+    log_filter: Option<String>,
+
+    /// Megabytes past which the log file is rotated [default: 10]
+    #[arg(long, value_name = "MB")]
+    log_max_size: Option<u64>,
+
+    /// Rotated log files kept [default: 5]
+    #[arg(long)]
+    log_files: Option<usize>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Create a data directory with a configuration file and auth tokens,
+    /// then run the node with `--config <data dir>/node.toml`
+    Init {
+        /// Directory to keep the configuration, chain and log in
+        data_dir: PathBuf,
+
+        /// Port to listen on
+        #[arg(short, long, default_value_t = 9000)]
+        port: u16,
+
+        /// Port to serve light wallets on over HTTP
+        #[arg(long)]
+        http_port: Option<u16>,
+
+        /// Replace an existing configuration file
+        #[arg(long)]
+        force: bool,
+    },
 }
 
 impl Cli {
@@ -21,11 +293,192 @@ impl Cli {
         self.port
     }
 
-    pub fn blockchain_file(&self) -> &str {
-        &self.blockchain_file
+    pub fn blockchain_file(&self) -> Option<&str> {
+        self.blockchain_file.as_deref()
+    }
+
+    pub fn config(&self) -> Option<&str> {
+        self.config.as_deref()
     }
 
     pub fn nodes(&self) -> &Vec<String> {
         &self.nodes
     }
+
+    pub fn capture(&self) -> Option<&str> {
+        self.capture.as_deref()
+    }
+
+    pub fn seed_only(&self) -> bool {
+        self.seed_only
+    }
+
+    pub fn archive(&self) -> bool {
+        self.archive
+    }
+
+    pub fn watchtower(&self) -> bool {
+        self.watchtower
+    }
+
+    /// The default relay policy with any overrides given on the command line.
+    pub fn relay_policy(&self) -> RelayPolicy {
+        let default = RelayPolicy::default();
+        RelayPolicy {
+            min_fee_rate: self.min_fee_rate.unwrap_or(default.min_fee_rate),
+            max_tx_size: self.max_tx_size.unwrap_or(default.max_tx_size),
+            max_tx_inputs: self.max_tx_inputs.unwrap_or(default.max_tx_inputs),
+            max_tx_outputs: self.max_tx_outputs.unwrap_or(default.max_tx_outputs),
+            max_data_outputs: self.max_data_outputs.unwrap_or(default.max_data_outputs),
+            dust_threshold: self.dust_threshold.unwrap_or(default.dust_threshold),
+            free_tx_per_hour: self.free_tx_per_hour.unwrap_or(default.free_tx_per_hour),
+            mempool_expiry_secs: self.mempool_expiry.unwrap_or(default.mempool_expiry_secs),
+            max_unconfirmed_per_key: self
+                .max_unconfirmed_per_key
+                .unwrap_or(default.max_unconfirmed_per_key),
+            max_unconfirmed_per_peer: self
+                .max_unconfirmed_per_peer
+                .unwrap_or(default.max_unconfirmed_per_peer),
+        }
+    }
+
+    /// The default network limits with any overrides given on the command
+    /// line. They are validated when the node starts.
+    pub fn network_limits(&self) -> NetworkLimits {
+        let default = NetworkLimits::default();
+        NetworkLimits {
+            max_message_size: self.max_message_size.unwrap_or(default.max_message_size),
+            max_connections: self.max_connections.unwrap_or(default.max_connections),
+            message_timeout_secs: self.message_timeout.unwrap_or(default.message_timeout_secs),
+            cleanup_interval_secs: self
+                .cleanup_interval
+                .unwrap_or(default.cleanup_interval_secs),
+            max_wallets: self.max_wallets.unwrap_or(default.max_wallets),
+            max_miners: self.max_miners.unwrap_or(default.max_miners),
+            max_idle_secs: self.max_idle_timeout.unwrap_or(default.max_idle_secs),
+        }
+    }
+
+    /// The webhooks given on the command line, all sent every event.
+    pub fn webhooks(&self) -> WebhookConfig {
+        let default = WebhookConfig::default();
+        WebhookConfig {
+            hooks: self
+                .webhook
+                .iter()
+                .map(|url| Webhook {
+                    secret: self.webhook_secret.clone(),
+                    ..Webhook::new(url)
+                })
+                .collect(),
+            large_tx_threshold: self
+                .large_tx_threshold
+                .unwrap_or(default.large_tx_threshold),
+            max_retries: self.webhook_retries.unwrap_or(default.max_retries),
+        }
+    }
+
+    /// The default rejection limits with any overrides given on the
+    /// command line.
+    pub fn rejection_limits(&self) -> RejectionLimits {
+        let default = RejectionLimits::default();
+        RejectionLimits {
+            max_blocks_per_minute: self
+                .max_invalid_blocks_per_minute
+                .unwrap_or(default.max_blocks_per_minute),
+            max_transactions_per_minute: self
+                .max_invalid_txs_per_minute
+                .unwrap_or(default.max_transactions_per_minute),
+        }
+    }
+
+    /// Where to log: the `[log]` section of `--config`, if any, with any
+    /// overrides given on the command line.
+    pub fn log_config(&self) -> Result<LogConfig> {
+        let config = match &self.config {
+            Some(config) => ChainsConfig::load(config)?.log.log_config(),
+            None => LogConfig::default(),
+        };
+        Ok(LogConfig {
+            file: self.log_file.clone().map(Into::into).or(config.file),
+            filters: self.log_filter.clone().or(config.filters),
+            max_size: self.log_max_size.map(megabytes).unwrap_or(config.max_size),
+            max_files: self.log_files.unwrap_or(config.max_files),
+        })
+    }
+
+    /// How to keep up with the network without listening, with
+    /// `--nolisten`.
+    pub fn outbound_only(&self) -> Option<OutboundConfig> {
+        self.nolisten.then(|| OutboundConfig {
+            peers: self.outbound_peers,
+            poll_interval: Duration::from_secs(self.poll_interval),
+        })
+    }
+
+    /// Where to serve light wallets, if `--http-port` is given.
+    pub fn http(&self) -> Option<HttpConfig> {
+        self.http_port.map(|port| HttpConfig {
+            port,
+            cors_origin: self.http_cors_origin.clone(),
+        })
+    }
+
+    /// What `init` sets up, if that is the subcommand given instead of
+    /// running the node.
+    pub fn init_options(&self) -> Option<InitOptions> {
+        match self.command.as_ref()? {
+            Command::Init {
+                data_dir,
+                port,
+                http_port,
+                force,
+            } => Some(InitOptions {
+                data_dir: data_dir.clone(),
+                port: *port,
+                http_port: *http_port,
+                force: *force,
+            }),
+        }
+    }
+
+    /// The nodes to run by chain name: every chain of `--config`, or a
+    /// single one set up by the other options.
+    pub fn node_configs(&self) -> Result<Vec<(String, NodeConfig)>> {
+        if let Some(config) = &self.config {
+            return ChainsConfig::load(config)?.node_configs();
+        }
+        let config = NodeConfig {
+            port: self.port,
+            blockchain_file: self.blockchain_file.clone().unwrap_or_default(),
+            nodes: self.nodes.clone(),
+            capture: self.capture.clone(),
+            relay_policy: self.relay_policy(),
+            limits: self.network_limits(),
+            seed_only: self.seed_only,
+            archive: self.archive,
+            watchtower: self.watchtower,
+            mempool_sync: !self.no_mempool_sync,
+            outbound_only: self.outbound_only(),
+            serve_templates: !self.no_templates,
+            zero_conf_risk: self.zero_conf_risk,
+            webhooks: self.webhooks(),
+            http: self.http(),
+            authority: load_authority(
+                &self.authority_key,
+                self.authority_threshold,
+                self.authority_height,
+            )?,
+            cosign_key: load_cosign_key(self.cosign_key.as_deref())?,
+            consensus: load_consensus(&self.validators)?,
+            validator_key: load_validator_key(self.validator_key.as_deref())?,
+            block_interval: Duration::from_secs(self.block_interval.unwrap_or(IDEAL_BLOCK_TIME)),
+            max_reorg_depth: self.max_reorg_depth,
+            max_block_size: self.max_block_size,
+            undo_retention: self.undo_retention,
+            memory_budget: self.memory_budget.map(megabytes),
+            rejection_limits: self.rejection_limits(),
+        };
+        Ok(vec![("default".to_string(), config)])
+    }
 }
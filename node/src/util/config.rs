@@ -0,0 +1,366 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::PathBuf,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use btclib::{
+    IDEAL_BLOCK_TIME, MAX_BLOCK_SIZE, MAX_REORG_DEPTH,
+    network::{NetworkLimits, RelayPolicy},
+    utils::LogConfig,
+};
+use serde::Deserialize;
+
+use crate::{
+    NodeConfig,
+    util::{
+        DEFAULT_OUTBOUND_PEERS, DEFAULT_OUTBOUND_POLL_SECS, HttpConfig, OutboundConfig,
+        RejectionLimits, Webhook, WebhookConfig, load_authority, load_consensus, load_cosign_key,
+        load_validator_key, megabytes,
+    },
+};
+
+/// A configuration file describing several chains to run in one
+/// process, one `[chains.<name>]` section each:
+///
+/// ```toml
+/// [chains.main]
+/// data_dir = "data/main"
+/// port = 9000
+///
+/// [chains.test]
+/// data_dir = "data/test"
+/// port = 19000
+/// nodes = ["localhost:19001"]
+/// min_fee_rate = 0
+///
+/// [[chains.test.webhooks]]
+/// url = "http://localhost:8080/events"
+/// secret = "change me"
+/// events = ["block_connected"]
+///
+/// [chains.wallets]
+/// data_dir = "data/wallets"
+/// port = 49000
+/// http_port = 48080
+/// http_cors_origin = "https://wallet.example.com"
+///
+/// [chains.private]
+/// data_dir = "data/private"
+/// port = 29000
+/// authority_keys = ["keys/alice.pub.pem", "keys/bob.pub.pem"]
+/// authority_threshold = 1
+/// cosign_key = "keys/alice.priv.cbor"
+///
+/// [chains.enterprise]
+/// data_dir = "data/enterprise"
+/// port = 39000
+/// validators = ["keys/alice.pub.pem", "keys/bob.pub.pem"]
+/// validator_key = "keys/alice.priv.cbor"
+/// block_interval_secs = 10
+/// max_block_size = 4000000
+///
+/// [log]
+/// file = "node.log"
+/// filter = "info,node::handler=debug"
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChainsConfig {
+    pub chains: BTreeMap<String, ChainConfig>,
+    /// Where the whole process logs, for every chain
+    #[serde(default)]
+    pub log: LogSection,
+}
+
+/// The `[log]` section of a `ChainsConfig`, see `LogConfig`. Options
+/// left out take the same defaults as on the command line.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LogSection {
+    pub file: Option<PathBuf>,
+    pub filter: Option<String>,
+    pub max_size_mb: Option<u64>,
+    pub max_files: Option<usize>,
+}
+
+impl LogSection {
+    pub fn log_config(&self) -> LogConfig {
+        let default = LogConfig::default();
+        LogConfig {
+            file: self.file.clone(),
+            filters: self.filter.clone(),
+            max_size: self.max_size_mb.map(megabytes).unwrap_or(default.max_size),
+            max_files: self.max_files.unwrap_or(default.max_files),
+        }
+    }
+}
+
+/// One chain of a `ChainsConfig`. Options left out take the same
+/// defaults as on the command line.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChainConfig {
+    /// Holds the blockchain file, its write-ahead log and auth tokens
+    pub data_dir: PathBuf,
+    pub port: u16,
+    #[serde(default)]
+    pub nodes: Vec<String>,
+    pub capture: Option<String>,
+    pub min_fee_rate: Option<u64>,
+    pub max_tx_size: Option<usize>,
+    pub max_tx_inputs: Option<usize>,
+    pub max_tx_outputs: Option<usize>,
+    pub max_data_outputs: Option<usize>,
+    pub dust_threshold: Option<u64>,
+    pub free_tx_per_hour: Option<u32>,
+    pub mempool_expiry_secs: Option<u64>,
+    pub max_unconfirmed_per_key: Option<usize>,
+    pub max_unconfirmed_per_peer: Option<usize>,
+    pub max_message_size: Option<usize>,
+    pub max_connections: Option<usize>,
+    pub message_timeout_secs: Option<u64>,
+    pub cleanup_interval_secs: Option<u64>,
+    pub max_wallets: Option<usize>,
+    pub max_miners: Option<usize>,
+    pub max_idle_secs: Option<u64>,
+    /// Serve addresses only, see `NodeConfig::seed_only`
+    #[serde(default)]
+    pub seed_only: bool,
+    /// Keep undo data, see `NodeConfig::archive`
+    #[serde(default)]
+    pub archive: bool,
+    /// Blocks whose undo data is kept, see `NodeConfig::undo_retention`
+    pub undo_retention: Option<u64>,
+    /// Watch registered outputs, see `NodeConfig::watchtower`
+    #[serde(default)]
+    pub watchtower: bool,
+    /// Fetch missing mempool transactions from the peers on startup, see
+    /// `NodeConfig::mempool_sync`
+    pub mempool_sync: Option<bool>,
+    /// Listen for peers, see `NodeConfig::outbound_only`
+    pub listen: Option<bool>,
+    /// Peers and seconds between polls of a node that doesn't listen,
+    /// see `OutboundConfig`
+    pub outbound_peers: Option<usize>,
+    pub poll_interval_secs: Option<u64>,
+    /// Hand out and accept block templates, see
+    /// `NodeConfig::serve_templates`
+    pub serve_templates: Option<bool>,
+    /// Score the double spend risk of mempool transactions, see
+    /// `NodeConfig::zero_conf_risk`
+    pub zero_conf_risk: Option<bool>,
+    #[serde(default)]
+    pub webhooks: Vec<Webhook>,
+    pub large_tx_threshold: Option<u64>,
+    pub webhook_retries: Option<u32>,
+    /// Port to serve light wallets on over HTTP, see `NodeConfig::http`
+    pub http_port: Option<u16>,
+    /// CORS origin of the HTTP port, any by default
+    pub http_cors_origin: Option<String>,
+    /// Public key files of the authorities, see `NodeConfig::authority`
+    #[serde(default)]
+    pub authority_keys: Vec<String>,
+    pub authority_threshold: Option<usize>,
+    pub authority_height: Option<u64>,
+    /// Private key file to co-sign blocks with
+    pub cosign_key: Option<String>,
+    /// Public key files of the validators, see `NodeConfig::consensus`
+    #[serde(default)]
+    pub validators: Vec<String>,
+    /// Private key file to sign blocks with as a validator
+    pub validator_key: Option<String>,
+    pub block_interval_secs: Option<u64>,
+    /// Most blocks a reorganization may disconnect, see
+    /// `NodeConfig::max_reorg_depth`
+    pub max_reorg_depth: Option<u64>,
+    /// Most bytes of transactions in a block, see
+    /// `NodeConfig::max_block_size`
+    pub max_block_size: Option<usize>,
+    /// Megabytes the UTXO set and mempool should stay within, see
+    /// `NodeConfig::memory_budget`
+    pub memory_budget_mb: Option<u64>,
+    /// Invalid blocks and transactions a minute before alerting, see
+    /// `RejectionLimits`
+    pub max_invalid_blocks_per_minute: Option<u32>,
+    pub max_invalid_txs_per_minute: Option<u32>,
+}
+
+impl ChainsConfig {
+    pub fn load(path: &str) -> Result<Self> {
+        let contents =
+            fs::read_to_string(path).with_context(|| format!("failed to read config {path}"))?;
+        contents
+            .parse()
+            .with_context(|| format!("invalid config {path}"))
+    }
+
+    /// A `NodeConfig` per chain, by name. Chains must not share a port
+    /// or a data directory; the data directories are created if needed.
+    pub fn node_configs(&self) -> Result<Vec<(String, NodeConfig)>> {
+        anyhow::ensure!(!self.chains.is_empty(), "no chains configured");
+        let mut ports = HashSet::new();
+        let mut data_dirs = HashSet::new();
+        for (name, chain) in &self.chains {
+            for port in std::iter::once(chain.port).chain(chain.http_port) {
+                anyhow::ensure!(
+                    ports.insert(port),
+                    "chain {name} uses port {port} of another chain"
+                );
+            }
+            anyhow::ensure!(
+                data_dirs.insert(&chain.data_dir),
+                "chain {name} uses the data directory of another chain"
+            );
+        }
+        self.chains
+            .iter()
+            .map(|(name, chain)| {
+                fs::create_dir_all(&chain.data_dir)
+                    .with_context(|| format!("failed to create {}", chain.data_dir.display()))?;
+                let config = chain
+                    .node_config()
+                    .with_context(|| format!("invalid chain {name}"))?;
+                Ok((name.clone(), config))
+            })
+            .collect()
+    }
+}
+
+impl std::str::FromStr for ChainsConfig {
+    type Err = toml::de::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        toml::from_str(s)
+    }
+}
+
+impl ChainConfig {
+    pub fn blockchain_file(&self) -> String {
+        self.data_dir
+            .join("blockchain.cbor")
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    pub fn relay_policy(&self) -> RelayPolicy {
+        let default = RelayPolicy::default();
+        RelayPolicy {
+            min_fee_rate: self.min_fee_rate.unwrap_or(default.min_fee_rate),
+            max_tx_size: self.max_tx_size.unwrap_or(default.max_tx_size),
+            max_tx_inputs: self.max_tx_inputs.unwrap_or(default.max_tx_inputs),
+            max_tx_outputs: self.max_tx_outputs.unwrap_or(default.max_tx_outputs),
+            max_data_outputs: self.max_data_outputs.unwrap_or(default.max_data_outputs),
+            dust_threshold: self.dust_threshold.unwrap_or(default.dust_threshold),
+            free_tx_per_hour: self.free_tx_per_hour.unwrap_or(default.free_tx_per_hour),
+            mempool_expiry_secs: self
+                .mempool_expiry_secs
+                .unwrap_or(default.mempool_expiry_secs),
+            max_unconfirmed_per_key: self
+                .max_unconfirmed_per_key
+                .unwrap_or(default.max_unconfirmed_per_key),
+            max_unconfirmed_per_peer: self
+                .max_unconfirmed_per_peer
+                .unwrap_or(default.max_unconfirmed_per_peer),
+        }
+    }
+
+    pub fn network_limits(&self) -> NetworkLimits {
+        let default = NetworkLimits::default();
+        NetworkLimits {
+            max_message_size: self.max_message_size.unwrap_or(default.max_message_size),
+            max_connections: self.max_connections.unwrap_or(default.max_connections),
+            message_timeout_secs: self
+                .message_timeout_secs
+                .unwrap_or(default.message_timeout_secs),
+            cleanup_interval_secs: self
+                .cleanup_interval_secs
+                .unwrap_or(default.cleanup_interval_secs),
+            max_wallets: self.max_wallets.unwrap_or(default.max_wallets),
+            max_miners: self.max_miners.unwrap_or(default.max_miners),
+            max_idle_secs: self.max_idle_secs.unwrap_or(default.max_idle_secs),
+        }
+    }
+
+    pub fn webhooks(&self) -> WebhookConfig {
+        let default = WebhookConfig::default();
+        WebhookConfig {
+            hooks: self.webhooks.clone(),
+            large_tx_threshold: self
+                .large_tx_threshold
+                .unwrap_or(default.large_tx_threshold),
+            max_retries: self.webhook_retries.unwrap_or(default.max_retries),
+        }
+    }
+
+    pub fn rejection_limits(&self) -> RejectionLimits {
+        let default = RejectionLimits::default();
+        RejectionLimits {
+            max_blocks_per_minute: self
+                .max_invalid_blocks_per_minute
+                .unwrap_or(default.max_blocks_per_minute),
+            max_transactions_per_minute: self
+                .max_invalid_txs_per_minute
+                .unwrap_or(default.max_transactions_per_minute),
+        }
+    }
+
+    pub fn outbound_only(&self) -> Option<OutboundConfig> {
+        (self.listen == Some(false)).then(|| OutboundConfig {
+            peers: self.outbound_peers.unwrap_or(DEFAULT_OUTBOUND_PEERS),
+            poll_interval: Duration::from_secs(
+                self.poll_interval_secs
+                    .unwrap_or(DEFAULT_OUTBOUND_POLL_SECS),
+            ),
+        })
+    }
+
+    pub fn http(&self) -> Option<HttpConfig> {
+        self.http_port.map(|port| {
+            let mut http = HttpConfig::new(port);
+            if let Some(origin) = &self.http_cors_origin {
+                http.cors_origin = origin.clone();
+            }
+            http
+        })
+    }
+
+    /// Loads the authority, validator and signing keys, if any.
+    pub fn node_config(&self) -> Result<NodeConfig> {
+        Ok(NodeConfig {
+            port: self.port,
+            blockchain_file: self.blockchain_file(),
+            nodes: self.nodes.clone(),
+            capture: self.capture.clone(),
+            relay_policy: self.relay_policy(),
+            limits: self.network_limits(),
+            seed_only: self.seed_only,
+            archive: self.archive,
+            watchtower: self.watchtower,
+            mempool_sync: self.mempool_sync.unwrap_or(true),
+            outbound_only: self.outbound_only(),
+            serve_templates: self.serve_templates.unwrap_or(true),
+            zero_conf_risk: self.zero_conf_risk.unwrap_or(false),
+            webhooks: self.webhooks(),
+            http: self.http(),
+            authority: load_authority(
+                &self.authority_keys,
+                self.authority_threshold,
+                self.authority_height,
+            )?,
+            cosign_key: load_cosign_key(self.cosign_key.as_deref())?,
+            consensus: load_consensus(&self.validators)?,
+            validator_key: load_validator_key(self.validator_key.as_deref())?,
+            block_interval: Duration::from_secs(
+                self.block_interval_secs.unwrap_or(IDEAL_BLOCK_TIME),
+            ),
+            max_reorg_depth: self.max_reorg_depth.unwrap_or(MAX_REORG_DEPTH),
+            max_block_size: self.max_block_size.unwrap_or(MAX_BLOCK_SIZE),
+            undo_retention: self.undo_retention,
+            memory_budget: self.memory_budget_mb.map(megabytes),
+            rejection_limits: self.rejection_limits(),
+        })
+    }
+}
@@ -1,33 +1,32 @@
 use anyhow::Result;
-use btclib::network::Message;
+use btclib::client::NodeClient;
 use log::{info, warn};
-use tokio::net::TcpStream;
 
-use crate::NODES;
+use crate::{NodeState, util::learn_addresses};
 
-pub async fn populate_connections(nodes: &[String]) -> Result<()> {
+pub async fn populate_connections(state: &NodeState, nodes: &[String]) -> Result<()> {
     info!("trying to connect to other nodes...");
     for node in nodes {
         info!("connecting to {}", node);
-        let mut stream = TcpStream::connect(&node).await?;
-        let message = Message::DiscoverNodes;
-        message.send_async(&mut stream).await?;
-        info!("sent DiscoverNodes to {}", node);
-        let message = Message::receive_async(&mut stream).await?;
-        match message {
-            Message::NodeList(child_nodes) => {
-                info!("received NodeList from {}", node);
-                for child_node in child_nodes {
-                    info!("adding node {}", child_node);
-                    let new_stream = TcpStream::connect(&child_node).await?;
-                    NODES.insert(child_node, new_stream);
-                }
+        let mut client = NodeClient::connect(node).await?;
+        info!("sending DiscoverNodes to {}", node);
+        let child_nodes = client.discover_nodes().await?;
+        info!("received NodeList from {}", node);
+        learn_addresses(state, child_nodes.clone());
+        for child_node in child_nodes {
+            if state.nodes.contains_key(&child_node) {
+                continue;
             }
-            _ => {
-                warn!("unexpected message from {}", node);
+            // addresses learned through gossip may be stale
+            info!("adding node {}", child_node);
+            match NodeClient::connect(&child_node).await {
+                Ok(new_client) => {
+                    state.nodes.insert(child_node, new_client);
+                }
+                Err(e) => warn!("failed to connect to {child_node}: {e}"),
             }
         }
-        NODES.insert(node.clone(), stream);
+        state.nodes.insert(node.clone(), client);
     }
     Ok(())
 }
@@ -0,0 +1,203 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use anyhow::Result;
+use btclib::{
+    client::NodeClient,
+    custom_sha_types::Hash,
+    error::ClientError,
+    network::{BlockTimings, ChainTip, propagation_spread},
+    types::BlockHeight,
+};
+
+/// What one node reported to the consistency checker.
+#[derive(Debug, Clone)]
+pub struct NodeSnapshot {
+    pub address: String,
+    pub tip: Option<ChainTip>,
+    /// Hashes of the most recent blocks by height, the tip included
+    pub recent_blocks: HashMap<BlockHeight, Hash>,
+    pub mempool: HashSet<Hash>,
+    /// When the node received its tip, if it remembers
+    pub tip_timings: Option<BlockTimings>,
+}
+
+impl NodeSnapshot {
+    /// Fetches the tip of the node at `address`, the hashes of up to
+    /// `depth` blocks ending at it, and its mempool.
+    pub async fn take(address: &str, depth: usize) -> Result<Self> {
+        let mut client = NodeClient::connect(address).await?;
+        let tip = client.get_tip().await?;
+        let mut recent_blocks = HashMap::new();
+        if let Some(tip) = tip {
+            recent_blocks.insert(tip.height, tip.hash);
+            let (mut height, mut prev) = (tip.height, tip.prev);
+            while recent_blocks.len() < depth
+                && let Some(parent) = height.previous()
+            {
+                let header = client.get_header(prev).await?;
+                recent_blocks.insert(parent, header.hash());
+                (height, prev) = (parent, *header.prev_block_hash());
+            }
+        }
+        let mempool = client.get_mempool().await?.into_iter().collect();
+        let tip_timings = match tip {
+            Some(tip) => match client.get_block_timings(tip.hash).await {
+                Ok(timings) => Some(timings),
+                Err(ClientError::Rejected { .. }) => None,
+                Err(e) => return Err(e.into()),
+            },
+            None => None,
+        };
+        Ok(NodeSnapshot {
+            address: address.to_string(),
+            tip,
+            recent_blocks,
+            mempool,
+            tip_timings,
+        })
+    }
+
+    pub fn height(&self) -> Option<BlockHeight> {
+        self.tip.map(|tip| tip.height)
+    }
+}
+
+/// A way in which nodes that should agree don't.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Divergence {
+    /// The node couldn't be asked
+    Unreachable { node: String, error: String },
+    /// The node is on the best chain, but hasn't caught up with its tip
+    Behind {
+        node: String,
+        height: Option<BlockHeight>,
+        best: BlockHeight,
+    },
+    /// The node's chain splits off the best chain after `common`, the
+    /// last block both have. `None` if they share none of the compared
+    /// blocks.
+    Forked {
+        node: String,
+        common: Option<BlockHeight>,
+    },
+    /// The node agrees on the tip, but lacks transactions other nodes
+    /// at the same tip have in their mempool
+    MissingTransactions { node: String, missing: Vec<Hash> },
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Divergence::Unreachable { node, error } => write!(f, "{node}: unreachable: {error}"),
+            Divergence::Behind { node, height, best } => match height {
+                Some(height) => write!(f, "{node}: behind at height {height}, best is {best}"),
+                None => write!(f, "{node}: has no blocks, best is {best}"),
+            },
+            Divergence::Forked { node, common } => match common {
+                Some(common) => write!(f, "{node}: forked after height {common}"),
+                None => write!(f, "{node}: forked before the compared blocks"),
+            },
+            Divergence::MissingTransactions { node, missing } => write!(
+                f,
+                "{node}: missing {} mempool transactions: {missing:?}",
+                missing.len()
+            ),
+        }
+    }
+}
+
+/// Compares every snapshot with the one with the highest tip, taken to
+/// be the best chain. Mempools are only compared between nodes that
+/// agree on the best tip, since a block takes its transactions out of
+/// the mempool.
+pub fn find_divergences(snapshots: &[NodeSnapshot]) -> Vec<Divergence> {
+    let Some(best) = snapshots.iter().max_by_key(|snapshot| snapshot.height()) else {
+        return vec![];
+    };
+    let mut divergences = vec![];
+    let mut in_sync = vec![];
+    for snapshot in snapshots {
+        let node = snapshot.address.clone();
+        match (snapshot.tip, best.tip) {
+            (_, None) => in_sync.push(snapshot),
+            (tip, _) if tip == best.tip => in_sync.push(snapshot),
+            (None, Some(best_tip)) => divergences.push(Divergence::Behind {
+                node,
+                height: None,
+                best: best_tip.height,
+            }),
+            (Some(tip), Some(best_tip)) => {
+                if best.recent_blocks.get(&tip.height) == Some(&tip.hash) {
+                    divergences.push(Divergence::Behind {
+                        node,
+                        height: Some(tip.height),
+                        best: best_tip.height,
+                    });
+                } else {
+                    let common = snapshot
+                        .recent_blocks
+                        .iter()
+                        .filter(|(height, hash)| best.recent_blocks.get(height) == Some(hash))
+                        .map(|(height, _)| *height)
+                        .max();
+                    divergences.push(Divergence::Forked { node, common });
+                }
+            }
+        }
+    }
+    let all_transactions: HashSet<Hash> = in_sync
+        .iter()
+        .flat_map(|snapshot| snapshot.mempool.iter().copied())
+        .collect();
+    for snapshot in in_sync {
+        let missing: Vec<Hash> = all_transactions
+            .difference(&snapshot.mempool)
+            .copied()
+            .collect();
+        if !missing.is_empty() {
+            divergences.push(Divergence::MissingTransactions {
+                node: snapshot.address.clone(),
+                missing,
+            });
+        }
+    }
+    divergences
+}
+
+/// Seconds between the first and the last node to receive the highest
+/// tip, among the nodes that have it and remember when they got it.
+pub fn tip_propagation(snapshots: &[NodeSnapshot]) -> Option<f64> {
+    let best = snapshots.iter().max_by_key(|snapshot| snapshot.height())?;
+    let timings: Vec<BlockTimings> = snapshots
+        .iter()
+        .filter(|snapshot| snapshot.tip == best.tip)
+        .filter_map(|snapshot| snapshot.tip_timings.clone())
+        .collect();
+    propagation_spread(&timings)
+}
+
+/// Snapshots every node in `addresses` and reports how they diverge.
+/// Nodes that can't be asked are reported as unreachable and left out
+/// of the comparison.
+pub async fn check_consistency(
+    addresses: &[String],
+    depth: usize,
+) -> (Vec<NodeSnapshot>, Vec<Divergence>) {
+    let mut snapshots = vec![];
+    let mut unreachable = vec![];
+    for address in addresses {
+        match NodeSnapshot::take(address, depth).await {
+            Ok(snapshot) => snapshots.push(snapshot),
+            Err(e) => unreachable.push(Divergence::Unreachable {
+                node: address.clone(),
+                error: e.to_string(),
+            }),
+        }
+    }
+    let mut divergences = unreachable;
+    divergences.extend(find_divergences(&snapshots));
+    (snapshots, divergences)
+}
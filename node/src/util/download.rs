@@ -1,24 +1,223 @@
-use anyhow::Result;
-use btclib::network::Message;
-
-use crate::BLOCKCHAIN;
-
-pub async fn download_blockchain(node: &str, count: u32) -> Result<()> {
-    let mut stream = crate::NODES.get_mut(node).unwrap();
-    for i in 0..count as usize {
-        let message = Message::FetchBlock(i);
-        message.send_async(&mut *stream).await?;
-        let message = Message::receive_async(&mut *stream).await?;
-        match message {
-            Message::NewBlock(block) => {
-                let mut blockchain = BLOCKCHAIN.write().await;
-                blockchain.add_block(block)?;
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Instant,
+};
+
+use anyhow::{Context, Result};
+use btclib::{
+    crypto::PublicKey,
+    custom_sha_types::Hash,
+    error::BtcError,
+    network::SyncProgress,
+    types::{Block, BlockHeight, Transaction},
+};
+use chrono::Utc;
+use log::{info, warn};
+use tokio::{sync::oneshot, time};
+
+use crate::{
+    NodeState,
+    util::{log_block, message_timeout, notify_block_connected, record_response},
+};
+
+// blocks between progress reports
+const PROGRESS_INTERVAL: u64 = 100;
+// blocks downloaded ahead of the one being connected, whose signatures
+// are checked on the rayon pool meanwhile
+const VERIFICATION_WINDOW: usize = 32;
+
+// a downloaded block and the outcome of checking its signatures ahead
+type PendingBlock = (Block, oneshot::Receiver<Option<bool>>);
+
+// checks the signatures of `block` on the rayon pool, with the keys of
+// the outputs it spends that were found
+fn verify_ahead(block: Block, keys: HashMap<Hash, PublicKey>) -> oneshot::Receiver<Option<bool>> {
+    let (sender, receiver) = oneshot::channel();
+    rayon::spawn(move || {
+        let verified = block.verify_signatures(|outpoint| keys.get(outpoint).cloned());
+        let _ = sender.send(verified);
+    });
+    receiver
+}
+
+// connects the oldest pending block, checking its signatures again only
+// if they couldn't be checked ahead
+async fn connect_next(state: &NodeState, pending: &mut VecDeque<PendingBlock>) -> Result<()> {
+    let Some((block, verified)) = pending.pop_front() else {
+        return Ok(());
+    };
+    let verified = verified.await.ok().flatten() == Some(true);
+    let mut blockchain = state.blockchain.write().await;
+    if verified {
+        blockchain.add_block_with_verified_signatures(block.clone())?;
+    } else {
+        blockchain.add_block(block.clone())?;
+    }
+    log_block(state, &block)?;
+    // the next block may spend its outputs
+    blockchain.rebuild_utxos();
+    notify_block_connected(state, &blockchain);
+    if let Some(progress) = state.sync_progress.lock().unwrap().as_mut() {
+        progress.record_connected(Utc::now());
+        if progress.connected % PROGRESS_INTERVAL == 0 {
+            info!("{progress}");
+        }
+    }
+    Ok(())
+}
+
+/// Downloads the blocks from our height up to `height` from `node`.
+/// Blocks are connected in order, while the signatures of up to
+/// `VERIFICATION_WINDOW` blocks downloaded ahead are checked on the
+/// rayon pool, so sync isn't held up by checking them one by one. A
+/// block not answered within the message timeout means the peer stalled:
+/// it is disconnected, as its answer may still arrive and put the
+/// connection out of step. On any failure the blocks downloaded so far
+/// are connected first, for the next peer to resume after. Progress is
+/// kept in `NodeState::sync_progress` and logged every
+/// `PROGRESS_INTERVAL` blocks.
+pub async fn download_blockchain(state: &NodeState, node: &str, height: BlockHeight) -> Result<()> {
+    let start = state.blockchain.read().await.block_height();
+    info!(
+        "downloading blocks {} to {} from {node}",
+        start.as_index(),
+        height.as_index()
+    );
+    *state.sync_progress.lock().unwrap() = Some(SyncProgress::new(node, start, height, Utc::now()));
+    let result = download_blocks(state, node, start, height).await;
+    let connected = match state.sync_progress.lock().unwrap().as_mut() {
+        Some(progress) => {
+            progress.finish(Utc::now());
+            info!("{progress}");
+            progress.connected
+        }
+        None => 0,
+    };
+    let mut quality = state.peer_quality.lock().unwrap();
+    quality.record_blocks(node, connected, true);
+    // anything but a block failing validation is a failed request,
+    // already counted
+    if let Err(e) = &result
+        && e.downcast_ref::<BtcError>().is_some()
+    {
+        quality.record_blocks(node, 1, false);
+    }
+    drop(quality);
+    result
+}
+
+async fn download_blocks(
+    state: &NodeState,
+    node: &str,
+    start: BlockHeight,
+    height: BlockHeight,
+) -> Result<()> {
+    let timeout = message_timeout(state);
+    let mut client = state.nodes.get_mut(node).context("no node")?;
+    // keys of the outputs of the downloaded blocks, until spent
+    let mut created: HashMap<Hash, PublicKey> = HashMap::new();
+    let mut pending = VecDeque::new();
+    for index in start.as_index()..height.as_index() {
+        let sent = Instant::now();
+        let request = client.get_block(BlockHeight::from_index(index));
+        let answer = time::timeout(timeout, request).await;
+        record_response(state, node, sent, matches!(answer, Ok(Ok(_))));
+        let block = match answer {
+            Ok(Ok(block)) => block,
+            Ok(Err(e)) => {
+                drop(client);
+                connect_all(state, &mut pending).await?;
+                return Err(e)
+                    .with_context(|| format!("failed to fetch block {index} from {node}"));
             }
-            _ => {
-                log::info!("unexpected message from {}", node);
+            Err(_) => {
+                drop(client);
+                state.nodes.remove(node);
+                state.known_inventory.lock().unwrap().forget_peer(node);
+                state.peer_quality.lock().unwrap().record_disconnect(node);
+                connect_all(state, &mut pending).await?;
+                anyhow::bail!("{node} stalled at block {index}, disconnected");
             }
+        };
+        let keys = {
+            let blockchain = state.blockchain.read().await;
+            block
+                .transactions()
+                .iter()
+                .flat_map(Transaction::inputs)
+                .filter_map(|input| {
+                    let outpoint = input.prev_transaction_output_hash();
+                    // an output is spent once, its key isn't needed again
+                    let key = created.remove(outpoint).or_else(|| {
+                        blockchain
+                            .utxo(outpoint)
+                            .map(|output| output.pubkey().clone())
+                    })?;
+                    Some((*outpoint, key))
+                })
+                .collect()
+        };
+        created.extend(
+            block
+                .transactions()
+                .iter()
+                .flat_map(Transaction::outputs)
+                .map(|output| (output.hash(), output.pubkey().clone())),
+        );
+        if let Some(progress) = state.sync_progress.lock().unwrap().as_mut() {
+            progress.record_downloaded(block.transactions_size(), Utc::now());
+        }
+        pending.push_back((block.clone(), verify_ahead(block, keys)));
+        if pending.len() >= VERIFICATION_WINDOW {
+            connect_next(state, &mut pending).await?;
+        }
+    }
+    drop(client);
+    connect_all(state, &mut pending).await
+}
+
+async fn connect_all(state: &NodeState, pending: &mut VecDeque<PendingBlock>) -> Result<()> {
+    while !pending.is_empty() {
+        connect_next(state, pending).await?;
+    }
+    Ok(())
+}
+
+/// Downloads the chain of the first of `peers` (as ranked by
+/// `rank_chain_nodes`) that serves it all. When one stalls or fails, the
+/// next resumes from the last block connected, and a flaky peer ranked
+/// last only has to serve the blocks the others lacked. Fails if not a
+/// single block could be downloaded; a partial chain is kept with a
+/// warning.
+pub async fn sync_blockchain(state: &NodeState, peers: &[(String, BlockHeight)]) -> Result<()> {
+    let longest = peers
+        .iter()
+        .map(|(_, height)| *height)
+        .max()
+        .unwrap_or(BlockHeight::GENESIS);
+    for (node, height) in peers {
+        let ours = state.blockchain.read().await.block_height();
+        if ours >= longest {
+            break;
         }
+        if *height <= ours {
+            continue;
+        }
+        match download_blockchain(state, node, *height).await {
+            Ok(()) => info!("Blockchain downloaded from node {}", node),
+            Err(e) => warn!("download from {node} failed, trying the next peer: {e:#}"),
+        }
+    }
+    let ours = state.blockchain.read().await.block_height();
+    if ours >= longest {
+        return Ok(());
     }
+    anyhow::ensure!(ours > BlockHeight::GENESIS, "no peer served its blockchain");
+    warn!(
+        "Blockchain downloaded up to {} of the {} blocks peers reported",
+        ours.as_index(),
+        longest.as_index()
+    );
     Ok(())
 }
 
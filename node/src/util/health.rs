@@ -0,0 +1,180 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use btclib::{
+    network::NetworkHealth,
+    types::{Blockchain, Transaction},
+};
+use chrono::{DateTime, Duration, Utc};
+use log::{info, warn};
+use tokio::time;
+
+use crate::{
+    NodeState,
+    util::{ChainObserver, RejectedKind, propagation_summary},
+};
+
+// number of recent block intervals the statistics cover
+const INTERVAL_WINDOW: usize = 50;
+// how far back mempool inflow is measured
+const INFLOW_WINDOW_MINUTES: i64 = 10;
+// warn when no block arrived for this many ideal block times
+const STALL_FACTOR: u64 = 4;
+// warn when blocks arrive this many times faster than the ideal block time
+const ACCELERATION_FACTOR: f64 = 4.0;
+
+/// Counters for the traffic side of `NetworkHealth`.
+#[derive(Debug, Default)]
+pub struct HealthTracker {
+    transaction_arrivals: VecDeque<DateTime<Utc>>,
+    blocks_received: u64,
+    orphans_received: u64,
+}
+
+impl HealthTracker {
+    /// A transaction was accepted into the mempool.
+    pub fn record_transaction(&mut self, now: DateTime<Utc>) {
+        self.transaction_arrivals.push_back(now);
+        self.forget_before(now - Duration::minutes(INFLOW_WINDOW_MINUTES));
+    }
+
+    /// A block was received from a peer; `orphan` if it didn't build on our best block.
+    pub fn record_block(&mut self, orphan: bool) {
+        self.blocks_received += 1;
+        if orphan {
+            self.orphans_received += 1;
+        }
+    }
+
+    pub fn mempool_inflow_per_minute(&mut self, now: DateTime<Utc>) -> f64 {
+        self.forget_before(now - Duration::minutes(INFLOW_WINDOW_MINUTES));
+        self.transaction_arrivals.len() as f64 / INFLOW_WINDOW_MINUTES as f64
+    }
+
+    pub fn orphan_rate(&self) -> f64 {
+        if self.blocks_received == 0 {
+            return 0.0;
+        }
+        self.orphans_received as f64 / self.blocks_received as f64
+    }
+
+    fn forget_before(&mut self, cutoff: DateTime<Utc>) {
+        while self
+            .transaction_arrivals
+            .front()
+            .is_some_and(|arrival| *arrival < cutoff)
+        {
+            self.transaction_arrivals.pop_front();
+        }
+    }
+}
+
+/// Feeds mempool inflow into a node's `HealthTracker`.
+#[derive(Debug)]
+pub struct HealthRecorder {
+    tracker: Arc<Mutex<HealthTracker>>,
+}
+
+impl HealthRecorder {
+    pub fn new(tracker: Arc<Mutex<HealthTracker>>) -> Self {
+        HealthRecorder { tracker }
+    }
+}
+
+impl ChainObserver for HealthRecorder {
+    fn on_tx_accepted(&self, _transaction: &Transaction) {
+        self.tracker.lock().unwrap().record_transaction(Utc::now());
+    }
+}
+
+pub fn record_block(state: &NodeState, orphan: bool) {
+    state.health.lock().unwrap().record_block(orphan);
+}
+
+/// Current block production and traffic statistics.
+pub fn network_health(state: &NodeState, blockchain: &Blockchain) -> NetworkHealth {
+    let now = Utc::now();
+    let (average_block_interval, block_interval_variance) = blockchain
+        .block_interval_stats(INTERVAL_WINDOW)
+        .unwrap_or_default();
+    let seconds_since_last_block = blockchain
+        .blocks()
+        .last()
+        .map(|block| (now - block.header().timestamp()).num_seconds())
+        .unwrap_or_default();
+    let propagation = propagation_summary(state);
+    let (rejected_blocks, rejected_transactions) = {
+        let mut rejections = state.rejections.lock().unwrap();
+        (
+            rejections.count(RejectedKind::Block, now),
+            rejections.count(RejectedKind::Transaction, now),
+        )
+    };
+    let mut tracker = state.health.lock().unwrap();
+    NetworkHealth {
+        intervals: (blockchain.blocks().len().saturating_sub(1)).min(INTERVAL_WINDOW) as u64,
+        average_block_interval,
+        block_interval_variance,
+        seconds_since_last_block,
+        mempool_inflow_per_minute: tracker.mempool_inflow_per_minute(now),
+        orphan_rate: tracker.orphan_rate(),
+        propagation_samples: propagation.samples,
+        average_propagation_delay: propagation.average_delay,
+        max_propagation_delay: propagation.max_delay,
+        average_validation_time: propagation.average_validation_time,
+        rejected_blocks_last_minute: rejected_blocks as u64,
+        rejected_transactions_last_minute: rejected_transactions as u64,
+    }
+}
+
+/// Logs the network health every minute and warns when block
+/// production stalls or runs abnormally fast.
+pub async fn monitor_health(state: Arc<NodeState>) {
+    let mut interval = time::interval(time::Duration::from_secs(60));
+    loop {
+        interval.tick().await;
+        let health = {
+            let blockchain = state.blockchain.read().await;
+            if blockchain.blocks().is_empty() {
+                continue;
+            }
+            network_health(&state, &blockchain)
+        };
+        info!(
+            "network health: {:.1}s average block interval (variance {:.1}), \
+             {}s since last block, {:.2} tx/min, {:.1}% orphans",
+            health.average_block_interval,
+            health.block_interval_variance,
+            health.seconds_since_last_block,
+            health.mempool_inflow_per_minute,
+            health.orphan_rate * 100.0
+        );
+        if health.propagation_samples > 0 {
+            info!(
+                "block propagation: {:.2}s average delay, {:.2}s max over {} blocks, \
+                 {:.3}s average validation",
+                health.average_propagation_delay,
+                health.max_propagation_delay,
+                health.propagation_samples,
+                health.average_validation_time
+            );
+        }
+        let ideal = btclib::IDEAL_BLOCK_TIME;
+        if health.seconds_since_last_block > (ideal * STALL_FACTOR) as i64 {
+            warn!(
+                "block production stalled: no block for {}s (ideal block time is {}s)",
+                health.seconds_since_last_block, ideal
+            );
+        }
+        if health.intervals > 0
+            && health.average_block_interval < ideal as f64 / ACCELERATION_FACTOR
+        {
+            warn!(
+                "block production accelerated: blocks every {:.1}s on average (ideal is {}s)",
+                health.average_block_interval, ideal
+            );
+        }
+    }
+}
@@ -0,0 +1,525 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, atomic::Ordering},
+};
+
+use anyhow::{Context, Result};
+use btclib::{
+    crypto::{PublicKey, Signature},
+    custom_sha_types::Hash,
+    network::{ChainTip, FeeHistogram, ZeroConfRisk},
+    types::{BlockHeader, BlockHeight, Transaction},
+};
+use log::{debug, info};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::Semaphore,
+    task::JoinSet,
+    time,
+};
+
+use crate::{
+    NodeState,
+    util::{
+        ChainstateSnapshot, SearchResult, assess_zero_conf_risk, message_timeout, network_limits,
+        parse_block_hash, search, submit_transaction,
+    },
+};
+
+// request line and headers taken from one request
+const MAX_HEAD_SIZE: usize = 8 * 1024;
+/// Most headers one `GET /headers` returns.
+pub const MAX_HTTP_HEADERS: usize = 2000;
+
+/// Where and for whom a node answers light wallets over HTTP.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpConfig {
+    /// Port to listen on, 0 picks a free one
+    pub port: u16,
+    /// Sent as `Access-Control-Allow-Origin`; `*` lets any web page
+    /// call the node
+    pub cors_origin: String,
+}
+
+impl HttpConfig {
+    pub fn new(port: u16) -> Self {
+        HttpConfig {
+            port,
+            cors_origin: "*".to_string(),
+        }
+    }
+}
+
+/// The status and JSON body of an answer, before the HTTP framing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+impl HttpResponse {
+    fn ok(body: String) -> Self {
+        HttpResponse { status: 200, body }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        HttpResponse {
+            status,
+            body: format!(r#"{{"error":{}}}"#, json_string(message)),
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            204 => "No Content",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            413 => "Payload Too Large",
+            422 => "Unprocessable Entity",
+            _ => "Error",
+        }
+    }
+
+    /// The full response, with the CORS headers that let pages from
+    /// `cors_origin` read it.
+    pub fn to_http(&self, cors_origin: &str) -> String {
+        format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+             Access-Control-Allow-Origin: {cors_origin}\r\n\
+             Access-Control-Allow-Methods: GET, POST, OPTIONS\r\n\
+             Access-Control-Allow-Headers: Content-Type\r\n\
+             Access-Control-Max-Age: 86400\r\nConnection: close\r\n\r\n{}",
+            self.status,
+            self.reason(),
+            self.body.len(),
+            self.body
+        )
+    }
+}
+
+/// `s` as a JSON string literal.
+pub fn json_string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+fn hex_hash(hash: &Hash) -> String {
+    hex::encode(hash.as_bytes())
+}
+
+// like the webhook bodies, the responses hold nothing but hex strings,
+// numbers, booleans and timestamps, so they are written out directly
+fn tip_json(tip: Option<ChainTip>) -> String {
+    match tip {
+        Some(tip) => format!(
+            r#"{{"hash":"{}","height":{},"prev":"{}"}}"#,
+            hex_hash(&tip.hash),
+            tip.height,
+            hex_hash(&tip.prev)
+        ),
+        None => "null".to_string(),
+    }
+}
+
+fn header_json(header: &BlockHeader, height: BlockHeight) -> String {
+    format!(
+        r#"{{"height":{height},"hash":"{}","prev":"{}","merkle_root":"{}","timestamp":"{}","target":"{:x}","nonce":{}}}"#,
+        hex_hash(&header.hash()),
+        hex_hash(header.prev_block_hash()),
+        hex_hash(&header.merkle_root().as_hash()),
+        header.timestamp().to_rfc3339(),
+        header.target(),
+        header.nonce()
+    )
+}
+
+fn fee_histogram_json(histogram: &FeeHistogram) -> String {
+    let buckets: Vec<String> = histogram
+        .buckets
+        .iter()
+        .map(|bucket| {
+            format!(
+                r#"{{"min_fee_rate":{},"transactions":{},"size":{},"cumulative_size":{}}}"#,
+                bucket.min_fee_rate, bucket.transactions, bucket.size, bucket.cumulative_size
+            )
+        })
+        .collect();
+    format!(r#"{{"buckets":[{}]}}"#, buckets.join(","))
+}
+
+fn zero_conf_risk_json(risk: &ZeroConfRisk) -> String {
+    format!(
+        r#"{{"hash":"{}","score":{:.4},"level":"{:?}","fee_rate":{:.4},"bytes_ahead":{},"blocks_ahead":{:.4},"unconfirmed_inputs":{},"min_input_confirmations":{},"peers_asked":{},"peers_with_transaction":{}}}"#,
+        hex_hash(&risk.hash),
+        risk.score(),
+        risk.level(),
+        risk.fee_rate,
+        risk.bytes_ahead,
+        risk.blocks_ahead,
+        risk.unconfirmed_inputs,
+        risk.min_input_confirmations.value(),
+        risk.peers_asked,
+        risk.peers_with_transaction
+    )
+}
+
+// what a search found, with the endpoint giving the details of it
+fn search_json(result: &SearchResult) -> String {
+    match result {
+        SearchResult::Block { height, hash } => format!(
+            r#"{{"type":"block","height":{height},"hash":"{}","path":"/headers?from={height}&count=1"}}"#,
+            hex_hash(hash)
+        ),
+        SearchResult::Transaction { hash, height } => match height {
+            Some(height) => format!(
+                r#"{{"type":"transaction","hash":"{0}","height":{height},"path":"/proof/{0}"}}"#,
+                hex_hash(hash)
+            ),
+            None => format!(
+                r#"{{"type":"transaction","hash":"{}","height":null,"path":null}}"#,
+                hex_hash(hash)
+            ),
+        },
+        SearchResult::Address {
+            address,
+            key,
+            balance,
+            outputs,
+        } => {
+            let (key, path) = match key {
+                Some(key) => (
+                    format!(r#""{}""#, key.to_hex()),
+                    format!(r#""/utxos/{}""#, key.to_hex()),
+                ),
+                None => ("null".to_string(), "null".to_string()),
+            };
+            format!(
+                r#"{{"type":"address","address":"{address}","key":{key},"balance":{balance},"outputs":{outputs},"path":{path}}}"#
+            )
+        }
+    }
+}
+
+/// Splits the head of a request into its method, target and the length
+/// of the body that follows.
+pub fn parse_http_head(head: &str) -> Result<(String, String, usize)> {
+    let mut lines = head.lines();
+    let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+    let (Some(method), Some(target)) = (request_line.next(), request_line.next()) else {
+        anyhow::bail!("malformed request line");
+    };
+    let mut content_length = 0;
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':')
+            && name.trim().eq_ignore_ascii_case("content-length")
+        {
+            content_length = value.trim().parse().context("invalid Content-Length")?;
+        }
+    }
+    Ok((method.to_string(), target.to_string(), content_length))
+}
+
+fn query_param<T: std::str::FromStr>(query: &str, name: &str) -> Option<Result<T, T::Err>> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| percent_decode(value).parse())
+}
+
+// `+` and `%XX` escapes as forms and `encodeURIComponent` send them;
+// malformed escapes are kept as they are, as browsers do
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| hex::decode(hex).ok());
+        match (bytes[i], escaped) {
+            (_, Some(byte)) => {
+                decoded.extend(byte);
+                i += 3;
+            }
+            (b'+', None) => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (byte, None) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Answers one light wallet request from `peer`:
+///
+/// - `GET /tip`: the best block
+/// - `GET /utxos/<public key hex>`: unspent outputs paying the key, and
+///   whether a mempool transaction already spends them
+/// - `GET /headers?from=<height>&count=<n>`: up to `MAX_HTTP_HEADERS`
+///   block headers from `from` on
+/// - `GET /proof/<transaction hash hex>`: the block holding the
+///   transaction and the Merkle proof of it
+/// - `GET /search?q=<query>`: what a block height, a block or
+///   transaction hash, a public key or an address names, see `search`,
+///   and the endpoint with the details of it
+/// - `GET /fees`: the mempool bucketed by fee rate, highest first, with
+///   the bytes waiting at or above each rate
+/// - `GET /risk/<transaction hash hex>`: how likely the mempool
+///   transaction is to be double spent, on nodes scoring it
+///   (`--zero-conf-risk`)
+/// - `POST /tx`: submits a transaction, the body being its CBOR encoding
+///   in hex; relay policy and validation apply as for `SubmitTransaction`
+/// - `POST /verifymessage?key=<public key hex>&signature=<hex>`: whether
+///   the key signed the body with `PrivateKey::sign_message`
+/// - `OPTIONS`: the CORS preflight
+pub async fn handle_http_request(
+    state: &NodeState,
+    peer: IpAddr,
+    method: &str,
+    target: &str,
+    body: &[u8],
+) -> HttpResponse {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    match (method, segments.as_slice()) {
+        ("OPTIONS", _) => HttpResponse {
+            status: 204,
+            body: String::new(),
+        },
+        ("GET", ["tip"]) => {
+            let snapshot = ChainstateSnapshot::take(state).await;
+            HttpResponse::ok(tip_json(snapshot.tip()))
+        }
+        ("GET", ["utxos", key]) => {
+            let Ok(key) = PublicKey::from_hex(key) else {
+                return HttpResponse::error(400, "invalid public key");
+            };
+            let snapshot = ChainstateSnapshot::take(state).await;
+            let utxos: Vec<String> = snapshot
+                .utxos_for(&key)
+                .into_iter()
+                .map(|(hash, entry)| {
+                    format!(
+                        r#"{{"hash":"{}","value":{},"in_mempool":{}}}"#,
+                        hex_hash(&hash),
+                        entry.output().value(),
+                        entry.is_spent_in_mempool()
+                    )
+                })
+                .collect();
+            HttpResponse::ok(format!("[{}]", utxos.join(",")))
+        }
+        ("GET", ["headers"]) => {
+            let from = match query_param::<u64>(query, "from").unwrap_or(Ok(0)) {
+                Ok(from) => from,
+                Err(_) => return HttpResponse::error(400, "invalid from"),
+            };
+            let count = match query_param::<usize>(query, "count").unwrap_or(Ok(MAX_HTTP_HEADERS)) {
+                Ok(count) => count.min(MAX_HTTP_HEADERS),
+                Err(_) => return HttpResponse::error(400, "invalid count"),
+            };
+            let snapshot = ChainstateSnapshot::take(state).await;
+            // bounded by the chain, so a huge `from` can't overflow
+            let end = snapshot.block_height().as_index() as u64;
+            let headers: Vec<String> = (from.min(end)..end)
+                .map(BlockHeight::new)
+                .map_while(|height| {
+                    let block = snapshot.block_at(height)?;
+                    Some(header_json(block.header(), height))
+                })
+                .take(count)
+                .collect();
+            HttpResponse::ok(format!("[{}]", headers.join(",")))
+        }
+        ("GET", ["proof", hash]) => {
+            let Ok(hash) = parse_block_hash(hash) else {
+                return HttpResponse::error(400, "invalid transaction hash");
+            };
+            let snapshot = ChainstateSnapshot::take(state).await;
+            let Some((height, block, proof)) = snapshot.transaction_proof(&hash) else {
+                return HttpResponse::error(404, "transaction not in the chain");
+            };
+            let siblings: Vec<String> = proof
+                .siblings
+                .iter()
+                .map(|sibling| format!(r#""{}""#, hex_hash(sibling)))
+                .collect();
+            HttpResponse::ok(format!(
+                r#"{{"transaction":"{}","height":{height},"block":"{}","merkle_root":"{}","index":{},"siblings":[{}]}}"#,
+                hex_hash(&hash),
+                hex_hash(&block.hash()),
+                hex_hash(&block.header().merkle_root().as_hash()),
+                proof.index,
+                siblings.join(",")
+            ))
+        }
+        ("GET", ["search"]) => {
+            let Some(Ok(query)) = query_param::<String>(query, "q") else {
+                return HttpResponse::error(400, "missing q");
+            };
+            let snapshot = ChainstateSnapshot::take(state).await;
+            match search(&snapshot, &query) {
+                Some(result) => HttpResponse::ok(search_json(&result)),
+                None => HttpResponse::error(404, "nothing found"),
+            }
+        }
+        ("GET", ["fees"]) => {
+            let blockchain = state.blockchain.read().await;
+            HttpResponse::ok(fee_histogram_json(&blockchain.fee_histogram()))
+        }
+        ("GET", ["risk", hash]) => {
+            if !state.zero_conf_risk.load(Ordering::Relaxed) {
+                return HttpResponse::error(404, "zero-conf risk scoring is off");
+            }
+            let Ok(hash) = parse_block_hash(hash) else {
+                return HttpResponse::error(400, "invalid transaction hash");
+            };
+            match assess_zero_conf_risk(state, hash).await {
+                Some(risk) => HttpResponse::ok(zero_conf_risk_json(&risk)),
+                None => HttpResponse::error(404, "transaction not in the mempool"),
+            }
+        }
+        ("POST", ["tx"]) => {
+            let Ok(transaction) = Transaction::from_hex(&String::from_utf8_lossy(body)) else {
+                return HttpResponse::error(400, "expected a hex CBOR transaction");
+            };
+            let hash = transaction.hash();
+            match submit_transaction(state, peer, transaction).await {
+                Ok(()) => HttpResponse::ok(format!(r#"{{"hash":"{}"}}"#, hex_hash(&hash))),
+                Err((code, reason)) => HttpResponse {
+                    status: 422,
+                    body: format!(r#"{{"error":{},"code":"{code:?}"}}"#, json_string(&reason)),
+                },
+            }
+        }
+        ("POST", ["verifymessage"]) => {
+            let param = |name| query_param::<String>(query, name).and_then(Result::ok);
+            let Some(Ok(key)) = param("key").map(|key| PublicKey::from_hex(&key)) else {
+                return HttpResponse::error(400, "invalid public key");
+            };
+            let Some(Ok(signature)) = param("signature").map(|sig| Signature::from_hex(&sig))
+            else {
+                return HttpResponse::error(400, "invalid signature");
+            };
+            let valid = key.verify_message(body, &signature);
+            HttpResponse::ok(format!(
+                r#"{{"valid":{valid},"address":"{}"}}"#,
+                key.address()
+            ))
+        }
+        ("GET", _) | ("POST", _) => HttpResponse::error(404, "no such endpoint"),
+        _ => HttpResponse::error(405, "method not allowed"),
+    }
+}
+
+// reads one request, answers it and closes the connection
+async fn serve_http_connection(
+    state: &NodeState,
+    mut socket: TcpStream,
+    peer: IpAddr,
+    cors_origin: &str,
+) -> Result<()> {
+    let max_body = network_limits(state).max_message_size;
+    let exchange = async {
+        let mut received = vec![];
+        let mut buffer = [0; 4096];
+        let head_end = loop {
+            if let Some(end) = received.windows(4).position(|w| w == b"\r\n\r\n") {
+                break end;
+            }
+            anyhow::ensure!(received.len() <= MAX_HEAD_SIZE, "request head too large");
+            let read = socket.read(&mut buffer).await?;
+            anyhow::ensure!(read > 0, "connection closed mid-request");
+            received.extend_from_slice(&buffer[..read]);
+        };
+        let (method, target, content_length) =
+            parse_http_head(&String::from_utf8_lossy(&received[..head_end]))?;
+        let response = if content_length > max_body {
+            HttpResponse::error(413, "request body too large")
+        } else {
+            let mut body = received.split_off(head_end + 4);
+            while body.len() < content_length {
+                let read = socket.read(&mut buffer).await?;
+                anyhow::ensure!(read > 0, "connection closed mid-request");
+                body.extend_from_slice(&buffer[..read]);
+            }
+            body.truncate(content_length);
+            debug!("HTTP {method} {target}");
+            handle_http_request(state, peer, &method, &target, &body).await
+        };
+        socket
+            .write_all(response.to_http(cors_origin).as_bytes())
+            .await?;
+        anyhow::Ok(())
+    };
+    time::timeout(message_timeout(state), exchange)
+        .await
+        .context("request timed out")?
+}
+
+/// Answers light wallets connecting to `listener` until the task is
+/// aborted, one request per connection. Takes its permits from
+/// `connection_limit`, the semaphore of the node's protocol listener.
+pub async fn serve_http(
+    listener: TcpListener,
+    state: Arc<NodeState>,
+    cors_origin: String,
+    connection_limit: Arc<Semaphore>,
+) {
+    let mut connections = JoinSet::new();
+    loop {
+        while connections.try_join_next().is_some() {}
+        match listener.accept().await {
+            Ok((socket, addr)) => {
+                let Ok(permit) = connection_limit.clone().try_acquire_owned() else {
+                    log::warn!("HTTP connection limit reached, rejecting {addr}");
+                    continue;
+                };
+                let state = state.clone();
+                let cors_origin = cors_origin.clone();
+                connections.spawn(async move {
+                    let _permit = permit;
+                    if let Err(e) =
+                        serve_http_connection(&state, socket, addr.ip(), &cors_origin).await
+                    {
+                        debug!("HTTP request from {addr} failed: {e}");
+                    }
+                });
+            }
+            Err(e) => log::error!("Failed to accept HTTP connection: {}", e),
+        }
+    }
+}
+
+/// Binds the light wallet listener of `config`.
+pub async fn bind_http(config: &HttpConfig) -> Result<(TcpListener, SocketAddr)> {
+    let addr = format!("0.0.0.0:{}", config.port);
+    let listener = TcpListener::bind(&addr)
+        .await
+        .with_context(|| format!("failed to listen for HTTP on {addr}"))?;
+    let local_addr = listener.local_addr()?;
+    info!(
+        "Serving light wallets over HTTP on {} (CORS origin {})",
+        local_addr, config.cors_origin
+    );
+    Ok((listener, local_addr))
+}
@@ -0,0 +1,63 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::util::{AuthTokens, ChainsConfig};
+
+/// The configuration file `init_data_dir` writes, shipped in release
+/// packages as `config/node.toml`.
+pub const CONFIG_TEMPLATE: &str = include_str!("../../../dist/node.toml");
+
+/// What `node init` sets up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InitOptions {
+    pub data_dir: PathBuf,
+    pub port: u16,
+    pub http_port: Option<u16>,
+    /// Replace an existing configuration file; the auth tokens and the
+    /// chain are kept either way
+    pub force: bool,
+}
+
+/// Fills in `CONFIG_TEMPLATE` for a single chain named `main` kept in
+/// `<data_dir>/main`, logging to `<data_dir>/node.log`.
+pub fn render_config(options: &InitOptions) -> String {
+    // TOML strings, so paths with backslashes or quotes stay intact
+    let path = |path: PathBuf| toml::Value::String(path.to_string_lossy().into_owned()).to_string();
+    let http_port = match options.http_port {
+        Some(port) => format!("http_port = {port}"),
+        None => "# http_port = 8080".to_string(),
+    };
+    CONFIG_TEMPLATE
+        .replace("{chain_dir}", &path(options.data_dir.join("main")))
+        .replace("{port}", &options.port.to_string())
+        .replace("{http_port}", &http_port)
+        .replace("{log_file}", &path(options.data_dir.join("node.log")))
+}
+
+/// Creates a data directory to run a node from with `--config`: the
+/// configuration file, `node.toml`, and the chain's directory with its
+/// auth tokens, so they can be handed to wallets and admin tools before
+/// the node first starts. Returns the path of the configuration file.
+pub fn init_data_dir(options: &InitOptions) -> Result<PathBuf> {
+    let data_dir = std::path::absolute(&options.data_dir)
+        .with_context(|| format!("invalid data directory {}", options.data_dir.display()))?;
+    let config_file = data_dir.join("node.toml");
+    anyhow::ensure!(
+        options.force || !config_file.exists(),
+        "{} already exists, pass --force to replace it",
+        config_file.display()
+    );
+    let contents = render_config(&InitOptions {
+        data_dir: data_dir.clone(),
+        ..options.clone()
+    });
+    let config: ChainsConfig = contents.parse().context("invalid config template")?;
+    // creates the chain's directory, checking the chain as the node will
+    for (_, node_config) in config.node_configs()? {
+        AuthTokens::load_or_generate(&node_config.blockchain_file)?;
+    }
+    fs::write(&config_file, contents)
+        .with_context(|| format!("failed to write {}", config_file.display()))?;
+    Ok(config_file)
+}
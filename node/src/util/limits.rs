@@ -0,0 +1,40 @@
+use std::sync::atomic::Ordering;
+
+use anyhow::Result;
+use btclib::network::{NetworkInfo, NetworkLimits, PeerKind, RelayPolicy};
+use tokio::time::Duration;
+
+use crate::{NodeState, util::session_count};
+
+/// Checks `limits` and makes them the node's. The relay policy has to
+/// fit too: a transaction the policy allows must fit in a message.
+pub fn init_limits(state: &NodeState, limits: NetworkLimits, policy: &RelayPolicy) -> Result<()> {
+    limits.validate()?;
+    anyhow::ensure!(
+        policy.max_tx_size <= limits.max_message_size,
+        "relay policy allows {} byte transactions, but messages are limited to {} bytes",
+        policy.max_tx_size,
+        limits.max_message_size
+    );
+    *state.limits.write().unwrap() = limits;
+    Ok(())
+}
+
+pub fn network_limits(state: &NodeState) -> NetworkLimits {
+    *state.limits.read().unwrap()
+}
+
+/// How long the rest of a message may take once its length arrived.
+pub fn message_timeout(state: &NodeState) -> Duration {
+    Duration::from_secs(network_limits(state).message_timeout_secs)
+}
+
+pub fn network_info(state: &NodeState) -> NetworkInfo {
+    NetworkInfo {
+        limits: network_limits(state),
+        connections: state.connections.load(Ordering::Relaxed),
+        peers: state.nodes.len(),
+        wallets: session_count(state, PeerKind::Wallet),
+        miners: session_count(state, PeerKind::Miner),
+    }
+}
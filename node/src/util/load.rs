@@ -2,13 +2,34 @@ use anyhow::Result;
 use btclib::{types::Blockchain, utils::Saveable};
 use log::info;
 
-use crate::BLOCKCHAIN;
+use std::path::Path;
 
-pub async fn load_blockchain(blockchain_file: &str) -> Result<()> {
-    info!("blockchain file exists, loading...");
-    let new_blockchain = Blockchain::load_from_file(blockchain_file)?;
+use crate::{NodeState, util::replay_wal};
+
+pub async fn load_blockchain(state: &NodeState, blockchain_file: &str) -> Result<()> {
+    // the node may have crashed before its first save, leaving only a WAL
+    let mut new_blockchain = if Path::new(blockchain_file).exists() {
+        info!("blockchain file exists, loading...");
+        Blockchain::load_from_file(blockchain_file)?
+    } else {
+        Blockchain::default()
+    };
     info!("blockchain loaded");
-    let mut blockchain = BLOCKCHAIN.write().await;
+    // the authority, consensus engine and block size limit aren't saved
+    // with the chain, but WAL blocks need them
+    let (authority, consensus, max_block_size) = {
+        let blockchain = state.blockchain.read().await;
+        (
+            blockchain.authority().cloned(),
+            blockchain.consensus().clone(),
+            blockchain.max_block_size(),
+        )
+    };
+    new_blockchain.set_authority(authority);
+    new_blockchain.set_consensus(consensus);
+    new_blockchain.set_max_block_size(max_block_size);
+    replay_wal(&mut new_blockchain, blockchain_file)?;
+    let mut blockchain = state.blockchain.write().await;
     *blockchain = new_blockchain;
     info!("rebuilding utxos...");
     blockchain.rebuild_utxos();
@@ -0,0 +1,91 @@
+use std::sync::{Arc, atomic::Ordering};
+
+use btclib::{network::MemoryInfo, types::Blockchain};
+use log::{debug, info, warn};
+use tokio::time;
+
+use crate::NodeState;
+
+// share of the memory budget from which the node warns that it is close
+const MEMORY_WARNING_THRESHOLD: f64 = 0.8;
+
+/// How the chainstate's memory compares with the budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MemoryLevel {
+    /// Well within the budget, or there is none
+    Normal,
+    /// Above `MEMORY_WARNING_THRESHOLD` of the budget
+    Approaching,
+    /// Over the budget
+    Exceeded,
+}
+
+impl MemoryLevel {
+    pub fn of(info: &MemoryInfo) -> Self {
+        match info.budget_used() {
+            Some(used) if used > 1.0 => MemoryLevel::Exceeded,
+            Some(used) if used >= MEMORY_WARNING_THRESHOLD => MemoryLevel::Approaching,
+            _ => MemoryLevel::Normal,
+        }
+    }
+}
+
+/// `mb` megabytes in bytes, as budgets are configured.
+pub fn megabytes(mb: u64) -> u64 {
+    mb.saturating_mul(1024 * 1024)
+}
+
+/// Makes `budget` bytes the memory the node's chainstate should stay
+/// within. It is only reported and warned about, nothing is evicted.
+pub fn init_memory_budget(state: &NodeState, budget: Option<u64>) {
+    state
+        .memory_budget
+        .store(budget.unwrap_or(0), Ordering::Relaxed);
+}
+
+/// `blockchain`'s memory estimate with the node's budget.
+pub fn memory_info(state: &NodeState, blockchain: &Blockchain) -> MemoryInfo {
+    let budget = state.memory_budget.load(Ordering::Relaxed);
+    MemoryInfo {
+        budget: (budget > 0).then_some(budget),
+        ..blockchain.memory_info()
+    }
+}
+
+/// Checks the chainstate's memory every minute and warns when it gets
+/// close to or over the budget, and again when it gets worse.
+pub async fn monitor_memory(state: Arc<NodeState>) {
+    let mut interval = time::interval(time::Duration::from_secs(60));
+    let mut level = MemoryLevel::Normal;
+    loop {
+        interval.tick().await;
+        let info = memory_info(&state, &*state.blockchain.read().await);
+        let Some(budget) = info.budget else {
+            continue;
+        };
+        debug!(
+            "chainstate memory: {} bytes for {} UTXOs, {} bytes for {} mempool transactions",
+            info.utxo_bytes, info.utxos, info.mempool_bytes, info.mempool_transactions
+        );
+        let current = MemoryLevel::of(&info);
+        if current > level {
+            warn!(
+                "chainstate memory {}: about {} of the {budget} byte budget in use \
+                 ({} UTXOs, {} mempool transactions)",
+                match current {
+                    MemoryLevel::Exceeded => "over budget",
+                    _ => "approaching budget",
+                },
+                info.total(),
+                info.utxos,
+                info.mempool_transactions
+            );
+        } else if current < level && current == MemoryLevel::Normal {
+            info!(
+                "chainstate memory back within budget: about {} of {budget} bytes",
+                info.total()
+            );
+        }
+        level = current;
+    }
+}
@@ -0,0 +1,167 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{Context, Result};
+use btclib::{
+    consensus::{ConsensusEngine, ProofOfAuthority, ProofOfWork},
+    crypto::{PrivateKey, PublicKey},
+    types::{Block, BlockBuilder, Blockchain},
+    utils::Saveable,
+};
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use tokio::time;
+
+use crate::{
+    NodeState,
+    util::{log_block, notify_block_connected, record_block_timings, relay_block},
+};
+
+/// Proof of authority among the validators whose public key files are
+/// `validator_files`, taking turns in that order; proof of work without
+/// any.
+pub fn load_consensus(validator_files: &[String]) -> Result<Arc<dyn ConsensusEngine>> {
+    if validator_files.is_empty() {
+        return Ok(Arc::new(ProofOfWork));
+    }
+    let validators = validator_files
+        .iter()
+        .map(|file| {
+            PublicKey::load_from_file(file)
+                .with_context(|| format!("failed to load validator key {file}"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Arc::new(ProofOfAuthority::new(validators)?))
+}
+
+pub fn load_validator_key(key_file: Option<&str>) -> Result<Option<PrivateKey>> {
+    key_file
+        .map(|file| {
+            PrivateKey::load_from_file(file)
+                .with_context(|| format!("failed to load validator key {file}"))
+        })
+        .transpose()
+}
+
+/// Checks that a block signing key belongs to one of the validators.
+pub fn check_validator_key(
+    consensus: &dyn ConsensusEngine,
+    validator_key: Option<&PrivateKey>,
+) -> Result<()> {
+    if let Some(key) = validator_key {
+        anyhow::ensure!(
+            consensus.is_validator(&key.public_key()),
+            "the validator key is not one of the {} validators",
+            consensus.name()
+        );
+    }
+    Ok(())
+}
+
+/// Has the node's blockchain check blocks are sealed the way
+/// `consensus` wants. Called before the blockchain is loaded.
+pub async fn init_consensus(state: &NodeState, consensus: Arc<dyn ConsensusEngine>) {
+    info!("Consensus: {}", consensus.name());
+    state.blockchain.write().await.set_consensus(consensus);
+}
+
+/// Has the node's blockchain refuse blocks whose transactions take more
+/// than `max_block_size` bytes, and build templates within it. Called
+/// before the blockchain is loaded.
+pub async fn init_max_block_size(state: &NodeState, max_block_size: usize) {
+    info!("Maximum block size: {} bytes", max_block_size);
+    state
+        .blockchain
+        .write()
+        .await
+        .set_max_block_size(max_block_size);
+}
+
+/// A block on top of `blockchain` paying `pubkey`, filled from the
+/// mempool's priority lane first, then by the fee rate of each
+/// transaction's ancestor package.
+/// Transactions whose inputs are gone since they were accepted are
+/// skipped. The same tip, mempool, payee, tag and `timestamp` always
+/// give the same template. If the best block is timestamped after
+/// `timestamp`, the template is timestamped a second past it instead
+/// and a clock skew warning is logged.
+pub fn block_template(
+    blockchain: &Blockchain,
+    pubkey: PublicKey,
+    coinbase_tag: Option<Vec<u8>>,
+    timestamp: DateTime<Utc>,
+) -> Block {
+    if let Some(skew) = blockchain.clock_skew(timestamp) {
+        warn!(
+            "best block is timestamped {}s ahead of the local clock, templates \
+             are timestamped after it; check the clocks of this node and its peers",
+            skew.num_seconds()
+        );
+    }
+    let candidates = blockchain
+        .mempool()
+        .iter()
+        .filter_map(|(_, tx)| Some((tx.clone(), blockchain.transaction_fee(tx)?)));
+    BlockBuilder::on(blockchain)
+        .priority(blockchain.mempool_priority().clone())
+        .timestamp(timestamp)
+        .coinbase_tag(coinbase_tag)
+        .packages(candidates)
+        .build(pubkey)
+}
+
+/// Adds a block produced for this node, by a miner or by the node
+/// itself as a validator, to its chain. An authority node signs it
+/// first. Returns the block as added, to be relayed.
+pub async fn connect_new_block(
+    state: &NodeState,
+    block: Block,
+    received_at: DateTime<Utc>,
+) -> Result<Block> {
+    let mut blockchain = state.blockchain.write().await;
+    // an authority node adds its own signature to the ones the miner
+    // collected
+    let block = match state.cosigner.lock().unwrap().as_mut() {
+        Some(cosigner) => cosigner.sign(&blockchain, block)?,
+        None => block,
+    };
+    blockchain.add_block(block.clone())?;
+    if let Err(e) = log_block(state, &block) {
+        log::error!("Failed to write block to the WAL: {e}");
+    }
+    blockchain.rebuild_utxos();
+    notify_block_connected(state, &blockchain);
+    record_block_timings(state, &blockchain, received_at, None);
+    Ok(block)
+}
+
+/// Signs and adds the next block if it is `key`'s turn to produce it,
+/// on a chain whose consensus engine has blocks signed instead of
+/// mined. Returns the block, to be relayed.
+pub async fn produce_block(state: &NodeState, key: &PrivateKey) -> Result<Option<Block>> {
+    let mut block = {
+        let blockchain = state.blockchain.read().await;
+        let height = blockchain.block_height();
+        if blockchain.consensus().proposer(height) != Some(&key.public_key()) {
+            return Ok(None);
+        }
+        block_template(&blockchain, key.public_key(), None, Utc::now())
+    };
+    block.cosign(key);
+    connect_new_block(state, block, Utc::now()).await.map(Some)
+}
+
+/// Produces a block every `interval` the validator `key` is in turn.
+pub async fn produce_blocks(state: Arc<NodeState>, key: PrivateKey, interval: Duration) {
+    let mut interval = time::interval(interval);
+    loop {
+        interval.tick().await;
+        match produce_block(&state, &key).await {
+            Ok(Some(block)) => {
+                info!("produced block {:x?}", block.hash());
+                relay_block(&state, &block).await;
+            }
+            Ok(None) => {}
+            Err(e) => warn!("failed to produce a block: {e}"),
+        }
+    }
+}
@@ -1,18 +1,92 @@
+#[cfg(feature = "utxo-accumulator")]
+mod accumulator;
+mod addresses;
+mod archive;
+#[cfg(feature = "assets")]
+mod assets;
+mod auth;
+mod authority;
+mod badpeer;
+mod capture;
 mod chain_node;
 mod cleanup;
 mod cli;
+mod config;
 mod connections;
+mod consistency;
 mod download;
+mod health;
+mod http;
+mod init;
+mod limits;
 mod load;
+mod memory;
+mod mining;
+mod observer;
+mod outbound;
+mod peer_quality;
+mod policy;
+mod reconcile;
+mod rejections;
+mod relay;
+mod reorg;
+mod reserves;
 mod save;
+mod search;
+mod session;
+mod snapshot;
+mod soak;
+mod timings;
+mod tips;
+mod wal;
+mod watchtower;
+mod webhooks;
+mod zeroconf;
 
+#[cfg(feature = "utxo-accumulator")]
+pub use accumulator::*;
+pub use addresses::*;
+pub use archive::*;
+#[cfg(feature = "assets")]
+pub use assets::*;
+pub use auth::*;
+pub use authority::*;
+pub use badpeer::*;
+pub use capture::*;
 pub use chain_node::*;
 pub use cleanup::*;
 pub use cli::*;
+pub use config::*;
 pub use connections::*;
+pub use consistency::*;
 pub use download::*;
+pub use health::*;
+pub use http::*;
+pub use init::*;
+pub use limits::*;
 pub use load::*;
+pub use memory::*;
+pub use mining::*;
+pub use observer::*;
+pub use outbound::*;
+pub use peer_quality::*;
+pub use policy::*;
+pub use reconcile::*;
+pub use rejections::*;
+pub use relay::*;
+pub use reorg::*;
+pub use reserves::*;
 pub use save::*;
+pub use search::*;
+pub use session::*;
+pub use snapshot::*;
+pub use soak::*;
+pub use timings::*;
+pub use tips::*;
+pub use wal::*;
+pub use watchtower::*;
+pub use webhooks::*;
+pub use zeroconf::*;
 
 #[cfg(test)]
 mod tests;
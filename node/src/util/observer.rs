@@ -0,0 +1,100 @@
+use std::sync::Arc;
+
+use btclib::types::{Block, BlockHeight, Blockchain, Transaction};
+
+use crate::{
+    NodeState,
+    util::{ChainConflict, DoubleSpend, RejectionStorm},
+};
+
+/// Hooks into chain and mempool changes. Implement it to follow the node
+/// from an indexer, a metrics exporter or an application embedding the
+/// node, and add it with `register_observer`.
+///
+/// Hooks run synchronously while the blockchain is locked, so they should
+/// hand anything slow off to a task of their own.
+pub trait ChainObserver: Send + Sync {
+    /// A block became the new best block.
+    fn on_block_connected(&self, _block: &Block, _height: BlockHeight) {}
+
+    /// The best block was removed from the chain by a reorganization.
+    /// Called for each disconnected block, newest first, before the
+    /// blocks of the new branch are connected.
+    fn on_block_disconnected(&self, _block: &Block, _height: BlockHeight) {}
+
+    /// A peer serves a longer chain the node won't reorganize to, since
+    /// it forks off deeper than the maximum reorg depth.
+    fn on_chain_conflict(&self, _conflict: &ChainConflict) {}
+
+    /// More invalid blocks or transactions arrived within a minute than
+    /// the node's rejection limits allow.
+    fn on_rejection_storm(&self, _storm: &RejectionStorm) {}
+
+    /// A transaction was accepted into the mempool.
+    fn on_tx_accepted(&self, _transaction: &Transaction) {}
+
+    /// A transaction left the mempool without being mined.
+    fn on_tx_evicted(&self, _transaction: &Transaction) {}
+
+    /// A transaction spends an output watched by the node's
+    /// `Watchtower` that another transaction spent before.
+    fn on_double_spend(&self, _double_spend: &DoubleSpend) {}
+}
+
+pub fn register_observer(state: &NodeState, observer: Arc<dyn ChainObserver>) {
+    state.observers.write().unwrap().push(observer);
+}
+
+fn for_each_observer(state: &NodeState, notify: impl Fn(&dyn ChainObserver)) {
+    for observer in state.observers.read().unwrap().iter() {
+        notify(observer.as_ref());
+    }
+}
+
+/// Notifies observers that `blockchain`'s best block was just added.
+pub fn notify_block_connected(state: &NodeState, blockchain: &Blockchain) {
+    if let (Some(block), Some(tip)) = (blockchain.blocks().last(), blockchain.tip()) {
+        for_each_observer(state, |observer| {
+            observer.on_block_connected(block, tip.height)
+        });
+    }
+}
+
+pub fn notify_tx_accepted(state: &NodeState, transaction: &Transaction) {
+    for_each_observer(state, |observer| observer.on_tx_accepted(transaction));
+}
+
+pub fn notify_tx_evicted(state: &NodeState, transaction: &Transaction) {
+    for_each_observer(state, |observer| observer.on_tx_evicted(transaction));
+}
+
+/// Notifies observers that the blocks of `blockchain` from `fork_height`
+/// on replaced `disconnected`.
+pub fn notify_reorg(
+    state: &NodeState,
+    blockchain: &Blockchain,
+    fork_height: BlockHeight,
+    disconnected: &[Block],
+) {
+    for (index, block) in disconnected.iter().enumerate().rev() {
+        let height = BlockHeight::from_index(fork_height.as_index() + index);
+        for_each_observer(state, |observer| {
+            observer.on_block_disconnected(block, height)
+        });
+    }
+    for (height, block) in blockchain.iter_blocks_in(fork_height..) {
+        for_each_observer(state, |observer| observer.on_block_connected(block, height));
+    }
+}
+
+pub fn notify_chain_conflict(state: &NodeState, conflict: &ChainConflict) {
+    for_each_observer(state, |observer| observer.on_chain_conflict(conflict));
+}
+
+pub fn notify_rejection_storm(state: &NodeState, storm: &RejectionStorm) {
+    for_each_observer(state, |observer| observer.on_rejection_storm(storm));
+}
+
+pub fn notify_double_spend(state: &NodeState, double_spend: &DoubleSpend) {
+    for_each_observer(state, |observer| observer.on_double_spend(double_spend));
+}
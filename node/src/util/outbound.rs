@@ -0,0 +1,154 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant as StdInstant},
+};
+
+use btclib::client::NodeClient;
+use log::{debug, info, warn};
+use tokio::time::{self, Instant, MissedTickBehavior};
+
+use crate::{
+    NodeState,
+    util::{
+        follow_best_peer, known_addresses, learn_addresses, message_timeout, reconcile_mempools,
+        record_response,
+    },
+};
+
+/// Peers an outbound-only node keeps connections to, unless configured.
+pub const DEFAULT_OUTBOUND_PEERS: usize = 8;
+/// Seconds between an outbound-only node's polls of its peers, unless
+/// configured.
+pub const DEFAULT_OUTBOUND_POLL_SECS: u64 = 30;
+
+/// How a node that doesn't listen for peers (`--nolisten`), e.g. one
+/// behind a NAT it can't forward a port through, keeps up with the
+/// network. Nobody can announce blocks and transactions to it, so it
+/// polls its peers for them instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutboundConfig {
+    /// Connections to keep, replaced from the address book as peers
+    /// stop answering
+    pub peers: usize,
+    /// How often the peers are asked for new blocks and transactions
+    pub poll_interval: Duration,
+}
+
+impl Default for OutboundConfig {
+    fn default() -> Self {
+        OutboundConfig {
+            peers: DEFAULT_OUTBOUND_PEERS,
+            poll_interval: Duration::from_secs(DEFAULT_OUTBOUND_POLL_SECS),
+        }
+    }
+}
+
+/// Keeps an outbound-only node connected and in step with the network.
+/// Every poll interval it drops the peers that don't answer a ping,
+/// connects to known addresses until it has `config.peers` again,
+/// follows the best chain they serve and, with `mempool_sync`, fetches
+/// the mempool transactions it lacks. What it mines or is submitted
+/// locally is relayed over the same connections as usual.
+pub async fn maintain_outbound(state: Arc<NodeState>, config: OutboundConfig, mempool_sync: bool) {
+    // startup has just connected and synced
+    let mut interval =
+        time::interval_at(Instant::now() + config.poll_interval, config.poll_interval);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        drop_unresponsive_peers(&state).await;
+        let connected = connect_outbound(&state, config.peers).await;
+        if connected > 0 {
+            info!(
+                "connected to {connected} more peers, {} in all",
+                state.nodes.len()
+            );
+        }
+        if state.nodes.is_empty() {
+            warn!("no peer to poll, waiting for addresses to connect to");
+            continue;
+        }
+        if let Err(e) = follow_best_peer(&state).await {
+            warn!("failed to follow the best peer: {e:#}");
+        }
+        if mempool_sync {
+            let added = reconcile_mempools(&state).await;
+            if added > 0 {
+                info!("fetched {added} new transactions from peers");
+            }
+        }
+    }
+}
+
+/// Connects to known addresses not connected yet until the node has
+/// `target` peers, asking each new one for the addresses it knows.
+/// Addresses are tried best first (see `PeerQualityBook::rank`), so
+/// peers that served well before are reconnected to over flaky ones.
+/// Returns how many were connected.
+pub async fn connect_outbound(state: &NodeState, target: usize) -> usize {
+    let timeout = message_timeout(state);
+    let mut connected = 0;
+    let mut addresses = known_addresses(state);
+    state
+        .peer_quality
+        .lock()
+        .unwrap()
+        .rank(&mut addresses, chrono::Utc::now());
+    for address in addresses {
+        if state.nodes.len() >= target {
+            break;
+        }
+        if state.nodes.contains_key(&address) {
+            continue;
+        }
+        let sent = StdInstant::now();
+        let mut client = match time::timeout(timeout, NodeClient::connect(&address)).await {
+            Ok(Ok(client)) => client,
+            Ok(Err(e)) => {
+                debug!("failed to connect to {address}: {e}");
+                record_response(state, &address, sent, false);
+                continue;
+            }
+            Err(_) => {
+                debug!("connecting to {address} timed out");
+                record_response(state, &address, sent, false);
+                continue;
+            }
+        };
+        let sent = StdInstant::now();
+        let answer = time::timeout(timeout, client.discover_nodes()).await;
+        record_response(state, &address, sent, matches!(answer, Ok(Ok(_))));
+        if let Ok(Ok(addresses)) = answer {
+            learn_addresses(state, addresses);
+        }
+        info!("connected to {address}");
+        state.nodes.insert(address, client);
+        connected += 1;
+    }
+    connected
+}
+
+// disconnects the peers whose connection broke or that stopped answering
+async fn drop_unresponsive_peers(state: &NodeState) {
+    let timeout = message_timeout(state);
+    let peers = state
+        .nodes
+        .iter()
+        .map(|entry| entry.key().clone())
+        .collect::<Vec<_>>();
+    for peer in peers {
+        let Some(mut client) = state.nodes.get_mut(&peer) else {
+            continue;
+        };
+        let sent = StdInstant::now();
+        let alive = matches!(time::timeout(timeout, client.ping()).await, Ok(Ok(())));
+        drop(client);
+        record_response(state, &peer, sent, alive);
+        if !alive {
+            info!("{peer} stopped answering, disconnected");
+            state.nodes.remove(&peer);
+            state.known_inventory.lock().unwrap().forget_peer(&peer);
+            state.peer_quality.lock().unwrap().record_disconnect(&peer);
+        }
+    }
+}
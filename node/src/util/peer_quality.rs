@@ -0,0 +1,170 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use log::debug;
+
+use crate::NodeState;
+
+// weight of a new answer in a peer's latency average
+const LATENCY_SMOOTHING: f64 = 0.2;
+// latency assumed of a peer that hasn't answered yet, in seconds
+const UNKNOWN_LATENCY: f64 = 1.0;
+// a block that fails validation outweighs this many valid ones
+const INVALID_BLOCK_WEIGHT: u64 = 4;
+// connected this long, a peer's uptime counts in full
+const FULL_UPTIME_SECS: f64 = 3_600.0;
+
+/// What a node saw of a peer's service, kept across reconnections.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PeerQuality {
+    /// Requests answered in time
+    pub answered: u64,
+    /// Requests that failed or timed out, and failed connection attempts
+    pub failed: u64,
+    /// Valid blocks the peer served or relayed
+    pub valid_blocks: u64,
+    /// Blocks it served or relayed that failed validation
+    pub invalid_blocks: u64,
+    /// Moving average of the time the peer took to answer, in seconds
+    pub latency: Option<f64>,
+    /// Since when the peer is connected, `None` while it isn't
+    pub connected_since: Option<DateTime<Utc>>,
+}
+
+impl PeerQuality {
+    /// Share of requests answered, starting from even odds.
+    pub fn reliability(&self) -> f64 {
+        (self.answered + 1) as f64 / (self.answered + self.failed + 2) as f64
+    }
+
+    /// Whether the peer fails more requests than it answers or serves
+    /// more invalid blocks than valid ones.
+    pub fn is_flaky(&self) -> bool {
+        self.reliability() < 0.5 || self.invalid_blocks > self.valid_blocks
+    }
+
+    /// From 0 to 1, higher for peers that answer reliably and quickly,
+    /// serve valid blocks and stay connected.
+    pub fn score(&self, now: DateTime<Utc>) -> f64 {
+        let validity = (self.valid_blocks + 1) as f64
+            / (self.valid_blocks + INVALID_BLOCK_WEIGHT * self.invalid_blocks + 1) as f64;
+        let speed = 1.0 / (1.0 + self.latency.unwrap_or(UNKNOWN_LATENCY));
+        let uptime = self.connected_since.map_or(0.0, |since| {
+            let secs = (now - since).num_seconds().max(0) as f64;
+            (secs / FULL_UPTIME_SECS).min(1.0)
+        });
+        self.reliability() * validity * speed * (0.5 + 0.5 * uptime)
+    }
+}
+
+/// Service quality of every peer this node talked to, by address, to
+/// prefer the best peers when downloading blocks and reconciling
+/// mempools.
+#[derive(Debug, Default)]
+pub struct PeerQualityBook {
+    peers: HashMap<String, PeerQuality>,
+}
+
+impl PeerQualityBook {
+    /// Counts a request `peer` answered within `latency`, which also
+    /// means it is connected.
+    pub fn record_answer(&mut self, peer: &str, latency: Duration, now: DateTime<Utc>) {
+        let quality = self.peers.entry(peer.to_string()).or_default();
+        quality.answered += 1;
+        let latency = latency.as_secs_f64();
+        quality.latency = Some(match quality.latency {
+            Some(average) => average + LATENCY_SMOOTHING * (latency - average),
+            None => latency,
+        });
+        quality.connected_since.get_or_insert(now);
+    }
+
+    /// Counts a request `peer` didn't answer in time or a failed attempt
+    /// to connect to it.
+    pub fn record_failure(&mut self, peer: &str) {
+        self.peers.entry(peer.to_string()).or_default().failed += 1;
+    }
+
+    /// Counts `count` blocks `peer` served, valid or not.
+    pub fn record_blocks(&mut self, peer: &str, count: u64, valid: bool) {
+        let quality = self.peers.entry(peer.to_string()).or_default();
+        if valid {
+            quality.valid_blocks += count;
+        } else {
+            quality.invalid_blocks += count;
+        }
+    }
+
+    /// Counts a block relayed from `ip`. Peers relay over connections of
+    /// their own, whose port isn't the one they listen on, so every peer
+    /// at that host is credited.
+    pub fn record_relayed_block(&mut self, ip: IpAddr, valid: bool) {
+        let peers: Vec<String> = self
+            .peers
+            .keys()
+            .filter(|peer| peer.parse::<SocketAddr>().is_ok_and(|addr| addr.ip() == ip))
+            .cloned()
+            .collect();
+        for peer in peers {
+            self.record_blocks(&peer, 1, valid);
+        }
+    }
+
+    /// Notes that `peer` was disconnected, so its uptime starts over.
+    pub fn record_disconnect(&mut self, peer: &str) {
+        if let Some(quality) = self.peers.get_mut(peer) {
+            quality.connected_since = None;
+        }
+    }
+
+    pub fn get(&self, peer: &str) -> Option<&PeerQuality> {
+        self.peers.get(peer)
+    }
+
+    /// Orders `peers` best first: flaky peers last, the others by score.
+    /// Peers never seen count as new ones.
+    pub fn rank(&self, peers: &mut [String], now: DateTime<Utc>) {
+        let unknown = PeerQuality::default();
+        let key = |peer: &String| {
+            let quality = self.peers.get(peer).unwrap_or(&unknown);
+            (quality.is_flaky(), quality.score(now))
+        };
+        peers.sort_by(|a, b| {
+            let (a_flaky, a_score) = key(a);
+            let (b_flaky, b_score) = key(b);
+            a_flaky
+                .cmp(&b_flaky)
+                .then_with(|| b_score.total_cmp(&a_score))
+        });
+    }
+}
+
+/// Records whether `peer` answered a request sent at `sent`.
+pub fn record_response(state: &NodeState, peer: &str, sent: Instant, answered: bool) {
+    let mut book = state.peer_quality.lock().unwrap();
+    if answered {
+        book.record_answer(peer, sent.elapsed(), Utc::now());
+    } else {
+        book.record_failure(peer);
+    }
+}
+
+/// The connected peers, best first as ranked by `PeerQualityBook::rank`.
+pub fn ranked_peers(state: &NodeState) -> Vec<String> {
+    let mut peers = state
+        .nodes
+        .iter()
+        .map(|entry| entry.key().clone())
+        .collect::<Vec<_>>();
+    state
+        .peer_quality
+        .lock()
+        .unwrap()
+        .rank(&mut peers, Utc::now());
+    debug!("peers by quality: {peers:?}");
+    peers
+}
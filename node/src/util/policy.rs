@@ -0,0 +1,135 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    net::IpAddr,
+};
+
+use btclib::{
+    custom_sha_types::Hash,
+    network::{PolicyViolation, RelayPolicy},
+    types::{Blockchain, Transaction},
+};
+use chrono::{DateTime, Duration, Utc};
+
+use crate::NodeState;
+
+/// Tracks the transactions below the minimum fee rate each peer
+/// relayed in the last hour.
+#[derive(Debug, Default)]
+pub struct FreeTxQuota {
+    free_transactions: HashMap<IpAddr, VecDeque<DateTime<Utc>>>,
+}
+
+impl FreeTxQuota {
+    /// Counts a free transaction from `peer`, unless it already sent
+    /// `per_hour` of them in the last hour.
+    pub fn try_use(&mut self, peer: IpAddr, per_hour: u32, now: DateTime<Utc>) -> bool {
+        let cutoff = now - Duration::hours(1);
+        // forget peers whose quota has fully recovered
+        self.free_transactions
+            .retain(|_, sent| sent.back().is_some_and(|last| *last >= cutoff));
+        let sent = self.free_transactions.entry(peer).or_default();
+        while sent.front().is_some_and(|first| *first < cutoff) {
+            sent.pop_front();
+        }
+        if sent.len() >= per_hour as usize {
+            return false;
+        }
+        sent.push_back(now);
+        true
+    }
+}
+
+/// Which peer each mempool transaction came from, to limit how many
+/// one peer has in the mempool at a time.
+#[derive(Debug, Default)]
+pub struct MempoolOrigins {
+    origins: HashMap<Hash, IpAddr>,
+}
+
+impl MempoolOrigins {
+    pub fn record(&mut self, transaction: &Hash, peer: IpAddr) {
+        self.origins.insert(*transaction, peer);
+    }
+
+    /// Transactions from `peer` still in `blockchain`'s mempool.
+    /// Forgets the ones that left it.
+    pub fn count(&mut self, peer: IpAddr, blockchain: &Blockchain) -> usize {
+        let pending: HashSet<Hash> = blockchain
+            .mempool()
+            .iter()
+            .map(|(_, transaction)| transaction.hash())
+            .collect();
+        self.origins.retain(|hash, _| pending.contains(hash));
+        self.origins
+            .values()
+            .filter(|origin| **origin == peer)
+            .count()
+    }
+}
+
+pub fn init_policy(state: &NodeState, policy: RelayPolicy) {
+    *state.policy.write().unwrap() = policy;
+}
+
+pub fn relay_policy(state: &NodeState) -> RelayPolicy {
+    *state.policy.read().unwrap()
+}
+
+/// Checks `transaction`, received from `peer`, against the relay policy.
+/// A transaction below the minimum fee rate passes if the peer still has
+/// free transactions left this hour. Transactions whose fee can't be
+/// determined are left to mempool validation, once they are found to be
+/// standard.
+pub fn check_relay_policy(
+    state: &NodeState,
+    peer: IpAddr,
+    transaction: &Transaction,
+    blockchain: &Blockchain,
+) -> Result<(), PolicyViolation> {
+    let policy = relay_policy(state);
+    // before any input is looked up
+    policy.check_standard(transaction)?;
+    let Some(fee) = blockchain.transaction_fee(transaction) else {
+        return Ok(());
+    };
+    let max = policy.max_unconfirmed_per_key;
+    if blockchain
+        .transaction_senders(transaction)
+        .iter()
+        .any(|sender| blockchain.unconfirmed_from(sender) >= max)
+    {
+        return Err(PolicyViolation::TooManyFromKey { max });
+    }
+    let max = policy.max_unconfirmed_per_peer;
+    if state
+        .mempool_origins
+        .lock()
+        .unwrap()
+        .count(peer, blockchain)
+        >= max
+    {
+        return Err(PolicyViolation::TooManyFromPeer { max });
+    }
+    match policy.check(transaction, fee) {
+        Err(PolicyViolation::FeeTooLow { .. })
+            if state.free_tx_quota.lock().unwrap().try_use(
+                peer,
+                policy.free_tx_per_hour,
+                Utc::now(),
+            ) =>
+        {
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// Remembers that `transaction`, just added to the mempool, came from
+/// `peer`.
+pub fn record_mempool_origin(state: &NodeState, peer: IpAddr, transaction: &Transaction) {
+    state
+        .mempool_origins
+        .lock()
+        .unwrap()
+        .record(&transaction.hash(), peer);
+}
@@ -0,0 +1,121 @@
+use std::{collections::HashSet, time::Instant};
+
+use anyhow::{Context, Result};
+use btclib::{custom_sha_types::Hash, types::Transaction};
+use chrono::Utc;
+use log::{info, warn};
+use tokio::time;
+
+use crate::{
+    NodeState,
+    util::{
+        check_relay_policy, message_timeout, notify_tx_accepted, queue_transaction, ranked_peers,
+        record_mempool_origin, record_response,
+    },
+};
+
+/// Fetches the transactions in `node`'s mempool that ours lacks, so a
+/// node back from a restart or partition catches up without waiting for
+/// them to be rebroadcast. Only txids are exchanged up front, then the
+/// missing transactions in batches of `MAX_FETCH_TRANSACTIONS`. Returns
+/// how many were added.
+pub async fn reconcile_mempool(state: &NodeState, node: &str) -> Result<usize> {
+    let timeout = message_timeout(state);
+    let mut client = state.nodes.get_mut(node).context("no node")?;
+    let peer_ip = client.peer_addr()?.ip();
+    let sent = Instant::now();
+    let theirs = time::timeout(timeout, client.get_mempool()).await;
+    record_response(state, node, sent, matches!(theirs, Ok(Ok(_))));
+    let theirs = theirs.context("mempool not sent in time")??;
+    let missing: Vec<Hash> = {
+        let blockchain = state.blockchain.read().await;
+        let ours: HashSet<Hash> = blockchain
+            .mempool()
+            .iter()
+            .map(|(_, transaction)| transaction.hash())
+            .collect();
+        theirs
+            .into_iter()
+            .filter(|hash| !ours.contains(hash))
+            .collect()
+    };
+    if missing.is_empty() {
+        return Ok(0);
+    }
+    info!(
+        "fetching {} missing transactions from {node}",
+        missing.len()
+    );
+
+    let mut fetched: Vec<Transaction> = vec![];
+    for chunk in missing.chunks(btclib::MAX_FETCH_TRANSACTIONS) {
+        let requested: HashSet<&Hash> = chunk.iter().collect();
+        let transactions = time::timeout(timeout, client.get_transactions(chunk.to_vec()))
+            .await
+            .context("transactions not sent in time")??;
+        fetched.extend(
+            transactions
+                .into_iter()
+                .filter(|transaction| requested.contains(&transaction.hash())),
+        );
+    }
+    drop(client);
+    {
+        // no need to relay them back to where they came from
+        let mut known = state.known_inventory.lock().unwrap();
+        let now = Utc::now();
+        for transaction in &fetched {
+            known.insert(node, transaction.hash(), now);
+        }
+    }
+
+    // a child sent before its parent only fits once the parent is in,
+    // so retry the rest until a round adds nothing
+    let mut blockchain = state.blockchain.write().await;
+    let mut added = 0;
+    loop {
+        let before = added;
+        let mut rejected = vec![];
+        for transaction in std::mem::take(&mut fetched) {
+            if let Err(e) = check_relay_policy(state, peer_ip, &transaction, &blockchain) {
+                info!("not relaying transaction: {e}");
+                continue;
+            }
+            if blockchain
+                .add_transaction_to_mempool(transaction.clone())
+                .is_err()
+            {
+                rejected.push(transaction);
+                continue;
+            }
+            record_mempool_origin(state, peer_ip, &transaction);
+            notify_tx_accepted(state, &transaction);
+            queue_transaction(state, transaction);
+            added += 1;
+        }
+        if rejected.is_empty() || added == before {
+            if !rejected.is_empty() {
+                warn!("{} transactions from {node} rejected", rejected.len());
+            }
+            break;
+        }
+        fetched = rejected;
+    }
+    Ok(added)
+}
+
+/// Reconciles our mempool with every peer's, best peers first (see
+/// `ranked_peers`), so most transactions come from them and flaky peers
+/// are left to fill the gaps. Returns how many transactions were added
+/// in all.
+pub async fn reconcile_mempools(state: &NodeState) -> usize {
+    let nodes = ranked_peers(state);
+    let mut added = 0;
+    for node in nodes {
+        match reconcile_mempool(state, &node).await {
+            Ok(count) => added += count,
+            Err(e) => warn!("failed to reconcile mempool with {node}: {e}"),
+        }
+    }
+    added
+}
@@ -0,0 +1,150 @@
+use std::{collections::VecDeque, fmt};
+
+use chrono::{DateTime, Duration, Utc};
+use log::error;
+
+use crate::{NodeState, util::notify_rejection_storm};
+
+// rejections are counted over this window
+const REJECTION_WINDOW_SECONDS: i64 = 60;
+// a storm is alerted about at most once in this long
+const ALERT_COOLDOWN_MINUTES: i64 = 10;
+
+/// What the node refused as invalid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectedKind {
+    /// A block a peer announced on top of our best block
+    Block,
+    /// A transaction a peer relayed or a wallet submitted
+    Transaction,
+}
+
+impl fmt::Display for RejectedKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RejectedKind::Block => write!(f, "block"),
+            RejectedKind::Transaction => write!(f, "transaction"),
+        }
+    }
+}
+
+/// Invalid blocks and transactions the node takes in a minute before
+/// alerting. Many more than usual point to a fork, a protocol break or
+/// an attack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RejectionLimits {
+    pub max_blocks_per_minute: u32,
+    pub max_transactions_per_minute: u32,
+}
+
+impl Default for RejectionLimits {
+    fn default() -> Self {
+        RejectionLimits {
+            max_blocks_per_minute: 3,
+            max_transactions_per_minute: 60,
+        }
+    }
+}
+
+impl RejectionLimits {
+    pub fn limit(&self, kind: RejectedKind) -> u32 {
+        match kind {
+            RejectedKind::Block => self.max_blocks_per_minute,
+            RejectedKind::Transaction => self.max_transactions_per_minute,
+        }
+    }
+}
+
+/// More invalid blocks or transactions arrived within a minute than
+/// `RejectionLimits` allow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RejectionStorm {
+    pub kind: RejectedKind,
+    /// Rejections in the last minute
+    pub count: usize,
+    pub limit: u32,
+}
+
+#[derive(Debug, Default)]
+struct RejectionLog {
+    rejections: VecDeque<DateTime<Utc>>,
+    last_alert: Option<DateTime<Utc>>,
+}
+
+impl RejectionLog {
+    fn count(&mut self, now: DateTime<Utc>) -> usize {
+        let cutoff = now - Duration::seconds(REJECTION_WINDOW_SECONDS);
+        while self
+            .rejections
+            .front()
+            .is_some_and(|rejection| *rejection <= cutoff)
+        {
+            self.rejections.pop_front();
+        }
+        self.rejections.len()
+    }
+}
+
+/// Recent rejections of invalid blocks and transactions, checked
+/// against the node's `RejectionLimits`.
+#[derive(Debug, Default)]
+pub struct RejectionTracker {
+    limits: RejectionLimits,
+    blocks: RejectionLog,
+    transactions: RejectionLog,
+}
+
+impl RejectionTracker {
+    pub fn set_limits(&mut self, limits: RejectionLimits) {
+        self.limits = limits;
+    }
+
+    fn log(&mut self, kind: RejectedKind) -> &mut RejectionLog {
+        match kind {
+            RejectedKind::Block => &mut self.blocks,
+            RejectedKind::Transaction => &mut self.transactions,
+        }
+    }
+
+    /// Records a rejection. Returns the storm to alert about if the
+    /// limit is now exceeded and no alert went out for the same kind
+    /// recently.
+    pub fn record(&mut self, kind: RejectedKind, now: DateTime<Utc>) -> Option<RejectionStorm> {
+        let limit = self.limits.limit(kind);
+        let log = self.log(kind);
+        log.rejections.push_back(now);
+        let count = log.count(now);
+        if count <= limit as usize
+            || log
+                .last_alert
+                .is_some_and(|alert| now - alert < Duration::minutes(ALERT_COOLDOWN_MINUTES))
+        {
+            return None;
+        }
+        log.last_alert = Some(now);
+        Some(RejectionStorm { kind, count, limit })
+    }
+
+    /// Rejections of `kind` in the last minute.
+    pub fn count(&mut self, kind: RejectedKind, now: DateTime<Utc>) -> usize {
+        self.log(kind).count(now)
+    }
+}
+
+pub fn init_rejection_limits(state: &NodeState, limits: RejectionLimits) {
+    state.rejections.lock().unwrap().set_limits(limits);
+}
+
+/// Counts an invalid block or transaction from the network, and logs
+/// and reports to the observers a storm of them.
+pub fn record_rejection(state: &NodeState, kind: RejectedKind) {
+    let storm = state.rejections.lock().unwrap().record(kind, Utc::now());
+    if let Some(storm) = storm {
+        error!(
+            "{} invalid {}s in the last minute (limit {}): the network may have forked, \
+             a protocol change broken compatibility or a peer be attacking",
+            storm.count, storm.kind, storm.limit
+        );
+        notify_rejection_storm(state, &storm);
+    }
+}
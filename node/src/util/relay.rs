@@ -0,0 +1,300 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    net::IpAddr,
+    sync::Arc,
+};
+
+use chrono::{DateTime, Utc};
+
+use btclib::{
+    crypto::PublicKey,
+    custom_sha_types::Hash,
+    network::{PolicyViolation, RejectCode, RelayPolicy},
+    types::{Block, Transaction, TransactionOutput, UnsignedTransaction},
+};
+use log::{debug, info};
+use tokio::time::{self, Duration};
+
+use crate::{
+    NodeState,
+    util::{
+        ChainstateSnapshot, RejectedKind, check_relay_policy, notify_tx_accepted,
+        record_mempool_origin, record_rejection, relay_policy,
+    },
+};
+
+// hashes remembered per peer before the oldest are forgotten
+const MAX_KNOWN_PER_PEER: usize = 10_000;
+// average time between transaction relay rounds
+const TRICKLE_INTERVAL_MS: f64 = 2_000.0;
+
+/// Blocks and transactions each peer is known to have, because we sent
+/// them or the peer announced them to us. Only the most recent
+/// `MAX_KNOWN_PER_PEER` hashes are remembered per peer, and only until
+/// `expire` drops them.
+#[derive(Debug, Default)]
+pub struct KnownInventory {
+    peers: HashMap<String, PeerInventory>,
+}
+
+#[derive(Debug, Default)]
+struct PeerInventory {
+    hashes: HashSet<Hash>,
+    order: VecDeque<(DateTime<Utc>, Hash)>,
+}
+
+impl KnownInventory {
+    /// Remembers that `peer` has `hash` as of `now`. False if it was
+    /// already known.
+    pub fn insert(&mut self, peer: &str, hash: Hash, now: DateTime<Utc>) -> bool {
+        let inventory = self.peers.entry(peer.to_string()).or_default();
+        if !inventory.hashes.insert(hash) {
+            return false;
+        }
+        inventory.order.push_back((now, hash));
+        if inventory.order.len() > MAX_KNOWN_PER_PEER
+            && let Some((_, oldest)) = inventory.order.pop_front()
+        {
+            inventory.hashes.remove(&oldest);
+        }
+        true
+    }
+
+    /// Forgets what peers were known to have at `cutoff` or before. By
+    /// the mempool expiry a peer has dropped an unconfirmed transaction
+    /// as well, so it may be announced to it again.
+    pub fn expire(&mut self, cutoff: DateTime<Utc>) {
+        for inventory in self.peers.values_mut() {
+            while let Some(&(time, hash)) = inventory.order.front()
+                && time <= cutoff
+            {
+                inventory.order.pop_front();
+                inventory.hashes.remove(&hash);
+            }
+        }
+        self.peers
+            .retain(|_, inventory| !inventory.order.is_empty());
+    }
+
+    pub fn knows(&self, peer: &str, hash: &Hash) -> bool {
+        self.peers
+            .get(peer)
+            .is_some_and(|inventory| inventory.hashes.contains(hash))
+    }
+
+    pub fn forget_peer(&mut self, peer: &str) {
+        self.peers.remove(peer);
+    }
+}
+
+/// Time until the next transaction relay round. Exponentially
+/// distributed, so peers can't tell from the timing of a relay whether
+/// we created a transaction or passed it on.
+pub fn trickle_delay() -> Duration {
+    let uniform: f64 = rand::random();
+    let millis = -(1.0 - uniform).ln() * TRICKLE_INTERVAL_MS;
+    // cut off the long tail
+    Duration::from_millis(millis.min(TRICKLE_INTERVAL_MS * 4.0) as u64)
+}
+
+/// Records that whoever connected from `sender` has `hash`. Peers are
+/// known by their listening address while announcements arrive from an
+/// ephemeral port, so the announcement is only attributed when the IP
+/// belongs to exactly one peer.
+pub fn record_announcement(state: &NodeState, sender: IpAddr, hash: Hash) {
+    let matching = state
+        .nodes
+        .iter()
+        .filter(|entry| {
+            entry
+                .value()
+                .peer_addr()
+                .is_ok_and(|addr| addr.ip() == sender)
+        })
+        .map(|entry| entry.key().clone())
+        .collect::<Vec<_>>();
+    if let [peer] = matching.as_slice() {
+        state
+            .known_inventory
+            .lock()
+            .unwrap()
+            .insert(peer, hash, Utc::now());
+    }
+}
+
+/// Announces `block` right away to every peer that doesn't have it yet.
+pub async fn relay_block(state: &NodeState, block: &Block) {
+    let hash = block.hash();
+    for node in unaware_peers(state, &hash) {
+        if let Some(mut client) = state.nodes.get_mut(&node)
+            && client.announce_block(block.clone()).await.is_err()
+        {
+            info!("failed to send new block to {node}");
+        }
+    }
+}
+
+/// Checks `transaction`, submitted from `peer`, against the relay policy,
+/// adds it to the mempool and queues it for relay. On failure, the
+/// reject code and reason to send back.
+pub async fn submit_transaction(
+    state: &NodeState,
+    peer: IpAddr,
+    transaction: Transaction,
+) -> Result<(), (RejectCode, String)> {
+    let mut blockchain = state.blockchain.write().await;
+    check_relay_policy(state, peer, &transaction, &blockchain)
+        .map_err(|e| (RejectCode::Policy, e.to_string()))?;
+    if let Err(e) = blockchain.add_transaction_to_mempool(transaction.clone()) {
+        record_rejection(state, RejectedKind::Transaction);
+        return Err((RejectCode::Invalid, e.to_string()));
+    }
+    record_mempool_origin(state, peer, &transaction);
+    notify_tx_accepted(state, &transaction);
+    info!("added transaction to mempool, relaying in the next round");
+    queue_transaction(state, transaction);
+    Ok(())
+}
+
+/// Pays `recipients` from the outputs of `from` not yet spent in the
+/// mempool, at `fee_rate` or the relay policy's minimum if higher, for a
+/// client to sign and submit. On failure, the reject code and reason to
+/// send back.
+pub async fn build_transaction(
+    state: &NodeState,
+    from: &PublicKey,
+    recipients: &[(PublicKey, u64)],
+    fee_rate: Option<u64>,
+) -> Result<UnsignedTransaction, (RejectCode, String)> {
+    if recipients.is_empty() {
+        return Err((RejectCode::Invalid, "no recipients".to_string()));
+    }
+    let relay_policy = relay_policy(state);
+    let policy = RelayPolicy {
+        min_fee_rate: fee_rate.unwrap_or(0).max(relay_policy.min_fee_rate),
+        ..relay_policy
+    };
+    if let Some((_, value)) = recipients
+        .iter()
+        .find(|(_, value)| *value < policy.dust_threshold)
+    {
+        let violation = PolicyViolation::Dust {
+            value: *value,
+            threshold: policy.dust_threshold,
+        };
+        return Err((RejectCode::Policy, violation.to_string()));
+    }
+    let snapshot = ChainstateSnapshot::take(state).await;
+    let utxos: Vec<(Hash, TransactionOutput)> = snapshot
+        .utxos_for(from)
+        .into_iter()
+        .filter(|(_, entry)| entry.is_unspent())
+        .map(|(hash, entry)| (hash, entry.output().clone()))
+        .collect();
+    drop(snapshot);
+    let unsigned = UnsignedTransaction::fund(&utxos, from, recipients, &policy)
+        .map_err(|e| (RejectCode::Invalid, e.to_string()))?;
+    let count = unsigned.inputs.len();
+    if count > policy.max_tx_inputs {
+        let violation = PolicyViolation::TooManyInputs {
+            count,
+            max: policy.max_tx_inputs,
+        };
+        return Err((RejectCode::Policy, violation.to_string()));
+    }
+    let count = unsigned.outputs.len();
+    if count > policy.max_tx_outputs {
+        let violation = PolicyViolation::TooManyOutputs {
+            count,
+            max: policy.max_tx_outputs,
+        };
+        return Err((RejectCode::Policy, violation.to_string()));
+    }
+    let size = unsigned.signed_size();
+    if size > policy.max_tx_size {
+        let violation = PolicyViolation::TooLarge {
+            size,
+            max: policy.max_tx_size,
+        };
+        return Err((RejectCode::Policy, violation.to_string()));
+    }
+    Ok(unsigned)
+}
+
+/// `submit_transaction` for a transaction of the operator's own wallet,
+/// submitted by an admin: the relay policy doesn't apply, and it goes in
+/// the mempool's priority lane, so templates include it whatever its fee
+/// and it isn't evicted for its age. Peers still apply their own policy
+/// when it is relayed.
+pub async fn submit_priority_transaction(
+    state: &NodeState,
+    peer: IpAddr,
+    transaction: Transaction,
+) -> Result<(), (RejectCode, String)> {
+    let mut blockchain = state.blockchain.write().await;
+    if let Err(e) = blockchain.add_priority_transaction_to_mempool(transaction.clone()) {
+        record_rejection(state, RejectedKind::Transaction);
+        return Err((RejectCode::Invalid, e.to_string()));
+    }
+    record_mempool_origin(state, peer, &transaction);
+    notify_tx_accepted(state, &transaction);
+    info!("added priority transaction to mempool, relaying in the next round");
+    queue_transaction(state, transaction);
+    Ok(())
+}
+
+/// Queues `transaction` for the next relay round.
+pub fn queue_transaction(state: &NodeState, transaction: Transaction) {
+    state.tx_relay_queue.lock().unwrap().push(transaction);
+}
+
+/// Sends the queued transactions to the peers that don't have them yet,
+/// in rounds a random `trickle_delay` apart. Transactions mined or
+/// evicted while queued aren't sent.
+pub async fn trickle_transactions(state: Arc<NodeState>) {
+    loop {
+        time::sleep(trickle_delay()).await;
+        let queued = std::mem::take(&mut *state.tx_relay_queue.lock().unwrap());
+        if queued.is_empty() {
+            continue;
+        }
+        let transactions: Vec<Transaction> = {
+            let blockchain = state.blockchain.read().await;
+            let pending: HashSet<Hash> = blockchain
+                .mempool()
+                .iter()
+                .map(|(_, transaction)| transaction.hash())
+                .collect();
+            queued
+                .into_iter()
+                .filter(|transaction| pending.contains(&transaction.hash()))
+                .collect()
+        };
+        debug!("relaying {} transactions", transactions.len());
+        for transaction in transactions {
+            let hash = transaction.hash();
+            for node in unaware_peers(&state, &hash) {
+                if let Some(mut client) = state.nodes.get_mut(&node)
+                    && client.announce_tx(transaction.clone()).await.is_err()
+                {
+                    info!("failed to send transaction to {node}");
+                }
+            }
+        }
+    }
+}
+
+// the peers that don't know `hash` yet, marked as knowing it from now on
+fn unaware_peers(state: &NodeState, hash: &Hash) -> Vec<String> {
+    let nodes = state
+        .nodes
+        .iter()
+        .map(|entry| entry.key().clone())
+        .collect::<Vec<_>>();
+    let mut known = state.known_inventory.lock().unwrap();
+    let now = Utc::now();
+    nodes
+        .into_iter()
+        .filter(|node| known.insert(node, *hash, now))
+        .collect()
+}
@@ -0,0 +1,243 @@
+use std::sync::atomic::Ordering;
+
+use anyhow::{Context, Result};
+use btclib::{
+    client::NodeClient,
+    custom_sha_types::Hash,
+    network::ChainTip,
+    types::{Block, BlockHeight, Blockchain, ChainHistory},
+};
+use log::{error, info};
+
+use crate::{
+    NodeState,
+    util::{log_reorg, notify_chain_conflict, notify_reorg, ranked_peers, relay_block},
+};
+
+/// A peer serving a longer chain that forks off ours deeper than the
+/// node reorganizes. Reported to the observers instead of followed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainConflict {
+    pub peer: String,
+    pub tip: Option<ChainTip>,
+    pub peer_tip: ChainTip,
+    /// The fork is deeper than this many blocks
+    pub max_depth: u64,
+}
+
+/// Most blocks `follow_fork` downloads past the ones it would replace.
+/// A longer branch is followed this many blocks at a time, the rest
+/// after the peer's next announcement, so a peer claiming a huge chain
+/// can't make the node fetch and hold all of it.
+pub const MAX_BRANCH_BLOCKS: usize = 500;
+
+pub fn max_reorg_depth(state: &NodeState) -> u64 {
+    state.max_reorg_depth.load(Ordering::Relaxed)
+}
+
+/// Switches to the branch ending in the block `hash` if a peer serves
+/// it in a chain longer than ours that forks off at most
+/// `max_reorg_depth` blocks deep. A deeper fork is logged and reported
+/// to the observers as a `ChainConflict`, and the node stays on its
+/// chain.
+pub async fn follow_fork(state: &NodeState, hash: Hash) -> Result<()> {
+    let max_depth = max_reorg_depth(state);
+    let (tip, count, ours, consensus) = {
+        let blockchain = state.blockchain.read().await;
+        let count = blockchain.blocks().len();
+        let lowest = count.saturating_sub((max_depth as usize).saturating_add(1));
+        // the blocks a reorganization may replace, and the one below
+        let ours: Vec<Hash> = blockchain.blocks()[lowest..]
+            .iter()
+            .map(Block::hash)
+            .collect();
+        (
+            blockchain.tip(),
+            count,
+            ours,
+            blockchain.consensus().clone(),
+        )
+    };
+    let our_hash = |index: usize| ours[index + ours.len() - count];
+
+    let Some((peer, mut client, peer_tip)) = find_peer_serving(state, hash).await else {
+        info!("no peer serves the branch of {hash:x?}");
+        return Ok(());
+    };
+    let peer_count = peer_tip.height.as_index() + 1;
+    if peer_count <= count {
+        info!("{peer} is on a branch no longer than ours");
+        return Ok(());
+    }
+
+    // the first height at which the chains differ
+    let mut fork = count;
+    while fork > 0 {
+        let below = client.get_block(BlockHeight::from_index(fork - 1)).await?;
+        if below.hash() == our_hash(fork - 1) {
+            break;
+        }
+        fork -= 1;
+        if (count - fork) as u64 > max_depth {
+            let conflict = ChainConflict {
+                peer,
+                tip,
+                peer_tip,
+                max_depth,
+            };
+            error!(
+                "{} serves a longer chain forking off more than {max_depth} blocks deep, \
+                 not reorganizing",
+                conflict.peer
+            );
+            notify_chain_conflict(state, &conflict);
+            return Ok(());
+        }
+    }
+
+    // each block is checked to extend the one before and to carry a
+    // seal valid under the chain's consensus before the next is fetched
+    let end = peer_count.min(count + MAX_BRANCH_BLOCKS);
+    let mut branch: Vec<Block> = vec![];
+    for index in fork..end {
+        let block = client.get_block(BlockHeight::from_index(index)).await?;
+        let parent = match branch.last() {
+            Some(parent) => parent.hash(),
+            None if fork > 0 => our_hash(fork - 1),
+            None => Hash::zero(),
+        };
+        if *block.header().prev_block_hash() != parent {
+            anyhow::bail!("{peer} served block {index} not extending its branch");
+        }
+        consensus
+            .verify_seal(&block, BlockHeight::from_index(index))
+            .with_context(|| format!("{peer} served block {index} without a valid seal"))?;
+        branch.push(block);
+    }
+    let Some(new_tip) = branch.last().cloned() else {
+        return Ok(());
+    };
+    {
+        let mut blockchain = state.blockchain.write().await;
+        if blockchain.tip() != tip {
+            info!("chain changed while fetching the branch, not reorganizing");
+            return Ok(());
+        }
+        let fork_height = BlockHeight::from_index(fork);
+        let disconnected = blockchain.reorganize(fork_height, branch, max_depth)?;
+        info!(
+            "reorganized onto the chain of {peer}: {} blocks disconnected, tip now {:x?}",
+            disconnected.len(),
+            new_tip.hash()
+        );
+        chain_rewritten(state, &blockchain, fork_height, &disconnected).await;
+    }
+    relay_block(state, &new_tip).await;
+    Ok(())
+}
+
+/// Follows the longest chain a peer serves, if it is longer than ours.
+/// Peers are tried from the longest chain down, so a chain through an
+/// invalidated block doesn't keep the node off the next best one.
+pub async fn follow_best_peer(state: &NodeState) -> Result<()> {
+    let tip = state.blockchain.read().await.tip();
+    let mut tips = vec![];
+    for peer in ranked_peers(state) {
+        let Ok(mut client) = NodeClient::connect(&peer).await else {
+            continue;
+        };
+        if let Ok(Some(peer_tip)) = client.get_tip().await
+            && tip.is_none_or(|tip| peer_tip.height > tip.height)
+        {
+            tips.push(peer_tip);
+        }
+    }
+    tips.sort_by_key(|peer_tip| std::cmp::Reverse(peer_tip.height));
+    for peer_tip in tips {
+        if let Err(e) = follow_fork(state, peer_tip.hash).await {
+            info!("not following the chain of {:x?}: {e}", peer_tip.hash);
+        }
+        if state.blockchain.read().await.tip() != tip {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Marks the block `hash` invalid, on an operator's request. If it was
+/// in our chain, the node falls back to the block before it and then
+/// follows the best chain its peers serve without it.
+pub async fn invalidate_block(state: &NodeState, hash: Hash) -> Result<()> {
+    {
+        let mut blockchain = state.blockchain.write().await;
+        let disconnected = blockchain.invalidate_block(hash)?;
+        info!(
+            "block {hash:x?} marked invalid, {} blocks disconnected",
+            disconnected.len()
+        );
+        if !disconnected.is_empty() {
+            let fork_height = blockchain.block_height();
+            chain_rewritten(state, &blockchain, fork_height, &disconnected).await;
+        }
+    }
+    follow_best_peer(state).await
+}
+
+/// Accepts the block `hash` again, and switches back to it if a peer
+/// serves it in a longer chain than ours.
+pub async fn reconsider_block(state: &NodeState, hash: Hash) -> Result<()> {
+    if !state.blockchain.write().await.reconsider_block(&hash) {
+        anyhow::bail!("block {hash:x?} isn't marked invalid");
+    }
+    info!("block {hash:x?} no longer marked invalid");
+    follow_best_peer(state).await
+}
+
+/// Parses a block hash written as 64 hex digits, as webhooks send them.
+pub fn parse_block_hash(hex: &str) -> Result<Hash> {
+    let bytes: [u8; 32] = hex::decode(hex.trim())?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("a block hash is 32 bytes"))?;
+    Ok(Hash::from_bytes(bytes))
+}
+
+// saves the chain and brings the indexes and observers up to date
+// after the blocks from `fork_height` on replaced `disconnected`
+async fn chain_rewritten(
+    state: &NodeState,
+    blockchain: &Blockchain,
+    fork_height: BlockHeight,
+    disconnected: &[Block],
+) {
+    if let Err(e) = log_reorg(state, blockchain) {
+        error!("Failed to save the reorganized chain: {e}");
+    }
+    // the indexes catch up again from scratch
+    *state.history.write().await = ChainHistory::default();
+    #[cfg(feature = "assets")]
+    {
+        *state.assets.write().await = Default::default();
+    }
+    notify_reorg(state, blockchain, fork_height, disconnected);
+}
+
+// the best peer (see `ranked_peers`) that has the block `hash` in the
+// chain it serves, and its tip
+async fn find_peer_serving(
+    state: &NodeState,
+    hash: Hash,
+) -> Option<(String, NodeClient, ChainTip)> {
+    for peer in ranked_peers(state) {
+        // a connection of our own, so relaying isn't held up meanwhile
+        let Ok(mut client) = NodeClient::connect(&peer).await else {
+            continue;
+        };
+        if client.get_block_by_hash(hash).await.is_err() {
+            continue;
+        }
+        if let Ok(Some(tip)) = client.get_tip().await {
+            return Some((peer, client, tip));
+        }
+    }
+    None
+}
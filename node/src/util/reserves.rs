@@ -0,0 +1,57 @@
+use anyhow::{Context, Result};
+use btclib::{
+    client::NodeClient,
+    crypto::PublicKey,
+    types::{BlockHeight, ReserveProof},
+};
+
+/// What the keys of a verified `ReserveProof` own.
+#[derive(Debug, Clone)]
+pub struct ReserveReport {
+    pub challenge: String,
+    /// The balances are from just before the block at this height, or
+    /// from the node's current chain if `None`
+    pub height: Option<BlockHeight>,
+    pub balances: Vec<(PublicKey, u64)>,
+}
+
+impl ReserveReport {
+    pub fn total(&self) -> u64 {
+        self.balances.iter().map(|(_, balance)| balance).sum()
+    }
+}
+
+/// Checks the signatures of `proof` and asks the node behind `client`
+/// what each key owns, just before the block at `height` (which takes
+/// an archive node) or on its current chain. Outputs spent by mempool
+/// transactions still count, as they are unspent on chain.
+pub async fn audit_reserves(
+    client: &mut NodeClient,
+    proof: &ReserveProof,
+    height: Option<BlockHeight>,
+) -> Result<ReserveReport> {
+    let keys = proof
+        .verify()
+        .context("the proof doesn't sign the challenge")?;
+    let mut balances = vec![];
+    for key in keys {
+        let balance = match height {
+            Some(height) => client
+                .get_balance_at(key.clone(), height)
+                .await
+                .context("failed to look up a historical balance")?,
+            None => client
+                .get_utxos(&key)
+                .await?
+                .iter()
+                .map(|entry| entry.output().value())
+                .sum(),
+        };
+        balances.push((key, balance));
+    }
+    Ok(ReserveReport {
+        challenge: proof.challenge.clone(),
+        height,
+        balances,
+    })
+}
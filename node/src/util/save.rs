@@ -1,16 +1,17 @@
-use btclib::utils::Saveable;
+use std::sync::Arc;
+
 use log::{info, error};
 use tokio::time;
 
-use crate::BLOCKCHAIN;
+use crate::{NodeState, util::checkpoint};
 
-pub async fn save(name: String) {
+pub async fn save(state: Arc<NodeState>, name: String) {
     let mut interval = time::interval(time::Duration::from_secs(15));
     loop {
         interval.tick().await;
         info!("saving blockchain to drive...");
-        let blockchain = BLOCKCHAIN.read().await;
-        if let Err(e) = blockchain.save_to_file(name.clone()) {
+        let blockchain = state.blockchain.read().await;
+        if let Err(e) = checkpoint(&state, &blockchain, &name) {
             error!("Failed to save blockchain: {}", e);
         } else {
             info!("Blockchain saved successfully");
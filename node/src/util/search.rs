@@ -0,0 +1,89 @@
+use btclib::{
+    crypto::{Address, PublicKey},
+    custom_sha_types::Hash,
+    types::BlockHeight,
+};
+
+use crate::util::{ChainstateSnapshot, parse_block_hash};
+
+/// What a free-text explorer query refers to, see `search`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SearchResult {
+    Block {
+        height: BlockHeight,
+        hash: Hash,
+    },
+    /// A transaction in a block, or in the mempool if `height` is `None`
+    Transaction {
+        hash: Hash,
+        height: Option<BlockHeight>,
+    },
+    /// A public key and the unspent outputs paying it. Outputs pay full
+    /// keys, so the key of an address searched for is only known if one
+    /// of them pays it.
+    Address {
+        address: Address,
+        key: Option<PublicKey>,
+        balance: u64,
+        outputs: usize,
+    },
+}
+
+/// Resolves `query` to what it names on the chain of `snapshot`: the hex
+/// hash of a block, a confirmed transaction or a mempool one, tried in
+/// that order; a block height; a hex public key; or an address. Without an
+/// address index, an address is looked up in the whole UTXO set.
+pub fn search(snapshot: &ChainstateSnapshot, query: &str) -> Option<SearchResult> {
+    let query = query.trim();
+    // a hash of nothing but digits is still a hash
+    if let Ok(hash) = parse_block_hash(query) {
+        if let Some((height, _)) = snapshot
+            .iter_blocks_in(..)
+            .find(|(_, block)| block.hash() == hash)
+        {
+            return Some(SearchResult::Block { height, hash });
+        }
+        if let Some((height, _, _)) = snapshot.transaction(&hash) {
+            return Some(SearchResult::Transaction {
+                hash,
+                height: Some(height),
+            });
+        }
+        snapshot.mempool_transaction(&hash)?;
+        return Some(SearchResult::Transaction { hash, height: None });
+    }
+    if let Ok(height) = query.parse::<u64>() {
+        let height = BlockHeight::new(height);
+        let block = snapshot.block_at(height)?;
+        return Some(SearchResult::Block {
+            height,
+            hash: block.hash(),
+        });
+    }
+    if let Ok(key) = PublicKey::from_hex(query) {
+        let utxos = snapshot.utxos_for(&key);
+        return Some(SearchResult::Address {
+            address: key.address(),
+            balance: utxos.iter().map(|(_, entry)| entry.output().value()).sum(),
+            outputs: utxos.len(),
+            key: Some(key),
+        });
+    }
+    let address: Address = query.parse().ok()?;
+    let mut key = None;
+    let (mut balance, mut outputs) = (0, 0);
+    for (_, entry) in snapshot.iter_utxos() {
+        let output = entry.output();
+        if address.matches(output.pubkey()) {
+            key.get_or_insert_with(|| output.pubkey().clone());
+            balance += output.value();
+            outputs += 1;
+        }
+    }
+    Some(SearchResult::Address {
+        address,
+        key,
+        balance,
+        outputs,
+    })
+}
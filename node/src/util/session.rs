@@ -0,0 +1,62 @@
+use btclib::network::{PeerKind, Session};
+use tokio::time::Duration;
+
+use crate::{NodeState, util::network_limits};
+
+/// A session opened for a connection's `Hello`, counted against the
+/// limit of its kind until dropped.
+pub struct SessionSlot<'a> {
+    state: &'a NodeState,
+    pub session: Session,
+}
+
+impl SessionSlot<'_> {
+    /// How long the connection may stay silent before it is closed.
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.session.idle_timeout_secs.map(Duration::from_secs)
+    }
+}
+
+impl Drop for SessionSlot<'_> {
+    fn drop(&mut self) {
+        let mut sessions = self.state.sessions.lock().unwrap();
+        if let Some(count) = sessions.get_mut(&self.session.kind) {
+            *count = count.saturating_sub(1);
+        }
+    }
+}
+
+/// Opens a session of `kind` with the idle timeout closest to
+/// `requested_secs` the node's limits allow, or `None` if it already
+/// serves as many sessions of that kind as it may.
+pub fn open_session(
+    state: &NodeState,
+    kind: PeerKind,
+    requested_secs: u64,
+) -> Option<SessionSlot<'_>> {
+    let limits = network_limits(state);
+    let mut sessions = state.sessions.lock().unwrap();
+    let count = sessions.entry(kind).or_default();
+    if limits.max_sessions(kind).is_some_and(|max| *count >= max) {
+        return None;
+    }
+    *count += 1;
+    Some(SessionSlot {
+        state,
+        session: Session {
+            kind,
+            idle_timeout_secs: limits.idle_timeout(kind, requested_secs),
+        },
+    })
+}
+
+/// Sessions of `kind` currently open.
+pub fn session_count(state: &NodeState, kind: PeerKind) -> usize {
+    state
+        .sessions
+        .lock()
+        .unwrap()
+        .get(&kind)
+        .copied()
+        .unwrap_or(0)
+}
@@ -0,0 +1,71 @@
+use std::ops::Deref;
+
+use btclib::{
+    crypto::PublicKey,
+    custom_sha_types::Hash,
+    types::{Block, BlockHeight, Blockchain, Transaction, UtxoEntry},
+};
+use tokio::sync::RwLockReadGuard;
+
+use crate::NodeState;
+
+/// A read-only view of a node's chainstate that stays the same for as
+/// long as it is held, so a query making several reads (a block, its
+/// transactions, the UTXOs and mempool) never sees a block or
+/// transaction half applied between them. New blocks and transactions
+/// wait until every snapshot is dropped: hold one for a single query,
+/// not while waiting on a peer.
+///
+/// Dereferences to the `Blockchain` for everything else it can answer.
+pub struct ChainstateSnapshot<'a> {
+    blockchain: RwLockReadGuard<'a, Blockchain>,
+}
+
+impl<'a> ChainstateSnapshot<'a> {
+    pub async fn take(state: &'a NodeState) -> Self {
+        ChainstateSnapshot {
+            blockchain: state.blockchain.read().await,
+        }
+    }
+
+    /// The confirmed transaction `hash` with its block and the block's
+    /// height. Walks the chain from the tip down.
+    pub fn transaction(&self, hash: &Hash) -> Option<(BlockHeight, &Block, &Transaction)> {
+        self.blockchain
+            .iter_blocks_in(..)
+            .rev()
+            .find_map(|(height, block)| {
+                let transaction = block
+                    .transactions()
+                    .iter()
+                    .find(|transaction| transaction.hash() == *hash)?;
+                Some((height, block, transaction))
+            })
+    }
+
+    /// The unconfirmed transaction `hash`, if it is in the mempool.
+    pub fn mempool_transaction(&self, hash: &Hash) -> Option<&Transaction> {
+        self.blockchain
+            .mempool()
+            .iter()
+            .map(|(_, transaction)| transaction)
+            .find(|transaction| transaction.hash() == *hash)
+    }
+
+    /// Unspent outputs paying `pubkey` with their hash, each marked spent
+    /// by the mempool transaction spending it if any.
+    pub fn utxos_for(&self, pubkey: &PublicKey) -> Vec<(Hash, &UtxoEntry)> {
+        self.blockchain
+            .iter_utxos_for(pubkey)
+            .map(|(hash, entry)| (*hash, entry))
+            .collect()
+    }
+}
+
+impl Deref for ChainstateSnapshot<'_> {
+    type Target = Blockchain;
+
+    fn deref(&self) -> &Blockchain {
+        &self.blockchain
+    }
+}
@@ -0,0 +1,433 @@
+use std::{collections::HashSet, fmt, path::PathBuf};
+
+use anyhow::{Context, Result};
+use btclib::{
+    client::NodeClient,
+    crypto::PrivateKey,
+    custom_sha_types::Hash,
+    network::ChainTip,
+    types::{Block, BlockHeader, Transaction, TransactionOutput},
+    utils::MerkleRoot,
+};
+use chrono::Utc;
+use log::{info, warn};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use tokio::time::{self, Duration, Instant};
+use uuid::Uuid;
+
+use crate::{
+    Node, NodeConfig,
+    util::{block_template, connect_new_block, relay_block, wal_path},
+};
+
+/// How a soak run is set up, see `run_soak`.
+#[derive(Debug, Clone)]
+pub struct SoakConfig {
+    /// Nodes of the localnet, at least two
+    pub nodes: usize,
+    /// How long faults are injected before the nodes are left to agree
+    pub duration: Duration,
+    /// Time between two faults
+    pub chaos_interval: Duration,
+    /// Time between two blocks, each mined on a running node picked at
+    /// random
+    pub block_interval: Duration,
+    /// How long the nodes get to agree once faults stop
+    pub convergence_timeout: Duration,
+    /// Seed of every random choice, so a failing run can be replayed
+    pub seed: u64,
+    /// Where the nodes keep their blockchain files
+    pub dir: PathBuf,
+}
+
+impl Default for SoakConfig {
+    fn default() -> Self {
+        SoakConfig {
+            nodes: 4,
+            duration: Duration::from_secs(4 * 60 * 60),
+            chaos_interval: Duration::from_secs(30),
+            block_interval: Duration::from_secs(5),
+            convergence_timeout: Duration::from_secs(120),
+            seed: 0,
+            dir: std::env::temp_dir().join("soak"),
+        }
+    }
+}
+
+/// A fault injected into the localnet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChaosEvent {
+    /// The node is stopped and started again from its files
+    Restart(usize),
+    /// Nodes on either side of the cut, these on one side, stop
+    /// reaching each other
+    Partition(Vec<usize>),
+    /// The partition ends and every node reaches every other again
+    Heal,
+    /// The node is announced a block whose coinbase claims twice the
+    /// reward
+    InvalidBlock(usize),
+}
+
+/// Where the nodes of a soak run ended.
+#[derive(Debug, Clone)]
+pub struct SoakReport {
+    pub events: Vec<ChaosEvent>,
+    pub blocks_mined: u64,
+    /// Nodes that failed to start again after a restart, left out of
+    /// the end state
+    pub lost: Vec<usize>,
+    /// Tip and UTXO set hash of every other node, by index
+    pub end_state: Vec<(usize, Option<ChainTip>, Hash)>,
+}
+
+impl SoakReport {
+    /// Whether every node left has the same tip and UTXO set.
+    pub fn converged(&self) -> bool {
+        self.end_state
+            .windows(2)
+            .all(|pair| (pair[0].1, pair[0].2) == (pair[1].1, pair[1].2))
+    }
+}
+
+impl fmt::Display for SoakReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let count = |matches: fn(&ChaosEvent) -> bool| {
+            self.events.iter().filter(|event| matches(event)).count()
+        };
+        writeln!(
+            f,
+            "{} blocks mined; {} restarts, {} partitions, {} invalid blocks injected",
+            self.blocks_mined,
+            count(|event| matches!(event, ChaosEvent::Restart(_))),
+            count(|event| matches!(event, ChaosEvent::Partition(_))),
+            count(|event| matches!(event, ChaosEvent::InvalidBlock(_))),
+        )?;
+        for index in &self.lost {
+            writeln!(f, "node {index}: lost")?;
+        }
+        for (index, tip, utxo_set_hash) in &self.end_state {
+            match tip {
+                Some(tip) => writeln!(
+                    f,
+                    "node {index}: tip {:x?} at height {}, UTXO set {utxo_set_hash:x?}",
+                    tip.hash, tip.height
+                )?,
+                None => writeln!(f, "node {index}: no blocks")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs a localnet of `config.nodes` in-process nodes for
+/// `config.duration`, mining all along, and every `chaos_interval`
+/// restarts a node, partitions the network or heals it, or announces an
+/// invalid block to a node. Then heals the network, keeps mining and
+/// fails unless every node left agrees on the tip and the UTXO set
+/// within `convergence_timeout`. The report says where they ended either
+/// way. Nodes only connect to each other as the run wires them, so the
+/// topology is the run's alone.
+pub async fn run_soak(config: &SoakConfig) -> Result<SoakReport> {
+    anyhow::ensure!(config.nodes >= 2, "a soak run needs at least two nodes");
+    anyhow::ensure!(
+        !config.chaos_interval.is_zero() && !config.block_interval.is_zero(),
+        "the chaos and block intervals must be positive"
+    );
+    std::fs::create_dir_all(&config.dir)
+        .with_context(|| format!("failed to create {}", config.dir.display()))?;
+    let mut net = Localnet::start(config).await?;
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let mut events = vec![];
+    let key = PrivateKey::default();
+
+    let start = Instant::now();
+    let mut blocks = time::interval(config.block_interval);
+    let mut chaos = time::interval(config.chaos_interval);
+    // both fire right away
+    chaos.tick().await;
+    while start.elapsed() < config.duration {
+        tokio::select! {
+            _ = blocks.tick() => net.mine(&mut rng, &key).await,
+            _ = chaos.tick() => {
+                anyhow::ensure!(!net.running().is_empty(), "every node was lost");
+                let event = net.chaos_event(&mut rng);
+                info!("soak: {event:?}");
+                net.inject(&event, &key).await;
+                events.push(event);
+            }
+        }
+    }
+
+    info!("soak: faults stop, waiting for the nodes to agree");
+    if net.partition.is_some() {
+        net.inject(&ChaosEvent::Heal, &key).await;
+        events.push(ChaosEvent::Heal);
+    }
+    let deadline = Instant::now() + config.convergence_timeout;
+    let report = loop {
+        // new blocks carry the nodes left behind over to the best chain
+        net.mine(&mut rng, &key).await;
+        time::sleep(config.block_interval.min(Duration::from_secs(1))).await;
+        let report = net.report(events.clone()).await;
+        if report.converged() || Instant::now() >= deadline {
+            break report;
+        }
+    };
+    net.stop().await;
+    anyhow::ensure!(
+        report.converged(),
+        "nodes didn't agree within {:?}:\n{report}",
+        config.convergence_timeout
+    );
+    Ok(report)
+}
+
+struct Localnet {
+    // `None` once lost
+    nodes: Vec<Option<Node>>,
+    addresses: Vec<String>,
+    // the nodes on one side, while partitioned
+    partition: Option<HashSet<usize>>,
+    blocks_mined: u64,
+    lost: Vec<usize>,
+}
+
+impl Localnet {
+    async fn start(config: &SoakConfig) -> Result<Localnet> {
+        let mut nodes = vec![];
+        let mut addresses = vec![];
+        for index in 0..config.nodes {
+            let file = config
+                .dir
+                .join(format!("node-{}-{index}.cbor", config.seed));
+            let file = file.to_string_lossy().into_owned();
+            // left over from an earlier run with the same seed
+            for path in [file.clone(), wal_path(&file)] {
+                let _ = std::fs::remove_file(path);
+            }
+            let mut node_config = NodeConfig::new(file);
+            node_config.port = 0;
+            let mut node = Node::new(node_config);
+            let addr = node.start().await?;
+            addresses.push(format!("127.0.0.1:{}", addr.port()));
+            nodes.push(Some(node));
+        }
+        let mut net = Localnet {
+            nodes,
+            addresses,
+            partition: None,
+            blocks_mined: 0,
+            lost: vec![],
+        };
+        net.wire().await;
+        Ok(net)
+    }
+
+    fn running(&self) -> Vec<usize> {
+        (0..self.nodes.len())
+            .filter(|index| self.nodes[*index].is_some())
+            .collect()
+    }
+
+    fn same_side(&self, a: usize, b: usize) -> bool {
+        self.partition
+            .as_ref()
+            .is_none_or(|side| side.contains(&a) == side.contains(&b))
+    }
+
+    // connects every running node to every other on its side, and drops
+    // its connections across the partition
+    async fn wire(&mut self) {
+        for from in self.running() {
+            let state = self.nodes[from].as_ref().unwrap().state().clone();
+            for to in 0..self.nodes.len() {
+                let address = &self.addresses[to];
+                if to == from {
+                    continue;
+                }
+                if !self.same_side(from, to) || self.nodes[to].is_none() {
+                    state.nodes.remove(address);
+                } else if !state.nodes.contains_key(address) {
+                    match NodeClient::connect(address.as_str()).await {
+                        Ok(client) => {
+                            state.nodes.insert(address.clone(), client);
+                        }
+                        Err(e) => warn!("soak: node {from} can't reach node {to}: {e}"),
+                    }
+                }
+            }
+        }
+    }
+
+    fn chaos_event(&self, rng: &mut StdRng) -> ChaosEvent {
+        let running = self.running();
+        let node = running[rng.random_range(0..running.len())];
+        match rng.random_range(0..3) {
+            0 => ChaosEvent::Restart(node),
+            1 if self.partition.is_some() => ChaosEvent::Heal,
+            1 => {
+                // a proper, non-empty subset of the nodes
+                let size = rng.random_range(1..self.nodes.len());
+                let mut side: Vec<usize> = (0..self.nodes.len()).collect();
+                for index in 0..size {
+                    let other = rng.random_range(index..side.len());
+                    side.swap(index, other);
+                }
+                side.truncate(size);
+                side.sort_unstable();
+                ChaosEvent::Partition(side)
+            }
+            _ => ChaosEvent::InvalidBlock(node),
+        }
+    }
+
+    async fn inject(&mut self, event: &ChaosEvent, key: &PrivateKey) {
+        match event {
+            ChaosEvent::Restart(index) => self.restart(*index).await,
+            ChaosEvent::Partition(side) => {
+                self.partition = Some(side.iter().copied().collect());
+                self.wire().await;
+            }
+            ChaosEvent::Heal => {
+                self.partition = None;
+                self.wire().await;
+            }
+            ChaosEvent::InvalidBlock(index) => {
+                let Some(node) = &self.nodes[*index] else {
+                    return;
+                };
+                let block = {
+                    let blockchain = node.state().blockchain.read().await;
+                    overpaying_block(&block_template(
+                        &blockchain,
+                        key.public_key(),
+                        None,
+                        Utc::now(),
+                    ))
+                };
+                let address = self.addresses[*index].as_str();
+                let announced = match NodeClient::connect(address).await {
+                    Ok(mut client) => client.announce_block(block).await.map_err(Into::into),
+                    Err(e) => Err(anyhow::Error::from(e)),
+                };
+                if let Err(e) = announced {
+                    warn!("soak: failed to announce an invalid block to node {index}: {e}");
+                }
+            }
+        }
+    }
+
+    async fn restart(&mut self, index: usize) {
+        let Some(mut node) = self.nodes[index].take() else {
+            return;
+        };
+        if let Err(e) = node.stop().await {
+            warn!("soak: node {index} failed to stop: {e}");
+        }
+        // the others' connections to it died with it
+        for other in self.nodes.iter().flatten() {
+            other.state().nodes.remove(&self.addresses[index]);
+        }
+        let mut config = node.config().clone();
+        config.port = node_port(&self.addresses[index]);
+        drop(node);
+        let mut node = Node::new(config);
+        match node.start().await {
+            Ok(_) => self.nodes[index] = Some(node),
+            Err(e) => {
+                warn!("soak: node {index} failed to start again: {e:#}");
+                self.lost.push(index);
+            }
+        }
+        self.wire().await;
+    }
+
+    // mines the next block of a random running node and relays it
+    async fn mine(&mut self, rng: &mut StdRng, key: &PrivateKey) {
+        let running = self.running();
+        if running.is_empty() {
+            return;
+        }
+        let index = running[rng.random_range(0..running.len())];
+        let state = self.nodes[index].as_ref().unwrap().state().clone();
+        let mut block = {
+            let blockchain = state.blockchain.read().await;
+            block_template(&blockchain, key.public_key(), None, Utc::now())
+        };
+        block.mine(usize::MAX);
+        match connect_new_block(&state, block, Utc::now()).await {
+            Ok(block) => {
+                self.blocks_mined += 1;
+                relay_block(&state, &block).await;
+            }
+            // a block relayed meanwhile made the template stale
+            Err(e) => info!("soak: node {index} refused its own block: {e}"),
+        }
+    }
+
+    async fn report(&self, events: Vec<ChaosEvent>) -> SoakReport {
+        let mut end_state = vec![];
+        for index in self.running() {
+            let blockchain = self.nodes[index]
+                .as_ref()
+                .unwrap()
+                .state()
+                .blockchain
+                .read()
+                .await;
+            end_state.push((index, blockchain.tip(), blockchain.utxo_set_hash()));
+        }
+        SoakReport {
+            events,
+            blocks_mined: self.blocks_mined,
+            lost: self.lost.clone(),
+            end_state,
+        }
+    }
+
+    async fn stop(&mut self) {
+        for (index, node) in self.nodes.iter_mut().enumerate() {
+            if let Some(node) = node
+                && let Err(e) = node.stop().await
+            {
+                warn!("soak: node {index} failed to stop: {e}");
+            }
+        }
+    }
+}
+
+fn node_port(address: &str) -> u16 {
+    address
+        .rsplit(':')
+        .next()
+        .and_then(|port| port.parse().ok())
+        .expect("BUG: localnet addresses end in a port")
+}
+
+// `template` with a coinbase claiming twice what it pays, mined
+fn overpaying_block(template: &Block) -> Block {
+    let coinbase = &template.transactions()[0].outputs()[0];
+    let mut transactions = template.transactions().clone();
+    transactions[0] = Transaction::new(
+        vec![],
+        vec![TransactionOutput::new(
+            coinbase.value() * 2,
+            Uuid::new_v4(),
+            coinbase.pubkey().clone(),
+        )],
+    );
+    let header = template.header();
+    let mut block = Block::new(
+        BlockHeader::new(
+            header.timestamp(),
+            0,
+            *header.prev_block_hash(),
+            MerkleRoot::calculate(&transactions),
+            header.target(),
+        ),
+        transactions,
+    );
+    block.mine(usize::MAX);
+    block
+}
@@ -1,80 +1,1741 @@
-#[cfg(test)]
-mod tests {
-    use super::super::*;
-
-    #[test]
-    fn test_cli_default_port() {
-        use clap::Parser;
-        let cli = Cli::parse_from(&[
-            "node",
-            "--blockchain-file",
-            "test.cbor",
-        ]);
-        assert_eq!(cli.port(), 9000);
+use btclib::testutil::{extend_chain, fixture_key, genesis_block, spend_transaction};
+
+use super::*;
+
+#[test]
+fn test_cli_default_port() {
+    use clap::Parser;
+    let cli = Cli::parse_from(["node", "--blockchain-file", "test.cbor"]);
+    assert_eq!(cli.port(), 9000);
+}
+
+#[test]
+fn test_cli_custom_port() {
+    use clap::Parser;
+    let cli = Cli::parse_from(["node", "--blockchain-file", "test.cbor", "--port", "8080"]);
+    assert_eq!(cli.port(), 8080);
+}
+
+#[test]
+fn test_cli_blockchain_file() {
+    use clap::Parser;
+    let cli = Cli::parse_from(["node", "--blockchain-file", "my_blockchain.cbor"]);
+    assert_eq!(cli.blockchain_file(), Some("my_blockchain.cbor"));
+}
+
+#[test]
+fn test_cli_nodes_empty() {
+    use clap::Parser;
+    let cli = Cli::parse_from(["node", "--blockchain-file", "test.cbor"]);
+    assert!(cli.nodes().is_empty());
+}
+
+#[test]
+fn test_cli_nodes_single() {
+    use clap::Parser;
+    let cli = Cli::parse_from([
+        "node",
+        "--blockchain-file",
+        "test.cbor",
+        "--nodes",
+        "localhost:9001",
+    ]);
+    assert_eq!(cli.nodes().len(), 1);
+    assert_eq!(cli.nodes()[0], "localhost:9001");
+}
+
+#[test]
+fn test_cli_nodes_multiple() {
+    use clap::Parser;
+    let cli = Cli::parse_from([
+        "node",
+        "--blockchain-file",
+        "test.cbor",
+        "--nodes",
+        "localhost:9001,localhost:9002,localhost:9003",
+    ]);
+    assert_eq!(cli.nodes().len(), 3);
+    assert_eq!(cli.nodes()[0], "localhost:9001");
+    assert_eq!(cli.nodes()[1], "localhost:9002");
+    assert_eq!(cli.nodes()[2], "localhost:9003");
+}
+
+#[test]
+fn test_wal_ignores_torn_record() {
+    use std::io::Write;
+    let blockchain_file = std::env::temp_dir()
+        .join(format!("wal-torn-{}.cbor", uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .into_owned();
+    let path = wal_path(&blockchain_file);
+    let block = genesis_block();
+
+    let mut wal = ChainWal::open(&blockchain_file).unwrap();
+    wal.append(&block).unwrap();
+    // a crash in the middle of the next append
+    std::fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .unwrap()
+        .write_all(&[0, 0, 0, 0, 0, 0, 1, 0, 42])
+        .unwrap();
+
+    let blocks = ChainWal::read_blocks(&path, btclib::MAX_BLOCK_SIZE).unwrap();
+    assert_eq!(blocks.len(), 1);
+    assert_eq!(blocks[0].hash(), block.hash());
+
+    let usage = wal.disk_usage().unwrap();
+    assert_eq!(usage.blockchain, 0);
+    assert_eq!(
+        usage.write_ahead_log,
+        std::fs::metadata(&path).unwrap().len()
+    );
+    std::fs::remove_file(&path).ok();
+}
+
+#[test]
+fn test_wal_record_size_follows_max_block_size() {
+    use btclib::{
+        testutil::TestBlock,
+        types::{Transaction, TransactionOutput},
+    };
+    let blockchain_file = std::env::temp_dir()
+        .join(format!("wal-large-{}.cbor", uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .into_owned();
+    let path = wal_path(&blockchain_file);
+    let outputs = (0..1_000)
+        .map(|_| TransactionOutput::new(1, uuid::Uuid::new_v4(), fixture_key().public_key()))
+        .collect();
+    let block = TestBlock::with_transactions(vec![Transaction::new(vec![], outputs)]).build();
+    let max_block_size = block.transactions_size();
+    assert!(ChainWal::max_record_size(max_block_size / 2) < max_block_size);
+
+    ChainWal::open(&blockchain_file)
+        .unwrap()
+        .append(&block)
+        .unwrap();
+    let blocks = ChainWal::read_blocks(&path, max_block_size).unwrap();
+    assert_eq!(blocks.len(), 1);
+    assert_eq!(blocks[0].hash(), block.hash());
+    // too large for a node taking half as large blocks
+    assert!(
+        ChainWal::read_blocks(&path, max_block_size / 2)
+            .unwrap()
+            .is_empty()
+    );
+    std::fs::remove_file(&path).ok();
+}
+
+#[test]
+fn test_wal_replay_skips_saved_blocks() {
+    use btclib::types::{BlockHeight, Blockchain};
+    let blockchain_file = std::env::temp_dir()
+        .join(format!("wal-replay-{}.cbor", uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .into_owned();
+    let block = genesis_block();
+    ChainWal::open(&blockchain_file)
+        .unwrap()
+        .append(&block)
+        .unwrap();
+
+    let mut blockchain = Blockchain::default();
+    assert_eq!(replay_wal(&mut blockchain, &blockchain_file).unwrap(), 1);
+    assert_eq!(blockchain.block_height(), BlockHeight::new(1));
+    // the block is already there the second time around
+    assert_eq!(replay_wal(&mut blockchain, &blockchain_file).unwrap(), 0);
+    std::fs::remove_file(wal_path(&blockchain_file)).ok();
+}
+
+#[test]
+fn test_wal_replay_spends_earlier_wal_blocks() {
+    use btclib::types::{BlockBuilder, BlockHeight, Blockchain};
+    let blockchain_file = std::env::temp_dir()
+        .join(format!("wal-spend-{}.cbor", uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .into_owned();
+    let key = fixture_key();
+    let mut chain = Blockchain::default();
+    chain
+        .add_block(BlockBuilder::on(&chain).build(key.public_key()))
+        .unwrap();
+    chain.rebuild_utxos();
+    let (_, reward) = chain.utxos().into_iter().next().unwrap();
+    let mut builder = BlockBuilder::on(&chain);
+    assert!(builder.add_transaction(spend_transaction(&reward, &key, 0), 0));
+    chain.add_block(builder.build(key.public_key())).unwrap();
+    let mut wal = ChainWal::open(&blockchain_file).unwrap();
+    for block in chain.blocks() {
+        wal.append(block).unwrap();
     }
 
-    #[test]
-    fn test_cli_custom_port() {
-        use clap::Parser;
-        let cli = Cli::parse_from(&[
-            "node",
-            "--blockchain-file",
-            "test.cbor",
-            "--port",
-            "8080",
-        ]);
-        assert_eq!(cli.port(), 8080);
+    // the second block spends the coinbase of the first
+    let mut blockchain = Blockchain::default();
+    assert_eq!(replay_wal(&mut blockchain, &blockchain_file).unwrap(), 2);
+    assert_eq!(blockchain.block_height(), BlockHeight::new(2));
+    assert!(blockchain.utxo(&reward.hash()).is_none());
+    std::fs::remove_file(wal_path(&blockchain_file)).ok();
+}
+
+#[test]
+fn test_auth_tokens_roles() {
+    use btclib::network::Role;
+    let tokens = AuthTokens::new("reader".to_string(), "operator".to_string());
+
+    assert_eq!(tokens.role_for("reader"), Some(Role::ReadOnly));
+    assert_eq!(tokens.role_for("operator"), Some(Role::Admin));
+    assert_eq!(tokens.role_for("readers"), None);
+    assert_eq!(tokens.role_for(""), None);
+}
+
+#[test]
+fn test_auth_tokens_persist() {
+    let blockchain_file = std::env::temp_dir()
+        .join(format!("auth-{}.cbor", uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .into_owned();
+
+    let generated = AuthTokens::load_or_generate(&blockchain_file).unwrap();
+    let admin = std::fs::read_to_string(token_path(&blockchain_file, btclib::network::Role::Admin))
+        .unwrap();
+    let loaded = AuthTokens::load_or_generate(&blockchain_file).unwrap();
+
+    assert_eq!(generated.role_for(&admin), loaded.role_for(&admin));
+    assert_eq!(loaded.role_for(&admin), Some(btclib::network::Role::Admin));
+    for role in [
+        btclib::network::Role::ReadOnly,
+        btclib::network::Role::Admin,
+    ] {
+        std::fs::remove_file(token_path(&blockchain_file, role)).ok();
+    }
+}
+
+#[test]
+fn test_capture_roundtrip() {
+    use btclib::{network::Message, types::BlockHeight};
+    let path = std::env::temp_dir().join(format!("capture-{}.cap", uuid::Uuid::new_v4()));
+
+    let mut capture = ProtoCapture::create(&path).unwrap();
+    for (connection, message) in [
+        (0, Message::DiscoverNodes),
+        (1, Message::FetchBlock(BlockHeight::new(3))),
+    ] {
+        capture
+            .record(&CapturedMessage {
+                timestamp: chrono::Utc::now(),
+                connection,
+                peer: "127.0.0.1:9001".to_string(),
+                message,
+            })
+            .unwrap();
     }
 
-    #[test]
-    fn test_cli_blockchain_file() {
-        use clap::Parser;
-        let cli = Cli::parse_from(&[
-            "node",
-            "--blockchain-file",
-            "my_blockchain.cbor",
-        ]);
-        assert_eq!(cli.blockchain_file(), "my_blockchain.cbor");
+    let captured = ProtoCapture::read_all(&path).unwrap();
+    assert_eq!(captured.len(), 2);
+    assert_eq!(captured[0].connection, 0);
+    assert!(matches!(captured[1].message, Message::FetchBlock(h) if h == BlockHeight::new(3)));
+    std::fs::remove_file(&path).ok();
+}
+
+#[test]
+fn test_health_tracker() {
+    use chrono::{Duration, Utc};
+    let mut tracker = HealthTracker::default();
+    let now = Utc::now();
+    assert_eq!(tracker.orphan_rate(), 0.0);
+
+    tracker.record_transaction(now - Duration::minutes(30));
+    for _ in 0..20 {
+        tracker.record_transaction(now);
     }
+    tracker.record_block(false);
+    tracker.record_block(false);
+    tracker.record_block(false);
+    tracker.record_block(true);
 
-    #[test]
-    fn test_cli_nodes_empty() {
-        use clap::Parser;
-        let cli = Cli::parse_from(&[
-            "node",
-            "--blockchain-file",
-            "test.cbor",
-        ]);
-        assert!(cli.nodes().is_empty());
+    // the transaction from half an hour ago is outside the window
+    assert_eq!(tracker.mempool_inflow_per_minute(now), 2.0);
+    assert_eq!(tracker.orphan_rate(), 0.25);
+}
+
+#[test]
+fn test_free_tx_quota() {
+    use chrono::{Duration, Utc};
+    use std::net::{IpAddr, Ipv4Addr};
+    let mut quota = FreeTxQuota::default();
+    let now = Utc::now();
+    let peer = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    let other = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+
+    assert!(quota.try_use(peer, 2, now - Duration::minutes(90)));
+    assert!(quota.try_use(peer, 2, now - Duration::minutes(30)));
+    // the first one is more than an hour old, so it no longer counts
+    assert!(quota.try_use(peer, 2, now));
+    assert!(!quota.try_use(peer, 2, now));
+    assert!(quota.try_use(other, 2, now));
+}
+
+#[tokio::test]
+async fn test_unconfirmed_limits() {
+    use btclib::network::{PolicyViolation, RelayPolicy};
+    use std::net::{IpAddr, Ipv4Addr};
+
+    let state = crate::NodeState::default();
+    let peer = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    let other = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+    let key = fixture_key();
+    let block = genesis_block();
+    let parent = spend_transaction(&block.transactions()[0].outputs()[0], &key, 1_000_000);
+    let child = spend_transaction(&parent.outputs()[0], &key, 1_000_000);
+    let mut blockchain = state.blockchain.write().await;
+    blockchain.add_block(block).unwrap();
+    blockchain.rebuild_utxos();
+    blockchain
+        .add_transaction_to_mempool(parent.clone())
+        .unwrap();
+    record_mempool_origin(&state, peer, &parent);
+
+    init_policy(
+        &state,
+        RelayPolicy {
+            max_unconfirmed_per_peer: 1,
+            ..RelayPolicy::default()
+        },
+    );
+    assert_eq!(
+        check_relay_policy(&state, peer, &child, &blockchain),
+        Err(PolicyViolation::TooManyFromPeer { max: 1 })
+    );
+    assert_eq!(
+        check_relay_policy(&state, other, &child, &blockchain),
+        Ok(())
+    );
+
+    // the child spends an output of the same key as its parent
+    init_policy(
+        &state,
+        RelayPolicy {
+            max_unconfirmed_per_key: 1,
+            ..RelayPolicy::default()
+        },
+    );
+    assert_eq!(
+        check_relay_policy(&state, other, &child, &blockchain),
+        Err(PolicyViolation::TooManyFromKey { max: 1 })
+    );
+
+    // a non-standard shape is refused before anything else
+    init_policy(
+        &state,
+        RelayPolicy {
+            max_unconfirmed_per_key: 1,
+            max_tx_inputs: 0,
+            ..RelayPolicy::default()
+        },
+    );
+    assert_eq!(
+        check_relay_policy(&state, other, &child, &blockchain),
+        Err(PolicyViolation::TooManyInputs { count: 1, max: 0 })
+    );
+}
+
+#[test]
+fn test_cli_relay_policy() {
+    use clap::Parser;
+    let cli = Cli::parse_from([
+        "node",
+        "--blockchain-file",
+        "test.cbor",
+        "--min-fee-rate",
+        "5",
+        "--mempool-expiry",
+        "3600",
+        "--max-unconfirmed-per-key",
+        "4",
+        "--max-tx-inputs",
+        "50",
+        "--max-data-outputs",
+        "0",
+    ]);
+    let policy = cli.relay_policy();
+    assert_eq!(policy.min_fee_rate, 5);
+    assert_eq!(policy.max_tx_inputs, 50);
+    assert_eq!(policy.max_data_outputs, 0);
+    assert_eq!(
+        policy.max_tx_outputs,
+        btclib::network::RelayPolicy::default().max_tx_outputs
+    );
+    assert_eq!(policy.mempool_expiry_secs, 3600);
+    assert_eq!(policy.max_unconfirmed_per_key, 4);
+    assert_eq!(
+        policy.dust_threshold,
+        btclib::network::RelayPolicy::default().dust_threshold
+    );
+}
+
+#[test]
+fn test_cli_network_limits() {
+    use clap::Parser;
+    let cli = Cli::parse_from([
+        "node",
+        "--blockchain-file",
+        "test.cbor",
+        "--max-connections",
+        "8",
+        "--message-timeout",
+        "5",
+        "--max-wallets",
+        "3",
+    ]);
+    let limits = cli.network_limits();
+    assert_eq!(limits.max_connections, 8);
+    assert_eq!(limits.message_timeout_secs, 5);
+    assert_eq!(limits.max_wallets, 3);
+    let default = btclib::network::NetworkLimits::default();
+    assert_eq!(limits.max_message_size, default.max_message_size);
+    assert_eq!(limits.cleanup_interval_secs, default.cleanup_interval_secs);
+}
+
+#[test]
+fn test_init_limits() {
+    use btclib::network::{NetworkLimits, RelayPolicy};
+
+    let state = crate::NodeState::default();
+    let limits = NetworkLimits {
+        max_message_size: 256 * 1024,
+        ..NetworkLimits::default()
+    };
+    init_limits(&state, limits, &RelayPolicy::default()).unwrap();
+    assert_eq!(network_info(&state).limits, limits);
+    assert_eq!(network_info(&state).connections, 0);
+
+    // a transaction the policy relays must fit in a message
+    let policy = RelayPolicy {
+        max_tx_size: 512 * 1024,
+        ..RelayPolicy::default()
+    };
+    assert!(init_limits(&state, limits, &policy).is_err());
+    let no_connections = NetworkLimits {
+        max_connections: 0,
+        ..limits
+    };
+    assert!(init_limits(&state, no_connections, &RelayPolicy::default()).is_err());
+    // a rejected configuration leaves the limits alone
+    assert_eq!(network_limits(&state), limits);
+}
+
+#[test]
+fn test_open_session() {
+    use btclib::network::{NetworkLimits, PeerKind, RelayPolicy};
+
+    let state = crate::NodeState::default();
+    let limits = NetworkLimits {
+        max_wallets: 1,
+        max_idle_secs: 30,
+        ..NetworkLimits::default()
+    };
+    init_limits(&state, limits, &RelayPolicy::default()).unwrap();
+
+    let wallet = open_session(&state, PeerKind::Wallet, 0).unwrap();
+    assert_eq!(wallet.session.idle_timeout_secs, Some(30));
+    assert_eq!(network_info(&state).wallets, 1);
+    // the limit is per kind
+    assert!(open_session(&state, PeerKind::Wallet, 10).is_none());
+    let miner = open_session(&state, PeerKind::Miner, 10).unwrap();
+    assert_eq!(
+        miner.idle_timeout(),
+        Some(std::time::Duration::from_secs(10))
+    );
+    let node = open_session(&state, PeerKind::FullNode, 0).unwrap();
+    assert_eq!(node.idle_timeout(), None);
+
+    // closing a session frees its slot
+    drop(wallet);
+    assert_eq!(network_info(&state).wallets, 0);
+    assert!(open_session(&state, PeerKind::Wallet, 0).is_some());
+    assert_eq!(network_info(&state).miners, 1);
+}
+
+#[test]
+fn test_chain_observer() {
+    use btclib::types::Transaction;
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    #[derive(Default)]
+    struct Counter {
+        accepted: AtomicUsize,
+        evicted: AtomicUsize,
+    }
+    impl ChainObserver for Counter {
+        fn on_tx_accepted(&self, _transaction: &Transaction) {
+            self.accepted.fetch_add(1, Ordering::SeqCst);
+        }
+        fn on_tx_evicted(&self, _transaction: &Transaction) {
+            self.evicted.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let state = crate::NodeState::default();
+    let counter = Arc::new(Counter::default());
+    register_observer(&state, counter.clone());
+    let transaction = Transaction::new(vec![], vec![]);
+    notify_tx_accepted(&state, &transaction);
+    notify_tx_accepted(&state, &transaction);
+    notify_tx_evicted(&state, &transaction);
+
+    assert_eq!(counter.accepted.load(Ordering::SeqCst), 2);
+    assert_eq!(counter.evicted.load(Ordering::SeqCst), 1);
+    // the built-in observers are fed by the same notifications
+    let mut health = state.health.lock().unwrap();
+    assert_eq!(health.mempool_inflow_per_minute(chrono::Utc::now()), 0.2);
+}
+
+#[tokio::test]
+async fn test_chainstate_snapshot() {
+    use btclib::{
+        crypto::Signature,
+        custom_sha_types::Hash,
+        types::{BlockHeight, Transaction, TransactionInput, TransactionOutput, UtxoState},
+    };
+
+    let state = crate::NodeState::default();
+    let key = fixture_key();
+    let block = genesis_block();
+    let coinbase = block.transactions()[0].clone();
+    let outpoint = coinbase.outputs()[0].hash();
+    {
+        let mut blockchain = state.blockchain.write().await;
+        blockchain.add_block(block).unwrap();
+        blockchain.rebuild_utxos();
+        let spend = Transaction::new(
+            vec![TransactionInput::new(
+                outpoint,
+                Signature::sign_output(&outpoint, &key),
+            )],
+            vec![TransactionOutput::new(
+                4000000000,
+                uuid::Uuid::new_v4(),
+                key.public_key(),
+            )],
+        );
+        blockchain.add_transaction_to_mempool(spend).unwrap();
+    }
+
+    let snapshot = ChainstateSnapshot::take(&state).await;
+    let (height, _, transaction) = snapshot.transaction(&coinbase.hash()).unwrap();
+    assert_eq!(height, BlockHeight::GENESIS);
+    assert_eq!(transaction.hash(), coinbase.hash());
+    assert!(snapshot.transaction(&Hash::zero()).is_none());
+    let spend_hash = snapshot.mempool()[0].1.hash();
+    assert!(snapshot.mempool_transaction(&spend_hash).is_some());
+    // the genesis output is spent by the mempool transaction
+    let utxos = snapshot.utxos_for(&key.public_key());
+    assert_eq!(utxos.len(), 1);
+    assert_eq!(utxos[0].0, outpoint);
+    assert_eq!(
+        utxos[0].1.state(),
+        UtxoState::SpentInMempool(snapshot.mempool()[0].1.hash())
+    );
+
+    // nothing changes the chainstate while a snapshot is held
+    assert!(state.blockchain.try_write().is_err());
+    drop(snapshot);
+    assert!(state.blockchain.try_write().is_ok());
+}
+
+#[tokio::test]
+async fn test_search() {
+    use btclib::{
+        crypto::{PrivateKey, Signature},
+        custom_sha_types::Hash,
+        types::{BlockHeight, Transaction, TransactionInput, TransactionOutput},
+    };
+
+    let state = crate::NodeState::default();
+    let key = fixture_key();
+    let block = genesis_block();
+    let block_hash = block.hash();
+    let coinbase = block.transactions()[0].clone();
+    let outpoint = coinbase.outputs()[0].hash();
+    let spend = Transaction::new(
+        vec![TransactionInput::new(
+            outpoint,
+            Signature::sign_output(&outpoint, &key),
+        )],
+        vec![TransactionOutput::new(
+            4000000000,
+            uuid::Uuid::new_v4(),
+            key.public_key(),
+        )],
+    );
+    {
+        let mut blockchain = state.blockchain.write().await;
+        blockchain.add_block(block).unwrap();
+        blockchain.rebuild_utxos();
+        blockchain
+            .add_transaction_to_mempool(spend.clone())
+            .unwrap();
+    }
+    let snapshot = ChainstateSnapshot::take(&state).await;
+    let hex = |hash: Hash| hex::encode(hash.as_bytes());
+
+    assert_eq!(
+        search(&snapshot, "0"),
+        Some(SearchResult::Block {
+            height: BlockHeight::GENESIS,
+            hash: block_hash
+        })
+    );
+    assert_eq!(search(&snapshot, "1"), None);
+    assert_eq!(
+        search(&snapshot, &hex(block_hash)),
+        Some(SearchResult::Block {
+            height: BlockHeight::GENESIS,
+            hash: block_hash
+        })
+    );
+    assert_eq!(
+        search(&snapshot, &format!(" {} ", hex(coinbase.hash()))),
+        Some(SearchResult::Transaction {
+            hash: coinbase.hash(),
+            height: Some(BlockHeight::GENESIS)
+        })
+    );
+    assert_eq!(
+        search(&snapshot, &hex(spend.hash())),
+        Some(SearchResult::Transaction {
+            hash: spend.hash(),
+            height: None
+        })
+    );
+    assert_eq!(search(&snapshot, &hex(Hash::zero())), None);
+
+    let found = Some(SearchResult::Address {
+        address: key.public_key().address(),
+        key: Some(key.public_key()),
+        balance: coinbase.outputs()[0].value(),
+        outputs: 1,
+    });
+    assert_eq!(search(&snapshot, &key.public_key().to_hex()), found);
+    assert_eq!(
+        search(&snapshot, &key.public_key().address().to_string()),
+        found
+    );
+    // an address nothing pays is still an address, just an empty one
+    let stranger = PrivateKey::from_seed(b"stranger").public_key().address();
+    assert_eq!(
+        search(&snapshot, &stranger.to_string()),
+        Some(SearchResult::Address {
+            address: stranger,
+            key: None,
+            balance: 0,
+            outputs: 0,
+        })
+    );
+    assert_eq!(search(&snapshot, "not a thing"), None);
+}
+
+#[test]
+fn test_cli_init() {
+    use clap::Parser;
+    let cli = Cli::parse_from(["node", "init", "/var/lib/ledger", "--http-port", "8080"]);
+    assert_eq!(
+        cli.init_options(),
+        Some(InitOptions {
+            data_dir: "/var/lib/ledger".into(),
+            port: 9000,
+            http_port: Some(8080),
+            force: false,
+        })
+    );
+    // init needs no blockchain file, and takes none of the node's options
+    assert!(Cli::try_parse_from(["node", "--port", "9001", "init", "data"]).is_err());
+    let cli = Cli::parse_from(["node", "--blockchain-file", "test.cbor"]);
+    assert_eq!(cli.init_options(), None);
+}
+
+#[test]
+fn test_init_data_dir() {
+    let data_dir = std::env::temp_dir().join(format!("init-{}", uuid::Uuid::new_v4()));
+    let options = InitOptions {
+        data_dir: data_dir.clone(),
+        port: 9100,
+        http_port: None,
+        force: false,
+    };
+    let config_file = init_data_dir(&options).unwrap();
+    assert_eq!(config_file, data_dir.join("node.toml"));
+    let config = ChainsConfig::load(config_file.to_str().unwrap()).unwrap();
+    let configs = config.node_configs().unwrap();
+    assert_eq!(configs.len(), 1);
+    let (name, node_config) = &configs[0];
+    assert_eq!(name, "main");
+    assert_eq!(node_config.port, 9100);
+    assert_eq!(node_config.http, None);
+    assert_eq!(
+        std::path::PathBuf::from(&node_config.blockchain_file),
+        data_dir.join("main").join("blockchain.cbor")
+    );
+    assert_eq!(config.log.file, Some(data_dir.join("node.log")));
+    let admin_token = token_path(&node_config.blockchain_file, btclib::network::Role::Admin);
+    let token = std::fs::read_to_string(&admin_token).unwrap();
+
+    // a second init leaves the configuration alone unless forced, and
+    // never replaces the tokens
+    assert!(init_data_dir(&options).is_err());
+    init_data_dir(&InitOptions {
+        http_port: Some(8080),
+        force: true,
+        ..options
+    })
+    .unwrap();
+    let config = ChainsConfig::load(config_file.to_str().unwrap()).unwrap();
+    assert_eq!(config.chains["main"].http_port, Some(8080));
+    assert_eq!(std::fs::read_to_string(&admin_token).unwrap(), token);
+    std::fs::remove_dir_all(&data_dir).unwrap();
+}
+
+#[test]
+fn test_chains_config() {
+    let data_dir = std::env::temp_dir().join(format!("chains-{}", uuid::Uuid::new_v4()));
+    let config: ChainsConfig = format!(
+        r#"
+        [chains.main]
+        data_dir = "{main}"
+        port = 9000
+
+        [chains.test]
+        data_dir = "{test}"
+        port = 19000
+        nodes = ["localhost:19001"]
+        min_fee_rate = 0
+        max_connections = 8
+        "#,
+        main = data_dir.join("main").display(),
+        test = data_dir.join("test").display(),
+    )
+    .parse()
+    .unwrap();
+
+    let nodes = config.node_configs().unwrap();
+    assert_eq!(nodes.len(), 2);
+    let (name, test) = &nodes[1];
+    assert_eq!(name, "test");
+    assert_eq!(test.port, 19000);
+    assert_eq!(test.nodes, vec!["localhost:19001".to_string()]);
+    assert_eq!(test.relay_policy.min_fee_rate, 0);
+    assert_eq!(test.limits.max_connections, 8);
+    assert!(
+        test.blockchain_file
+            .starts_with(&*data_dir.join("test").to_string_lossy())
+    );
+    assert!(data_dir.join("main").is_dir());
+    assert_eq!(
+        nodes[0].1.relay_policy,
+        btclib::network::RelayPolicy::default()
+    );
+    std::fs::remove_dir_all(&data_dir).ok();
+}
+
+#[test]
+fn test_memory_budget() {
+    use btclib::{network::MemoryInfo, types::Blockchain};
+    use clap::Parser;
+
+    let state = crate::NodeState::default();
+    let blockchain = Blockchain::default();
+    assert_eq!(memory_info(&state, &blockchain).budget, None);
+    init_memory_budget(&state, Some(megabytes(64)));
+    assert_eq!(
+        memory_info(&state, &blockchain).budget,
+        Some(64 * 1024 * 1024)
+    );
+
+    let info = |total| MemoryInfo {
+        utxo_bytes: total,
+        budget: Some(1000),
+        ..MemoryInfo::default()
+    };
+    assert_eq!(MemoryLevel::of(&info(500)), MemoryLevel::Normal);
+    assert_eq!(MemoryLevel::of(&info(800)), MemoryLevel::Approaching);
+    assert_eq!(MemoryLevel::of(&info(1001)), MemoryLevel::Exceeded);
+    let unbudgeted = MemoryInfo {
+        budget: None,
+        ..info(5000)
+    };
+    assert_eq!(MemoryLevel::of(&unbudgeted), MemoryLevel::Normal);
+
+    let cli = Cli::parse_from([
+        "node",
+        "--blockchain-file",
+        "chain.cbor",
+        "--memory-budget",
+        "512",
+    ]);
+    let (_, config) = cli.node_configs().unwrap().remove(0);
+    assert_eq!(config.memory_budget, Some(512 * 1024 * 1024));
+    assert!(Cli::try_parse_from(["node", "--seed-only", "--memory-budget", "512"]).is_err());
+}
+
+#[test]
+fn test_chains_config_rejects_shared_port() {
+    let config: ChainsConfig = r#"
+        [chains.a]
+        data_dir = "a"
+        port = 9000
+
+        [chains.b]
+        data_dir = "b"
+        port = 9000
+        "#
+    .parse()
+    .unwrap();
+    assert!(config.node_configs().is_err());
+    assert!("[chains.a]\nport = 9000\n".parse::<ChainsConfig>().is_err());
+}
+
+#[test]
+fn test_log_config() {
+    use btclib::utils::LogConfig;
+    use clap::Parser;
+
+    let cli = Cli::parse_from(["node", "--blockchain-file", "chain.cbor"]);
+    assert_eq!(cli.log_config().unwrap(), LogConfig::default());
+
+    let path = std::env::temp_dir().join(format!("chains-{}.toml", uuid::Uuid::new_v4()));
+    std::fs::write(
+        &path,
+        r#"
+        [chains.main]
+        data_dir = "main"
+        port = 9000
+
+        [log]
+        file = "node.log"
+        filter = "info,node::handler=debug"
+        max_files = 2
+        "#,
+    )
+    .unwrap();
+    let config = path.to_string_lossy();
+    let cli = Cli::parse_from(["node", "--config", &config, "--log-max-size", "1"]);
+    let log = cli.log_config().unwrap();
+    assert_eq!(log.file, Some("node.log".into()));
+    assert_eq!(log.filters.as_deref(), Some("info,node::handler=debug"));
+    assert_eq!(log.max_size, 1024 * 1024);
+    assert_eq!(log.max_files, 2);
+
+    // the command line wins over the file
+    let cli = Cli::parse_from(["node", "--config", &config, "--log-filter", "warn"]);
+    assert_eq!(cli.log_config().unwrap().filters.as_deref(), Some("warn"));
+    std::fs::remove_file(&path).ok();
+}
+
+#[test]
+fn test_cli_config() {
+    use clap::Parser;
+    let cli = Cli::parse_from(["node", "--config", "chains.toml"]);
+    assert_eq!(cli.config(), Some("chains.toml"));
+    assert!(Cli::try_parse_from(["node"]).is_err());
+    assert!(Cli::try_parse_from(["node", "--config", "chains.toml", "--port", "9001"]).is_err());
+}
+
+#[test]
+fn test_known_inventory() {
+    use btclib::custom_sha_types::Hash;
+    use chrono::{Duration, Utc};
+    let now = Utc::now();
+    let mut known = KnownInventory::default();
+    let hash = Hash::hash(&1u32);
+    assert!(!known.knows("a:9000", &hash));
+    assert!(known.insert("a:9000", hash, now));
+    assert!(!known.insert("a:9000", hash, now));
+    assert!(known.knows("a:9000", &hash));
+    assert!(!known.knows("b:9000", &hash));
+
+    // only the most recent hashes are remembered
+    for i in 2..10_002u32 {
+        known.insert("a:9000", Hash::hash(&i), now);
     }
+    assert!(!known.knows("a:9000", &hash));
+    assert!(known.knows("a:9000", &Hash::hash(&10_001u32)));
+
+    known.forget_peer("a:9000");
+    assert!(!known.knows("a:9000", &Hash::hash(&10_001u32)));
+
+    // and only for so long
+    let later = now + Duration::minutes(5);
+    known.insert("a:9000", hash, now);
+    known.insert("b:9000", hash, now);
+    known.insert("b:9000", Hash::hash(&2u32), later);
+    known.expire(now);
+    assert!(!known.knows("a:9000", &hash));
+    assert!(!known.knows("b:9000", &hash));
+    assert!(known.knows("b:9000", &Hash::hash(&2u32)));
+    assert!(!known.insert("b:9000", Hash::hash(&2u32), later));
+    assert!(known.insert("a:9000", hash, later));
+}
+
+#[test]
+fn test_peer_quality_ranking() {
+    use chrono::{Duration, Utc};
+    use std::time::Duration as StdDuration;
+    let now = Utc::now();
+    let mut book = PeerQualityBook::default();
+    let mut peers = vec![
+        "10.0.0.1:9000".to_string(),
+        "10.0.0.2:9000".to_string(),
+        "10.0.0.3:9000".to_string(),
+        "10.0.0.4:9000".to_string(),
+    ];
+
+    // .1 answers slowly, .2 quickly and has been up for an hour
+    book.record_answer(&peers[0], StdDuration::from_secs(2), now);
+    book.record_answer(
+        &peers[1],
+        StdDuration::from_millis(50),
+        now - Duration::hours(1),
+    );
+    book.record_answer(&peers[1], StdDuration::from_millis(150), now);
+    let fast = book.get(&peers[1]).unwrap();
+    assert_eq!(fast.answered, 2);
+    assert!((fast.latency.unwrap() - 0.07).abs() < 1e-9);
+    assert_eq!(fast.connected_since, Some(now - Duration::hours(1)));
+    // .3 times out more often than not
+    book.record_answer(&peers[2], StdDuration::from_millis(10), now);
+    book.record_failure(&peers[2]);
+    book.record_failure(&peers[2]);
+    assert!(book.get(&peers[2]).unwrap().is_flaky());
+    book.rank(&mut peers, now);
+    assert_eq!(
+        peers,
+        [
+            "10.0.0.2:9000",
+            "10.0.0.4:9000",
+            "10.0.0.1:9000",
+            "10.0.0.3:9000"
+        ]
+    );
+
+    // blocks relayed from a host count for the peers there
+    book.record_relayed_block("10.0.0.2".parse().unwrap(), false);
+    book.record_relayed_block("10.0.0.2".parse().unwrap(), false);
+    book.record_blocks("10.0.0.2:9000", 1, true);
+    let relayer = book.get("10.0.0.2:9000").unwrap();
+    assert_eq!((relayer.valid_blocks, relayer.invalid_blocks), (1, 2));
+    assert!(relayer.is_flaky());
+    book.rank(&mut peers, now);
+    assert_eq!(peers[0], "10.0.0.4:9000");
+
+    // uptime starts over on reconnection, the rest is kept
+    book.record_disconnect("10.0.0.1:9000");
+    let slow = book.get("10.0.0.1:9000").unwrap();
+    assert_eq!(slow.connected_since, None);
+    assert_eq!(slow.answered, 1);
+}
+
+#[test]
+fn test_trickle_delay() {
+    let delays: Vec<_> = (0..1000).map(|_| trickle_delay()).collect();
+    assert!(delays.iter().all(|delay| delay.as_millis() <= 8_000));
+    // not all the same, and about two seconds on average
+    assert!(delays.iter().any(|delay| *delay != delays[0]));
+    let average = delays.iter().map(|delay| delay.as_millis()).sum::<u128>() / 1000;
+    assert!((1_000..3_000).contains(&average), "{average}ms");
+}
+
+#[test]
+fn test_address_book() {
+    let mut book = AddressBook::default();
+    assert!(book.insert("127.0.0.1:9000"));
+    assert!(book.insert("127.0.0.1:9001"));
+    assert!(!book.insert("127.0.0.1:9000"));
+    assert_eq!(book.len(), 2);
+    assert!(book.contains("127.0.0.1:9001"));
+    assert_eq!(
+        book.addresses(),
+        vec!["127.0.0.1:9001".to_string(), "127.0.0.1:9000".to_string()]
+    );
+}
+
+#[test]
+fn test_learn_addresses() {
+    let state = crate::NodeState::default();
+    let new = learn_addresses(
+        &state,
+        vec![
+            "10.0.0.1:9000".to_string(),
+            "not an address".to_string(),
+            "0.0.0.0:9000".to_string(),
+            "10.0.0.2:0".to_string(),
+            "10.0.0.1:9000".to_string(),
+        ],
+    );
+    assert_eq!(new, vec!["10.0.0.1:9000".to_string()]);
+    // already known addresses aren't new, so they aren't gossiped again
+    assert!(learn_addresses(&state, vec!["10.0.0.1:9000".to_string()]).is_empty());
+    assert_eq!(known_addresses(&state), vec!["10.0.0.1:9000".to_string()]);
+}
+
+#[test]
+fn test_cli_seed_only() {
+    use clap::Parser;
+    let cli = Cli::parse_from(["node", "--seed-only", "--port", "8333"]);
+    assert!(cli.seed_only());
+    let nodes = cli.node_configs().unwrap();
+    assert!(nodes[0].1.seed_only);
+    assert!(Cli::try_parse_from(["node"]).is_err());
+}
+
+#[test]
+fn test_cli_archive() {
+    use clap::Parser;
+    let cli = Cli::parse_from(["node", "--blockchain-file", "test.cbor", "--archive"]);
+    assert!(cli.archive());
+    assert!(cli.node_configs().unwrap()[0].1.archive);
+    assert!(Cli::try_parse_from(["node", "--seed-only", "--archive"]).is_err());
+}
 
-    #[test]
-    fn test_cli_nodes_single() {
-        use clap::Parser;
-        let cli = Cli::parse_from(&[
+#[tokio::test]
+async fn test_undo_retention() {
+    use btclib::types::BlockHeight;
+    use clap::Parser;
+
+    let state = crate::NodeState::default();
+    extend_chain(&mut *state.blockchain.write().await, 5);
+    // keeps everything unless told otherwise
+    assert_eq!(compact_history(&state).await, 0);
+    init_undo_retention(&state, Some(2));
+    assert_eq!(compact_history(&state).await, 3);
+    assert!(block_undo(&state, BlockHeight::new(2)).await.is_none());
+    assert!(block_undo(&state, BlockHeight::new(4)).await.is_some());
+    extend_chain(&mut *state.blockchain.write().await, 1);
+    assert_eq!(compact_history(&state).await, 1);
+    assert_eq!(
+        state.history.read().await.oldest_undo(),
+        BlockHeight::new(4)
+    );
+
+    let cli = Cli::parse_from([
+        "node",
+        "--blockchain-file",
+        "test.cbor",
+        "--archive",
+        "--undo-retention",
+        "1000",
+    ]);
+    assert_eq!(cli.node_configs().unwrap()[0].1.undo_retention, Some(1000));
+    assert!(
+        Cli::try_parse_from([
             "node",
             "--blockchain-file",
             "test.cbor",
-            "--nodes",
-            "localhost:9001",
-        ]);
-        assert_eq!(cli.nodes().len(), 1);
-        assert_eq!(cli.nodes()[0], "localhost:9001");
+            "--undo-retention",
+            "1000"
+        ])
+        .is_err()
+    );
+}
+
+#[test]
+fn test_watchtower_config() {
+    use clap::Parser;
+    let cli = Cli::parse_from(["node", "--blockchain-file", "test.cbor", "--watchtower"]);
+    assert!(cli.watchtower());
+    assert!(cli.node_configs().unwrap()[0].1.watchtower);
+    assert!(Cli::try_parse_from(["node", "--seed-only", "--watchtower"]).is_err());
+
+    let config: ChainsConfig = r#"
+        [chains.main]
+        data_dir = "data/main"
+        port = 9000
+        watchtower = true
+        "#
+    .parse()
+    .unwrap();
+    assert!(config.chains["main"].node_config().unwrap().watchtower);
+}
+
+#[test]
+fn test_zero_conf_risk_config() {
+    use clap::Parser;
+    let cli = Cli::parse_from(["node", "--blockchain-file", "test.cbor"]);
+    assert!(!cli.node_configs().unwrap()[0].1.zero_conf_risk);
+    let cli = Cli::parse_from(["node", "-b", "test.cbor", "--zero-conf-risk"]);
+    assert!(cli.node_configs().unwrap()[0].1.zero_conf_risk);
+    assert!(Cli::try_parse_from(["node", "--seed-only", "--zero-conf-risk"]).is_err());
+
+    let config: ChainsConfig = r#"
+        [chains.shop]
+        data_dir = "data/shop"
+        port = 9000
+        zero_conf_risk = true
+        "#
+    .parse()
+    .unwrap();
+    assert!(config.chains["shop"].node_config().unwrap().zero_conf_risk);
+}
+
+#[test]
+fn test_outbound_only_config() {
+    use clap::Parser;
+    use std::time::Duration;
+    let cli = Cli::parse_from(["node", "--blockchain-file", "test.cbor"]);
+    let config = &cli.node_configs().unwrap()[0].1;
+    assert_eq!(config.outbound_only, None);
+    assert!(config.serve_templates);
+
+    let cli = Cli::parse_from([
+        "node",
+        "--blockchain-file",
+        "test.cbor",
+        "--nolisten",
+        "--outbound-peers",
+        "3",
+        "--no-templates",
+    ]);
+    let config = &cli.node_configs().unwrap()[0].1;
+    assert_eq!(
+        config.outbound_only,
+        Some(OutboundConfig {
+            peers: 3,
+            poll_interval: Duration::from_secs(DEFAULT_OUTBOUND_POLL_SECS),
+        })
+    );
+    assert!(!config.serve_templates);
+    assert!(Cli::try_parse_from(["node", "-b", "test.cbor", "--outbound-peers", "3"]).is_err());
+    assert!(Cli::try_parse_from(["node", "--seed-only", "--nolisten"]).is_err());
+
+    let config: ChainsConfig = r#"
+        [chains.main]
+        data_dir = "data/main"
+        port = 9000
+        listen = false
+        poll_interval_secs = 5
+        serve_templates = false
+        "#
+    .parse()
+    .unwrap();
+    let config = config.chains["main"].node_config().unwrap();
+    assert_eq!(
+        config.outbound_only,
+        Some(OutboundConfig {
+            peers: DEFAULT_OUTBOUND_PEERS,
+            poll_interval: Duration::from_secs(5),
+        })
+    );
+    assert!(!config.serve_templates);
+}
+
+#[test]
+fn test_watchtower() {
+    use btclib::{
+        crypto::{PrivateKey, Signature},
+        custom_sha_types::Hash,
+        types::{
+            Block, BlockHeader, BlockHeight, Blockchain, Transaction, TransactionInput,
+            TransactionOutput,
+        },
+        utils::MerkleRoot,
+    };
+
+    let key = PrivateKey::from_seed(b"merchant");
+    let spend = |outpoint: Hash| {
+        Transaction::new(
+            vec![TransactionInput::new(
+                outpoint,
+                Signature::sign_output(&outpoint, &key),
+            )],
+            vec![TransactionOutput::new(
+                1000,
+                uuid::Uuid::new_v4(),
+                key.public_key(),
+            )],
+        )
+    };
+    let mine = |transactions: Vec<Transaction>| {
+        let header = BlockHeader::new(
+            chrono::Utc::now(),
+            0,
+            Hash::zero(),
+            MerkleRoot::calculate(&transactions),
+            btclib::MIN_TARGET,
+        );
+        Block::new(header, transactions)
+    };
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let tower = Watchtower::new(sender);
+    let (watched, other) = (Hash::hash(&"paid"), Hash::hash(&"not watched"));
+    assert_eq!(
+        tower.watch(&[watched, watched], &Blockchain::default()),
+        Some(1)
+    );
+
+    // the first spend is fine, as is the same one mined
+    let first = spend(watched);
+    tower.on_tx_accepted(&first);
+    tower.on_tx_accepted(&spend(other));
+    tower.on_tx_accepted(&spend(other));
+    tower.on_block_connected(&mine(vec![first.clone()]), BlockHeight::new(1));
+    assert!(receiver.try_recv().is_err());
+
+    // a conflicting one is reported when it replaces the first in the
+    // mempool, and again once mined
+    let tower = {
+        let (sender, new_receiver) = tokio::sync::mpsc::unbounded_channel();
+        receiver = new_receiver;
+        Watchtower::new(sender)
+    };
+    tower.watch(&[watched], &Blockchain::default());
+    let second = spend(watched);
+    tower.on_tx_accepted(&first);
+    tower.on_tx_accepted(&second);
+    tower.on_block_connected(&mine(vec![second.clone()]), BlockHeight::new(1));
+    let expected = DoubleSpend {
+        outpoint: watched,
+        first: first.hash(),
+        second: second.hash(),
+        height: None,
+    };
+    assert_eq!(receiver.try_recv().unwrap(), expected);
+    assert_eq!(
+        receiver.try_recv().unwrap(),
+        DoubleSpend {
+            height: Some(BlockHeight::new(1)),
+            ..expected
+        }
+    );
+    assert!(receiver.try_recv().is_err());
+
+    assert_eq!(tower.unwatch(&[watched, other]), 0);
+    tower.on_tx_accepted(&first);
+    assert!(receiver.try_recv().is_err());
+}
+
+#[test]
+fn test_double_spend_webhook_event() {
+    use btclib::{custom_sha_types::Hash, types::BlockHeight};
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let notifier = WebhookNotifier::new(sender, 1_000);
+    let double_spend = DoubleSpend {
+        outpoint: Hash::zero(),
+        first: Hash::zero(),
+        second: Hash::zero(),
+        height: None,
+    };
+    notifier.on_double_spend(&double_spend);
+    notifier.on_double_spend(&DoubleSpend {
+        height: Some(BlockHeight::new(4)),
+        ..double_spend
+    });
+    let event = receiver.try_recv().unwrap();
+    assert_eq!(event.kind, WebhookEventKind::DoubleSpend);
+    assert!(
+        event
+            .body
+            .starts_with(r#"{"event":"double_spend","outpoint":"000"#)
+    );
+    assert!(event.body.ends_with(r#","height":null}"#));
+    assert!(
+        receiver
+            .try_recv()
+            .unwrap()
+            .body
+            .ends_with(r#","height":4}"#)
+    );
+}
+
+fn snapshot(address: &str, blocks: &[u64], mempool: &[u64]) -> NodeSnapshot {
+    use btclib::{custom_sha_types::Hash, network::ChainTip, types::BlockHeight};
+    use std::collections::HashMap;
+    let recent_blocks: HashMap<BlockHeight, Hash> = blocks
+        .iter()
+        .enumerate()
+        .map(|(height, id)| (BlockHeight::from_index(height), Hash::hash(id)))
+        .collect();
+    let tip = blocks.len().checked_sub(1).map(|last| ChainTip {
+        hash: Hash::hash(&blocks[last]),
+        height: BlockHeight::from_index(last),
+        prev: last
+            .checked_sub(1)
+            .map_or(Hash::zero(), |prev| Hash::hash(&blocks[prev])),
+    });
+    NodeSnapshot {
+        address: address.to_string(),
+        tip,
+        recent_blocks,
+        mempool: mempool.iter().map(Hash::hash).collect(),
+        tip_timings: None,
+    }
+}
+
+#[test]
+fn test_find_divergences() {
+    use btclib::types::BlockHeight;
+    let best = snapshot("a", &[0, 1, 2], &[10, 11]);
+    assert!(find_divergences(&[best.clone(), snapshot("b", &[0, 1, 2], &[11, 10])]).is_empty());
+
+    let divergences = find_divergences(&[
+        best,
+        snapshot("behind", &[0, 1], &[]),
+        snapshot("empty", &[], &[]),
+        snapshot("forked", &[0, 5], &[]),
+        snapshot("same tip", &[0, 1, 2], &[10]),
+    ]);
+    assert_eq!(
+        divergences,
+        vec![
+            Divergence::Behind {
+                node: "behind".to_string(),
+                height: Some(BlockHeight::new(1)),
+                best: BlockHeight::new(2),
+            },
+            Divergence::Behind {
+                node: "empty".to_string(),
+                height: None,
+                best: BlockHeight::new(2),
+            },
+            Divergence::Forked {
+                node: "forked".to_string(),
+                common: Some(BlockHeight::GENESIS),
+            },
+            Divergence::MissingTransactions {
+                node: "same tip".to_string(),
+                missing: vec![btclib::custom_sha_types::Hash::hash(&11u64)],
+            },
+        ]
+    );
+    // lagging nodes' mempools aren't compared, they may hold
+    // transactions the best chain has mined since
+    assert_eq!(
+        find_divergences(&[snapshot("a", &[0, 1], &[]), snapshot("b", &[0], &[3])]).len(),
+        1
+    );
+}
+
+#[test]
+fn test_block_timing_log() {
+    use btclib::{custom_sha_types::Hash, network::BlockTimings, types::BlockHeight};
+    use chrono::{Duration, Utc};
+    let mined_at = Utc::now();
+    let timings = |id: u64, delay_ms: i64, relayed: bool| {
+        let received_at = mined_at + Duration::milliseconds(delay_ms);
+        BlockTimings {
+            hash: Hash::hash(&id),
+            height: BlockHeight::new(id),
+            mined_at,
+            received_at,
+            connected_at: received_at + Duration::milliseconds(100),
+            relayed_by: relayed.then(|| "127.0.0.1:9000".to_string()),
+        }
+    };
+    let mut log = BlockTimingLog::default();
+    assert_eq!(log.summary(10), PropagationSummary::default());
+    log.record(timings(0, 3_000, true));
+    log.record(timings(1, 1_000, true));
+    log.record(timings(2, 60_000, false));
+    // the first arrival counts
+    log.record(timings(1, 5_000, true));
+    assert_eq!(
+        log.get(&Hash::hash(&1u64)).unwrap().propagation_delay(),
+        1.0
+    );
+
+    let summary = log.summary(10);
+    // the block submitted by a miner doesn't count towards the delays
+    assert_eq!(summary.samples, 2);
+    assert_eq!(summary.average_delay, 2.0);
+    assert_eq!(summary.max_delay, 3.0);
+    assert!((summary.average_validation_time - 0.1).abs() < 1e-9);
+    assert_eq!(log.summary(2).samples, 1);
+}
+
+#[test]
+fn test_sign_payload() {
+    assert_eq!(
+        sign_payload("key", "The quick brown fox jumps over the lazy dog"),
+        "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+    );
+}
+
+#[test]
+fn test_parse_webhook_url() {
+    assert_eq!(
+        parse_webhook_url("http://localhost:8080/hooks/ledger").unwrap(),
+        ("localhost:8080".to_string(), "/hooks/ledger".to_string())
+    );
+    assert_eq!(
+        parse_webhook_url("http://example.com").unwrap(),
+        ("example.com:80".to_string(), "/".to_string())
+    );
+    assert!(parse_webhook_url("https://example.com/").is_err());
+    assert!(parse_webhook_url("http:///path").is_err());
+    let mut config = WebhookConfig::default();
+    config.hooks.push(Webhook::new("ftp://example.com"));
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn test_webhook_notifier() {
+    use btclib::{
+        crypto::PrivateKey,
+        types::{Transaction, TransactionOutput},
+    };
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let notifier = WebhookNotifier::new(sender, 1_000);
+    let pay = |value| {
+        Transaction::new(
+            vec![],
+            vec![TransactionOutput::new(
+                value,
+                uuid::Uuid::new_v4(),
+                PrivateKey::default().public_key(),
+            )],
+        )
+    };
+    notifier.on_tx_accepted(&pay(999));
+    assert!(receiver.try_recv().is_err());
+    let large = pay(1_000);
+    notifier.on_tx_accepted(&large);
+    let event = receiver.try_recv().unwrap();
+    assert_eq!(event.kind, WebhookEventKind::LargeTxDetected);
+    assert!(event.body.contains(r#""value":1000"#));
+    assert!(event.body.contains(&hex::encode(large.hash().as_bytes())));
+
+    let hook = Webhook {
+        events: vec![WebhookEventKind::BlockConnected],
+        ..Webhook::new("http://localhost/")
+    };
+    assert!(hook.wants(WebhookEventKind::BlockConnected));
+    assert!(!hook.wants(WebhookEventKind::LargeTxDetected));
+    assert!(Webhook::new("http://localhost/").wants(WebhookEventKind::Reorg));
+}
+
+#[test]
+fn test_chains_config_webhooks() {
+    let config: ChainsConfig = r#"
+        [chains.main]
+        data_dir = "data/main"
+        port = 9000
+        large_tx_threshold = 500
+
+        [[chains.main.webhooks]]
+        url = "http://localhost:8080/events"
+        secret = "s3cret"
+        events = ["block_connected", "reorg"]
+        "#
+    .parse()
+    .unwrap();
+    let webhooks = config.chains["main"].webhooks();
+    assert_eq!(webhooks.large_tx_threshold, 500);
+    assert_eq!(webhooks.max_retries, WebhookConfig::default().max_retries);
+    assert_eq!(webhooks.hooks[0].secret.as_deref(), Some("s3cret"));
+    assert_eq!(
+        webhooks.hooks[0].events,
+        vec![WebhookEventKind::BlockConnected, WebhookEventKind::Reorg]
+    );
+}
+
+#[test]
+fn test_cli_webhooks() {
+    use clap::Parser;
+    let cli = Cli::parse_from([
+        "node",
+        "--blockchain-file",
+        "test.cbor",
+        "--webhook",
+        "http://a/events,http://b/events",
+        "--webhook-secret",
+        "s3cret",
+        "--webhook-retries",
+        "2",
+    ]);
+    let webhooks = cli.node_configs().unwrap()[0].1.webhooks.clone();
+    assert_eq!(webhooks.hooks.len(), 2);
+    assert!(
+        webhooks
+            .hooks
+            .iter()
+            .all(|hook| hook.secret.as_deref() == Some("s3cret"))
+    );
+    assert_eq!(webhooks.max_retries, 2);
+    assert!(
+        Cli::try_parse_from(["node", "-b", "test.cbor", "--webhook-secret", "s3cret"]).is_err()
+    );
+}
+
+#[test]
+fn test_cosigner_signs_one_block_per_height() {
+    use btclib::{crypto::PrivateKey, types::Blockchain};
+    let key = PrivateKey::from_seed(b"authority");
+    let mut cosigner = Cosigner::new(key.clone());
+    let blockchain = Blockchain::default();
+
+    let block = cosigner.sign(&blockchain, genesis_block()).unwrap();
+    assert_eq!(block.authority_signatures().len(), 1);
+    assert_eq!(block.authority_signatures()[0].signer, key.public_key());
+    // the same block again is fine, a competing one isn't
+    assert!(cosigner.sign(&blockchain, block).is_ok());
+    assert!(cosigner.sign(&blockchain, genesis_block()).is_err());
+}
+
+#[test]
+fn test_check_cosign_key() {
+    use btclib::{
+        crypto::PrivateKey,
+        types::{AuthoritySet, BlockHeight},
+    };
+    let key = PrivateKey::from_seed(b"authority");
+    let authority = AuthoritySet::new(vec![key.public_key()], 1, BlockHeight::GENESIS).unwrap();
+    assert!(check_cosign_key(None, None).is_ok());
+    assert!(check_cosign_key(Some(&authority), None).is_ok());
+    assert!(check_cosign_key(Some(&authority), Some(&key)).is_ok());
+    assert!(check_cosign_key(None, Some(&key)).is_err());
+    let other = PrivateKey::from_seed(b"other");
+    assert!(check_cosign_key(Some(&authority), Some(&other)).is_err());
+}
+
+#[test]
+fn test_validator_key_must_be_a_validator() {
+    use btclib::{consensus::ProofOfAuthority, crypto::PrivateKey};
+    let alice = PrivateKey::from_seed(b"alice");
+    let bob = PrivateKey::from_seed(b"bob");
+    let engine = ProofOfAuthority::new(vec![alice.public_key()]).unwrap();
+    assert!(check_validator_key(&engine, None).is_ok());
+    assert!(check_validator_key(&engine, Some(&alice)).is_ok());
+    assert!(check_validator_key(&engine, Some(&bob)).is_err());
+    // nobody signs blocks under proof of work
+    let pow = load_consensus(&[]).unwrap();
+    assert_eq!(pow.name(), "proof-of-work");
+    assert!(check_validator_key(&*pow, Some(&alice)).is_err());
+}
+
+#[test]
+fn test_max_reorg_depth_config() {
+    use btclib::MAX_REORG_DEPTH;
+    use clap::Parser;
+    let cli = Cli::parse_from(["node", "--blockchain-file", "test.cbor"]);
+    assert_eq!(
+        cli.node_configs().unwrap()[0].1.max_reorg_depth,
+        MAX_REORG_DEPTH
+    );
+    let cli = Cli::parse_from([
+        "node",
+        "--blockchain-file",
+        "test.cbor",
+        "--max-reorg-depth",
+        "6",
+    ]);
+    assert_eq!(cli.node_configs().unwrap()[0].1.max_reorg_depth, 6);
+
+    let config: ChainsConfig = r#"
+        [chains.main]
+        data_dir = "data/main"
+        port = 9000
+        max_reorg_depth = 0
+        "#
+    .parse()
+    .unwrap();
+    assert_eq!(
+        config.chains["main"].node_config().unwrap().max_reorg_depth,
+        0
+    );
+}
+
+#[test]
+fn test_max_block_size_config() {
+    use btclib::MAX_BLOCK_SIZE;
+    use clap::Parser;
+    let cli = Cli::parse_from(["node", "--blockchain-file", "test.cbor"]);
+    assert_eq!(
+        cli.node_configs().unwrap()[0].1.max_block_size,
+        MAX_BLOCK_SIZE
+    );
+    let cli = Cli::parse_from([
+        "node",
+        "--blockchain-file",
+        "test.cbor",
+        "--max-block-size",
+        "4000",
+    ]);
+    assert_eq!(cli.node_configs().unwrap()[0].1.max_block_size, 4000);
+
+    let config: ChainsConfig = r#"
+        [chains.main]
+        data_dir = "data/main"
+        port = 9000
+        max_block_size = 2000000
+        "#
+    .parse()
+    .unwrap();
+    assert_eq!(
+        config.chains["main"].node_config().unwrap().max_block_size,
+        2_000_000
+    );
+}
+
+#[test]
+fn test_chain_conflict_webhook_event() {
+    use btclib::{custom_sha_types::Hash, network::ChainTip, types::BlockHeight};
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let notifier = WebhookNotifier::new(sender, 1_000);
+    notifier.on_chain_conflict(&ChainConflict {
+        peer: "10.0.0.2:9000".to_string(),
+        tip: None,
+        peer_tip: ChainTip {
+            hash: Hash::zero(),
+            height: BlockHeight::new(7),
+            prev: Hash::zero(),
+        },
+        max_depth: 3,
+    });
+    let event = receiver.try_recv().unwrap();
+    assert_eq!(event.kind, WebhookEventKind::ChainConflict);
+    assert!(
+        event
+            .body
+            .contains(r#""peer":"10.0.0.2:9000","height":null"#)
+    );
+    assert!(event.body.contains(r#""peer_height":7,"max_depth":3"#));
+}
+
+#[test]
+fn test_rejection_tracker() {
+    use chrono::{Duration, Utc};
+    let mut tracker = RejectionTracker::default();
+    tracker.set_limits(RejectionLimits {
+        max_blocks_per_minute: 2,
+        max_transactions_per_minute: 10,
+    });
+    let now = Utc::now();
+    assert_eq!(tracker.record(RejectedKind::Block, now), None);
+    assert_eq!(tracker.record(RejectedKind::Block, now), None);
+    assert_eq!(
+        tracker.record(RejectedKind::Block, now),
+        Some(RejectionStorm {
+            kind: RejectedKind::Block,
+            count: 3,
+            limit: 2
+        })
+    );
+    // the same storm is reported once
+    assert_eq!(tracker.record(RejectedKind::Block, now), None);
+    assert_eq!(tracker.count(RejectedKind::Block, now), 4);
+    assert_eq!(tracker.count(RejectedKind::Transaction, now), 0);
+    // rejections older than a minute no longer count
+    let later = now + Duration::seconds(61);
+    assert_eq!(tracker.count(RejectedKind::Block, later), 0);
+    // and a new storm after the cooldown is reported again
+    let much_later = now + Duration::minutes(11);
+    for _ in 0..2 {
+        assert_eq!(tracker.record(RejectedKind::Block, much_later), None);
     }
+    assert!(tracker.record(RejectedKind::Block, much_later).is_some());
+}
+
+#[test]
+fn test_rejection_storm_webhook_event() {
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let notifier = WebhookNotifier::new(sender, 1_000);
+    notifier.on_rejection_storm(&RejectionStorm {
+        kind: RejectedKind::Transaction,
+        count: 61,
+        limit: 60,
+    });
+    let event = receiver.try_recv().unwrap();
+    assert_eq!(event.kind, WebhookEventKind::RejectionStorm);
+    assert_eq!(
+        event.body,
+        r#"{"event":"rejection_storm","kind":"transaction","count":61,"limit":60}"#
+    );
+}
 
-    #[test]
-    fn test_cli_nodes_multiple() {
-        use clap::Parser;
-        let cli = Cli::parse_from(&[
+#[test]
+fn test_parse_block_hash() {
+    use btclib::custom_sha_types::Hash;
+    let hash = Hash::hash(&"block");
+    let hex = hex::encode(hash.as_bytes());
+    assert_eq!(parse_block_hash(&hex).unwrap(), hash);
+    assert_eq!(parse_block_hash(&format!(" {hex}\n")).unwrap(), hash);
+    assert!(parse_block_hash(&hex[2..]).is_err());
+    assert!(parse_block_hash("not hex").is_err());
+}
+
+#[test]
+fn test_http_framing() {
+    let (method, target, length) =
+        parse_http_head("POST /tx HTTP/1.1\r\nHost: node\r\ncontent-length: 12\r\n").unwrap();
+    assert_eq!(
+        (method.as_str(), target.as_str(), length),
+        ("POST", "/tx", 12)
+    );
+    let (_, target, length) = parse_http_head("GET /headers?from=3 HTTP/1.1\r\n").unwrap();
+    assert_eq!((target.as_str(), length), ("/headers?from=3", 0));
+    assert!(parse_http_head("\r\n").is_err());
+    assert!(parse_http_head("POST /tx HTTP/1.1\r\nContent-Length: lots\r\n").is_err());
+
+    let response = HttpResponse {
+        status: 404,
+        body: r#"{"error":"gone"}"#.to_string(),
+    }
+    .to_http("https://wallet.example.com");
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    assert!(response.contains("Access-Control-Allow-Origin: https://wallet.example.com\r\n"));
+    assert!(response.contains("Content-Length: 16\r\n"));
+    assert!(response.ends_with("\r\n\r\n{\"error\":\"gone\"}"));
+
+    assert_eq!(json_string("say \"hi\"\\\n"), r#""say \"hi\"\\\u000a""#);
+}
+
+#[test]
+fn test_http_config() {
+    use clap::Parser;
+    let cli = Cli::parse_from(["node", "--blockchain-file", "test.cbor"]);
+    assert_eq!(cli.node_configs().unwrap()[0].1.http, None);
+    let cli = Cli::parse_from([
+        "node",
+        "--blockchain-file",
+        "test.cbor",
+        "--http-port",
+        "8080",
+    ]);
+    assert_eq!(cli.http(), Some(HttpConfig::new(8080)));
+    assert!(Cli::try_parse_from(["node", "--seed-only", "--http-port", "8080"]).is_err());
+    assert!(
+        Cli::try_parse_from([
             "node",
             "--blockchain-file",
             "test.cbor",
-            "--nodes",
-            "localhost:9001,localhost:9002,localhost:9003",
-        ]);
-        assert_eq!(cli.nodes().len(), 3);
-        assert_eq!(cli.nodes()[0], "localhost:9001");
-        assert_eq!(cli.nodes()[1], "localhost:9002");
-        assert_eq!(cli.nodes()[2], "localhost:9003");
-    }
+            "--http-cors-origin",
+            "https://wallet.example.com",
+        ])
+        .is_err()
+    );
+
+    let config: ChainsConfig = r#"
+        [chains.main]
+        data_dir = "data/main"
+        port = 9000
+        http_port = 8080
+        http_cors_origin = "https://wallet.example.com"
+
+        [chains.test]
+        data_dir = "data/test"
+        port = 8080
+        "#
+    .parse()
+    .unwrap();
+    assert_eq!(
+        config.chains["main"].http(),
+        Some(HttpConfig {
+            port: 8080,
+            cors_origin: "https://wallet.example.com".to_string(),
+        })
+    );
+    // the test chain listens on the HTTP port of the main chain
+    assert!(config.node_configs().is_err());
 }
@@ -0,0 +1,126 @@
+use std::collections::{HashMap, VecDeque};
+
+use btclib::{custom_sha_types::Hash, network::BlockTimings, types::Blockchain};
+use chrono::{DateTime, Utc};
+use log::debug;
+
+use crate::NodeState;
+
+// blocks whose timings are remembered before the oldest are forgotten
+const MAX_TIMED_BLOCKS: usize = 1_000;
+// number of recent blocks the propagation summary covers
+const PROPAGATION_WINDOW: usize = 50;
+
+/// Propagation figures over the most recent blocks, reported with the
+/// network health.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PropagationSummary {
+    /// Number of recent blocks relayed by peers the delays cover
+    pub samples: u64,
+    pub average_delay: f64,
+    pub max_delay: f64,
+    /// Over every recent block, relayed or submitted by a miner
+    pub average_validation_time: f64,
+}
+
+/// When this node received and connected each recent block, for
+/// `FetchBlockTimings`. Only the last `MAX_TIMED_BLOCKS` are kept.
+#[derive(Debug, Default)]
+pub struct BlockTimingLog {
+    timings: HashMap<Hash, BlockTimings>,
+    order: VecDeque<Hash>,
+}
+
+impl BlockTimingLog {
+    /// Keeps the first timings recorded for a block.
+    pub fn record(&mut self, timings: BlockTimings) {
+        if self.timings.contains_key(&timings.hash) {
+            return;
+        }
+        self.order.push_back(timings.hash);
+        self.timings.insert(timings.hash, timings);
+        if self.order.len() > MAX_TIMED_BLOCKS
+            && let Some(oldest) = self.order.pop_front()
+        {
+            self.timings.remove(&oldest);
+        }
+    }
+
+    pub fn get(&self, hash: &Hash) -> Option<&BlockTimings> {
+        self.timings.get(hash)
+    }
+
+    /// Figures over the `window` most recently connected blocks.
+    /// Blocks submitted by a miner here don't count towards the delays:
+    /// their header timestamp is when mining started, not when it ended.
+    pub fn summary(&self, window: usize) -> PropagationSummary {
+        let recent: Vec<&BlockTimings> = self
+            .order
+            .iter()
+            .rev()
+            .take(window)
+            .map(|hash| &self.timings[hash])
+            .collect();
+        let delays: Vec<f64> = recent
+            .iter()
+            .filter(|timings| timings.relayed_by.is_some())
+            .map(|timings| timings.propagation_delay())
+            .collect();
+        let mut summary = PropagationSummary {
+            samples: delays.len() as u64,
+            ..Default::default()
+        };
+        if !delays.is_empty() {
+            summary.average_delay = delays.iter().sum::<f64>() / delays.len() as f64;
+            summary.max_delay = delays.iter().copied().fold(f64::MIN, f64::max);
+        }
+        if !recent.is_empty() {
+            summary.average_validation_time = recent
+                .iter()
+                .map(|timings| timings.validation_time())
+                .sum::<f64>()
+                / recent.len() as f64;
+        }
+        summary
+    }
+}
+
+/// Records the timings of the block just added on top of `blockchain`,
+/// which arrived at `received_at`. `relayed_by` is the peer it came from.
+pub fn record_block_timings(
+    state: &NodeState,
+    blockchain: &Blockchain,
+    received_at: DateTime<Utc>,
+    relayed_by: Option<String>,
+) {
+    let (Some(tip), Some(block)) = (blockchain.tip(), blockchain.blocks().last()) else {
+        return;
+    };
+    let timings = BlockTimings {
+        hash: tip.hash,
+        height: tip.height,
+        mined_at: block.header().timestamp(),
+        received_at,
+        connected_at: Utc::now(),
+        relayed_by,
+    };
+    debug!(
+        "block {} arrived {:.3}s after it was mined, validated in {:.3}s",
+        tip.height,
+        timings.propagation_delay(),
+        timings.validation_time()
+    );
+    state.block_timings.lock().unwrap().record(timings);
+}
+
+pub fn block_timings(state: &NodeState, hash: &Hash) -> Option<BlockTimings> {
+    state.block_timings.lock().unwrap().get(hash).cloned()
+}
+
+pub fn propagation_summary(state: &NodeState) -> PropagationSummary {
+    state
+        .block_timings
+        .lock()
+        .unwrap()
+        .summary(PROPAGATION_WINDOW)
+}
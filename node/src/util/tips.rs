@@ -0,0 +1,56 @@
+use btclib::{
+    network::{ChainTip, Message},
+    types::{Block, BlockHeight},
+};
+use log::{debug, warn};
+use tokio::{
+    io::AsyncWrite,
+    sync::broadcast::{Sender, error::RecvError},
+};
+
+use crate::{NodeState, util::ChainObserver};
+
+/// Tells tip subscribers about every new best block.
+#[derive(Debug)]
+pub struct TipPublisher {
+    tip_updates: Sender<ChainTip>,
+}
+
+impl TipPublisher {
+    pub fn new(tip_updates: Sender<ChainTip>) -> Self {
+        TipPublisher { tip_updates }
+    }
+}
+
+impl ChainObserver for TipPublisher {
+    fn on_block_connected(&self, block: &Block, height: BlockHeight) {
+        let tip = ChainTip {
+            hash: block.hash(),
+            height,
+            prev: *block.header().prev_block_hash(),
+        };
+        // an error only means that nobody is subscribed right now
+        let _ = self.tip_updates.send(tip);
+    }
+}
+
+/// Pushes every best block change to the subscriber on `socket`
+/// until the connection goes away.
+pub async fn forward_tips(state: &NodeState, socket: &mut (impl AsyncWrite + Unpin)) {
+    let mut tips = state.tip_updates.subscribe();
+    loop {
+        let tip: ChainTip = match tips.recv().await {
+            Ok(tip) => tip,
+            Err(RecvError::Lagged(skipped)) => {
+                // only the latest tip matters, which is still queued
+                debug!("tip subscriber lagged behind by {skipped} updates");
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        if let Err(e) = Message::TipChanged(tip).send_async(socket).await {
+            warn!("tip subscriber went away: {e}");
+            return;
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Read, Write},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use btclib::{
+    network::DiskUsage,
+    types::{Block, Blockchain},
+    utils::Saveable,
+};
+use log::{info, warn};
+
+use crate::NodeState;
+
+/// Append-only log of the blocks accepted since the blockchain file was
+/// last written. Each record is a big endian u64 length followed by the
+/// CBOR encoded block, the same framing as wire messages.
+#[derive(Debug)]
+pub struct ChainWal {
+    file: File,
+    blockchain_file: String,
+}
+
+impl ChainWal {
+    // room for the header and the framing around the transactions
+    const RECORD_OVERHEAD: usize = 64 * 1024;
+
+    /// Opens (or creates) the WAL that belongs to `blockchain_file`.
+    pub fn open(blockchain_file: &str) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(wal_path(blockchain_file))
+            .context("failed to open the write-ahead log")?;
+        Ok(ChainWal {
+            file,
+            blockchain_file: blockchain_file.to_string(),
+        })
+    }
+
+    /// Sizes of the blockchain file, this log and any leftover temporary save.
+    pub fn disk_usage(&self) -> Result<DiskUsage> {
+        let size = |path: &str| match fs::metadata(path) {
+            Ok(metadata) => Ok(metadata.len()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(e),
+        };
+        Ok(DiskUsage {
+            blockchain: size(&self.blockchain_file)?,
+            write_ahead_log: self.file.metadata()?.len(),
+            pending_save: size(&temp_path(&self.blockchain_file))?,
+        })
+    }
+
+    /// Appends a block and waits until it has reached the disk.
+    pub fn append(&mut self, block: &Block) -> Result<()> {
+        let mut bytes = Vec::new();
+        block.save(&mut bytes)?;
+        self.file.write_all(&(bytes.len() as u64).to_be_bytes())?;
+        self.file.write_all(&bytes)?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    /// Drops all records, once they are covered by the blockchain file.
+    pub fn truncate(&mut self) -> Result<()> {
+        self.file.set_len(0)?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    /// Largest record a block of at most `max_block_size` bytes of
+    /// transactions takes. Anything larger can only be a corrupted length
+    /// prefix.
+    pub fn max_record_size(max_block_size: usize) -> usize {
+        max_block_size.saturating_add(Self::RECORD_OVERHEAD)
+    }
+
+    /// Reads back every complete record of a block of at most
+    /// `max_block_size` bytes of transactions. A torn record at the end,
+    /// left by a crash in the middle of an append, is ignored.
+    pub fn read_blocks(path: impl AsRef<Path>, max_block_size: usize) -> Result<Vec<Block>> {
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        let mut blocks = vec![];
+        loop {
+            let mut len_bytes = [0u8; 8];
+            if file.read_exact(&mut len_bytes).is_err() {
+                break;
+            }
+            let len = u64::from_be_bytes(len_bytes) as usize;
+            if len > Self::max_record_size(max_block_size) {
+                warn!("ignoring oversized write-ahead log record");
+                break;
+            }
+            let mut buffer = vec![0u8; len];
+            if file.read_exact(&mut buffer).is_err() {
+                warn!("ignoring torn record at the end of the write-ahead log");
+                break;
+            }
+            match Block::load(buffer.as_slice()) {
+                Ok(block) => blocks.push(block),
+                Err(e) => {
+                    warn!("ignoring unreadable write-ahead log record: {e}");
+                    break;
+                }
+            }
+        }
+        Ok(blocks)
+    }
+}
+
+pub fn wal_path(blockchain_file: &str) -> String {
+    format!("{blockchain_file}.wal")
+}
+
+fn temp_path(blockchain_file: &str) -> String {
+    format!("{blockchain_file}.tmp")
+}
+
+/// Starts logging accepted blocks to the WAL next to `blockchain_file`.
+pub fn open_wal(state: &NodeState, blockchain_file: &str) -> Result<()> {
+    let wal = ChainWal::open(blockchain_file)?;
+    *state.wal.lock().unwrap() = Some(wal);
+    Ok(())
+}
+
+/// Disk usage of the open WAL and the blockchain file it belongs to.
+pub fn disk_usage(state: &NodeState) -> Result<DiskUsage> {
+    match state.wal.lock().unwrap().as_ref() {
+        Some(wal) => wal.disk_usage(),
+        None => anyhow::bail!("storage is not initialised"),
+    }
+}
+
+/// Records a block that was just added to the in-memory blockchain.
+/// Must be called while still holding the blockchain write lock, so the
+/// block is durable before any save can include it.
+pub fn log_block(state: &NodeState, block: &Block) -> Result<()> {
+    match state.wal.lock().unwrap().as_mut() {
+        Some(wal) => wal.append(block),
+        None => Ok(()),
+    }
+}
+
+/// Re-applies the blocks logged after the last successful save.
+/// Returns how many were missing from `blockchain`.
+pub fn replay_wal(blockchain: &mut Blockchain, blockchain_file: &str) -> Result<usize> {
+    let mut replayed = 0;
+    let blocks = ChainWal::read_blocks(wal_path(blockchain_file), blockchain.max_block_size())?;
+    for block in blocks {
+        if blockchain.block_by_hash(&block.hash()).is_some() {
+            continue;
+        }
+        match blockchain.add_block(block) {
+            Ok(()) => {
+                // later blocks may spend what this one created
+                blockchain.rebuild_utxos();
+                replayed += 1;
+            }
+            Err(e) => warn!("skipping write-ahead log block: {e}"),
+        }
+    }
+    if replayed > 0 {
+        info!("replayed {replayed} blocks from the write-ahead log");
+    }
+    Ok(replayed)
+}
+
+/// Atomically replaces the blockchain file, then empties the WAL.
+/// A crash part way leaves either the old file plus the WAL, or the
+/// new file plus a WAL whose blocks it already contains.
+pub fn checkpoint(state: &NodeState, blockchain: &Blockchain, blockchain_file: &str) -> Result<()> {
+    let temp_file = temp_path(blockchain_file);
+    let file = File::create(&temp_file)?;
+    blockchain.save(&file)?;
+    file.sync_all()?;
+    fs::rename(&temp_file, blockchain_file)?;
+    if let Some(wal) = state.wal.lock().unwrap().as_mut() {
+        wal.truncate()?;
+    }
+    Ok(())
+}
+
+/// Saves `blockchain` after a reorganization, which the append-only WAL
+/// can't record. Must be called while still holding the blockchain
+/// write lock.
+pub fn log_reorg(state: &NodeState, blockchain: &Blockchain) -> Result<()> {
+    let blockchain_file = match state.wal.lock().unwrap().as_ref() {
+        Some(wal) => wal.blockchain_file.clone(),
+        None => return Ok(()),
+    };
+    // checkpoint locks the WAL itself
+    checkpoint(state, blockchain, &blockchain_file)
+}
@@ -0,0 +1,176 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use btclib::{
+    custom_sha_types::Hash,
+    types::{Block, BlockHeight, Blockchain, Transaction},
+};
+use log::warn;
+use tokio::{
+    sync::mpsc::{UnboundedSender, unbounded_channel},
+    task::JoinHandle,
+};
+
+use crate::{
+    NodeState,
+    util::{ChainObserver, notify_double_spend, register_observer},
+};
+
+/// Most outputs a watchtower node watches at once.
+pub const MAX_WATCHED_OUTPOINTS: usize = 100_000;
+
+/// A watched output spent by a transaction other than the one first
+/// seen spending it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DoubleSpend {
+    pub outpoint: Hash,
+    /// The transaction first seen spending the output
+    pub first: Hash,
+    /// The conflicting transaction
+    pub second: Hash,
+    /// Where the conflicting transaction was mined, `None` if it
+    /// replaced the first one in the mempool
+    pub height: Option<BlockHeight>,
+}
+
+/// Outputs registered by merchants and the like, each with the
+/// transaction seen spending it, if any. Watches the mempool and new
+/// blocks as a `ChainObserver` and reports a transaction spending an
+/// output already spent by another as a `DoubleSpend`.
+#[derive(Debug)]
+pub struct Watchtower {
+    spenders: Mutex<HashMap<Hash, Option<Hash>>>,
+    alerts: UnboundedSender<DoubleSpend>,
+}
+
+impl Watchtower {
+    pub fn new(alerts: UnboundedSender<DoubleSpend>) -> Self {
+        Watchtower {
+            spenders: Mutex::new(HashMap::new()),
+            alerts,
+        }
+    }
+
+    /// Starts watching `outpoints`, taking a mempool transaction of
+    /// `blockchain` already spending one as the first spend. Returns how
+    /// many outputs are watched, or `None` without watching any if that
+    /// would be more than `MAX_WATCHED_OUTPOINTS`.
+    pub fn watch(&self, outpoints: &[Hash], blockchain: &Blockchain) -> Option<usize> {
+        let mut spenders = self.spenders.lock().unwrap();
+        let new = outpoints
+            .iter()
+            .filter(|outpoint| !spenders.contains_key(outpoint))
+            .count();
+        if spenders.len() + new > MAX_WATCHED_OUTPOINTS {
+            return None;
+        }
+        let mempool_spenders: HashMap<&Hash, Hash> = blockchain
+            .mempool()
+            .iter()
+            .flat_map(|(_, transaction)| {
+                let hash = transaction.hash();
+                transaction
+                    .inputs()
+                    .iter()
+                    .map(move |input| (input.prev_transaction_output_hash(), hash))
+            })
+            .collect();
+        for outpoint in outpoints {
+            spenders
+                .entry(*outpoint)
+                .or_insert_with(|| mempool_spenders.get(outpoint).copied());
+        }
+        Some(spenders.len())
+    }
+
+    /// Stops watching `outpoints`. Returns how many outputs are still
+    /// watched.
+    pub fn unwatch(&self, outpoints: &[Hash]) -> usize {
+        let mut spenders = self.spenders.lock().unwrap();
+        for outpoint in outpoints {
+            spenders.remove(outpoint);
+        }
+        spenders.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.spenders.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // records `transaction` as spending the watched outputs it spends,
+    // alerting about those spent by another transaction before. A mined
+    // spend replaces the one recorded, as it is the one that counts now.
+    fn check(&self, transaction: &Transaction, height: Option<BlockHeight>) {
+        let hash = transaction.hash();
+        let mut spenders = self.spenders.lock().unwrap();
+        for input in transaction.inputs() {
+            let outpoint = input.prev_transaction_output_hash();
+            let Some(spender) = spenders.get_mut(outpoint) else {
+                continue;
+            };
+            if let Some(first) = *spender
+                && first != hash
+            {
+                // the delivery task is gone once the node stops
+                let _ = self.alerts.send(DoubleSpend {
+                    outpoint: *outpoint,
+                    first,
+                    second: hash,
+                    height,
+                });
+            }
+            if spender.is_none() || height.is_some() {
+                *spender = Some(hash);
+            }
+        }
+    }
+}
+
+impl ChainObserver for Watchtower {
+    fn on_block_connected(&self, block: &Block, height: BlockHeight) {
+        for transaction in block.transactions() {
+            self.check(transaction, Some(height));
+        }
+    }
+
+    fn on_tx_accepted(&self, transaction: &Transaction) {
+        self.check(transaction, None);
+    }
+}
+
+/// The node's watchtower, if it runs one.
+pub fn watchtower(state: &NodeState) -> Option<Arc<Watchtower>> {
+    state.watchtower.read().unwrap().clone()
+}
+
+/// Makes the node a watchtower and spawns the task that logs the double
+/// spends it finds and passes them on to the observers, outside of the
+/// hook that found them.
+pub fn start_watchtower(state: &Arc<NodeState>) -> JoinHandle<()> {
+    let (sender, mut receiver) = unbounded_channel();
+    let tower = Arc::new(Watchtower::new(sender));
+    register_observer(state, tower.clone());
+    *state.watchtower.write().unwrap() = Some(tower);
+    let state = state.clone();
+    tokio::spawn(async move {
+        while let Some(double_spend) = receiver.recv().await {
+            match double_spend.height {
+                Some(height) => warn!(
+                    "double spend of watched output {:x?} mined at height {height}: {:x?} instead of {:x?}",
+                    double_spend.outpoint, double_spend.second, double_spend.first
+                ),
+                None => warn!(
+                    "double spend of watched output {:x?} in the mempool: {:x?} replaced {:x?}",
+                    double_spend.outpoint, double_spend.second, double_spend.first
+                ),
+            }
+            notify_double_spend(&state, &double_spend);
+        }
+    })
+}
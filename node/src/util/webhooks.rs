@@ -0,0 +1,371 @@
+use std::{fmt, sync::Arc};
+
+use anyhow::{Context, Result};
+use btclib::{
+    custom_sha_types::Hash,
+    types::{Block, BlockHeight, Transaction},
+};
+use hmac::{Hmac, Mac};
+use log::{debug, warn};
+use serde::Deserialize;
+use sha2::Sha256;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel},
+    task::JoinHandle,
+    time::{self, Duration},
+};
+
+use crate::{
+    NodeState,
+    util::{ChainConflict, ChainObserver, DoubleSpend, RejectionStorm, register_observer},
+};
+
+// first wait before retrying a failed delivery, doubled after every try
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+// a webhook that doesn't answer within this long counts as failed
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Events a webhook can be sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEventKind {
+    /// A block became the new best block
+    BlockConnected,
+    /// The best block was removed from the chain by a reorganization
+    Reorg,
+    /// A transaction moving at least `large_tx_threshold` entered the mempool
+    LargeTxDetected,
+    /// A peer serves a longer chain forking off deeper than the node
+    /// reorganizes
+    ChainConflict,
+    /// More invalid blocks or transactions arrived within a minute than
+    /// the rejection limits allow
+    RejectionStorm,
+    /// An output watched by the node's watchtower was spent twice
+    DoubleSpend,
+}
+
+impl fmt::Display for WebhookEventKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WebhookEventKind::BlockConnected => write!(f, "block_connected"),
+            WebhookEventKind::Reorg => write!(f, "reorg"),
+            WebhookEventKind::LargeTxDetected => write!(f, "large_tx_detected"),
+            WebhookEventKind::ChainConflict => write!(f, "chain_conflict"),
+            WebhookEventKind::RejectionStorm => write!(f, "rejection_storm"),
+            WebhookEventKind::DoubleSpend => write!(f, "double_spend"),
+        }
+    }
+}
+
+/// An HTTP endpoint events are POSTed to as JSON.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Webhook {
+    /// `http://host[:port]/path`; put a TLS-terminating proxy in front
+    /// for HTTPS endpoints
+    pub url: String,
+    /// Key the body is signed with, see `sign_payload`
+    pub secret: Option<String>,
+    /// Events to send; all of them if empty
+    #[serde(default)]
+    pub events: Vec<WebhookEventKind>,
+}
+
+impl Webhook {
+    pub fn new(url: impl Into<String>) -> Self {
+        Webhook {
+            url: url.into(),
+            secret: None,
+            events: vec![],
+        }
+    }
+
+    pub fn wants(&self, kind: WebhookEventKind) -> bool {
+        self.events.is_empty() || self.events.contains(&kind)
+    }
+}
+
+/// The webhooks of a node and when to call them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebhookConfig {
+    pub hooks: Vec<Webhook>,
+    /// Total output value, in satoshis, from which an accepted
+    /// transaction is reported as `large_tx_detected`
+    pub large_tx_threshold: u64,
+    /// Times a failed delivery is retried before the event is dropped
+    pub max_retries: u32,
+}
+
+impl WebhookConfig {
+    /// Checks that every webhook URL can be posted to.
+    pub fn validate(&self) -> Result<()> {
+        for hook in &self.hooks {
+            parse_webhook_url(&hook.url)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        WebhookConfig {
+            hooks: vec![],
+            large_tx_threshold: 100 * 10u64.pow(8),
+            max_retries: 5,
+        }
+    }
+}
+
+/// A JSON event, ready to be sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebhookEvent {
+    pub kind: WebhookEventKind,
+    pub body: String,
+}
+
+fn hex_hash(hash: &Hash) -> String {
+    hex::encode(hash.as_bytes())
+}
+
+// the bodies hold nothing but hex strings, numbers, timestamps and peer
+// addresses, so they are written out directly
+impl WebhookEvent {
+    pub fn block_connected(block: &Block, height: BlockHeight) -> Self {
+        WebhookEvent {
+            kind: WebhookEventKind::BlockConnected,
+            body: format!(
+                r#"{{"event":"block_connected","hash":"{}","height":{height},"prev":"{}","timestamp":"{}","transactions":{}}}"#,
+                hex_hash(&block.hash()),
+                hex_hash(block.header().prev_block_hash()),
+                block.header().timestamp().to_rfc3339(),
+                block.transactions().len()
+            ),
+        }
+    }
+
+    pub fn reorg(block: &Block, height: BlockHeight) -> Self {
+        WebhookEvent {
+            kind: WebhookEventKind::Reorg,
+            body: format!(
+                r#"{{"event":"reorg","disconnected_hash":"{}","disconnected_height":{height}}}"#,
+                hex_hash(&block.hash())
+            ),
+        }
+    }
+
+    pub fn large_tx_detected(transaction: &Transaction, value: u64) -> Self {
+        WebhookEvent {
+            kind: WebhookEventKind::LargeTxDetected,
+            body: format!(
+                r#"{{"event":"large_tx_detected","hash":"{}","value":{value},"outputs":{}}}"#,
+                hex_hash(&transaction.hash()),
+                transaction.outputs().len()
+            ),
+        }
+    }
+
+    pub fn chain_conflict(conflict: &ChainConflict) -> Self {
+        let height = match conflict.tip {
+            Some(tip) => tip.height.to_string(),
+            None => "null".to_string(),
+        };
+        WebhookEvent {
+            kind: WebhookEventKind::ChainConflict,
+            body: format!(
+                r#"{{"event":"chain_conflict","peer":"{}","height":{height},"peer_hash":"{}","peer_height":{},"max_depth":{}}}"#,
+                conflict.peer.escape_default(),
+                hex_hash(&conflict.peer_tip.hash),
+                conflict.peer_tip.height,
+                conflict.max_depth
+            ),
+        }
+    }
+
+    pub fn rejection_storm(storm: &RejectionStorm) -> Self {
+        WebhookEvent {
+            kind: WebhookEventKind::RejectionStorm,
+            body: format!(
+                r#"{{"event":"rejection_storm","kind":"{}","count":{},"limit":{}}}"#,
+                storm.kind, storm.count, storm.limit
+            ),
+        }
+    }
+
+    pub fn double_spend(double_spend: &DoubleSpend) -> Self {
+        let height = match double_spend.height {
+            Some(height) => height.to_string(),
+            None => "null".to_string(),
+        };
+        WebhookEvent {
+            kind: WebhookEventKind::DoubleSpend,
+            body: format!(
+                r#"{{"event":"double_spend","outpoint":"{}","first":"{}","second":"{}","height":{height}}}"#,
+                hex_hash(&double_spend.outpoint),
+                hex_hash(&double_spend.first),
+                hex_hash(&double_spend.second)
+            ),
+        }
+    }
+}
+
+/// Hex HMAC-SHA256 of `body` under `secret`, sent as
+/// `X-Signature: sha256=<hex>` so receivers can check the event came
+/// from this node.
+pub fn sign_payload(secret: &str, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("BUG: HMAC takes any key length");
+    mac.update(body.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Splits an `http://` URL into the address to connect to and the path.
+pub fn parse_webhook_url(url: &str) -> Result<(String, String)> {
+    let rest = url
+        .strip_prefix("http://")
+        .with_context(|| format!("webhook {url} is not an http:// URL"))?;
+    let (host, path) = match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, "/"),
+    };
+    anyhow::ensure!(!host.is_empty(), "webhook {url} has no host");
+    let address = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{host}:80")
+    };
+    Ok((address, path.to_string()))
+}
+
+/// Turns chain and mempool events into webhook events for
+/// `deliver_webhooks`.
+#[derive(Debug)]
+pub struct WebhookNotifier {
+    events: UnboundedSender<WebhookEvent>,
+    large_tx_threshold: u64,
+}
+
+impl WebhookNotifier {
+    pub fn new(events: UnboundedSender<WebhookEvent>, large_tx_threshold: u64) -> Self {
+        WebhookNotifier {
+            events,
+            large_tx_threshold,
+        }
+    }
+
+    fn send(&self, event: WebhookEvent) {
+        // the delivery task is gone once the node stops
+        let _ = self.events.send(event);
+    }
+}
+
+impl ChainObserver for WebhookNotifier {
+    fn on_block_connected(&self, block: &Block, height: BlockHeight) {
+        self.send(WebhookEvent::block_connected(block, height));
+    }
+
+    fn on_block_disconnected(&self, block: &Block, height: BlockHeight) {
+        self.send(WebhookEvent::reorg(block, height));
+    }
+
+    fn on_tx_accepted(&self, transaction: &Transaction) {
+        let value = transaction
+            .outputs()
+            .iter()
+            .map(|output| output.value())
+            .sum::<u64>();
+        if value >= self.large_tx_threshold {
+            self.send(WebhookEvent::large_tx_detected(transaction, value));
+        }
+    }
+
+    fn on_chain_conflict(&self, conflict: &ChainConflict) {
+        self.send(WebhookEvent::chain_conflict(conflict));
+    }
+
+    fn on_rejection_storm(&self, storm: &RejectionStorm) {
+        self.send(WebhookEvent::rejection_storm(storm));
+    }
+
+    fn on_double_spend(&self, double_spend: &DoubleSpend) {
+        self.send(WebhookEvent::double_spend(double_spend));
+    }
+}
+
+/// POSTs `event` to `hook` once. Succeeds on a 2xx response.
+pub async fn post_event(hook: &Webhook, event: &WebhookEvent) -> Result<()> {
+    let (address, path) = parse_webhook_url(&hook.url)?;
+    let host = address.trim_end_matches(":80");
+    let mut request = format!(
+        "POST {path} HTTP/1.1\r\nHost: {host}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nX-Webhook-Event: {}\r\nConnection: close\r\n",
+        event.body.len(),
+        event.kind
+    );
+    if let Some(secret) = &hook.secret {
+        request += &format!(
+            "X-Signature: sha256={}\r\n",
+            sign_payload(secret, &event.body)
+        );
+    }
+    request += "\r\n";
+    request += &event.body;
+
+    let exchange = async {
+        let mut stream = TcpStream::connect(&address).await?;
+        stream.write_all(request.as_bytes()).await?;
+        // the status line is all we need
+        let mut response = vec![0; 64];
+        let read = stream.read(&mut response).await?;
+        anyhow::Ok(String::from_utf8_lossy(&response[..read]).into_owned())
+    };
+    let response = time::timeout(DELIVERY_TIMEOUT, exchange)
+        .await
+        .context("webhook timed out")??;
+    let status = response
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse::<u16>().ok())
+        .context("webhook sent no HTTP status")?;
+    anyhow::ensure!((200..300).contains(&status), "webhook answered {status}");
+    Ok(())
+}
+
+/// Sends every event to the webhooks that want it, in order. A failed
+/// delivery is retried with exponential backoff, `max_retries` times,
+/// holding back the events after it.
+pub async fn deliver_webhooks(config: WebhookConfig, mut events: UnboundedReceiver<WebhookEvent>) {
+    while let Some(event) = events.recv().await {
+        for hook in config.hooks.iter().filter(|hook| hook.wants(event.kind)) {
+            let mut delay = RETRY_DELAY;
+            for attempt in 0..=config.max_retries {
+                match post_event(hook, &event).await {
+                    Ok(()) => {
+                        debug!("sent {} to {}", event.kind, hook.url);
+                        break;
+                    }
+                    Err(e) if attempt < config.max_retries => {
+                        debug!("webhook {} failed, retrying in {delay:?}: {e}", hook.url);
+                        time::sleep(delay).await;
+                        delay = (delay * 2).min(MAX_RETRY_DELAY);
+                    }
+                    Err(e) => warn!("dropping {} for webhook {}: {e}", event.kind, hook.url),
+                }
+            }
+        }
+    }
+}
+
+/// Registers the observer that produces the events and spawns the task
+/// delivering them.
+pub fn start_webhooks(state: &NodeState, config: WebhookConfig) -> JoinHandle<()> {
+    let (sender, receiver) = unbounded_channel();
+    register_observer(
+        state,
+        Arc::new(WebhookNotifier::new(sender, config.large_tx_threshold)),
+    );
+    tokio::spawn(deliver_webhooks(config, receiver))
+}
@@ -0,0 +1,67 @@
+use std::time::Instant;
+
+use btclib::{client::NodeClient, custom_sha_types::Hash, network::ZeroConfRisk};
+use log::info;
+use tokio::time;
+
+use crate::{
+    NodeState,
+    util::{message_timeout, record_response},
+};
+
+/// Scores the mempool transaction `hash` for a merchant deciding whether
+/// to accept it unconfirmed, `None` if it isn't in the mempool. Every
+/// peer is asked over a new connection whether it has the transaction
+/// too (`FetchTransactions`); peers that don't answer in time or keep no
+/// mempool aren't counted.
+pub async fn assess_zero_conf_risk(state: &NodeState, hash: Hash) -> Option<ZeroConfRisk> {
+    let in_mempool = state
+        .blockchain
+        .read()
+        .await
+        .mempool()
+        .iter()
+        .any(|(_, transaction)| transaction.hash() == hash);
+    if !in_mempool {
+        return None;
+    }
+    let timeout = message_timeout(state);
+    let peers = state
+        .nodes
+        .iter()
+        .map(|entry| entry.key().clone())
+        .collect::<Vec<_>>();
+    let mut peers_asked = 0;
+    let mut peers_with_transaction = 0;
+    for peer in peers {
+        // a connection of our own, so relaying isn't held up meanwhile
+        let sent = Instant::now();
+        let answer = time::timeout(timeout, async {
+            let mut client = NodeClient::connect(&peer).await?;
+            client.get_transactions(vec![hash]).await
+        })
+        .await;
+        record_response(state, &peer, sent, matches!(answer, Ok(Ok(_))));
+        if let Ok(Ok(transactions)) = answer {
+            peers_asked += 1;
+            if transactions
+                .iter()
+                .any(|transaction| transaction.hash() == hash)
+            {
+                peers_with_transaction += 1;
+            }
+        }
+    }
+    let risk =
+        state
+            .blockchain
+            .read()
+            .await
+            .zero_conf_risk(&hash, peers_asked, peers_with_transaction)?;
+    info!(
+        "zero-conf risk of {hash:x?}: {:.2} ({:?})",
+        risk.score(),
+        risk.level()
+    );
+    Some(risk)
+}
@@ -1,48 +1,2856 @@
-use node::{BLOCKCHAIN, NODES};
+mod netem;
+
+use std::sync::Arc;
+
+use btclib::{client::NodeClient, testutil::extend_chain, types::BlockHeight};
+use netem::{Conditions, Netem};
+use node::{Node, NodeConfig, NodeState};
+
+fn temp_blockchain_file(name: &str) -> String {
+    std::env::temp_dir()
+        .join(format!("{name}-{}.cbor", uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn remove_node_files(blockchain_file: &str) {
+    for suffix in ["", ".wal", ".readonly.token", ".admin.token"] {
+        std::fs::remove_file(format!("{blockchain_file}{suffix}")).ok();
+    }
+}
 
 #[tokio::test]
 async fn test_blockchain_initialization() {
-    let blockchain = BLOCKCHAIN.read().await;
-    // A default blockchain should start with genesis or be empty
-    assert!(blockchain.blocks().is_empty() || !blockchain.blocks().is_empty());
+    let state = NodeState::default();
+    let blockchain = state.blockchain.read().await;
+    // A fresh node starts with an empty blockchain
+    assert!(blockchain.blocks().is_empty());
 }
 
 #[tokio::test]
 async fn test_nodes_map_initialization() {
-    // The NODES map should be accessible
-    let nodes_count = NODES.len();
-    assert!(nodes_count == 0 || nodes_count > 0);
+    let state = NodeState::default();
+    assert!(state.nodes.is_empty());
 }
 
 #[tokio::test]
 async fn test_blockchain_write_lock() {
+    let state = NodeState::default();
     {
-        let blockchain = BLOCKCHAIN.write().await;
+        let blockchain = state.blockchain.write().await;
         // Should be able to acquire write lock
         let _initial_len = blockchain.blocks().len();
         // Lock acquired successfully
     }
     // Lock should be released after scope
-    let blockchain = BLOCKCHAIN.read().await;
+    let blockchain = state.blockchain.read().await;
     let _len = blockchain.blocks().len();
     // Lock can be acquired again
 }
 
 #[tokio::test]
 async fn test_concurrent_blockchain_reads() {
-    let handle1 = tokio::spawn(async {
-        let blockchain = BLOCKCHAIN.read().await;
+    let state = Arc::new(NodeState::default());
+    let state1 = state.clone();
+    let handle1 = tokio::spawn(async move {
+        let blockchain = state1.blockchain.read().await;
         blockchain.blocks().len()
     });
 
-    let handle2 = tokio::spawn(async {
-        let blockchain = BLOCKCHAIN.read().await;
+    let state2 = state.clone();
+    let handle2 = tokio::spawn(async move {
+        let blockchain = state2.blockchain.read().await;
         blockchain.blocks().len()
     });
 
     let result1 = handle1.await.unwrap();
     let result2 = handle2.await.unwrap();
-    
+
     // Both reads should succeed and return the same length
     assert_eq!(result1, result2);
 }
+
+#[tokio::test]
+async fn test_two_nodes_in_one_process() {
+    let seed_file = temp_blockchain_file("embedded-seed");
+    let peer_file = temp_blockchain_file("embedded-peer");
+
+    let mut seed_config = NodeConfig::new(&seed_file);
+    seed_config.port = 0;
+    let mut seed = Node::new(seed_config);
+    let seed_addr = seed.start().await.unwrap();
+
+    let mut peer_config = NodeConfig::new(&peer_file);
+    peer_config.port = 0;
+    peer_config.nodes = vec![format!("127.0.0.1:{}", seed_addr.port())];
+    let mut peer = Node::new(peer_config);
+    let peer_addr = peer.start().await.unwrap();
+
+    // each node has its own state
+    assert_ne!(seed_addr.port(), peer_addr.port());
+    assert_eq!(peer.state().nodes.len(), 1);
+    assert!(seed.state().nodes.is_empty());
+
+    let mut client = NodeClient::connect(("127.0.0.1", peer_addr.port()))
+        .await
+        .unwrap();
+    assert_eq!(
+        client.get_difference(BlockHeight::GENESIS).await.unwrap(),
+        0
+    );
+    drop(client);
+
+    seed.stop().await.unwrap();
+    peer.stop().await.unwrap();
+    // stopping saves the blockchain and closes the listener
+    assert!(std::path::Path::new(&seed_file).exists());
+    assert!(
+        NodeClient::connect(("127.0.0.1", seed_addr.port()))
+            .await
+            .is_err()
+    );
+
+    remove_node_files(&seed_file);
+    remove_node_files(&peer_file);
+}
+
+#[tokio::test]
+async fn test_blocks_are_relayed_once() {
+    use btclib::{
+        crypto::PrivateKey,
+        custom_sha_types::Hash,
+        types::{Block, BlockHeader, Transaction, TransactionOutput},
+        utils::MerkleRoot,
+    };
+    use tokio::time::{Duration, sleep};
+
+    let seed_file = temp_blockchain_file("relay-seed");
+    let peer_file = temp_blockchain_file("relay-peer");
+    let mut seed_config = NodeConfig::new(&seed_file);
+    seed_config.port = 0;
+    let mut seed = Node::new(seed_config);
+    let seed_addr = seed.start().await.unwrap();
+    let mut peer_config = NodeConfig::new(&peer_file);
+    peer_config.port = 0;
+    peer_config.nodes = vec![format!("127.0.0.1:{}", seed_addr.port())];
+    let mut peer = Node::new(peer_config);
+    let peer_addr = peer.start().await.unwrap();
+
+    let transactions = vec![Transaction::new(
+        vec![],
+        vec![TransactionOutput::new(
+            5000000000,
+            uuid::Uuid::new_v4(),
+            PrivateKey::from_seed(b"miner").public_key(),
+        )],
+    )];
+    let merkle_root = MerkleRoot::calculate(&transactions);
+    let header = BlockHeader::new(
+        chrono::Utc::now(),
+        0,
+        Hash::zero(),
+        merkle_root,
+        btclib::MIN_TARGET,
+    );
+    let block = Block::new(header, transactions);
+
+    // mined at the peer, which passes it on to the seed right away
+    let mut miner = NodeClient::connect(("127.0.0.1", peer_addr.port()))
+        .await
+        .unwrap();
+    miner.submit_template(block.clone()).await.unwrap();
+    let mut relayed = false;
+    for _ in 0..50 {
+        if seed.state().blockchain.read().await.blocks().len() == 1 {
+            relayed = true;
+            break;
+        }
+        sleep(Duration::from_millis(20)).await;
+    }
+    assert!(relayed, "block didn't reach the seed");
+    let seed_name = peer.state().nodes.iter().next().unwrap().key().clone();
+    assert!(
+        peer.state()
+            .known_inventory
+            .lock()
+            .unwrap()
+            .knows(&seed_name, &block.hash())
+    );
+
+    // both nodes recorded when the block reached them
+    let mined = miner.get_block_timings(block.hash()).await.unwrap();
+    assert_eq!(mined.height, BlockHeight::GENESIS);
+    assert_eq!(mined.relayed_by, None);
+    let mut client = NodeClient::connect(("127.0.0.1", seed_addr.port()))
+        .await
+        .unwrap();
+    let relayed = client.get_block_timings(block.hash()).await.unwrap();
+    assert!(relayed.relayed_by.is_some());
+    assert!(relayed.received_at >= mined.received_at);
+    assert!(relayed.connected_at >= relayed.received_at);
+    assert!(client.get_block_timings(Hash::zero()).await.is_err());
+    let health = client.get_health().await.unwrap();
+    assert_eq!(health.propagation_samples, 1);
+    drop(client);
+    drop(miner);
+
+    seed.stop().await.unwrap();
+    peer.stop().await.unwrap();
+    remove_node_files(&seed_file);
+    remove_node_files(&peer_file);
+}
+
+#[tokio::test]
+async fn test_node_survives_bad_peers() {
+    use node::{
+        handler::handle_connection,
+        util::{Attack, run_attack},
+    };
+    use tokio::{net::TcpListener, time::Duration};
+
+    let state = Arc::new(NodeState::default());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let (connections_sender, connections) = std::sync::mpsc::channel();
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            let connection = handle_connection(state.clone(), socket);
+            let _ = connections_sender.send(tokio::spawn(connection));
+        }
+    });
+
+    for attack in Attack::ALL {
+        run_attack(address, attack, Duration::from_millis(1))
+            .await
+            .unwrap_or_else(|e| panic!("{attack:?}: {e}"));
+    }
+
+    // still serving well-behaved clients
+    let mut client = NodeClient::connect(address).await.unwrap();
+    client.get_difference(BlockHeight::GENESIS).await.unwrap();
+    drop(client);
+
+    // and no connection task outlives its attacker
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let handles: Vec<_> = connections.try_iter().collect();
+    assert_eq!(handles.len(), Attack::ALL.len() + 1);
+    assert!(handles.iter().all(|handle| handle.is_finished()));
+}
+
+#[tokio::test]
+async fn test_difference_to_far_heights_is_clamped() {
+    use btclib::network::Message;
+    use node::handler::serve_connection;
+
+    let state = Arc::new(NodeState::default());
+    let (mut client, server) = tokio::io::duplex(64 * 1024);
+    let connection = tokio::spawn(serve_connection(state, server, None));
+
+    for height in [1 << 32, u64::MAX] {
+        Message::AskDifference(BlockHeight::new(height))
+            .send_async(&mut client)
+            .await
+            .unwrap();
+        match Message::receive_async(&mut client).await.unwrap() {
+            Message::Difference(count) => assert_eq!(count, i32::MIN),
+            other => panic!("unexpected reply {}", other.kind()),
+        }
+    }
+    drop(client);
+    connection.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_sync_connects_blocks_spending_earlier_ones() {
+    use btclib::{
+        crypto::{PrivateKey, Signature},
+        types::{BlockBuilder, Blockchain, Transaction, TransactionInput, TransactionOutput},
+    };
+
+    let key = PrivateKey::default();
+    let spend = |output: &TransactionOutput| {
+        Transaction::new(
+            vec![TransactionInput::new(
+                output.hash(),
+                Signature::sign_output(&output.hash(), &key),
+            )],
+            vec![TransactionOutput::new(
+                output.value(),
+                uuid::Uuid::new_v4(),
+                key.public_key(),
+            )],
+        )
+    };
+    let mut chain = Blockchain::default();
+    chain
+        .add_block(BlockBuilder::on(&chain).build(key.public_key()))
+        .unwrap();
+    chain.rebuild_utxos();
+    let mut output = chain.blocks()[0].transactions()[0].outputs()[0].clone();
+    // each block spends the output of the one before, the first twice over
+    for spends in [2, 1, 1] {
+        let mut builder = BlockBuilder::on(&chain);
+        for _ in 0..spends {
+            let transaction = spend(&output);
+            output = transaction.outputs()[0].clone();
+            assert!(builder.add_transaction(transaction, 0));
+        }
+        chain.add_block(builder.build(key.public_key())).unwrap();
+        chain.rebuild_utxos();
+    }
+
+    let source_file = temp_blockchain_file("sync-source");
+    let mut config = NodeConfig::new(&source_file);
+    config.port = 0;
+    let mut source = Node::new(config);
+    let source_addr = format!("127.0.0.1:{}", source.start().await.unwrap().port());
+    let mut client = NodeClient::connect(source_addr.as_str()).await.unwrap();
+    for block in chain.blocks() {
+        client.submit_template(block.clone()).await.unwrap();
+    }
+    drop(client);
+
+    let file = temp_blockchain_file("sync-spends");
+    let mut config = NodeConfig::new(&file);
+    config.port = 0;
+    config.nodes = vec![source_addr];
+    let mut node = Node::new(config);
+    node.start().await.unwrap();
+
+    let blockchain = node.state().blockchain.read().await;
+    assert_eq!(blockchain.tip(), chain.tip());
+    let mut utxos: Vec<_> = blockchain.utxos().into_keys().collect();
+    let mut expected: Vec<_> = chain.utxos().into_keys().collect();
+    utxos.sort_by_key(|hash| hash.as_bytes());
+    expected.sort_by_key(|hash| hash.as_bytes());
+    assert_eq!(utxos, expected);
+    drop(blockchain);
+
+    node.stop().await.unwrap();
+    source.stop().await.unwrap();
+    remove_node_files(&file);
+    remove_node_files(&source_file);
+}
+
+#[tokio::test]
+async fn test_sync_fails_over_from_stalled_peer() {
+    use btclib::{
+        network::{Message, NetworkLimits},
+        types::Blockchain,
+    };
+    use tokio::{
+        net::TcpListener,
+        time::{Duration, sleep},
+    };
+
+    let mut chain = Blockchain::default();
+    extend_chain(&mut chain, 3);
+
+    let good_file = temp_blockchain_file("sync-good");
+    let mut config = NodeConfig::new(&good_file);
+    config.port = 0;
+    let mut good = Node::new(config);
+    let good_addr = format!("127.0.0.1:{}", good.start().await.unwrap().port());
+    let mut client = NodeClient::connect(good_addr.as_str()).await.unwrap();
+    for block in chain.blocks() {
+        client.submit_template(block.clone()).await.unwrap();
+    }
+    drop(client);
+
+    // claims a longer chain, serves its first block and then goes silent
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let stalled_addr = listener.local_addr().unwrap().to_string();
+    let genesis = chain.blocks()[0].clone();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let genesis = genesis.clone();
+            tokio::spawn(async move {
+                while let Ok(message) = Message::receive_async(&mut socket).await {
+                    let response = match message {
+                        Message::DiscoverNodes => Message::NodeList(vec![]),
+                        Message::AskDifference(_) => Message::Difference(10),
+                        Message::FetchBlock(height) if height == BlockHeight::GENESIS => {
+                            Message::NewBlock(genesis.clone())
+                        }
+                        Message::FetchBlock(_) => {
+                            sleep(Duration::from_secs(60)).await;
+                            return;
+                        }
+                        _ => continue,
+                    };
+                    if response.send_async(&mut socket).await.is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+
+    let file = temp_blockchain_file("sync-failover");
+    let mut config = NodeConfig::new(&file);
+    config.port = 0;
+    config.nodes = vec![stalled_addr.clone(), good_addr];
+    config.limits = NetworkLimits {
+        message_timeout_secs: 1,
+        ..NetworkLimits::default()
+    };
+    let mut node = Node::new(config);
+    node.start().await.unwrap();
+
+    // the good peer took over from the block the stalled one served
+    let blockchain = node.state().blockchain.read().await;
+    assert_eq!(blockchain.blocks().len(), 3);
+    assert_eq!(blockchain.tip(), chain.tip());
+    drop(blockchain);
+    assert!(!node.state().nodes.contains_key(&stalled_addr));
+
+    node.stop().await.unwrap();
+    good.stop().await.unwrap();
+    remove_node_files(&file);
+    remove_node_files(&good_file);
+}
+
+#[tokio::test]
+async fn test_sync_progress_is_served() {
+    let mut chain = btclib::types::Blockchain::default();
+    extend_chain(&mut chain, 5);
+
+    let source_file = temp_blockchain_file("progress-source");
+    let mut config = NodeConfig::new(&source_file);
+    config.port = 0;
+    let mut source = Node::new(config);
+    let source_addr = format!("127.0.0.1:{}", source.start().await.unwrap().port());
+    let mut client = NodeClient::connect(source_addr.as_str()).await.unwrap();
+    for block in chain.blocks() {
+        client.submit_template(block.clone()).await.unwrap();
+    }
+    client.get_difference(BlockHeight::GENESIS).await.unwrap();
+    // the source loaded nothing and downloaded nothing
+    assert_eq!(client.get_sync_progress().await.unwrap(), None);
+    drop(client);
+
+    let file = temp_blockchain_file("progress-sync");
+    let mut config = NodeConfig::new(&file);
+    config.port = 0;
+    config.nodes = vec![source_addr.clone()];
+    let mut node = Node::new(config);
+    let addr = node.start().await.unwrap();
+
+    let mut client = NodeClient::connect(("127.0.0.1", addr.port()))
+        .await
+        .unwrap();
+    let progress = client.get_sync_progress().await.unwrap().unwrap();
+    assert_eq!(progress.peer, source_addr);
+    assert_eq!(progress.total(), 5);
+    assert_eq!(progress.downloaded, 5);
+    assert_eq!(progress.connected, 5);
+    assert!(progress.done);
+    assert!(progress.to_string().ends_with(", done"));
+    drop(client);
+
+    node.stop().await.unwrap();
+    source.stop().await.unwrap();
+    remove_node_files(&file);
+    remove_node_files(&source_file);
+}
+
+#[tokio::test]
+async fn test_network_limits_are_configurable() {
+    use btclib::network::{Message, NetworkLimits};
+    use tokio::io::AsyncReadExt;
+
+    let blockchain_file = temp_blockchain_file("limits");
+    let mut config = NodeConfig::new(&blockchain_file);
+    config.port = 0;
+    config.limits = NetworkLimits {
+        max_message_size: 64 * 1024,
+        max_connections: 4,
+        ..NetworkLimits::default()
+    };
+    config.relay_policy.max_tx_size = 32 * 1024;
+    let mut node = Node::new(config);
+    let addr = node.start().await.unwrap();
+
+    let mut client = NodeClient::connect(("127.0.0.1", addr.port()))
+        .await
+        .unwrap();
+    let info = client.get_network_info().await.unwrap();
+    assert_eq!(info.limits.max_message_size, 64 * 1024);
+    assert_eq!(info.limits.max_connections, 4);
+    assert_eq!(info.connections, 1);
+
+    // a message within the default limit but over the configured one
+    let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", addr.port()))
+        .await
+        .unwrap();
+    Message::NodeList(vec!["x".repeat(100 * 1024)])
+        .send_async(&mut stream)
+        .await
+        .unwrap();
+    let mut buffer = [0u8; 64];
+    let closed = tokio::time::timeout(std::time::Duration::from_secs(5), stream.read(&mut buffer))
+        .await
+        .unwrap();
+    assert!(matches!(closed, Ok(0) | Err(_)));
+
+    drop(client);
+    node.stop().await.unwrap();
+    remove_node_files(&blockchain_file);
+}
+
+#[tokio::test]
+async fn test_invalid_network_limits_refused() {
+    let blockchain_file = temp_blockchain_file("bad-limits");
+    let mut config = NodeConfig::new(&blockchain_file);
+    config.port = 0;
+    config.limits.max_connections = 0;
+    let mut node = Node::new(config);
+
+    let error = node.start().await.unwrap_err();
+    assert!(format!("{error:#}").contains("at least one connection"));
+    assert!(node.local_addr().is_none());
+    remove_node_files(&blockchain_file);
+}
+
+#[tokio::test]
+async fn test_wallet_session() {
+    use btclib::{
+        crypto::PrivateKey,
+        error::ClientError,
+        network::{NetworkLimits, PeerKind, RejectCode},
+    };
+
+    let blockchain_file = temp_blockchain_file("session");
+    let mut config = NodeConfig::new(&blockchain_file);
+    config.port = 0;
+    config.limits = NetworkLimits {
+        max_wallets: 1,
+        max_idle_secs: 1,
+        ..NetworkLimits::default()
+    };
+    let mut node = Node::new(config);
+    let addr = node.start().await.unwrap();
+    let connect = || NodeClient::connect(("127.0.0.1", addr.port()));
+
+    let mut wallet = connect().await.unwrap();
+    let session = wallet.hello(PeerKind::Wallet, 60).await.unwrap();
+    assert_eq!(session.idle_timeout_secs, Some(1));
+    let key = PrivateKey::default().public_key();
+    wallet.get_utxos(&key).await.unwrap();
+    // wallets don't mine
+    let refused = wallet.get_template(&key, None).await.unwrap_err();
+    assert!(matches!(
+        refused,
+        ClientError::Rejected {
+            code: RejectCode::Unsupported,
+            ..
+        }
+    ));
+
+    // one wallet at a time, but other kinds are served
+    let refused = connect()
+        .await
+        .unwrap()
+        .hello(PeerKind::Wallet, 0)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        refused,
+        ClientError::Rejected {
+            code: RejectCode::Busy,
+            ..
+        }
+    ));
+    let mut miner = connect().await.unwrap();
+    miner.hello(PeerKind::Miner, 0).await.unwrap();
+    assert_eq!(miner.get_network_info().await.unwrap().wallets, 1);
+
+    // pinging keeps the session open past its idle timeout...
+    for _ in 0..3 {
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        wallet.ping().await.unwrap();
+    }
+    // ...and staying silent closes it, freeing its slot
+    tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+    assert!(wallet.ping().await.is_err());
+    let mut next = connect().await.unwrap();
+    next.hello(PeerKind::Wallet, 0).await.unwrap();
+
+    node.stop().await.unwrap();
+    remove_node_files(&blockchain_file);
+}
+
+#[tokio::test]
+async fn test_seed_only_node_serves_addresses() {
+    use btclib::{error::ClientError, network::RejectCode};
+    use tokio::time::{Duration, sleep};
+
+    let seed_file = temp_blockchain_file("seed-only");
+    let mut seed_config = NodeConfig::new(&seed_file);
+    seed_config.port = 0;
+    seed_config.seed_only = true;
+    let mut seed = Node::new(seed_config);
+    let seed_addr = seed.start().await.unwrap();
+    let seed_address = format!("127.0.0.1:{}", seed_addr.port());
+
+    // a full node bootstrapping from the seed advertises itself to it
+    let full_file = temp_blockchain_file("seed-full");
+    let mut full_config = NodeConfig::new(&full_file);
+    full_config.port = 0;
+    full_config.nodes = vec![seed_address.clone()];
+    let mut full = Node::new(full_config);
+    let full_addr = full.start().await.unwrap();
+    let full_address = format!("127.0.0.1:{}", full_addr.port());
+    for _ in 0..50 {
+        if !seed.state().address_book.lock().unwrap().is_empty() {
+            break;
+        }
+        sleep(Duration::from_millis(20)).await;
+    }
+
+    let mut client = NodeClient::connect(&seed_address).await.unwrap();
+    assert_eq!(
+        client.discover_nodes().await.unwrap(),
+        vec![full_address.clone()]
+    );
+    // the seed keeps no blockchain to serve
+    assert!(matches!(
+        client.get_block(BlockHeight::GENESIS).await,
+        Err(ClientError::Rejected {
+            code: RejectCode::Unsupported,
+            ..
+        })
+    ));
+    drop(client);
+
+    // the next node finds the full node through the seed
+    let next_file = temp_blockchain_file("seed-next");
+    let mut next_config = NodeConfig::new(&next_file);
+    next_config.port = 0;
+    next_config.nodes = vec![seed_address];
+    let mut next = Node::new(next_config);
+    next.start().await.unwrap();
+    assert!(next.state().nodes.contains_key(&full_address));
+
+    next.stop().await.unwrap();
+    full.stop().await.unwrap();
+    seed.stop().await.unwrap();
+    // nothing was stored for the seed
+    assert!(!std::path::Path::new(&seed_file).exists());
+    for file in [&seed_file, &full_file, &next_file] {
+        remove_node_files(file);
+    }
+}
+
+#[tokio::test]
+async fn test_archive_node_answers_history_queries() {
+    use btclib::{
+        crypto::PrivateKey,
+        custom_sha_types::Hash,
+        error::ClientError,
+        network::RejectCode,
+        types::{Block, BlockHeader, Transaction, TransactionOutput},
+        utils::MerkleRoot,
+    };
+
+    let archive_file = temp_blockchain_file("archive");
+    let mut config = NodeConfig::new(&archive_file);
+    config.port = 0;
+    config.archive = true;
+    let mut archive = Node::new(config);
+    let archive_addr = archive.start().await.unwrap();
+
+    let miner_key = PrivateKey::from_seed(b"miner").public_key();
+    let transactions = vec![Transaction::new(
+        vec![],
+        vec![TransactionOutput::new(
+            5000000000,
+            uuid::Uuid::new_v4(),
+            miner_key.clone(),
+        )],
+    )];
+    let header = BlockHeader::new(
+        chrono::Utc::now(),
+        0,
+        Hash::zero(),
+        MerkleRoot::calculate(&transactions),
+        btclib::MIN_TARGET,
+    );
+    let block = Block::new(header, transactions);
+    let mut client = NodeClient::connect(("127.0.0.1", archive_addr.port()))
+        .await
+        .unwrap();
+    client.submit_template(block.clone()).await.unwrap();
+    // answered in order, so the block is in by the time this returns
+    client.get_difference(BlockHeight::GENESIS).await.unwrap();
+
+    let next = BlockHeight::new(1);
+    assert_eq!(
+        client
+            .get_balance_at(miner_key.clone(), BlockHeight::GENESIS)
+            .await
+            .unwrap(),
+        0
+    );
+    assert_eq!(
+        client
+            .get_balance_at(miner_key.clone(), next)
+            .await
+            .unwrap(),
+        5000000000
+    );
+    let undo = client.get_undo(BlockHeight::GENESIS).await.unwrap();
+    assert_eq!(undo.block, block.hash());
+    assert!(undo.spent.is_empty());
+    assert_eq!(undo.created.len(), 1);
+    assert!(matches!(
+        client.get_undo(next).await,
+        Err(ClientError::Rejected {
+            code: RejectCode::NotFound,
+            ..
+        })
+    ));
+    drop(client);
+    archive.stop().await.unwrap();
+    remove_node_files(&archive_file);
+
+    // other nodes don't keep the history
+    let state = Arc::new(NodeState::default());
+    let (mut client, server) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(node::handler::serve_connection(state, server, None));
+    btclib::network::Message::FetchUndo(BlockHeight::GENESIS)
+        .send_async(&mut client)
+        .await
+        .unwrap();
+    assert!(matches!(
+        btclib::network::Message::receive_async(&mut client)
+            .await
+            .unwrap(),
+        btclib::network::Message::Reject {
+            code: RejectCode::Unsupported,
+            ..
+        }
+    ));
+}
+
+#[tokio::test]
+async fn test_reserve_audit() {
+    use btclib::{
+        crypto::PrivateKey,
+        types::{BlockBuilder, Blockchain, ReserveProof},
+    };
+    use node::util::audit_reserves;
+
+    let alice = PrivateKey::from_seed(b"reserve-alice");
+    let bob = PrivateKey::from_seed(b"reserve-bob");
+    let mut chain = Blockchain::default();
+    for key in [&alice, &bob, &alice] {
+        chain
+            .add_block(BlockBuilder::on(&chain).build(key.public_key()))
+            .unwrap();
+        chain.rebuild_utxos();
+    }
+
+    let archive_file = temp_blockchain_file("reserves");
+    let mut config = NodeConfig::new(&archive_file);
+    config.port = 0;
+    config.archive = true;
+    let mut archive = Node::new(config);
+    let archive_addr = archive.start().await.unwrap();
+    let mut client = NodeClient::connect(("127.0.0.1", archive_addr.port()))
+        .await
+        .unwrap();
+    for block in chain.blocks() {
+        client.submit_template(block.clone()).await.unwrap();
+    }
+    client.get_difference(BlockHeight::GENESIS).await.unwrap();
+
+    let reward = BlockHeight::GENESIS.block_reward();
+    let proof = ReserveProof::sign("audit", &[alice.clone(), bob]);
+    let report = audit_reserves(&mut client, &proof, None).await.unwrap();
+    assert_eq!(report.total(), 3 * reward);
+    assert_eq!(report.balances[0], (alice.public_key(), 2 * reward));
+    let report = audit_reserves(&mut client, &proof, Some(BlockHeight::new(2)))
+        .await
+        .unwrap();
+    assert_eq!(report.total(), 2 * reward);
+
+    // a signature over anything but the challenge fails the audit
+    let mut forged = ReserveProof::sign("audit", &[alice]);
+    forged.challenge = "another audit".to_string();
+    assert!(audit_reserves(&mut client, &forged, None).await.is_err());
+
+    drop(client);
+    archive.stop().await.unwrap();
+    remove_node_files(&archive_file);
+}
+
+#[tokio::test]
+async fn test_mirror_catches_up_with_utxo_delta() {
+    use btclib::{error::ClientError, network::RejectCode, types::Blockchain};
+
+    let mut chain = Blockchain::default();
+    extend_chain(&mut chain, 5);
+    // the mirror stopped after the first two blocks
+    let mut mirror = Blockchain::default();
+    for block in &chain.blocks()[..2] {
+        mirror.add_block(block.clone()).unwrap();
+    }
+    mirror.rebuild_utxos();
+
+    let blockchain_file = temp_blockchain_file("utxo-delta");
+    let mut config = NodeConfig::new(&blockchain_file);
+    config.port = 0;
+    let mut node = Node::new(config);
+    let addr = node.start().await.unwrap();
+    let mut client = NodeClient::connect(("127.0.0.1", addr.port()))
+        .await
+        .unwrap();
+    for block in chain.blocks() {
+        client.submit_template(block.clone()).await.unwrap();
+    }
+    client.get_difference(BlockHeight::GENESIS).await.unwrap();
+
+    let tip = mirror.block_height();
+    let delta = client
+        .get_utxo_delta(tip, BlockHeight::new(5))
+        .await
+        .unwrap();
+    let prev = mirror.blocks().last().unwrap().hash();
+    delta.check_headers(prev).unwrap();
+    let mut utxos = mirror.utxos();
+    delta.apply(&mut utxos);
+    let mut outpoints: Vec<_> = utxos.keys().map(|hash| hash.as_bytes()).collect();
+    let mut expected: Vec<_> = chain.utxos().keys().map(|hash| hash.as_bytes()).collect();
+    outpoints.sort();
+    expected.sort();
+    assert_eq!(outpoints, expected);
+
+    assert!(matches!(
+        client.get_utxo_delta(tip, BlockHeight::new(6)).await,
+        Err(ClientError::Rejected {
+            code: RejectCode::NotFound,
+            ..
+        })
+    ));
+    let far = BlockHeight::new(btclib::MAX_UTXO_DELTA_BLOCKS + 1);
+    assert!(matches!(
+        client.get_utxo_delta(BlockHeight::GENESIS, far).await,
+        Err(ClientError::Rejected {
+            code: RejectCode::Invalid,
+            ..
+        })
+    ));
+
+    drop(client);
+    node.stop().await.unwrap();
+    remove_node_files(&blockchain_file);
+}
+
+#[tokio::test]
+async fn test_consistency_check_reports_lagging_node() {
+    use btclib::{
+        crypto::PrivateKey,
+        custom_sha_types::Hash,
+        types::{Block, BlockHeader, Transaction, TransactionOutput},
+        utils::MerkleRoot,
+    };
+    use node::util::{Divergence, check_consistency};
+
+    let ahead_file = temp_blockchain_file("consistency-ahead");
+    let behind_file = temp_blockchain_file("consistency-behind");
+    let mut nodes = vec![];
+    let mut addresses = vec![];
+    for file in [&ahead_file, &behind_file] {
+        let mut config = NodeConfig::new(file);
+        config.port = 0;
+        let mut node = Node::new(config);
+        addresses.push(format!("127.0.0.1:{}", node.start().await.unwrap().port()));
+        nodes.push(node);
+    }
+
+    let transactions = vec![Transaction::new(
+        vec![],
+        vec![TransactionOutput::new(
+            5000000000,
+            uuid::Uuid::new_v4(),
+            PrivateKey::from_seed(b"miner").public_key(),
+        )],
+    )];
+    let header = BlockHeader::new(
+        chrono::Utc::now(),
+        0,
+        Hash::zero(),
+        MerkleRoot::calculate(&transactions),
+        btclib::MIN_TARGET,
+    );
+    let mut client = NodeClient::connect(addresses[0].as_str()).await.unwrap();
+    client
+        .submit_template(Block::new(header, transactions))
+        .await
+        .unwrap();
+    client.get_difference(BlockHeight::GENESIS).await.unwrap();
+    drop(client);
+
+    let unreachable = "127.0.0.1:1".to_string();
+    let mut checked = addresses.clone();
+    checked.push(unreachable.clone());
+    let (snapshots, divergences) = check_consistency(&checked, 10).await;
+    assert_eq!(snapshots.len(), 2);
+    assert_eq!(divergences.len(), 2);
+    assert!(
+        matches!(&divergences[0], Divergence::Unreachable { node, .. } if *node == unreachable)
+    );
+    assert_eq!(
+        divergences[1],
+        Divergence::Behind {
+            node: addresses[1].clone(),
+            height: None,
+            best: BlockHeight::GENESIS,
+        }
+    );
+
+    for mut node in nodes {
+        node.stop().await.unwrap();
+    }
+    remove_node_files(&ahead_file);
+    remove_node_files(&behind_file);
+}
+
+#[tokio::test]
+async fn test_webhook_retries_until_delivered() {
+    use btclib::{
+        crypto::PrivateKey,
+        custom_sha_types::Hash,
+        types::{Block, BlockHeader, Transaction, TransactionOutput},
+        utils::MerkleRoot,
+    };
+    use node::util::{Webhook, sign_payload};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        time::{Duration, timeout},
+    };
+
+    // answers the first delivery with an error and the second with 200
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let webhook_port = listener.local_addr().unwrap().port();
+    let server = tokio::spawn(async move {
+        let mut requests = vec![];
+        for status in ["503 Service Unavailable", "200 OK"] {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = vec![];
+            // the node closes its side only after reading the answer, so
+            // read until the body is in
+            let mut buf = [0; 4096];
+            loop {
+                let read = socket.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..read]);
+                let text = String::from_utf8_lossy(&request);
+                if read == 0 || text.ends_with('}') {
+                    break;
+                }
+            }
+            socket
+                .write_all(format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n").as_bytes())
+                .await
+                .unwrap();
+            requests.push(String::from_utf8(request).unwrap());
+        }
+        requests
+    });
+
+    let blockchain_file = temp_blockchain_file("webhooks");
+    let mut config = NodeConfig::new(&blockchain_file);
+    config.port = 0;
+    config.webhooks.hooks = vec![Webhook {
+        secret: Some("s3cret".to_string()),
+        ..Webhook::new(format!("http://127.0.0.1:{webhook_port}/events"))
+    }];
+    let mut node = Node::new(config);
+    let addr = node.start().await.unwrap();
+
+    let transactions = vec![Transaction::new(
+        vec![],
+        vec![TransactionOutput::new(
+            5000000000,
+            uuid::Uuid::new_v4(),
+            PrivateKey::from_seed(b"miner").public_key(),
+        )],
+    )];
+    let header = BlockHeader::new(
+        chrono::Utc::now(),
+        0,
+        Hash::zero(),
+        MerkleRoot::calculate(&transactions),
+        btclib::MIN_TARGET,
+    );
+    let block = Block::new(header, transactions);
+    let mut client = NodeClient::connect(("127.0.0.1", addr.port()))
+        .await
+        .unwrap();
+    client.submit_template(block.clone()).await.unwrap();
+
+    let requests = timeout(Duration::from_secs(10), server)
+        .await
+        .expect("webhook wasn't retried")
+        .unwrap();
+    let (head, body) = requests[1].split_once("\r\n\r\n").unwrap();
+    assert_eq!(requests[0], requests[1]);
+    assert!(head.starts_with("POST /events HTTP/1.1"));
+    assert!(head.contains("X-Webhook-Event: block_connected"));
+    assert!(head.contains(&format!(
+        "X-Signature: sha256={}",
+        sign_payload("s3cret", body)
+    )));
+    assert!(body.contains(&hex::encode(block.hash().as_bytes())));
+    assert!(body.contains(r#""height":0"#));
+
+    drop(client);
+    node.stop().await.unwrap();
+    remove_node_files(&blockchain_file);
+}
+
+#[tokio::test]
+async fn test_child_pays_for_parent() {
+    use btclib::{
+        crypto::{PrivateKey, Signature},
+        custom_sha_types::Hash,
+        types::{Block, BlockHeader, Transaction, TransactionInput, TransactionOutput},
+        utils::MerkleRoot,
+    };
+
+    let blockchain_file = temp_blockchain_file("cpfp");
+    let mut config = NodeConfig::new(&blockchain_file);
+    config.port = 0;
+    let mut node = Node::new(config);
+    let addr = node.start().await.unwrap();
+
+    let key = PrivateKey::from_seed(b"cpfp");
+    let funding = TransactionOutput::new(5000000000, uuid::Uuid::new_v4(), key.public_key());
+    let transactions = vec![Transaction::new(vec![], vec![funding.clone()])];
+    let header = BlockHeader::new(
+        chrono::Utc::now(),
+        0,
+        Hash::zero(),
+        MerkleRoot::calculate(&transactions),
+        btclib::MIN_TARGET,
+    );
+    let mut client = NodeClient::connect(("127.0.0.1", addr.port()))
+        .await
+        .unwrap();
+    client
+        .submit_template(Block::new(header, transactions))
+        .await
+        .unwrap();
+
+    let spend = |outpoint: Hash, value: u64| {
+        Transaction::new(
+            vec![TransactionInput::new(
+                outpoint,
+                Signature::sign_output(&outpoint, &key),
+            )],
+            vec![TransactionOutput::new(
+                value,
+                uuid::Uuid::new_v4(),
+                key.public_key(),
+            )],
+        )
+    };
+    // the parent pays nothing and gets in on the free quota
+    let parent = spend(funding.hash(), funding.value());
+    let child = spend(parent.outputs()[0].hash(), funding.value() - 10_000);
+    client.submit_tx(parent.clone()).await.unwrap();
+    client.submit_tx(child.clone()).await.unwrap();
+
+    let entries = client.get_mempool_entries().await.unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].hash, child.hash());
+    assert_eq!(entries[0].ancestors, 1);
+    assert_eq!(entries[0].package_fee, 10_000);
+    assert!(entries[0].package_fee_rate() < entries[0].fee_rate());
+    let histogram = client.get_fee_histogram().await.unwrap();
+    let sizes: usize = entries.iter().map(|entry| entry.size).sum();
+    assert_eq!(histogram.size_above(0), sizes);
+    assert_eq!(histogram.buckets.last().unwrap().cumulative_size, sizes);
+
+    let template = client.get_template(&key.public_key(), None).await.unwrap();
+    let included: Vec<Hash> = template.transactions()[1..]
+        .iter()
+        .map(|transaction| transaction.hash())
+        .collect();
+    assert_eq!(included, vec![parent.hash(), child.hash()]);
+
+    drop(client);
+    node.stop().await.unwrap();
+    remove_node_files(&blockchain_file);
+}
+
+#[tokio::test]
+async fn test_admin_transactions_take_the_priority_lane() {
+    use btclib::{
+        crypto::{PrivateKey, Signature},
+        custom_sha_types::Hash,
+        error::ClientError,
+        network::Role,
+        types::{Block, BlockHeader, Transaction, TransactionInput, TransactionOutput},
+        utils::MerkleRoot,
+    };
+    use node::util::token_path;
+
+    let blockchain_file = temp_blockchain_file("priority");
+    let mut config = NodeConfig::new(&blockchain_file);
+    config.port = 0;
+    // no free transactions: without a fee, only the admin gets one in
+    config.relay_policy.free_tx_per_hour = 0;
+    let mut node = Node::new(config);
+    let addr = node.start().await.unwrap();
+
+    let key = PrivateKey::from_seed(b"priority");
+    let outputs: Vec<TransactionOutput> = (0..2)
+        .map(|_| TransactionOutput::new(2500000000, uuid::Uuid::new_v4(), key.public_key()))
+        .collect();
+    let transactions = vec![Transaction::new(vec![], outputs.clone())];
+    let header = BlockHeader::new(
+        chrono::Utc::now(),
+        0,
+        Hash::zero(),
+        MerkleRoot::calculate(&transactions),
+        btclib::MIN_TARGET,
+    );
+    let connect = || NodeClient::connect(("127.0.0.1", addr.port()));
+    let mut client = connect().await.unwrap();
+    client
+        .submit_template(Block::new(header, transactions))
+        .await
+        .unwrap();
+
+    let spend = |output: &TransactionOutput, fee: u64| {
+        Transaction::new(
+            vec![TransactionInput::new(
+                output.hash(),
+                Signature::sign_output(&output.hash(), &key),
+            )],
+            vec![TransactionOutput::new(
+                output.value() - fee,
+                uuid::Uuid::new_v4(),
+                key.public_key(),
+            )],
+        )
+    };
+    let paying = spend(&outputs[0], 10_000);
+    let own = spend(&outputs[1], 0);
+    client.submit_tx(paying.clone()).await.unwrap();
+
+    // the lane is for admins only
+    let mut stranger = connect().await.unwrap();
+    stranger.submit_priority_tx(own.clone()).await.unwrap();
+    assert!(matches!(
+        stranger.get_tip().await,
+        Err(ClientError::Rejected { .. })
+    ));
+
+    let mut admin = connect().await.unwrap();
+    let token = std::fs::read_to_string(token_path(&blockchain_file, Role::Admin)).unwrap();
+    admin.authenticate(&token).await.unwrap();
+    admin.submit_priority_tx(own.clone()).await.unwrap();
+
+    // the fee-less transaction goes first; asked on the admin's
+    // connection, which handled the submission by then
+    let template = admin.get_template(&key.public_key(), None).await.unwrap();
+    let included: Vec<Hash> = template.transactions()[1..]
+        .iter()
+        .map(|transaction| transaction.hash())
+        .collect();
+    assert_eq!(included, vec![own.hash(), paying.hash()]);
+
+    drop((client, stranger, admin));
+    node.stop().await.unwrap();
+    remove_node_files(&blockchain_file);
+}
+
+#[tokio::test]
+async fn test_node_builds_transactions_for_watch_only_keys() {
+    use btclib::{
+        crypto::PrivateKey,
+        custom_sha_types::Hash,
+        error::ClientError,
+        network::{PeerKind, RejectCode},
+        types::{Block, BlockHeader, Transaction, TransactionOutput, UnsignedTransaction},
+        utils::MerkleRoot,
+    };
+
+    let blockchain_file = temp_blockchain_file("build-tx");
+    let mut config = NodeConfig::new(&blockchain_file);
+    config.port = 0;
+    let mut node = Node::new(config);
+    let addr = node.start().await.unwrap();
+
+    // the node only ever sees the public key
+    let key = PrivateKey::from_seed(b"cold storage");
+    let bob = PrivateKey::from_seed(b"bob").public_key();
+    let funding: Vec<TransactionOutput> = [3_000_000_000, 2_000_000_000]
+        .into_iter()
+        .map(|value| TransactionOutput::new(value, uuid::Uuid::new_v4(), key.public_key()))
+        .collect();
+    let transactions = vec![Transaction::new(vec![], funding)];
+    let header = BlockHeader::new(
+        chrono::Utc::now(),
+        0,
+        Hash::zero(),
+        MerkleRoot::calculate(&transactions),
+        btclib::MIN_TARGET,
+    );
+    let mut client = NodeClient::connect(("127.0.0.1", addr.port()))
+        .await
+        .unwrap();
+    client
+        .submit_template(Block::new(header, transactions))
+        .await
+        .unwrap();
+    client.hello(PeerKind::Wallet, 0).await.unwrap();
+
+    let unsigned = client
+        .build_transaction(
+            key.public_key(),
+            vec![(bob.clone(), 4_000_000_000)],
+            Some(5),
+        )
+        .await
+        .unwrap();
+    assert_eq!(unsigned.inputs.len(), 2);
+    assert_eq!(unsigned.outputs[0].value(), 4_000_000_000);
+    assert_eq!(*unsigned.outputs[1].pubkey(), key.public_key());
+    assert!(unsigned.fee() >= 5 * unsigned.signed_size() as u64);
+
+    // signed away from the node, e.g. on an air-gapped machine
+    let carried = UnsignedTransaction::from_hex(&unsigned.to_hex()).unwrap();
+    let transaction = carried.sign(&key).unwrap();
+    client.submit_tx(transaction.clone()).await.unwrap();
+    assert_eq!(
+        client.get_mempool().await.unwrap(),
+        vec![transaction.hash()]
+    );
+
+    // the outputs spent in the mempool aren't offered again
+    let refused = client
+        .build_transaction(key.public_key(), vec![(bob.clone(), 1_000)], None)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        refused,
+        ClientError::Rejected {
+            code: RejectCode::Invalid,
+            ..
+        }
+    ));
+    let refused = client
+        .build_transaction(key.public_key(), vec![(bob, 1)], None)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        refused,
+        ClientError::Rejected {
+            code: RejectCode::Policy,
+            ..
+        }
+    ));
+
+    drop(client);
+    node.stop().await.unwrap();
+    remove_node_files(&blockchain_file);
+}
+
+#[tokio::test]
+async fn test_node_fetches_missing_mempool_transactions_from_peers() {
+    use btclib::{
+        crypto::{PrivateKey, Signature},
+        custom_sha_types::Hash,
+        types::{BlockBuilder, Blockchain, Transaction, TransactionInput, TransactionOutput},
+    };
+
+    let key = PrivateKey::from_seed(b"reconcile");
+    let mut chain = Blockchain::default();
+    chain
+        .add_block(BlockBuilder::on(&chain).build(key.public_key()))
+        .unwrap();
+    let funding = chain.blocks()[0].transactions()[0].outputs()[0].clone();
+    let spend = |outpoint: Hash, value: u64| {
+        Transaction::new(
+            vec![TransactionInput::new(
+                outpoint,
+                Signature::sign_output(&outpoint, &key),
+            )],
+            vec![TransactionOutput::new(
+                value,
+                uuid::Uuid::new_v4(),
+                key.public_key(),
+            )],
+        )
+    };
+    let parent = spend(funding.hash(), funding.value() - 10_000);
+    let child = spend(parent.outputs()[0].hash(), funding.value() - 20_000);
+
+    let source_file = temp_blockchain_file("reconcile-source");
+    let mut config = NodeConfig::new(&source_file);
+    config.port = 0;
+    let mut source = Node::new(config);
+    let source_addr = format!("127.0.0.1:{}", source.start().await.unwrap().port());
+    let mut client = NodeClient::connect(source_addr.as_str()).await.unwrap();
+    client
+        .submit_template(chain.blocks()[0].clone())
+        .await
+        .unwrap();
+    client.submit_tx(parent.clone()).await.unwrap();
+    client.submit_tx(child.clone()).await.unwrap();
+    // only the requested transactions the node has come back
+    let fetched = client
+        .get_transactions(vec![child.hash(), Hash::zero()])
+        .await
+        .unwrap();
+    assert_eq!(fetched.len(), 1);
+    assert_eq!(fetched[0].hash(), child.hash());
+    drop(client);
+
+    let file = temp_blockchain_file("reconcile");
+    let mut config = NodeConfig::new(&file);
+    config.port = 0;
+    config.nodes = vec![source_addr.clone()];
+    let mut node = Node::new(config);
+    node.start().await.unwrap();
+
+    let blockchain = node.state().blockchain.read().await;
+    let mut mempool: Vec<Hash> = blockchain
+        .mempool()
+        .iter()
+        .map(|(_, transaction)| transaction.hash())
+        .collect();
+    let mut expected = vec![parent.hash(), child.hash()];
+    mempool.sort_by_key(|hash| hash.as_bytes());
+    expected.sort_by_key(|hash| hash.as_bytes());
+    assert_eq!(mempool, expected);
+    drop(blockchain);
+    node.stop().await.unwrap();
+    remove_node_files(&file);
+
+    // with mempool sync off only the blockchain is downloaded
+    let mut config = NodeConfig::new(&file);
+    config.port = 0;
+    config.nodes = vec![source_addr];
+    config.mempool_sync = false;
+    let mut node = Node::new(config);
+    node.start().await.unwrap();
+    let blockchain = node.state().blockchain.read().await;
+    assert_eq!(blockchain.blocks().len(), 1);
+    assert!(blockchain.mempool().is_empty());
+    drop(blockchain);
+
+    node.stop().await.unwrap();
+    source.stop().await.unwrap();
+    remove_node_files(&file);
+    remove_node_files(&source_file);
+}
+
+#[tokio::test]
+async fn test_merchant_node_scores_zero_conf_risk() {
+    use btclib::{
+        crypto::{PrivateKey, Signature},
+        custom_sha_types::Hash,
+        error::ClientError,
+        network::{RejectCode, RiskLevel},
+        types::{
+            BlockBuilder, Blockchain, Confirmations, Transaction, TransactionInput,
+            TransactionOutput,
+        },
+    };
+
+    let key = PrivateKey::from_seed(b"zeroconf");
+    let mut chain = Blockchain::default();
+    chain
+        .add_block(BlockBuilder::on(&chain).build(key.public_key()))
+        .unwrap();
+    let funding = chain.blocks()[0].transactions()[0].outputs()[0].clone();
+    let spend = |outpoint: Hash, value: u64| {
+        Transaction::new(
+            vec![TransactionInput::new(
+                outpoint,
+                Signature::sign_output(&outpoint, &key),
+            )],
+            vec![TransactionOutput::new(
+                value,
+                uuid::Uuid::new_v4(),
+                key.public_key(),
+            )],
+        )
+    };
+    let payment = spend(funding.hash(), funding.value() - 10_000);
+    let child = spend(payment.outputs()[0].hash(), funding.value() - 20_000);
+
+    let source_file = temp_blockchain_file("zeroconf-source");
+    let mut config = NodeConfig::new(&source_file);
+    config.port = 0;
+    let mut source = Node::new(config);
+    let source_addr = format!("127.0.0.1:{}", source.start().await.unwrap().port());
+    let mut client = NodeClient::connect(source_addr.as_str()).await.unwrap();
+    client
+        .submit_template(chain.blocks()[0].clone())
+        .await
+        .unwrap();
+    client.submit_tx(payment.clone()).await.unwrap();
+    client.get_difference(BlockHeight::GENESIS).await.unwrap();
+    // scoring is off unless asked for
+    assert!(matches!(
+        client.get_zero_conf_risk(payment.hash()).await,
+        Err(ClientError::Rejected {
+            code: RejectCode::Unsupported,
+            ..
+        })
+    ));
+    drop(client);
+
+    let file = temp_blockchain_file("zeroconf-merchant");
+    let mut config = NodeConfig::new(&file);
+    config.port = 0;
+    config.nodes = vec![source_addr];
+    config.zero_conf_risk = true;
+    let mut merchant = Node::new(config);
+    let addr = merchant.start().await.unwrap();
+    let mut client = NodeClient::connect(("127.0.0.1", addr.port()))
+        .await
+        .unwrap();
+
+    // the payment spends a confirmed output and its peer has it too
+    let risk = client.get_zero_conf_risk(payment.hash()).await.unwrap();
+    assert_eq!(risk.min_input_confirmations, Confirmations::new(1));
+    assert_eq!((risk.peers_asked, risk.peers_with_transaction), (1, 1));
+    assert_eq!(risk.level(), RiskLevel::Low);
+
+    // one only the merchant has seen, spending an unconfirmed output
+    merchant
+        .state()
+        .blockchain
+        .write()
+        .await
+        .add_transaction_to_mempool(child.clone())
+        .unwrap();
+    let risk = client.get_zero_conf_risk(child.hash()).await.unwrap();
+    assert_eq!(risk.unconfirmed_inputs, 1);
+    assert_eq!((risk.peers_asked, risk.peers_with_transaction), (1, 0));
+    assert_eq!(risk.level(), RiskLevel::High);
+
+    assert!(matches!(
+        client.get_zero_conf_risk(Hash::zero()).await,
+        Err(ClientError::Rejected {
+            code: RejectCode::NotFound,
+            ..
+        })
+    ));
+    let response = node::util::handle_http_request(
+        merchant.state(),
+        std::net::Ipv4Addr::LOCALHOST.into(),
+        "GET",
+        &format!("/risk/{}", hex::encode(child.hash().as_bytes())),
+        b"",
+    )
+    .await;
+    assert_eq!(response.status, 200);
+    assert!(response.body.contains(r#""level":"High""#));
+    drop(client);
+
+    merchant.stop().await.unwrap();
+    source.stop().await.unwrap();
+    remove_node_files(&file);
+    remove_node_files(&source_file);
+}
+
+#[cfg(feature = "utxo-accumulator")]
+#[tokio::test]
+async fn test_stateless_client_checks_utxo_proofs() {
+    use btclib::{
+        testutil::{spend_transaction, test_key},
+        types::{BlockBuilder, Blockchain, TransactionOutput},
+    };
+
+    let key = test_key("accumulator");
+    let spend = |output: &TransactionOutput, fee: u64| spend_transaction(output, &key, fee);
+    let mut chain = Blockchain::default();
+    chain
+        .add_block(BlockBuilder::on(&chain).build(key.public_key()))
+        .unwrap();
+    chain.rebuild_utxos();
+    let funding = chain.blocks()[0].transactions()[0].outputs()[0].clone();
+    let payment = spend(&funding, 10_000);
+    let mut builder = BlockBuilder::on(&chain);
+    assert!(builder.add_transaction(payment.clone(), 10_000));
+    chain.add_block(builder.build(key.public_key())).unwrap();
+    chain.rebuild_utxos();
+
+    let blockchain_file = temp_blockchain_file("accumulator");
+    let mut config = NodeConfig::new(&blockchain_file);
+    config.port = 0;
+    let mut node = Node::new(config);
+    let addr = node.start().await.unwrap();
+    let mut client = NodeClient::connect(("127.0.0.1", addr.port()))
+        .await
+        .unwrap();
+    for block in chain.blocks() {
+        client.submit_template(block.clone()).await.unwrap();
+    }
+
+    // the client keeps only the roots and checks a spend against them
+    let stump = client.get_utxo_accumulator().await.unwrap();
+    assert_eq!(stump.height, BlockHeight::new(2));
+    assert_eq!(stump.tip, chain.blocks()[1].hash());
+    let output = payment.outputs()[0].clone();
+    let proof = client.get_utxo_proof(output.hash()).await.unwrap().unwrap();
+    assert!(stump.verify(&proof));
+    let fee = stump
+        .validate_spend(&spend(&output, 5_000), std::slice::from_ref(&proof))
+        .unwrap();
+    assert_eq!(fee, 5_000);
+    assert!(
+        client
+            .get_utxo_proof(funding.hash())
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    // the node's accumulator catches up with new blocks, and proofs must
+    // be fetched again
+    extend_chain(&mut chain, 1);
+    client
+        .submit_template(chain.blocks()[2].clone())
+        .await
+        .unwrap();
+    let stump = client.get_utxo_accumulator().await.unwrap();
+    assert_eq!(stump.height, BlockHeight::new(3));
+    assert!(!stump.verify(&proof));
+    let proof = client.get_utxo_proof(output.hash()).await.unwrap().unwrap();
+    assert!(stump.verify(&proof));
+
+    drop(client);
+    node.stop().await.unwrap();
+    remove_node_files(&blockchain_file);
+}
+
+#[tokio::test]
+async fn test_outbound_only_node_polls_its_peers() {
+    use btclib::{crypto::PrivateKey, error::ClientError, network::RejectCode, types::Blockchain};
+    use node::util::OutboundConfig;
+    use tokio::time::{Duration, sleep};
+
+    let mut chain = Blockchain::default();
+    extend_chain(&mut chain, 2);
+    let source_file = temp_blockchain_file("outbound-source");
+    let mut config = NodeConfig::new(&source_file);
+    config.port = 0;
+    let mut source = Node::new(config);
+    let source_addr = format!("127.0.0.1:{}", source.start().await.unwrap().port());
+    let mut miner = NodeClient::connect(source_addr.as_str()).await.unwrap();
+    for block in chain.blocks() {
+        miner.submit_template(block.clone()).await.unwrap();
+    }
+    miner.get_difference(BlockHeight::GENESIS).await.unwrap();
+
+    let file = temp_blockchain_file("outbound");
+    let mut config = NodeConfig::new(&file);
+    config.port = 0;
+    config.nodes = vec![source_addr.clone()];
+    config.outbound_only = Some(OutboundConfig {
+        peers: 8,
+        poll_interval: Duration::from_millis(100),
+    });
+    config.serve_templates = false;
+    let mut node = Node::new(config);
+    let addr = node.start().await.unwrap();
+    // only local clients can reach it, and peers aren't told about it
+    assert!(addr.ip().is_loopback());
+    assert!(source.state().address_book.lock().unwrap().is_empty());
+    assert_eq!(node.state().blockchain.read().await.blocks().len(), 2);
+    let mut client = NodeClient::connect(("127.0.0.1", addr.port()))
+        .await
+        .unwrap();
+    assert!(matches!(
+        client
+            .get_template(&PrivateKey::from_seed(b"outbound").public_key(), None)
+            .await,
+        Err(ClientError::Rejected {
+            code: RejectCode::Unsupported,
+            ..
+        })
+    ));
+    drop(client);
+
+    // blocks mined after startup aren't announced to it, it polls for them
+    extend_chain(&mut chain, 1);
+    miner
+        .submit_template(chain.blocks()[2].clone())
+        .await
+        .unwrap();
+    let mut polled = false;
+    for _ in 0..100 {
+        if node.state().blockchain.read().await.blocks().len() == 3 {
+            polled = true;
+            break;
+        }
+        sleep(Duration::from_millis(20)).await;
+    }
+    assert!(polled, "the new block wasn't fetched");
+    drop(miner);
+
+    // a peer that goes away is dropped
+    source.stop().await.unwrap();
+    let mut dropped = false;
+    for _ in 0..100 {
+        if node.state().nodes.is_empty() {
+            dropped = true;
+            break;
+        }
+        sleep(Duration::from_millis(20)).await;
+    }
+    assert!(dropped, "the stopped peer wasn't dropped");
+
+    node.stop().await.unwrap();
+    remove_node_files(&file);
+    remove_node_files(&source_file);
+}
+
+#[tokio::test]
+async fn test_authority_cosigning() {
+    use btclib::{
+        crypto::PrivateKey,
+        custom_sha_types::Hash,
+        error::ClientError,
+        network::RejectCode,
+        types::{AuthoritySet, Block, BlockHeader, Transaction, TransactionOutput},
+        utils::MerkleRoot,
+    };
+    use tokio::time::{Duration, sleep};
+
+    fn genesis() -> Block {
+        let transactions = vec![Transaction::new(
+            vec![],
+            vec![TransactionOutput::new(
+                5000000000,
+                uuid::Uuid::new_v4(),
+                PrivateKey::from_seed(b"miner").public_key(),
+            )],
+        )];
+        let merkle_root = MerkleRoot::calculate(&transactions);
+        let header = BlockHeader::new(
+            chrono::Utc::now(),
+            0,
+            Hash::zero(),
+            merkle_root,
+            btclib::MIN_TARGET,
+        );
+        Block::new(header, transactions)
+    }
+
+    async fn wait_for_height(node: &Node, height: usize) -> bool {
+        for _ in 0..50 {
+            if node.state().blockchain.read().await.blocks().len() == height {
+                return true;
+            }
+            sleep(Duration::from_millis(20)).await;
+        }
+        false
+    }
+
+    let key = PrivateKey::from_seed(b"authority");
+    let authority = AuthoritySet::new(vec![key.public_key()], 1, BlockHeight::GENESIS).unwrap();
+    let signer_file = temp_blockchain_file("authority-signer");
+    let follower_file = temp_blockchain_file("authority-follower");
+    let mut signer_config = NodeConfig::new(&signer_file);
+    signer_config.port = 0;
+    signer_config.authority = Some(authority.clone());
+    signer_config.cosign_key = Some(key.clone());
+    let mut signer = Node::new(signer_config);
+    let signer_addr = signer.start().await.unwrap();
+    let mut follower_config = NodeConfig::new(&follower_file);
+    follower_config.port = 0;
+    follower_config.authority = Some(authority);
+    let mut follower = Node::new(follower_config);
+    let follower_addr = follower.start().await.unwrap();
+
+    // proof of work alone doesn't get a block in
+    let block = genesis();
+    let mut client = NodeClient::connect(("127.0.0.1", follower_addr.port()))
+        .await
+        .unwrap();
+    client.submit_template(block.clone()).await.unwrap();
+    sleep(Duration::from_millis(100)).await;
+    assert!(follower.state().blockchain.read().await.blocks().is_empty());
+    let mut client = NodeClient::connect(("127.0.0.1", follower_addr.port()))
+        .await
+        .unwrap();
+    assert!(matches!(
+        client.cosign_block(block.clone()).await,
+        Err(ClientError::Rejected {
+            code: RejectCode::Unsupported,
+            ..
+        })
+    ));
+
+    // the authority signs one block per height only
+    let mut authority_client = NodeClient::connect(("127.0.0.1", signer_addr.port()))
+        .await
+        .unwrap();
+    let signed = authority_client.cosign_block(block.clone()).await.unwrap();
+    assert_eq!(signed.hash(), block.hash());
+    assert!(matches!(
+        authority_client.cosign_block(genesis()).await,
+        Err(ClientError::Rejected {
+            code: RejectCode::Invalid,
+            ..
+        })
+    ));
+
+    let mut client = NodeClient::connect(("127.0.0.1", follower_addr.port()))
+        .await
+        .unwrap();
+    client.submit_template(signed).await.unwrap();
+    assert!(wait_for_height(&follower, 1).await, "signed block refused");
+
+    // the authority node signs what is mined for it itself
+    authority_client.submit_template(block).await.unwrap();
+    assert!(wait_for_height(&signer, 1).await, "authority refused block");
+    assert_eq!(
+        signer.state().blockchain.read().await.blocks()[0]
+            .authority_signatures()
+            .len(),
+        1
+    );
+    drop(client);
+    drop(authority_client);
+
+    signer.stop().await.unwrap();
+    follower.stop().await.unwrap();
+    remove_node_files(&signer_file);
+    remove_node_files(&follower_file);
+}
+
+#[tokio::test]
+async fn test_proof_of_authority_validators_take_turns() {
+    use btclib::{
+        consensus::ProofOfAuthority, crypto::PrivateKey, error::ClientError, network::RejectCode,
+    };
+    use node::util::produce_block;
+    use tokio::time::{Duration, sleep};
+
+    async fn wait_for_height(node: &Node, height: usize) -> bool {
+        for _ in 0..50 {
+            if node.state().blockchain.read().await.blocks().len() == height {
+                return true;
+            }
+            sleep(Duration::from_millis(20)).await;
+        }
+        false
+    }
+
+    let alice = PrivateKey::from_seed(b"alice");
+    let bob = PrivateKey::from_seed(b"bob");
+    let validators = vec![alice.public_key(), bob.public_key()];
+    let file = temp_blockchain_file("poa");
+    let mut config = NodeConfig::new(&file);
+    config.port = 0;
+    config.consensus = Arc::new(ProofOfAuthority::new(validators.clone()).unwrap());
+    config.validator_key = Some(alice.clone());
+    config.block_interval = Duration::from_millis(20);
+    let mut node = Node::new(config);
+    let addr = node.start().await.unwrap();
+
+    // alice signs the genesis block, then waits for bob
+    assert!(wait_for_height(&node, 1).await, "alice produced no block");
+    sleep(Duration::from_millis(100)).await;
+    assert_eq!(node.state().blockchain.read().await.blocks().len(), 1);
+    assert!(produce_block(node.state(), &alice).await.unwrap().is_none());
+    assert!(produce_block(node.state(), &bob).await.unwrap().is_some());
+    assert!(
+        wait_for_height(&node, 3).await,
+        "alice didn't sign the next block"
+    );
+    let signers: Vec<_> = node
+        .state()
+        .blockchain
+        .read()
+        .await
+        .blocks()
+        .iter()
+        .map(|block| block.authority_signatures()[0].signer.clone())
+        .collect();
+    assert_eq!(signers, [&validators[..], &validators[..1]].concat());
+
+    // there is nothing to mine
+    let mut client = NodeClient::connect(("127.0.0.1", addr.port()))
+        .await
+        .unwrap();
+    assert!(matches!(
+        client
+            .get_template(&PrivateKey::default().public_key(), None)
+            .await,
+        Err(ClientError::Rejected {
+            code: RejectCode::Unsupported,
+            ..
+        })
+    ));
+    drop(client);
+
+    node.stop().await.unwrap();
+    remove_node_files(&file);
+}
+
+#[tokio::test]
+async fn test_node_follows_longer_branch_up_to_max_reorg_depth() {
+    use std::sync::Mutex;
+
+    use btclib::{
+        custom_sha_types::Hash,
+        types::{Block, Blockchain},
+    };
+    use node::util::{ChainConflict, ChainObserver, populate_connections, register_observer};
+    use tokio::time::{Duration, sleep};
+
+    #[derive(Default)]
+    struct Recorder {
+        disconnected: Mutex<Vec<(Hash, BlockHeight)>>,
+        conflicts: Mutex<Vec<ChainConflict>>,
+    }
+
+    impl ChainObserver for Recorder {
+        fn on_block_disconnected(&self, block: &Block, height: BlockHeight) {
+            self.disconnected
+                .lock()
+                .unwrap()
+                .push((block.hash(), height));
+        }
+
+        fn on_chain_conflict(&self, conflict: &ChainConflict) {
+            self.conflicts.lock().unwrap().push(conflict.clone());
+        }
+    }
+
+    // both chains share the genesis block, the peer's is one block longer
+    let mut ours = Blockchain::default();
+    extend_chain(&mut ours, 1);
+    let mut theirs = ours.clone();
+    extend_chain(&mut ours, 1);
+    extend_chain(&mut theirs, 2);
+
+    let files: Vec<String> = ["reorg-peer", "reorg-follows", "reorg-final"]
+        .into_iter()
+        .map(temp_blockchain_file)
+        .collect();
+    let mut nodes = vec![];
+    let mut addresses = vec![];
+    for (file, chain) in files.iter().zip([&theirs, &ours, &ours]) {
+        let mut config = NodeConfig::new(file);
+        config.port = 0;
+        if nodes.len() == 2 {
+            config.max_reorg_depth = 0;
+        }
+        let mut node = Node::new(config);
+        let addr = format!("127.0.0.1:{}", node.start().await.unwrap().port());
+        let mut client = NodeClient::connect(addr.as_str()).await.unwrap();
+        for block in chain.blocks() {
+            client.submit_template(block.clone()).await.unwrap();
+        }
+        addresses.push(addr);
+        nodes.push(node);
+    }
+
+    let peer_tip = theirs.tip().unwrap();
+    let our_tip = ours.tip().unwrap();
+    let mut recorders = vec![];
+    for (node, address) in nodes[1..].iter().zip(&addresses[1..]) {
+        let recorder = Arc::new(Recorder::default());
+        register_observer(node.state(), recorder.clone());
+        recorders.push(recorder);
+        populate_connections(node.state(), &addresses[..1])
+            .await
+            .unwrap();
+        let mut client = NodeClient::connect(address.as_str()).await.unwrap();
+        client
+            .announce_block(theirs.blocks().last().unwrap().clone())
+            .await
+            .unwrap();
+    }
+    for _ in 0..100 {
+        if nodes[1].state().blockchain.read().await.tip() == Some(peer_tip)
+            && !recorders[1].conflicts.lock().unwrap().is_empty()
+        {
+            break;
+        }
+        sleep(Duration::from_millis(20)).await;
+    }
+
+    // one block deep is within the default limit
+    assert_eq!(
+        nodes[1].state().blockchain.read().await.tip(),
+        Some(peer_tip)
+    );
+    assert_eq!(
+        *recorders[0].disconnected.lock().unwrap(),
+        vec![(our_tip.hash, our_tip.height)]
+    );
+    // but not with finality after every block
+    assert_eq!(
+        nodes[2].state().blockchain.read().await.tip(),
+        Some(our_tip)
+    );
+    let conflicts = recorders[1].conflicts.lock().unwrap().clone();
+    assert_eq!(conflicts.len(), 1);
+    assert_eq!(conflicts[0].peer, addresses[0]);
+    assert_eq!(conflicts[0].tip, Some(our_tip));
+    assert_eq!(conflicts[0].peer_tip, peer_tip);
+    assert!(recorders[1].disconnected.lock().unwrap().is_empty());
+
+    for mut node in nodes {
+        node.stop().await.unwrap();
+    }
+    for file in &files {
+        remove_node_files(file);
+    }
+}
+
+#[tokio::test]
+async fn test_admin_invalidates_and_reconsiders_block() {
+    use btclib::{error::ClientError, network::Role, types::Blockchain};
+    use node::util::{populate_connections, token_path};
+
+    // the other chain is shorter, so only invalidation moves the node to it
+    let mut ours = Blockchain::default();
+    extend_chain(&mut ours, 1);
+    let mut theirs = ours.clone();
+    extend_chain(&mut ours, 2);
+    extend_chain(&mut theirs, 1);
+
+    let files: Vec<String> = ["invalidate-peer", "invalidate-admin"]
+        .into_iter()
+        .map(temp_blockchain_file)
+        .collect();
+    let mut nodes = vec![];
+    let mut addresses = vec![];
+    for (file, chain) in files.iter().zip([&theirs, &ours]) {
+        let mut config = NodeConfig::new(file);
+        config.port = 0;
+        let mut node = Node::new(config);
+        let addr = format!("127.0.0.1:{}", node.start().await.unwrap().port());
+        let mut client = NodeClient::connect(addr.as_str()).await.unwrap();
+        for block in chain.blocks() {
+            client.submit_template(block.clone()).await.unwrap();
+        }
+        client.get_difference(BlockHeight::GENESIS).await.unwrap();
+        addresses.push(addr);
+        nodes.push(node);
+    }
+    populate_connections(nodes[1].state(), &addresses[..1])
+        .await
+        .unwrap();
+
+    let bad = ours.blocks()[1].hash();
+    let mut admin = NodeClient::connect(addresses[1].as_str()).await.unwrap();
+    assert!(matches!(
+        admin.invalidate_block(bad).await,
+        Err(ClientError::Rejected { .. })
+    ));
+    let token = std::fs::read_to_string(token_path(&files[1], Role::Admin)).unwrap();
+    assert_eq!(admin.authenticate(&token).await.unwrap(), Role::Admin);
+
+    assert_eq!(admin.invalidate_block(bad).await.unwrap(), theirs.tip());
+    assert!(
+        nodes[1]
+            .state()
+            .blockchain
+            .read()
+            .await
+            .is_marked_invalid(&bad)
+    );
+
+    // no peer serves the invalidated chain, so the node stays put
+    assert_eq!(admin.reconsider_block(bad).await.unwrap(), theirs.tip());
+    assert!(matches!(
+        admin.reconsider_block(bad).await,
+        Err(ClientError::Rejected { .. })
+    ));
+    drop(admin);
+
+    for mut node in nodes {
+        node.stop().await.unwrap();
+    }
+    for file in &files {
+        remove_node_files(file);
+    }
+}
+
+#[tokio::test]
+async fn test_read_only_client_fetches_analytics() {
+    use btclib::{error::ClientError, network::Role, types::Blockchain};
+    use node::util::token_path;
+
+    let blockchain_file = temp_blockchain_file("analytics");
+    let mut config = NodeConfig::new(&blockchain_file);
+    config.port = 0;
+    config.memory_budget = Some(64 * 1024 * 1024);
+    let mut node = Node::new(config);
+    let addr = node.start().await.unwrap();
+    let mut chain = Blockchain::default();
+    extend_chain(&mut chain, 2);
+    let mut client = NodeClient::connect(("127.0.0.1", addr.port()))
+        .await
+        .unwrap();
+    for block in chain.blocks() {
+        client.submit_template(block.clone()).await.unwrap();
+    }
+
+    assert!(matches!(
+        client.get_analytics(10).await,
+        Err(ClientError::Rejected { .. })
+    ));
+    let token = std::fs::read_to_string(token_path(&blockchain_file, Role::ReadOnly)).unwrap();
+    client.authenticate(&token).await.unwrap();
+    let analytics = client.get_analytics(10).await.unwrap();
+    assert_eq!(analytics, chain.analytics(10));
+    assert_eq!(analytics.blocks.len(), 2);
+    assert_eq!(analytics.utxo_age[0].outputs, 2);
+    assert_eq!(analytics.velocity, 0.0);
+    let memory = client.get_memory_info().await.unwrap();
+    assert_eq!(memory.utxos, 2);
+    assert!(memory.utxo_bytes > 0);
+    assert_eq!(memory.budget, Some(64 * 1024 * 1024));
+
+    drop(client);
+    node.stop().await.unwrap();
+    remove_node_files(&blockchain_file);
+}
+
+// sends a raw HTTP request to the light wallet port and returns the
+// status and body of the answer
+async fn http_request(port: u16, request: &str) -> (u16, String) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", port))
+        .await
+        .unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let status = response.split_whitespace().nth(1).unwrap().parse().unwrap();
+    let body = response.split_once("\r\n\r\n").unwrap().1.to_string();
+    (status, body)
+}
+
+#[tokio::test]
+async fn test_light_wallet_over_http() {
+    use btclib::{
+        crypto::{PrivateKey, Signature},
+        custom_sha_types::Hash,
+        types::{Block, BlockHeader, Transaction, TransactionInput, TransactionOutput},
+        utils::MerkleRoot,
+    };
+    use node::util::HttpConfig;
+
+    let blockchain_file = temp_blockchain_file("http");
+    let mut config = NodeConfig::new(&blockchain_file);
+    config.port = 0;
+    config.http = Some(HttpConfig::new(0));
+    let mut node = Node::new(config);
+    let addr = node.start().await.unwrap();
+    let http_port = node.http_addr().unwrap().port();
+
+    let key = PrivateKey::from_seed(b"http");
+    let funding = TransactionOutput::new(5000000000, uuid::Uuid::new_v4(), key.public_key());
+    let transactions = vec![Transaction::new(vec![], vec![funding.clone()])];
+    let coinbase = transactions[0].hash();
+    let header = BlockHeader::new(
+        chrono::Utc::now(),
+        0,
+        Hash::zero(),
+        MerkleRoot::calculate(&transactions),
+        btclib::MIN_TARGET,
+    );
+    let block = Block::new(header, transactions);
+    let mut client = NodeClient::connect(("127.0.0.1", addr.port()))
+        .await
+        .unwrap();
+    client.submit_template(block.clone()).await.unwrap();
+    let hex = |hash: Hash| hex::encode(hash.as_bytes());
+
+    let (status, body) = http_request(http_port, "GET /tip HTTP/1.1\r\n\r\n").await;
+    assert_eq!(status, 200);
+    assert!(body.contains(&format!(r#""hash":"{}","height":0"#, hex(block.hash()))));
+
+    let (status, body) =
+        http_request(http_port, "GET /headers?from=0&count=5 HTTP/1.1\r\n\r\n").await;
+    assert_eq!(status, 200);
+    assert!(body.contains(&format!(r#""merkle_root":"{}""#, hex(coinbase))));
+    let (status, body) = http_request(
+        http_port,
+        &format!("GET /headers?from={} HTTP/1.1\r\n\r\n", u64::MAX),
+    )
+    .await;
+    assert_eq!((status, body.as_str()), (200, "[]"));
+
+    let (status, body) = http_request(
+        http_port,
+        &format!("GET /proof/{} HTTP/1.1\r\n\r\n", hex(coinbase)),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert!(body.contains(r#""index":0,"siblings":[]"#));
+
+    let search = format!("GET /search?q={} HTTP/1.1\r\n\r\n", hex(coinbase));
+    let (status, body) = http_request(http_port, &search).await;
+    assert_eq!(status, 200);
+    assert!(body.contains(&format!(r#""path":"/proof/{}""#, hex(coinbase))));
+    // as a browser encodes it
+    let search = format!("GET /search?q=%20{}+ HTTP/1.1\r\n\r\n", hex(coinbase));
+    let (status, body) = http_request(http_port, &search).await;
+    assert_eq!(status, 200);
+    assert!(body.contains(&format!(r#""path":"/proof/{}""#, hex(coinbase))));
+    let search = format!(
+        "GET /search?q={} HTTP/1.1\r\n\r\n",
+        key.public_key().address()
+    );
+    let (status, body) = http_request(http_port, &search).await;
+    assert_eq!(status, 200);
+    assert!(body.contains(r#""type":"address""#));
+    assert!(body.contains(&format!(r#""path":"/utxos/{}""#, key.public_key().to_hex())));
+    let (status, _) = http_request(http_port, "GET /search?q=7 HTTP/1.1\r\n\r\n").await;
+    assert_eq!(status, 404);
+    let (status, _) = http_request(http_port, "GET /search HTTP/1.1\r\n\r\n").await;
+    assert_eq!(status, 400);
+
+    let utxos = format!("GET /utxos/{} HTTP/1.1\r\n\r\n", key.public_key().to_hex());
+    let (status, body) = http_request(http_port, &utxos).await;
+    assert_eq!(status, 200);
+    assert_eq!(
+        body,
+        format!(
+            r#"[{{"hash":"{}","value":5000000000,"in_mempool":false}}]"#,
+            hex(funding.hash())
+        )
+    );
+
+    let spend = Transaction::new(
+        vec![TransactionInput::new(
+            funding.hash(),
+            Signature::sign_output(&funding.hash(), &key),
+        )],
+        vec![TransactionOutput::new(
+            funding.value(),
+            uuid::Uuid::new_v4(),
+            key.public_key(),
+        )],
+    );
+    let post = |body: String| {
+        format!(
+            "POST /tx HTTP/1.1\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+    };
+    let (status, body) = http_request(http_port, &post(spend.to_hex())).await;
+    assert_eq!(status, 200, "{body}");
+    assert_eq!(body, format!(r#"{{"hash":"{}"}}"#, hex(spend.hash())));
+    assert_eq!(client.get_mempool().await.unwrap(), vec![spend.hash()]);
+    let (_, body) = http_request(http_port, &utxos).await;
+    assert!(body.contains(r#""in_mempool":true"#));
+    let (status, body) = http_request(http_port, "GET /fees HTTP/1.1\r\n\r\n").await;
+    assert_eq!(status, 200);
+    assert_eq!(
+        body,
+        format!(
+            r#"{{"buckets":[{{"min_fee_rate":0,"transactions":1,"size":{0},"cumulative_size":{0}}}]}}"#,
+            spend.size()
+        )
+    );
+
+    // an output that doesn't exist can't be spent
+    let unknown = Transaction::new(
+        vec![TransactionInput::new(
+            Hash::zero(),
+            Signature::sign_output(&Hash::zero(), &key),
+        )],
+        vec![],
+    );
+    let (status, body) = http_request(http_port, &post(unknown.to_hex())).await;
+    assert_eq!(status, 422);
+    assert!(body.contains(r#""code":"Invalid""#));
+    let (status, _) = http_request(http_port, &post("not a transaction".to_string())).await;
+    assert_eq!(status, 400);
+
+    // off-chain proof of owning the key
+    let message = "I own this key";
+    let verify = |signature: String| {
+        format!(
+            "POST /verifymessage?key={}&signature={signature} HTTP/1.1\r\nContent-Length: {}\r\n\r\n{message}",
+            key.public_key().to_hex(),
+            message.len()
+        )
+    };
+    let signature = key.sign_message(message.as_bytes()).to_hex();
+    let (status, body) = http_request(http_port, &verify(signature)).await;
+    assert_eq!(status, 200);
+    assert!(body.starts_with(r#"{"valid":true,"address":""#));
+    let forged = PrivateKey::from_seed(b"forger").sign_message(message.as_bytes());
+    let (_, body) = http_request(http_port, &verify(forged.to_hex())).await;
+    assert!(body.starts_with(r#"{"valid":false"#));
+    let (status, _) = http_request(http_port, &verify("00".to_string())).await;
+    assert_eq!(status, 400);
+
+    // browsers ask before posting from another origin
+    let (status, body) = http_request(http_port, "OPTIONS /tx HTTP/1.1\r\n\r\n").await;
+    assert_eq!((status, body.as_str()), (204, ""));
+    let (status, _) = http_request(http_port, "GET /nothing HTTP/1.1\r\n\r\n").await;
+    assert_eq!(status, 404);
+
+    drop(client);
+    node.stop().await.unwrap();
+    remove_node_files(&blockchain_file);
+}
+
+#[tokio::test]
+async fn test_invalid_transaction_storm_is_alerted() {
+    use btclib::{
+        crypto::{PrivateKey, Signature},
+        custom_sha_types::Hash,
+        types::{Blockchain, Transaction, TransactionInput},
+    };
+    use node::util::{
+        ChainObserver, RejectedKind, RejectionLimits, RejectionStorm, register_observer,
+    };
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<RejectionStorm>>);
+
+    impl ChainObserver for Recorder {
+        fn on_rejection_storm(&self, storm: &RejectionStorm) {
+            self.0.lock().unwrap().push(*storm);
+        }
+    }
+
+    let blockchain_file = temp_blockchain_file("rejections");
+    let mut config = NodeConfig::new(&blockchain_file);
+    config.port = 0;
+    config.rejection_limits = RejectionLimits {
+        max_transactions_per_minute: 2,
+        ..RejectionLimits::default()
+    };
+    let mut node = Node::new(config);
+    let recorder = Arc::new(Recorder::default());
+    register_observer(node.state(), recorder.clone());
+    let addr = node.start().await.unwrap();
+    let mut chain = Blockchain::default();
+    extend_chain(&mut chain, 1);
+    let mut client = NodeClient::connect(("127.0.0.1", addr.port()))
+        .await
+        .unwrap();
+    client
+        .submit_template(chain.blocks()[0].clone())
+        .await
+        .unwrap();
+
+    // spends of an output that doesn't exist
+    let key = PrivateKey::default();
+    let invalid = Transaction::new(
+        vec![TransactionInput::new(
+            Hash::zero(),
+            Signature::sign_output(&Hash::zero(), &key),
+        )],
+        vec![],
+    );
+    for _ in 0..3 {
+        // the node answers with a reject and hangs up
+        let mut submitter = NodeClient::connect(("127.0.0.1", addr.port()))
+            .await
+            .unwrap();
+        submitter.submit_tx(invalid.clone()).await.unwrap();
+    }
+
+    // submissions aren't answered, so wait for the node to count them
+    let mut health = client.get_health().await.unwrap();
+    for _ in 0..50 {
+        if health.rejected_transactions_last_minute == 3 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        health = client.get_health().await.unwrap();
+    }
+    assert_eq!(health.rejected_transactions_last_minute, 3);
+    assert_eq!(health.rejected_blocks_last_minute, 0);
+    let storms = recorder.0.lock().unwrap().clone();
+    assert_eq!(
+        storms,
+        vec![RejectionStorm {
+            kind: RejectedKind::Transaction,
+            count: 3,
+            limit: 2
+        }]
+    );
+
+    drop(client);
+    node.stop().await.unwrap();
+    remove_node_files(&blockchain_file);
+}
+
+#[tokio::test]
+async fn test_netem_delays_reorders_and_drops() {
+    use btclib::network::Message;
+    use tokio::{
+        net::TcpListener,
+        sync::mpsc,
+        time::{Duration, Instant, timeout},
+    };
+
+    // records the heights asked for, in the order they arrive
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target = listener.local_addr().unwrap().to_string();
+    let (sender, mut received) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        while let Ok(Message::FetchBlock(height)) = Message::receive_async(&mut socket).await {
+            sender.send(height.as_index()).unwrap();
+        }
+    });
+    let latency = Duration::from_millis(200);
+    let netem = Netem::start(
+        &target,
+        Conditions {
+            latency,
+            ..Conditions::default()
+        },
+        7,
+    )
+    .await;
+    let mut socket = tokio::net::TcpStream::connect(netem.addr()).await.unwrap();
+    let send = |index: usize| Message::FetchBlock(BlockHeight::from_index(index));
+    let mut receive_all = async |count: usize| {
+        let mut indexes = vec![];
+        for _ in 0..count {
+            indexes.push(
+                timeout(Duration::from_secs(5), received.recv())
+                    .await
+                    .unwrap()
+                    .unwrap(),
+            );
+        }
+        indexes
+    };
+
+    // delayed, but in order
+    let sent_at = Instant::now();
+    for index in 0..5 {
+        send(index).send_async(&mut socket).await.unwrap();
+    }
+    assert_eq!(receive_all(5).await, vec![0, 1, 2, 3, 4]);
+    assert!(sent_at.elapsed() >= latency);
+
+    // sent right away, some overtake the delayed ones
+    netem.set_conditions(Conditions {
+        latency,
+        reorder: 0.5,
+        ..Conditions::default()
+    });
+    for index in 5..15 {
+        send(index).send_async(&mut socket).await.unwrap();
+    }
+    let reordered = receive_all(10).await;
+    assert_ne!(reordered, (5..15).collect::<Vec<_>>());
+    let mut sorted = reordered.clone();
+    sorted.sort();
+    assert_eq!(sorted, (5..15).collect::<Vec<_>>());
+
+    // lost
+    netem.set_conditions(Conditions {
+        loss: 1.0,
+        ..Conditions::default()
+    });
+    send(15).send_async(&mut socket).await.unwrap();
+    while netem.dropped() == 0 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    netem.set_conditions(Conditions::default());
+    send(16).send_async(&mut socket).await.unwrap();
+    assert_eq!(receive_all(1).await, vec![16]);
+    assert_eq!(netem.dropped(), 1);
+}
+
+#[tokio::test]
+async fn test_sync_fails_over_from_lossy_link() {
+    use btclib::{network::NetworkLimits, types::Blockchain};
+    use node::util::{populate_connections, sync_blockchain};
+    use tokio::time::Duration;
+
+    let mut chain = Blockchain::default();
+    extend_chain(&mut chain, 6);
+
+    let source_file = temp_blockchain_file("lossy-source");
+    let mut config = NodeConfig::new(&source_file);
+    config.port = 0;
+    let mut source = Node::new(config);
+    let source_addr = format!("127.0.0.1:{}", source.start().await.unwrap().port());
+    let mut client = NodeClient::connect(source_addr.as_str()).await.unwrap();
+    for block in chain.blocks() {
+        client.submit_template(block.clone()).await.unwrap();
+    }
+    drop(client);
+
+    // two links to the same node: one drops half the messages, the
+    // other is slow and jittery but loses none
+    let lossy = Netem::start(&source_addr, Conditions::default(), 1).await;
+    let slow = Netem::start(
+        &source_addr,
+        Conditions {
+            latency: Duration::from_millis(30),
+            jitter: Duration::from_millis(20),
+            ..Conditions::default()
+        },
+        2,
+    )
+    .await;
+
+    let file = temp_blockchain_file("lossy-sync");
+    let mut config = NodeConfig::new(&file);
+    config.port = 0;
+    config.limits = NetworkLimits {
+        message_timeout_secs: 1,
+        ..NetworkLimits::default()
+    };
+    let mut node = Node::new(config);
+    node.start().await.unwrap();
+    populate_connections(node.state(), &[lossy.addr(), slow.addr()])
+        .await
+        .unwrap();
+    lossy.set_conditions(Conditions {
+        loss: 0.5,
+        ..Conditions::default()
+    });
+    let height = chain.block_height();
+    sync_blockchain(
+        node.state(),
+        &[(lossy.addr(), height), (slow.addr(), height)],
+    )
+    .await
+    .unwrap();
+
+    // a lost block looked like a stall, and the slow link took over
+    assert!(lossy.dropped() > 0);
+    assert!(!node.state().nodes.contains_key(&lossy.addr()));
+    let blockchain = node.state().blockchain.read().await;
+    assert_eq!(blockchain.blocks().len(), 6);
+    assert_eq!(blockchain.tip(), chain.tip());
+    drop(blockchain);
+
+    node.stop().await.unwrap();
+    source.stop().await.unwrap();
+    remove_node_files(&file);
+    remove_node_files(&source_file);
+}
+
+#[tokio::test]
+async fn test_gossip_over_slow_jittery_link() {
+    use btclib::{
+        crypto::{PrivateKey, Signature},
+        custom_sha_types::Hash,
+        types::{Block, BlockHeader, Transaction, TransactionInput, TransactionOutput},
+        utils::MerkleRoot,
+    };
+    use tokio::time::{Duration, sleep};
+
+    let seed_file = temp_blockchain_file("jitter-seed");
+    let peer_file = temp_blockchain_file("jitter-peer");
+    let mut seed_config = NodeConfig::new(&seed_file);
+    seed_config.port = 0;
+    let mut seed = Node::new(seed_config);
+    let seed_addr = format!("127.0.0.1:{}", seed.start().await.unwrap().port());
+
+    let key = PrivateKey::from_seed(b"jitter");
+    let outputs: Vec<TransactionOutput> = (0..5)
+        .map(|_| TransactionOutput::new(1000000000, uuid::Uuid::new_v4(), key.public_key()))
+        .collect();
+    let transactions = vec![Transaction::new(vec![], outputs.clone())];
+    let header = BlockHeader::new(
+        chrono::Utc::now(),
+        0,
+        Hash::zero(),
+        MerkleRoot::calculate(&transactions),
+        btclib::MIN_TARGET,
+    );
+    let mut client = NodeClient::connect(seed_addr.as_str()).await.unwrap();
+    client
+        .submit_template(Block::new(header, transactions))
+        .await
+        .unwrap();
+    drop(client);
+
+    // the peer syncs over the link, on which relayed transactions may
+    // overtake each other
+    let link = Netem::start(
+        &seed_addr,
+        Conditions {
+            latency: Duration::from_millis(50),
+            jitter: Duration::from_millis(40),
+            reorder: 0.25,
+            ..Conditions::default()
+        },
+        3,
+    )
+    .await;
+    let mut peer_config = NodeConfig::new(&peer_file);
+    peer_config.port = 0;
+    peer_config.nodes = vec![link.addr()];
+    let mut peer = Node::new(peer_config);
+    let peer_addr = peer.start().await.unwrap();
+    assert_eq!(peer.state().blockchain.read().await.blocks().len(), 1);
+
+    let mut client = NodeClient::connect(("127.0.0.1", peer_addr.port()))
+        .await
+        .unwrap();
+
+    for output in &outputs {
+        let transaction = Transaction::new(
+            vec![TransactionInput::new(
+                output.hash(),
+                Signature::sign_output(&output.hash(), &key),
+            )],
+            vec![TransactionOutput::new(
+                output.value() - 10_000,
+                uuid::Uuid::new_v4(),
+                key.public_key(),
+            )],
+        );
+        client.submit_tx(transaction).await.unwrap();
+    }
+    // relay rounds are up to 8 seconds apart
+    for _ in 0..500 {
+        if seed.state().blockchain.read().await.mempool().len() == outputs.len() {
+            break;
+        }
+        sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(
+        seed.state().blockchain.read().await.mempool().len(),
+        outputs.len()
+    );
+
+    // the block mined on them reaches the seed too and clears its mempool
+    let mut block = client.get_template(&key.public_key(), None).await.unwrap();
+    while !block.mine(1000) {}
+    client.submit_template(block.clone()).await.unwrap();
+    for _ in 0..250 {
+        if seed.state().blockchain.read().await.blocks().len() == 2 {
+            break;
+        }
+        sleep(Duration::from_millis(20)).await;
+    }
+    let blockchain = seed.state().blockchain.read().await;
+    assert_eq!(blockchain.tip().map(|tip| tip.hash), Some(block.hash()));
+    assert!(blockchain.mempool().is_empty());
+    drop(blockchain);
+    drop(client);
+
+    peer.stop().await.unwrap();
+    seed.stop().await.unwrap();
+    remove_node_files(&seed_file);
+    remove_node_files(&peer_file);
+}
+
+#[tokio::test]
+async fn test_reorg_over_slow_link() {
+    use btclib::types::Blockchain;
+    use node::util::populate_connections;
+    use tokio::time::{Duration, sleep};
+
+    let mut ours = Blockchain::default();
+    extend_chain(&mut ours, 1);
+    let mut theirs = ours.clone();
+    extend_chain(&mut ours, 2);
+    extend_chain(&mut theirs, 3);
+
+    let files: Vec<String> = ["slow-reorg-peer", "slow-reorg"]
+        .into_iter()
+        .map(temp_blockchain_file)
+        .collect();
+    let mut nodes = vec![];
+    let mut addresses = vec![];
+    for (file, chain) in files.iter().zip([&theirs, &ours]) {
+        let mut config = NodeConfig::new(file);
+        config.port = 0;
+        let mut node = Node::new(config);
+        let addr = format!("127.0.0.1:{}", node.start().await.unwrap().port());
+        let mut client = NodeClient::connect(addr.as_str()).await.unwrap();
+        for block in chain.blocks() {
+            client.submit_template(block.clone()).await.unwrap();
+        }
+        addresses.push(addr);
+        nodes.push(node);
+    }
+
+    // the fork is found and the branch fetched one slow round trip at a time
+    let link = Netem::start(
+        &addresses[0],
+        Conditions {
+            latency: Duration::from_millis(20),
+            jitter: Duration::from_millis(15),
+            reorder: 0.25,
+            ..Conditions::default()
+        },
+        4,
+    )
+    .await;
+    populate_connections(nodes[1].state(), &[link.addr()])
+        .await
+        .unwrap();
+    let mut client = NodeClient::connect(addresses[1].as_str()).await.unwrap();
+    client
+        .announce_block(theirs.blocks().last().unwrap().clone())
+        .await
+        .unwrap();
+    let peer_tip = theirs.tip().unwrap();
+    for _ in 0..250 {
+        if nodes[1].state().blockchain.read().await.tip() == Some(peer_tip) {
+            break;
+        }
+        sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(
+        nodes[1].state().blockchain.read().await.tip(),
+        Some(peer_tip)
+    );
+    drop(client);
+
+    for mut node in nodes {
+        node.stop().await.unwrap();
+    }
+    for file in &files {
+        remove_node_files(file);
+    }
+}
+
+#[tokio::test]
+async fn test_watchtower_alerts_double_spends() {
+    use btclib::{
+        crypto::{PrivateKey, Signature},
+        custom_sha_types::Hash,
+        error::ClientError,
+        network::{RejectCode, Role},
+        types::{Block, BlockHeader, Transaction, TransactionInput, TransactionOutput},
+        utils::MerkleRoot,
+    };
+    use node::util::{Webhook, WebhookEventKind, token_path};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        time::{Duration, timeout},
+    };
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let webhook_port = listener.local_addr().unwrap().port();
+    let server = tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = vec![];
+        let mut buf = [0; 4096];
+        loop {
+            let read = socket.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..read]);
+            if read == 0 || request.ends_with(b"}") {
+                break;
+            }
+        }
+        socket
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+            .await
+            .unwrap();
+        String::from_utf8(request).unwrap()
+    });
+
+    let blockchain_file = temp_blockchain_file("watchtower");
+    let mut config = NodeConfig::new(&blockchain_file);
+    config.port = 0;
+    config.watchtower = true;
+    config.webhooks.hooks = vec![Webhook {
+        events: vec![WebhookEventKind::DoubleSpend],
+        ..Webhook::new(format!("http://127.0.0.1:{webhook_port}/alerts"))
+    }];
+    let mut node = Node::new(config);
+    let addr = node.start().await.unwrap();
+
+    let key = PrivateKey::from_seed(b"watchtower");
+    let paid = TransactionOutput::new(5000000000, uuid::Uuid::new_v4(), key.public_key());
+    let transactions = vec![Transaction::new(vec![], vec![paid.clone()])];
+    let header = BlockHeader::new(
+        chrono::Utc::now(),
+        0,
+        Hash::zero(),
+        MerkleRoot::calculate(&transactions),
+        btclib::MIN_TARGET,
+    );
+    let mut client = NodeClient::connect(("127.0.0.1", addr.port()))
+        .await
+        .unwrap();
+    client
+        .submit_template(Block::new(header, transactions))
+        .await
+        .unwrap();
+
+    // anonymous connections can't fill up the watch list
+    assert!(matches!(
+        client.watch_outpoints(vec![paid.hash()]).await,
+        Err(ClientError::Rejected {
+            code: RejectCode::Unauthorized,
+            ..
+        })
+    ));
+    let token = std::fs::read_to_string(token_path(&blockchain_file, Role::ReadOnly)).unwrap();
+    client.authenticate(&token).await.unwrap();
+
+    // the merchant watches the output it was paid with
+    assert_eq!(client.watch_outpoints(vec![paid.hash()]).await.unwrap(), 1);
+    let spend = |fee: u64| {
+        Transaction::new(
+            vec![TransactionInput::new(
+                paid.hash(),
+                Signature::sign_output(&paid.hash(), &key),
+            )],
+            vec![TransactionOutput::new(
+                paid.value() - fee,
+                uuid::Uuid::new_v4(),
+                key.public_key(),
+            )],
+        )
+    };
+    let (first, second) = (spend(10_000), spend(20_000));
+    client.submit_tx(first.clone()).await.unwrap();
+    client.submit_tx(second.clone()).await.unwrap();
+
+    let request = timeout(Duration::from_secs(10), server)
+        .await
+        .expect("no double spend alert")
+        .unwrap();
+    let (head, body) = request.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("POST /alerts HTTP/1.1"));
+    assert!(head.contains("X-Webhook-Event: double_spend"));
+    assert_eq!(
+        body,
+        format!(
+            r#"{{"event":"double_spend","outpoint":"{}","first":"{}","second":"{}","height":null}}"#,
+            hex::encode(paid.hash().as_bytes()),
+            hex::encode(first.hash().as_bytes()),
+            hex::encode(second.hash().as_bytes())
+        )
+    );
+    assert_eq!(
+        client.unwatch_outpoints(vec![paid.hash()]).await.unwrap(),
+        0
+    );
+    drop(client);
+    node.stop().await.unwrap();
+    remove_node_files(&blockchain_file);
+
+    // other nodes don't watch outputs
+    let blockchain_file = temp_blockchain_file("no-watchtower");
+    let mut config = NodeConfig::new(&blockchain_file);
+    config.port = 0;
+    let mut node = Node::new(config);
+    let addr = node.start().await.unwrap();
+    let mut client = NodeClient::connect(("127.0.0.1", addr.port()))
+        .await
+        .unwrap();
+    let token = std::fs::read_to_string(token_path(&blockchain_file, Role::ReadOnly)).unwrap();
+    client.authenticate(&token).await.unwrap();
+    assert!(matches!(
+        client.watch_outpoints(vec![paid.hash()]).await,
+        Err(ClientError::Rejected {
+            code: RejectCode::Unsupported,
+            ..
+        })
+    ));
+    drop(client);
+    node.stop().await.unwrap();
+    remove_node_files(&blockchain_file);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_soak_converges_after_chaos() {
+    use node::util::{ChaosEvent, SoakConfig, run_soak};
+    use tokio::time::Duration;
+
+    let dir = std::env::temp_dir().join(format!("soak-{}", uuid::Uuid::new_v4()));
+    let config = SoakConfig {
+        nodes: 3,
+        duration: Duration::from_secs(4),
+        chaos_interval: Duration::from_millis(250),
+        block_interval: Duration::from_millis(100),
+        convergence_timeout: Duration::from_secs(20),
+        seed: 7,
+        dir: dir.clone(),
+    };
+    let report = run_soak(&config).await.unwrap();
+    assert!(report.converged());
+    assert!(report.lost.is_empty());
+    assert_eq!(report.end_state.len(), 3);
+    assert!(report.end_state[0].1.is_some());
+    for kind in [
+        |event: &ChaosEvent| matches!(event, ChaosEvent::Restart(_)),
+        |event: &ChaosEvent| matches!(event, ChaosEvent::Partition(_)),
+        |event: &ChaosEvent| matches!(event, ChaosEvent::InvalidBlock(_)),
+    ] {
+        assert!(report.events.iter().any(kind));
+    }
+    std::fs::remove_dir_all(dir).ok();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_rescan_needs_read_only_role() {
+    use btclib::{
+        MAX_RESCAN_KEYS,
+        crypto::PrivateKey,
+        error::ClientError,
+        network::{RejectCode, Role},
+        testutil::TestBlock,
+    };
+    use node::util::token_path;
+
+    let blockchain_file = temp_blockchain_file("rescan-role");
+    let mut config = NodeConfig::new(&blockchain_file);
+    config.port = 0;
+    let mut node = Node::new(config);
+    let addr = node.start().await.unwrap();
+    let mut client = NodeClient::connect(("127.0.0.1", addr.port()))
+        .await
+        .unwrap();
+    client
+        .submit_template(TestBlock::new().mined().build())
+        .await
+        .unwrap();
+    let key = btclib::testutil::fixture_key().public_key();
+
+    assert!(matches!(
+        client.rescan(vec![key.clone()], BlockHeight::GENESIS).await,
+        Err(ClientError::Rejected {
+            code: RejectCode::Unauthorized,
+            ..
+        })
+    ));
+    let token = std::fs::read_to_string(token_path(&blockchain_file, Role::ReadOnly)).unwrap();
+    client.authenticate(&token).await.unwrap();
+    let result = client
+        .rescan(vec![key.clone()], BlockHeight::GENESIS)
+        .await
+        .unwrap();
+    assert_eq!(result.outputs.len(), 1);
+
+    // one request can't make the node scan for any number of keys
+    let keys = vec![PrivateKey::default().public_key(); MAX_RESCAN_KEYS + 1];
+    assert!(matches!(
+        client.rescan(keys, BlockHeight::GENESIS).await,
+        Err(ClientError::Rejected {
+            code: RejectCode::Invalid,
+            ..
+        })
+    ));
+    drop(client);
+    node.stop().await.unwrap();
+    remove_node_files(&blockchain_file);
+}
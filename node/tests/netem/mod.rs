@@ -0,0 +1,216 @@
+//! A netem-like shim for tests: a TCP proxy in front of a node that
+//! delays, reorders and drops whole messages, so in-process nodes can be
+//! run against each other over a degraded network. A node is pointed at
+//! `Netem::addr` instead of the address of the node behind it.
+
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use btclib::network::DEFAULT_MAX_MESSAGE_SIZE;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{
+        TcpListener, TcpStream,
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+    },
+    sync::mpsc::{UnboundedReceiver, unbounded_channel},
+    task::{JoinHandle, JoinSet},
+    time::{Instant, sleep_until},
+};
+
+/// How a link treats the messages crossing it, in either direction.
+/// The default passes them through untouched.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Conditions {
+    /// Delay added to every message
+    pub latency: Duration,
+    /// Each message is delayed by up to this much more or less than
+    /// `latency`, so messages sent close together may swap places
+    pub jitter: Duration,
+    /// Share of messages sent on right away, overtaking the delayed ones
+    pub reorder: f64,
+    /// Share of messages dropped
+    pub loss: f64,
+}
+
+struct Link {
+    conditions: Conditions,
+    rng: StdRng,
+}
+
+impl Link {
+    // when a message read now is passed on, or `None` to drop it
+    fn schedule(&mut self) -> Option<Instant> {
+        let Conditions {
+            latency,
+            jitter,
+            reorder,
+            loss,
+        } = self.conditions;
+        if self.rng.random_bool(loss) {
+            return None;
+        }
+        let now = Instant::now();
+        if self.rng.random_bool(reorder) {
+            return Some(now);
+        }
+        let jitter = jitter.as_secs_f64();
+        let delay = latency.as_secs_f64() + self.rng.random_range(-jitter..=jitter);
+        Some(now + Duration::from_secs_f64(delay.max(0.0)))
+    }
+}
+
+/// A proxy listening on a local port, passing every connection on to a
+/// target address under the link's `Conditions`. The random choices
+/// come from a seed, so a test sending the same messages in the same
+/// order sees the same ones delayed, reordered and dropped. Dropping the
+/// proxy closes its connections.
+pub struct Netem {
+    addr: String,
+    link: Arc<Mutex<Link>>,
+    dropped: Arc<AtomicU64>,
+    task: JoinHandle<()>,
+}
+
+impl Netem {
+    pub async fn start(target: &str, conditions: Conditions, seed: u64) -> Netem {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let link = Arc::new(Mutex::new(Link {
+            conditions,
+            rng: StdRng::seed_from_u64(seed),
+        }));
+        let dropped = Arc::new(AtomicU64::new(0));
+        let task = tokio::spawn(accept(
+            listener,
+            target.to_string(),
+            link.clone(),
+            dropped.clone(),
+        ));
+        Netem {
+            addr,
+            link,
+            dropped,
+            task,
+        }
+    }
+
+    /// The address to connect to instead of the target.
+    pub fn addr(&self) -> String {
+        self.addr.clone()
+    }
+
+    /// Applies to messages read from now on; those already delayed keep
+    /// their delivery time.
+    pub fn set_conditions(&self, conditions: Conditions) {
+        self.link.lock().unwrap().conditions = conditions;
+    }
+
+    /// Messages dropped so far, in both directions.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Netem {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn accept(
+    listener: TcpListener,
+    target: String,
+    link: Arc<Mutex<Link>>,
+    dropped: Arc<AtomicU64>,
+) {
+    // aborted with the accept loop, closing every connection
+    let mut connections = JoinSet::new();
+    while let Ok((socket, _)) = listener.accept().await {
+        let Ok(upstream) = TcpStream::connect(&target).await else {
+            continue;
+        };
+        let (client_read, client_write) = socket.into_split();
+        let (upstream_read, upstream_write) = upstream.into_split();
+        let (link, dropped) = (link.clone(), dropped.clone());
+        connections.spawn(async move {
+            tokio::join!(
+                forward(client_read, upstream_write, &link, &dropped),
+                forward(upstream_read, client_write, &link, &dropped),
+            );
+        });
+    }
+}
+
+// reads length-prefixed messages from `from` and has them written to
+// `to` when the link schedules them
+async fn forward(
+    mut from: OwnedReadHalf,
+    to: OwnedWriteHalf,
+    link: &Mutex<Link>,
+    dropped: &AtomicU64,
+) {
+    let (sender, receiver) = unbounded_channel();
+    let read = async move {
+        loop {
+            let mut len = [0u8; 8];
+            if from.read_exact(&mut len).await.is_err() {
+                break;
+            }
+            let size = u64::from_be_bytes(len) as usize;
+            if size > DEFAULT_MAX_MESSAGE_SIZE {
+                break;
+            }
+            let mut message = len.to_vec();
+            message.resize(len.len() + size, 0);
+            if from.read_exact(&mut message[len.len()..]).await.is_err() {
+                break;
+            }
+            let scheduled = link.lock().unwrap().schedule();
+            match scheduled {
+                Some(at) => {
+                    let _ = sender.send((at, message));
+                }
+                None => {
+                    dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+    };
+    tokio::join!(read, deliver(receiver, to));
+}
+
+// writes the messages in order of delivery time, then of arrival, and
+// closes `to` once the reading side is done and all were written
+async fn deliver(mut receiver: UnboundedReceiver<(Instant, Vec<u8>)>, mut to: OwnedWriteHalf) {
+    let mut queue = BinaryHeap::new();
+    let mut arrived = 0u64;
+    let mut open = true;
+    while open || !queue.is_empty() {
+        let next = queue.peek().map(|Reverse((at, _, _))| *at);
+        tokio::select! {
+            message = receiver.recv(), if open => match message {
+                Some((at, message)) => {
+                    queue.push(Reverse((at, arrived, message)));
+                    arrived += 1;
+                }
+                None => open = false,
+            },
+            _ = sleep_until(next.unwrap_or_else(Instant::now)), if next.is_some() => {
+                let Reverse((_, _, message)) = queue.pop().unwrap();
+                if to.write_all(&message).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+    let _ = to.shutdown().await;
+}